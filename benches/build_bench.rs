@@ -72,6 +72,7 @@ fn bench_dockerfile_source_match(c: &mut Criterion) {
     let github_source = DockerfileSource::GitHub {
         repo_url: "https://github.com/user/repo.git".to_string(),
         git_ref: Some("main".to_string()),
+        subdir: None,
         dockerfile_path: None,
         token: None,
     };
@@ -24,6 +24,7 @@ const BUILDKIT_PROTOS: &[&str] = &[
     "frontend/gateway/pb/gateway.proto",
     // Util
     "util/apicaps/pb/caps.proto",
+    "util/stack/stack.proto",
     // Session
     "session/auth/auth.proto",
     "session/secrets/secrets.proto",
@@ -61,6 +62,10 @@ const VENDOR_MAPPINGS: &[(&str, &str)] = &[
         "vendor/github.com/containerd/containerd/api/types/mount.proto",
         "github.com/containerd/containerd/api/types/mount.proto",
     ),
+    (
+        "vendor/github.com/containerd/containerd/api/services/content/v1/content.proto",
+        "github.com/containerd/containerd/api/services/content/v1/content.proto",
+    ),
 ];
 
 // Google RPC proto files
@@ -70,6 +75,10 @@ const GOOGLE_RPC_PROTOS: &[&str] = &[
     "google/rpc/error_details.proto",
 ];
 
+// gRPC health checking proto, answered by the session tunnel. It is not part
+// of BuildKit's tree, so every fetch mode takes the crate's vendored copy.
+const GRPC_HEALTH_PROTO: &str = "grpc/health/v1/health.proto";
+
 #[derive(Debug, Clone, PartialEq)]
 enum FetchMode {
     /// Use vendored proto files from the crate's `proto/` directory (no network).
@@ -234,6 +243,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             // Fetch Google APIs protos
             let stats = fetch_googleapis_protos(&config)?;
             total_stats.merge(&stats);
+
+            // Copy the gRPC health proto
+            println!("\nCopying gRPC health proto...");
+            if copy_proto(
+                &config.vendored_proto_dir,
+                GRPC_HEALTH_PROTO,
+                &config.proto_dir,
+            )? {
+                total_stats.copied += 1;
+            } else {
+                total_stats.missing += 1;
+            }
         }
     }
 
@@ -678,7 +699,8 @@ fn print_summary(config: &ProtoConfig, stats: &FetchStats) {
     println!("  │   ├── tonistiigi/fsutil/");
     println!("  │   ├── planetscale/vtprotobuf/");
     println!("  │   └── containerd/containerd/");
-    println!("  └── google/rpc/");
+    println!("  ├── google/rpc/");
+    println!("  └── grpc/health/v1/");
     println!("{}", "=".repeat(60));
 }
 
@@ -689,6 +711,11 @@ fn compile_protos() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("\nCompiling proto files with tonic-build...");
 
+    // containerd's comments hold tab-indented text that rustdoc would run
+    // as doctests
+    let mut config = tonic_build::Config::new();
+    config.disable_comments([".containerd"]);
+
     // Configure tonic-build
     tonic_build::configure()
         .build_server(true) // We need server for session services
@@ -696,12 +723,24 @@ fn compile_protos() -> Result<(), Box<dyn std::error::Error>> {
         .out_dir(&out_dir)
         .compile_well_known_types(true)
         .extern_path(".google.protobuf", "::prost_types")
-        .compile_protos(
+        .extern_path(".google.protobuf.Empty", "()")
+        // Ops are addressed by the digest of their encoding, which must not
+        // depend on hash map order
+        .btree_map([".pb.SourceOp.attrs"])
+        .compile_protos_with_config(
+            config,
             &[
                 proto_dir.join("github.com/moby/buildkit/api/services/control/control.proto"),
                 proto_dir.join("github.com/moby/buildkit/session/filesync/filesync.proto"),
                 proto_dir.join("github.com/moby/buildkit/session/auth/auth.proto"),
                 proto_dir.join("github.com/moby/buildkit/session/secrets/secrets.proto"),
+                proto_dir.join("github.com/moby/buildkit/session/sshforward/ssh.proto"),
+                proto_dir.join("github.com/moby/buildkit/frontend/gateway/pb/gateway.proto"),
+                proto_dir.join("github.com/moby/buildkit/solver/errdefs/errdefs.proto"),
+                proto_dir.join("github.com/moby/buildkit/util/stack/stack.proto"),
+                proto_dir
+                    .join("github.com/containerd/containerd/api/services/content/v1/content.proto"),
+                proto_dir.join(GRPC_HEALTH_PROTO),
             ],
             &[&proto_dir], // Include path
         )?;
@@ -20,6 +20,44 @@
 //! assert_eq!(go_mode.as_u32(), 0o644); // Regular file with 0o644 permissions
 //! ```
 //!
+//! ## Converting back to Unix modes
+//!
+//! ```
+//! use filemode::{UnixMode, GoFileMode};
+//!
+//! // Modes received from BuildKit are written to disk as Unix modes
+//! let go_mode = GoFileMode::from(0x800001ed);
+//! assert!(go_mode.is_dir());
+//! assert_eq!(UnixMode::from(go_mode).as_u32(), 0o040755);
+//! ```
+//!
+//! ## Inspecting modes
+//!
+//! ```
+//! use filemode::{FileType, GoFileMode, UnixMode};
+//!
+//! let mode = UnixMode::from(0o104755);
+//! assert_eq!(mode.file_type(), FileType::Regular);
+//! assert_eq!(mode.permissions(), 0o4755);
+//! assert_eq!(mode.to_string(), "-rwsr-xr-x");
+//!
+//! let go_mode = GoFileMode::from(0x800001ed);
+//! assert_eq!(go_mode.to_string(), "drwxr-xr-x");
+//! ```
+//!
+//! ## Parsing modes
+//!
+//! ```
+//! use filemode::{GoFileMode, UnixMode};
+//!
+//! assert_eq!("0o755".parse::<UnixMode>().unwrap().as_u32(), 0o755);
+//! assert_eq!("-rw-r--r--".parse::<UnixMode>().unwrap().as_u32(), 0o100644);
+//! assert_eq!("drwxr-xr-x".parse::<GoFileMode>().unwrap().as_u32(), 0x800001ed);
+//! ```
+//!
+//! With the `serde` feature, both types serialize as numbers and
+//! deserialize from numbers or any of these strings.
+//!
 //! ## Using the legacy function API
 //!
 //! ```
@@ -29,6 +67,12 @@
 //! assert_eq!(go_mode, 0x800001ed);
 //! ```
 
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+mod serde_impl;
+
 // Unix file type constants
 const S_IFMT: u32 = 0o170000; // File type mask
 const S_IFDIR: u32 = 0o040000; // Directory
@@ -49,6 +93,80 @@ const GO_MODE_SETUID: u32 = 0x00800000; // 1 << 23 - Setuid
 const GO_MODE_SETGID: u32 = 0x00400000; // 1 << 22 - Setgid
 const GO_MODE_CHAR_DEVICE: u32 = 0x00200000; // 1 << 21 - Character device
 const GO_MODE_STICKY: u32 = 0x00100000; // 1 << 20 - Sticky bit
+const GO_MODE_IRREGULAR: u32 = 0x00080000; // 1 << 19 - Non-regular file
+const GO_MODE_TYPE: u32 = GO_MODE_DIR
+    | GO_MODE_SYMLINK
+    | GO_MODE_NAMED_PIPE
+    | GO_MODE_SOCKET
+    | GO_MODE_DEVICE
+    | GO_MODE_CHAR_DEVICE
+    | GO_MODE_IRREGULAR; // Go's os.ModeType
+
+/// Type of the file a mode describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileType {
+    /// Regular file
+    Regular,
+    /// Directory
+    Dir,
+    /// Symbolic link
+    Symlink,
+    /// Named pipe (FIFO)
+    NamedPipe,
+    /// Socket
+    Socket,
+    /// Character device
+    CharDevice,
+    /// Block device
+    BlockDevice,
+    /// A file of no known type, such as Go's `ModeIrregular`
+    Irregular,
+}
+
+impl FileType {
+    /// Type of the `ls -l` character `c`.
+    const fn from_char(c: char) -> Option<Self> {
+        match c {
+            '-' => Some(FileType::Regular),
+            'd' => Some(FileType::Dir),
+            'l' => Some(FileType::Symlink),
+            'p' => Some(FileType::NamedPipe),
+            's' => Some(FileType::Socket),
+            'c' => Some(FileType::CharDevice),
+            'b' => Some(FileType::BlockDevice),
+            '?' => Some(FileType::Irregular),
+            _ => None,
+        }
+    }
+
+    /// Unix mode_t type bits; irregular files have none.
+    const fn unix_bits(self) -> u32 {
+        match self {
+            FileType::Regular => S_IFREG,
+            FileType::Dir => S_IFDIR,
+            FileType::Symlink => S_IFLNK,
+            FileType::NamedPipe => S_IFIFO,
+            FileType::Socket => S_IFSOCK,
+            FileType::CharDevice => S_IFCHR,
+            FileType::BlockDevice => S_IFBLK,
+            FileType::Irregular => 0,
+        }
+    }
+
+    /// Character `ls -l` shows for the type.
+    pub const fn as_char(self) -> char {
+        match self {
+            FileType::Regular => '-',
+            FileType::Dir => 'd',
+            FileType::Symlink => 'l',
+            FileType::NamedPipe => 'p',
+            FileType::Socket => 's',
+            FileType::CharDevice => 'c',
+            FileType::BlockDevice => 'b',
+            FileType::Irregular => '?',
+        }
+    }
+}
 
 /// A Unix file mode (mode_t) value.
 ///
@@ -68,6 +186,46 @@ impl UnixMode {
     pub const fn as_u32(self) -> u32 {
         self.0
     }
+
+    /// Type of the file the mode describes.
+    pub const fn file_type(self) -> FileType {
+        match self.0 & S_IFMT {
+            S_IFREG => FileType::Regular,
+            S_IFDIR => FileType::Dir,
+            S_IFLNK => FileType::Symlink,
+            S_IFIFO => FileType::NamedPipe,
+            S_IFSOCK => FileType::Socket,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            _ => FileType::Irregular,
+        }
+    }
+
+    /// Whether the mode describes a directory.
+    #[inline]
+    pub const fn is_dir(self) -> bool {
+        self.0 & S_IFMT == S_IFDIR
+    }
+
+    /// Whether the mode describes a symbolic link.
+    #[inline]
+    pub const fn is_symlink(self) -> bool {
+        self.0 & S_IFMT == S_IFLNK
+    }
+
+    /// Whether the mode describes a regular file.
+    #[inline]
+    pub const fn is_regular(self) -> bool {
+        self.0 & S_IFMT == S_IFREG
+    }
+
+    /// Permission bits, including setuid, setgid and sticky (`0o7777`).
+    ///
+    /// This is the value to pass to `chmod`.
+    #[inline]
+    pub const fn permissions(self) -> u32 {
+        self.0 & 0o7777
+    }
 }
 
 impl From<u32> for UnixMode {
@@ -103,6 +261,68 @@ impl GoFileMode {
     pub const fn as_u32(self) -> u32 {
         self.0
     }
+
+    /// Whether the mode describes a directory.
+    #[inline]
+    pub const fn is_dir(self) -> bool {
+        self.0 & GO_MODE_DIR != 0
+    }
+
+    /// Whether the mode describes a symbolic link.
+    #[inline]
+    pub const fn is_symlink(self) -> bool {
+        self.0 & GO_MODE_SYMLINK != 0
+    }
+
+    /// Whether the mode describes a regular file, i.e. has no type bits set.
+    #[inline]
+    pub const fn is_regular(self) -> bool {
+        self.0 & GO_MODE_TYPE == 0
+    }
+
+    /// Type of the file the mode describes.
+    pub const fn file_type(self) -> FileType {
+        let mode = self.0;
+        // Character devices also carry ModeDevice
+        if mode & GO_MODE_DIR != 0 {
+            FileType::Dir
+        } else if mode & GO_MODE_SYMLINK != 0 {
+            FileType::Symlink
+        } else if mode & GO_MODE_NAMED_PIPE != 0 {
+            FileType::NamedPipe
+        } else if mode & GO_MODE_SOCKET != 0 {
+            FileType::Socket
+        } else if mode & GO_MODE_CHAR_DEVICE != 0 {
+            FileType::CharDevice
+        } else if mode & GO_MODE_DEVICE != 0 {
+            FileType::BlockDevice
+        } else if mode & GO_MODE_IRREGULAR != 0 {
+            FileType::Irregular
+        } else {
+            FileType::Regular
+        }
+    }
+
+    /// Permission bits in Unix form, including setuid, setgid and sticky
+    /// (`0o7777`).
+    ///
+    /// Unlike Go's `FileMode.Perm`, the special bits are moved to where
+    /// `chmod` expects them.
+    #[inline]
+    pub const fn permissions(self) -> u32 {
+        let mode = self.0;
+        let mut permissions = mode & 0o777;
+        if mode & GO_MODE_SETUID != 0 {
+            permissions |= 0o4000;
+        }
+        if mode & GO_MODE_SETGID != 0 {
+            permissions |= 0o2000;
+        }
+        if mode & GO_MODE_STICKY != 0 {
+            permissions |= 0o1000;
+        }
+        permissions
+    }
 }
 
 impl From<u32> for GoFileMode {
@@ -157,6 +377,221 @@ impl From<UnixMode> for GoFileMode {
     }
 }
 
+/// Convert Go os.FileMode to Unix mode_t.
+///
+/// This is the inverse of the Unix to Go conversion. Irregular files have
+/// no Unix equivalent and come out without a file type.
+impl From<GoFileMode> for UnixMode {
+    fn from(go_mode: GoFileMode) -> Self {
+        let mode = go_mode.0;
+        let mut unix_mode = mode & 0o777;
+
+        // Convert special permission bits
+        if mode & GO_MODE_SETUID != 0 {
+            unix_mode |= 0o4000;
+        }
+        if mode & GO_MODE_SETGID != 0 {
+            unix_mode |= 0o2000;
+        }
+        if mode & GO_MODE_STICKY != 0 {
+            unix_mode |= 0o1000;
+        }
+
+        // Convert file type bits; character devices also carry ModeDevice
+        unix_mode |= if mode & GO_MODE_DIR != 0 {
+            S_IFDIR
+        } else if mode & GO_MODE_SYMLINK != 0 {
+            S_IFLNK
+        } else if mode & GO_MODE_NAMED_PIPE != 0 {
+            S_IFIFO
+        } else if mode & GO_MODE_SOCKET != 0 {
+            S_IFSOCK
+        } else if mode & GO_MODE_CHAR_DEVICE != 0 {
+            S_IFCHR
+        } else if mode & GO_MODE_DEVICE != 0 {
+            S_IFBLK
+        } else if mode & GO_MODE_IRREGULAR != 0 {
+            0
+        } else {
+            S_IFREG
+        };
+
+        UnixMode(unix_mode)
+    }
+}
+
+/// Write a mode the way `ls -l` shows it, e.g. `drwxr-xr-x`
+fn fmt_mode(f: &mut fmt::Formatter<'_>, file_type: FileType, permissions: u32) -> fmt::Result {
+    let mut text = [file_type.as_char(); 10];
+    for (i, c) in "rwxrwxrwx".chars().enumerate() {
+        text[i + 1] = if permissions & (0o400 >> i) != 0 {
+            c
+        } else {
+            '-'
+        };
+    }
+    // Special bits replace the execute bit of their class
+    for (bit, index, set) in [(0o4000, 3, 's'), (0o2000, 6, 's'), (0o1000, 9, 't')] {
+        if permissions & bit != 0 {
+            text[index] = if text[index] == 'x' {
+                set
+            } else {
+                set.to_ascii_uppercase()
+            };
+        }
+    }
+    text.iter().try_for_each(|c| f.write_char(*c))
+}
+
+/// Renders the mode like `ls -l`, e.g. `drwxr-xr-x` or `-rwsr-xr-x`.
+impl fmt::Display for UnixMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_mode(f, self.file_type(), self.permissions())
+    }
+}
+
+/// Renders the mode like `ls -l`, e.g. `drwxr-xr-x` or `-rwsr-xr-x`.
+impl fmt::Display for GoFileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_mode(f, self.file_type(), self.permissions())
+    }
+}
+
+/// Error parsing a file mode from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseModeError {
+    input: String,
+}
+
+impl fmt::Display for ParseModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid file mode: {:?}", self.input)
+    }
+}
+
+impl std::error::Error for ParseModeError {}
+
+/// A mode as written in text
+enum ParsedMode {
+    /// Octal number, e.g. `0o755` or `0100644`
+    Octal(u32),
+    /// Symbolic permissions, e.g. `rwxr-xr-x`, with the file type if given
+    Symbolic(Option<FileType>, u32),
+}
+
+impl ParsedMode {
+    /// Parse an octal number (`0o755`, `0755` or `755`), `ls -l` permissions
+    /// with or without the file type (`-rw-r--r--`, `rwxr-xr-x`) or Go's
+    /// `FileMode.String` form (`drwxr-xr-x`, `urwxr-xr-x`, `Dcrw-rw-rw-`).
+    fn parse(input: &str) -> Result<Self, ParseModeError> {
+        let error = || ParseModeError {
+            input: input.to_string(),
+        };
+        let text = input.trim();
+
+        let digits = text.strip_prefix("0o").unwrap_or(text);
+        if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+            return u32::from_str_radix(digits, 8)
+                .map(ParsedMode::Octal)
+                .map_err(|_| error());
+        }
+
+        let chars: Vec<char> = text.chars().collect();
+        if chars.len() < 9 {
+            return Err(error());
+        }
+        let (prefix, rwx) = chars.split_at(chars.len() - 9);
+        let mut permissions = parse_rwx(rwx).ok_or_else(error)?;
+
+        let file_type = match prefix {
+            [] => None,
+            [c] if FileType::from_char(*c).is_some() => FileType::from_char(*c),
+            letters => {
+                // Go's type and special bit letters, from "dalTLDpSugct?"
+                let mut go_mode = 0;
+                for c in letters {
+                    go_mode |= match c {
+                        'd' => GO_MODE_DIR,
+                        'L' => GO_MODE_SYMLINK,
+                        'D' => GO_MODE_DEVICE,
+                        'p' => GO_MODE_NAMED_PIPE,
+                        'S' => GO_MODE_SOCKET,
+                        'u' => GO_MODE_SETUID,
+                        'g' => GO_MODE_SETGID,
+                        'c' => GO_MODE_CHAR_DEVICE,
+                        't' => GO_MODE_STICKY,
+                        '?' => GO_MODE_IRREGULAR,
+                        _ => return Err(error()),
+                    };
+                }
+                let go_mode = GoFileMode(go_mode);
+                permissions |= go_mode.permissions();
+                Some(go_mode.file_type())
+            }
+        };
+        Ok(ParsedMode::Symbolic(file_type, permissions))
+    }
+}
+
+/// Parse `rwxrwxrwx` permissions, with `s`/`S` and `t`/`T` for the special
+/// bits as `ls -l` shows them
+fn parse_rwx(rwx: &[char]) -> Option<u32> {
+    let mut permissions = 0;
+    for (i, (&c, expected)) in rwx.iter().zip("rwxrwxrwx".chars()).enumerate() {
+        let bit = 0o400 >> i;
+        let special = match i {
+            2 => 0o4000,
+            5 => 0o2000,
+            8 => 0o1000,
+            _ => 0,
+        };
+        let set = if i == 8 { 't' } else { 's' };
+        permissions |= match c {
+            '-' => 0,
+            c if c == expected => bit,
+            c if special != 0 && c == set => bit | special,
+            c if special != 0 && c == set.to_ascii_uppercase() => special,
+            _ => return None,
+        };
+    }
+    Some(permissions)
+}
+
+/// Parses an octal mode (`0o755`, `0100644`), `ls -l` permissions
+/// (`rwxr-xr-x`, `-rw-r--r--`) or Go's `FileMode.String` form.
+impl FromStr for UnixMode {
+    type Err = ParseModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match ParsedMode::parse(s)? {
+            ParsedMode::Octal(mode) => UnixMode(mode),
+            ParsedMode::Symbolic(file_type, permissions) => {
+                UnixMode(file_type.map_or(0, FileType::unix_bits) | permissions)
+            }
+        })
+    }
+}
+
+/// Parses the same forms as [`UnixMode`]; octal numbers are Unix modes,
+/// e.g. `0o040755` for a directory.
+impl FromStr for GoFileMode {
+    type Err = ParseModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match ParsedMode::parse(s)? {
+            ParsedMode::Octal(mode) => GoFileMode::from(UnixMode(mode)),
+            ParsedMode::Symbolic(Some(FileType::Irregular), permissions) => {
+                let mut go_mode = GoFileMode::from(UnixMode(permissions));
+                go_mode.0 |= GO_MODE_IRREGULAR;
+                go_mode
+            }
+            ParsedMode::Symbolic(file_type, permissions) => GoFileMode::from(UnixMode(
+                file_type.map_or(0, FileType::unix_bits) | permissions,
+            )),
+        })
+    }
+}
+
 /// Convert Unix mode_t format to Go os.FileMode format.
 ///
 /// This is a convenience function that wraps the type-safe conversion.
@@ -192,6 +627,41 @@ pub fn unix_mode_to_go_filemode(unix_mode: u32) -> u32 {
     GoFileMode::from(UnixMode::from(unix_mode)).as_u32()
 }
 
+/// Convert Go os.FileMode format to Unix mode_t format.
+///
+/// This is a convenience function that wraps the type-safe conversion.
+/// For new code, consider using the [`From`] trait implementation instead.
+///
+/// # Arguments
+///
+/// * `go_mode` - A u32 value in Go os.FileMode format
+///
+/// # Returns
+///
+/// A Unix mode_t value combining file type and permissions
+///
+/// # Examples
+///
+/// ```
+/// use filemode::go_filemode_to_unix_mode;
+///
+/// // Directory with 0o755 permissions
+/// let dir_mode = go_filemode_to_unix_mode(0x800001ed);
+/// assert_eq!(dir_mode, 0o040755);
+///
+/// // Regular file with 0o644 permissions
+/// let file_mode = go_filemode_to_unix_mode(0o644);
+/// assert_eq!(file_mode, 0o100644);
+///
+/// // Character device with 0o666 permissions (ModeDevice | ModeCharDevice)
+/// let char_mode = go_filemode_to_unix_mode(0x042001b6);
+/// assert_eq!(char_mode, 0o020666);
+/// ```
+#[inline]
+pub fn go_filemode_to_unix_mode(go_mode: u32) -> u32 {
+    UnixMode::from(GoFileMode::from(go_mode)).as_u32()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -312,4 +782,190 @@ mod tests {
 
         assert_eq!(result, 0o644); // Regular file loses type bits in Go
     }
+
+    #[test]
+    fn test_go_to_unix_round_trip() {
+        for unix in [
+            0o100644, 0o040755, 0o120777, 0o010644, 0o140666, 0o020666, 0o060666, 0o107777,
+            0o041755,
+        ] {
+            let go_mode = GoFileMode::from(UnixMode::from(unix));
+            assert_eq!(UnixMode::from(go_mode).as_u32(), unix, "mode 0o{:o}", unix);
+        }
+    }
+
+    #[test]
+    fn test_go_to_unix_type_bits() {
+        assert_eq!(go_filemode_to_unix_mode(0o644), 0o100644);
+        assert_eq!(go_filemode_to_unix_mode(GO_MODE_DIR | 0o755), 0o040755);
+        assert_eq!(go_filemode_to_unix_mode(GO_MODE_SYMLINK | 0o777), 0o120777);
+        assert_eq!(
+            go_filemode_to_unix_mode(GO_MODE_NAMED_PIPE | 0o644),
+            0o010644
+        );
+        assert_eq!(go_filemode_to_unix_mode(GO_MODE_SOCKET | 0o666), 0o140666);
+        assert_eq!(go_filemode_to_unix_mode(GO_MODE_DEVICE | 0o660), 0o060660);
+        assert_eq!(
+            go_filemode_to_unix_mode(GO_MODE_DEVICE | GO_MODE_CHAR_DEVICE | 0o666),
+            0o020666
+        );
+        // Go sets only ModeCharDevice for some character devices
+        assert_eq!(
+            go_filemode_to_unix_mode(GO_MODE_CHAR_DEVICE | 0o666),
+            0o020666
+        );
+        assert_eq!(
+            go_filemode_to_unix_mode(GO_MODE_SETUID | GO_MODE_SETGID | GO_MODE_STICKY | 0o755),
+            0o107755
+        );
+        // Irregular files have no Unix file type
+        assert_eq!(go_filemode_to_unix_mode(GO_MODE_IRREGULAR | 0o600), 0o600);
+    }
+
+    #[test]
+    fn test_unix_to_go_round_trip() {
+        for go in [
+            0o644,
+            GO_MODE_DIR | 0o755,
+            GO_MODE_SYMLINK | 0o777,
+            GO_MODE_NAMED_PIPE | 0o644,
+            GO_MODE_SOCKET | 0o666,
+            GO_MODE_DEVICE | 0o660,
+            GO_MODE_DEVICE | GO_MODE_CHAR_DEVICE | 0o666,
+            GO_MODE_DIR | GO_MODE_SETGID | GO_MODE_STICKY | 0o775,
+        ] {
+            let unix_mode = UnixMode::from(GoFileMode::from(go));
+            assert_eq!(GoFileMode::from(unix_mode).as_u32(), go, "mode {:#x}", go);
+        }
+    }
+
+    #[test]
+    fn test_file_types() {
+        for (unix, file_type) in [
+            (0o100644, FileType::Regular),
+            (0o040755, FileType::Dir),
+            (0o120777, FileType::Symlink),
+            (0o010644, FileType::NamedPipe),
+            (0o140666, FileType::Socket),
+            (0o020666, FileType::CharDevice),
+            (0o060666, FileType::BlockDevice),
+        ] {
+            let unix_mode = UnixMode::from(unix);
+            assert_eq!(unix_mode.file_type(), file_type, "mode 0o{:o}", unix);
+            assert_eq!(GoFileMode::from(unix_mode).file_type(), file_type);
+        }
+        assert_eq!(UnixMode::from(0o644).file_type(), FileType::Irregular);
+        assert_eq!(
+            GoFileMode::from(GO_MODE_IRREGULAR | 0o644).file_type(),
+            FileType::Irregular
+        );
+
+        assert!(UnixMode::from(0o040755).is_dir());
+        assert!(UnixMode::from(0o120777).is_symlink());
+        assert!(UnixMode::from(0o100644).is_regular());
+        assert!(!UnixMode::from(0o140666).is_regular());
+    }
+
+    #[test]
+    fn test_permissions() {
+        assert_eq!(UnixMode::from(0o107755).permissions(), 0o7755);
+        assert_eq!(UnixMode::from(0o040700).permissions(), 0o700);
+        assert_eq!(
+            GoFileMode::from(GO_MODE_DIR | GO_MODE_SETGID | GO_MODE_STICKY | 0o775).permissions(),
+            0o3775
+        );
+        assert_eq!(GoFileMode::from(0o644).permissions(), 0o644);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(UnixMode::from(0o100644).to_string(), "-rw-r--r--");
+        assert_eq!(UnixMode::from(0o040755).to_string(), "drwxr-xr-x");
+        assert_eq!(UnixMode::from(0o120777).to_string(), "lrwxrwxrwx");
+        assert_eq!(UnixMode::from(0o020620).to_string(), "crw--w----");
+        assert_eq!(UnixMode::from(0o104755).to_string(), "-rwsr-xr-x");
+        assert_eq!(UnixMode::from(0o102644).to_string(), "-rw-r-Sr--");
+        assert_eq!(UnixMode::from(0o041777).to_string(), "drwxrwxrwt");
+        assert_eq!(
+            GoFileMode::from(GO_MODE_DIR | 0o755).to_string(),
+            "drwxr-xr-x"
+        );
+        assert_eq!(
+            GoFileMode::from(GO_MODE_NAMED_PIPE | 0o600).to_string(),
+            "prw-------"
+        );
+    }
+
+    #[test]
+    fn test_parse_octal() {
+        assert_eq!("0o755".parse::<UnixMode>().unwrap().as_u32(), 0o755);
+        assert_eq!("0644".parse::<UnixMode>().unwrap().as_u32(), 0o644);
+        assert_eq!("100644".parse::<UnixMode>().unwrap().as_u32(), 0o100644);
+        assert_eq!(
+            "0o040755".parse::<GoFileMode>().unwrap().as_u32(),
+            0x800001ed
+        );
+        assert!("0o789".parse::<UnixMode>().is_err());
+        assert!("0o".parse::<UnixMode>().is_err());
+    }
+
+    #[test]
+    fn test_parse_symbolic() {
+        assert_eq!("rwxr-xr-x".parse::<UnixMode>().unwrap().as_u32(), 0o755);
+        assert_eq!("-rw-r--r--".parse::<UnixMode>().unwrap().as_u32(), 0o100644);
+        assert_eq!("drwxr-xr-x".parse::<UnixMode>().unwrap().as_u32(), 0o040755);
+        assert_eq!("-rwsr-xr-x".parse::<UnixMode>().unwrap().as_u32(), 0o104755);
+        assert_eq!("drwxrwxrwT".parse::<UnixMode>().unwrap().as_u32(), 0o041776);
+        assert_eq!("-rw-r--r--".parse::<GoFileMode>().unwrap().as_u32(), 0o644);
+        assert_eq!(
+            "lrwxrwxrwx".parse::<GoFileMode>().unwrap().as_u32(),
+            GO_MODE_SYMLINK | 0o777
+        );
+        assert!("rwxr-xr-".parse::<UnixMode>().is_err());
+        assert!("xrwxr-xr-x".parse::<UnixMode>().is_err());
+        assert!("rwxr-xr-s".parse::<UnixMode>().is_err());
+    }
+
+    #[test]
+    fn test_parse_go_strings() {
+        // Go's FileMode.String uses its own type and special bit letters
+        assert_eq!(
+            "urwxr-xr-x".parse::<GoFileMode>().unwrap().as_u32(),
+            GO_MODE_SETUID | 0o755
+        );
+        assert_eq!(
+            "Dcrw-rw-rw-".parse::<GoFileMode>().unwrap().as_u32(),
+            GO_MODE_DEVICE | GO_MODE_CHAR_DEVICE | 0o666
+        );
+        assert_eq!(
+            "dtrwxrwxrwx".parse::<UnixMode>().unwrap().as_u32(),
+            0o041777
+        );
+        assert_eq!("Lrwxrwxrwx".parse::<UnixMode>().unwrap().as_u32(), 0o120777);
+        assert_eq!(
+            "?rw-------".parse::<GoFileMode>().unwrap().as_u32(),
+            GO_MODE_IRREGULAR | 0o600
+        );
+        assert!("xrw-r--r--r".parse::<GoFileMode>().is_err());
+    }
+
+    #[test]
+    fn test_display_parses_back() {
+        for unix in [0o100644, 0o040755, 0o120777, 0o104755, 0o041777, 0o060660] {
+            let mode = UnixMode::from(unix);
+            assert_eq!(mode.to_string().parse::<UnixMode>().unwrap(), mode);
+            let go_mode = GoFileMode::from(mode);
+            assert_eq!(go_mode.to_string().parse::<GoFileMode>().unwrap(), go_mode);
+        }
+    }
+
+    #[test]
+    fn test_type_predicates() {
+        assert!(GoFileMode::from(0o644).is_regular());
+        assert!(GoFileMode::from(GO_MODE_SETUID | 0o755).is_regular());
+        assert!(GoFileMode::from(GO_MODE_DIR | 0o755).is_dir());
+        assert!(!GoFileMode::from(GO_MODE_DIR | 0o755).is_regular());
+        assert!(GoFileMode::from(GO_MODE_SYMLINK | 0o777).is_symlink());
+        assert!(!GoFileMode::from(GO_MODE_NAMED_PIPE | 0o644).is_regular());
+    }
 }
@@ -0,0 +1,97 @@
+//! Serialize and Deserialize for the mode types
+//!
+//! Modes serialize as their raw number. They deserialize from a number or
+//! from any string their `FromStr` implementation accepts, such as `"0o755"`
+//! or `"-rw-r--r--"`.
+
+use crate::{GoFileMode, UnixMode};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+/// Accepts a mode as a number or a string
+struct ModeVisitor<T>(PhantomData<T>);
+
+impl<T> Visitor<'_> for ModeVisitor<T>
+where
+    T: From<u32> + FromStr,
+    T::Err: fmt::Display,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a file mode as a number or a string such as \"0o755\" or \"rwxr-xr-x\"")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<T, E> {
+        u32::try_from(value)
+            .map(T::from)
+            .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(value), &self))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<T, E> {
+        u32::try_from(value)
+            .map(T::from)
+            .map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl Serialize for UnixMode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.as_u32())
+    }
+}
+
+impl<'de> Deserialize<'de> for UnixMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ModeVisitor(PhantomData))
+    }
+}
+
+impl Serialize for GoFileMode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.as_u32())
+    }
+}
+
+impl<'de> Deserialize<'de> for GoFileMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ModeVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_serialize_as_numbers() {
+        assert_eq!(
+            serde_json::to_string(&UnixMode::from(0o100644)).unwrap(),
+            "33188"
+        );
+        assert_eq!(
+            serde_json::to_string(&GoFileMode::from(0x800001ed)).unwrap(),
+            "2147484141"
+        );
+    }
+
+    #[test]
+    fn modes_deserialize_from_numbers_and_strings() {
+        let mode: UnixMode = serde_json::from_str("420").unwrap();
+        assert_eq!(mode.as_u32(), 0o644);
+        let mode: UnixMode = serde_json::from_str("\"0o755\"").unwrap();
+        assert_eq!(mode.as_u32(), 0o755);
+        let mode: GoFileMode = serde_json::from_str("\"drwxr-xr-x\"").unwrap();
+        assert_eq!(mode.as_u32(), 0x800001ed);
+
+        assert!(serde_json::from_str::<UnixMode>("-1").is_err());
+        assert!(serde_json::from_str::<UnixMode>("\"rwx\"").is_err());
+    }
+}
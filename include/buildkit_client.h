@@ -0,0 +1,70 @@
+/*
+ * C API for buildkit-client
+ *
+ * Build the shared library with:
+ *
+ *   cargo rustc --release --lib --no-default-features --features ffi --crate-type cdylib
+ *
+ * Functions returning a pointer return NULL on failure; functions returning
+ * int return 0 on success and -1 on failure. bk_last_error() describes the
+ * last failure on the calling thread. All calls block.
+ *
+ * A BkClient is thread-safe: several threads may call bk_client_build and
+ * bk_client_health_check on it at once, but it must not be freed while a
+ * call is running. BkBuildConfig and BkBuildResult handles must be used from
+ * one thread at a time.
+ */
+
+#ifndef BUILDKIT_CLIENT_H
+#define BUILDKIT_CLIENT_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct BkClient BkClient;
+typedef struct BkBuildConfig BkBuildConfig;
+typedef struct BkBuildResult BkBuildResult;
+
+/* Called with one JSON document per progress update (same format as the
+ * CLI's --json output). The string is only valid during the call, which may
+ * happen on another thread. */
+typedef void (*BkProgressCallback)(void *user_data, const char *json);
+
+/* Errors and strings */
+const char *bk_last_error(void);
+void bk_string_free(char *s);
+
+/* Client */
+BkClient *bk_client_connect(const char *addr);
+int bk_client_health_check(BkClient *client);
+void bk_client_free(BkClient *client);
+
+/* Build configuration */
+BkBuildConfig *bk_build_config_new_local(const char *context_path);
+BkBuildConfig *bk_build_config_new_github(const char *repo_url);
+int bk_build_config_set_dockerfile(BkBuildConfig *config, const char *path);
+int bk_build_config_add_tag(BkBuildConfig *config, const char *tag);
+int bk_build_config_add_build_arg(BkBuildConfig *config, const char *key, const char *value);
+int bk_build_config_set_target(BkBuildConfig *config, const char *target);
+int bk_build_config_add_platform(BkBuildConfig *config, const char *platform);
+int bk_build_config_set_github_token(BkBuildConfig *config, const char *token);
+int bk_build_config_add_registry_auth(BkBuildConfig *config, const char *host,
+                                      const char *username, const char *password);
+int bk_build_config_add_secret(BkBuildConfig *config, const char *id, const char *value);
+int bk_build_config_set_no_cache(BkBuildConfig *config, int no_cache);
+int bk_build_config_set_pull(BkBuildConfig *config, int pull);
+void bk_build_config_free(BkBuildConfig *config);
+
+/* Builds */
+BkBuildResult *bk_client_build(BkClient *client, const BkBuildConfig *config,
+                               BkProgressCallback progress, void *user_data);
+const char *bk_build_result_digest(const BkBuildResult *result);
+char *bk_build_result_metadata_json(const BkBuildResult *result);
+void bk_build_result_free(BkBuildResult *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BUILDKIT_CLIENT_H */
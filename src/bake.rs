@@ -0,0 +1,421 @@
+//! Bake-style build definitions
+//!
+//! Models the JSON form of `docker buildx bake` files: variables, targets
+//! that inherit from each other and may fan out over a matrix, and groups
+//! of targets. [`BakeFile::resolve`] turns a selection of groups and
+//! targets into one [`BuildConfig`] per target.
+//!
+//! # Example
+//!
+//! ```
+//! use buildkit_client::bake::BakeFile;
+//!
+//! let bake = BakeFile::from_json(r#"{
+//!     "variable": { "TAG": { "default": "latest" } },
+//!     "group": { "default": { "targets": ["app", "worker"] } },
+//!     "target": {
+//!         "base": { "context": "./src", "platforms": ["linux/amd64", "linux/arm64"] },
+//!         "app": { "inherits": ["base"], "target": "app", "tags": ["registry.example.com/app:${TAG}"] },
+//!         "worker": { "inherits": ["base"], "target": "worker", "tags": ["registry.example.com/worker:${TAG}"] }
+//!     }
+//! }"#)?
+//! .set("TAG", "v1.2.3");
+//!
+//! let configs = bake.resolve(&[])?;
+//! assert_eq!(configs.len(), 2);
+//! assert_eq!(configs[0].tags, vec!["registry.example.com/app:v1.2.3"]);
+//! # Ok::<(), buildkit_client::Error>(())
+//! ```
+
+use crate::builder::{BuildConfig, Platform};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Group built when no targets are selected
+const DEFAULT_GROUP: &str = "default";
+
+/// A bake file: variables, groups and targets
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BakeFile {
+    /// Variables referenced as `${NAME}` in target fields
+    #[serde(default)]
+    pub variable: BTreeMap<String, Variable>,
+    /// Named groups of targets
+    #[serde(default)]
+    pub group: BTreeMap<String, Group>,
+    /// Named targets
+    #[serde(default)]
+    pub target: BTreeMap<String, Target>,
+    /// Values set by the caller, taking precedence over the environment
+    #[serde(skip)]
+    overrides: HashMap<String, String>,
+}
+
+/// A variable with a default value
+///
+/// An environment variable of the same name overrides the default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Variable {
+    /// Value used when neither the caller nor the environment sets one
+    #[serde(default)]
+    pub default: String,
+}
+
+/// A named set of targets or other groups
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Group {
+    /// Names of targets or groups
+    #[serde(default)]
+    pub targets: Vec<String>,
+}
+
+/// A build definition
+///
+/// Unset fields are taken from the targets listed in `inherits`, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Target {
+    /// Targets to inherit unset fields from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inherits: Vec<String>,
+    /// Build context directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    /// Dockerfile path, relative to the context
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dockerfile: Option<String>,
+    /// Stage to build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Image tags to push
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Platforms to build for (e.g. `linux/arm64`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub platforms: Vec<String>,
+    /// Build arguments; inherited arguments are merged
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub args: BTreeMap<String, String>,
+    /// Cache import sources
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cache_from: Vec<String>,
+    /// Cache export destinations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cache_to: Vec<String>,
+    /// Build without cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_cache: Option<bool>,
+    /// Always pull base images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pull: Option<bool>,
+    /// Values to fan the target out over, referenced as `${KEY}`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub matrix: BTreeMap<String, Vec<String>>,
+    /// Name template for matrix targets, e.g. `app-${VARIANT}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// A target after inheritance, matrix expansion and variable substitution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedTarget {
+    /// Target name; matrix targets get one name per combination
+    pub name: String,
+    /// Resolved definition, without `inherits` or `matrix`
+    pub target: Target,
+}
+
+impl BakeFile {
+    /// Parse a bake file in JSON form
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| Error::InvalidConfig(format!("invalid bake file: {}", e)))
+    }
+
+    /// Set a variable, overriding its default and the environment
+    pub fn set(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.overrides.insert(name.into(), value.into());
+        self
+    }
+
+    /// Build configurations for the given groups and targets
+    ///
+    /// Selects the `default` group if `names` is empty.
+    pub fn resolve(&self, names: &[&str]) -> Result<Vec<BuildConfig>> {
+        self.resolve_targets(names)?
+            .iter()
+            .map(|resolved| resolved.target.build_config())
+            .collect()
+    }
+
+    /// Resolved targets for the given groups and targets, in selection
+    /// order and without duplicates
+    pub fn resolve_targets(&self, names: &[&str]) -> Result<Vec<ResolvedTarget>> {
+        let names: Vec<&str> = if names.is_empty() {
+            vec![DEFAULT_GROUP]
+        } else {
+            names.to_vec()
+        };
+
+        let mut selected = Vec::new();
+        for name in names {
+            self.collect_targets(name, &mut Vec::new(), &mut selected)?;
+        }
+
+        let variables = self.variables();
+        let mut resolved = Vec::new();
+        for name in selected {
+            let target = self.inherited(&name, &mut Vec::new())?;
+            resolved.extend(expand_matrix(&name, target, &variables)?);
+        }
+        Ok(resolved)
+    }
+
+    /// Values of all variables
+    fn variables(&self) -> HashMap<String, String> {
+        self.variable
+            .iter()
+            .map(|(name, variable)| {
+                let value = self
+                    .overrides
+                    .get(name)
+                    .cloned()
+                    .or_else(|| std::env::var(name).ok())
+                    .unwrap_or_else(|| variable.default.clone());
+                (name.clone(), value)
+            })
+            .chain(self.overrides.clone())
+            .collect()
+    }
+
+    /// Add the targets a group or target name selects
+    fn collect_targets(
+        &self,
+        name: &str,
+        visiting: &mut Vec<String>,
+        selected: &mut Vec<String>,
+    ) -> Result<()> {
+        if let Some(group) = self.group.get(name) {
+            if visiting.iter().any(|v| v == name) {
+                return Err(Error::InvalidConfig(format!(
+                    "group '{}' includes itself",
+                    name
+                )));
+            }
+            visiting.push(name.to_string());
+            for member in &group.targets {
+                self.collect_targets(member, visiting, selected)?;
+            }
+            visiting.pop();
+            Ok(())
+        } else if self.target.contains_key(name) {
+            if !selected.iter().any(|s| s == name) {
+                selected.push(name.to_string());
+            }
+            Ok(())
+        } else {
+            Err(Error::InvalidConfig(format!(
+                "no bake target or group named '{}'",
+                name
+            )))
+        }
+    }
+
+    /// A target with the fields it inherits filled in
+    fn inherited(&self, name: &str, visiting: &mut Vec<String>) -> Result<Target> {
+        if visiting.iter().any(|v| v == name) {
+            visiting.push(name.to_string());
+            return Err(Error::InvalidConfig(format!(
+                "bake targets inherit in a cycle: {}",
+                visiting.join(" -> ")
+            )));
+        }
+        let target = self
+            .target
+            .get(name)
+            .ok_or_else(|| Error::InvalidConfig(format!("no bake target named '{}'", name)))?;
+
+        visiting.push(name.to_string());
+        let mut merged = Target::default();
+        for parent in &target.inherits {
+            merged = merged.merge(self.inherited(parent, visiting)?);
+        }
+        visiting.pop();
+
+        Ok(merged.merge(Target {
+            inherits: Vec::new(),
+            ..target.clone()
+        }))
+    }
+}
+
+impl Target {
+    /// This target's fields, overridden by those set in `child`
+    fn merge(self, child: Target) -> Target {
+        fn list(parent: Vec<String>, child: Vec<String>) -> Vec<String> {
+            if child.is_empty() {
+                parent
+            } else {
+                child
+            }
+        }
+
+        let mut args = self.args;
+        args.extend(child.args);
+        let mut matrix = self.matrix;
+        matrix.extend(child.matrix);
+
+        Target {
+            inherits: Vec::new(),
+            context: child.context.or(self.context),
+            dockerfile: child.dockerfile.or(self.dockerfile),
+            target: child.target.or(self.target),
+            tags: list(self.tags, child.tags),
+            platforms: list(self.platforms, child.platforms),
+            args,
+            cache_from: list(self.cache_from, child.cache_from),
+            cache_to: list(self.cache_to, child.cache_to),
+            no_cache: child.no_cache.or(self.no_cache),
+            pull: child.pull.or(self.pull),
+            matrix,
+            name: child.name.or(self.name),
+        }
+    }
+
+    /// Replace `${NAME}` references in every string field
+    fn substitute(self, variables: &HashMap<String, String>) -> Result<Target> {
+        let one = |value: Option<String>| value.map(|v| substitute(&v, variables)).transpose();
+        let many = |values: Vec<String>| {
+            values
+                .iter()
+                .map(|v| substitute(v, variables))
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Target {
+            inherits: self.inherits,
+            context: one(self.context)?,
+            dockerfile: one(self.dockerfile)?,
+            target: one(self.target)?,
+            tags: many(self.tags)?,
+            platforms: many(self.platforms)?,
+            args: self
+                .args
+                .into_iter()
+                .map(|(k, v)| Ok((k, substitute(&v, variables)?)))
+                .collect::<Result<_>>()?,
+            cache_from: many(self.cache_from)?,
+            cache_to: many(self.cache_to)?,
+            no_cache: self.no_cache,
+            pull: self.pull,
+            matrix: self.matrix,
+            name: one(self.name)?,
+        })
+    }
+
+    /// Build configuration for this target
+    ///
+    /// The context defaults to the current directory.
+    pub fn build_config(&self) -> Result<BuildConfig> {
+        let mut config = BuildConfig::local(self.context.as_deref().unwrap_or("."));
+        if let Some(dockerfile) = &self.dockerfile {
+            config = config.dockerfile(dockerfile.clone());
+        }
+        if let Some(target) = &self.target {
+            config = config.target(target.clone());
+        }
+        for tag in &self.tags {
+            config = config.tag(tag.clone());
+        }
+        if !self.platforms.is_empty() {
+            config.platforms.clear();
+            for platform in &self.platforms {
+                config = config.platform(Platform::parse(platform)?);
+            }
+        }
+        for (key, value) in &self.args {
+            config = config.build_arg(key.clone(), value.clone());
+        }
+        for source in &self.cache_from {
+            config = config.cache_from(source.clone());
+        }
+        for dest in &self.cache_to {
+            config = config.cache_to(dest.clone());
+        }
+        Ok(config
+            .no_cache(self.no_cache.unwrap_or(false))
+            .pull(self.pull.unwrap_or(false)))
+    }
+}
+
+/// One resolved target per combination of the target's matrix values
+fn expand_matrix(
+    name: &str,
+    target: Target,
+    variables: &HashMap<String, String>,
+) -> Result<Vec<ResolvedTarget>> {
+    let mut combinations: Vec<Vec<(&String, &String)>> = vec![Vec::new()];
+    for (key, values) in &target.matrix {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut next = combination.clone();
+                    next.push((key, value));
+                    next
+                })
+            })
+            .collect();
+    }
+
+    let is_matrix = !target.matrix.is_empty();
+    combinations
+        .into_iter()
+        .map(|combination| {
+            let mut scope = variables.clone();
+            for (key, value) in &combination {
+                scope.insert((*key).clone(), (*value).clone());
+            }
+
+            let mut resolved = Target {
+                matrix: BTreeMap::new(),
+                ..target.clone()
+            }
+            .substitute(&scope)?;
+            let name = match resolved.name.take() {
+                Some(template) => template,
+                None if is_matrix => std::iter::once(name)
+                    .chain(combination.iter().map(|(_, value)| value.as_str()))
+                    .collect::<Vec<_>>()
+                    .join("-"),
+                None => name.to_string(),
+            };
+            Ok(ResolvedTarget {
+                name,
+                target: resolved,
+            })
+        })
+        .collect()
+}
+
+/// Replace `${NAME}` references with variable values
+fn substitute(value: &str, variables: &HashMap<String, String>) -> Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find('}').ok_or_else(|| {
+            Error::InvalidConfig(format!("unterminated variable reference in '{}'", value))
+        })?;
+        let name = &after[..end];
+        let replacement = variables
+            .get(name)
+            .ok_or_else(|| Error::InvalidConfig(format!("undefined bake variable '{}'", name)))?;
+        out.push_str(replacement);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
@@ -0,0 +1,428 @@
+//! Running many independent builds with a concurrency limit
+//!
+//! A [`BatchManifest`] lists builds the way a release pipeline would
+//! describe them: a context, Dockerfile, tags, platforms and build
+//! arguments each. [`BuildKitClient::build_batch`] runs them with at most
+//! `concurrency` builds in flight, each with its own session, and reports
+//! every outcome instead of stopping at the first failure.
+//!
+//! # Example
+//!
+//! ```
+//! use buildkit_client::batch::BatchManifest;
+//!
+//! let manifest: BatchManifest = serde_json::from_str(r#"{
+//!     "concurrency": 4,
+//!     "builds": [
+//!         { "name": "api", "context": "services/api", "tags": ["registry.example.com/api:latest"] },
+//!         { "name": "web", "context": "services/web", "platforms": ["linux/amd64", "linux/arm64"] }
+//!     ]
+//! }"#)?;
+//!
+//! let builds = manifest.build_configs(".")?;
+//! assert_eq!(builds.len(), 2);
+//! assert_eq!(builds[0].0, "api");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::builder::{BuildConfig, Platform};
+use crate::client::BuildKitClient;
+use crate::error::{Error, Result};
+use crate::progress::ProgressHandler;
+use crate::proto::moby::buildkit::v1::StatusResponse;
+use crate::report::BuildWarning;
+use crate::solve::BuildResult;
+use crate::targets::ForwardProgress;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinSet;
+
+/// A list of builds to run together
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchManifest {
+    /// Maximum number of builds running at once, if the caller sets none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    /// Builds to run
+    pub builds: Vec<BatchBuild>,
+}
+
+/// One build of a [`BatchManifest`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BatchBuild {
+    /// Name used in progress and the summary, defaulting to the context
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Context directory, relative to the manifest
+    pub context: PathBuf,
+    /// Dockerfile path, relative to the context
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dockerfile: Option<String>,
+    /// Target stage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Image tags to push
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Target platforms
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub platforms: Vec<String>,
+    /// Build arguments
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub args: BTreeMap<String, String>,
+    /// Disable caching
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub no_cache: bool,
+    /// Always pull base images
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub pull: bool,
+}
+
+impl BatchManifest {
+    /// Turn every build into a named [`BuildConfig`]
+    ///
+    /// Relative contexts are resolved against `base_dir`, normally the
+    /// directory of the manifest file. Fails on duplicate names and
+    /// invalid platforms.
+    pub fn build_configs(&self, base_dir: impl AsRef<Path>) -> Result<Vec<(String, BuildConfig)>> {
+        let base_dir = base_dir.as_ref();
+        let mut names = HashSet::new();
+        self.builds
+            .iter()
+            .map(|build| {
+                let name = build.name();
+                if !names.insert(name.clone()) {
+                    return Err(Error::InvalidConfig(format!(
+                        "duplicate build name in batch: {}",
+                        name
+                    )));
+                }
+                Ok((name, build.build_config(base_dir)?))
+            })
+            .collect()
+    }
+}
+
+impl BatchBuild {
+    /// Name of the build, defaulting to its context path
+    pub fn name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| self.context.display().to_string())
+    }
+
+    /// Build configuration with the context resolved against `base_dir`
+    pub fn build_config(&self, base_dir: impl AsRef<Path>) -> Result<BuildConfig> {
+        let mut config = BuildConfig::local(base_dir.as_ref().join(&self.context));
+        if let Some(dockerfile) = &self.dockerfile {
+            config = config.dockerfile(dockerfile.clone());
+        }
+        if let Some(target) = &self.target {
+            config = config.target(target.clone());
+        }
+        for tag in &self.tags {
+            config = config.tag(tag.clone());
+        }
+        if !self.platforms.is_empty() {
+            config.platforms.clear();
+            for platform in &self.platforms {
+                config = config.platform(Platform::parse(platform)?);
+            }
+        }
+        for (key, value) in &self.args {
+            config = config.build_arg(key.clone(), value.clone());
+        }
+        Ok(config.no_cache(self.no_cache).pull(self.pull))
+    }
+}
+
+/// Outcome of one build of [`BuildKitClient::build_batch`]
+#[derive(Debug)]
+pub struct BatchResult {
+    /// Build name
+    pub name: String,
+    /// Tags the build pushes to
+    pub tags: Vec<String>,
+    /// Time from the build starting to finishing, excluding queueing
+    pub duration: Duration,
+    /// Build result, or the error the build failed with
+    pub result: Result<BuildResult>,
+}
+
+impl BatchResult {
+    /// JSON summary of a batch: per-build status, digest, duration and
+    /// error, plus success and failure counts
+    pub fn summary_json(results: &[BatchResult]) -> serde_json::Value {
+        let failed = results.iter().filter(|r| r.result.is_err()).count();
+        serde_json::json!({
+            "succeeded": results.len() - failed,
+            "failed": failed,
+            "builds": results.iter().map(|r| {
+                serde_json::json!({
+                    "name": r.name,
+                    "tags": r.tags,
+                    "status": if r.result.is_ok() { "succeeded" } else { "failed" },
+                    "digest": r.result.as_ref().ok().and_then(|b| b.digest.clone()),
+                    "duration_secs": r.duration.as_secs_f64(),
+                    "error": r.result.as_ref().err().map(|e| e.to_string()),
+                })
+            }).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl BuildKitClient {
+    /// Run many builds, at most `concurrency` at a time
+    ///
+    /// Each build has its own session. Status updates of all builds go to
+    /// `progress_handler`, with step names labelled with the build's name,
+    /// e.g. `[api 2/5] RUN make`. Returns one result per build, in the
+    /// order given; a failing build does not stop the others.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::batch::BatchResult;
+    /// use buildkit_client::{BuildConfig, BuildKitClient};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     let builds = vec![
+    ///         ("api".to_string(), BuildConfig::local("./api").tag("localhost:5000/api:latest")),
+    ///         ("web".to_string(), BuildConfig::local("./web").tag("localhost:5000/web:latest")),
+    ///     ];
+    ///
+    ///     let results = client.build_batch(builds, 2, None).await?;
+    ///     println!("{}", BatchResult::summary_json(&results));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn build_batch(
+        &self,
+        builds: Vec<(String, BuildConfig)>,
+        concurrency: usize,
+        mut progress_handler: Option<Box<dyn ProgressHandler>>,
+    ) -> Result<Vec<BatchResult>> {
+        if builds.is_empty() {
+            return Err(Error::InvalidConfig("no builds in batch".to_string()));
+        }
+        if concurrency == 0 {
+            return Err(Error::InvalidConfig(
+                "batch concurrency must be at least 1".to_string(),
+            ));
+        }
+        tracing::info!("Running {} builds, {} at a time", builds.len(), concurrency);
+
+        let permits = Arc::new(Semaphore::new(concurrency));
+        let (status_tx, mut status_rx) = mpsc::unbounded_channel();
+
+        if let Some(ref mut handler) = progress_handler {
+            handler.on_start()?;
+        }
+
+        let mut running = JoinSet::new();
+        for (index, (name, config)) in builds.into_iter().enumerate() {
+            let client = self.clone();
+            let permits = permits.clone();
+            let status_tx = status_tx.clone();
+            running.spawn(async move {
+                // The semaphore is never closed
+                let _permit = permits.acquire_owned().await;
+                tracing::info!("Starting batch build {}", name);
+
+                let tags = config.tags.clone();
+                let started = Instant::now();
+                let forward: Box<dyn ProgressHandler> =
+                    Box::new(LabelledProgress::new(&name, ForwardProgress(status_tx)));
+                let result = client.build(config, Some(forward)).await;
+                if let Err(e) = &result {
+                    tracing::warn!("Batch build {} failed: {}", name, e);
+                }
+                (
+                    index,
+                    BatchResult {
+                        name,
+                        tags,
+                        duration: started.elapsed(),
+                        result,
+                    },
+                )
+            });
+        }
+        // Forwarding ends once every build has dropped its sender
+        drop(status_tx);
+
+        let forward = async {
+            while let Some(status) = status_rx.recv().await {
+                if let Some(ref mut handler) = progress_handler {
+                    handler.on_status(status)?;
+                }
+            }
+            Ok::<_, Error>(())
+        };
+        let collect = async {
+            let mut results = Vec::new();
+            while let Some(joined) = running.join_next().await {
+                results.push(
+                    joined.map_err(|e| Error::other(format!("batch build task failed: {}", e)))?,
+                );
+            }
+            Ok::<_, Error>(results)
+        };
+        let (forwarded, collected) = tokio::join!(forward, collect);
+        forwarded?;
+        let mut results = collected?;
+        results.sort_by_key(|(index, _)| *index);
+
+        if let Some(ref mut handler) = progress_handler {
+            let failed = results.iter().filter(|(_, r)| r.result.is_err()).count();
+            if failed > 0 {
+                handler.on_error(&format!("{} of {} builds failed", failed, results.len()))?;
+            } else {
+                handler.on_complete()?;
+            }
+        }
+
+        Ok(results.into_iter().map(|(_, result)| result).collect())
+    }
+}
+
+/// Progress handler labelling the steps of one build of a batch with the
+/// build's name, as `docker buildx bake` does
+pub(crate) struct LabelledProgress<H> {
+    label: String,
+    inner: H,
+}
+
+impl<H: ProgressHandler> LabelledProgress<H> {
+    pub(crate) fn new(label: &str, inner: H) -> Self {
+        Self {
+            label: label.to_string(),
+            inner,
+        }
+    }
+
+    /// `[1/3] FROM alpine` becomes `[api 1/3] FROM alpine`, other names
+    /// `[api] name`
+    fn label(&self, name: &str) -> String {
+        match name.strip_prefix('[') {
+            Some(rest) => format!("[{} {}", self.label, rest),
+            None => format!("[{}] {}", self.label, name),
+        }
+    }
+}
+
+impl<H: ProgressHandler> ProgressHandler for LabelledProgress<H> {
+    fn on_start(&mut self) -> Result<()> {
+        self.inner.on_start()
+    }
+
+    fn on_status(&mut self, mut status: StatusResponse) -> Result<()> {
+        for vertex in &mut status.vertexes {
+            vertex.name = self.label(&vertex.name);
+        }
+        self.inner.on_status(status)
+    }
+
+    fn on_complete(&mut self) -> Result<()> {
+        self.inner.on_complete()
+    }
+
+    fn on_error(&mut self, error: &str) -> Result<()> {
+        self.inner.on_error(error)
+    }
+
+    fn on_warning(&mut self, warning: &BuildWarning) -> Result<()> {
+        self.inner.on_warning(warning)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::moby::buildkit::v1::Vertex;
+
+    #[test]
+    fn summary_counts_failures() {
+        let results = vec![
+            BatchResult {
+                name: "api".to_string(),
+                tags: vec!["localhost:5000/api:latest".to_string()],
+                duration: Duration::from_secs(3),
+                result: Ok(BuildResult {
+                    build_ref: "build-1".to_string(),
+                    digest: Some("sha256:abc".to_string()),
+                    metadata: Default::default(),
+                    report: Default::default(),
+                    warnings: Vec::new(),
+                    platforms: Vec::new(),
+                    context_stats: Default::default(),
+                }),
+            },
+            BatchResult {
+                name: "web".to_string(),
+                tags: vec![],
+                duration: Duration::from_secs(1),
+                result: Err(Error::other("boom")),
+            },
+        ];
+
+        let summary = BatchResult::summary_json(&results);
+        assert_eq!(summary["succeeded"], 1);
+        assert_eq!(summary["failed"], 1);
+        assert_eq!(summary["builds"][0]["digest"], "sha256:abc");
+        assert_eq!(summary["builds"][0]["duration_secs"], 3.0);
+        assert_eq!(summary["builds"][1]["status"], "failed");
+        assert!(summary["builds"][1]["error"]
+            .as_str()
+            .unwrap()
+            .contains("boom"));
+    }
+
+    #[test]
+    fn steps_are_labelled_with_their_build() {
+        let (status_tx, mut status_rx) = mpsc::unbounded_channel();
+        let mut api = LabelledProgress::new("api", ForwardProgress(status_tx.clone()));
+        let mut web = LabelledProgress::new("web", ForwardProgress(status_tx));
+
+        let status = |names: &[&str]| StatusResponse {
+            vertexes: names
+                .iter()
+                .map(|name| Vertex {
+                    name: name.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        api.on_status(status(&[
+            "[1/2] FROM alpine",
+            "[internal] load .dockerignore",
+        ]))
+        .unwrap();
+        web.on_status(status(&["[1/2] FROM alpine", "exporting to image"]))
+            .unwrap();
+
+        let names = |status: StatusResponse| {
+            status
+                .vertexes
+                .into_iter()
+                .map(|v| v.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(status_rx.try_recv().unwrap()),
+            ["[api 1/2] FROM alpine", "[api internal] load .dockerignore"]
+        );
+        assert_eq!(
+            names(status_rx.try_recv().unwrap()),
+            ["[web 1/2] FROM alpine", "[web] exporting to image"]
+        );
+    }
+}
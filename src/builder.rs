@@ -1,17 +1,26 @@
 //! Build operations and configuration
 
+use crate::docker::DockerDaemon;
 use crate::error::{Error, Result};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use crate::redact::{redact_option, Redacted, Scrubber};
+use crate::session::SshAgent;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWrite;
 
 /// Source location for Dockerfile
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub enum DockerfileSource {
     /// Local filesystem path
     Local {
         /// Path to the context directory
         context_path: PathBuf,
-        /// Path to the Dockerfile (relative to context or absolute)
+        /// Path to the Dockerfile, relative to the context or absolute; it
+        /// may be outside the context
         dockerfile_path: Option<PathBuf>,
     },
     /// GitHub repository
@@ -20,11 +29,183 @@ pub enum DockerfileSource {
         repo_url: String,
         /// Git reference (branch, tag, or commit SHA)
         git_ref: Option<String>,
-        /// Path to Dockerfile within the repository
+        /// Directory of the repository to use as the context
+        subdir: Option<String>,
+        /// Path to Dockerfile within the context
         dockerfile_path: Option<String>,
         /// GitHub token for private repositories
         token: Option<String>,
     },
+    /// Dockerfile content held in memory, built against a local context
+    Inline {
+        /// Dockerfile content
+        content: Vec<u8>,
+        /// Path to the context directory
+        context_path: PathBuf,
+    },
+    /// Any Git repository, over HTTPS or SSH
+    Git {
+        /// Remote URL, e.g. `https://gitlab.com/org/repo.git` or
+        /// `git@bitbucket.org:org/repo.git`
+        url: String,
+        /// Git reference (branch, tag, or commit SHA)
+        git_ref: Option<String>,
+        /// Directory of the repository to use as the context
+        subdir: Option<String>,
+        /// Path to the Dockerfile within the context
+        dockerfile_path: Option<String>,
+        /// Credentials, passed to BuildKit through the session
+        auth: Option<GitAuth>,
+    },
+    /// Tarball of the context downloaded by BuildKit, e.g. a source release
+    TarballUrl {
+        /// HTTP or HTTPS URL of the tarball
+        url: String,
+        /// Expected digest of the tarball, e.g. `sha256:<hex>`
+        checksum: Option<String>,
+        /// Path to the Dockerfile within the tarball
+        dockerfile_path: Option<String>,
+        /// HTTP headers sent with the download, e.g. `Authorization`
+        headers: BTreeMap<String, String>,
+    },
+}
+
+impl fmt::Debug for DockerfileSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DockerfileSource::Local {
+                context_path,
+                dockerfile_path,
+            } => f
+                .debug_struct("Local")
+                .field("context_path", context_path)
+                .field("dockerfile_path", dockerfile_path)
+                .finish(),
+            DockerfileSource::GitHub {
+                repo_url,
+                git_ref,
+                subdir,
+                dockerfile_path,
+                token,
+            } => f
+                .debug_struct("GitHub")
+                .field("repo_url", repo_url)
+                .field("git_ref", git_ref)
+                .field("subdir", subdir)
+                .field("dockerfile_path", dockerfile_path)
+                .field("token", &redact_option(token))
+                .finish(),
+            DockerfileSource::Inline {
+                content,
+                context_path,
+            } => f
+                .debug_struct("Inline")
+                .field("content", content)
+                .field("context_path", context_path)
+                .finish(),
+            DockerfileSource::Git {
+                url,
+                git_ref,
+                subdir,
+                dockerfile_path,
+                auth,
+            } => f
+                .debug_struct("Git")
+                .field("url", url)
+                .field("git_ref", git_ref)
+                .field("subdir", subdir)
+                .field("dockerfile_path", dockerfile_path)
+                .field("auth", auth)
+                .finish(),
+            DockerfileSource::TarballUrl {
+                url,
+                checksum,
+                dockerfile_path,
+                headers,
+            } => f
+                .debug_struct("TarballUrl")
+                .field("url", url)
+                .field("checksum", checksum)
+                .field("dockerfile_path", dockerfile_path)
+                .field(
+                    "headers",
+                    &headers
+                        .keys()
+                        .map(|name| (name, "<redacted>"))
+                        .collect::<BTreeMap<_, _>>(),
+                )
+                .finish(),
+        }
+    }
+}
+
+/// Secret BuildKit reads the token for HTTPS Git remotes from
+pub(crate) const GIT_AUTH_TOKEN: &str = "GIT_AUTH_TOKEN";
+
+/// Secret BuildKit reads the `Authorization` header for HTTPS Git remotes from
+pub(crate) const GIT_AUTH_HEADER: &str = "GIT_AUTH_HEADER";
+
+/// Secret holding the `Authorization` header of a tarball context download
+pub(crate) const TARBALL_AUTH_HEADER: &str = "TARBALL_AUTH_HEADER";
+
+/// Credentials for fetching a Git context
+///
+/// They reach BuildKit through the session's secrets and SSH services, so
+/// they never appear in the context URL, build logs or build history.
+#[derive(Clone)]
+pub enum GitAuth {
+    /// Token for HTTPS remotes, e.g. a GitHub or GitLab access token
+    Token(String),
+    /// Complete `Authorization` header value for HTTPS remotes, e.g.
+    /// `Basic <base64>` for Bitbucket app passwords
+    Header(String),
+    /// SSH agent for `ssh://` and `git@host:path` remotes
+    Ssh(SshAgent),
+}
+
+impl fmt::Debug for GitAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitAuth::Token(_) => f.write_str("Token(<redacted>)"),
+            GitAuth::Header(_) => f.write_str("Header(<redacted>)"),
+            GitAuth::Ssh(agent) => f.debug_tuple("Ssh").field(agent).finish(),
+        }
+    }
+}
+
+/// Fail unless `path` is an existing directory
+fn check_directory(what: &str, path: &Path) -> Result<()> {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => Err(Error::InvalidConfig(format!(
+            "{} {} is not a directory",
+            what,
+            path.display()
+        ))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::InvalidConfig(format!(
+            "{} {} not found",
+            what,
+            path.display()
+        ))),
+        Err(e) => Err(Error::file_operation("stat", path, e)),
+    }
+}
+
+/// Split the `#<ref>:<subdir>` fragment off a Git URL
+fn split_git_fragment(url: String) -> (String, Option<String>, Option<String>) {
+    let Some((url, fragment)) = url.split_once('#') else {
+        return (url, None, None);
+    };
+    let (git_ref, subdir) = match fragment.split_once(':') {
+        Some((git_ref, subdir)) => (git_ref, Some(subdir)),
+        None => (fragment, None),
+    };
+    let non_empty = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+    (
+        url.to_string(),
+        non_empty(git_ref),
+        subdir.and_then(non_empty),
+    )
 }
 
 /// Platform specification for multi-platform builds
@@ -83,8 +264,86 @@ impl std::fmt::Display for Platform {
     }
 }
 
+/// Registry operations a set of credentials applies to
+///
+/// Allows separate identities per host, e.g. a read-only account for a
+/// pull-through mirror and a write-scoped robot account for pushing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CredentialScope {
+    /// Used for both pulling and pushing
+    #[default]
+    Any,
+    /// Used only for pulling
+    Pull,
+    /// Used for pushing, and for pulling when no pull credentials exist
+    Push,
+}
+
+impl CredentialScope {
+    /// How well credentials with this scope fit a requested scope
+    ///
+    /// Lower is better; `None` means the credentials must not be used.
+    /// `Any` requests come from callers that don't know the operation and
+    /// prefer push credentials, which can usually pull as well.
+    pub(crate) fn preference(self, requested: CredentialScope) -> Option<u8> {
+        match (self, requested) {
+            (scope, requested) if scope == requested => Some(0),
+            (CredentialScope::Any, _) => Some(1),
+            (CredentialScope::Push, _) => Some(2),
+            (CredentialScope::Pull, CredentialScope::Any) => Some(3),
+            (CredentialScope::Pull, _) => None,
+        }
+    }
+}
+
+/// A privilege a build is granted beyond the default sandbox
+///
+/// BuildKit only grants entitlements its daemon allows, e.g. with
+/// `--allow-insecure-entitlement network.host`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Entitlement {
+    /// `RUN --network=host`: steps use the host's network
+    NetworkHost,
+    /// `RUN --security=insecure`: steps run privileged
+    SecurityInsecure,
+}
+
+impl Entitlement {
+    /// Parse an entitlement name such as `network.host`
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "network.host" => Ok(Entitlement::NetworkHost),
+            "security.insecure" => Ok(Entitlement::SecurityInsecure),
+            _ => Err(Error::InvalidConfig(format!(
+                "unknown entitlement {}, expected network.host or security.insecure",
+                name
+            ))),
+        }
+    }
+
+    /// Name BuildKit uses for the entitlement
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Entitlement::NetworkHost => "network.host",
+            Entitlement::SecurityInsecure => "security.insecure",
+        }
+    }
+}
+
+impl fmt::Display for Entitlement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Registry authentication credentials
-#[derive(Debug, Clone)]
+///
+/// Besides username and password, registries that issue identity tokens
+/// (e.g., `docker login` with an access token) or pre-fetched bearer tokens
+/// can be configured through `identity_token` and `registry_token`.
+///
+/// The `Debug` output never includes the password or tokens.
+#[derive(Clone, Default)]
 pub struct RegistryAuth {
     /// Registry host (e.g., "docker.io", "localhost:5000")
     pub host: String,
@@ -92,10 +351,893 @@ pub struct RegistryAuth {
     pub username: String,
     /// Password or token
     pub password: String,
+    /// OAuth refresh token used instead of username and password
+    pub identity_token: Option<String>,
+    /// Bearer token sent to the registry as-is, skipping token exchange
+    pub registry_token: Option<String>,
+    /// Operations these credentials are used for
+    pub scope: CredentialScope,
+}
+
+impl fmt::Debug for RegistryAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegistryAuth")
+            .field("host", &self.host)
+            .field("username", &self.username)
+            .field("password", &Redacted::new(&self.password))
+            .field("identity_token", &redact_option(&self.identity_token))
+            .field("registry_token", &redact_option(&self.registry_token))
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
+
+/// Frontend turning the build definition into a build graph
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Frontend {
+    /// BuildKit's built-in Dockerfile frontend, which also follows a
+    /// `# syntax=` directive in the Dockerfile
+    #[default]
+    Dockerfile,
+
+    /// A frontend image, e.g. `docker/dockerfile:1.7` to pin the Dockerfile
+    /// syntax, or a frontend for another build language
+    Gateway {
+        /// Image reference of the frontend
+        image: String,
+        /// Options passed to the frontend besides the build's own, such as
+        /// `build-arg:*`, `target` and `platform`
+        attrs: BTreeMap<String, String>,
+    },
+}
+
+impl Frontend {
+    /// Frontend image without extra options
+    pub fn gateway(image: impl Into<String>) -> Self {
+        Frontend::Gateway {
+            image: image.into(),
+            attrs: BTreeMap::new(),
+        }
+    }
+
+    /// Name of the frontend in a solve request
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Frontend::Dockerfile => crate::raw::DOCKERFILE_FRONTEND,
+            Frontend::Gateway { .. } => crate::raw::GATEWAY_FRONTEND,
+        }
+    }
+
+    /// Add the frontend's options to those of the build
+    ///
+    /// Options may not replace the build's own, so that e.g. a stray
+    /// `context` cannot point the frontend at another build context.
+    pub(crate) fn apply_attrs(&self, frontend_attrs: &mut HashMap<String, String>) -> Result<()> {
+        let Frontend::Gateway { image, attrs } = self else {
+            return Ok(());
+        };
+        if image.is_empty() {
+            return Err(Error::InvalidConfig("frontend image is empty".to_string()));
+        }
+        for (key, value) in std::iter::once(("source", image))
+            .chain(attrs.iter().map(|(key, value)| (key.as_str(), value)))
+        {
+            if frontend_attrs.contains_key(key) {
+                return Err(Error::InvalidConfig(format!(
+                    "frontend option {} is set by the build config",
+                    key
+                )));
+            }
+            frontend_attrs.insert(key.to_string(), value.clone());
+        }
+        Ok(())
+    }
+}
+
+/// A build output written on the client, besides pushing the tags
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Export {
+    /// Write the filesystem of the result into a local directory
+    ///
+    /// For multi-platform builds, each platform gets a subdirectory such
+    /// as `linux_amd64`.
+    Local(PathBuf),
+
+    /// Write the image as an OCI image layout tarball
+    ///
+    /// Multi-platform builds produce one tarball with an image index.
+    Oci(PathBuf),
+
+    /// Write the image as a tarball in the `docker save` format
+    Docker(PathBuf),
+
+    /// Load the image into a Docker daemon, without writing a tarball
+    DockerLoad(DockerDaemon),
+}
+
+/// Where the value of a secret is read from when the build starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretSource {
+    /// Contents of a file
+    File(PathBuf),
+    /// Value of an environment variable
+    Env(String),
+}
+
+impl SecretSource {
+    /// Parse a `--secret` flag in the buildx syntax
+    ///
+    /// `id=<id>,src=<path>` reads a file and `id=<id>,env=<var>` an
+    /// environment variable; `source` and `type=file|env` are accepted
+    /// too. Without a source, the variable named like the ID is read.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::SecretSource;
+    /// use std::path::PathBuf;
+    ///
+    /// assert_eq!(
+    ///     SecretSource::parse("id=npmrc,src=./.npmrc")?,
+    ///     ("npmrc".to_string(), SecretSource::File(PathBuf::from("./.npmrc")))
+    /// );
+    /// assert_eq!(
+    ///     SecretSource::parse("id=TOKEN")?,
+    ///     ("TOKEN".to_string(), SecretSource::Env("TOKEN".to_string()))
+    /// );
+    /// # Ok::<(), buildkit_client::Error>(())
+    /// ```
+    pub fn parse(spec: &str) -> Result<(String, Self)> {
+        let invalid =
+            |reason: &str| Error::InvalidConfig(format!("invalid secret {}: {}", spec, reason));
+
+        let mut id = None;
+        let mut kind = None;
+        let mut src = None;
+        let mut env = None;
+        for field in spec.split(',') {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| invalid("expected key=value fields"))?;
+            match key.trim() {
+                "id" => id = Some(value.to_string()),
+                "type" => kind = Some(value.to_string()),
+                "src" | "source" => src = Some(value.to_string()),
+                "env" => env = Some(value.to_string()),
+                other => return Err(invalid(&format!("unknown field {}", other))),
+            }
+        }
+
+        let id = id
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| invalid("missing id"))?;
+        let source = match kind.as_deref() {
+            None => match (src, env) {
+                (Some(path), _) => SecretSource::File(PathBuf::from(path)),
+                (None, Some(var)) => SecretSource::Env(var),
+                (None, None) => SecretSource::Env(id.clone()),
+            },
+            Some("file") => {
+                SecretSource::File(PathBuf::from(src.ok_or_else(|| invalid("missing src"))?))
+            }
+            // For environment secrets, `src` names the variable
+            Some("env") => SecretSource::Env(env.or(src).unwrap_or_else(|| id.clone())),
+            Some(other) => return Err(invalid(&format!("unknown type {}", other))),
+        };
+        Ok((id, source))
+    }
+
+    /// Read the secret value
+    pub fn read(&self) -> Result<Vec<u8>> {
+        match self {
+            SecretSource::File(path) => {
+                std::fs::read(path).map_err(|e| Error::file_operation("read secret", path, e))
+            }
+            SecretSource::Env(var) => std::env::var_os(var)
+                .map(|value| value.into_encoded_bytes())
+                .ok_or_else(|| {
+                    Error::SecretNotFound(format!("environment variable {} is not set", var))
+                }),
+        }
+    }
+}
+
+/// Default scope of GitHub Actions caches, as in buildx
+const DEFAULT_GHA_SCOPE: &str = "buildkit";
+
+/// Which layers a registry or GitHub Actions cache export includes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Only the layers of the exported image
+    Min,
+    /// The layers of every intermediate step
+    #[default]
+    Max,
+}
+
+impl CacheMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            CacheMode::Min => "min",
+            CacheMode::Max => "max",
+        }
+    }
+}
+
+/// The GitHub Actions cache service a cache is kept in
+///
+/// The `Debug` output never includes the token.
+#[derive(Clone, PartialEq, Eq)]
+pub struct GhaCache {
+    /// Cache service URL, `$ACTIONS_CACHE_URL` in a workflow
+    pub url: String,
+    /// Results service URL of the v2 cache API, `$ACTIONS_RESULTS_URL` in a
+    /// workflow
+    pub url_v2: Option<String>,
+    /// Runtime token of the workflow, `$ACTIONS_RUNTIME_TOKEN`
+    pub token: String,
+    /// Scope separating the caches of e.g. several images; `buildkit`
+    /// unless set
+    pub scope: String,
+}
+
+impl GhaCache {
+    /// Cache service settings
+    pub fn new(url: impl Into<String>, token: impl Into<String>, scope: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            url_v2: None,
+            token: token.into(),
+            scope: scope.into(),
+        }
+    }
+
+    /// Settings of the workflow this runs in, from `ACTIONS_CACHE_URL`,
+    /// `ACTIONS_RESULTS_URL` and `ACTIONS_RUNTIME_TOKEN`
+    ///
+    /// These are only set for steps that expose them, e.g. with
+    /// `crazy-max/ghaction-github-runtime`.
+    pub fn from_env(scope: impl Into<String>) -> Result<Self> {
+        let token = env_var("ACTIONS_RUNTIME_TOKEN")
+            .ok_or_else(|| Error::InvalidConfig("ACTIONS_RUNTIME_TOKEN is not set".to_string()))?;
+        let url = env_var("ACTIONS_CACHE_URL");
+        let url_v2 = env_var("ACTIONS_RESULTS_URL");
+        if url.is_none() && url_v2.is_none() {
+            return Err(Error::InvalidConfig(
+                "neither ACTIONS_CACHE_URL nor ACTIONS_RESULTS_URL is set".to_string(),
+            ));
+        }
+        Ok(Self {
+            url: url.unwrap_or_default(),
+            url_v2,
+            token,
+            scope: scope.into(),
+        })
+    }
+
+    /// Settings from cache attributes, taking missing URLs and the token
+    /// from the environment like buildx
+    fn from_attrs(mut attrs: BTreeMap<String, String>) -> Self {
+        let mut take = |key: &str, var: &str| attrs.remove(key).or_else(|| env_var(var));
+        Self {
+            url: take("url", "ACTIONS_CACHE_URL").unwrap_or_default(),
+            url_v2: take("url_v2", "ACTIONS_RESULTS_URL"),
+            token: take("token", "ACTIONS_RUNTIME_TOKEN").unwrap_or_default(),
+            scope: attrs
+                .remove("scope")
+                .unwrap_or_else(|| DEFAULT_GHA_SCOPE.to_string()),
+        }
+    }
+
+    fn attrs(&self) -> BTreeMap<String, String> {
+        let mut attrs = BTreeMap::from([
+            ("url".to_string(), self.url.clone()),
+            ("token".to_string(), self.token.clone()),
+            ("scope".to_string(), self.scope.clone()),
+        ]);
+        if let Some(url_v2) = &self.url_v2 {
+            attrs.insert("url_v2".to_string(), url_v2.clone());
+        }
+        attrs
+    }
+}
+
+impl fmt::Debug for GhaCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GhaCache")
+            .field("url", &self.url)
+            .field("url_v2", &self.url_v2)
+            .field("token", &Redacted::new(&self.token))
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
+
+/// A cache in an S3 bucket or an S3-compatible service
+///
+/// Credentials left unset are taken from `AWS_ACCESS_KEY_ID`,
+/// `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` when the cache is
+/// created; without any, BuildKit uses its own. The `Debug` output never
+/// includes the secret key or session token.
+#[derive(Clone, PartialEq, Eq)]
+pub struct S3Cache {
+    /// Bucket name
+    pub bucket: String,
+    /// Bucket region
+    pub region: String,
+    /// Prefix of the cache's objects in the bucket
+    pub prefix: Option<String>,
+    /// Name of the cache manifest, `buildkit` unless set
+    pub name: Option<String>,
+    /// Endpoint of an S3-compatible service, e.g. MinIO
+    pub endpoint_url: Option<String>,
+    /// Address the bucket in the path instead of the host name
+    pub use_path_style: bool,
+    /// Access key ID
+    pub access_key_id: Option<String>,
+    /// Secret access key
+    pub secret_access_key: Option<String>,
+    /// Session token of temporary credentials
+    pub session_token: Option<String>,
+}
+
+impl S3Cache {
+    /// Cache in `bucket`, with credentials from the environment
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::{BuildConfig, S3Cache};
+    ///
+    /// let config = BuildConfig::local("./app").cache_s3(
+    ///     S3Cache::new("build-cache", "eu-west-1")
+    ///         .prefix("app/")
+    ///         .endpoint_url("http://minio:9000"),
+    /// );
+    /// ```
+    pub fn new(bucket: impl Into<String>, region: impl Into<String>) -> Self {
+        Self {
+            bucket: bucket.into(),
+            region: region.into(),
+            prefix: None,
+            name: None,
+            endpoint_url: None,
+            use_path_style: false,
+            access_key_id: env_var("AWS_ACCESS_KEY_ID"),
+            secret_access_key: env_var("AWS_SECRET_ACCESS_KEY"),
+            session_token: env_var("AWS_SESSION_TOKEN"),
+        }
+    }
+
+    /// Set the prefix of the cache's objects
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Set the name of the cache manifest
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Use an S3-compatible service, addressing buckets by path
+    pub fn endpoint_url(mut self, url: impl Into<String>) -> Self {
+        self.endpoint_url = Some(url.into());
+        self.use_path_style = true;
+        self
+    }
+
+    /// Set the credentials, replacing those from the environment
+    pub fn credentials(
+        mut self,
+        access_key_id: impl Into<String>,
+        secret_access_key: impl Into<String>,
+    ) -> Self {
+        self.access_key_id = Some(access_key_id.into());
+        self.secret_access_key = Some(secret_access_key.into());
+        self.session_token = None;
+        self
+    }
+
+    /// Set the session token of temporary credentials
+    pub fn session_token(mut self, token: impl Into<String>) -> Self {
+        self.session_token = Some(token.into());
+        self
+    }
+
+    /// Settings from cache attributes, taking missing credentials and
+    /// the region from the environment
+    fn from_attrs(mut attrs: BTreeMap<String, String>) -> Self {
+        let region = attrs
+            .remove("region")
+            .or_else(|| env_var("AWS_REGION"))
+            .or_else(|| env_var("AWS_DEFAULT_REGION"))
+            .unwrap_or_default();
+        let mut cache = S3Cache::new(attrs.remove("bucket").unwrap_or_default(), region);
+        cache.prefix = attrs.remove("prefix");
+        cache.name = attrs.remove("name");
+        cache.endpoint_url = attrs.remove("endpoint_url");
+        cache.use_path_style = attrs.remove("use_path_style").as_deref() == Some("true");
+        if let Some(key) = attrs.remove("access_key_id") {
+            cache.access_key_id = Some(key);
+        }
+        if let Some(secret) = attrs.remove("secret_access_key") {
+            cache.secret_access_key = Some(secret);
+        }
+        if let Some(token) = attrs.remove("session_token") {
+            cache.session_token = Some(token);
+        }
+        cache
+    }
+
+    fn attrs(&self) -> BTreeMap<String, String> {
+        let mut attrs = BTreeMap::from([
+            ("bucket".to_string(), self.bucket.clone()),
+            ("region".to_string(), self.region.clone()),
+        ]);
+        let optional = [
+            ("prefix", &self.prefix),
+            ("name", &self.name),
+            ("endpoint_url", &self.endpoint_url),
+            ("access_key_id", &self.access_key_id),
+            ("secret_access_key", &self.secret_access_key),
+            ("session_token", &self.session_token),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                attrs.insert(key.to_string(), value.clone());
+            }
+        }
+        if self.use_path_style {
+            attrs.insert("use_path_style".to_string(), "true".to_string());
+        }
+        attrs
+    }
+}
+
+impl fmt::Debug for S3Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Cache")
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("prefix", &self.prefix)
+            .field("name", &self.name)
+            .field("endpoint_url", &self.endpoint_url)
+            .field("use_path_style", &self.use_path_style)
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &redact_option(&self.secret_access_key))
+            .field("session_token", &redact_option(&self.session_token))
+            .finish()
+    }
+}
+
+/// Attributes of [`GhaCache`]
+const GHA_ATTRS: &[&str] = &["url", "url_v2", "token", "scope"];
+/// Attributes of [`S3Cache`]
+const S3_ATTRS: &[&str] = &[
+    "bucket",
+    "region",
+    "prefix",
+    "name",
+    "endpoint_url",
+    "use_path_style",
+    "access_key_id",
+    "secret_access_key",
+    "session_token",
+];
+/// Cache attributes holding credentials, left out of displayed specs
+const SECRET_CACHE_ATTRS: &[&str] = &["token", "secret_access_key", "session_token"];
+
+/// Whether cache attributes are all in `known`, besides `extra`
+fn known_attrs(attrs: &BTreeMap<String, String>, known: &[&str], extra: &str) -> bool {
+    attrs
+        .keys()
+        .all(|key| known.contains(&key.as_str()) || key == extra)
+}
+
+/// Non-empty value of an environment variable
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Where build cache is imported from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheImport {
+    /// Cache in a registry, exported by a registry or inline cache export
+    Registry {
+        /// Image reference of the cache
+        reference: String,
+    },
+    /// GitHub Actions cache service
+    Gha(GhaCache),
+    /// S3 bucket
+    S3(S3Cache),
+    /// Any cache backend with its attributes, e.g. `type=azblob`
+    Other {
+        /// Backend type
+        cache_type: String,
+        /// Backend attributes
+        attrs: BTreeMap<String, String>,
+    },
+}
+
+impl CacheImport {
+    /// Parse a `--cache-from` value in the buildx syntax
+    ///
+    /// A plain image reference is a registry cache; `type=<type>,<key>=<value>...`
+    /// selects any backend. Unknown types and attributes are passed to
+    /// BuildKit as they are. A `type=gha` cache without `url` or `token`
+    /// takes them from the environment of the workflow, and a `type=s3`
+    /// cache takes missing credentials and region from the AWS variables.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::CacheImport;
+    ///
+    /// assert_eq!(
+    ///     CacheImport::parse("type=registry,ref=registry.example.com/app:cache"),
+    ///     CacheImport::Registry { reference: "registry.example.com/app:cache".to_string() }
+    /// );
+    /// assert_eq!(CacheImport::parse("registry.example.com/app:cache"), CacheImport::parse("type=registry,ref=registry.example.com/app:cache"));
+    /// ```
+    pub fn parse(spec: &str) -> Self {
+        let (cache_type, mut attrs) = parse_cache_spec(spec);
+        match cache_type.as_str() {
+            "registry" if attrs.len() == 1 && attrs.contains_key("ref") => CacheImport::Registry {
+                reference: attrs.remove("ref").unwrap_or_default(),
+            },
+            "gha" if known_attrs(&attrs, GHA_ATTRS, "") => {
+                CacheImport::Gha(GhaCache::from_attrs(attrs))
+            }
+            "s3" if known_attrs(&attrs, S3_ATTRS, "") => {
+                CacheImport::S3(S3Cache::from_attrs(attrs))
+            }
+            _ => CacheImport::Other { cache_type, attrs },
+        }
+    }
+
+    /// Backend type, e.g. `registry`
+    pub fn cache_type(&self) -> &str {
+        match self {
+            CacheImport::Registry { .. } => "registry",
+            CacheImport::Gha(_) => "gha",
+            CacheImport::S3(_) => "s3",
+            CacheImport::Other { cache_type, .. } => cache_type,
+        }
+    }
+
+    /// Backend attributes
+    pub fn attrs(&self) -> BTreeMap<String, String> {
+        match self {
+            CacheImport::Registry { reference } => {
+                BTreeMap::from([("ref".to_string(), reference.clone())])
+            }
+            CacheImport::Gha(cache) => cache.attrs(),
+            CacheImport::S3(cache) => cache.attrs(),
+            CacheImport::Other { attrs, .. } => attrs.clone(),
+        }
+    }
+}
+
+impl From<&str> for CacheImport {
+    fn from(spec: &str) -> Self {
+        CacheImport::parse(spec)
+    }
+}
+
+impl From<String> for CacheImport {
+    fn from(spec: String) -> Self {
+        CacheImport::parse(&spec)
+    }
+}
+
+/// The spec, without credentials
+impl fmt::Display for CacheImport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_cache_spec(f, self.cache_type(), &self.attrs())
+    }
+}
+
+/// Where build cache is exported to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheExport {
+    /// Cache metadata embedded in the pushed image, like
+    /// `BUILDKIT_INLINE_CACHE=1`; always [`CacheMode::Min`]
+    Inline,
+    /// Cache image in a registry
+    Registry {
+        /// Image reference of the cache
+        reference: String,
+        /// Layers to include
+        mode: CacheMode,
+    },
+    /// GitHub Actions cache service
+    Gha {
+        /// Service settings
+        cache: GhaCache,
+        /// Layers to include
+        mode: CacheMode,
+    },
+    /// S3 bucket
+    S3 {
+        /// Bucket settings
+        cache: S3Cache,
+        /// Layers to include
+        mode: CacheMode,
+    },
+    /// Any cache backend with its attributes, e.g. `type=azblob`
+    Other {
+        /// Backend type
+        cache_type: String,
+        /// Backend attributes
+        attrs: BTreeMap<String, String>,
+    },
+}
+
+impl CacheExport {
+    /// Parse a `--cache-to` value in the buildx syntax
+    ///
+    /// A plain image reference is a registry cache; `type=inline` embeds
+    /// the cache in the image, and `type=<type>,<key>=<value>...` selects
+    /// any backend. Registry, GitHub Actions and S3 caches default to
+    /// `mode=max`, unlike buildx; see [`CacheImport::parse`] for the
+    /// environment `type=gha` and `type=s3` caches read.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::{CacheExport, CacheMode};
+    ///
+    /// assert_eq!(CacheExport::parse("type=inline"), CacheExport::Inline);
+    /// assert_eq!(
+    ///     CacheExport::parse("type=registry,ref=registry.example.com/app:cache,mode=min"),
+    ///     CacheExport::Registry {
+    ///         reference: "registry.example.com/app:cache".to_string(),
+    ///         mode: CacheMode::Min,
+    ///     }
+    /// );
+    /// ```
+    pub fn parse(spec: &str) -> Self {
+        let (cache_type, mut attrs) = parse_cache_spec(spec);
+        let mode = match attrs.get("mode").map(String::as_str) {
+            None => Some(CacheMode::Max),
+            Some("min") => Some(CacheMode::Min),
+            Some("max") => Some(CacheMode::Max),
+            Some(_) => None,
+        };
+        let known = attrs.keys().all(|key| key == "ref" || key == "mode");
+        match (cache_type.as_str(), mode) {
+            ("inline", _) if attrs.is_empty() => CacheExport::Inline,
+            ("registry", Some(mode)) if known && attrs.contains_key("ref") => {
+                CacheExport::Registry {
+                    reference: attrs.remove("ref").unwrap_or_default(),
+                    mode,
+                }
+            }
+            ("gha", Some(mode)) if known_attrs(&attrs, GHA_ATTRS, "mode") => {
+                attrs.remove("mode");
+                CacheExport::Gha {
+                    cache: GhaCache::from_attrs(attrs),
+                    mode,
+                }
+            }
+            ("s3", Some(mode)) if known_attrs(&attrs, S3_ATTRS, "mode") => {
+                attrs.remove("mode");
+                CacheExport::S3 {
+                    cache: S3Cache::from_attrs(attrs),
+                    mode,
+                }
+            }
+            _ => CacheExport::Other { cache_type, attrs },
+        }
+    }
+
+    /// Export to a registry with the given mode
+    pub fn registry(reference: impl Into<String>, mode: CacheMode) -> Self {
+        CacheExport::Registry {
+            reference: reference.into(),
+            mode,
+        }
+    }
+
+    /// Backend type, e.g. `registry`
+    pub fn cache_type(&self) -> &str {
+        match self {
+            CacheExport::Inline => "inline",
+            CacheExport::Registry { .. } => "registry",
+            CacheExport::Gha { .. } => "gha",
+            CacheExport::S3 { .. } => "s3",
+            CacheExport::Other { cache_type, .. } => cache_type,
+        }
+    }
+
+    /// Backend attributes
+    pub fn attrs(&self) -> BTreeMap<String, String> {
+        match self {
+            CacheExport::Inline => BTreeMap::new(),
+            CacheExport::Registry { reference, mode } => BTreeMap::from([
+                ("ref".to_string(), reference.clone()),
+                ("mode".to_string(), mode.as_str().to_string()),
+            ]),
+            CacheExport::Gha { cache, mode } => {
+                let mut attrs = cache.attrs();
+                attrs.insert("mode".to_string(), mode.as_str().to_string());
+                attrs
+            }
+            CacheExport::S3 { cache, mode } => {
+                let mut attrs = cache.attrs();
+                attrs.insert("mode".to_string(), mode.as_str().to_string());
+                attrs
+            }
+            CacheExport::Other { attrs, .. } => attrs.clone(),
+        }
+    }
+}
+
+impl From<&str> for CacheExport {
+    fn from(spec: &str) -> Self {
+        CacheExport::parse(spec)
+    }
+}
+
+impl From<String> for CacheExport {
+    fn from(spec: String) -> Self {
+        CacheExport::parse(&spec)
+    }
+}
+
+/// The spec, without credentials
+impl fmt::Display for CacheExport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_cache_spec(f, self.cache_type(), &self.attrs())
+    }
+}
+
+/// Type and attributes of a cache spec; a spec without `=` is a registry
+/// reference
+fn parse_cache_spec(spec: &str) -> (String, BTreeMap<String, String>) {
+    if !spec.contains('=') {
+        return (
+            "registry".to_string(),
+            BTreeMap::from([("ref".to_string(), spec.to_string())]),
+        );
+    }
+    let mut cache_type = String::new();
+    let mut attrs = BTreeMap::new();
+    for field in spec.split(',').filter(|field| !field.is_empty()) {
+        let (key, value) = field.split_once('=').unwrap_or((field, ""));
+        match key.trim() {
+            "type" => cache_type = value.to_string(),
+            key => {
+                attrs.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+    (cache_type, attrs)
+}
+
+fn write_cache_spec(
+    f: &mut fmt::Formatter<'_>,
+    cache_type: &str,
+    attrs: &BTreeMap<String, String>,
+) -> fmt::Result {
+    write!(f, "type={}", cache_type)?;
+    for (key, value) in attrs
+        .iter()
+        .filter(|(key, _)| !SECRET_CACHE_ATTRS.contains(&key.as_str()))
+    {
+        write!(f, ",{}={}", key, value)?;
+    }
+    Ok(())
+}
+
+/// Source of a named build context, used by `FROM <name>` and
+/// `COPY --from=<name>` instead of a stage or image of that name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NamedContext {
+    /// Local directory, served by the session
+    Local(PathBuf),
+    /// Container image reference
+    Image(String),
+    /// Git repository or HTTP(S) URL
+    Url(String),
+}
+
+impl NamedContext {
+    /// Parse a `--build-context` flag in the buildx syntax
+    ///
+    /// `<name>=docker-image://<ref>` uses an image, URLs such as
+    /// `https://github.com/user/repo.git#main` or `git@host:repo.git` are
+    /// passed to BuildKit, and anything else is a local directory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::NamedContext;
+    /// use std::path::PathBuf;
+    ///
+    /// assert_eq!(
+    ///     NamedContext::parse("alpine=docker-image://alpine:3.20")?,
+    ///     ("alpine".to_string(), NamedContext::Image("alpine:3.20".to_string()))
+    /// );
+    /// assert_eq!(
+    ///     NamedContext::parse("assets=../assets")?,
+    ///     ("assets".to_string(), NamedContext::Local(PathBuf::from("../assets")))
+    /// );
+    /// # Ok::<(), buildkit_client::Error>(())
+    /// ```
+    pub fn parse(spec: &str) -> Result<(String, Self)> {
+        let invalid = |reason: &str| {
+            Error::InvalidConfig(format!("invalid build context {}: {}", spec, reason))
+        };
+
+        let (name, value) = spec
+            .split_once('=')
+            .ok_or_else(|| invalid("expected name=value"))?;
+        if name.is_empty() {
+            return Err(invalid("missing name"));
+        }
+        if value.is_empty() {
+            return Err(invalid("missing value"));
+        }
+
+        let source = if let Some(reference) = value.strip_prefix("docker-image://") {
+            NamedContext::Image(reference.to_string())
+        } else if value.contains("://") || value.starts_with("git@") {
+            NamedContext::Url(value.to_string())
+        } else {
+            NamedContext::Local(PathBuf::from(value))
+        };
+        Ok((name.to_string(), source))
+    }
+
+    /// Value of the `context:<name>` frontend option
+    pub(crate) fn frontend_value(&self, name: &str) -> String {
+        match self {
+            // Served by the session under the context's name
+            NamedContext::Local(_) => format!("local:{}", name),
+            NamedContext::Image(reference) => format!("docker-image://{}", reference),
+            NamedContext::Url(url) => url.clone(),
+        }
+    }
+}
+
+/// Destination of a build's log, see [`BuildConfig::log_output`]
+#[derive(Clone)]
+pub enum LogSink {
+    /// A file, created or truncated when the build starts
+    File(PathBuf),
+    /// A writer shared with the caller, e.g. a socket or an in-memory buffer
+    Writer(Arc<tokio::sync::Mutex<dyn AsyncWrite + Send + Unpin>>),
+}
+
+impl LogSink {
+    /// Log to a file
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        LogSink::File(path.into())
+    }
+
+    /// Log to a writer
+    pub fn writer(writer: impl AsyncWrite + Send + Unpin + 'static) -> Self {
+        LogSink::Writer(Arc::new(tokio::sync::Mutex::new(writer)))
+    }
+}
+
+impl fmt::Debug for LogSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogSink::File(path) => f.debug_tuple("File").field(path).finish(),
+            LogSink::Writer(_) => f.write_str("Writer"),
+        }
+    }
 }
 
 /// Build configuration
-#[derive(Debug, Clone)]
+///
+/// The `Debug` output redacts credentials and secret values.
+#[derive(Clone)]
 pub struct BuildConfig {
     /// Dockerfile source
     pub source: DockerfileSource,
@@ -112,26 +1254,97 @@ pub struct BuildConfig {
     /// Image tags to push
     pub tags: Vec<String>,
 
+    /// Push the tags to their registries (default true)
+    ///
+    /// When disabled, tags only name the images of client-side exports.
+    pub push: bool,
+
     /// Registry authentication
     pub registry_auth: Option<RegistryAuth>,
 
+    /// Credentials for additional registry hosts
+    pub registry_auths: Vec<RegistryAuth>,
+
+    /// Fall back to the Docker CLI configuration for registry credentials
+    ///
+    /// See [`DockerConfig`](crate::session::DockerConfig).
+    pub docker_credentials: bool,
+
+    /// Explicit insecure (plain HTTP or unverified TLS) setting per registry host
+    ///
+    /// Hosts that are not listed fall back to a guess based on the host name.
+    pub insecure_registries: HashMap<String, bool>,
+
     /// Cache imports (registry or local paths)
-    pub cache_from: Vec<String>,
+    pub cache_from: Vec<CacheImport>,
 
     /// Cache exports
-    pub cache_to: Vec<String>,
+    pub cache_to: Vec<CacheExport>,
 
     /// Secrets to mount during build
     pub secrets: HashMap<String, String>,
 
-    /// SSH agent sockets to forward
-    pub ssh_agents: Vec<String>,
+    /// Secrets read from files or environment variables when the build starts
+    pub secret_sources: HashMap<String, SecretSource>,
+
+    /// SSH agents to forward, by the ID `RUN --mount=type=ssh` uses
+    pub ssh_agents: HashMap<String, SshAgent>,
+
+    /// Additional build contexts by name
+    pub named_contexts: HashMap<String, NamedContext>,
+
+    /// Privileges granted to the build
+    pub entitlements: Vec<Entitlement>,
+
+    /// Source policy JSON file, read when the build starts
+    pub source_policy: Option<PathBuf>,
+
+    /// Deadline of the solve, after which the build is cancelled
+    pub timeout: Option<Duration>,
 
     /// No cache flag
     pub no_cache: bool,
 
     /// Pull always flag
     pub pull: bool,
+
+    /// Only upload the context paths the Dockerfile reads
+    ///
+    /// Best effort: the sources of `COPY`/`ADD` and context bind mounts
+    /// are parsed from the Dockerfile, and `.dockerignore` is applied. The
+    /// whole context is sent when the sources cannot be determined.
+    pub prune_context: bool,
+
+    /// Snapshot the context at build start
+    ///
+    /// Files are hashed and kept open when the build starts, so edits made
+    /// while the build runs are not picked up; a file modified in place
+    /// fails the build instead of uploading half-saved content.
+    pub snapshot_context: bool,
+
+    /// Directory keeping content hashes of context files between builds
+    ///
+    /// See [`FileSyncServer::with_change_cache`](crate::session::FileSyncServer::with_change_cache).
+    pub change_cache: Option<PathBuf>,
+
+    /// Containerd-style filters the worker running the build must match
+    ///
+    /// For example `platforms==linux/arm64` or
+    /// `labels."org.mobyproject.buildkit.worker.hostname"==arm-builder-1`.
+    pub worker_constraints: Vec<String>,
+
+    /// Outputs written on the client
+    pub exports: Vec<Export>,
+
+    /// Where to write the log of every step, whatever the progress handler
+    pub log_output: Option<LogSink>,
+
+    /// Attestations to attach to the image, by type (`provenance`, `sbom`),
+    /// with their parameters, e.g. `mode=max`
+    pub attestations: BTreeMap<String, String>,
+
+    /// Frontend building the Dockerfile
+    pub frontend: Frontend,
 }
 
 impl Default for BuildConfig {
@@ -145,17 +1358,76 @@ impl Default for BuildConfig {
             target: None,
             platforms: vec![Platform::linux_amd64()],
             tags: Vec::new(),
+            push: true,
             registry_auth: None,
+            registry_auths: Vec::new(),
+            docker_credentials: false,
+            insecure_registries: HashMap::new(),
             cache_from: Vec::new(),
             cache_to: Vec::new(),
             secrets: HashMap::new(),
-            ssh_agents: Vec::new(),
+            secret_sources: HashMap::new(),
+            ssh_agents: HashMap::new(),
+            named_contexts: HashMap::new(),
+            entitlements: Vec::new(),
+            source_policy: None,
+            timeout: None,
             no_cache: false,
             pull: false,
+            prune_context: false,
+            snapshot_context: false,
+            change_cache: None,
+            worker_constraints: Vec::new(),
+            exports: Vec::new(),
+            log_output: None,
+            attestations: BTreeMap::new(),
+            frontend: Frontend::default(),
         }
     }
 }
 
+impl fmt::Debug for BuildConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secrets: HashMap<&String, Redacted<&String>> = self
+            .secrets
+            .iter()
+            .map(|(id, value)| (id, Redacted::new(value)))
+            .collect();
+
+        f.debug_struct("BuildConfig")
+            .field("source", &self.source)
+            .field("build_args", &self.build_args)
+            .field("target", &self.target)
+            .field("platforms", &self.platforms)
+            .field("tags", &self.tags)
+            .field("push", &self.push)
+            .field("registry_auth", &self.registry_auth)
+            .field("registry_auths", &self.registry_auths)
+            .field("docker_credentials", &self.docker_credentials)
+            .field("insecure_registries", &self.insecure_registries)
+            .field("cache_from", &self.cache_from)
+            .field("cache_to", &self.cache_to)
+            .field("secrets", &secrets)
+            .field("secret_sources", &self.secret_sources)
+            .field("ssh_agents", &self.ssh_agents)
+            .field("named_contexts", &self.named_contexts)
+            .field("entitlements", &self.entitlements)
+            .field("source_policy", &self.source_policy)
+            .field("timeout", &self.timeout)
+            .field("no_cache", &self.no_cache)
+            .field("pull", &self.pull)
+            .field("prune_context", &self.prune_context)
+            .field("snapshot_context", &self.snapshot_context)
+            .field("change_cache", &self.change_cache)
+            .field("worker_constraints", &self.worker_constraints)
+            .field("exports", &self.exports)
+            .field("log_output", &self.log_output)
+            .field("attestations", &self.attestations)
+            .field("frontend", &self.frontend)
+            .finish()
+    }
+}
+
 impl BuildConfig {
     /// Create a new build configuration with local Dockerfile
     pub fn local(context_path: impl Into<PathBuf>) -> Self {
@@ -169,11 +1441,16 @@ impl BuildConfig {
     }
 
     /// Create a new build configuration with GitHub repository
+    ///
+    /// A `#<ref>:<subdir>` fragment selects the reference and the context
+    /// directory, as in `https://github.com/org/monorepo.git#main:services/api`.
     pub fn github(repo_url: impl Into<String>) -> Self {
+        let (repo_url, git_ref, subdir) = split_git_fragment(repo_url.into());
         Self {
             source: DockerfileSource::GitHub {
-                repo_url: repo_url.into(),
-                git_ref: None,
+                repo_url,
+                git_ref,
+                subdir,
                 dockerfile_path: None,
                 token: None,
             },
@@ -181,7 +1458,75 @@ impl BuildConfig {
         }
     }
 
+    /// Create a new build configuration with any Git repository
+    ///
+    /// Like [`github`](Self::github), a `#<ref>:<subdir>` fragment selects
+    /// the reference and context directory; credentials are set with
+    /// [`git_auth`](Self::git_auth). HTTPS URLs without a `.git` suffix get one, so that BuildKit
+    /// recognises them as repositories.
+    ///
+    /// # Example
+    /// ```
+    /// use buildkit_client::{BuildConfig, GitAuth};
+    ///
+    /// let config = BuildConfig::git("https://gitlab.com/org/repo.git")
+    ///     .git_ref("v1.2.0")
+    ///     .git_auth(GitAuth::Token("glpat-xxxx".to_string()));
+    /// ```
+    pub fn git(url: impl Into<String>) -> Self {
+        let (url, git_ref, subdir) = split_git_fragment(url.into());
+        Self {
+            source: DockerfileSource::Git {
+                url,
+                git_ref,
+                subdir,
+                dockerfile_path: None,
+                auth: None,
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Create a new build configuration whose context is a tarball BuildKit
+    /// downloads from an HTTP or HTTPS URL
+    ///
+    /// # Example
+    /// ```
+    /// use buildkit_client::BuildConfig;
+    ///
+    /// let config = BuildConfig::tarball_url("https://example.com/app-1.2.0.tar.gz")
+    ///     .tarball_checksum("sha256:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")
+    ///     .dockerfile("app-1.2.0/Dockerfile");
+    /// ```
+    pub fn tarball_url(url: impl Into<String>) -> Self {
+        Self {
+            source: DockerfileSource::TarballUrl {
+                url: url.into(),
+                checksum: None,
+                dockerfile_path: None,
+                headers: BTreeMap::new(),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Create a new build configuration from in-memory Dockerfile content
+    ///
+    /// The content is served to BuildKit as a virtual `Dockerfile`, while the
+    /// build context is still read from `context_path`.
+    pub fn inline(content: impl Into<Vec<u8>>, context_path: impl Into<PathBuf>) -> Self {
+        Self {
+            source: DockerfileSource::Inline {
+                content: content.into(),
+                context_path: context_path.into(),
+            },
+            ..Default::default()
+        }
+    }
+
     /// Set Dockerfile path
+    ///
+    /// Has no effect for inline Dockerfiles.
     pub fn dockerfile(mut self, path: impl Into<String>) -> Self {
         match &mut self.source {
             DockerfileSource::Local {
@@ -194,6 +1539,15 @@ impl BuildConfig {
             } => {
                 *dockerfile_path = Some(path.into());
             }
+            DockerfileSource::Git {
+                dockerfile_path, ..
+            }
+            | DockerfileSource::TarballUrl {
+                dockerfile_path, ..
+            } => {
+                *dockerfile_path = Some(path.into());
+            }
+            DockerfileSource::Inline { .. } => {}
         }
         self
     }
@@ -228,7 +1582,315 @@ impl BuildConfig {
         self
     }
 
+    /// Add credentials for another registry host
+    ///
+    /// Use this when tags are pushed to several registries in one build;
+    /// each host's credentials are offered to BuildKit when it pushes there.
+    pub fn add_registry_auth(mut self, auth: RegistryAuth) -> Self {
+        self.registry_auths.push(auth);
+        self
+    }
+
+    /// Use credentials from `~/.docker/config.json` for registries without
+    /// explicit credentials, including its credential helpers
+    pub fn docker_credentials(mut self, enabled: bool) -> Self {
+        self.docker_credentials = enabled;
+        self
+    }
+
+    /// Mark a registry host as insecure (plain HTTP) or secure (HTTPS)
+    ///
+    /// Overrides the host-name based guess, e.g. for cluster-internal
+    /// plain-HTTP registries or TLS-enabled registries on localhost.
+    pub fn insecure_registry(mut self, host: impl Into<String>, insecure: bool) -> Self {
+        self.insecure_registries.insert(host.into(), insecure);
+        self
+    }
+
+    /// All configured registry credentials
+    pub fn all_registry_auths(&self) -> impl Iterator<Item = &RegistryAuth> {
+        self.registry_auth.iter().chain(&self.registry_auths)
+    }
+
+    /// Stable hash identifying what this configuration builds
+    ///
+    /// Covers the source, Dockerfile, build arguments, target, platforms,
+    /// tags, cache settings and secret IDs. Credentials and secret values are
+    /// left out, so rotating a token does not change the hash. Returned as
+    /// `sha256:<hex>`.
+    pub fn config_hash(&self) -> String {
+        let source = match &self.source {
+            DockerfileSource::Local {
+                context_path,
+                dockerfile_path,
+            } => serde_json::json!({
+                "local": context_path,
+                "dockerfile": dockerfile_path,
+            }),
+            DockerfileSource::GitHub {
+                repo_url,
+                git_ref,
+                subdir,
+                dockerfile_path,
+                ..
+            } => {
+                let mut source = serde_json::json!({
+                    "github": repo_url,
+                    "ref": git_ref,
+                    "dockerfile": dockerfile_path,
+                });
+                // Only set when used, to keep the hashes of existing configs
+                if let Some(subdir) = subdir {
+                    source["subdir"] = serde_json::json!(subdir);
+                }
+                source
+            }
+            DockerfileSource::Inline {
+                content,
+                context_path,
+            } => serde_json::json!({
+                "local": context_path,
+                "inline": format!("{:x}", Sha256::digest(content)),
+            }),
+            DockerfileSource::Git {
+                url,
+                git_ref,
+                subdir,
+                dockerfile_path,
+                ..
+            } => serde_json::json!({
+                "git": url,
+                "ref": git_ref,
+                "subdir": subdir,
+                "dockerfile": dockerfile_path,
+            }),
+            DockerfileSource::TarballUrl {
+                url,
+                checksum,
+                dockerfile_path,
+                ..
+            } => serde_json::json!({
+                "tarball": url,
+                "checksum": checksum,
+                "dockerfile": dockerfile_path,
+            }),
+        };
+        let mut tags = self.tags.clone();
+        tags.sort();
+        let mut secret_ids: Vec<&String> = self
+            .secrets
+            .keys()
+            .chain(self.secret_sources.keys())
+            .collect();
+        secret_ids.sort();
+        let named_contexts: BTreeMap<&String, String> = self
+            .named_contexts
+            .iter()
+            .map(|(name, source)| {
+                let value = match source {
+                    NamedContext::Local(path) => path.to_string_lossy().to_string(),
+                    other => other.frontend_value(name),
+                };
+                (name, value)
+            })
+            .collect();
+
+        let canonical = serde_json::json!({
+            "source": source,
+            "build_args": self.build_args.iter().collect::<BTreeMap<_, _>>(),
+            "target": self.target,
+            "platforms": self.platforms.iter().map(Platform::to_string).collect::<Vec<_>>(),
+            "tags": tags,
+            "cache_from": self.cache_from.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "cache_to": self.cache_to.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "secrets": secret_ids,
+            "named_contexts": named_contexts,
+            "no_cache": self.no_cache,
+            "pull": self.pull,
+        });
+        format!("sha256:{:x}", Sha256::digest(canonical.to_string()))
+    }
+
+    /// Check the local paths of the build before starting it
+    ///
+    /// The context and local named contexts must be directories, and the
+    /// Dockerfile of a local build a file within the context. Errors name
+    /// the exact path, instead of BuildKit failing mid-build when it cannot
+    /// fetch it. Builds run this check before they start.
+    ///
+    /// # Example
+    /// ```
+    /// use buildkit_client::{BuildConfig, Error};
+    ///
+    /// let config = BuildConfig::local("./does-not-exist");
+    /// assert!(matches!(config.validate(), Err(Error::InvalidConfig(_))));
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        match &self.source {
+            DockerfileSource::Local {
+                context_path,
+                dockerfile_path,
+            } => {
+                check_directory("build context", context_path)?;
+                let dockerfile = context_path.join(
+                    dockerfile_path
+                        .as_deref()
+                        .unwrap_or(Path::new("Dockerfile")),
+                );
+                match std::fs::metadata(&dockerfile) {
+                    Ok(metadata) if metadata.is_dir() => {
+                        return Err(Error::InvalidConfig(format!(
+                            "Dockerfile {} is a directory",
+                            dockerfile.display()
+                        )));
+                    }
+                    Ok(_) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        return Err(Error::InvalidConfig(format!(
+                            "Dockerfile {} not found",
+                            dockerfile.display()
+                        )));
+                    }
+                    Err(e) => return Err(Error::file_operation("stat", &dockerfile, e)),
+                }
+            }
+            DockerfileSource::Inline { context_path, .. } => {
+                check_directory("build context", context_path)?;
+            }
+            DockerfileSource::GitHub { .. }
+            | DockerfileSource::Git { .. }
+            | DockerfileSource::TarballUrl { .. } => {}
+        }
+        for (name, context) in &self.named_contexts {
+            if let NamedContext::Local(path) = context {
+                check_directory(&format!("build context {}", name), path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scrubber for every credential and secret value in this configuration
+    ///
+    /// Used to keep these values out of build logs and error messages.
+    pub(crate) fn scrubber(&self) -> Scrubber {
+        // Sources that cannot be read now fail the build when it starts
+        let source_values: Vec<String> = self
+            .secret_sources
+            .values()
+            .filter_map(|source| source.read().ok())
+            .filter_map(|value| String::from_utf8(value).ok())
+            .collect();
+        let cache_tokens: Vec<String> = self
+            .cache_from
+            .iter()
+            .map(CacheImport::attrs)
+            .chain(self.cache_to.iter().map(CacheExport::attrs))
+            .flat_map(|attrs| {
+                SECRET_CACHE_ATTRS
+                    .iter()
+                    .filter_map(move |key| attrs.get(*key).cloned())
+            })
+            .collect();
+
+        let source_secrets: Vec<&str> = match &self.source {
+            DockerfileSource::GitHub { token, .. } => token.as_deref().into_iter().collect(),
+            DockerfileSource::Git {
+                auth: Some(GitAuth::Token(secret) | GitAuth::Header(secret)),
+                ..
+            } => vec![secret.as_str()],
+            DockerfileSource::TarballUrl { headers, .. } => {
+                headers.values().map(String::as_str).collect()
+            }
+            _ => Vec::new(),
+        };
+        let auths = self.all_registry_auths().flat_map(|auth| {
+            [
+                Some(auth.password.as_str()),
+                auth.identity_token.as_deref(),
+                auth.registry_token.as_deref(),
+            ]
+        });
+
+        Scrubber::new(
+            source_secrets
+                .into_iter()
+                .chain(auths.flatten())
+                .chain(cache_tokens.iter().map(String::as_str))
+                .chain(self.secrets.values().map(String::as_str))
+                .chain(source_values.iter().map(String::as_str)),
+        )
+    }
+
+    /// Values of all secrets, reading file and environment sources
+    pub(crate) fn resolve_secrets(&self) -> Result<HashMap<String, Vec<u8>>> {
+        let mut secrets: HashMap<String, Vec<u8>> = self
+            .secrets
+            .iter()
+            .map(|(id, value)| (id.clone(), value.clone().into_bytes()))
+            .collect();
+        for (id, source) in &self.secret_sources {
+            secrets.insert(id.clone(), source.read()?);
+        }
+        let git_secret = match &self.source {
+            DockerfileSource::GitHub {
+                token: Some(token), ..
+            }
+            | DockerfileSource::Git {
+                auth: Some(GitAuth::Token(token)),
+                ..
+            } => Some((GIT_AUTH_TOKEN, token)),
+            DockerfileSource::Git {
+                auth: Some(GitAuth::Header(header)),
+                ..
+            } => Some((GIT_AUTH_HEADER, header)),
+            _ => None,
+        };
+        if let Some((id, value)) = git_secret {
+            secrets
+                .entry(id.to_string())
+                .or_insert_with(|| value.clone().into_bytes());
+        }
+        if let Some(value) = self.tarball_authorization() {
+            secrets
+                .entry(TARBALL_AUTH_HEADER.to_string())
+                .or_insert_with(|| value.as_bytes().to_vec());
+        }
+        Ok(secrets)
+    }
+
+    /// `Authorization` header of a tarball context download, if set
+    pub(crate) fn tarball_authorization(&self) -> Option<&str> {
+        match &self.source {
+            DockerfileSource::TarballUrl { headers, .. } => headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+                .map(|(_, value)| value.as_str()),
+            _ => None,
+        }
+    }
+
+    /// SSH agents to forward, including the agent of an SSH Git context
+    ///
+    /// BuildKit fetches SSH remotes through the default agent; an agent
+    /// already forwarded under that ID takes precedence.
+    pub(crate) fn resolve_ssh_agents(&self) -> HashMap<String, SshAgent> {
+        let mut agents = self.ssh_agents.clone();
+        if let DockerfileSource::Git {
+            auth: Some(GitAuth::Ssh(agent)),
+            ..
+        } = &self.source
+        {
+            agents
+                .entry(crate::session::ssh::DEFAULT_SSH_ID.to_string())
+                .or_insert_with(|| agent.clone());
+        }
+        agents
+    }
+
     /// Set GitHub token for private repositories
+    ///
+    /// The token is passed to BuildKit as the `GIT_AUTH_TOKEN` secret rather
+    /// than in the repository URL.
     pub fn github_token(mut self, token: impl Into<String>) -> Self {
         if let DockerfileSource::GitHub {
             token: ref mut t, ..
@@ -239,10 +1901,37 @@ impl BuildConfig {
         self
     }
 
+    /// Set the expected digest of a tarball context, e.g. `sha256:<hex>`
+    ///
+    /// BuildKit fails the build if the downloaded tarball does not match.
+    pub fn tarball_checksum(mut self, digest: impl Into<String>) -> Self {
+        if let DockerfileSource::TarballUrl { checksum, .. } = &mut self.source {
+            *checksum = Some(digest.into());
+        }
+        self
+    }
+
+    /// Send an HTTP header when downloading a tarball context, e.g.
+    /// `Authorization` for a private release
+    ///
+    /// `Authorization` reaches BuildKit as a session secret. Other headers
+    /// are sent in the solve request's source policy and are not
+    /// confidential; their values are only scrubbed from progress output
+    /// and errors.
+    pub fn tarball_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        if let DockerfileSource::TarballUrl { headers, .. } = &mut self.source {
+            headers.insert(name.into(), value.into());
+        }
+        self
+    }
+
     /// Set git reference (branch, tag, or commit)
     pub fn git_ref(mut self, git_ref: impl Into<String>) -> Self {
         if let DockerfileSource::GitHub {
             git_ref: ref mut r, ..
+        }
+        | DockerfileSource::Git {
+            git_ref: ref mut r, ..
         } = &mut self.source
         {
             *r = Some(git_ref.into());
@@ -250,33 +1939,319 @@ impl BuildConfig {
         self
     }
 
+    /// Use a directory of a Git or GitHub repository as the context
+    ///
+    /// The path is relative to the repository root and may not leave it.
+    pub fn git_subdir(mut self, subdir: impl Into<String>) -> Self {
+        if let DockerfileSource::GitHub { subdir: s, .. }
+        | DockerfileSource::Git { subdir: s, .. } = &mut self.source
+        {
+            *s = Some(subdir.into());
+        }
+        self
+    }
+
+    /// Set the credentials for a Git context
+    pub fn git_auth(mut self, auth: GitAuth) -> Self {
+        if let DockerfileSource::Git { auth: a, .. } = &mut self.source {
+            *a = Some(auth);
+        }
+        self
+    }
+
     /// Add cache import source
-    pub fn cache_from(mut self, source: impl Into<String>) -> Self {
+    ///
+    /// Strings are parsed with [`CacheImport::parse`].
+    pub fn cache_from(mut self, source: impl Into<CacheImport>) -> Self {
         self.cache_from.push(source.into());
         self
     }
 
     /// Add cache export destination
-    pub fn cache_to(mut self, dest: impl Into<String>) -> Self {
+    ///
+    /// Strings are parsed with [`CacheExport::parse`].
+    pub fn cache_to(mut self, dest: impl Into<CacheExport>) -> Self {
         self.cache_to.push(dest.into());
         self
     }
 
+    /// Import from and export to the GitHub Actions cache under `scope`
+    ///
+    /// All layers are exported, as with `mode=max`.
+    pub fn cache_gha(
+        mut self,
+        url: impl Into<String>,
+        token: impl Into<String>,
+        scope: impl Into<String>,
+    ) -> Self {
+        let cache = GhaCache::new(url, token, scope);
+        self.cache_from.push(CacheImport::Gha(cache.clone()));
+        self.cache_to.push(CacheExport::Gha {
+            cache,
+            mode: CacheMode::Max,
+        });
+        self
+    }
+
+    /// Import from and export to an S3 bucket
+    ///
+    /// All layers are exported, as with `mode=max`.
+    pub fn cache_s3(mut self, cache: S3Cache) -> Self {
+        self.cache_from.push(CacheImport::S3(cache.clone()));
+        self.cache_to.push(CacheExport::S3 {
+            cache,
+            mode: CacheMode::Max,
+        });
+        self
+    }
+
+    /// Like [`cache_gha`](Self::cache_gha), with the cache service of the
+    /// running workflow
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use buildkit_client::BuildConfig;
+    ///
+    /// # fn main() -> buildkit_client::Result<()> {
+    /// let config = BuildConfig::local("./app")
+    ///     .tag("registry.example.com/app:latest")
+    ///     .cache_gha_from_env("app")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn cache_gha_from_env(mut self, scope: impl Into<String>) -> Result<Self> {
+        let cache = GhaCache::from_env(scope)?;
+        self.cache_from.push(CacheImport::Gha(cache.clone()));
+        self.cache_to.push(CacheExport::Gha {
+            cache,
+            mode: CacheMode::Max,
+        });
+        Ok(self)
+    }
+
     /// Add a secret
     pub fn secret(mut self, id: impl Into<String>, value: impl Into<String>) -> Self {
         self.secrets.insert(id.into(), value.into());
         self
     }
 
+    /// Add a secret read from a file when the build starts
+    pub fn secret_file(mut self, id: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.secret_sources
+            .insert(id.into(), SecretSource::File(path.into()));
+        self
+    }
+
+    /// Add a secret read from an environment variable when the build starts
+    pub fn secret_env(mut self, id: impl Into<String>, var: impl Into<String>) -> Self {
+        self.secret_sources
+            .insert(id.into(), SecretSource::Env(var.into()));
+        self
+    }
+
+    /// Forward an SSH agent to `RUN --mount=type=ssh,id=<id>` mounts
+    ///
+    /// Mounts without an `id` use [`DEFAULT_SSH_ID`](crate::session::ssh::DEFAULT_SSH_ID).
+    pub fn ssh_agent(mut self, id: impl Into<String>, agent: SshAgent) -> Self {
+        self.ssh_agents.insert(id.into(), agent);
+        self
+    }
+
+    /// Add a named build context
+    ///
+    /// Dockerfile stages and `COPY --from` can refer to it by name, and it
+    /// replaces an image of the same name, e.g. `alpine`.
+    pub fn named_context(mut self, name: impl Into<String>, source: NamedContext) -> Self {
+        self.named_contexts.insert(name.into(), source);
+        self
+    }
+
+    /// Grant the build an entitlement, e.g. for `RUN --network=host`
+    pub fn allow(mut self, entitlement: Entitlement) -> Self {
+        if !self.entitlements.contains(&entitlement) {
+            self.entitlements.push(entitlement);
+        }
+        self
+    }
+
+    /// Apply the source policy in a JSON file, see [`crate::source_policy`]
+    pub fn source_policy_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.source_policy = Some(path.into());
+        self
+    }
+
+    /// Attach an attestation to the image, like `docker buildx build
+    /// --attest type=<kind>,<params>`
+    ///
+    /// `kind` is `provenance` or `sbom`; `params` are comma-separated
+    /// options such as `mode=max`, or empty. Multi-platform images get one
+    /// attestation manifest per platform.
+    ///
+    /// # Example
+    /// ```
+    /// use buildkit_client::BuildConfig;
+    ///
+    /// let config = BuildConfig::local("./my-app")
+    ///     .attest("provenance", "mode=max")
+    ///     .attest("sbom", "");
+    /// ```
+    pub fn attest(mut self, kind: impl Into<String>, params: impl Into<String>) -> Self {
+        self.attestations.insert(kind.into(), params.into());
+        self
+    }
+
+    /// Build with a frontend image instead of the built-in Dockerfile frontend
+    ///
+    /// The frontend gets the build's context, Dockerfile and options; its
+    /// own options must not clash with them.
+    ///
+    /// # Example
+    /// ```
+    /// use buildkit_client::{BuildConfig, Frontend};
+    ///
+    /// let config = BuildConfig::local("./my-app")
+    ///     .frontend(Frontend::gateway("docker/dockerfile:1.7"));
+    /// ```
+    pub fn frontend(mut self, frontend: Frontend) -> Self {
+        self.frontend = frontend;
+        self
+    }
+
+    /// Write the log of every step to a file or writer
+    ///
+    /// The log is complete and in order whatever the progress handler
+    /// shows, e.g. for builds run by a service. It has the format of
+    /// [`PlainProgressHandler`](crate::progress::PlainProgressHandler).
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::builder::LogSink;
+    /// use buildkit_client::BuildConfig;
+    ///
+    /// let config = BuildConfig::local("./my-app").log_output(LogSink::file("build.log"));
+    /// ```
+    pub fn log_output(mut self, sink: LogSink) -> Self {
+        self.log_output = Some(sink);
+        self
+    }
+
+    /// Cancel the build when its solve runs longer than `timeout`
+    ///
+    /// The build then fails with [`Error::Build`](crate::Error::Build).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     /// Set no-cache flag
     pub fn no_cache(mut self, no_cache: bool) -> Self {
         self.no_cache = no_cache;
         self
     }
 
+    /// Set whether the tags are pushed to their registries
+    ///
+    /// Disable it to only name the images of client-side exports, e.g. to
+    /// build for a local Docker daemon without a registry.
+    pub fn push(mut self, push: bool) -> Self {
+        self.push = push;
+        self
+    }
+
     /// Set pull flag
     pub fn pull(mut self, pull: bool) -> Self {
         self.pull = pull;
         self
     }
+
+    /// Only upload the context paths the Dockerfile reads, minus `.dockerignore`d files
+    pub fn prune_context(mut self, prune: bool) -> Self {
+        self.prune_context = prune;
+        self
+    }
+
+    /// Snapshot the context at build start to guard against edits made mid-build
+    pub fn snapshot_context(mut self, snapshot: bool) -> Self {
+        self.snapshot_context = snapshot;
+        self
+    }
+
+    /// Keep content hashes of context files in `dir`, so that files
+    /// rewritten without changing are not uploaded again
+    pub fn change_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.change_cache = Some(dir.into());
+        self
+    }
+
+    /// Require the worker running the build to match a containerd-style
+    /// filter on its ID, labels or platforms
+    ///
+    /// All constraints must match. BuildKit runs builds on its default
+    /// worker, so the build fails before starting if that worker does not
+    /// match, instead of falling back to emulation.
+    pub fn worker_constraint(mut self, filter: impl Into<String>) -> Self {
+        self.worker_constraints.push(filter.into());
+        self
+    }
+
+    /// Add a client-side output
+    ///
+    /// A build can have several outputs besides pushing its tags, e.g. an
+    /// OCI tarball and a local directory, which must not write to the same
+    /// path. Several outputs need BuildKit v0.13 or later.
+    pub fn export(mut self, export: Export) -> Self {
+        self.exports.push(export);
+        self
+    }
+
+    /// Write the built filesystem into a local directory
+    ///
+    /// Works with or without tags to push. Files already in the directory
+    /// are kept unless the build outputs the same path.
+    pub fn export_local(mut self, dest: impl Into<PathBuf>) -> Self {
+        self.exports.push(Export::Local(dest.into()));
+        self
+    }
+
+    /// Write the image as an OCI image layout tarball
+    ///
+    /// The image is annotated with the build's tags, if any, so that tools
+    /// loading the tarball know its name.
+    pub fn export_oci(mut self, path: impl Into<PathBuf>) -> Self {
+        self.exports.push(Export::Oci(path.into()));
+        self
+    }
+
+    /// Write the image as a tarball in the `docker save` format
+    ///
+    /// The tarball can be loaded with `docker load`; like
+    /// [`export_oci`](Self::export_oci), it names the image after the tags.
+    pub fn export_docker(mut self, path: impl Into<PathBuf>) -> Self {
+        self.exports.push(Export::Docker(path.into()));
+        self
+    }
+
+    /// Load the image into a Docker daemon
+    ///
+    /// The tarball is streamed into the daemon as BuildKit sends it, and
+    /// the image is named after the tags. Combine with
+    /// [`push(false)`](Self::push) to build without a registry.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::docker::DockerDaemon;
+    /// use buildkit_client::BuildConfig;
+    ///
+    /// let config = BuildConfig::local("./app")
+    ///     .tag("app:dev")
+    ///     .push(false)
+    ///     .load_into_docker(DockerDaemon::default());
+    /// assert_eq!(config.exports.len(), 1);
+    /// ```
+    pub fn load_into_docker(mut self, daemon: DockerDaemon) -> Self {
+        self.exports.push(Export::DockerLoad(daemon));
+        self
+    }
 }
@@ -1,20 +1,48 @@
 //! BuildKit gRPC client implementation
 
+use crate::docker::DockerDaemon;
 use crate::error::{Error, Result};
+use crate::events::BuildEventSink;
+use crate::metrics::Metrics;
 use crate::proto::moby::buildkit::v1::control_client::ControlClient;
-use tonic::transport::{Channel, Endpoint};
+use crate::proto::moby::buildkit::v1::{SolveRequest, SolveResponse};
+use hyper_util::rt::TokioIo;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tonic::transport::{Certificate, Channel, Endpoint, Identity, Uri};
 
 /// BuildKit client for interacting with buildkitd
+///
+/// Cloning a client is cheap: clones share its connection, which
+/// multiplexes their calls over one HTTP/2 channel, as well as its event
+/// sinks, cancellation token, metrics and build limit. Builds run concurrently on
+/// clones or through a shared reference each get their own session and
+/// build reference.
 #[derive(Clone)]
 pub struct BuildKitClient {
+    channel: Channel,
     control: ControlClient<Channel>,
+    event_sinks: Vec<Arc<dyn BuildEventSink>>,
+    cancel_token: Option<CancellationToken>,
+    options: ClientOptions,
+    build_limit: Option<Arc<Semaphore>>,
+    metrics: Option<Arc<Metrics>>,
 }
 
 impl BuildKitClient {
     /// Create a new BuildKit client connected to the specified address
     ///
     /// # Arguments
-    /// * `addr` - The address of the buildkitd service (e.g., "http://localhost:1234")
+    /// * `addr` - The address of the buildkitd service: an HTTP URL such as
+    ///   `http://localhost:1234`, a socket such as
+    ///   `unix:///run/buildkit/buildkitd.sock`, or `docker-container://<name>`
+    ///   for the buildkitd of a buildx `docker-container` builder, reached
+    ///   through the Docker daemon named by `DOCKER_HOST`
     ///
     /// # Example
     /// ```no_run
@@ -30,20 +58,282 @@ impl BuildKitClient {
         let addr = addr.into();
         tracing::info!("Connecting to buildkitd at {}", addr);
 
-        let endpoint = Endpoint::from_shared(addr.clone())
-            .map_err(|_| Error::InvalidEndpoint(addr.clone()))?
-            .timeout(std::time::Duration::from_secs(30));
-
-        let channel = endpoint.connect().await.map_err(|e| Error::Connection {
+        let connection = match Dialer::parse(&addr)? {
+            Some(dialer) => {
+                // Requests only use this URI for their authority
+                Endpoint::from_static("http://buildkitd")
+                    .timeout(CONNECT_TIMEOUT)
+                    .connect_with_connector(tower::service_fn(move |_: Uri| dialer.clone().dial()))
+                    .await
+            }
+            None => {
+                Endpoint::from_shared(addr.clone())
+                    .map_err(|_| Error::InvalidEndpoint(addr.clone()))?
+                    .timeout(CONNECT_TIMEOUT)
+                    .connect()
+                    .await
+            }
+        };
+        let channel = connection.map_err(|e| Error::Connection {
             endpoint: addr,
             source: e,
         })?;
 
-        let control = ControlClient::new(channel);
+        tracing::info!("Successfully connected to buildkitd");
+        Ok(Self::from_channel(channel))
+    }
+
+    /// Create a new BuildKit client connected to the specified address over TLS
+    ///
+    /// `addr` is a `tcp://`, `http://` or `https://` address; the
+    /// connection uses TLS whatever its scheme, like `buildctl --addr
+    /// tcp://... --tlscacert ...`. A client certificate in the
+    /// configuration enables mutual TLS.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::client::{BuildKitClient, ClientTlsConfig};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let tls = ClientTlsConfig::new()
+    ///         .ca_cert("/etc/buildkit/certs/ca.pem")
+    ///         .identity("/etc/buildkit/certs/cert.pem", "/etc/buildkit/certs/key.pem");
+    ///     let client = BuildKitClient::connect_with_tls("tcp://buildkitd:1234", tls).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn connect_with_tls(addr: impl Into<String>, tls: ClientTlsConfig) -> Result<Self> {
+        let addr = addr.into();
+        tracing::info!("Connecting to buildkitd at {} over TLS", addr);
+
+        if Dialer::parse(&addr)?.is_some() {
+            return Err(Error::InvalidConfig(format!(
+                "TLS is only supported for TCP addresses: {}",
+                addr
+            )));
+        }
+        let uri = https_uri(&addr).ok_or_else(|| Error::InvalidEndpoint(addr.clone()))?;
+        let connection = Endpoint::from_shared(uri)
+            .map_err(|_| Error::InvalidEndpoint(addr.clone()))?
+            .timeout(CONNECT_TIMEOUT)
+            .tls_config(tls.load()?);
+        let connection = match connection {
+            Ok(endpoint) => endpoint.connect().await,
+            Err(e) => Err(e),
+        };
+        let channel = connection.map_err(|e| Error::Connection {
+            endpoint: addr,
+            source: e,
+        })?;
 
         tracing::info!("Successfully connected to buildkitd");
+        Ok(Self::from_channel(channel))
+    }
 
-        Ok(Self { control })
+    fn from_channel(channel: Channel) -> Self {
+        Self {
+            control: ControlClient::new(channel.clone()),
+            channel,
+            event_sinks: Vec::new(),
+            cancel_token: None,
+            options: ClientOptions::default(),
+            build_limit: None,
+            metrics: None,
+        }
+    }
+
+    /// Send lifecycle events of every build run by this client to a sink
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::client::BuildKitClient;
+    /// use buildkit_client::events::BuildEvent;
+    /// use std::sync::Arc;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let client = BuildKitClient::connect("http://localhost:1234")
+    ///         .await?
+    ///         .with_event_sink(Arc::new(|event: &BuildEvent| {
+    ///             println!("{} {}", event.build_ref, event.kind.name());
+    ///         }));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_event_sink(mut self, sink: Arc<dyn BuildEventSink>) -> Self {
+        self.event_sinks.push(sink);
+        self
+    }
+
+    /// Event sinks registered on this client
+    pub(crate) fn event_sinks(&self) -> &[Arc<dyn BuildEventSink>] {
+        &self.event_sinks
+    }
+
+    /// Cancel the builds of this client when `token` is cancelled
+    ///
+    /// The solve is abandoned, which makes BuildKit stop the build, and the
+    /// build fails with [`Error::BuildCancelled`] holding the steps that
+    /// ran so far. Clones of the client share the token.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::{BuildConfig, BuildKitClient, CancellationToken};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let token = CancellationToken::new();
+    ///     let mut client = BuildKitClient::connect("http://localhost:1234")
+    ///         .await?
+    ///         .with_cancel_token(token.clone());
+    ///
+    ///     tokio::spawn(async move {
+    ///         tokio::signal::ctrl_c().await.ok();
+    ///         token.cancel();
+    ///     });
+    ///     client.build(BuildConfig::local("./my-app"), None).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_cancel_token(mut self, token: CancellationToken) -> Self {
+        self.cancel_token = Some(token);
+        self
+    }
+
+    /// Add up the builds of this client and their context uploads in
+    /// `metrics`
+    ///
+    /// Builds run by [`build`](Self::build) and
+    /// [`solve_llb`](Self::solve_llb) are recorded. See the
+    /// [`metrics`](crate::metrics) module for an example.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Metrics the builds of this client are recorded in, if any
+    pub fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_deref()
+    }
+
+    /// Token cancelling the builds of this client, if any
+    pub(crate) fn cancel_token(&self) -> Option<&CancellationToken> {
+        self.cancel_token.as_ref()
+    }
+
+    /// Run at most `max` builds of this client and its clones at a time
+    ///
+    /// Further builds wait for a running one to finish before starting
+    /// their session, reporting [`BuildEventKind::Queued`] until then. A
+    /// limit of 0 is treated as 1.
+    ///
+    /// [`BuildEventKind::Queued`]: crate::events::BuildEventKind::Queued
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::{BuildConfig, BuildKitClient};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let client = BuildKitClient::connect("http://localhost:1234")
+    ///         .await?
+    ///         .with_max_concurrent_builds(2);
+    ///
+    ///     let (web, api, worker) = tokio::join!(
+    ///         client.build(BuildConfig::local("./web"), None),
+    ///         client.build(BuildConfig::local("./api"), None),
+    ///         client.build(BuildConfig::local("./worker"), None),
+    ///     );
+    ///     println!("{:?} {:?} {:?}", web?.digest, api?.digest, worker?.digest);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_max_concurrent_builds(mut self, max: usize) -> Self {
+        self.build_limit = Some(Arc::new(Semaphore::new(max.max(1))));
+        self
+    }
+
+    /// Wait until the build limit lets another build start
+    ///
+    /// The build runs for as long as it holds the permit; without a limit
+    /// there is none. Fails with [`Error::BuildCancelled`] if the client's
+    /// token is cancelled while waiting.
+    pub(crate) async fn build_permit(&self) -> Result<Option<OwnedSemaphorePermit>> {
+        let Some(limit) = &self.build_limit else {
+            return Ok(None);
+        };
+        match self.unless_cancelled(limit.clone().acquire_owned()).await {
+            // The semaphore is never closed
+            Some(permit) => Ok(permit.ok()),
+            None => Err(Error::BuildCancelled(Box::default())),
+        }
+    }
+
+    /// Run `future` to completion unless `timeout` expires first, in which
+    /// case it is dropped and `None` returned
+    pub(crate) async fn within<F: Future>(
+        timeout: Option<Duration>,
+        future: F,
+    ) -> Option<F::Output> {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, future).await.ok(),
+            None => Some(future.await),
+        }
+    }
+
+    /// Set the retry policy of calls to buildkitd
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::client::{BuildKitClient, ClientOptions};
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let client = BuildKitClient::connect("http://localhost:1234")
+    ///         .await?
+    ///         .with_options(ClientOptions::new().max_retries(5).backoff(Duration::from_secs(1)));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_options(mut self, options: ClientOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Retry policy of calls to buildkitd
+    pub fn options(&self) -> &ClientOptions {
+        &self.options
+    }
+
+    /// Send a solve request, retrying it if it never reached buildkitd
+    ///
+    /// A solve that failed once sent is not sent again: the session it
+    /// names may be gone with the connection, and BuildKit may already be
+    /// running the build.
+    pub(crate) async fn solve(
+        &self,
+        request: tonic::Request<SolveRequest>,
+    ) -> std::result::Result<tonic::Response<SolveResponse>, tonic::Status> {
+        let (metadata, _, message) = request.into_parts();
+        self.options
+            .retry_if("Solve", never_sent, || {
+                let mut control = self.control.clone();
+                let mut request = tonic::Request::new(message.clone());
+                *request.metadata_mut() = metadata.clone();
+                async move { control.solve(request).await }
+            })
+            .await
+    }
+
+    /// Run `future` to completion unless the client's token is cancelled
+    /// first, in which case it is dropped and `None` returned
+    pub(crate) async fn unless_cancelled<F: Future>(&self, future: F) -> Option<F::Output> {
+        match &self.cancel_token {
+            Some(token) => token.run_until_cancelled(future).await,
+            None => Some(future.await),
+        }
     }
 
     /// Get a reference to the control client
@@ -51,6 +341,14 @@ impl BuildKitClient {
         &mut self.control
     }
 
+    /// Get the underlying gRPC channel
+    ///
+    /// Cloning a channel is cheap; clients created from it share the
+    /// connection. See [`crate::raw`] for the generated clients.
+    pub fn channel(&self) -> Channel {
+        self.channel.clone()
+    }
+
     /// Check if the buildkitd service is available
     pub async fn health_check(&mut self) -> Result<()> {
         use crate::proto::moby::buildkit::v1::InfoRequest;
@@ -61,3 +359,408 @@ impl BuildKitClient {
         Ok(())
     }
 }
+
+/// Retry policy of calls to buildkitd
+///
+/// Calls failing with one of the `retry_on` codes are sent again after a
+/// backoff. Solve calls are only sent again if no connection to buildkitd
+/// could be established, e.g. while the daemon restarts, since a solve that
+/// reached it is tied to a session that does not outlive the connection. A
+/// status stream that breaks is reopened for the same build ref, at most
+/// `max_retries` times per build.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientOptions {
+    /// Retries of a failed call before its error is returned
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further retry
+    pub backoff: Duration,
+    /// Status codes of failures that are retried
+    pub retry_on: Vec<tonic::Code>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            backoff: Duration::from_millis(500),
+            retry_on: vec![tonic::Code::Unavailable],
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Create the default policy: 3 retries of `UNAVAILABLE` failures
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of retries, 0 to disable them
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Set the delay before the first retry
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set the status codes of failures that are retried
+    pub fn retry_on(mut self, codes: impl IntoIterator<Item = tonic::Code>) -> Self {
+        self.retry_on = codes.into_iter().collect();
+        self
+    }
+
+    /// Whether a call that failed after `retries` retries is tried again
+    pub(crate) fn should_retry(&self, status: &tonic::Status, retries: u32) -> bool {
+        retries < self.max_retries && self.retry_on.contains(&status.code())
+    }
+
+    /// Delay before retry number `retries`, counting from 0
+    pub(crate) fn delay(&self, retries: u32) -> Duration {
+        self.backoff.saturating_mul(1 << retries.min(16))
+    }
+
+    /// Run a call until it succeeds, fails permanently or runs out of retries
+    pub(crate) async fn retry<T, F, Fut>(
+        &self,
+        name: &str,
+        call: F,
+    ) -> std::result::Result<T, tonic::Status>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::result::Result<T, tonic::Status>>,
+    {
+        self.retry_if(name, |_| true, call).await
+    }
+
+    /// Like [`Self::retry`], only retrying failures `retryable` accepts
+    pub(crate) async fn retry_if<T, F, Fut>(
+        &self,
+        name: &str,
+        retryable: impl Fn(&tonic::Status) -> bool,
+        mut call: F,
+    ) -> std::result::Result<T, tonic::Status>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::result::Result<T, tonic::Status>>,
+    {
+        let mut retries = 0;
+        loop {
+            match call().await {
+                Err(status) if self.should_retry(&status, retries) && retryable(&status) => {
+                    let delay = self.delay(retries);
+                    tracing::warn!(
+                        "{} failed ({}), retrying in {:?}",
+                        name,
+                        status.message(),
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether a call failed because no connection to buildkitd could be
+/// established, so that it never reached the daemon
+pub(crate) fn never_sent(status: &tonic::Status) -> bool {
+    let mut source = std::error::Error::source(status);
+    while let Some(error) = source {
+        if error.is::<tonic::ConnectError>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// TLS settings of a connection to buildkitd
+///
+/// Certificates and keys are PEM files, read when connecting. Without a
+/// CA certificate the server is verified against the system's roots.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientTlsConfig {
+    /// CA certificate the server certificate must be signed by
+    pub ca_cert: Option<PathBuf>,
+    /// Client certificate for mutual TLS
+    pub cert: Option<PathBuf>,
+    /// Private key of the client certificate
+    pub key: Option<PathBuf>,
+    /// Name to verify the server certificate against, instead of the
+    /// address's host
+    pub server_name: Option<String>,
+}
+
+impl ClientTlsConfig {
+    /// Create a configuration trusting the system's roots
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Verify the server against a CA certificate
+    pub fn ca_cert(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca_cert = Some(path.into());
+        self
+    }
+
+    /// Authenticate with a client certificate and its key
+    pub fn identity(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.cert = Some(cert.into());
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Override the name the server certificate is verified against
+    pub fn server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Read the certificates into tonic's configuration
+    fn load(&self) -> Result<tonic::transport::ClientTlsConfig> {
+        let read = |path: &PathBuf| {
+            std::fs::read(path).map_err(|e| Error::file_operation("read", path, e))
+        };
+
+        let mut config = tonic::transport::ClientTlsConfig::new();
+        config = match &self.ca_cert {
+            Some(path) => config.ca_certificate(Certificate::from_pem(read(path)?)),
+            None => config.with_native_roots(),
+        };
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => {
+                config = config.identity(Identity::from_pem(read(cert)?, read(key)?));
+            }
+            (None, None) => {}
+            _ => {
+                return Err(Error::InvalidConfig(
+                    "a TLS client certificate needs both a certificate and a key".to_string(),
+                ))
+            }
+        }
+        if let Some(name) = &self.server_name {
+            config = config.domain_name(name);
+        }
+        Ok(config)
+    }
+}
+
+/// `https://` URI of a TCP address
+fn https_uri(addr: &str) -> Option<String> {
+    let authority = ["tcp://", "http://", "https://"]
+        .iter()
+        .find_map(|scheme| addr.strip_prefix(scheme))?;
+    Some(format!("https://{}", authority))
+}
+
+/// Timeout of requests to buildkitd
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Command that bridges stdio to buildkitd inside a builder container
+const DIAL_STDIO: &[&str] = &["buildctl", "dial-stdio"];
+
+/// Connection to buildkitd other than over TCP
+#[derive(Debug, Clone)]
+enum Dialer {
+    /// Unix socket of the daemon
+    Unix(PathBuf),
+    /// `buildctl dial-stdio` run in a container through the Docker API
+    DockerContainer {
+        daemon: DockerDaemon,
+        container: String,
+    },
+}
+
+/// Stream of a dialed connection
+trait DialStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> DialStream for T {}
+
+impl Dialer {
+    /// Dialer for an address, `None` for HTTP endpoints
+    fn parse(addr: &str) -> Result<Option<Self>> {
+        if let Some(path) = addr.strip_prefix("unix://") {
+            if path.is_empty() {
+                return Err(Error::InvalidEndpoint(addr.to_string()));
+            }
+            return Ok(Some(Dialer::Unix(PathBuf::from(path))));
+        }
+        if let Some(container) = addr.strip_prefix("docker-container://") {
+            let container = container.trim_end_matches('/');
+            if container.is_empty() || container.contains('/') {
+                return Err(Error::InvalidEndpoint(addr.to_string()));
+            }
+            return Ok(Some(Dialer::DockerContainer {
+                daemon: DockerDaemon::from_env()?,
+                container: container.to_string(),
+            }));
+        }
+        Ok(None)
+    }
+
+    async fn dial(self) -> std::io::Result<TokioIo<Box<dyn DialStream>>> {
+        let stream: Box<dyn DialStream> = match self {
+            #[cfg(unix)]
+            Dialer::Unix(path) => Box::new(tokio::net::UnixStream::connect(path).await?),
+            #[cfg(not(unix))]
+            Dialer::Unix(path) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!(
+                        "Unix sockets are not supported on this platform: {}",
+                        path.display()
+                    ),
+                ))
+            }
+            Dialer::DockerContainer { daemon, container } => Box::new(
+                daemon
+                    .exec_stdio(&container, DIAL_STDIO)
+                    .await
+                    .map_err(std::io::Error::other)?,
+            ),
+        };
+        Ok(TokioIo::new(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn dialers_are_chosen_by_scheme() {
+        assert!(matches!(
+            Dialer::parse("unix:///run/buildkit/buildkitd.sock").unwrap(),
+            Some(Dialer::Unix(path)) if path == Path::new("/run/buildkit/buildkitd.sock")
+        ));
+        assert!(matches!(
+            Dialer::parse("docker-container://buildx_buildkit_default0").unwrap(),
+            Some(Dialer::DockerContainer { container, .. }) if container == "buildx_buildkit_default0"
+        ));
+        assert!(Dialer::parse("http://localhost:1234").unwrap().is_none());
+        assert!(Dialer::parse("unix://").is_err());
+        assert!(Dialer::parse("docker-container://").is_err());
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let options = ClientOptions::new()
+            .max_retries(2)
+            .backoff(Duration::from_millis(1));
+        let mut calls = 0;
+        let result = options
+            .retry("Test", || {
+                calls += 1;
+                let attempt = calls;
+                async move {
+                    if attempt < 3 {
+                        Err(tonic::Status::unavailable("connection reset"))
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: std::result::Result<(), _> = options
+            .retry("Test", || {
+                calls += 1;
+                async { Err(tonic::Status::invalid_argument("bad request")) }
+            })
+            .await;
+        assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+        assert_eq!(calls, 1);
+        assert_eq!(options.delay(2), Duration::from_millis(4));
+    }
+
+    #[tokio::test]
+    async fn solves_are_only_retried_if_never_sent() {
+        let options = ClientOptions::new()
+            .max_retries(2)
+            .backoff(Duration::from_millis(1));
+        let refused = || {
+            let error = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+            tonic::Status::from_error(Box::new(tonic::ConnectError(Box::new(error))))
+        };
+        assert_eq!(refused().code(), tonic::Code::Unavailable);
+        assert!(never_sent(&refused()));
+
+        let mut calls = 0;
+        let result: std::result::Result<(), _> = options
+            .retry_if("Solve", never_sent, || {
+                calls += 1;
+                async { Err(tonic::Status::unavailable("connection reset")) }
+            })
+            .await;
+        assert_eq!(result.unwrap_err().code(), tonic::Code::Unavailable);
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let result: std::result::Result<(), _> = options
+            .retry_if("Solve", never_sent, || {
+                calls += 1;
+                async move { Err(refused()) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn clones_share_the_build_limit() {
+        let channel = Endpoint::from_static("http://buildkitd").connect_lazy();
+        let token = CancellationToken::new();
+        let client = BuildKitClient::from_channel(channel)
+            .with_cancel_token(token.clone())
+            .with_max_concurrent_builds(1);
+        let clone = client.clone();
+
+        let permit = client.build_permit().await.unwrap();
+        assert!(permit.is_some());
+        let waiting = tokio::time::timeout(Duration::from_millis(20), clone.build_permit());
+        assert!(waiting.await.is_err());
+
+        drop(permit);
+        assert!(clone.build_permit().await.unwrap().is_some());
+
+        let _permit = client.build_permit().await.unwrap();
+        token.cancel();
+        assert!(matches!(
+            clone.build_permit().await,
+            Err(Error::BuildCancelled(_))
+        ));
+    }
+
+    #[test]
+    fn tls_addresses_use_https() {
+        assert_eq!(
+            https_uri("tcp://buildkitd:1234").as_deref(),
+            Some("https://buildkitd:1234")
+        );
+        assert_eq!(
+            https_uri("http://10.0.0.5:1234").as_deref(),
+            Some("https://10.0.0.5:1234")
+        );
+        assert!(https_uri("buildkitd:1234").is_none());
+    }
+
+    #[test]
+    fn tls_identity_needs_a_key() {
+        let config = ClientTlsConfig {
+            cert: Some(PathBuf::from("cert.pem")),
+            ..ClientTlsConfig::new()
+        };
+        assert!(matches!(config.load(), Err(Error::InvalidConfig(_))));
+
+        let missing = ClientTlsConfig::new().ca_cert("/nonexistent/ca.pem");
+        assert!(missing.load().is_err());
+    }
+}
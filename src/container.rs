@@ -0,0 +1,373 @@
+//! Containers on build results through BuildKit's gateway API
+//!
+//! Processes run on the builder, inside the build, with their stdio
+//! streamed over the gateway's `ExecProcess` call. Used to smoke-test a
+//! result before it is pushed and to debug failed steps.
+
+use crate::error::{Error, Result};
+use crate::gateway::{GatewayBridge, GatewayResult};
+use crate::proto::moby::buildkit::v1::frontend::{
+    exec_message, ExecMessage, FdMessage, InitMessage, NewContainerRequest, ReleaseContainerRequest,
+};
+use crate::proto::pb::{Meta, Mount, MountType, NetMode};
+use std::io::Write;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+/// `PATH` used when the image config sets none
+const DEFAULT_PATH: &str = "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Options for [`run_in_result`]
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Extra environment variables (`KEY=value`), added to the image's
+    pub env: Vec<String>,
+    /// Working directory, defaulting to the image's
+    pub cwd: Option<String>,
+    /// User to run as, defaulting to the image's
+    pub user: Option<String>,
+    /// Run without network access
+    pub no_network: bool,
+    /// Also write the process output to this process's stdout and stderr
+    pub stream_output: bool,
+}
+
+/// Output of a process run by [`run_in_result`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunOutput {
+    /// Process exit code
+    pub exit_code: u32,
+    /// Everything written to stdout
+    pub stdout: Vec<u8>,
+    /// Everything written to stderr
+    pub stderr: Vec<u8>,
+}
+
+impl RunOutput {
+    /// Whether the process exited with code 0
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+/// Run a command in an ephemeral container on a build result's rootfs
+///
+/// The container uses the image's environment, user and working directory,
+/// overridden by `opts`, and is released once the command exits.
+///
+/// # Example
+/// ```no_run
+/// use buildkit_client::container::{run_in_result, RunOptions};
+/// use buildkit_client::{BuildConfig, BuildKitClient};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+///
+///     let output = client
+///         .gateway_build(BuildConfig::local("./my-app"), None, |result| async move {
+///             let cmd = vec!["my-app".to_string(), "--version".to_string()];
+///             run_in_result(&result, &cmd, RunOptions::default()).await
+///         })
+///         .await?;
+///     assert!(output.success());
+///     Ok(())
+/// }
+/// ```
+pub async fn run_in_result(
+    result: &GatewayResult,
+    cmd: &[String],
+    opts: RunOptions,
+) -> Result<RunOutput> {
+    if cmd.is_empty() {
+        return Err(Error::InvalidConfig("no command to run".to_string()));
+    }
+
+    let bridge = result.bridge();
+    let mut meta = image_meta(result.image_config());
+    meta.args = cmd.to_vec();
+    meta.env.extend(opts.env.iter().cloned());
+    if let Some(cwd) = opts.cwd {
+        meta.cwd = cwd;
+    }
+    if let Some(user) = opts.user {
+        meta.user = user;
+    }
+
+    let rootfs = Mount {
+        dest: "/".to_string(),
+        result_id: result.ref_id().to_string(),
+        mount_type: MountType::Bind as i32,
+        ..Default::default()
+    };
+    let network = if opts.no_network {
+        NetMode::None
+    } else {
+        NetMode::Unset
+    };
+    let request = NewContainerRequest {
+        mounts: vec![rootfs],
+        network: network as i32,
+        ..Default::default()
+    };
+    let container_id = start_container(bridge, request).await?;
+
+    let mut stdout = CapturedOutput::new(
+        opts.stream_output
+            .then(|| Box::new(std::io::stdout()) as Box<dyn Write + Send>),
+    );
+    let mut stderr = CapturedOutput::new(
+        opts.stream_output
+            .then(|| Box::new(std::io::stderr()) as Box<dyn Write + Send>),
+    );
+    let exit_code = exec_process(
+        bridge,
+        &container_id,
+        meta,
+        false,
+        tokio::io::empty(),
+        &mut stdout,
+        &mut stderr,
+    )
+    .await;
+    release_container(bridge, container_id).await;
+
+    Ok(RunOutput {
+        exit_code: exit_code?,
+        stdout: stdout.data,
+        stderr: stderr.data,
+    })
+}
+
+/// Process defaults from an image config's `Env`, `WorkingDir` and `User`
+fn image_meta(image_config: Option<&[u8]>) -> Meta {
+    let config = image_config
+        .and_then(|data| serde_json::from_slice::<serde_json::Value>(data).ok())
+        .and_then(|image| image.get("config").cloned())
+        .unwrap_or_default();
+    let field = |name: &str| {
+        config
+            .get(name)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    let mut env: Vec<String> = config
+        .get("Env")
+        .and_then(|v| v.as_array())
+        .map(|vars| {
+            vars.iter()
+                .filter_map(|v| v.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    if !env.iter().any(|var| var.starts_with("PATH=")) {
+        env.push(DEFAULT_PATH.to_string());
+    }
+
+    let cwd = field("WorkingDir");
+    Meta {
+        env,
+        cwd: if cwd.is_empty() { "/".to_string() } else { cwd },
+        user: field("User"),
+        ..Default::default()
+    }
+}
+
+/// Create a container under a new ID, returning the ID
+pub(crate) async fn start_container(
+    bridge: &GatewayBridge,
+    mut request: NewContainerRequest,
+) -> Result<String> {
+    let container_id = format!("container-{}", Uuid::new_v4());
+    request.container_id = container_id.clone();
+    bridge
+        .client()
+        .new_container(bridge.request(request))
+        .await?;
+    tracing::debug!("Started container {}", container_id);
+    Ok(container_id)
+}
+
+/// Release a container; failures are logged, since the build releases it
+/// anyway when it finishes
+pub(crate) async fn release_container(bridge: &GatewayBridge, container_id: String) {
+    let request = ReleaseContainerRequest { container_id };
+    if let Err(e) = bridge
+        .client()
+        .release_container(bridge.request(request))
+        .await
+    {
+        tracing::debug!("Failed to release container: {}", e);
+    }
+}
+
+/// Run a process in a container, returning its exit code
+///
+/// `stdin` is forwarded until EOF; output is copied to `stdout` and
+/// `stderr` until the process is done.
+pub(crate) async fn exec_process<I, O, E>(
+    bridge: &GatewayBridge,
+    container_id: &str,
+    meta: Meta,
+    tty: bool,
+    mut stdin: I,
+    stdout: &mut O,
+    stderr: &mut E,
+) -> Result<u32>
+where
+    I: AsyncRead + Unpin + Send + 'static,
+    O: AsyncWrite + Unpin,
+    E: AsyncWrite + Unpin,
+{
+    let process_id = format!("process-{}", Uuid::new_v4());
+    let (tx, rx) = mpsc::channel(16);
+
+    let init = ExecMessage {
+        process_id: process_id.clone(),
+        input: Some(exec_message::Input::Init(InitMessage {
+            container_id: container_id.to_string(),
+            meta: Some(meta),
+            fds: vec![0, 1, 2],
+            tty,
+            ..Default::default()
+        })),
+    };
+    tx.send(init)
+        .await
+        .map_err(|_| Error::send_failed("ExecMessage", "exec stream closed"))?;
+
+    let mut output = bridge
+        .client()
+        .exec_process(bridge.request(ReceiverStream::new(rx)))
+        .await?
+        .into_inner();
+
+    // Keep a sender so the stream stays open after stdin reaches EOF
+    let input = tx.clone();
+    let stdin_process_id = process_id.clone();
+    let forward_stdin = tokio::spawn(async move {
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let n = stdin.read(&mut buf).await.unwrap_or(0);
+            let message = ExecMessage {
+                process_id: stdin_process_id.clone(),
+                input: Some(exec_message::Input::File(FdMessage {
+                    fd: 0,
+                    eof: n == 0,
+                    data: buf[..n].to_vec(),
+                })),
+            };
+            if input.send(message).await.is_err() || n == 0 {
+                break;
+            }
+        }
+    });
+
+    let mut exit_code = None;
+    let result = async {
+        while let Some(message) = output.message().await? {
+            match message.input {
+                Some(exec_message::Input::File(file)) if file.fd == 2 => {
+                    stderr.write_all(&file.data).await?;
+                    stderr.flush().await?;
+                }
+                Some(exec_message::Input::File(file)) => {
+                    stdout.write_all(&file.data).await?;
+                    stdout.flush().await?;
+                }
+                Some(exec_message::Input::Exit(exit)) => {
+                    if let Some(error) = exit.error.filter(|e| e.code != 0) {
+                        tracing::debug!("Process {} error: {}", process_id, error.message);
+                    }
+                    exit_code = Some(exit.code);
+                }
+                Some(exec_message::Input::Done(_)) => break,
+                _ => {}
+            }
+        }
+        Ok::<_, Error>(())
+    }
+    .await;
+
+    forward_stdin.abort();
+    drop(tx);
+    result?;
+    exit_code.ok_or_else(|| Error::protocol("process ended without an exit code"))
+}
+
+/// Process output kept in memory and optionally echoed
+struct CapturedOutput {
+    data: Vec<u8>,
+    echo: Option<Box<dyn Write + Send>>,
+}
+
+impl CapturedOutput {
+    fn new(echo: Option<Box<dyn Write + Send>>) -> Self {
+        Self {
+            data: Vec::new(),
+            echo,
+        }
+    }
+}
+
+impl AsyncWrite for CapturedOutput {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if let Some(echo) = this.echo.as_mut() {
+            echo.write_all(buf)?;
+        }
+        this.data.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if let Some(echo) = self.get_mut().echo.as_mut() {
+            echo.flush()?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_meta_uses_config_defaults() {
+        let config = br#"{"config":{"Env":["PATH=/app/bin","LANG=C.UTF-8"],"WorkingDir":"/app","User":"app"}}"#;
+        let meta = image_meta(Some(&config[..]));
+        assert_eq!(meta.env, vec!["PATH=/app/bin", "LANG=C.UTF-8"]);
+        assert_eq!(meta.cwd, "/app");
+        assert_eq!(meta.user, "app");
+    }
+
+    #[test]
+    fn image_meta_without_config_has_default_path() {
+        let meta = image_meta(None);
+        assert_eq!(meta.env, vec![DEFAULT_PATH]);
+        assert_eq!(meta.cwd, "/");
+        assert_eq!(meta.user, "");
+    }
+
+    #[tokio::test]
+    async fn captured_output_keeps_written_bytes() {
+        let mut output = CapturedOutput::new(None);
+        output.write_all(b"v1.2.3\n").await.unwrap();
+        output.flush().await.unwrap();
+        assert_eq!(output.data, b"v1.2.3\n");
+    }
+}
@@ -0,0 +1,334 @@
+//! Interactive debugging of failed build steps
+//!
+//! A debug build runs the Dockerfile through BuildKit's gateway API. When a
+//! step fails, BuildKit keeps the failed step's mounts and reports their
+//! IDs in the error, so a container can be started on the step's
+//! filesystem before the build is released, like `buildx debug`.
+
+use crate::builder::BuildConfig;
+use crate::client::BuildKitClient;
+use crate::container::{exec_process, release_container, start_container};
+use crate::error::{Error, Result};
+use crate::events::BuildEvents;
+use crate::gateway::{GatewayBridge, GatewayError};
+use crate::progress::ProgressHandler;
+use crate::proto::errdefs::Solve as SolveErrorInfo;
+use crate::proto::google::rpc::Status as RpcStatus;
+use crate::proto::moby::buildkit::v1::frontend::{
+    NewContainerRequest, SolveRequest as FrontendSolveRequest,
+};
+use crate::proto::pb::{op, Meta, Mount};
+use crate::solve::BuildResult;
+use prost::Message;
+use std::future::Future;
+use tokio::io::{AsyncRead, AsyncWrite};
+use uuid::Uuid;
+
+/// Shell started by [`DebugContainer::shell`]
+const DEBUG_SHELL: &str = "/bin/sh";
+
+/// Container on the filesystem of a failed build step
+///
+/// Valid until the callback given to [`BuildKitClient::debug_build`]
+/// returns.
+pub struct DebugContainer {
+    bridge: GatewayBridge,
+    id: String,
+    meta: Meta,
+    error: String,
+}
+
+impl DebugContainer {
+    /// ID of the container on the builder
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Command of the failed step
+    pub fn command(&self) -> &[String] {
+        &self.meta.args
+    }
+
+    /// Working directory of the failed step
+    pub fn cwd(&self) -> &str {
+        &self.meta.cwd
+    }
+
+    /// Error the step failed with
+    pub fn error(&self) -> &str {
+        &self.error
+    }
+
+    /// Run a process in the container, returning its exit code
+    ///
+    /// The process gets the failed step's environment, user and working
+    /// directory. Output is copied to `stdout` and `stderr` until the
+    /// process exits.
+    pub async fn exec<I, O, E>(
+        &self,
+        args: &[String],
+        tty: bool,
+        stdin: I,
+        mut stdout: O,
+        mut stderr: E,
+    ) -> Result<u32>
+    where
+        I: AsyncRead + Unpin + Send + 'static,
+        O: AsyncWrite + Unpin,
+        E: AsyncWrite + Unpin,
+    {
+        let meta = Meta {
+            args: args.to_vec(),
+            ..self.meta.clone()
+        };
+        exec_process(
+            &self.bridge,
+            &self.id,
+            meta,
+            tty,
+            stdin,
+            &mut stdout,
+            &mut stderr,
+        )
+        .await
+    }
+
+    /// Run an interactive shell in the container on this process's stdio
+    pub async fn shell(&self) -> Result<u32> {
+        self.exec(
+            &[DEBUG_SHELL.to_string()],
+            false,
+            tokio::io::stdin(),
+            tokio::io::stdout(),
+            tokio::io::stderr(),
+        )
+        .await
+    }
+}
+
+impl BuildKitClient {
+    /// Build and push, opening a container on the failed step if a step fails
+    ///
+    /// Behaves like [`BuildKitClient::build`], but runs through the gateway
+    /// API. When a `RUN` step fails, `on_error` is called with a container
+    /// on that step's filesystem while the build is held open; the build's
+    /// error is returned once the callback completes.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::{BuildConfig, BuildKitClient};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     let config = BuildConfig::local("./my-app").tag("localhost:5000/my-app:latest");
+    ///
+    ///     client
+    ///         .debug_build(config, None, |container| async move {
+    ///             eprintln!("step failed: {}", container.error());
+    ///             container.shell().await.map(|_| ())
+    ///         })
+    ///         .await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn debug_build<F, Fut>(
+        &mut self,
+        config: BuildConfig,
+        progress_handler: Option<Box<dyn ProgressHandler>>,
+        on_error: F,
+    ) -> Result<BuildResult>
+    where
+        F: FnOnce(DebugContainer) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let build_ref = format!("build-{}", Uuid::new_v4());
+        tracing::info!("Starting debug build with ref: {}", build_ref);
+
+        let mut events = BuildEvents::new(self.event_sinks().to_vec(), &build_ref, &config);
+        events.queued();
+
+        let client_side = |bridge: GatewayBridge, request: FrontendSolveRequest| async move {
+            let error = match bridge.solve_result(request).await {
+                Ok(result) => {
+                    bridge
+                        .finish(Ok(result))
+                        .await
+                        .map_err(GatewayError::Solve)?;
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+
+            match solve_error_info(&error) {
+                Some(info) => {
+                    if let Err(e) = debug_failed_step(&bridge, &error, info, on_error).await {
+                        tracing::warn!("Debug container failed: {}", e);
+                    }
+                }
+                None => tracing::info!("Failed step cannot be debugged: {}", error),
+            }
+
+            if let Err(e) = bridge.finish(Err(&error)).await {
+                tracing::debug!("Failed to return gateway result: {}", e);
+            }
+            Err(GatewayError::Solve(error))
+        };
+
+        let result = self
+            .run_gateway(
+                &build_ref,
+                &config,
+                progress_handler,
+                &mut events,
+                true,
+                client_side,
+            )
+            .await
+            .map(|((), exporter_response, tracker)| BuildResult {
+                report: tracker.report(),
+                warnings: tracker.warnings(),
+                ..BuildResult::from_exporter_response(build_ref, exporter_response)
+            });
+        match &result {
+            Ok(result) => events.completed(result.digest.clone()),
+            Err(e) => events.failed(e),
+        }
+        result
+    }
+}
+
+/// Start a container on the mounts of a failed exec step and hand it to
+/// the callback, releasing it afterwards
+async fn debug_failed_step<F, Fut>(
+    bridge: &GatewayBridge,
+    error: &Error,
+    info: SolveErrorInfo,
+    on_error: F,
+) -> Result<()>
+where
+    F: FnOnce(DebugContainer) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let op = info
+        .op
+        .as_ref()
+        .ok_or_else(|| Error::protocol("solve error has no op"))?;
+    let Some(op::Op::Exec(exec)) = &op.op else {
+        return Err(Error::build("only RUN steps can be debugged"));
+    };
+
+    let meta = exec.meta.clone().unwrap_or_default();
+    let request = NewContainerRequest {
+        mounts: step_mounts(&exec.mounts, &info),
+        network: exec.network,
+        platform: op.platform.clone(),
+        constraints: op.constraints.clone(),
+        extra_hosts: meta.extra_hosts.clone(),
+        hostname: meta.hostname.clone(),
+        ..Default::default()
+    };
+    let container_id = start_container(bridge, request).await?;
+    tracing::info!("Started debug container {}", container_id);
+
+    let container = DebugContainer {
+        bridge: bridge.clone(),
+        id: container_id.clone(),
+        meta,
+        error: error.to_string(),
+    };
+    let result = on_error(container).await;
+    release_container(bridge, container_id).await;
+    result
+}
+
+/// Mounts of a failed exec step, pointing at the results BuildKit kept
+///
+/// Mount results reflect the filesystem at the time of failure; inputs are
+/// used for mounts that have none.
+fn step_mounts(mounts: &[Mount], info: &SolveErrorInfo) -> Vec<Mount> {
+    mounts
+        .iter()
+        .enumerate()
+        .map(|(i, mount)| {
+            let result_id = info
+                .mount_i_ds
+                .get(i)
+                .filter(|id| !id.is_empty())
+                .or_else(|| {
+                    usize::try_from(mount.input)
+                        .ok()
+                        .and_then(|input| info.input_i_ds.get(input))
+                })
+                .cloned()
+                .unwrap_or_default();
+            Mount {
+                result_id,
+                ..mount.clone()
+            }
+        })
+        .collect()
+}
+
+/// Details BuildKit attaches to the error of a failed solve step
+fn solve_error_info(error: &Error) -> Option<SolveErrorInfo> {
+    let status = error.grpc_status()?;
+    let details = RpcStatus::decode(status.details()).ok()?;
+    details
+        .details
+        .iter()
+        .filter(|any| any.type_url.ends_with("errdefs.Solve"))
+        .find_map(|any| SolveErrorInfo::decode(any.value.as_slice()).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bind(input: i64, dest: &str) -> Mount {
+        Mount {
+            input,
+            dest: dest.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mounts_use_results_kept_at_failure() {
+        let info = SolveErrorInfo {
+            input_i_ds: vec!["input-0".to_string(), "input-1".to_string()],
+            mount_i_ds: vec!["mount-0".to_string(), String::new()],
+            ..Default::default()
+        };
+        let mounts = step_mounts(&[bind(0, "/"), bind(1, "/src"), bind(-1, "/tmp")], &info);
+        assert_eq!(mounts[0].result_id, "mount-0");
+        assert_eq!(mounts[0].dest, "/");
+        assert_eq!(mounts[1].result_id, "input-1");
+        assert_eq!(mounts[2].result_id, "");
+    }
+
+    #[test]
+    fn solve_error_info_is_read_from_status_details() {
+        let info = SolveErrorInfo {
+            mount_i_ds: vec!["mount-0".to_string()],
+            ..Default::default()
+        };
+        let details = RpcStatus {
+            code: tonic::Code::Unknown as i32,
+            message: "process did not complete successfully".to_string(),
+            details: vec![prost_types::Any {
+                type_url: "type.googleapis.com/errdefs.Solve".to_string(),
+                value: info.encode_to_vec(),
+            }],
+        };
+        let status = tonic::Status::with_details(
+            tonic::Code::Unknown,
+            "process did not complete successfully",
+            details.encode_to_vec().into(),
+        );
+
+        let error = Error::from(status);
+        assert_eq!(solve_error_info(&error), Some(info));
+        assert_eq!(solve_error_info(&Error::build("failed")), None);
+    }
+}
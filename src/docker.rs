@@ -0,0 +1,428 @@
+//! Loading built images into a local Docker daemon
+//!
+//! A build exported with [`BuildConfig::load_into_docker`] streams its
+//! docker-format tarball straight into the daemon's `POST /images/load`
+//! endpoint while BuildKit sends it, so the image can be run without
+//! going through a registry.
+//!
+//! [`BuildConfig::load_into_docker`]: crate::BuildConfig::load_into_docker
+
+use crate::error::{Error, Result};
+use bytes::{Buf, Bytes, BytesMut};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_stream::Stream;
+
+/// Docker socket used when `DOCKER_HOST` is not set
+pub const DEFAULT_DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+/// Address of a Docker daemon's API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DockerDaemon {
+    /// API served on a Unix socket
+    Unix(PathBuf),
+    /// API served over HTTP, e.g. `http://127.0.0.1:2375`
+    Http(String),
+}
+
+impl Default for DockerDaemon {
+    fn default() -> Self {
+        DockerDaemon::Unix(PathBuf::from(DEFAULT_DOCKER_SOCKET))
+    }
+}
+
+impl DockerDaemon {
+    /// Daemon named by `DOCKER_HOST`, or the local socket
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("DOCKER_HOST") {
+            Ok(host) if !host.is_empty() => Self::parse(&host),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Parse a `DOCKER_HOST` style address
+    ///
+    /// Accepts `unix://` socket paths and `tcp://`, `http://` or
+    /// `https://` URLs; `tcp://` is plain HTTP.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::docker::DockerDaemon;
+    /// use std::path::PathBuf;
+    ///
+    /// assert_eq!(
+    ///     DockerDaemon::parse("unix:///run/user/1000/docker.sock")?,
+    ///     DockerDaemon::Unix(PathBuf::from("/run/user/1000/docker.sock"))
+    /// );
+    /// assert_eq!(
+    ///     DockerDaemon::parse("tcp://10.0.0.5:2375")?,
+    ///     DockerDaemon::Http("http://10.0.0.5:2375".to_string())
+    /// );
+    /// # Ok::<(), buildkit_client::Error>(())
+    /// ```
+    pub fn parse(host: &str) -> Result<Self> {
+        if let Some(path) = host.strip_prefix("unix://") {
+            if path.is_empty() {
+                return Err(Error::InvalidConfig(format!(
+                    "Docker host has no socket path: {}",
+                    host
+                )));
+            }
+            return Ok(DockerDaemon::Unix(PathBuf::from(path)));
+        }
+        if let Some(address) = host.strip_prefix("tcp://") {
+            return Ok(DockerDaemon::Http(format!(
+                "http://{}",
+                address.trim_end_matches('/')
+            )));
+        }
+        if host.starts_with("http://") || host.starts_with("https://") {
+            return Ok(DockerDaemon::Http(host.trim_end_matches('/').to_string()));
+        }
+        Err(Error::InvalidConfig(format!(
+            "unsupported Docker host: {} (expected unix://, tcp:// or http(s)://)",
+            host
+        )))
+    }
+
+    /// Load a docker-format image tarball into the daemon
+    ///
+    /// The tarball is streamed to the daemon as it is read. Returns the
+    /// names of the loaded images, or their IDs for untagged images.
+    pub async fn load<S>(&self, tarball: S) -> Result<Vec<String>>
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+    {
+        let (http, base) = self.client()?;
+        let url = format!("{}/images/load?quiet=1", base);
+        tracing::info!("Loading image into Docker daemon at {}", self);
+
+        let response = http
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-tar")
+            .body(reqwest::Body::wrap_stream(tarball))
+            .send()
+            .await
+            .map_err(|e| Error::docker(format!("failed to reach {}: {}", self, e)))?;
+
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| Error::docker(format!("failed to read load response: {}", e)))?;
+        if !status.is_success() {
+            return Err(Error::docker(format!(
+                "image load returned {}: {}",
+                status,
+                error_message(body)
+            )));
+        }
+
+        let images = parse_load_output(&body)?;
+        tracing::info!("Loaded {}", images.join(", "));
+        Ok(images)
+    }
+
+    /// Run a command in a running container, attached to its stdin and stdout
+    ///
+    /// Used to reach the buildkitd of a buildx `docker-container` builder
+    /// through `buildctl dial-stdio`. Standard error of the command is
+    /// logged.
+    pub async fn exec_stdio(&self, container: &str, cmd: &[&str]) -> Result<ExecStream> {
+        let (http, base) = self.client()?;
+        tracing::debug!("Running {:?} in container {} at {}", cmd, container, self);
+
+        let response = http
+            .post(format!("{}/containers/{}/exec", base, container))
+            .json(&serde_json::json!({
+                "AttachStdin": true,
+                "AttachStdout": true,
+                "AttachStderr": true,
+                "Cmd": cmd,
+            }))
+            .send()
+            .await
+            .map_err(|e| Error::docker(format!("failed to reach {}: {}", self, e)))?;
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| Error::docker(format!("failed to read exec response: {}", e)))?;
+        if !status.is_success() {
+            return Err(Error::docker(format!(
+                "exec in container {} returned {}: {}",
+                container,
+                status,
+                error_message(body)
+            )));
+        }
+        let created: serde_json::Value = serde_json::from_str(&body)
+            .map_err(|e| Error::docker(format!("invalid exec response: {}", e)))?;
+        let id = created["Id"]
+            .as_str()
+            .ok_or_else(|| Error::docker("exec response has no ID"))?;
+
+        // The daemon hijacks the connection for the exec's stdio once it
+        // is upgraded
+        let response = http
+            .post(format!("{}/exec/{}/start", base, id))
+            .header(reqwest::header::CONNECTION, "Upgrade")
+            .header(reqwest::header::UPGRADE, "tcp")
+            .json(&serde_json::json!({ "Detach": false, "Tty": false }))
+            .send()
+            .await
+            .map_err(|e| Error::docker(format!("failed to reach {}: {}", self, e)))?;
+        let status = response.status();
+        if status != reqwest::StatusCode::SWITCHING_PROTOCOLS {
+            let body = response.text().await.unwrap_or_default();
+            return Err(Error::docker(format!(
+                "exec start returned {}: {}",
+                status,
+                error_message(body)
+            )));
+        }
+        let io = response
+            .upgrade()
+            .await
+            .map_err(|e| Error::docker(format!("failed to attach to exec: {}", e)))?;
+        Ok(ExecStream::new(io))
+    }
+
+    /// HTTP client for the daemon and the base URL of its API
+    fn client(&self) -> Result<(reqwest::Client, String)> {
+        match self {
+            #[cfg(unix)]
+            DockerDaemon::Unix(path) => {
+                let http = reqwest::Client::builder()
+                    .unix_socket(path.as_path())
+                    .build()
+                    .map_err(|e| Error::docker(format!("failed to create client: {}", e)))?;
+                // The host is ignored when connecting over the socket
+                Ok((http, "http://localhost".to_string()))
+            }
+            #[cfg(not(unix))]
+            DockerDaemon::Unix(path) => Err(Error::InvalidConfig(format!(
+                "Unix sockets are not supported on this platform: {}",
+                path.display()
+            ))),
+            DockerDaemon::Http(url) => Ok((reqwest::Client::new(), url.clone())),
+        }
+    }
+}
+
+impl std::fmt::Display for DockerDaemon {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DockerDaemon::Unix(path) => write!(f, "unix://{}", path.display()),
+            DockerDaemon::Http(url) => f.write_str(url),
+        }
+    }
+}
+
+/// Standard output of a Docker exec, with the stdin of the command as its
+/// write half
+///
+/// Without a TTY the daemon multiplexes stdout and stderr in frames of an
+/// 8-byte header (stream, 3 bytes padding, big-endian length) and the
+/// payload; reads return the stdout payloads only.
+pub struct ExecStream<T = reqwest::Upgraded> {
+    io: T,
+    buffer: BytesMut,
+    /// Stream of the current frame, 1 for stdout and 2 for stderr
+    stream: u8,
+    /// Bytes of the current frame's payload not yet buffered or returned
+    remaining: usize,
+}
+
+impl<T> ExecStream<T> {
+    fn new(io: T) -> Self {
+        Self {
+            io,
+            buffer: BytesMut::new(),
+            stream: 1,
+            remaining: 0,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for ExecStream<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExecStream")
+            .field("stream", &self.stream)
+            .field("remaining", &self.remaining)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for ExecStream<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            if this.remaining > 0 && !this.buffer.is_empty() {
+                let n = this.remaining.min(this.buffer.len());
+                if this.stream == 1 {
+                    let n = n.min(buf.remaining());
+                    buf.put_slice(&this.buffer[..n]);
+                    this.buffer.advance(n);
+                    this.remaining -= n;
+                    return Poll::Ready(Ok(()));
+                }
+                let output = this.buffer.split_to(n);
+                tracing::debug!(
+                    "exec stderr: {}",
+                    String::from_utf8_lossy(&output).trim_end()
+                );
+                this.remaining -= n;
+                continue;
+            }
+            if this.remaining == 0 && this.buffer.len() >= 8 {
+                let header = this.buffer.split_to(8);
+                this.stream = header[0];
+                this.remaining =
+                    u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+                continue;
+            }
+
+            let mut chunk = [0u8; 8192];
+            let mut read = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.io).poll_read(cx, &mut read))?;
+            if read.filled().is_empty() {
+                if this.remaining == 0 && this.buffer.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+                return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            this.buffer.extend_from_slice(read.filled());
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for ExecStream<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+/// Message of a Docker API error body, or the body itself
+fn error_message(body: String) -> String {
+    serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|v| v["message"].as_str().map(str::to_string))
+        .unwrap_or(body)
+        .trim()
+        .to_string()
+}
+
+/// Collect loaded images from the JSON messages of `/images/load`
+///
+/// The daemon reports a failed load with status 200 and an `error`
+/// message, so every line is checked.
+fn parse_load_output(body: &str) -> Result<Vec<String>> {
+    let mut images = Vec::new();
+    for line in body.lines().filter(|line| !line.trim().is_empty()) {
+        let message: serde_json::Value = serde_json::from_str(line)
+            .map_err(|e| Error::docker(format!("invalid load response: {}", e)))?;
+        if let Some(error) = message["error"].as_str() {
+            return Err(Error::docker(format!("image load failed: {}", error)));
+        }
+        let Some(stream) = message["stream"].as_str() else {
+            continue;
+        };
+        for output in stream.lines() {
+            let image = output
+                .strip_prefix("Loaded image: ")
+                .or_else(|| output.strip_prefix("Loaded image ID: "));
+            if let Some(image) = image {
+                images.push(image.trim().to_string());
+            }
+        }
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_output_lists_loaded_images() {
+        let body = concat!(
+            "{\"stream\":\"Loaded image: app:latest\\n\"}\r\n",
+            "{\"stream\":\"Loaded image ID: sha256:abc\\n\"}\r\n",
+        );
+        assert_eq!(
+            parse_load_output(body).unwrap(),
+            vec!["app:latest", "sha256:abc"]
+        );
+    }
+
+    #[test]
+    fn load_errors_are_reported() {
+        let body =
+            "{\"errorDetail\":{\"message\":\"unexpected EOF\"},\"error\":\"unexpected EOF\"}\n";
+        let err = parse_load_output(body).unwrap_err();
+        assert!(matches!(err, Error::Docker(_)));
+        assert!(err.to_string().contains("unexpected EOF"));
+    }
+
+    #[tokio::test]
+    async fn exec_output_is_demultiplexed() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (client, mut daemon) = tokio::io::duplex(64);
+        let mut stream = ExecStream::new(client);
+        tokio::spawn(async move {
+            let mut frames = Vec::new();
+            for (kind, payload) in [
+                (1u8, &b"hello "[..]),
+                (2, b"warning"),
+                (1, b""),
+                (1, b"world"),
+            ] {
+                frames.extend_from_slice(&[kind, 0, 0, 0]);
+                frames.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+                frames.extend_from_slice(payload);
+            }
+            // Written in small pieces to split headers across reads
+            for piece in frames.chunks(5) {
+                daemon.write_all(piece).await.unwrap();
+            }
+        });
+
+        let mut output = String::new();
+        stream.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "hello world");
+    }
+
+    #[test]
+    fn unsupported_hosts_are_rejected() {
+        assert_eq!(
+            DockerDaemon::parse("https://docker.example.com:2376/").unwrap(),
+            DockerDaemon::Http("https://docker.example.com:2376".to_string())
+        );
+        assert!(DockerDaemon::parse("ssh://user@host").is_err());
+        assert!(DockerDaemon::parse("unix://").is_err());
+    }
+}
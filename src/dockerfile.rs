@@ -0,0 +1,291 @@
+//! Best-effort static analysis of Dockerfiles
+
+/// Context paths a Dockerfile reads, for pruning the context upload
+///
+/// Collects the sources of `COPY` and `ADD` instructions (excluding
+/// `--from` stages, URLs and heredocs) and of `RUN --mount=type=bind`
+/// mounts from the context. Glob sources are reduced to the directory
+/// before the first wildcard.
+///
+/// Returns `None` when the whole context may be needed: a source is the
+/// context root, depends on a build argument, or cannot be parsed.
+///
+/// # Example
+///
+/// ```
+/// use buildkit_client::dockerfile::context_sources;
+///
+/// let dockerfile = "FROM rust\nCOPY Cargo.toml Cargo.lock ./\nCOPY src/ src/\n";
+/// assert_eq!(
+///     context_sources(dockerfile),
+///     Some(vec!["Cargo.lock".to_string(), "Cargo.toml".to_string(), "src".to_string()])
+/// );
+/// assert_eq!(context_sources("FROM rust\nCOPY . .\n"), None);
+/// ```
+pub fn context_sources(dockerfile: &str) -> Option<Vec<String>> {
+    let mut sources = Vec::new();
+
+    for instruction in instructions(dockerfile) {
+        let (keyword, rest) = split_keyword(&instruction);
+        match keyword.to_ascii_uppercase().as_str() {
+            "COPY" | "ADD" => {
+                let Some(args) = copy_sources(rest)? else {
+                    continue;
+                };
+                for source in args {
+                    sources.push(source_root(&source)?);
+                }
+            }
+            "RUN" => {
+                for source in bind_mount_sources(rest) {
+                    sources.push(source_root(&source)?);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    sources.sort();
+    sources.dedup();
+    Some(sources)
+}
+
+/// Logical instructions with line continuations joined, comments and
+/// heredoc bodies removed
+fn instructions(dockerfile: &str) -> Vec<String> {
+    let mut escape = '\\';
+    let mut lines = dockerfile.lines().peekable();
+
+    // Parser directives must come first; only `escape` matters here
+    while let Some(line) = lines.peek() {
+        let Some(directive) = line.trim().strip_prefix('#') else {
+            break;
+        };
+        if let Some((key, value)) = directive.split_once('=') {
+            if key.trim().eq_ignore_ascii_case("escape") {
+                escape = value.trim().chars().next().unwrap_or('\\');
+            }
+            lines.next();
+        } else {
+            break;
+        }
+    }
+
+    let mut instructions = Vec::new();
+    let mut current = String::new();
+    let mut heredoc_end: Vec<String> = Vec::new();
+
+    for line in lines {
+        if let Some(end) = heredoc_end.first() {
+            if line.trim() == end {
+                heredoc_end.remove(0);
+            }
+            continue;
+        }
+
+        // Comments and blank lines are dropped, also inside continuations
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        match trimmed.strip_suffix(escape) {
+            Some(head) => {
+                current.push_str(head);
+                current.push(' ');
+            }
+            None => {
+                current.push_str(trimmed);
+                heredoc_end = heredoc_terminators(&current);
+                instructions.push(std::mem::take(&mut current));
+            }
+        }
+    }
+    if !current.is_empty() {
+        instructions.push(current);
+    }
+    instructions
+}
+
+/// Terminator words of the heredocs (`<<EOF`, `<<-"EOF"`) opened by an instruction
+fn heredoc_terminators(instruction: &str) -> Vec<String> {
+    instruction
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix("<<"))
+        .map(|word| word.trim_start_matches('-').trim_matches(['"', '\'']))
+        .filter(|word| !word.is_empty() && word.chars().all(|c| c.is_alphanumeric() || c == '_'))
+        .map(str::to_string)
+        .collect()
+}
+
+fn split_keyword(instruction: &str) -> (&str, &str) {
+    match instruction.split_once(char::is_whitespace) {
+        Some((keyword, rest)) => (keyword, rest.trim()),
+        None => (instruction, ""),
+    }
+}
+
+/// Context sources of a `COPY`/`ADD` instruction
+///
+/// `Some(None)` for instructions that do not read the context (`--from`,
+/// heredocs); `None` if the arguments cannot be parsed.
+fn copy_sources(args: &str) -> Option<Option<Vec<String>>> {
+    let mut rest = args;
+    while let Some(flag) = rest.strip_prefix("--") {
+        let (flag, tail) = flag.split_once(char::is_whitespace).unwrap_or((flag, ""));
+        if flag.starts_with("from=") {
+            return Some(None);
+        }
+        rest = tail.trim_start();
+    }
+
+    let mut words: Vec<String> = if rest.starts_with('[') {
+        serde_json::from_str(rest).ok()?
+    } else {
+        rest.split_whitespace().map(str::to_string).collect()
+    };
+    // The last argument is the destination
+    words.pop()?;
+    if words.is_empty() {
+        return None;
+    }
+
+    let sources = words
+        .into_iter()
+        .filter(|w| !w.starts_with("<<") && !w.contains("://") && !w.starts_with("git@"))
+        .collect();
+    Some(Some(sources))
+}
+
+/// Context sources of `--mount=type=bind` flags on a `RUN` instruction
+///
+/// Bind mounts without `from=` read the build context, `.` by default.
+fn bind_mount_sources(args: &str) -> Vec<String> {
+    let mut sources = Vec::new();
+    let mut rest = args;
+    while let Some(flag) = rest.strip_prefix("--") {
+        let (flag, tail) = flag.split_once(char::is_whitespace).unwrap_or((flag, ""));
+        rest = tail.trim_start();
+
+        let Some(options) = flag.strip_prefix("mount=") else {
+            continue;
+        };
+        let mut mount_type = "bind";
+        let mut source = ".";
+        let mut from_context = true;
+        for option in options.split(',') {
+            match option.split_once('=') {
+                Some(("type", value)) => mount_type = value,
+                Some(("source" | "src", value)) => source = value,
+                Some(("from", _)) => from_context = false,
+                _ => {}
+            }
+        }
+        if mount_type == "bind" && from_context {
+            sources.push(source.to_string());
+        }
+    }
+    sources
+}
+
+/// Directory or file under the context root that a source reads
+///
+/// `None` if the source covers the whole context or cannot be resolved
+/// statically.
+fn source_root(source: &str) -> Option<String> {
+    if source.contains('$') {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    for segment in source.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            s if s.contains(['*', '?', '[', '\\']) => break,
+            s => segments.push(s),
+        }
+    }
+
+    if segments.is_empty() {
+        None
+    } else {
+        Some(segments.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(dockerfile: &str) -> Option<Vec<String>> {
+        context_sources(dockerfile)
+    }
+
+    fn paths(paths: &[&str]) -> Option<Vec<String>> {
+        Some(paths.iter().map(|p| p.to_string()).collect())
+    }
+
+    #[test]
+    fn copy_and_add_sources_are_collected() {
+        let dockerfile = r#"
+FROM rust:1 AS build
+WORKDIR /app
+COPY --chown=1000:1000 Cargo.toml ./
+COPY ["crates/core", "/app/crates/core"]
+ADD https://example.com/file.tar.gz /tmp/
+ADD vendor/archive.tar.gz /opt/
+COPY src/*.rs /app/src/
+
+FROM alpine
+COPY --from=build /app/target/release/app /usr/bin/app
+"#;
+        assert_eq!(
+            sources(dockerfile),
+            paths(&["Cargo.toml", "crates/core", "src", "vendor/archive.tar.gz"])
+        );
+    }
+
+    #[test]
+    fn whole_context_sources_disable_pruning() {
+        assert_eq!(sources("FROM a\nCOPY . /app\n"), None);
+        assert_eq!(sources("FROM a\nCOPY ./ /app\n"), None);
+        assert_eq!(sources("FROM a\nCOPY * /app/\n"), None);
+        assert_eq!(sources("FROM a\nARG DIR\nCOPY $DIR /app\n"), None);
+        assert_eq!(sources("FROM a\nRUN --mount=type=bind make\n"), None);
+    }
+
+    #[test]
+    fn continuations_comments_and_heredocs_are_handled() {
+        let dockerfile = r#"# syntax=docker/dockerfile:1
+FROM alpine
+# COPY ignored /x
+COPY a.txt \
+     b.txt \
+     /dst/
+RUN <<EOF
+COPY . /nope
+EOF
+COPY <<EOF /etc/config
+key=value
+EOF
+RUN --mount=type=bind,source=scripts,target=/scripts \
+    --mount=type=cache,target=/root/.cache \
+    /scripts/build.sh
+"#;
+        assert_eq!(sources(dockerfile), paths(&["a.txt", "b.txt", "scripts"]));
+    }
+
+    #[test]
+    fn escape_directive_is_honored() {
+        let dockerfile = "# escape=`\nFROM windows\nCOPY app `\n  C:\\app\n";
+        assert_eq!(sources(dockerfile), paths(&["app"]));
+    }
+
+    #[test]
+    fn dockerfile_without_context_reads_is_empty() {
+        assert_eq!(sources("FROM alpine\nRUN echo hi\n"), paths(&[]));
+    }
+}
@@ -1,5 +1,12 @@
 //! Error types for BuildKit client operations
 
+use crate::proto::errdefs::{Source as SourceErrorInfo, Vertex as VertexErrorInfo};
+use crate::proto::google::rpc::Status as RpcStatus;
+use crate::proto::stack::Stack;
+use crate::redact::Scrubber;
+use crate::report::BuildReport;
+use prost::Message;
+use serde::Serialize;
 use std::path::PathBuf;
 use thiserror::Error;
 
@@ -21,10 +28,42 @@ pub enum Error {
     #[error("Invalid BuildKit endpoint URL: {0}")]
     InvalidEndpoint(String),
 
-    /// gRPC communication errors
+    /// gRPC communication errors not covered by a more specific variant
     #[error("gRPC communication failed: {0}")]
     Grpc(Box<tonic::Status>),
 
+    /// BuildKit is temporarily unreachable (`UNAVAILABLE`)
+    #[error("BuildKit unavailable: {}", .0.message())]
+    Unavailable(Box<tonic::Status>),
+
+    /// The call did not complete before its deadline (`DEADLINE_EXCEEDED`)
+    #[error("BuildKit request timed out: {}", .0.message())]
+    DeadlineExceeded(Box<tonic::Status>),
+
+    /// The caller lacks permission for the operation (`PERMISSION_DENIED`)
+    #[error("Permission denied: {}", .0.message())]
+    PermissionDenied(Box<tonic::Status>),
+
+    /// The caller could not be authenticated (`UNAUTHENTICATED`)
+    #[error("Authentication failed: {}", .0.message())]
+    Unauthenticated(Box<tonic::Status>),
+
+    /// BuildKit ran out of a resource such as disk or workers (`RESOURCE_EXHAUSTED`)
+    #[error("BuildKit resources exhausted: {}", .0.message())]
+    ResourceExhausted(Box<tonic::Status>),
+
+    /// A referenced entity does not exist (`NOT_FOUND`)
+    #[error("Not found: {}", .0.message())]
+    NotFound(Box<tonic::Status>),
+
+    /// BuildKit rejected the request as malformed (`INVALID_ARGUMENT`)
+    #[error("Invalid argument: {}", .0.message())]
+    InvalidArgument(Box<tonic::Status>),
+
+    /// The operation was cancelled (`CANCELLED`)
+    #[error("Operation cancelled: {}", .0.message())]
+    Cancelled(Box<tonic::Status>),
+
     /// Session-related errors
     #[error("Session error: {0}")]
     Session(String),
@@ -37,6 +76,17 @@ pub enum Error {
     #[error("File system error: {0}")]
     Io(#[from] std::io::Error),
 
+    /// A filesystem operation on a specific path failed
+    #[error("Failed to {operation} {}: {source}", .path.display())]
+    FileOperation {
+        /// Operation that failed (e.g., "open", "read directory")
+        operation: &'static str,
+        /// Path the operation was performed on
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
     /// Path does not exist
     #[error("Path does not exist: {0}")]
     PathNotFound(PathBuf),
@@ -57,10 +107,34 @@ pub enum Error {
         source: std::io::Error,
     },
 
+    /// A context file changed after the context was snapshotted
+    #[error("Build context file changed during the build: {}", .0.display())]
+    ContextChanged(PathBuf),
+
     /// Build execution errors
     #[error("Build execution failed: {0}")]
     Build(String),
 
+    /// The build was cancelled through the client's cancellation token,
+    /// with the steps that ran until then
+    #[error("Build cancelled")]
+    BuildCancelled(Box<BuildReport>),
+
+    /// A build step failed, with the tail of its log output and the
+    /// Dockerfile lines it came from
+    #[error(
+        "Build step '{}' failed: {}{}{}",
+        .0.step,
+        .0.message,
+        format_location(.0.source_location.as_ref()),
+        format_log_tail(&.0.logs)
+    )]
+    BuildStepFailed(Box<StepFailure>),
+
+    /// No BuildKit worker satisfies the build's worker constraints
+    #[error("No worker matches constraints {constraints}: {reason}")]
+    WorkerUnavailable { constraints: String, reason: String },
+
     /// Invalid build configuration
     #[error("Invalid build configuration: {0}")]
     InvalidConfig(String),
@@ -69,6 +143,10 @@ pub enum Error {
     #[error("Invalid platform format: {0}")]
     InvalidPlatform(String),
 
+    /// Invalid image reference
+    #[error("Invalid image reference '{reference}': {reason}")]
+    InvalidReference { reference: String, reason: String },
+
     /// Progress monitoring errors
     #[error("Progress monitoring failed: {0}")]
     Progress(String),
@@ -114,6 +192,14 @@ pub enum Error {
         source: prost::EncodeError,
     },
 
+    /// Registry API errors
+    #[error("Registry error: {0}")]
+    Registry(String),
+
+    /// Docker daemon API errors
+    #[error("Docker daemon error: {0}")]
+    Docker(String),
+
     /// Secrets error
     #[error("Secrets error: {0}")]
     Secrets(String),
@@ -126,6 +212,10 @@ pub enum Error {
     #[error("Secrets service is not configured")]
     SecretsNotConfigured,
 
+    /// A secret is larger than BuildKit accepts
+    #[error("Secret '{id}' is {size} bytes, more than the maximum of {max}")]
+    SecretTooLarge { id: String, size: usize, max: usize },
+
     /// Generic error for compatibility during migration
     #[error("{0}")]
     Other(String),
@@ -152,11 +242,34 @@ impl Error {
         Error::Progress(msg.into())
     }
 
+    /// Create a registry error
+    pub fn registry(msg: impl Into<String>) -> Self {
+        Error::Registry(msg.into())
+    }
+
+    /// Create a Docker daemon error
+    pub fn docker(msg: impl Into<String>) -> Self {
+        Error::Docker(msg.into())
+    }
+
     /// Create a secrets error
     pub fn secrets(msg: impl Into<String>) -> Self {
         Error::Secrets(msg.into())
     }
 
+    /// Create a file operation error for the given path
+    pub fn file_operation(
+        operation: &'static str,
+        path: impl Into<PathBuf>,
+        source: std::io::Error,
+    ) -> Self {
+        Error::FileOperation {
+            operation,
+            path: path.into(),
+            source,
+        }
+    }
+
     /// Create a send failed error
     pub fn send_failed(message_type: impl Into<String>, reason: impl Into<String>) -> Self {
         Error::SendFailed {
@@ -185,6 +298,442 @@ impl Error {
     pub fn other(msg: impl Into<String>) -> Self {
         Error::Other(msg.into())
     }
+
+    /// The underlying gRPC status, if this error originated from one
+    pub fn grpc_status(&self) -> Option<&tonic::Status> {
+        match self {
+            Error::Grpc(status)
+            | Error::Unavailable(status)
+            | Error::DeadlineExceeded(status)
+            | Error::PermissionDenied(status)
+            | Error::Unauthenticated(status)
+            | Error::ResourceExhausted(status)
+            | Error::NotFound(status)
+            | Error::InvalidArgument(status)
+            | Error::Cancelled(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// Remove credentials and secret values from the error's messages
+    ///
+    /// Applied to errors returned from a build, since BuildKit echoes the
+    /// context URL (including a GitHub token) and command output back in
+    /// its error messages.
+    pub(crate) fn scrub(self, scrubber: &Scrubber) -> Self {
+        let scrub_status = |status: Box<tonic::Status>| {
+            Box::new(tonic::Status::with_details_and_metadata(
+                status.code(),
+                scrubber.scrub(status.message()),
+                bytes::Bytes::copy_from_slice(status.details()),
+                status.metadata().clone(),
+            ))
+        };
+
+        match self {
+            Error::Grpc(status) => Error::Grpc(scrub_status(status)),
+            Error::Unavailable(status) => Error::Unavailable(scrub_status(status)),
+            Error::DeadlineExceeded(status) => Error::DeadlineExceeded(scrub_status(status)),
+            Error::PermissionDenied(status) => Error::PermissionDenied(scrub_status(status)),
+            Error::Unauthenticated(status) => Error::Unauthenticated(scrub_status(status)),
+            Error::ResourceExhausted(status) => Error::ResourceExhausted(scrub_status(status)),
+            Error::NotFound(status) => Error::NotFound(scrub_status(status)),
+            Error::InvalidArgument(status) => Error::InvalidArgument(scrub_status(status)),
+            Error::Cancelled(status) => Error::Cancelled(scrub_status(status)),
+            Error::BuildStepFailed(mut failure) => {
+                failure.step = scrubber.scrub(&failure.step);
+                failure.message = scrubber.scrub(&failure.message);
+                for line in &mut failure.logs {
+                    *line = scrubber.scrub(line);
+                }
+                if let Some(location) = &mut failure.source_location {
+                    for line in &mut location.lines {
+                        *line = scrubber.scrub(line);
+                    }
+                }
+                Error::BuildStepFailed(failure)
+            }
+            Error::Session(msg) => Error::Session(scrubber.scrub(&msg)),
+            Error::Build(msg) => Error::Build(scrubber.scrub(&msg)),
+            Error::Protocol(msg) => Error::Protocol(scrubber.scrub(&msg)),
+            Error::Registry(msg) => Error::Registry(scrubber.scrub(&msg)),
+            Error::Other(msg) => Error::Other(scrubber.scrub(&msg)),
+            other => other,
+        }
+    }
+
+    /// Whether retrying the same operation may succeed
+    ///
+    /// True for transient conditions: the daemon being unreachable, timeouts,
+    /// resource exhaustion, aborted transactions, and context files changing
+    /// while they were uploaded.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Connection { .. }
+            | Error::Unavailable(_)
+            | Error::DeadlineExceeded(_)
+            | Error::ResourceExhausted(_)
+            | Error::Http2Handshake { .. }
+            | Error::ContextChanged(_) => true,
+            Error::Grpc(status) => status.code() == tonic::Code::Aborted,
+            _ => false,
+        }
+    }
+
+    /// Whether the error was caused by the caller's input or credentials
+    ///
+    /// Such errors will fail again on retry and usually should not page an
+    /// operator; they need to be fixed by whoever submitted the build.
+    pub fn is_user_error(&self) -> bool {
+        match self {
+            Error::PermissionDenied(_)
+            | Error::Unauthenticated(_)
+            | Error::NotFound(_)
+            | Error::InvalidArgument(_)
+            | Error::InvalidEndpoint(_)
+            | Error::PathNotFound(_)
+            | Error::NotADirectory(_)
+            | Error::PathOutsideRoot { .. }
+            | Error::BuildStepFailed(_)
+            | Error::InvalidConfig(_)
+            | Error::InvalidPlatform(_)
+            | Error::InvalidReference { .. }
+            | Error::SecretNotFound(_)
+            | Error::SecretTooLarge { .. } => true,
+            Error::FileOperation { source, .. } => matches!(
+                source.kind(),
+                std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::NotFound
+            ),
+            Error::Grpc(status) => status.code() == tonic::Code::FailedPrecondition,
+            _ => false,
+        }
+    }
+
+    /// Stable, machine-readable name of the error kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Connection { .. } => "connection",
+            Error::InvalidEndpoint(_) => "invalid_endpoint",
+            Error::Grpc(_) => "grpc",
+            Error::Unavailable(_) => "unavailable",
+            Error::DeadlineExceeded(_) => "deadline_exceeded",
+            Error::PermissionDenied(_) => "permission_denied",
+            Error::Unauthenticated(_) => "unauthenticated",
+            Error::ResourceExhausted(_) => "resource_exhausted",
+            Error::NotFound(_) => "not_found",
+            Error::InvalidArgument(_) => "invalid_argument",
+            Error::Cancelled(_) => "cancelled",
+            Error::Session(_) => "session",
+            Error::SessionNotStarted => "session_not_started",
+            Error::Io(_) => "io",
+            Error::FileOperation { .. } => "file_operation",
+            Error::PathNotFound(_) => "path_not_found",
+            Error::NotADirectory(_) => "not_a_directory",
+            Error::PathOutsideRoot { .. } => "path_outside_root",
+            Error::PathResolution { .. } => "path_resolution",
+            Error::ContextChanged(_) => "context_changed",
+            Error::Build(_) => "build",
+            Error::BuildStepFailed(_) => "build_step_failed",
+            Error::BuildCancelled(_) => "build_cancelled",
+            Error::WorkerUnavailable { .. } => "worker_unavailable",
+            Error::InvalidConfig(_) => "invalid_config",
+            Error::InvalidPlatform(_) => "invalid_platform",
+            Error::InvalidReference { .. } => "invalid_reference",
+            Error::Progress(_) => "progress",
+            Error::Protocol(_) => "protocol",
+            Error::Http2Handshake { .. } => "http2_handshake",
+            Error::Http2Stream { .. } => "http2_stream",
+            Error::SendFailed { .. } => "send_failed",
+            Error::Decode { .. } => "decode",
+            Error::Encode { .. } => "encode",
+            Error::Registry(_) => "registry",
+            Error::Docker(_) => "docker",
+            Error::Secrets(_) => "secrets",
+            Error::SecretNotFound(_) => "secret_not_found",
+            Error::SecretsNotConfigured => "secrets_not_configured",
+            Error::SecretTooLarge { .. } => "secret_too_large",
+            Error::Other(_) => "other",
+        }
+    }
+
+    /// Build a structured report of this error
+    pub fn to_report(&self) -> ErrorReport {
+        let mut chain = vec![self.to_string()];
+        let mut source = std::error::Error::source(self);
+        while let Some(err) = source {
+            chain.push(err.to_string());
+            source = err.source();
+        }
+
+        let mut report = ErrorReport {
+            kind: self.kind(),
+            message: chain[0].clone(),
+            chain,
+            step: None,
+            digest: None,
+            exit_code: None,
+            logs: Vec::new(),
+            source_location: None,
+            session_id: None,
+            retryable: self.is_retryable(),
+            hints: self.hints(),
+        };
+
+        if let Error::BuildStepFailed(failure) = self {
+            // The log tail is reported separately, so keep the message short
+            report.message = format!("Build step '{}' failed: {}", failure.step, failure.message);
+            report.step = Some(failure.step.clone());
+            report.digest = Some(failure.digest.clone());
+            report.exit_code = failure.exit_code;
+            report.logs = failure.logs.clone();
+            report.session_id = failure.session_id.clone();
+            report.source_location = failure.source_location.clone();
+        }
+
+        report
+    }
+
+    /// Suggestions for resolving the error, if there are any
+    fn hints(&self) -> Vec<String> {
+        let hints: &[&str] = match self {
+            Error::Connection { .. } | Error::Unavailable(_) => {
+                &["Check that buildkitd is running and reachable at the configured address"]
+            }
+            Error::DeadlineExceeded(_) => &["The request timed out; retry or raise the timeout"],
+            Error::Unauthenticated(_) | Error::PermissionDenied(_) => {
+                &["Check the registry credentials and their permissions"]
+            }
+            Error::ResourceExhausted(_) => {
+                &["BuildKit is out of resources; free disk space or prune the build cache"]
+            }
+            Error::PathNotFound(_) | Error::NotADirectory(_) | Error::PathResolution { .. } => {
+                &["Check that the build context path exists and is a directory"]
+            }
+            Error::BuildStepFailed(_) => &["Inspect the step output for the cause of the failure"],
+            Error::InvalidReference { .. } => {
+                &["Image names must be lowercase, e.g. registry.example.com/team/app:tag"]
+            }
+            Error::InvalidPlatform(_) => &["Platforms use the form os/arch[/variant]"],
+            Error::WorkerUnavailable { .. } => &[
+                "Connect to a builder whose default worker matches; list workers with `buildctl debug workers -v`",
+            ],
+            Error::SecretNotFound(_) => &["Provide the secret referenced by the Dockerfile"],
+            Error::SecretTooLarge { .. } => {
+                &["Mount large files with a bind or cache mount instead of a secret"]
+            }
+            Error::Docker(_) => {
+                &["Check that dockerd is running; set DOCKER_HOST to use another daemon"]
+            }
+            _ => &[],
+        };
+        hints.iter().map(|h| h.to_string()).collect()
+    }
+}
+
+/// Details of a failed build step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepFailure {
+    /// Vertex name (e.g., "[2/3] RUN make")
+    pub step: String,
+    /// Vertex digest
+    pub digest: String,
+    /// Process exit code, if the step was a failed process
+    pub exit_code: Option<i32>,
+    /// Error message reported by BuildKit
+    pub message: String,
+    /// Last lines of the step's log output
+    pub logs: Vec<String>,
+    /// Session the failing build ran in
+    pub session_id: Option<String>,
+    /// Dockerfile (or other build definition) lines the step came from
+    pub source_location: Option<SourceLocation>,
+    /// Frames of the BuildKit stack trace attached to the error, innermost
+    /// first, e.g. `github.com/moby/buildkit/solver.(*edge).execOp
+    /// (solver/edge.go:123)`
+    pub stack: Vec<String>,
+}
+
+/// Lines of a build definition source, such as the Dockerfile instruction
+/// of a failed step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceLocation {
+    /// Source file name, e.g. `Dockerfile`
+    pub filename: String,
+    /// First line of the range, counting from 1
+    pub start_line: i32,
+    /// Last line of the range
+    pub end_line: i32,
+    /// Text of the lines, if BuildKit sent the source
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lines: Vec<String>,
+}
+
+impl std::fmt::Display for SourceLocation {
+    /// `Dockerfile:3` or `Dockerfile:3-5`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.filename, self.start_line)?;
+        if self.end_line > self.start_line {
+            write!(f, "-{}", self.end_line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Typed details BuildKit attaches to the gRPC status of a failed solve
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SolveErrorDetails {
+    /// Digest of the vertex that failed
+    pub(crate) vertex: Option<String>,
+    /// Source lines of the failed step
+    pub(crate) source_location: Option<SourceLocation>,
+    /// Stack trace frames, innermost first
+    pub(crate) stack: Vec<String>,
+}
+
+impl SolveErrorDetails {
+    /// Decode the `errdefs.Vertex`, `errdefs.Source` and `stack.Stack`
+    /// details of a status; the first of each kind is used
+    pub(crate) fn decode(status: &tonic::Status) -> Self {
+        let mut details = Self::default();
+        let Ok(rpc_status) = RpcStatus::decode(status.details()) else {
+            return details;
+        };
+        for any in &rpc_status.details {
+            let value = any.value.as_slice();
+            if any.type_url.ends_with("errdefs.Vertex") && details.vertex.is_none() {
+                details.vertex = VertexErrorInfo::decode(value)
+                    .ok()
+                    .map(|vertex| vertex.digest)
+                    .filter(|digest| !digest.is_empty());
+            } else if any.type_url.ends_with("errdefs.Source") && details.source_location.is_none()
+            {
+                details.source_location = SourceErrorInfo::decode(value)
+                    .ok()
+                    .and_then(|source| source_location(&source));
+            } else if any.type_url.ends_with("stack.Stack") && details.stack.is_empty() {
+                if let Ok(stack) = Stack::decode(value) {
+                    details.stack = stack
+                        .frames
+                        .iter()
+                        .map(|frame| format!("{} ({}:{})", frame.name, frame.file, frame.line))
+                        .collect();
+                }
+            }
+        }
+        details
+    }
+}
+
+/// Location of the first range of an `errdefs.Source`
+fn source_location(source: &SourceErrorInfo) -> Option<SourceLocation> {
+    let info = source.info.as_ref()?;
+    let range = source.ranges.first()?;
+    let start_line = range.start.as_ref()?.line;
+    let end_line = range
+        .end
+        .as_ref()
+        .map_or(start_line, |end| end.line.max(start_line));
+    let text = String::from_utf8_lossy(&info.data);
+    let lines = text
+        .lines()
+        .skip(usize::try_from(start_line - 1).unwrap_or(0))
+        .take(usize::try_from(end_line - start_line + 1).unwrap_or(0))
+        .map(str::to_string)
+        .collect();
+    Some(SourceLocation {
+        filename: info.filename.clone(),
+        start_line,
+        end_line,
+        lines,
+    })
+}
+
+/// Structured, serializable description of an [`Error`]
+///
+/// Intended for build orchestration systems that need to parse failures
+/// rather than scrape human-readable messages.
+///
+/// # Example
+///
+/// ```
+/// use buildkit_client::Error;
+///
+/// let report = Error::InvalidPlatform("linux".to_string()).to_report();
+/// assert_eq!(report.kind, "invalid_platform");
+/// println!("{}", report.to_json());
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    /// Stable machine-readable error kind (e.g., "build_step_failed")
+    pub kind: &'static str,
+    /// Top-level error message
+    pub message: String,
+    /// Messages of the error and all of its sources, outermost first
+    pub chain: Vec<String>,
+    /// Name of the failing build step, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+    /// Digest of the failing vertex, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// Exit code of the failing process, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Last lines of the failing step's output
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<String>,
+    /// Build definition lines of the failing step, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_location: Option<SourceLocation>,
+    /// Session the error occurred in, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Whether retrying the operation may succeed
+    pub retryable: bool,
+    /// Suggestions for resolving the error
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hints: Vec<String>,
+}
+
+impl ErrorReport {
+    /// Set the session id, e.g. when the caller manages the session itself
+    pub fn with_session_id(mut self, session_id: impl Into<String>) -> Self {
+        self.session_id = Some(session_id.into());
+        self
+    }
+
+    /// Serialize the report as a single-line JSON string
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| {
+            serde_json::json!({ "kind": self.kind, "message": self.message }).to_string()
+        })
+    }
+}
+
+/// Render the source lines of a failed step for inclusion in an error
+/// message, like `docker build` does
+fn format_location(location: Option<&SourceLocation>) -> String {
+    let Some(location) = location else {
+        return String::new();
+    };
+    let mut out = format!("\n--- {} ---", location);
+    for (line, text) in (location.start_line..).zip(&location.lines) {
+        out.push_str(&format!("\n{:>4} | {}", line, text));
+    }
+    out
+}
+
+/// Render a step's log tail for inclusion in an error message
+fn format_log_tail(logs: &[String]) -> String {
+    if logs.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("\n--- step output ---");
+    for line in logs {
+        out.push('\n');
+        out.push_str(line);
+    }
+    out
 }
 
 // Implement From for common error types
@@ -208,6 +757,17 @@ impl From<prost::DecodeError> for Error {
 
 impl From<tonic::Status> for Error {
     fn from(status: tonic::Status) -> Self {
-        Error::Grpc(Box::new(status))
+        let status = Box::new(status);
+        match status.code() {
+            tonic::Code::Unavailable => Error::Unavailable(status),
+            tonic::Code::DeadlineExceeded => Error::DeadlineExceeded(status),
+            tonic::Code::PermissionDenied => Error::PermissionDenied(status),
+            tonic::Code::Unauthenticated => Error::Unauthenticated(status),
+            tonic::Code::ResourceExhausted => Error::ResourceExhausted(status),
+            tonic::Code::NotFound => Error::NotFound(status),
+            tonic::Code::InvalidArgument => Error::InvalidArgument(status),
+            tonic::Code::Cancelled => Error::Cancelled(status),
+            _ => Error::Grpc(status),
+        }
     }
 }
@@ -0,0 +1,637 @@
+//! Build lifecycle events for telemetry
+//!
+//! Sinks registered on a [`BuildKitClient`](crate::BuildKitClient) receive an
+//! event when a build is queued, when its session starts, whenever a step
+//! finishes, and when it completes or fails. [`WebhookSink`] delivers events
+//! as JSON to an HTTP endpoint.
+
+use crate::builder::BuildConfig;
+use crate::error::{Error, ErrorReport};
+use crate::proto::moby::buildkit::v1::StatusResponse;
+use crate::redact::Redacted;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+
+/// Header carrying the HMAC-SHA256 signature of a webhook body
+pub const SIGNATURE_HEADER: &str = "X-Buildkit-Signature";
+
+/// Header carrying the event name of a webhook delivery
+pub const EVENT_HEADER: &str = "X-Buildkit-Event";
+
+/// Lifecycle event of a single build
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildEvent {
+    /// Build reference shared by all events of a build
+    pub build_ref: String,
+    /// Time the event occurred, in milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// What happened
+    #[serde(flatten)]
+    pub kind: BuildEventKind,
+}
+
+/// Kind of build lifecycle event
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum BuildEventKind {
+    /// The build was submitted
+    Queued {
+        /// Image tags the build pushes
+        tags: Vec<String>,
+        /// Hash of the build configuration, see [`BuildConfig::config_hash`]
+        config_hash: String,
+    },
+    /// The session was established and the solve request is being sent
+    Started {
+        /// Session ID used by the build
+        session_id: String,
+    },
+    /// A build step completed, was served from cache, or failed
+    StepFinished {
+        /// Step name as shown in progress output
+        step: String,
+        /// Vertex digest
+        digest: String,
+        /// Whether the step was served from cache
+        cached: bool,
+        /// Step duration, if BuildKit reported start and completion times
+        duration_ms: Option<u64>,
+        /// Error reported for the step
+        error: Option<String>,
+    },
+    /// The build completed successfully
+    Completed {
+        /// Build summary
+        summary: BuildSummary,
+    },
+    /// The build failed
+    Failed {
+        /// Build summary up to the failure
+        summary: BuildSummary,
+        /// Structured description of the error
+        error: Box<ErrorReport>,
+    },
+}
+
+impl BuildEventKind {
+    /// Stable name of the event, matching the `event` field in JSON
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuildEventKind::Queued { .. } => "queued",
+            BuildEventKind::Started { .. } => "started",
+            BuildEventKind::StepFinished { .. } => "step_finished",
+            BuildEventKind::Completed { .. } => "completed",
+            BuildEventKind::Failed { .. } => "failed",
+        }
+    }
+}
+
+/// Summary of a finished build
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BuildSummary {
+    /// Image tags the build pushes
+    pub tags: Vec<String>,
+    /// Image digest, if the build produced one
+    pub digest: Option<String>,
+    /// Wall-clock build duration in milliseconds
+    pub duration_ms: u64,
+    /// Number of finished steps
+    pub steps: usize,
+    /// Number of steps served from cache
+    pub cached_steps: usize,
+    /// Number of steps that reported an error
+    pub failed_steps: usize,
+}
+
+/// Receiver of build lifecycle events
+///
+/// `on_event` is called inline while the build runs, so implementations
+/// must not block; hand slow work such as network delivery to a background
+/// task, as [`WebhookSink`] does.
+///
+/// Closures taking a `&BuildEvent` implement this trait.
+pub trait BuildEventSink: Send + Sync {
+    /// Called for each event of every build run by the client
+    fn on_event(&self, event: &BuildEvent);
+}
+
+impl<F> BuildEventSink for F
+where
+    F: Fn(&BuildEvent) + Send + Sync,
+{
+    fn on_event(&self, event: &BuildEvent) {
+        self(event)
+    }
+}
+
+/// Emits the events of one build to the client's sinks
+pub(crate) struct BuildEvents {
+    sinks: Vec<Arc<dyn BuildEventSink>>,
+    build_ref: String,
+    config_hash: String,
+    started_at: Instant,
+    summary: BuildSummary,
+    finished: HashSet<String>,
+}
+
+impl BuildEvents {
+    pub(crate) fn new(
+        sinks: Vec<Arc<dyn BuildEventSink>>,
+        build_ref: &str,
+        config: &BuildConfig,
+    ) -> Self {
+        Self {
+            config_hash: if sinks.is_empty() {
+                String::new()
+            } else {
+                config.config_hash()
+            },
+            sinks,
+            build_ref: build_ref.to_string(),
+            started_at: Instant::now(),
+            summary: BuildSummary {
+                tags: config.tags.clone(),
+                ..Default::default()
+            },
+            finished: HashSet::new(),
+        }
+    }
+
+    fn emit(&self, kind: BuildEventKind) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        let event = BuildEvent {
+            build_ref: self.build_ref.clone(),
+            timestamp: unix_millis(),
+            kind,
+        };
+        for sink in &self.sinks {
+            sink.on_event(&event);
+        }
+    }
+
+    pub(crate) fn queued(&self) {
+        self.emit(BuildEventKind::Queued {
+            tags: self.summary.tags.clone(),
+            config_hash: self.config_hash.clone(),
+        });
+    }
+
+    pub(crate) fn started(&self, session_id: &str) {
+        self.emit(BuildEventKind::Started {
+            session_id: session_id.to_string(),
+        });
+    }
+
+    /// Emit a step event for each vertex that finished in this update
+    pub(crate) fn observe(&mut self, status: &StatusResponse) {
+        for vertex in &status.vertexes {
+            let failed = !vertex.error.is_empty();
+            if (vertex.completed.is_none() && !failed)
+                || !self.finished.insert(vertex.digest.clone())
+            {
+                continue;
+            }
+
+            self.summary.steps += 1;
+            if vertex.cached {
+                self.summary.cached_steps += 1;
+            }
+            if failed {
+                self.summary.failed_steps += 1;
+            }
+
+            let duration_ms = match (&vertex.started, &vertex.completed) {
+                (Some(start), Some(end)) => {
+                    let millis = (end.seconds - start.seconds) * 1000
+                        + i64::from(end.nanos - start.nanos) / 1_000_000;
+                    u64::try_from(millis).ok()
+                }
+                _ => None,
+            };
+
+            self.emit(BuildEventKind::StepFinished {
+                step: vertex.name.clone(),
+                digest: vertex.digest.clone(),
+                cached: vertex.cached,
+                duration_ms,
+                error: failed.then(|| vertex.error.clone()),
+            });
+        }
+    }
+
+    fn finish_summary(&self, digest: Option<String>) -> BuildSummary {
+        BuildSummary {
+            digest,
+            duration_ms: u64::try_from(self.started_at.elapsed().as_millis()).unwrap_or(u64::MAX),
+            ..self.summary.clone()
+        }
+    }
+
+    pub(crate) fn completed(&self, digest: Option<String>) {
+        self.emit(BuildEventKind::Completed {
+            summary: self.finish_summary(digest),
+        });
+    }
+
+    pub(crate) fn failed(&self, error: &Error) {
+        self.emit(BuildEventKind::Failed {
+            summary: self.finish_summary(None),
+            error: Box::new(error.to_report()),
+        });
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or(0)
+}
+
+/// Compute the signature header value for a webhook body
+///
+/// The value is `sha256=` followed by the hex HMAC-SHA256 of the body keyed
+/// with the shared secret, so receivers can verify deliveries.
+///
+/// # Example
+///
+/// ```
+/// use buildkit_client::events::sign_payload;
+///
+/// let signature = sign_payload("secret", br#"{"event":"queued"}"#);
+/// assert!(signature.starts_with("sha256="));
+/// ```
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// Message handled by the webhook delivery task
+enum Delivery {
+    Event(Box<BuildEvent>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Build event sink that POSTs each event as JSON to a webhook URL
+///
+/// Events are delivered in order by a background task, so builds are never
+/// slowed down by the endpoint. Failed deliveries (connection errors, `429`
+/// and `5xx` responses) are retried with exponential backoff; events that
+/// still fail are logged and dropped. With a secret configured, each request
+/// carries an [`SIGNATURE_HEADER`] computed by [`sign_payload`].
+///
+/// # Example
+///
+/// ```no_run
+/// use buildkit_client::events::WebhookSink;
+/// use buildkit_client::{BuildConfig, BuildKitClient};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let webhook = std::sync::Arc::new(
+///         WebhookSink::new("https://ci.example.com/hooks/builds").secret("shared-secret"),
+///     );
+///     let mut client = BuildKitClient::connect("http://localhost:1234")
+///         .await?
+///         .with_event_sink(webhook.clone());
+///
+///     client.build(BuildConfig::local("./app"), None).await?;
+///     webhook.flush().await;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct WebhookSink {
+    url: String,
+    secret: Option<Redacted<String>>,
+    max_retries: u32,
+    retry_backoff: Duration,
+    http: reqwest::Client,
+    sender: OnceLock<mpsc::UnboundedSender<Delivery>>,
+}
+
+impl WebhookSink {
+    /// Create a sink delivering to the given URL
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            secret: None,
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(500),
+            http: reqwest::Client::new(),
+            sender: OnceLock::new(),
+        }
+    }
+
+    /// Sign request bodies with a shared secret
+    pub fn secret(mut self, secret: impl Into<String>) -> Self {
+        self.secret = Some(Redacted::new(secret.into()));
+        self
+    }
+
+    /// Set how many times a failed delivery is retried (default 3)
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Set the delay before the first retry, doubled for each further retry
+    /// (default 500ms)
+    pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Wait until all events emitted so far have been delivered or dropped
+    pub async fn flush(&self) {
+        let Some(sender) = self.sender.get() else {
+            return;
+        };
+        let (tx, rx) = oneshot::channel();
+        if sender.send(Delivery::Flush(tx)).is_ok() {
+            let _ = rx.await;
+        }
+    }
+
+    /// Channel to the delivery task, started on first use
+    fn sender(&self) -> Option<&mpsc::UnboundedSender<Delivery>> {
+        if let Some(sender) = self.sender.get() {
+            return Some(sender);
+        }
+        let runtime = tokio::runtime::Handle::try_current().ok()?;
+        Some(self.sender.get_or_init(|| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            runtime.spawn(deliver_events(self.worker(), rx));
+            tx
+        }))
+    }
+
+    fn worker(&self) -> WebhookWorker {
+        WebhookWorker {
+            url: self.url.clone(),
+            secret: self.secret.clone(),
+            max_retries: self.max_retries,
+            retry_backoff: self.retry_backoff,
+            http: self.http.clone(),
+        }
+    }
+}
+
+impl BuildEventSink for WebhookSink {
+    fn on_event(&self, event: &BuildEvent) {
+        match self.sender() {
+            Some(sender) => {
+                let _ = sender.send(Delivery::Event(Box::new(event.clone())));
+            }
+            None => tracing::warn!(
+                "Dropping {} event for {}: no tokio runtime",
+                event.kind.name(),
+                event.build_ref
+            ),
+        }
+    }
+}
+
+/// State owned by the webhook delivery task
+struct WebhookWorker {
+    url: String,
+    secret: Option<Redacted<String>>,
+    max_retries: u32,
+    retry_backoff: Duration,
+    http: reqwest::Client,
+}
+
+impl WebhookWorker {
+    async fn deliver(&self, event: &BuildEvent) {
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("Failed to serialize build event: {}", e);
+                return;
+            }
+        };
+        let signature = self
+            .secret
+            .as_ref()
+            .map(|secret| sign_payload(secret.expose(), &body));
+
+        let mut backoff = self.retry_backoff;
+        for attempt in 0..=self.max_retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+
+            let mut request = self
+                .http
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(EVENT_HEADER, event.kind.name())
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+
+            match request.send().await {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response)
+                    if response.status().is_server_error()
+                        || response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    tracing::debug!(
+                        "Webhook returned {} for {} event, attempt {}",
+                        response.status(),
+                        event.kind.name(),
+                        attempt + 1
+                    );
+                }
+                Ok(response) => {
+                    tracing::warn!(
+                        "Webhook rejected {} event with {}",
+                        event.kind.name(),
+                        response.status()
+                    );
+                    return;
+                }
+                Err(e) => {
+                    tracing::debug!(
+                        "Webhook delivery of {} event failed, attempt {}: {}",
+                        event.kind.name(),
+                        attempt + 1,
+                        e
+                    );
+                }
+            }
+        }
+
+        tracing::warn!(
+            "Giving up on {} event for {} after {} attempts",
+            event.kind.name(),
+            event.build_ref,
+            self.max_retries + 1
+        );
+    }
+}
+
+async fn deliver_events(worker: WebhookWorker, mut rx: mpsc::UnboundedReceiver<Delivery>) {
+    while let Some(delivery) = rx.recv().await {
+        match delivery {
+            Delivery::Event(event) => worker.deliver(&event).await,
+            Delivery::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::moby::buildkit::v1::Vertex;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn timestamp(seconds: i64) -> Option<prost_types::Timestamp> {
+        Some(prost_types::Timestamp { seconds, nanos: 0 })
+    }
+
+    #[test]
+    fn step_events_are_emitted_once_per_vertex() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let received = received.clone();
+            move |event: &BuildEvent| received.lock().unwrap().push(event.kind.clone())
+        };
+        let mut events = BuildEvents::new(vec![Arc::new(sink)], "build-1", &BuildConfig::default());
+
+        let running = Vertex {
+            digest: "sha256:a".to_string(),
+            name: "[1/2] RUN make".to_string(),
+            started: timestamp(10),
+            ..Default::default()
+        };
+        let done = Vertex {
+            completed: timestamp(13),
+            ..running.clone()
+        };
+        let cached = Vertex {
+            digest: "sha256:b".to_string(),
+            name: "[2/2] COPY . .".to_string(),
+            cached: true,
+            completed: timestamp(13),
+            ..Default::default()
+        };
+
+        for vertexes in [vec![running], vec![done.clone(), cached], vec![done]] {
+            events.observe(&StatusResponse {
+                vertexes,
+                ..Default::default()
+            });
+        }
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        match &received[0] {
+            BuildEventKind::StepFinished {
+                step, duration_ms, ..
+            } => {
+                assert_eq!(step, "[1/2] RUN make");
+                assert_eq!(*duration_ms, Some(3000));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert_eq!(events.summary.steps, 2);
+        assert_eq!(events.summary.cached_steps, 1);
+    }
+
+    #[test]
+    fn events_serialize_with_event_name() {
+        let event = BuildEvent {
+            build_ref: "build-1".to_string(),
+            timestamp: 1,
+            kind: BuildEventKind::Queued {
+                tags: vec!["app:latest".to_string()],
+                config_hash: "sha256:abc".to_string(),
+            },
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event"], "queued");
+        assert_eq!(json["build_ref"], "build-1");
+        assert_eq!(json["tags"][0], "app:latest");
+    }
+
+    #[test]
+    fn signature_matches_known_vector() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign_payload("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    /// Accept connections and answer with the given statuses in order,
+    /// returning the raw requests received
+    async fn serve(statuses: Vec<u16>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 16 * 1024];
+                let mut request = String::new();
+                loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.push_str(&String::from_utf8_lossy(&buf[..n]));
+                    if n == 0 || (request.contains("\r\n\r\n{") && request.ends_with('}')) {
+                        break;
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn webhook_retries_and_signs_deliveries() {
+        let (url, server) = serve(vec![503, 200]).await;
+        let sink = WebhookSink::new(url)
+            .secret("shared")
+            .retry_backoff(Duration::from_millis(10));
+
+        sink.on_event(&BuildEvent {
+            build_ref: "build-1".to_string(),
+            timestamp: 1,
+            kind: BuildEventKind::Started {
+                session_id: "session".to_string(),
+            },
+        });
+        sink.flush().await;
+
+        let requests = server.await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let request = requests[1].to_ascii_lowercase();
+        let body = &requests[1][requests[1].find("\r\n\r\n").unwrap() + 4..];
+        assert!(request.contains("x-buildkit-event: started"));
+        assert!(request.contains(&format!(
+            "x-buildkit-signature: {}",
+            sign_payload("shared", body.as_bytes())
+        )));
+    }
+}
@@ -0,0 +1,630 @@
+//! C API for embedding the client in non-Rust tools
+//!
+//! Enabled with the `ffi` feature. Build the shared library with
+//!
+//! ```text
+//! cargo rustc --release --lib --no-default-features --features ffi --crate-type cdylib
+//! ```
+//!
+//! and include `include/buildkit_client.h`. All functions are blocking;
+//! each client owns a Tokio runtime that drives its builds.
+//!
+//! Conventions:
+//! - Functions returning a pointer return `NULL` on failure, functions
+//!   returning `int` return `0` on success and `-1` on failure. The reason
+//!   is available from [`bk_last_error`] on the same thread.
+//! - Objects created by `bk_*_new`/`bk_client_connect`/`bk_client_build`
+//!   are released with the matching `*_free` function; strings returned as
+//!   `char *` are released with [`bk_string_free`].
+//! - String arguments are NUL-terminated UTF-8.
+//! - Client handles are thread-safe: several threads may build and check
+//!   health through one client at once. Configuration and result handles
+//!   must be used from one thread at a time.
+
+use crate::builder::{BuildConfig, Platform, RegistryAuth};
+use crate::client::BuildKitClient;
+use crate::error::{Error, Result};
+use crate::progress::{status_json, ProgressHandler};
+use crate::proto::moby::buildkit::v1::StatusResponse;
+use crate::solve::{BuildResult, MetadataFormat};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+/// Client handle: a connected client and the runtime driving it
+pub struct BkClient {
+    runtime: tokio::runtime::Runtime,
+    client: BuildKitClient,
+}
+
+/// Build configuration handle
+pub struct BkBuildConfig {
+    config: BuildConfig,
+    /// Whether the default platform has been replaced
+    platforms_set: bool,
+}
+
+/// Build result handle
+pub struct BkBuildResult {
+    result: BuildResult,
+    digest: Option<CString>,
+}
+
+/// Progress callback: receives one JSON document per progress update, in the
+/// format of the CLI's `--progress json` output. The string is only valid
+/// during the call.
+pub type BkProgressCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, json: *const c_char)>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl Into<String>) {
+    let message = message.into().replace('\0', " ");
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Run an FFI body, converting errors and panics into `on_error` and the
+/// thread's last error
+fn guard<T>(on_error: T, body: impl FnOnce() -> Result<T>) -> T {
+    match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            on_error
+        }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            set_last_error(format!("panic in buildkit-client: {}", message));
+            on_error
+        }
+    }
+}
+
+/// Read a required string argument
+///
+/// # Safety
+///
+/// `ptr` must be NULL or point to a NUL-terminated string.
+unsafe fn arg_str<'a>(ptr: *const c_char, name: &str) -> Result<&'a str> {
+    if ptr.is_null() {
+        return Err(Error::InvalidConfig(format!("{} must not be NULL", name)));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| Error::InvalidConfig(format!("{} is not valid UTF-8", name)))
+}
+
+/// Borrow a handle mutably
+///
+/// # Safety
+///
+/// `ptr` must be NULL or a live handle of type `T` not used concurrently.
+unsafe fn handle<'a, T>(ptr: *mut T, name: &str) -> Result<&'a mut T> {
+    ptr.as_mut()
+        .ok_or_else(|| Error::InvalidConfig(format!("{} must not be NULL", name)))
+}
+
+/// Borrow a handle that may be shared between threads
+///
+/// # Safety
+///
+/// `ptr` must be NULL or a live handle of type `T`, not freed while
+/// borrowed.
+unsafe fn shared<'a, T: Sync>(ptr: *const T, name: &str) -> Result<&'a T> {
+    ptr.as_ref()
+        .ok_or_else(|| Error::InvalidConfig(format!("{} must not be NULL", name)))
+}
+
+fn into_c_string(value: String) -> *mut c_char {
+    CString::new(value.replace('\0', " "))
+        .map(CString::into_raw)
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Message of the last error on the calling thread, or NULL if none
+///
+/// The string stays valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn bk_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Free a string returned by this library
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by this library, not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn bk_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Connect to a BuildKit daemon, e.g. `"http://localhost:1234"`
+///
+/// # Safety
+///
+/// `addr` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bk_client_connect(addr: *const c_char) -> *mut BkClient {
+    guard(ptr::null_mut(), || {
+        let addr = arg_str(addr, "addr")?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(Error::Io)?;
+        let client = runtime.block_on(BuildKitClient::connect(addr))?;
+        Ok(Box::into_raw(Box::new(BkClient { runtime, client })))
+    })
+}
+
+/// Check that the daemon is reachable
+///
+/// May be called from several threads at once.
+///
+/// # Safety
+///
+/// `client` must be NULL or a live client handle.
+#[no_mangle]
+pub unsafe extern "C" fn bk_client_health_check(client: *mut BkClient) -> c_int {
+    guard(-1, || {
+        let client = shared(client, "client")?;
+        // Clones share the connection
+        client
+            .runtime
+            .block_on(client.client.clone().health_check())?;
+        Ok(0)
+    })
+}
+
+/// Free a client
+///
+/// # Safety
+///
+/// `client` must be NULL or a live client handle, not used afterwards nor
+/// by calls still running on other threads.
+#[no_mangle]
+pub unsafe extern "C" fn bk_client_free(client: *mut BkClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Create a configuration building a local context directory
+///
+/// # Safety
+///
+/// `context_path` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_new_local(
+    context_path: *const c_char,
+) -> *mut BkBuildConfig {
+    guard(ptr::null_mut(), || {
+        let config = BuildConfig::local(arg_str(context_path, "context_path")?);
+        Ok(Box::into_raw(Box::new(BkBuildConfig {
+            config,
+            platforms_set: false,
+        })))
+    })
+}
+
+/// Create a configuration building a GitHub repository
+///
+/// # Safety
+///
+/// `repo_url` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_new_github(repo_url: *const c_char) -> *mut BkBuildConfig {
+    guard(ptr::null_mut(), || {
+        let config = BuildConfig::github(arg_str(repo_url, "repo_url")?);
+        Ok(Box::into_raw(Box::new(BkBuildConfig {
+            config,
+            platforms_set: false,
+        })))
+    })
+}
+
+/// Apply a builder method to a configuration handle
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle.
+unsafe fn update_config(
+    config: *mut BkBuildConfig,
+    update: impl FnOnce(&mut BkBuildConfig, BuildConfig) -> Result<BuildConfig>,
+) -> c_int {
+    guard(-1, || {
+        let handle = handle(config, "config")?;
+        let config = handle.config.clone();
+        handle.config = update(handle, config)?;
+        Ok(0)
+    })
+}
+
+/// Set the Dockerfile path
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle; `path` must be
+/// NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_set_dockerfile(
+    config: *mut BkBuildConfig,
+    path: *const c_char,
+) -> c_int {
+    update_config(config, |_, c| Ok(c.dockerfile(arg_str(path, "path")?)))
+}
+
+/// Add an image tag
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle; `tag` must be NULL
+/// or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_add_tag(
+    config: *mut BkBuildConfig,
+    tag: *const c_char,
+) -> c_int {
+    update_config(config, |_, c| Ok(c.tag(arg_str(tag, "tag")?)))
+}
+
+/// Add a build argument
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle; `key` and `value`
+/// must be NULL or NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_add_build_arg(
+    config: *mut BkBuildConfig,
+    key: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    update_config(config, |_, c| {
+        Ok(c.build_arg(arg_str(key, "key")?, arg_str(value, "value")?))
+    })
+}
+
+/// Set the target stage
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle; `target` must be
+/// NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_set_target(
+    config: *mut BkBuildConfig,
+    target: *const c_char,
+) -> c_int {
+    update_config(config, |_, c| Ok(c.target(arg_str(target, "target")?)))
+}
+
+/// Add a target platform such as `"linux/arm64"`
+///
+/// The first call replaces the default `linux/amd64`.
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle; `platform` must be
+/// NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_add_platform(
+    config: *mut BkBuildConfig,
+    platform: *const c_char,
+) -> c_int {
+    update_config(config, |handle, mut c| {
+        let platform = Platform::parse(arg_str(platform, "platform")?)?;
+        if !handle.platforms_set {
+            c.platforms.clear();
+            handle.platforms_set = true;
+        }
+        Ok(c.platform(platform))
+    })
+}
+
+/// Set the GitHub token for private repositories
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle; `token` must be
+/// NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_set_github_token(
+    config: *mut BkBuildConfig,
+    token: *const c_char,
+) -> c_int {
+    update_config(config, |_, c| Ok(c.github_token(arg_str(token, "token")?)))
+}
+
+/// Add username/password credentials for a registry host
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle; `host`, `username`
+/// and `password` must be NULL or NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_add_registry_auth(
+    config: *mut BkBuildConfig,
+    host: *const c_char,
+    username: *const c_char,
+    password: *const c_char,
+) -> c_int {
+    update_config(config, |_, c| {
+        Ok(c.add_registry_auth(RegistryAuth {
+            host: arg_str(host, "host")?.to_string(),
+            username: arg_str(username, "username")?.to_string(),
+            password: arg_str(password, "password")?.to_string(),
+            ..Default::default()
+        }))
+    })
+}
+
+/// Add a build secret
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle; `id` and `value`
+/// must be NULL or NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_add_secret(
+    config: *mut BkBuildConfig,
+    id: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    update_config(config, |_, c| {
+        Ok(c.secret(arg_str(id, "id")?, arg_str(value, "value")?))
+    })
+}
+
+/// Enable or disable the build cache (`no_cache` non-zero disables it)
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_set_no_cache(
+    config: *mut BkBuildConfig,
+    no_cache: c_int,
+) -> c_int {
+    update_config(config, |_, c| Ok(c.no_cache(no_cache != 0)))
+}
+
+/// Always pull base images when `pull` is non-zero
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_set_pull(
+    config: *mut BkBuildConfig,
+    pull: c_int,
+) -> c_int {
+    update_config(config, |_, c| Ok(c.pull(pull != 0)))
+}
+
+/// Free a configuration
+///
+/// # Safety
+///
+/// `config` must be NULL or a live configuration handle, not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_config_free(config: *mut BkBuildConfig) {
+    if !config.is_null() {
+        drop(Box::from_raw(config));
+    }
+}
+
+/// Progress handler forwarding JSON updates to a C callback
+struct CallbackProgressHandler {
+    callback: unsafe extern "C" fn(*mut c_void, *const c_char),
+    user_data: *mut c_void,
+}
+
+// The caller of `bk_client_build` guarantees the callback and user data may
+// be used from the runtime's threads for the duration of the build
+unsafe impl Send for CallbackProgressHandler {}
+
+impl CallbackProgressHandler {
+    fn emit(&self, json: serde_json::Value) {
+        if let Ok(json) = CString::new(json.to_string()) {
+            // SAFETY: guaranteed by the caller of `bk_client_build`
+            unsafe { (self.callback)(self.user_data, json.as_ptr()) }
+        }
+    }
+}
+
+impl ProgressHandler for CallbackProgressHandler {
+    fn on_start(&mut self) -> Result<()> {
+        self.emit(serde_json::json!({ "status": "started" }));
+        Ok(())
+    }
+
+    fn on_status(&mut self, status: StatusResponse) -> Result<()> {
+        self.emit(status_json(&status));
+        Ok(())
+    }
+
+    fn on_complete(&mut self) -> Result<()> {
+        self.emit(serde_json::json!({ "status": "completed" }));
+        Ok(())
+    }
+
+    fn on_error(&mut self, error: &str) -> Result<()> {
+        self.emit(serde_json::json!({ "status": "failed", "error": error }));
+        Ok(())
+    }
+}
+
+/// Run a build, blocking until it finishes
+///
+/// `progress` may be NULL; otherwise it is called with `user_data` for each
+/// progress update, possibly from another thread. Returns NULL on failure.
+///
+/// Several threads may build through one client at once; the builds share
+/// its connection and runtime.
+///
+/// # Safety
+///
+/// `client` and `config` must be NULL or live handles. `progress`, if set,
+/// must be safe to call from any thread with `user_data` until this
+/// function returns.
+#[no_mangle]
+pub unsafe extern "C" fn bk_client_build(
+    client: *mut BkClient,
+    config: *const BkBuildConfig,
+    progress: BkProgressCallback,
+    user_data: *mut c_void,
+) -> *mut BkBuildResult {
+    guard(ptr::null_mut(), || {
+        let client = shared(client, "client")?;
+        let config = config
+            .as_ref()
+            .ok_or_else(|| Error::InvalidConfig("config must not be NULL".to_string()))?
+            .config
+            .clone();
+
+        let handler = progress.map(|callback| {
+            Box::new(CallbackProgressHandler {
+                callback,
+                user_data,
+            }) as Box<dyn ProgressHandler>
+        });
+        let result = client
+            .runtime
+            .block_on(client.client.build(config, handler))?;
+
+        let digest = result
+            .digest
+            .as_ref()
+            .and_then(|d| CString::new(d.as_str()).ok());
+        Ok(Box::into_raw(Box::new(BkBuildResult { result, digest })))
+    })
+}
+
+/// Image digest of a build, or NULL if it produced none
+///
+/// The string is owned by the result and valid until it is freed.
+///
+/// # Safety
+///
+/// `result` must be NULL or a live result handle.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_result_digest(result: *const BkBuildResult) -> *const c_char {
+    result
+        .as_ref()
+        .and_then(|r| r.digest.as_ref())
+        .map_or(ptr::null(), |d| d.as_ptr())
+}
+
+/// Build metadata as JSON in the format of buildx's `--metadata-file`
+///
+/// Free the returned string with [`bk_string_free`].
+///
+/// # Safety
+///
+/// `result` must be NULL or a live result handle.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_result_metadata_json(
+    result: *const BkBuildResult,
+) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let result = result
+            .as_ref()
+            .ok_or_else(|| Error::InvalidConfig("result must not be NULL".to_string()))?;
+        Ok(into_c_string(
+            result
+                .result
+                .metadata_json(MetadataFormat::Buildx)
+                .to_string(),
+        ))
+    })
+}
+
+/// Free a build result
+///
+/// # Safety
+///
+/// `result` must be NULL or a live result handle, not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn bk_build_result_free(result: *mut BkBuildResult) {
+    if !result.is_null() {
+        drop(Box::from_raw(result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        let ptr = bk_last_error();
+        assert!(!ptr.is_null());
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn config_builder_functions_update_the_config() {
+        unsafe {
+            let config = bk_build_config_new_local(c"./app".as_ptr());
+            assert!(!config.is_null());
+            assert_eq!(bk_build_config_add_tag(config, c"app:latest".as_ptr()), 0);
+            assert_eq!(
+                bk_build_config_add_build_arg(config, c"VERSION".as_ptr(), c"1.0".as_ptr()),
+                0
+            );
+            assert_eq!(
+                bk_build_config_add_platform(config, c"linux/arm64".as_ptr()),
+                0
+            );
+            assert_eq!(bk_build_config_set_no_cache(config, 1), 0);
+
+            let built = &(*config).config;
+            assert_eq!(built.tags, vec!["app:latest"]);
+            assert_eq!(built.build_args["VERSION"], "1.0");
+            assert_eq!(built.platforms.len(), 1);
+            assert_eq!(built.platforms[0].to_string(), "linux/arm64");
+            assert!(built.no_cache);
+
+            bk_build_config_free(config);
+        }
+    }
+
+    #[test]
+    fn invalid_arguments_set_last_error() {
+        unsafe {
+            assert_eq!(bk_build_config_add_tag(ptr::null_mut(), c"x".as_ptr()), -1);
+            assert!(last_error().contains("config must not be NULL"));
+
+            let config = bk_build_config_new_local(c".".as_ptr());
+            assert_eq!(bk_build_config_add_platform(config, c"".as_ptr()), -1);
+            assert!(last_error().contains("platform"));
+            bk_build_config_free(config);
+
+            assert!(bk_client_connect(c"not a url".as_ptr()).is_null());
+            assert!(last_error().contains("not a url"));
+        }
+    }
+
+    #[test]
+    fn null_handles_are_ignored_by_free_and_getters() {
+        unsafe {
+            bk_client_free(ptr::null_mut());
+            bk_build_config_free(ptr::null_mut());
+            bk_build_result_free(ptr::null_mut());
+            bk_string_free(ptr::null_mut());
+            assert!(bk_build_result_digest(ptr::null()).is_null());
+        }
+    }
+}
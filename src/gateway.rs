@@ -0,0 +1,654 @@
+//! Client-side builds through BuildKit's gateway API
+//!
+//! A gateway build solves the Dockerfile without exporting anything and
+//! hands the result to a callback while the build is still open, so files
+//! can be read from the built filesystem before it is released.
+//!
+//! Frontends written in Rust get a [`GatewayClient`] instead, which solves
+//! LLB definitions or other frontends and returns one of the results to be
+//! exported like a Dockerfile build.
+
+use crate::builder::BuildConfig;
+use crate::client::BuildKitClient;
+use crate::error::{Error, Result};
+use crate::events::BuildEvents;
+use crate::progress::{ProgressHandler, StatusTracker};
+use crate::proto::google::rpc::Status as RpcStatus;
+use crate::proto::moby::buildkit::v1::frontend::{
+    llb_bridge_client::LlbBridgeClient, result, CacheOptionsEntry, FileRange, ReadDirRequest,
+    ReadFileRequest, Ref as ResultRef, Result as FrontendResult, ReturnRequest,
+    SolveRequest as FrontendSolveRequest, StatFileRequest,
+};
+use crate::proto::pb::Definition;
+use crate::raw::{with_session_metadata, SolveRequestBuilder};
+use crate::solve::{
+    build_log, cache_import_attrs, cache_options, entitlements, exporters, source_policy,
+    tag_local_cache_exports, BuildResult, ProgressContext,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use tonic::metadata::MetadataValue;
+use tonic::transport::Channel;
+use uuid::Uuid;
+
+pub use crate::proto::fsutil::types::Stat;
+
+/// gRPC metadata key routing gateway calls to their build
+const BUILD_ID_HEADER: &str = "buildid";
+
+/// Result metadata key of the image config produced by the frontend
+const IMAGE_CONFIG_KEY: &str = "containerimage.config";
+
+/// Gateway API connection bound to one build
+#[derive(Clone)]
+pub(crate) struct GatewayBridge {
+    client: LlbBridgeClient<Channel>,
+    build_ref: String,
+}
+
+impl GatewayBridge {
+    pub(crate) fn new(channel: Channel, build_ref: &str) -> Self {
+        Self {
+            client: LlbBridgeClient::new(channel),
+            build_ref: build_ref.to_string(),
+        }
+    }
+
+    /// Wrap a message in a request addressed to this build
+    pub(crate) fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Ok(value) = MetadataValue::try_from(self.build_ref.as_str()) {
+            request.metadata_mut().insert(BUILD_ID_HEADER, value);
+        }
+        request
+    }
+
+    pub(crate) fn client(&self) -> LlbBridgeClient<Channel> {
+        self.client.clone()
+    }
+
+    /// Solve a frontend request, returning its result
+    pub(crate) async fn solve_result(
+        &self,
+        request: FrontendSolveRequest,
+    ) -> Result<FrontendResult> {
+        let response = self
+            .client()
+            .solve(self.request(request))
+            .await?
+            .into_inner();
+
+        match response.result {
+            Some(result) => Ok(result),
+            None if !response.r#ref.is_empty() => Ok(FrontendResult {
+                result: Some(result::Result::RefDeprecated(response.r#ref)),
+                ..Default::default()
+            }),
+            None => Err(Error::protocol("gateway solve returned no result")),
+        }
+    }
+
+    /// Solve the Dockerfile, returning the ID of the result reference and
+    /// the image config the frontend produced for it
+    async fn solve(&self, request: FrontendSolveRequest) -> Result<(String, Option<Vec<u8>>)> {
+        let solved = self.solve_result(request).await?;
+        let ref_id = match solved.result {
+            Some(result::Result::Ref(r)) => r.id,
+            Some(result::Result::RefDeprecated(id)) => id,
+            Some(result::Result::Refs(map)) if map.refs.len() == 1 => map
+                .refs
+                .into_values()
+                .next()
+                .map(|r| r.id)
+                .unwrap_or_default(),
+            Some(result::Result::Refs(_)) | Some(result::Result::RefsDeprecated(_)) => {
+                return Err(Error::InvalidConfig(
+                    "gateway builds support a single platform".to_string(),
+                ))
+            }
+            None => return Err(Error::protocol("gateway solve returned no result")),
+        };
+
+        // Keyed by platform when the frontend returned a platform map
+        let image_config = solved
+            .metadata
+            .into_iter()
+            .find(|(key, _)| {
+                key == IMAGE_CONFIG_KEY
+                    || key
+                        .strip_prefix(IMAGE_CONFIG_KEY)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .map(|(_, config)| config);
+        Ok((ref_id, image_config))
+    }
+
+    /// Finish the build with a result to export, or with the error that
+    /// failed the client-side work
+    pub(crate) async fn finish(
+        &self,
+        outcome: std::result::Result<FrontendResult, &Error>,
+    ) -> Result<()> {
+        let request = match outcome {
+            Ok(result) => ReturnRequest {
+                result: Some(result),
+                error: None,
+            },
+            Err(e) => ReturnRequest {
+                result: None,
+                error: Some(RpcStatus {
+                    code: e.grpc_status().map_or(tonic::Code::Unknown, |s| s.code()) as i32,
+                    message: e.to_string(),
+                    details: vec![],
+                }),
+            },
+        };
+        self.client().r#return(self.request(request)).await?;
+        Ok(())
+    }
+}
+
+/// Result of a gateway build, readable until the callback returns
+///
+/// Paths are absolute within the built filesystem.
+#[derive(Clone)]
+pub struct GatewayResult {
+    bridge: GatewayBridge,
+    ref_id: String,
+    image_config: Option<Vec<u8>>,
+}
+
+impl GatewayResult {
+    /// ID of the result reference on the builder
+    pub fn ref_id(&self) -> &str {
+        &self.ref_id
+    }
+
+    /// Image config JSON the Dockerfile frontend produced for the result
+    pub fn image_config(&self) -> Option<&[u8]> {
+        self.image_config.as_deref()
+    }
+
+    pub(crate) fn bridge(&self) -> &GatewayBridge {
+        &self.bridge
+    }
+
+    /// The result as returned to BuildKit for export
+    fn frontend_result(&self) -> FrontendResult {
+        FrontendResult {
+            result: Some(result::Result::Ref(ResultRef {
+                id: self.ref_id.clone(),
+                def: None,
+            })),
+            metadata: self
+                .image_config
+                .iter()
+                .map(|config| (IMAGE_CONFIG_KEY.to_string(), config.clone()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Read a whole file
+    pub async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        self.read(path, None).await
+    }
+
+    /// Read `length` bytes of a file starting at `offset`
+    pub async fn read_file_range(&self, path: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+        self.read(
+            path,
+            Some(FileRange {
+                offset: offset as i64,
+                length: length as i64,
+            }),
+        )
+        .await
+    }
+
+    async fn read(&self, path: &str, range: Option<FileRange>) -> Result<Vec<u8>> {
+        let request = ReadFileRequest {
+            r#ref: self.ref_id.clone(),
+            file_path: path.to_string(),
+            range,
+        };
+        let response = self
+            .bridge
+            .client()
+            .read_file(self.bridge.request(request))
+            .await?;
+        Ok(response.into_inner().data)
+    }
+
+    /// Metadata of a file or directory
+    pub async fn stat_file(&self, path: &str) -> Result<Stat> {
+        let request = StatFileRequest {
+            r#ref: self.ref_id.clone(),
+            path: path.to_string(),
+        };
+        let response = self
+            .bridge
+            .client()
+            .stat_file(self.bridge.request(request))
+            .await?;
+        response
+            .into_inner()
+            .stat
+            .ok_or_else(|| Error::protocol(format!("no stat returned for {}", path)))
+    }
+
+    /// Entries of a directory, optionally filtered by a glob pattern
+    pub async fn read_dir(&self, path: &str, include_pattern: Option<&str>) -> Result<Vec<Stat>> {
+        let request = ReadDirRequest {
+            r#ref: self.ref_id.clone(),
+            dir_path: path.to_string(),
+            include_pattern: include_pattern.unwrap_or_default().to_string(),
+        };
+        let response = self
+            .bridge
+            .client()
+            .read_dir(self.bridge.request(request))
+            .await?;
+        Ok(response.into_inner().entries)
+    }
+}
+
+/// Gateway API of a running build, for frontends written in Rust
+///
+/// Each solve returns a [`GatewayResult`] whose files can be read while
+/// the build is open.
+#[derive(Clone)]
+pub struct GatewayClient {
+    bridge: GatewayBridge,
+    frontend_attrs: HashMap<String, String>,
+    cache_imports: Vec<CacheOptionsEntry>,
+}
+
+impl GatewayClient {
+    /// Frontend options derived from the build configuration
+    ///
+    /// These are the options the Dockerfile frontend would get: build
+    /// arguments as `build-arg:NAME`, `target`, `platform` and the build
+    /// `context`.
+    pub fn frontend_attrs(&self) -> &HashMap<String, String> {
+        &self.frontend_attrs
+    }
+
+    /// Solve an LLB definition
+    pub async fn solve(&self, definition: Definition) -> Result<GatewayResult> {
+        self.solve_request(FrontendSolveRequest {
+            definition: Some(definition),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Solve with another frontend, e.g. `dockerfile.v0`
+    pub async fn solve_frontend(
+        &self,
+        frontend: impl Into<String>,
+        attrs: HashMap<String, String>,
+    ) -> Result<GatewayResult> {
+        self.solve_request(FrontendSolveRequest {
+            frontend: frontend.into(),
+            frontend_opt: attrs,
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn solve_request(&self, request: FrontendSolveRequest) -> Result<GatewayResult> {
+        let request = FrontendSolveRequest {
+            allow_result_return: true,
+            cache_imports: self.cache_imports.clone(),
+            ..request
+        };
+        let (ref_id, image_config) = self.bridge.solve(request).await?;
+        Ok(GatewayResult {
+            bridge: self.bridge.clone(),
+            ref_id,
+            image_config,
+        })
+    }
+}
+
+impl BuildKitClient {
+    /// Build without exporting and inspect the result through the gateway API
+    ///
+    /// The Dockerfile is solved on the builder, then `inspect` is called with
+    /// the result while the build is held open. Nothing is pushed: tags and
+    /// cache exports in `config` are ignored. Only single-platform builds are
+    /// supported.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::{BuildConfig, BuildKitClient};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     let config = BuildConfig::local("./my-app").target("build");
+    ///
+    ///     let binary = client
+    ///         .gateway_build(config, None, |result| async move {
+    ///             result.read_file("/app/target/release/my-app").await
+    ///         })
+    ///         .await?;
+    ///     std::fs::write("my-app", binary)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn gateway_build<F, Fut, T>(
+        &mut self,
+        config: BuildConfig,
+        progress_handler: Option<Box<dyn ProgressHandler>>,
+        inspect: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(GatewayResult) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let build_ref = format!("build-{}", Uuid::new_v4());
+        tracing::info!("Starting gateway build with ref: {}", build_ref);
+
+        let mut events = BuildEvents::new(self.event_sinks().to_vec(), &build_ref, &config);
+        events.queued();
+
+        let result = self
+            .run_gateway_build(&build_ref, config, progress_handler, &mut events, inspect)
+            .await;
+        match &result {
+            Ok(_) => events.completed(None),
+            Err(e) => events.failed(e),
+        }
+        result
+    }
+
+    /// Build with a frontend running in this process and export its result
+    ///
+    /// `frontend` is given a [`GatewayClient`] on a build with the session
+    /// of `config`, and returns the result to export to the configured tags,
+    /// outputs and cache destinations. Only single-platform results are
+    /// supported.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::raw::DOCKERFILE_FRONTEND;
+    /// use buildkit_client::{BuildConfig, BuildKitClient};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     let config = BuildConfig::local("./my-app").tag("localhost:5000/my-app:latest");
+    ///
+    ///     client
+    ///         .frontend_build(config, None, |gateway| async move {
+    ///             let attrs = gateway.frontend_attrs().clone();
+    ///             let result = gateway.solve_frontend(DOCKERFILE_FRONTEND, attrs).await?;
+    ///             result.stat_file("/app").await?;
+    ///             Ok(result)
+    ///         })
+    ///         .await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn frontend_build<F, Fut>(
+        &mut self,
+        config: BuildConfig,
+        progress_handler: Option<Box<dyn ProgressHandler>>,
+        frontend: F,
+    ) -> Result<BuildResult>
+    where
+        F: FnOnce(GatewayClient) -> Fut,
+        Fut: Future<Output = Result<GatewayResult>>,
+    {
+        let build_ref = format!("build-{}", Uuid::new_v4());
+        tracing::info!("Starting frontend build with ref: {}", build_ref);
+
+        let mut events = BuildEvents::new(self.event_sinks().to_vec(), &build_ref, &config);
+        events.queued();
+
+        let client_side = |bridge: GatewayBridge, request: FrontendSolveRequest| async move {
+            let gateway = GatewayClient {
+                bridge: bridge.clone(),
+                frontend_attrs: request.frontend_opt,
+                cache_imports: request.cache_imports,
+            };
+            match frontend(gateway).await {
+                Ok(result) => bridge
+                    .finish(Ok(result.frontend_result()))
+                    .await
+                    .map_err(GatewayError::Solve),
+                Err(e) => {
+                    if let Err(e) = bridge.finish(Err(&e)).await {
+                        tracing::debug!("Failed to return gateway result: {}", e);
+                    }
+                    Err(GatewayError::Solve(e))
+                }
+            }
+        };
+
+        let result = self
+            .run_gateway(
+                &build_ref,
+                &config,
+                progress_handler,
+                &mut events,
+                true,
+                client_side,
+            )
+            .await
+            .map(|((), exporter_response, tracker)| BuildResult {
+                report: tracker.report(),
+                warnings: tracker.warnings(),
+                ..BuildResult::from_exporter_response(build_ref, exporter_response)
+            });
+        match &result {
+            Ok(result) => events.completed(result.digest.clone()),
+            Err(e) => events.failed(e),
+        }
+        result
+    }
+
+    async fn run_gateway_build<F, Fut, T>(
+        &mut self,
+        build_ref: &str,
+        config: BuildConfig,
+        progress_handler: Option<Box<dyn ProgressHandler>>,
+        events: &mut BuildEvents,
+        inspect: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(GatewayResult) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let client_side = |bridge: GatewayBridge, request: FrontendSolveRequest| async move {
+            let outcome = match bridge.solve(request).await {
+                Ok((ref_id, image_config)) => {
+                    let result = GatewayResult {
+                        bridge: bridge.clone(),
+                        ref_id,
+                        image_config,
+                    };
+                    inspect(result).await.map_err(GatewayError::Inspect)
+                }
+                Err(e) => Err(GatewayError::Solve(e)),
+            };
+            // Always return, so BuildKit releases the build
+            let returned = match &outcome {
+                Ok(_) => Ok(FrontendResult::default()),
+                Err(e) => Err(e.error()),
+            };
+            if let Err(e) = bridge.finish(returned).await {
+                tracing::debug!("Failed to return gateway result: {}", e);
+            }
+            outcome
+        };
+
+        let (value, _, _) = self
+            .run_gateway(
+                build_ref,
+                &config,
+                progress_handler,
+                events,
+                false,
+                client_side,
+            )
+            .await?;
+        Ok(value)
+    }
+
+    /// Run a build whose result is produced over the gateway API
+    ///
+    /// `client_side` is given the Dockerfile solve request and must return a
+    /// result or error to BuildKit through the bridge before it completes.
+    /// With `export` set, the returned result is pushed to the configured
+    /// tags and cache destinations, and the exporter response is returned
+    /// along with the tracked status of the build.
+    pub(crate) async fn run_gateway<F, Fut, T>(
+        &mut self,
+        build_ref: &str,
+        config: &BuildConfig,
+        mut progress_handler: Option<Box<dyn ProgressHandler>>,
+        events: &mut BuildEvents,
+        export: bool,
+        client_side: F,
+    ) -> Result<(T, HashMap<String, String>, StatusTracker)>
+    where
+        F: FnOnce(GatewayBridge, FrontendSolveRequest) -> Fut,
+        Fut: Future<Output = std::result::Result<T, GatewayError>>,
+    {
+        let _permit = self.build_permit().await?;
+        let session = self.start_session(config).await?;
+        tracing::info!("Session started: {}", session.get_id());
+        events.started(&session.get_id());
+
+        let frontend_request = FrontendSolveRequest {
+            frontend: config.frontend.name().to_string(),
+            frontend_opt: self.frontend_attrs(config, &session).await?,
+            allow_result_return: true,
+            allow_result_array_ref: true,
+            cache_imports: config
+                .cache_from
+                .iter()
+                .filter_map(|source| {
+                    Some(CacheOptionsEntry {
+                        r#type: source.cache_type().to_string(),
+                        attrs: cache_import_attrs(source)?.into_iter().collect(),
+                    })
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        // An empty frontend makes BuildKit wait for the result to be returned
+        // over the gateway API
+        let mut control_request = SolveRequestBuilder::new(build_ref)
+            .session(&session)
+            .frontend("")
+            .build();
+        control_request.entitlements = entitlements(config);
+        control_request.source_policy = source_policy(config)?;
+        if export {
+            control_request.exporters = exporters(config)?;
+            control_request.cache = Some(cache_options(config));
+        }
+
+        let bridge = GatewayBridge::new(self.channel(), build_ref);
+        let mut tracker = StatusTracker::new();
+        let scrubber = config.scrubber();
+        let mut log = build_log(config).await?;
+        if let Some(ref mut handler) = progress_handler {
+            handler.on_start()?;
+        }
+
+        let status_control = self.control().clone();
+        let mut solve_control = self.control().clone();
+        let outcome = self
+            .unless_cancelled(Self::within(config.timeout, async {
+                // As for single builds, BuildKit replays the frontend's
+                // early steps to a status call arriving after the solve
+                tokio::join!(
+                    Self::monitor_progress(
+                        status_control,
+                        self.options(),
+                        build_ref,
+                        ProgressContext {
+                            handler: progress_handler.as_mut(),
+                            log: log.as_mut(),
+                            tracker: &mut tracker,
+                            events,
+                            scrubber: &scrubber,
+                        },
+                    ),
+                    client_side(bridge, frontend_request),
+                    solve_control.solve(with_session_metadata(control_request, &session)),
+                )
+            }))
+            .await;
+        let (monitor_result, gateway_result, solve_result) = match outcome {
+            Some(Some(results)) => results,
+            stopped => {
+                session.close();
+                let error = match stopped {
+                    None => self.build_cancelled(&tracker).await,
+                    Some(_) => self.build_timed_out(config.timeout).await,
+                };
+                if let Some(ref mut handler) = progress_handler {
+                    handler.on_error(&error.to_string())?;
+                }
+                return Err(error);
+            }
+        };
+
+        let result = match (gateway_result, solve_result) {
+            // A step that failed on the status stream fails the build even
+            // when the solve itself returned successfully
+            (Ok(value), Ok(response)) => match tracker.failure(&session.get_id(), None) {
+                Some(failure) => Err(failure),
+                None => Ok((value, response.into_inner().exporter_response)),
+            },
+            (Err(GatewayError::Solve(e)), _) => Err(tracker
+                .failure(&session.get_id(), e.grpc_status())
+                .unwrap_or(e)),
+            (Err(GatewayError::Inspect(e)), _) => Err(e),
+            (Ok(_), Err(status)) => Err(Error::from(status)),
+        };
+        let value = match result {
+            Ok(value) => value,
+            Err(e) => {
+                let error = e.scrub(&scrubber);
+                if let Some(ref mut handler) = progress_handler {
+                    handler.on_error(&error.to_string())?;
+                }
+                return Err(error);
+            }
+        };
+        monitor_result.map_err(|e| e.scrub(&scrubber))?;
+        let (value, exporter_response) = value;
+        if export {
+            tag_local_cache_exports(config, &exporter_response)?;
+        }
+
+        if let Some(ref mut handler) = progress_handler {
+            handler.on_complete()?;
+        }
+        Ok((value, exporter_response, tracker))
+    }
+}
+
+/// Where a gateway build failed
+pub(crate) enum GatewayError {
+    /// Solving the Dockerfile
+    Solve(Error),
+    /// The caller's inspection of the result
+    Inspect(Error),
+}
+
+impl GatewayError {
+    pub(crate) fn error(&self) -> &Error {
+        match self {
+            GatewayError::Solve(e) | GatewayError::Inspect(e) => e,
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Build history kept by the daemon
+//!
+//! BuildKit records every build it runs: its frontend and attributes,
+//! exporters, step counts, error, and blobs such as the OpenTelemetry
+//! trace and the provenance attestations. Records are kept until the
+//! daemon's history GC removes them, unless they are pinned.
+
+use crate::client::BuildKitClient;
+use crate::error::{Error, Result};
+use crate::proto::containerd::services::content::v1::content_client::ContentClient;
+use crate::proto::containerd::services::content::v1::ReadContentRequest;
+use crate::proto::moby::buildkit::v1::{
+    BuildHistoryEventType, BuildHistoryRecord, BuildHistoryRequest, Descriptor,
+    UpdateBuildHistoryRequest,
+};
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime};
+use tokio_stream::StreamExt;
+
+/// Annotation naming the predicate of an in-toto attestation
+const PREDICATE_TYPE_ANNOTATION: &str = "in-toto.io/predicate-type";
+
+/// Prefix of the predicate types of SLSA provenance
+const SLSA_PROVENANCE_PREFIX: &str = "https://slsa.dev/provenance/";
+
+impl BuildKitClient {
+    /// List the builds in the daemon's history matching any of `filters`,
+    /// newest first
+    ///
+    /// Filters use containerd syntax over the record fields, e.g.
+    /// `ref==<build ref>`. Running builds are included.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::BuildKitClient;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     for record in client.build_history::<&str>(&[]).await? {
+    ///         println!("{} {}", record.r#ref, record.num_total_steps);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn build_history<S: AsRef<str>>(
+        &mut self,
+        filters: &[S],
+    ) -> Result<Vec<BuildHistoryRecord>> {
+        let request = BuildHistoryRequest {
+            early_exit: true,
+            filter: filters.iter().map(|f| f.as_ref().to_string()).collect(),
+            ..Default::default()
+        };
+        let mut records = self.listen_history(request).await?;
+        records.sort_by_key(|r| std::cmp::Reverse(created_at(r)));
+        Ok(records)
+    }
+
+    /// Get the history record of one build
+    pub async fn build_history_record(&mut self, build_ref: &str) -> Result<BuildHistoryRecord> {
+        let request = BuildHistoryRequest {
+            r#ref: build_ref.to_string(),
+            early_exit: true,
+            ..Default::default()
+        };
+        self.listen_history(request)
+            .await?
+            .into_iter()
+            .find(|r| r.r#ref == build_ref)
+            .ok_or_else(|| {
+                Error::NotFound(Box::new(tonic::Status::not_found(format!(
+                    "build {} is not in the history",
+                    build_ref
+                ))))
+            })
+    }
+
+    /// Pin a build's record so history GC keeps it, or unpin it
+    pub async fn pin_build(&mut self, build_ref: &str, pinned: bool) -> Result<()> {
+        self.control()
+            .update_build_history(UpdateBuildHistoryRequest {
+                r#ref: build_ref.to_string(),
+                pinned,
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a build's record from the history
+    pub async fn delete_build_history(&mut self, build_ref: &str) -> Result<()> {
+        self.control()
+            .update_build_history(UpdateBuildHistoryRequest {
+                r#ref: build_ref.to_string(),
+                delete: true,
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// OpenTelemetry trace of a build as OTLP JSON, if BuildKit kept one
+    pub async fn build_trace(&mut self, record: &BuildHistoryRecord) -> Result<Option<Vec<u8>>> {
+        match &record.trace {
+            Some(trace) => Ok(Some(self.read_content(trace).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// SLSA provenance attestations of a build's results, as in-toto
+    /// statements
+    ///
+    /// Builds only have provenance when the `attest:provenance` frontend
+    /// attribute requested it.
+    pub async fn build_provenance(
+        &mut self,
+        record: &BuildHistoryRecord,
+    ) -> Result<Vec<serde_json::Value>> {
+        let mut statements = Vec::new();
+        for descriptor in provenance_descriptors(record) {
+            let blob = self.read_content(descriptor).await?;
+            statements.push(serde_json::from_slice(&blob).map_err(|e| {
+                Error::Protocol(format!("invalid provenance {}: {}", descriptor.digest, e))
+            })?);
+        }
+        Ok(statements)
+    }
+
+    /// Read a blob of the daemon's content store, checking its digest
+    pub async fn read_content(&mut self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let mut content = ContentClient::new(self.channel());
+        let mut stream = content
+            .read(ReadContentRequest {
+                digest: descriptor.digest.clone(),
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        let mut blob = Vec::with_capacity(descriptor.size.max(0) as usize);
+        while let Some(chunk) = stream.next().await {
+            blob.extend_from_slice(&chunk?.data);
+        }
+        let digest = format!("sha256:{:x}", Sha256::digest(&blob));
+        if digest != descriptor.digest {
+            return Err(Error::Protocol(format!(
+                "content {} has digest {}",
+                descriptor.digest, digest
+            )));
+        }
+        Ok(blob)
+    }
+
+    /// Records of a history request, without those deleted meanwhile
+    async fn listen_history(
+        &mut self,
+        request: BuildHistoryRequest,
+    ) -> Result<Vec<BuildHistoryRecord>> {
+        let mut stream = self
+            .control()
+            .listen_build_history(request)
+            .await?
+            .into_inner();
+
+        let mut records: Vec<BuildHistoryRecord> = Vec::new();
+        while let Some(event) = stream.next().await {
+            let event = event?;
+            let kind = event.r#type();
+            let Some(record) = event.record else {
+                continue;
+            };
+            records.retain(|r| r.r#ref != record.r#ref);
+            if kind != BuildHistoryEventType::Deleted {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Descriptors of the SLSA provenance attestations of a build's results
+pub fn provenance_descriptors(record: &BuildHistoryRecord) -> Vec<&Descriptor> {
+    record
+        .result
+        .iter()
+        .chain(record.results.values())
+        .flat_map(|result| &result.attestations)
+        .filter(|d| {
+            d.annotations
+                .get(PREDICATE_TYPE_ANNOTATION)
+                .is_some_and(|t| t.starts_with(SLSA_PROVENANCE_PREFIX))
+        })
+        .collect()
+}
+
+/// When a build started
+pub fn created_at(record: &BuildHistoryRecord) -> Option<SystemTime> {
+    record.created_at.and_then(|t| SystemTime::try_from(t).ok())
+}
+
+/// How long a build ran, `None` while it is running
+pub fn duration(record: &BuildHistoryRecord) -> Option<Duration> {
+    let completed = record
+        .completed_at
+        .and_then(|t| SystemTime::try_from(t).ok())?;
+    completed.duration_since(created_at(record)?).ok()
+}
+
+/// Status of a build: `running`, `failed` or `completed`
+pub fn status(record: &BuildHistoryRecord) -> &'static str {
+    if record.completed_at.is_none() {
+        "running"
+    } else if record.error.as_ref().is_some_and(|e| e.code != 0) {
+        "failed"
+    } else {
+        "completed"
+    }
+}
+
+/// JSON document of a history record
+pub fn record_json(record: &BuildHistoryRecord) -> serde_json::Value {
+    let time = |t: &Option<prost_types::Timestamp>| t.as_ref().map(|t| t.to_string());
+    let exporters: Vec<&str> = record.exporters.iter().map(|e| e.r#type.as_str()).collect();
+    serde_json::json!({
+        "ref": record.r#ref,
+        "status": status(record),
+        "frontend": record.frontend,
+        "frontend_attrs": record.frontend_attrs,
+        "exporters": exporters,
+        "exporter_response": record.exporter_response,
+        "error": record.error.as_ref().map(|e| &e.message),
+        "created_at": time(&record.created_at),
+        "completed_at": time(&record.completed_at),
+        "duration_secs": duration(record).map(|d| d.as_secs_f64()),
+        "steps": {
+            "total": record.num_total_steps,
+            "completed": record.num_completed_steps,
+            "cached": record.num_cached_steps,
+        },
+        "warnings": record.num_warnings,
+        "pinned": record.pinned,
+        "trace": record.trace.as_ref().map(|d| &d.digest),
+        "provenance": provenance_descriptors(record)
+            .iter()
+            .map(|d| &d.digest)
+            .collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::google::rpc::Status;
+    use crate::proto::moby::buildkit::v1::BuildResultInfo;
+
+    fn timestamp(seconds: i64) -> Option<prost_types::Timestamp> {
+        Some(prost_types::Timestamp { seconds, nanos: 0 })
+    }
+
+    #[test]
+    fn records_report_status_and_duration() {
+        let mut record = BuildHistoryRecord {
+            r#ref: "abc".to_string(),
+            created_at: timestamp(1_000),
+            ..Default::default()
+        };
+        assert_eq!(status(&record), "running");
+        assert_eq!(duration(&record), None);
+
+        record.completed_at = timestamp(1_042);
+        assert_eq!(status(&record), "completed");
+        assert_eq!(duration(&record), Some(Duration::from_secs(42)));
+
+        record.error = Some(Status {
+            code: 2,
+            message: "exit code 1".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(status(&record), "failed");
+        assert_eq!(record_json(&record)["error"], "exit code 1");
+    }
+
+    #[test]
+    fn provenance_is_found_among_attestations() {
+        let attestation = |digest: &str, predicate: &str| Descriptor {
+            digest: digest.to_string(),
+            annotations: [(PREDICATE_TYPE_ANNOTATION.to_string(), predicate.to_string())].into(),
+            ..Default::default()
+        };
+        let record = BuildHistoryRecord {
+            results: [(
+                "linux/amd64".to_string(),
+                BuildResultInfo {
+                    attestations: vec![
+                        attestation("sha256:sbom", "https://spdx.dev/Document"),
+                        attestation("sha256:prov", "https://slsa.dev/provenance/v0.2"),
+                    ],
+                    ..Default::default()
+                },
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let digests: Vec<&str> = provenance_descriptors(&record)
+            .iter()
+            .map(|d| d.digest.as_str())
+            .collect();
+        assert_eq!(digests, vec!["sha256:prov"]);
+    }
+}
@@ -0,0 +1,390 @@
+//! Client-side record of past builds
+//!
+//! [`BuildLedger`] appends one JSON line per finished build to
+//! `builds.jsonl` in a state directory and answers queries over it, so teams
+//! without access to the daemon's build history can still find out when a
+//! configuration was last built and what it produced. Register it on a
+//! client as an event sink:
+//!
+//! ```no_run
+//! use buildkit_client::ledger::BuildLedger;
+//! use buildkit_client::BuildKitClient;
+//! use std::sync::Arc;
+//!
+//! #[tokio::main]
+//! async fn main() -> anyhow::Result<()> {
+//!     let ledger = Arc::new(BuildLedger::open(BuildLedger::default_dir())?);
+//!     let client = BuildKitClient::connect("http://localhost:1234")
+//!         .await?
+//!         .with_event_sink(ledger.clone());
+//!     Ok(())
+//! }
+//! ```
+
+use crate::error::{Error, Result};
+use crate::events::{BuildEvent, BuildEventKind, BuildEventSink, BuildSummary};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// File name of the ledger inside its state directory
+const LEDGER_FILE: &str = "builds.jsonl";
+
+/// One finished build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildRecord {
+    /// Build reference
+    pub build_ref: String,
+    /// Configuration hash, see [`BuildConfig::config_hash`](crate::BuildConfig::config_hash)
+    pub config_hash: String,
+    /// Image tags the build pushed
+    pub tags: Vec<String>,
+    /// Image digest, if the build produced one
+    pub digest: Option<String>,
+    /// Time the build finished, in milliseconds since the Unix epoch
+    pub finished_at: u64,
+    /// Wall-clock build duration in milliseconds
+    pub duration_ms: u64,
+    /// Number of finished steps
+    pub steps: usize,
+    /// Number of steps served from cache
+    pub cached_steps: usize,
+    /// Whether the build succeeded
+    pub success: bool,
+    /// Error message of a failed build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BuildRecord {
+    fn from_summary(
+        build_ref: &str,
+        config_hash: String,
+        finished_at: u64,
+        summary: &BuildSummary,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            build_ref: build_ref.to_string(),
+            config_hash,
+            tags: summary.tags.clone(),
+            digest: summary.digest.clone(),
+            finished_at,
+            duration_ms: summary.duration_ms,
+            steps: summary.steps,
+            cached_steps: summary.cached_steps,
+            success: error.is_none(),
+            error,
+        }
+    }
+}
+
+/// Filter for [`BuildLedger::query`]
+///
+/// All set conditions must match. Results are ordered newest first.
+#[derive(Debug, Clone, Default)]
+pub struct LedgerQuery {
+    /// Only builds of this configuration hash
+    pub config_hash: Option<String>,
+    /// Only builds that pushed this tag
+    pub tag: Option<String>,
+    /// Only successful (`true`) or failed (`false`) builds
+    pub success: Option<bool>,
+    /// Only builds finished at or after this time (milliseconds since the Unix epoch)
+    pub since: Option<u64>,
+    /// Maximum number of records returned
+    pub limit: Option<usize>,
+}
+
+impl LedgerQuery {
+    /// Match every build
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only builds of this configuration hash
+    pub fn config_hash(mut self, hash: impl Into<String>) -> Self {
+        self.config_hash = Some(hash.into());
+        self
+    }
+
+    /// Only builds that pushed this tag
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Only successful or failed builds
+    pub fn success(mut self, success: bool) -> Self {
+        self.success = Some(success);
+        self
+    }
+
+    /// Only builds finished at or after this time
+    pub fn since(mut self, unix_millis: u64) -> Self {
+        self.since = Some(unix_millis);
+        self
+    }
+
+    /// Return at most this many records
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    fn matches(&self, record: &BuildRecord) -> bool {
+        !matches!(&self.config_hash, Some(h) if *h != record.config_hash)
+            && !matches!(&self.tag, Some(t) if !record.tags.contains(t))
+            && !matches!(self.success, Some(s) if s != record.success)
+            && !matches!(self.since, Some(since) if record.finished_at < since)
+    }
+}
+
+/// Append-only JSON-lines store of finished builds
+///
+/// As an event sink it records every build that completes or fails. Records
+/// can also be added directly with [`BuildLedger::record`].
+#[derive(Debug)]
+pub struct BuildLedger {
+    path: PathBuf,
+    /// Configuration hashes of builds queued but not yet finished
+    pending: Mutex<HashMap<String, String>>,
+}
+
+impl BuildLedger {
+    /// Open (creating if needed) the ledger in a state directory
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)
+            .map_err(|e| Error::file_operation("create directory", dir, e))?;
+        Ok(Self {
+            path: dir.join(LEDGER_FILE),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Default state directory
+    ///
+    /// `$BUILDKIT_CLIENT_STATE_DIR` if set, otherwise
+    /// `$XDG_STATE_HOME/buildkit-client`, falling back to
+    /// `~/.local/state/buildkit-client`.
+    pub fn default_dir() -> PathBuf {
+        if let Some(dir) = std::env::var_os("BUILDKIT_CLIENT_STATE_DIR") {
+            return PathBuf::from(dir);
+        }
+        let state_home = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+            })
+            .unwrap_or_else(std::env::temp_dir);
+        state_home.join("buildkit-client")
+    }
+
+    /// Path of the ledger file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a record
+    pub fn record(&self, record: &BuildRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record)
+            .map_err(|e| Error::other(format!("Failed to serialize build record: {}", e)))?;
+        line.push(b'\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| Error::file_operation("open", &self.path, e))?;
+        file.write_all(&line)
+            .map_err(|e| Error::file_operation("write", &self.path, e))
+    }
+
+    /// All records, oldest first
+    ///
+    /// Lines that cannot be parsed (e.g. a partially written last line) are
+    /// skipped.
+    pub fn records(&self) -> Result<Vec<BuildRecord>> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::file_operation("open", &self.path, e)),
+        };
+
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| Error::file_operation("read", &self.path, e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(record) => records.push(record),
+                Err(e) => tracing::warn!("Skipping malformed ledger entry: {}", e),
+            }
+        }
+        Ok(records)
+    }
+
+    /// Records matching a query, newest first
+    pub fn query(&self, query: &LedgerQuery) -> Result<Vec<BuildRecord>> {
+        let matching = self
+            .records()?
+            .into_iter()
+            .rev()
+            .filter(|r| query.matches(r))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect();
+        Ok(matching)
+    }
+
+    /// Most recent successful build of a configuration
+    pub fn last_success(&self, config_hash: &str) -> Result<Option<BuildRecord>> {
+        let query = LedgerQuery::new()
+            .config_hash(config_hash)
+            .success(true)
+            .limit(1);
+        Ok(self.query(&query)?.into_iter().next())
+    }
+}
+
+impl BuildEventSink for BuildLedger {
+    fn on_event(&self, event: &BuildEvent) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let record = match &event.kind {
+            BuildEventKind::Queued { config_hash, .. } => {
+                pending.insert(event.build_ref.clone(), config_hash.clone());
+                return;
+            }
+            BuildEventKind::Completed { summary } => BuildRecord::from_summary(
+                &event.build_ref,
+                pending.remove(&event.build_ref).unwrap_or_default(),
+                event.timestamp,
+                summary,
+                None,
+            ),
+            BuildEventKind::Failed { summary, error } => BuildRecord::from_summary(
+                &event.build_ref,
+                pending.remove(&event.build_ref).unwrap_or_default(),
+                event.timestamp,
+                summary,
+                Some(error.message.clone()),
+            ),
+            _ => return,
+        };
+        drop(pending);
+
+        if let Err(e) = self.record(&record) {
+            tracing::warn!("Failed to record build {}: {}", record.build_ref, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(build_ref: &str, hash: &str, finished_at: u64, success: bool) -> BuildRecord {
+        BuildRecord {
+            build_ref: build_ref.to_string(),
+            config_hash: hash.to_string(),
+            tags: vec!["app:latest".to_string()],
+            digest: success.then(|| format!("sha256:{}", build_ref)),
+            finished_at,
+            duration_ms: 1000,
+            steps: 4,
+            cached_steps: 2,
+            success,
+            error: (!success).then(|| "failed".to_string()),
+        }
+    }
+
+    #[test]
+    fn query_filters_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = BuildLedger::open(dir.path()).unwrap();
+        ledger.record(&record("a", "h1", 1, true)).unwrap();
+        ledger.record(&record("b", "h2", 2, true)).unwrap();
+        ledger.record(&record("c", "h1", 3, false)).unwrap();
+        ledger.record(&record("d", "h1", 4, true)).unwrap();
+
+        let refs = |query: LedgerQuery| -> Vec<String> {
+            ledger
+                .query(&query)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.build_ref)
+                .collect()
+        };
+        assert_eq!(refs(LedgerQuery::new().config_hash("h1")), ["d", "c", "a"]);
+        assert_eq!(refs(LedgerQuery::new().success(false)), ["c"]);
+        assert_eq!(refs(LedgerQuery::new().since(2).limit(2)), ["d", "c"]);
+        assert_eq!(
+            ledger
+                .last_success("h1")
+                .unwrap()
+                .unwrap()
+                .digest
+                .as_deref(),
+            Some("sha256:d")
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = BuildLedger::open(dir.path()).unwrap();
+        ledger.record(&record("a", "h1", 1, true)).unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(ledger.path())
+            .unwrap()
+            .write_all(b"{\"build_ref\":")
+            .unwrap();
+
+        assert_eq!(ledger.records().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn missing_ledger_has_no_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = BuildLedger::open(dir.path().join("state")).unwrap();
+        assert!(ledger.records().unwrap().is_empty());
+    }
+
+    #[test]
+    fn events_are_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = BuildLedger::open(dir.path()).unwrap();
+        let event = |kind| BuildEvent {
+            build_ref: "build-1".to_string(),
+            timestamp: 42,
+            kind,
+        };
+
+        ledger.on_event(&event(BuildEventKind::Queued {
+            tags: vec!["app:latest".to_string()],
+            config_hash: "sha256:cfg".to_string(),
+        }));
+        ledger.on_event(&event(BuildEventKind::Completed {
+            summary: BuildSummary {
+                tags: vec!["app:latest".to_string()],
+                digest: Some("sha256:img".to_string()),
+                duration_ms: 5,
+                steps: 3,
+                cached_steps: 1,
+                failed_steps: 0,
+            },
+        }));
+
+        let records = ledger.records().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].config_hash, "sha256:cfg");
+        assert_eq!(records[0].digest.as_deref(), Some("sha256:img"));
+        assert_eq!(records[0].finished_at, 42);
+        assert!(records[0].success);
+    }
+}
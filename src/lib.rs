@@ -4,13 +4,35 @@
 //!
 //! # Features
 //!
-//! - Build from local Dockerfile or GitHub repository
+//! - Build from local Dockerfile, Git or GitHub repository, or remote tarball
+//! - Dockerfiles held in memory instead of on disk
 //! - Support for private GitHub repositories with authentication
 //! - Push images to registries with authentication
 //! - Multi-platform builds
 //! - Build arguments, target stages, and advanced options
 //! - Real-time progress monitoring
 //! - Cache import/export
+//! - Inspect pushed images in a registry
+//! - Build lifecycle events with an HTTP webhook sink
+//! - Local ledger of past builds
+//! - Interactive debugging of failed build steps
+//! - Smoke-testing build results in ephemeral containers
+//! - Building several targets of one Dockerfile with a single context sync
+//! - Bake-style build definitions with groups, inheritance and matrices
+//! - Batches of independent builds with a concurrency limit
+//! - Listing workers and pinning builds to them by platform or label
+//! - HTTP/JSON build service (`serve` feature)
+//! - Build graphs with step timings as DOT or Mermaid
+//! - Cancelling builds on the daemon, e.g. on Ctrl-C
+//! - Exporting build results to a local directory or an OCI tarball
+//! - Loading built images into a local Docker daemon without a registry
+//! - SSH agent forwarding for `RUN --mount=type=ssh`
+//! - Build graphs defined in Rust with the `llb` module
+//! - Inspecting and pruning the daemon's build cache
+//! - Listing, inspecting and pinning builds in the daemon's history
+//! - Build progress as a stream of typed events
+//! - Context upload statistics and Prometheus-format metrics
+//! - Per-session tracing spans, with packet-level detail (`debug_wire` feature)
 //!
 //! # Examples
 //!
@@ -76,6 +98,7 @@
 //!             host: "docker.io".to_string(),
 //!             username: "myuser".to_string(),
 //!             password: "mytoken".to_string(),
+//!             ..Default::default()
 //!         });
 //!
 //!     let progress = Box::new(ConsoleProgressHandler::new(true));
@@ -86,16 +109,51 @@
 //! }
 //! ```
 
+pub mod bake;
+pub mod batch;
 pub mod builder;
 pub mod client;
+pub mod container;
+pub mod debug;
+pub mod docker;
+pub mod dockerfile;
 pub mod error;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod gateway;
+pub mod history;
+pub mod ledger;
+pub mod llb;
+pub mod metrics;
 pub mod progress;
 pub mod proto;
+pub mod raw;
+pub mod redact;
+pub mod reference;
+pub mod registry;
+pub mod report;
+#[cfg(feature = "serve")]
+pub mod server;
 pub mod session;
 pub mod solve;
+pub mod source_policy;
+pub mod storage;
+pub mod stream;
+pub mod targets;
+#[cfg(test)]
+mod testing;
+pub mod workers;
 
 // Re-export main types
-pub use builder::{BuildConfig, DockerfileSource, Platform, RegistryAuth};
-pub use client::BuildKitClient;
-pub use error::{Error, Result};
-pub use solve::BuildResult;
+pub use builder::{
+    BuildConfig, CacheExport, CacheImport, CacheMode, CredentialScope, DockerfileSource,
+    Entitlement, Export, Frontend, GhaCache, GitAuth, LogSink, NamedContext, Platform,
+    RegistryAuth, S3Cache, SecretSource,
+};
+pub use client::{BuildKitClient, ClientOptions, ClientTlsConfig};
+pub use error::{Error, ErrorReport, Result, SourceLocation, StepFailure};
+pub use metrics::{ContextStats, Metrics};
+pub use reference::Reference;
+pub use solve::{BuildResult, MetadataFormat};
+pub use tokio_util::sync::CancellationToken;
@@ -0,0 +1,807 @@
+//! Build graphs in LLB, BuildKit's low-level build definition
+//!
+//! A [`State`] is a filesystem in the graph: an image, a directory of the
+//! session, or the result of running a command or copying files onto
+//! another state. [`State::marshal`] turns the graph leading to a state
+//! into a [`Definition`] that
+//! [`BuildKitClient::solve_llb`](crate::BuildKitClient::solve_llb) builds
+//! like a Dockerfile.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use buildkit_client::{llb, BuildConfig, BuildKitClient};
+//!
+//! #[tokio::main]
+//! async fn main() -> anyhow::Result<()> {
+//!     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+//!
+//!     let base = llb::image("rust:1-alpine");
+//!     let src = llb::copy(&llb::local("context"), "/", &base, "/src");
+//!     let built = llb::exec(&src, ["cargo", "build", "--release"])
+//!         .dir("/src")
+//!         .cache_mount("/src/target", "cargo-target")
+//!         .root();
+//!     let image = llb::copy(
+//!         &built,
+//!         "/src/target/release/my-app",
+//!         &llb::image("alpine"),
+//!         "/usr/local/bin/",
+//!     );
+//!
+//!     let config = BuildConfig::local("./my-app").tag("localhost:5000/my-app:latest");
+//!     client.solve_llb(config, image.marshal(), None).await?;
+//!     Ok(())
+//! }
+//! ```
+
+use crate::builder::Platform;
+use crate::proto::pb::{
+    self, file_action, op, CacheOpt, CacheSharingOpt, ExecOp, FileAction, FileActionCopy,
+    FileActionMkDir, FileActionMkFile, FileOp, Input, Meta, Mount, MountType, OpMetadata,
+    SecretOpt, SourceOp, TmpfsOpt,
+};
+use crate::reference::Reference;
+use prost::Message;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+pub use crate::proto::pb::{Definition, NetMode};
+
+/// `PATH` of commands whose state does not set one
+const DEFAULT_PATH_ENV: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Input index of a mount on an empty filesystem
+const EMPTY_INPUT: i64 = -1;
+
+/// Output index of a mount whose changes are discarded
+const SKIP_OUTPUT: i64 = -1;
+
+/// Unset mode or timestamp of a file action
+const UNSET: i64 = -1;
+
+/// Description key of the name shown in progress output
+const CUSTOM_NAME_KEY: &str = "llb.customname";
+
+/// Operation in the graph with the outputs it reads
+#[derive(Debug)]
+struct Vertex {
+    /// The operation, without its inputs
+    op: pb::Op,
+    inputs: Vec<Output>,
+    custom_name: Option<String>,
+}
+
+/// One output of an operation
+#[derive(Debug, Clone)]
+struct Output {
+    vertex: Arc<Vertex>,
+    index: i64,
+}
+
+/// A filesystem in a build graph
+///
+/// States are cheap to clone and never change; operations on a state
+/// return a new one. Besides the filesystem, a state carries the
+/// environment, working directory and user that commands run on it get.
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    /// `None` for an empty filesystem
+    output: Option<Output>,
+    env: BTreeMap<String, String>,
+    dir: Option<String>,
+    user: Option<String>,
+    platform: Option<Platform>,
+}
+
+impl State {
+    /// Set an environment variable for commands run on this state
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Set the working directory of commands run on this state
+    pub fn dir(mut self, dir: impl Into<String>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Set the user commands run on this state run as
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Set the platform of operations on this state
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.platform = Some(platform);
+        self
+    }
+
+    /// Whether this is an empty filesystem without any operation
+    pub fn is_scratch(&self) -> bool {
+        self.output.is_none()
+    }
+
+    /// Create a directory, including missing parents
+    pub fn mkdir(&self, path: impl Into<String>, mode: u32) -> State {
+        self.file_action(file_action::Action::Mkdir(FileActionMkDir {
+            path: path.into(),
+            mode: mode as i32,
+            make_parents: true,
+            owner: None,
+            timestamp: UNSET,
+        }))
+    }
+
+    /// Create a file with the given content
+    pub fn mkfile(&self, path: impl Into<String>, mode: u32, data: impl Into<Vec<u8>>) -> State {
+        self.file_action(file_action::Action::Mkfile(FileActionMkFile {
+            path: path.into(),
+            mode: mode as i32,
+            data: data.into(),
+            owner: None,
+            timestamp: UNSET,
+        }))
+    }
+
+    fn file_action(&self, action: file_action::Action) -> State {
+        let mut inputs = Vec::new();
+        let input = self.input_index(&mut inputs);
+        self.derive(
+            op::Op::File(FileOp {
+                actions: vec![FileAction {
+                    input,
+                    secondary_input: EMPTY_INPUT,
+                    output: 0,
+                    action: Some(action),
+                }],
+            }),
+            inputs,
+            None,
+            0,
+        )
+    }
+
+    /// Index of this state among `inputs`, adding it if it is not empty
+    fn input_index(&self, inputs: &mut Vec<Output>) -> i64 {
+        match &self.output {
+            Some(output) => {
+                inputs.push(output.clone());
+                inputs.len() as i64 - 1
+            }
+            None => EMPTY_INPUT,
+        }
+    }
+
+    /// State for an output of a new operation, keeping this state's
+    /// environment
+    fn derive(
+        &self,
+        op: op::Op,
+        inputs: Vec<Output>,
+        custom_name: Option<String>,
+        index: i64,
+    ) -> State {
+        let vertex = Vertex {
+            op: pb::Op {
+                op: Some(op),
+                platform: self.platform.as_ref().map(pb_platform),
+                ..Default::default()
+            },
+            inputs,
+            custom_name,
+        };
+        State {
+            output: Some(Output {
+                vertex: Arc::new(vertex),
+                index,
+            }),
+            ..self.clone()
+        }
+    }
+
+    /// Definition of the graph leading to this state
+    pub fn marshal(&self) -> Definition {
+        let Some(output) = &self.output else {
+            return Definition::default();
+        };
+        let mut marshaller = Marshaller::default();
+        let digest = marshaller.vertex(&output.vertex);
+        // The last op only points at the result
+        marshaller.push(
+            pb::Op {
+                inputs: vec![Input {
+                    digest,
+                    index: output.index,
+                }],
+                ..Default::default()
+            },
+            None,
+        );
+        Definition {
+            def: marshaller.def,
+            metadata: marshaller.metadata,
+            source: None,
+        }
+    }
+}
+
+/// An empty filesystem
+pub fn scratch() -> State {
+    State::default()
+}
+
+/// Image source
+///
+/// References are normalized like Docker does, so `alpine` pulls
+/// `docker.io/library/alpine:latest`.
+#[derive(Debug, Clone)]
+pub struct Image {
+    reference: String,
+    platform: Option<Platform>,
+    pull: bool,
+}
+
+impl Image {
+    /// Image with the given reference
+    pub fn new(reference: impl Into<String>) -> Self {
+        Self {
+            reference: reference.into(),
+            platform: None,
+            pull: false,
+        }
+    }
+
+    /// Use the variant of the image for a platform
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.platform = Some(platform);
+        self
+    }
+
+    /// Always check the registry for a newer image
+    pub fn pull(mut self, pull: bool) -> Self {
+        self.pull = pull;
+        self
+    }
+}
+
+impl From<Image> for State {
+    fn from(image: Image) -> Self {
+        // BuildKit reports references it cannot parse when solving
+        let reference = Reference::parse(&image.reference)
+            .map(|r| r.to_string())
+            .unwrap_or(image.reference);
+        let mut attrs = BTreeMap::new();
+        if image.pull {
+            attrs.insert("image.resolvemode".to_string(), "pull".to_string());
+        }
+        let state = State {
+            platform: image.platform,
+            ..State::default()
+        };
+        state.derive(
+            op::Op::Source(SourceOp {
+                identifier: format!("docker-image://{}", reference),
+                attrs,
+            }),
+            Vec::new(),
+            None,
+            0,
+        )
+    }
+}
+
+/// Image by reference, e.g. `alpine:3.20`
+pub fn image(reference: impl Into<String>) -> State {
+    Image::new(reference).into()
+}
+
+/// Directory of the build's session
+///
+/// Builds started from a local [`BuildConfig`](crate::BuildConfig) serve
+/// their context as `context`.
+#[derive(Debug, Clone)]
+pub struct Local {
+    name: String,
+    include_patterns: Vec<String>,
+    exclude_patterns: Vec<String>,
+}
+
+impl Local {
+    /// Session directory with the given name
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            include_patterns: Vec::new(),
+            exclude_patterns: Vec::new(),
+        }
+    }
+
+    /// Only transfer files matching a pattern
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.include_patterns.push(pattern.into());
+        self
+    }
+
+    /// Skip files matching a pattern, in `.dockerignore` syntax
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude_patterns.push(pattern.into());
+        self
+    }
+}
+
+impl From<Local> for State {
+    fn from(local: Local) -> Self {
+        let mut attrs = BTreeMap::new();
+        if !local.include_patterns.is_empty() {
+            attrs.insert(
+                "local.includepattern".to_string(),
+                serde_json::to_string(&local.include_patterns).unwrap_or_default(),
+            );
+        }
+        if !local.exclude_patterns.is_empty() {
+            attrs.insert(
+                "local.excludepatterns".to_string(),
+                serde_json::to_string(&local.exclude_patterns).unwrap_or_default(),
+            );
+        }
+        State::default().derive(
+            op::Op::Source(SourceOp {
+                identifier: format!("local://{}", local.name),
+                attrs,
+            }),
+            Vec::new(),
+            None,
+            0,
+        )
+    }
+}
+
+/// Directory of the build's session by name
+pub fn local(name: impl Into<String>) -> State {
+    Local::new(name).into()
+}
+
+/// Copy `src` of one state to `dest` on another, like a Dockerfile `COPY`
+///
+/// The contents of directories are copied, wildcards are expanded and
+/// missing parents of `dest` are created.
+pub fn copy(from: &State, src: impl Into<String>, onto: &State, dest: impl Into<String>) -> State {
+    let mut inputs = Vec::new();
+    let input = onto.input_index(&mut inputs);
+    let secondary_input = from.input_index(&mut inputs);
+    onto.derive(
+        op::Op::File(FileOp {
+            actions: vec![FileAction {
+                input,
+                secondary_input,
+                output: 0,
+                action: Some(file_action::Action::Copy(FileActionCopy {
+                    src: src.into(),
+                    dest: dest.into(),
+                    mode: UNSET as i32,
+                    follow_symlink: true,
+                    dir_copy_contents: true,
+                    create_dest_path: true,
+                    allow_wildcard: true,
+                    allow_empty_wildcard: true,
+                    timestamp: UNSET,
+                    ..Default::default()
+                })),
+            }],
+        }),
+        inputs,
+        None,
+        0,
+    )
+}
+
+/// Run a command on a state
+///
+/// The command gets the state's environment, working directory and user,
+/// which can be overridden on the returned [`Exec`].
+pub fn exec<I, S>(root: &State, args: I) -> Exec
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    Exec {
+        root: root.clone(),
+        args: args.into_iter().map(Into::into).collect(),
+        env: root.env.clone(),
+        dir: root.dir.clone(),
+        user: root.user.clone(),
+        mounts: Vec::new(),
+        network: NetMode::Unset,
+        custom_name: None,
+    }
+}
+
+/// Mount of a command besides its root filesystem
+#[derive(Debug, Clone)]
+enum ExecMount {
+    Bind {
+        dest: String,
+        source: State,
+        readonly: bool,
+    },
+    Cache {
+        dest: String,
+        id: String,
+    },
+    Tmpfs {
+        dest: String,
+    },
+    Secret {
+        dest: String,
+        id: String,
+    },
+}
+
+/// A command to run, see [`exec`]
+#[derive(Debug, Clone)]
+pub struct Exec {
+    root: State,
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+    dir: Option<String>,
+    user: Option<String>,
+    mounts: Vec<ExecMount>,
+    network: NetMode,
+    custom_name: Option<String>,
+}
+
+impl Exec {
+    /// Set an environment variable
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Set the working directory
+    pub fn dir(mut self, dir: impl Into<String>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Set the user to run as
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Mount a state at `dest`; its changes are available from
+    /// [`Exec::output`]
+    pub fn mount(mut self, dest: impl Into<String>, source: &State) -> Self {
+        self.mounts.push(ExecMount::Bind {
+            dest: dest.into(),
+            source: source.clone(),
+            readonly: false,
+        });
+        self
+    }
+
+    /// Mount a state at `dest` read-only
+    pub fn readonly_mount(mut self, dest: impl Into<String>, source: &State) -> Self {
+        self.mounts.push(ExecMount::Bind {
+            dest: dest.into(),
+            source: source.clone(),
+            readonly: true,
+        });
+        self
+    }
+
+    /// Mount a cache directory shared between builds, like
+    /// `RUN --mount=type=cache`
+    pub fn cache_mount(mut self, dest: impl Into<String>, id: impl Into<String>) -> Self {
+        self.mounts.push(ExecMount::Cache {
+            dest: dest.into(),
+            id: id.into(),
+        });
+        self
+    }
+
+    /// Mount an empty in-memory filesystem
+    pub fn tmpfs(mut self, dest: impl Into<String>) -> Self {
+        self.mounts.push(ExecMount::Tmpfs { dest: dest.into() });
+        self
+    }
+
+    /// Mount a build secret as a file, like `RUN --mount=type=secret`
+    pub fn secret(mut self, dest: impl Into<String>, id: impl Into<String>) -> Self {
+        self.mounts.push(ExecMount::Secret {
+            dest: dest.into(),
+            id: id.into(),
+        });
+        self
+    }
+
+    /// Set the network mode
+    pub fn network(mut self, network: NetMode) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Name shown for the step in progress output
+    pub fn custom_name(mut self, name: impl Into<String>) -> Self {
+        self.custom_name = Some(name.into());
+        self
+    }
+
+    /// Root filesystem after the command ran
+    pub fn root(&self) -> State {
+        self.state(0)
+    }
+
+    /// Contents of the writable mount at `dest` after the command ran
+    pub fn output(&self, dest: &str) -> Option<State> {
+        self.mounts
+            .iter()
+            .filter(|mount| {
+                matches!(
+                    mount,
+                    ExecMount::Bind {
+                        readonly: false,
+                        ..
+                    }
+                )
+            })
+            .position(|mount| matches!(mount, ExecMount::Bind { dest: d, .. } if d == dest))
+            .map(|position| {
+                let state = self.state(position as i64 + 1);
+                State {
+                    env: BTreeMap::new(),
+                    dir: None,
+                    user: None,
+                    ..state
+                }
+            })
+    }
+
+    fn state(&self, index: i64) -> State {
+        let mut inputs = Vec::new();
+        let mut mounts = vec![Mount {
+            input: self.root.input_index(&mut inputs),
+            dest: "/".to_string(),
+            output: 0,
+            ..Default::default()
+        }];
+        let mut outputs = 1;
+        for mount in &self.mounts {
+            mounts.push(match mount {
+                ExecMount::Bind {
+                    dest,
+                    source,
+                    readonly,
+                } => Mount {
+                    input: source.input_index(&mut inputs),
+                    dest: dest.clone(),
+                    output: if *readonly {
+                        SKIP_OUTPUT
+                    } else {
+                        outputs += 1;
+                        outputs - 1
+                    },
+                    readonly: *readonly,
+                    ..Default::default()
+                },
+                ExecMount::Cache { dest, id } => Mount {
+                    input: EMPTY_INPUT,
+                    dest: dest.clone(),
+                    output: SKIP_OUTPUT,
+                    mount_type: MountType::Cache as i32,
+                    cache_opt: Some(CacheOpt {
+                        id: id.clone(),
+                        sharing: CacheSharingOpt::Shared as i32,
+                    }),
+                    ..Default::default()
+                },
+                ExecMount::Tmpfs { dest } => Mount {
+                    input: EMPTY_INPUT,
+                    dest: dest.clone(),
+                    output: SKIP_OUTPUT,
+                    mount_type: MountType::Tmpfs as i32,
+                    tmpfs_opt: Some(TmpfsOpt::default()),
+                    ..Default::default()
+                },
+                ExecMount::Secret { dest, id } => Mount {
+                    input: EMPTY_INPUT,
+                    dest: dest.clone(),
+                    output: SKIP_OUTPUT,
+                    mount_type: MountType::Secret as i32,
+                    secret_opt: Some(SecretOpt {
+                        id: id.clone(),
+                        mode: 0o400,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            });
+        }
+
+        let mut env = self.env.clone();
+        env.entry("PATH".to_string())
+            .or_insert_with(|| DEFAULT_PATH_ENV.to_string());
+        let meta = Meta {
+            args: self.args.clone(),
+            env: env.iter().map(|(k, v)| format!("{}={}", k, v)).collect(),
+            cwd: self.dir.clone().unwrap_or_else(|| "/".to_string()),
+            user: self.user.clone().unwrap_or_default(),
+            ..Default::default()
+        };
+        let state = self.root.derive(
+            op::Op::Exec(ExecOp {
+                meta: Some(meta),
+                mounts,
+                network: self.network as i32,
+                ..Default::default()
+            }),
+            inputs,
+            self.custom_name.clone(),
+            index,
+        );
+        State {
+            env: self.env.clone(),
+            dir: self.dir.clone(),
+            user: self.user.clone(),
+            ..state
+        }
+    }
+}
+
+/// Serializes a graph, each distinct op once and inputs first
+#[derive(Default)]
+struct Marshaller {
+    def: Vec<Vec<u8>>,
+    metadata: HashMap<String, OpMetadata>,
+    /// Digests of vertices already marshalled
+    digests: HashMap<*const Vertex, String>,
+}
+
+impl Marshaller {
+    fn vertex(&mut self, vertex: &Arc<Vertex>) -> String {
+        if let Some(digest) = self.digests.get(&Arc::as_ptr(vertex)) {
+            return digest.clone();
+        }
+        let inputs = vertex
+            .inputs
+            .iter()
+            .map(|input| Input {
+                digest: self.vertex(&input.vertex),
+                index: input.index,
+            })
+            .collect();
+        let op = pb::Op {
+            inputs,
+            ..vertex.op.clone()
+        };
+        let digest = self.push(op, vertex.custom_name.as_deref());
+        self.digests.insert(Arc::as_ptr(vertex), digest.clone());
+        digest
+    }
+
+    fn push(&mut self, op: pb::Op, custom_name: Option<&str>) -> String {
+        let bytes = op.encode_to_vec();
+        let digest = format!("sha256:{:x}", Sha256::digest(&bytes));
+        if !self.metadata.contains_key(&digest) {
+            let description = custom_name
+                .map(|name| HashMap::from([(CUSTOM_NAME_KEY.to_string(), name.to_string())]))
+                .unwrap_or_default();
+            self.metadata.insert(
+                digest.clone(),
+                OpMetadata {
+                    description,
+                    ..Default::default()
+                },
+            );
+            self.def.push(bytes);
+        }
+        digest
+    }
+}
+
+fn pb_platform(platform: &Platform) -> pb::Platform {
+    pb::Platform {
+        architecture: platform.arch.clone(),
+        os: platform.os.clone(),
+        variant: platform.variant.clone().unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(definition: &Definition) -> Vec<pb::Op> {
+        definition
+            .def
+            .iter()
+            .map(|bytes| pb::Op::decode(bytes.as_slice()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn inputs_come_before_the_ops_using_them() {
+        let base = image("alpine");
+        let built = exec(&base, ["sh", "-c", "echo hi > /hi"]).root();
+        let result = copy(&built, "/hi", &base, "/copied");
+
+        let definition = result.marshal();
+        let ops = ops(&definition);
+        // alpine, exec, copy and the final pointer; alpine is shared
+        assert_eq!(ops.len(), 4);
+        assert_eq!(definition.metadata.len(), 4);
+
+        let digests: Vec<String> = definition
+            .def
+            .iter()
+            .map(|bytes| format!("sha256:{:x}", Sha256::digest(bytes)))
+            .collect();
+        for (i, op) in ops.iter().enumerate() {
+            for input in &op.inputs {
+                let position = digests.iter().position(|d| *d == input.digest).unwrap();
+                assert!(position < i);
+            }
+        }
+        assert!(ops[3].op.is_none());
+        match &ops[0].op {
+            Some(op::Op::Source(source)) => {
+                assert_eq!(
+                    source.identifier,
+                    "docker-image://docker.io/library/alpine:latest"
+                )
+            }
+            other => panic!("unexpected op {:?}", other),
+        }
+    }
+
+    #[test]
+    fn marshalling_is_deterministic() {
+        let state = || {
+            let src = Local::new("context")
+                .include("src")
+                .exclude("target")
+                .into();
+            let base = image("rust").env("CARGO_HOME", "/cargo");
+            exec(&copy(&src, "/", &base, "/src"), ["cargo", "build"])
+                .env("RUSTFLAGS", "-Dwarnings")
+                .cache_mount("/cargo", "cargo")
+                .root()
+        };
+        assert_eq!(state().marshal().def, state().marshal().def);
+    }
+
+    #[test]
+    fn exec_gets_the_state_environment() {
+        let base = image("alpine").env("A", "1").dir("/work").user("nobody");
+        let run = exec(&base, ["true"])
+            .env("B", "2")
+            .mount("/out", &scratch())
+            .readonly_mount("/in", &local("context"));
+        let definition = run.output("/out").unwrap().marshal();
+        let ops = ops(&definition);
+
+        let Some(op::Op::Exec(exec)) = &ops[2].op else {
+            panic!("expected exec op, got {:?}", ops[2].op);
+        };
+        let meta = exec.meta.as_ref().unwrap();
+        assert_eq!(
+            meta.env,
+            ["A=1", "B=2", &format!("PATH={}", DEFAULT_PATH_ENV)]
+        );
+        assert_eq!(meta.cwd, "/work");
+        assert_eq!(meta.user, "nobody");
+        let outputs: Vec<(i64, i64)> = exec.mounts.iter().map(|m| (m.input, m.output)).collect();
+        assert_eq!(outputs, [(0, 0), (EMPTY_INPUT, 1), (1, SKIP_OUTPUT)]);
+        assert_eq!(ops[3].inputs[0].index, 1);
+    }
+
+    #[test]
+    fn scratch_marshals_to_an_empty_definition() {
+        assert!(scratch().marshal().def.is_empty());
+    }
+}
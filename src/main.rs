@@ -1,34 +1,107 @@
 use anyhow::Result;
-use buildkit_client::progress::{ConsoleProgressHandler, JsonProgressHandler};
-use buildkit_client::{BuildConfig, BuildKitClient, Platform, RegistryAuth};
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use buildkit_client::bake::{BakeFile, ResolvedTarget};
+use buildkit_client::batch::{BatchManifest, BatchResult};
+use buildkit_client::docker::DockerDaemon;
+use buildkit_client::history;
+use buildkit_client::progress::{
+    format_bytes, ConsoleProgressHandler, JsonProgressHandler, PlainProgressHandler,
+    ProgressHandler, TtyProgressHandler,
+};
+use buildkit_client::proto::moby::buildkit::v1::types::WorkerRecord;
+use buildkit_client::proto::moby::buildkit::v1::{BuildHistoryRecord, UsageRecord};
+use buildkit_client::storage::{parse_bytes, usage_json, PruneOptions};
+use buildkit_client::workers::workers_json;
+use buildkit_client::{
+    BuildConfig, BuildKitClient, BuildResult, CacheExport, CacheImport, CancellationToken,
+    ClientTlsConfig, Entitlement, ErrorReport, LogSink, MetadataFormat, NamedContext, Platform,
+    Reference, RegistryAuth, SecretSource,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio_stream::StreamExt;
+
+/// Exit code of a build cancelled by a signal, as for a shell's SIGINT
+const CANCELLED_EXIT_CODE: i32 = 130;
+
+/// Builds run at once by `batch` and `bake` when neither the flag nor the
+/// manifest sets a limit
+const DEFAULT_BATCH_CONCURRENCY: usize = 4;
 
 #[derive(Parser)]
 #[command(name = "buildkit-client")]
 #[command(about = "BuildKit Rust client for building container images", long_about = None)]
 struct Cli {
-    /// BuildKit daemon address
+    /// BuildKit daemon address (http://, unix:// or docker-container://)
     #[arg(short, long, default_value = "http://localhost:1234")]
     addr: String,
 
+    /// CA certificate to verify buildkitd with; enables TLS
+    #[arg(long)]
+    tlscacert: Option<PathBuf>,
+
+    /// Client certificate for mutual TLS
+    #[arg(long, requires = "tlskey")]
+    tlscert: Option<PathBuf>,
+
+    /// Key of the client certificate
+    #[arg(long, requires = "tlscert")]
+    tlskey: Option<PathBuf>,
+
+    /// Server name to verify the buildkitd certificate against
+    #[arg(long)]
+    tlsservername: Option<String>,
+
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
 
+    /// Format used to report errors
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Text)]
+    error_format: ErrorFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ErrorFormat {
+    /// Human-readable message
+    Text,
+    /// Structured JSON report on stderr
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProgressMode {
+    /// Redraw running steps in place on a terminal, print lines otherwise
+    Auto,
+    /// Print every step and all of its logs, like buildx's plain progress
+    Plain,
+    /// Redraw running steps in place; needs a terminal
+    Tty,
+    /// JSON documents of each status update
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OnError {
+    /// Report the error and exit
+    Fail,
+    /// Open a shell in the failed step's container before exiting
+    Debug,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Build from a local Dockerfile
     Local {
-        /// Context directory
+        /// Context directory, or "-" to read a tar archive or a Dockerfile from stdin
         #[arg(short, long, default_value = ".")]
         context: PathBuf,
 
-        /// Dockerfile path (relative to context or absolute)
+        /// Dockerfile path (relative to context or absolute), or "-" to read it from stdin
         #[arg(short = 'f', long)]
         dockerfile: Option<PathBuf>,
 
@@ -48,6 +121,42 @@ enum Commands {
         #[arg(long)]
         platform: Vec<String>,
 
+        /// Attestation, e.g. type=provenance,mode=max or type=sbom (repeatable)
+        #[arg(long)]
+        attest: Vec<String>,
+
+        /// Build secret, e.g. id=npmrc,src=./.npmrc or id=token,env=TOKEN (repeatable)
+        #[arg(long)]
+        secret: Vec<String>,
+
+        /// Named build context, e.g. assets=../assets or alpine=docker-image://alpine:3.20 (repeatable)
+        #[arg(long = "build-context")]
+        build_context: Vec<String>,
+
+        /// Cache import, e.g. registry.example.com/app:cache or type=gha (repeatable)
+        #[arg(long)]
+        cache_from: Vec<String>,
+
+        /// Cache export, e.g. type=inline or type=registry,ref=registry.example.com/app:cache,mode=min (repeatable)
+        #[arg(long)]
+        cache_to: Vec<String>,
+
+        /// Grant an entitlement: network.host or security.insecure (repeatable)
+        #[arg(long)]
+        allow: Vec<String>,
+
+        /// Source policy JSON file to pin or deny build sources
+        #[arg(long)]
+        source_policy: Option<PathBuf>,
+
+        /// Cancel the build after this many seconds
+        #[arg(long)]
+        timeout: Option<u64>,
+
+        /// Write the log of every step to a file
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+
         /// Registry host for authentication
         #[arg(long)]
         registry_host: Option<String>,
@@ -60,6 +169,18 @@ enum Commands {
         #[arg(long)]
         registry_password: Option<String>,
 
+        /// Ignore credentials from the Docker CLI configuration
+        #[arg(long)]
+        no_docker_config: bool,
+
+        /// Registry to push to over plain HTTP (repeatable)
+        #[arg(long)]
+        insecure_registry: Vec<String>,
+
+        /// Registry to always push to over HTTPS, even if its name looks local (repeatable)
+        #[arg(long)]
+        secure_registry: Vec<String>,
+
         /// No cache
         #[arg(long)]
         no_cache: bool,
@@ -68,20 +189,68 @@ enum Commands {
         #[arg(long)]
         pull: bool,
 
-        /// JSON output
+        /// Only upload the context paths the Dockerfile reads
         #[arg(long)]
-        json: bool,
+        prune_context: bool,
+
+        /// Snapshot the context at build start, ignoring later edits
+        #[arg(long)]
+        snapshot_context: bool,
+
+        /// Progress output
+        #[arg(long, value_enum, default_value_t = ProgressMode::Auto)]
+        progress: ProgressMode,
+
+        /// Write build result metadata (buildx format) to a file
+        #[arg(long)]
+        metadata_file: Option<PathBuf>,
+
+        /// Write the build graph to a file (Mermaid for .mmd, otherwise DOT)
+        #[arg(long)]
+        graph: Option<PathBuf>,
+
+        /// Print how long each step took, slowest first
+        #[arg(long)]
+        timing: bool,
+
+        /// Write the built filesystem into a local directory
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+
+        /// Write the image as an OCI layout tarball
+        #[arg(long)]
+        output_oci: Option<PathBuf>,
+
+        /// Write the image as a `docker load` tarball
+        #[arg(long)]
+        output_docker: Option<PathBuf>,
+
+        /// Load the image into the Docker daemon at DOCKER_HOST
+        #[arg(long)]
+        load: bool,
+
+        /// Do not push the tags; they only name exported images
+        #[arg(long)]
+        no_push: bool,
+
+        /// What to do when a build step fails
+        #[arg(long, value_enum, default_value_t = OnError::Fail)]
+        on_error: OnError,
     },
 
     /// Build from a GitHub repository
     Github {
-        /// Repository URL
+        /// Repository URL, optionally with a #<ref>:<subdir> fragment
         repo: String,
 
         /// Git reference (branch, tag, or commit)
         #[arg(short = 'b', long)]
         git_ref: Option<String>,
 
+        /// Directory of the repository to use as the context
+        #[arg(long)]
+        subdir: Option<String>,
+
         /// GitHub token for private repositories
         #[arg(long, env = "GITHUB_TOKEN")]
         token: Option<String>,
@@ -106,6 +275,42 @@ enum Commands {
         #[arg(long)]
         platform: Vec<String>,
 
+        /// Attestation, e.g. type=provenance,mode=max or type=sbom (repeatable)
+        #[arg(long)]
+        attest: Vec<String>,
+
+        /// Build secret, e.g. id=npmrc,src=./.npmrc or id=token,env=TOKEN (repeatable)
+        #[arg(long)]
+        secret: Vec<String>,
+
+        /// Named build context, e.g. assets=../assets or alpine=docker-image://alpine:3.20 (repeatable)
+        #[arg(long = "build-context")]
+        build_context: Vec<String>,
+
+        /// Cache import, e.g. registry.example.com/app:cache or type=gha (repeatable)
+        #[arg(long)]
+        cache_from: Vec<String>,
+
+        /// Cache export, e.g. type=inline or type=registry,ref=registry.example.com/app:cache,mode=min (repeatable)
+        #[arg(long)]
+        cache_to: Vec<String>,
+
+        /// Grant an entitlement: network.host or security.insecure (repeatable)
+        #[arg(long)]
+        allow: Vec<String>,
+
+        /// Source policy JSON file to pin or deny build sources
+        #[arg(long)]
+        source_policy: Option<PathBuf>,
+
+        /// Cancel the build after this many seconds
+        #[arg(long)]
+        timeout: Option<u64>,
+
+        /// Write the log of every step to a file
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+
         /// Registry host for authentication
         #[arg(long)]
         registry_host: Option<String>,
@@ -118,6 +323,18 @@ enum Commands {
         #[arg(long)]
         registry_password: Option<String>,
 
+        /// Ignore credentials from the Docker CLI configuration
+        #[arg(long)]
+        no_docker_config: bool,
+
+        /// Registry to push to over plain HTTP (repeatable)
+        #[arg(long)]
+        insecure_registry: Vec<String>,
+
+        /// Registry to always push to over HTTPS, even if its name looks local (repeatable)
+        #[arg(long)]
+        secure_registry: Vec<String>,
+
         /// No cache
         #[arg(long)]
         no_cache: bool,
@@ -126,15 +343,191 @@ enum Commands {
         #[arg(long)]
         pull: bool,
 
+        /// Progress output
+        #[arg(long, value_enum, default_value_t = ProgressMode::Auto)]
+        progress: ProgressMode,
+
+        /// Write build result metadata (buildx format) to a file
+        #[arg(long)]
+        metadata_file: Option<PathBuf>,
+
+        /// Write the build graph to a file (Mermaid for .mmd, otherwise DOT)
+        #[arg(long)]
+        graph: Option<PathBuf>,
+
+        /// Print how long each step took, slowest first
+        #[arg(long)]
+        timing: bool,
+
+        /// Write the built filesystem into a local directory
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+
+        /// Write the image as an OCI layout tarball
+        #[arg(long)]
+        output_oci: Option<PathBuf>,
+
+        /// Write the image as a `docker load` tarball
+        #[arg(long)]
+        output_docker: Option<PathBuf>,
+
+        /// Load the image into the Docker daemon at DOCKER_HOST
+        #[arg(long)]
+        load: bool,
+
+        /// Do not push the tags; they only name exported images
+        #[arg(long)]
+        no_push: bool,
+
+        /// What to do when a build step fails
+        #[arg(long, value_enum, default_value_t = OnError::Fail)]
+        on_error: OnError,
+    },
+
+    /// Run the builds listed in a YAML manifest
+    Batch {
+        /// Manifest file listing the builds
+        #[arg(short, long)]
+        file: PathBuf,
+
+        /// Maximum number of builds running at once
+        #[arg(short, long)]
+        concurrency: Option<usize>,
+
+        /// Progress output
+        #[arg(long, value_enum, default_value_t = ProgressMode::Auto)]
+        progress: ProgressMode,
+
+        /// Write the JSON summary to a file instead of stdout
+        #[arg(long)]
+        summary_file: Option<PathBuf>,
+    },
+
+    /// Build the targets of a bake file concurrently
+    Bake {
+        /// Bake file, in the JSON form of `docker buildx bake` or as YAML
+        #[arg(short, long, default_value = "docker-bake.json")]
+        file: PathBuf,
+
+        /// Groups or targets to build; the "default" group if none
+        targets: Vec<String>,
+
+        /// Set a variable, e.g. TAG=v1.2.3 (repeatable)
+        #[arg(long = "set")]
+        set: Vec<String>,
+
+        /// Maximum number of builds running at once
+        #[arg(short, long)]
+        concurrency: Option<usize>,
+
+        /// Print the resolved targets as JSON without building
+        #[arg(long)]
+        print: bool,
+
+        /// Progress output
+        #[arg(long, value_enum, default_value_t = ProgressMode::Auto)]
+        progress: ProgressMode,
+
+        /// Write the JSON summary to a file instead of stdout
+        #[arg(long)]
+        summary_file: Option<PathBuf>,
+    },
+
+    /// Serve an HTTP/JSON API for submitting and following builds
+    #[cfg(feature = "serve")]
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        /// Directory the contexts of submitted builds are resolved in
+        #[arg(long, default_value = ".")]
+        context_root: PathBuf,
+
+        /// Maximum number of builds running at once
+        #[arg(long, default_value_t = 4)]
+        max_concurrent: usize,
+    },
+
+    /// Remove build cache from the daemon
+    Prune {
+        /// Amount of cache to keep, e.g. 10GB
+        #[arg(long)]
+        keep_storage: Option<String>,
+
+        /// Only remove cache matching a filter, e.g. type==regular (repeatable)
+        #[arg(long)]
+        filter: Vec<String>,
+
+        /// Also remove cache that is still referenced
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Show the daemon's build cache records
+    Du {
+        /// Only show records matching a filter, e.g. type==regular (repeatable)
+        #[arg(long)]
+        filter: Vec<String>,
+
         /// JSON output
         #[arg(long)]
         json: bool,
     },
 
+    /// List the daemon's workers
+    Workers {
+        /// Only show workers matching a filter, e.g. platforms==linux/arm64 (repeatable)
+        #[arg(long)]
+        filter: Vec<String>,
+
+        /// JSON output
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Inspect the daemon's build history
+    History {
+        #[command(subcommand)]
+        command: HistoryCommand,
+    },
+
     /// Check BuildKit health
     Health,
 }
 
+#[derive(Subcommand)]
+enum HistoryCommand {
+    /// List past and running builds, newest first
+    List {
+        /// Only show builds matching a filter (repeatable)
+        #[arg(long)]
+        filter: Vec<String>,
+
+        /// JSON output
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show the details of a build
+    Inspect {
+        /// Build ref, as shown by `history list`
+        build_ref: String,
+
+        /// JSON output
+        #[arg(long)]
+        json: bool,
+
+        /// Print the build's SLSA provenance
+        #[arg(long)]
+        provenance: bool,
+
+        /// Write the build's OpenTelemetry trace to a file
+        #[arg(long)]
+        trace: Option<PathBuf>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -148,8 +541,109 @@ async fn main() -> Result<()> {
         )
         .init();
 
+    let error_format = cli.error_format;
+    match run(cli).await {
+        Err(e) if error_format == ErrorFormat::Json => {
+            eprintln!("{}", error_report(&e).to_json());
+            std::process::exit(exit_code(&e));
+        }
+        Err(e) if exit_code(&e) == CANCELLED_EXIT_CODE => {
+            eprintln!("🛑 {}", e);
+            if let Some(buildkit_client::Error::BuildCancelled(report)) = e.downcast_ref() {
+                eprint!("{}", report.timing_summary());
+            }
+            std::process::exit(CANCELLED_EXIT_CODE);
+        }
+        result => result,
+    }
+}
+
+/// Exit code of the CLI for an error
+fn exit_code(error: &anyhow::Error) -> i32 {
+    match error.downcast_ref() {
+        Some(buildkit_client::Error::BuildCancelled(_)) => CANCELLED_EXIT_CODE,
+        _ => 1,
+    }
+}
+
+/// Cancel running builds on Ctrl-C or SIGTERM
+///
+/// BuildKit stops the build once its solve is abandoned. A second signal
+/// exits right away without waiting for that.
+fn cancel_on_signal(token: CancellationToken) {
+    tokio::spawn(async move {
+        if shutdown_signal().await.is_err() {
+            return;
+        }
+        eprintln!("🛑 Cancelling build... (press Ctrl-C again to exit immediately)");
+        token.cancel();
+        if shutdown_signal().await.is_ok() {
+            std::process::exit(CANCELLED_EXIT_CODE);
+        }
+    });
+}
+
+/// Wait for Ctrl-C or, on Unix, SIGTERM
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    // The build service keeps the default signal handling, so Ctrl-C
+    // stops it
+    let token = CancellationToken::new();
+    let handle_signals = match cli.command {
+        #[cfg(feature = "serve")]
+        Commands::Serve { .. } => false,
+        _ => true,
+    };
+    if handle_signals {
+        cancel_on_signal(token.clone());
+    }
+
+    // Printing bake targets needs no daemon
+    if let Commands::Bake {
+        file,
+        targets,
+        set,
+        print: true,
+        ..
+    } = &cli.command
+    {
+        let targets: serde_json::Map<String, serde_json::Value> = resolve_bake(file, targets, set)?
+            .into_iter()
+            .map(|r| Ok((r.name, serde_json::to_value(r.target)?)))
+            .collect::<Result<_>>()?;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({ "target": targets }))?
+        );
+        return Ok(());
+    }
+
     // Connect to BuildKit
-    let mut client = BuildKitClient::connect(&cli.addr).await?;
+    let tls = ClientTlsConfig {
+        ca_cert: cli.tlscacert,
+        cert: cli.tlscert,
+        key: cli.tlskey,
+        server_name: cli.tlsservername,
+    };
+    let client = if tls == ClientTlsConfig::default() {
+        BuildKitClient::connect(&cli.addr).await?
+    } else {
+        BuildKitClient::connect_with_tls(&cli.addr, tls).await?
+    };
+    let mut client = client.with_cancel_token(token);
 
     match cli.command {
         Commands::Local {
@@ -159,20 +653,73 @@ async fn main() -> Result<()> {
             build_arg,
             target,
             platform,
+            attest,
+            secret,
+            build_context,
+            cache_from,
+            cache_to,
+            allow,
+            source_policy,
+            timeout,
+            log_file,
             registry_host,
             registry_user,
             registry_password,
+            no_docker_config,
+            insecure_registry,
+            secure_registry,
             no_cache,
             pull,
-            json,
+            prune_context,
+            snapshot_context,
+            progress,
+            metadata_file,
+            graph,
+            timing,
+            output_dir,
+            output_oci,
+            output_docker,
+            load,
+            no_push,
+            on_error,
         } => {
-            let mut config = BuildConfig::local(context);
+            let dockerfile_from_stdin = dockerfile.as_deref() == Some(Path::new("-"));
+            let context_from_stdin = context.as_path() == Path::new("-");
 
-            if let Some(df) = dockerfile {
-                config = config.dockerfile(df.to_string_lossy().to_string());
+            if dockerfile_from_stdin && context_from_stdin {
+                anyhow::bail!("cannot read both the Dockerfile and the context from stdin");
+            }
+
+            // Keep the extracted context alive until the build has finished
+            let (stdin_context, stdin_dockerfile) = if context_from_stdin {
+                let (dir, dockerfile) = read_context_from_stdin()?;
+                (Some(dir), dockerfile)
+            } else {
+                (None, None)
+            };
+            let context = match &stdin_context {
+                Some(dir) => dir.path().to_path_buf(),
+                None => context,
+            };
+            if stdin_dockerfile.is_some() && dockerfile.is_some() {
+                anyhow::bail!("--file cannot be used when stdin is a Dockerfile");
             }
 
-            for t in tag {
+            let mut config = if dockerfile_from_stdin {
+                let mut content = Vec::new();
+                std::io::stdin().read_to_end(&mut content)?;
+                BuildConfig::inline(content, context)
+            } else if let Some(content) = stdin_dockerfile {
+                BuildConfig::inline(content, context)
+            } else {
+                let mut config = BuildConfig::local(context);
+                if let Some(df) = dockerfile {
+                    config = config.dockerfile(df.to_string_lossy().to_string());
+                }
+                config
+            };
+
+            for t in normalize_tags(&tag)? {
                 config = config.tag(t);
             }
 
@@ -192,6 +739,39 @@ async fn main() -> Result<()> {
                     config = config.platform(Platform::parse(&p)?);
                 }
             }
+            for spec in attest {
+                let (kind, params) = parse_attest(&spec)?;
+                config = config.attest(kind, params);
+            }
+
+            for spec in secret {
+                let (id, source) = SecretSource::parse(&spec)?;
+                config.secret_sources.insert(id, source);
+            }
+
+            for spec in build_context {
+                let (name, source) = NamedContext::parse(&spec)?;
+                config.named_contexts.insert(name, source);
+            }
+
+            config
+                .cache_from
+                .extend(cache_from.into_iter().map(CacheImport::from));
+            config
+                .cache_to
+                .extend(cache_to.into_iter().map(CacheExport::from));
+            for name in allow {
+                config = config.allow(Entitlement::parse(&name)?);
+            }
+            if let Some(path) = source_policy {
+                config = config.source_policy_file(path);
+            }
+            if let Some(secs) = timeout {
+                config = config.timeout(Duration::from_secs(secs));
+            }
+            if let Some(path) = log_file {
+                config = config.log_output(LogSink::file(path));
+            }
 
             if let (Some(host), Some(user), Some(pass)) =
                 (registry_host, registry_user, registry_password)
@@ -200,39 +780,102 @@ async fn main() -> Result<()> {
                     host,
                     username: user,
                     password: pass,
+                    ..Default::default()
                 });
             }
 
-            config = config.no_cache(no_cache).pull(pull);
+            config = config.docker_credentials(!no_docker_config);
 
-            let progress: Box<dyn buildkit_client::progress::ProgressHandler> = if json {
-                Box::new(JsonProgressHandler::new())
-            } else {
-                Box::new(ConsoleProgressHandler::new(cli.verbose))
-            };
+            for host in insecure_registry {
+                config = config.insecure_registry(host, true);
+            }
+            for host in secure_registry {
+                config = config.insecure_registry(host, false);
+            }
 
-            let result = client.build(config, Some(progress)).await?;
+            config = config
+                .no_cache(no_cache)
+                .pull(pull)
+                .prune_context(prune_context)
+                .snapshot_context(snapshot_context);
+
+            if let Some(dir) = output_dir {
+                config = config.export_local(dir);
+            }
+            if let Some(path) = output_oci {
+                config = config.export_oci(path);
+            }
+            if let Some(path) = output_docker {
+                config = config.export_docker(path);
+            }
+            if load {
+                config = config.load_into_docker(DockerDaemon::from_env()?);
+            }
+            config = config.push(!no_push);
+
+            let progress = progress_handler(progress, cli.verbose)?;
+
+            let result = run_build(&mut client, config, progress, on_error).await?;
+
+            if let Some(path) = metadata_file {
+                result.write_metadata(path, MetadataFormat::Buildx)?;
+            }
+
+            if let Some(path) = graph {
+                write_graph(&result, &path)?;
+            }
+
+            if timing {
+                print_timings(&result);
+            }
 
             if let Some(digest) = result.digest {
                 println!("\n📦 Image digest: {}", digest);
             }
+            if !result.platforms.is_empty() {
+                let platforms: Vec<String> =
+                    result.platforms.iter().map(|p| p.to_string()).collect();
+                println!("🧩 Platforms: {}", platforms.join(", "));
+            }
         }
 
         Commands::Github {
             repo,
             git_ref,
+            subdir,
             token,
             dockerfile,
             tag,
             build_arg,
             target,
             platform,
+            attest,
+            secret,
+            build_context,
+            cache_from,
+            cache_to,
+            allow,
+            source_policy,
+            timeout,
+            log_file,
             registry_host,
             registry_user,
             registry_password,
+            no_docker_config,
+            insecure_registry,
+            secure_registry,
             no_cache,
             pull,
-            json,
+            progress,
+            metadata_file,
+            graph,
+            timing,
+            output_dir,
+            output_oci,
+            output_docker,
+            load,
+            no_push,
+            on_error,
         } => {
             let mut config = BuildConfig::github(repo);
 
@@ -240,6 +883,10 @@ async fn main() -> Result<()> {
                 config = config.git_ref(git_ref);
             }
 
+            if let Some(subdir) = subdir {
+                config = config.git_subdir(subdir);
+            }
+
             if let Some(token) = token {
                 config = config.github_token(token);
             }
@@ -248,7 +895,7 @@ async fn main() -> Result<()> {
                 config = config.dockerfile(df);
             }
 
-            for t in tag {
+            for t in normalize_tags(&tag)? {
                 config = config.tag(t);
             }
 
@@ -268,6 +915,39 @@ async fn main() -> Result<()> {
                     config = config.platform(Platform::parse(&p)?);
                 }
             }
+            for spec in attest {
+                let (kind, params) = parse_attest(&spec)?;
+                config = config.attest(kind, params);
+            }
+
+            for spec in secret {
+                let (id, source) = SecretSource::parse(&spec)?;
+                config.secret_sources.insert(id, source);
+            }
+
+            for spec in build_context {
+                let (name, source) = NamedContext::parse(&spec)?;
+                config.named_contexts.insert(name, source);
+            }
+
+            config
+                .cache_from
+                .extend(cache_from.into_iter().map(CacheImport::from));
+            config
+                .cache_to
+                .extend(cache_to.into_iter().map(CacheExport::from));
+            for name in allow {
+                config = config.allow(Entitlement::parse(&name)?);
+            }
+            if let Some(path) = source_policy {
+                config = config.source_policy_file(path);
+            }
+            if let Some(secs) = timeout {
+                config = config.timeout(Duration::from_secs(secs));
+            }
+            if let Some(path) = log_file {
+                config = config.log_output(LogSink::file(path));
+            }
 
             if let (Some(host), Some(user), Some(pass)) =
                 (registry_host, registry_user, registry_password)
@@ -276,24 +956,222 @@ async fn main() -> Result<()> {
                     host,
                     username: user,
                     password: pass,
+                    ..Default::default()
                 });
             }
 
+            config = config.docker_credentials(!no_docker_config);
+
+            for host in insecure_registry {
+                config = config.insecure_registry(host, true);
+            }
+            for host in secure_registry {
+                config = config.insecure_registry(host, false);
+            }
+
             config = config.no_cache(no_cache).pull(pull);
 
-            let progress: Box<dyn buildkit_client::progress::ProgressHandler> = if json {
-                Box::new(JsonProgressHandler::new())
-            } else {
-                Box::new(ConsoleProgressHandler::new(cli.verbose))
-            };
+            if let Some(dir) = output_dir {
+                config = config.export_local(dir);
+            }
+            if let Some(path) = output_oci {
+                config = config.export_oci(path);
+            }
+            if let Some(path) = output_docker {
+                config = config.export_docker(path);
+            }
+            if load {
+                config = config.load_into_docker(DockerDaemon::from_env()?);
+            }
+            config = config.push(!no_push);
+
+            let progress = progress_handler(progress, cli.verbose)?;
+
+            let result = run_build(&mut client, config, progress, on_error).await?;
 
-            let result = client.build(config, Some(progress)).await?;
+            if let Some(path) = metadata_file {
+                result.write_metadata(path, MetadataFormat::Buildx)?;
+            }
+
+            if let Some(path) = graph {
+                write_graph(&result, &path)?;
+            }
+
+            if timing {
+                print_timings(&result);
+            }
 
             if let Some(digest) = result.digest {
                 println!("\n📦 Image digest: {}", digest);
             }
+            if !result.platforms.is_empty() {
+                let platforms: Vec<String> =
+                    result.platforms.iter().map(|p| p.to_string()).collect();
+                println!("🧩 Platforms: {}", platforms.join(", "));
+            }
         }
 
+        Commands::Batch {
+            file,
+            concurrency,
+            progress,
+            summary_file,
+        } => {
+            let manifest: BatchManifest = serde_yaml::from_str(&std::fs::read_to_string(&file)?)?;
+            let base_dir = file.parent().unwrap_or(Path::new("."));
+            let builds = manifest.build_configs(base_dir)?;
+            let concurrency = concurrency
+                .or(manifest.concurrency)
+                .unwrap_or(DEFAULT_BATCH_CONCURRENCY);
+
+            let progress = progress_handler(progress, cli.verbose)?;
+
+            let results = client
+                .build_batch(builds, concurrency, Some(progress))
+                .await?;
+
+            let summary = serde_json::to_string_pretty(&BatchResult::summary_json(&results))?;
+            match summary_file {
+                Some(path) => std::fs::write(path, summary)?,
+                None => println!("{}", summary),
+            }
+
+            let failed = results.iter().filter(|r| r.result.is_err()).count();
+            if failed > 0 {
+                anyhow::bail!("{} of {} builds failed", failed, results.len());
+            }
+        }
+
+        Commands::Bake {
+            file,
+            targets,
+            set,
+            concurrency,
+            progress,
+            summary_file,
+            ..
+        } => {
+            let builds = resolve_bake(&file, &targets, &set)?
+                .into_iter()
+                .map(|r| Ok((r.name, r.target.build_config()?)))
+                .collect::<buildkit_client::Result<Vec<_>>>()?;
+            let concurrency = concurrency.unwrap_or(DEFAULT_BATCH_CONCURRENCY);
+
+            let progress = progress_handler(progress, cli.verbose)?;
+
+            let results = client
+                .build_batch(builds, concurrency, Some(progress))
+                .await?;
+
+            let summary = serde_json::to_string_pretty(&BatchResult::summary_json(&results))?;
+            match summary_file {
+                Some(path) => std::fs::write(path, summary)?,
+                None => println!("{}", summary),
+            }
+
+            let failed = results.iter().filter(|r| r.result.is_err()).count();
+            if failed > 0 {
+                anyhow::bail!("{} of {} targets failed", failed, results.len());
+            }
+        }
+
+        #[cfg(feature = "serve")]
+        Commands::Serve {
+            listen,
+            context_root,
+            max_concurrent,
+        } => {
+            let listener = tokio::net::TcpListener::bind(&listen).await?;
+            buildkit_client::server::BuildServer::new(client, context_root)
+                .max_concurrent(max_concurrent)
+                .serve(listener)
+                .await?;
+        }
+
+        Commands::Prune {
+            keep_storage,
+            filter,
+            all,
+        } => {
+            let mut options = PruneOptions::new().all(all);
+            options.filters = filter;
+            if let Some(size) = keep_storage {
+                options = options.keep_bytes(parse_bytes(&size)?);
+            }
+
+            let mut freed = std::pin::pin!(client.prune(options).await?);
+            let mut total = 0;
+            while let Some(record) = freed.next().await {
+                let record = record?;
+                println!(
+                    "{:<28} {:>10}  {}",
+                    record.id,
+                    format_bytes(record.size as f64),
+                    record.description
+                );
+                total += record.size;
+            }
+            println!("🧹 Reclaimed {}", format_bytes(total as f64));
+        }
+
+        Commands::Du { filter, json } => {
+            let records = client.disk_usage(&filter[..]).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&usage_json(&records))?);
+            } else {
+                print_usage(&records);
+            }
+        }
+
+        Commands::Workers { filter, json } => {
+            let workers = client.list_workers(&filter[..]).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&workers_json(&workers))?);
+            } else {
+                print_workers(&workers);
+            }
+        }
+
+        Commands::History { command } => match command {
+            HistoryCommand::List { filter, json } => {
+                let records = client.build_history(&filter[..]).await?;
+                if json {
+                    let records: Vec<_> = records.iter().map(history::record_json).collect();
+                    println!("{}", serde_json::to_string_pretty(&records)?);
+                } else {
+                    print_history(&records);
+                }
+            }
+            HistoryCommand::Inspect {
+                build_ref,
+                json,
+                provenance,
+                trace,
+            } => {
+                let record = client.build_history_record(&build_ref).await?;
+                if json {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&history::record_json(&record))?
+                    );
+                } else {
+                    print_history_record(&record);
+                }
+                if provenance {
+                    for statement in client.build_provenance(&record).await? {
+                        println!("{}", serde_json::to_string_pretty(&statement)?);
+                    }
+                }
+                if let Some(path) = trace {
+                    let Some(otlp) = client.build_trace(&record).await? else {
+                        anyhow::bail!("build {} has no trace", build_ref);
+                    };
+                    std::fs::write(&path, otlp)?;
+                    println!("📈 Trace written to {}", path.display());
+                }
+            }
+        },
+
         Commands::Health => {
             client.health_check().await?;
             println!("✅ BuildKit is healthy");
@@ -302,3 +1180,490 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Targets of a bake file selected by name, with variables set from
+/// `NAME=VALUE` assignments
+fn resolve_bake(file: &Path, targets: &[String], set: &[String]) -> Result<Vec<ResolvedTarget>> {
+    // YAML is a superset of the JSON form
+    let mut bake: BakeFile = serde_yaml::from_str(&std::fs::read_to_string(file)?)?;
+    for assignment in set {
+        let (name, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("expected NAME=VALUE, got {}", assignment))?;
+        bake = bake.set(name, value);
+    }
+    let names: Vec<&str> = targets.iter().map(String::as_str).collect();
+    Ok(bake.resolve_targets(&names)?)
+}
+
+/// Progress handler for a `--progress` mode
+fn progress_handler(mode: ProgressMode, verbose: bool) -> Result<Box<dyn ProgressHandler>> {
+    Ok(match mode {
+        ProgressMode::Auto | ProgressMode::Tty => {
+            let handler = TtyProgressHandler::new().show_internal(verbose);
+            match (handler.is_interactive(), mode) {
+                (true, _) => Box::new(handler),
+                (false, ProgressMode::Tty) => {
+                    anyhow::bail!("--progress tty needs stdout to be a terminal")
+                }
+                (false, _) => Box::new(ConsoleProgressHandler::new(verbose).show_internal(verbose)),
+            }
+        }
+        ProgressMode::Plain => Box::new(PlainProgressHandler::new()),
+        ProgressMode::Json => Box::new(JsonProgressHandler::new()),
+    })
+}
+
+/// Run a build, opening a debug shell on a failed step if requested
+async fn run_build(
+    client: &mut BuildKitClient,
+    config: BuildConfig,
+    progress: Box<dyn ProgressHandler>,
+    on_error: OnError,
+) -> Result<BuildResult> {
+    let result = match on_error {
+        OnError::Fail => client.build(config, Some(progress)).await?,
+        OnError::Debug => {
+            client
+                .debug_build(config, Some(progress), |container| async move {
+                    eprintln!("\n🐛 Step failed: {}", container.error());
+                    eprintln!("   Command: {}", container.command().join(" "));
+                    eprintln!("   Opening a shell in {}; exit to finish", container.cwd());
+                    let code = container.shell().await?;
+                    eprintln!("🐛 Debug shell exited with code {}", code);
+                    Ok(())
+                })
+                .await?
+        }
+    };
+    Ok(result)
+}
+
+/// Write the build graph, as Mermaid for `.mmd`/`.mermaid` files and DOT
+/// otherwise
+fn write_graph(result: &BuildResult, path: &Path) -> Result<()> {
+    let graph = match path.extension().and_then(|e| e.to_str()) {
+        Some("mmd" | "mermaid") => result.report.to_mermaid(),
+        _ => result.report.to_dot(),
+    };
+    std::fs::write(path, graph)?;
+    println!("📈 Build graph written to {}", path.display());
+    Ok(())
+}
+
+/// Print the steps of a build, slowest first, with their share of the
+/// build's wall-clock time
+fn print_timings(result: &BuildResult) {
+    let total = result.report.duration();
+    println!("\n{:>9}  {:>6}  STEP", "DURATION", "SHARE");
+    for step in result.step_timings() {
+        let duration = match step.duration {
+            Some(duration) => format!("{:.1}s", duration.as_secs_f64()),
+            None if step.cached => "cached".to_string(),
+            None => "-".to_string(),
+        };
+        let share = match (step.duration, total) {
+            (Some(duration), Some(total)) if !total.is_zero() => {
+                format!(
+                    "{:.0}%",
+                    100.0 * duration.as_secs_f64() / total.as_secs_f64()
+                )
+            }
+            _ => String::new(),
+        };
+        println!("{:>9}  {:>6}  {}", duration, share, step.name);
+    }
+    if let Some(total) = total {
+        println!(
+            "{:>9}  {:>6}  total",
+            format!("{:.1}s", total.as_secs_f64()),
+            ""
+        );
+    }
+}
+
+/// Print workers with their platforms, labels and GC policy
+fn print_workers(workers: &[WorkerRecord]) {
+    for (i, worker) in workers.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let platforms: Vec<String> = worker
+            .platforms
+            .iter()
+            .map(|p| Platform::from(p).to_string())
+            .collect();
+        println!("ID:        {}", worker.id);
+        println!("Platforms: {}", platforms.join(", "));
+        if let Some(version) = &worker.buildkit_version {
+            println!("BuildKit:  {} {}", version.version, version.revision);
+        }
+
+        let mut labels: Vec<_> = worker.labels.iter().collect();
+        labels.sort();
+        println!("Labels:");
+        for (key, value) in labels {
+            println!("  {}: {}", key, value);
+        }
+
+        println!("GC Policy:");
+        for policy in &worker.gc_policy {
+            let mut rule = Vec::new();
+            if policy.all {
+                rule.push("all records".to_string());
+            }
+            if policy.keep_duration > 0 {
+                rule.push(format!(
+                    "keep {:?}",
+                    Duration::from_nanos(policy.keep_duration as u64)
+                ));
+            }
+            if !policy.filters.is_empty() {
+                rule.push(format!("matching {}", policy.filters.join(",")));
+            }
+            for (name, bytes) in [
+                ("reserved", policy.reserved_space),
+                ("max used", policy.max_used_space),
+                ("min free", policy.min_free_space),
+            ] {
+                if bytes > 0 {
+                    rule.push(format!("{} {}", name, format_bytes(bytes as f64)));
+                }
+            }
+            println!("  {}", rule.join(", "));
+        }
+    }
+}
+
+/// Print history records as a table
+fn print_history(records: &[BuildHistoryRecord]) {
+    println!(
+        "{:<28} {:<10} {:<16} {:>9}  STEPS",
+        "REF", "STATUS", "CREATED", "DURATION"
+    );
+    for record in records {
+        let created = history::created_at(record).map_or_else(|| "-".to_string(), ago);
+        let duration = history::duration(record)
+            .map_or_else(|| "-".to_string(), |d| format!("{:.1}s", d.as_secs_f64()));
+        println!(
+            "{:<28} {:<10} {:<16} {:>9}  {}/{} ({} cached)",
+            record.r#ref,
+            history::status(record),
+            created,
+            duration,
+            record.num_completed_steps,
+            record.num_total_steps,
+            record.num_cached_steps
+        );
+    }
+}
+
+/// Print the details of a history record
+fn print_history_record(record: &BuildHistoryRecord) {
+    println!("Ref:       {}", record.r#ref);
+    println!("Status:    {}", history::status(record));
+    if let Some(error) = &record.error {
+        if error.code != 0 {
+            println!("Error:     {}", error.message);
+        }
+    }
+    if let Some(created) = history::created_at(record) {
+        println!("Created:   {}", ago(created));
+    }
+    if let Some(duration) = history::duration(record) {
+        println!("Duration:  {:.1}s", duration.as_secs_f64());
+    }
+    println!(
+        "Steps:     {}/{} ({} cached)",
+        record.num_completed_steps, record.num_total_steps, record.num_cached_steps
+    );
+    if record.num_warnings > 0 {
+        println!("Warnings:  {}", record.num_warnings);
+    }
+    println!("Pinned:    {}", record.pinned);
+    println!("Frontend:  {}", record.frontend);
+
+    let mut attrs: Vec<_> = record.frontend_attrs.iter().collect();
+    attrs.sort();
+    for (key, value) in attrs {
+        println!("  {}: {}", key, value);
+    }
+    for exporter in &record.exporters {
+        println!("Exporter:  {}", exporter.r#type);
+    }
+    if let Some(digest) = record.exporter_response.get("containerimage.digest") {
+        println!("Digest:    {}", digest);
+    }
+    if let Some(trace) = &record.trace {
+        println!("Trace:     {}", trace.digest);
+    }
+    for provenance in history::provenance_descriptors(record) {
+        println!("Provenance: {}", provenance.digest);
+    }
+}
+
+/// Print cache records as a table, largest first
+fn print_usage(records: &[UsageRecord]) {
+    let mut records: Vec<&UsageRecord> = records.iter().collect();
+    records.sort_by_key(|r| std::cmp::Reverse(r.size));
+
+    println!(
+        "{:<28} {:>10}  {:<14} DESCRIPTION",
+        "ID", "SIZE", "LAST USED"
+    );
+    for record in &records {
+        let id = if record.mutable {
+            format!("{}*", record.id)
+        } else {
+            record.id.clone()
+        };
+        println!(
+            "{:<28} {:>10}  {:<14} {}",
+            id,
+            format_bytes(record.size as f64),
+            last_used(record),
+            record.description
+        );
+    }
+    let total: i64 = records.iter().map(|r| r.size).sum();
+    println!("Total: {}", format_bytes(total as f64));
+}
+
+/// How long ago a cache record was last used, e.g. `3 hours ago`
+fn last_used(record: &UsageRecord) -> String {
+    if record.in_use {
+        return "in use".to_string();
+    }
+    match record
+        .last_used_at
+        .and_then(|t| SystemTime::try_from(t).ok())
+    {
+        Some(time) => ago(time),
+        None => "never".to_string(),
+    }
+}
+
+/// How long ago a point in time was, e.g. `3 hours ago`
+fn ago(time: SystemTime) -> String {
+    let age = time.elapsed().unwrap_or_default();
+    let (value, unit) = match age.as_secs() {
+        s if s < 60 => return "just now".to_string(),
+        s if s < 3600 => (s / 60, "minute"),
+        s if s < 86400 => (s / 3600, "hour"),
+        s => (s / 86400, "day"),
+    };
+    format!(
+        "{} {}{} ago",
+        value,
+        unit,
+        if value == 1 { "" } else { "s" }
+    )
+}
+
+/// Build a structured report for an error returned by the CLI
+fn error_report(error: &anyhow::Error) -> ErrorReport {
+    if let Some(e) = error.downcast_ref::<buildkit_client::Error>() {
+        return e.to_report();
+    }
+
+    let chain: Vec<String> = error.chain().map(|e| e.to_string()).collect();
+    // A build failure wrapped in CLI context still points at its step
+    let source_location = error
+        .chain()
+        .find_map(|e| e.downcast_ref::<buildkit_client::Error>())
+        .and_then(|e| e.to_report().source_location);
+    ErrorReport {
+        kind: "cli",
+        message: error.to_string(),
+        chain,
+        step: None,
+        digest: None,
+        exit_code: None,
+        logs: Vec::new(),
+        source_location,
+        session_id: None,
+        retryable: false,
+        hints: Vec::new(),
+    }
+}
+
+/// Validate and normalize image tags before starting a build
+///
+/// Catches invalid names up front instead of failing at push time, and
+/// prints the fully-qualified names that will be pushed to stderr, keeping
+/// stdout for machine-readable output.
+fn normalize_tags(tags: &[String]) -> Result<Vec<String>> {
+    let normalized = tags
+        .iter()
+        .map(|t| Reference::parse(t).map(|r| r.to_string()))
+        .collect::<buildkit_client::Result<Vec<_>>>()?;
+
+    for name in &normalized {
+        eprintln!("🏷️  {}", name);
+    }
+
+    Ok(normalized)
+}
+
+/// Split an `--attest` value such as `type=provenance,mode=max` into the
+/// attestation type and its parameters
+fn parse_attest(spec: &str) -> Result<(String, String)> {
+    let mut kind = None;
+    let mut params = Vec::new();
+    for field in spec.split(',') {
+        match field.strip_prefix("type=") {
+            Some(t) => kind = Some(t.to_string()),
+            None => params.push(field),
+        }
+    }
+    let kind = kind.ok_or_else(|| anyhow::anyhow!("attestation without a type: {}", spec))?;
+    Ok((kind, params.join(",")))
+}
+
+/// Read the context from stdin into a temporary directory
+///
+/// A tar archive, plain or gzipped, is extracted into the directory;
+/// anything else is returned as the Dockerfile, with the directory left as
+/// an empty context.
+fn read_context_from_stdin() -> Result<(tempfile::TempDir, Option<Vec<u8>>)> {
+    let dir = tempfile::Builder::new()
+        .prefix("buildkit-context-")
+        .tempdir()?;
+    let dockerfile = read_context(std::io::stdin().lock(), dir.path())?;
+    Ok((dir, dockerfile))
+}
+
+/// Extract a context archive from `input` into `dir`, or return `input` as
+/// the Dockerfile if it is no archive
+///
+/// Only the first bytes are read to tell the two apart; archives are
+/// extracted as they are read.
+fn read_context(mut input: impl Read, dir: &Path) -> Result<Option<Vec<u8>>> {
+    let mut head = Vec::with_capacity(TAR_HEADER_SIZE);
+    (&mut input)
+        .take(TAR_HEADER_SIZE as u64)
+        .read_to_end(&mut head)?;
+    let format = ContextFormat::detect(&head);
+    let mut input = std::io::Cursor::new(head).chain(input);
+
+    match format {
+        // Like `docker build -`, anything but an archive is a Dockerfile
+        // built without a context
+        ContextFormat::Dockerfile => {
+            let mut data = Vec::new();
+            input.read_to_end(&mut data)?;
+            tracing::debug!("Read a Dockerfile from stdin, building with an empty context");
+            return Ok(Some(data));
+        }
+        ContextFormat::Tar => tar::Archive::new(input).unpack(dir)?,
+        ContextFormat::Gzip => {
+            tar::Archive::new(flate2::read::GzDecoder::new(input)).unpack(dir)?
+        }
+        ContextFormat::Unsupported(compression) => anyhow::bail!(
+            "{}-compressed contexts are not supported on stdin; decompress the archive first",
+            compression
+        ),
+    }
+    tracing::debug!("Extracted stdin context to {}", dir.display());
+    Ok(None)
+}
+
+/// Size of a tar header, which holds the `ustar` magic
+const TAR_HEADER_SIZE: usize = 512;
+
+/// What a context read from stdin is, by its first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContextFormat {
+    Tar,
+    Gzip,
+    /// Compressed archive that is not extracted, by compression name
+    Unsupported(&'static str),
+    Dockerfile,
+}
+
+impl ContextFormat {
+    fn detect(head: &[u8]) -> Self {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Self::Gzip
+        } else if head.starts_with(b"BZh") {
+            Self::Unsupported("bzip2")
+        } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::Unsupported("xz")
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Unsupported("zstd")
+        } else if head.get(257..262) == Some(b"ustar".as_slice()) {
+            Self::Tar
+        } else {
+            Self::Dockerfile
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn context_tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let dockerfile = b"FROM scratch\n";
+        let mut header = tar::Header::new_ustar();
+        header.set_size(dockerfile.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "Dockerfile", dockerfile.as_slice())
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn plain_tar_contexts_are_extracted() {
+        let dir = tempfile::tempdir().unwrap();
+        let dockerfile = read_context(context_tar().as_slice(), dir.path()).unwrap();
+        assert!(dockerfile.is_none());
+        assert_eq!(
+            std::fs::read(dir.path().join("Dockerfile")).unwrap(),
+            b"FROM scratch\n"
+        );
+    }
+
+    #[test]
+    fn gzipped_tar_contexts_are_extracted() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&context_tar()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let dockerfile = read_context(gzipped.as_slice(), dir.path()).unwrap();
+        assert!(dockerfile.is_none());
+        assert!(dir.path().join("Dockerfile").exists());
+    }
+
+    #[test]
+    fn other_input_is_a_dockerfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = b"FROM alpine\nRUN echo hello\n";
+        let dockerfile = read_context(input.as_slice(), dir.path()).unwrap();
+        assert_eq!(dockerfile.as_deref(), Some(input.as_slice()));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let error = read_context(b"BZh91AY&SY".as_slice(), dir.path()).unwrap_err();
+        assert!(error.to_string().contains("bzip2"));
+    }
+
+    #[test]
+    fn cli_errors_are_reported_with_their_chain() {
+        let error = anyhow::anyhow!("connection refused").context("failed to reach buildkitd");
+        let report = error_report(&error);
+        assert_eq!(report.kind, "cli");
+        assert_eq!(
+            report.chain,
+            ["failed to reach buildkitd", "connection refused"]
+        );
+        assert!(report.source_location.is_none());
+
+        let error = buildkit_client::Error::InvalidPlatform("linux".to_string());
+        let report = error_report(&anyhow::Error::new(error));
+        assert_eq!(report.kind, "invalid_platform");
+    }
+}
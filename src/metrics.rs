@@ -0,0 +1,298 @@
+//! Statistics of context uploads and metrics of a client's builds
+//!
+//! Every build records what its session sent BuildKit from the local
+//! context in [`BuildResult::context_stats`](crate::BuildResult). A
+//! [`Metrics`] registry attached with
+//! [`BuildKitClient::with_metrics`](crate::BuildKitClient::with_metrics)
+//! adds up the builds of a client and renders them in the Prometheus text
+//! format, to monitor context upload performance of a service.
+//!
+//! # Example
+//! ```no_run
+//! use buildkit_client::metrics::Metrics;
+//! use buildkit_client::{BuildConfig, BuildKitClient};
+//! use std::sync::Arc;
+//!
+//! #[tokio::main]
+//! async fn main() -> anyhow::Result<()> {
+//!     let metrics = Arc::new(Metrics::new());
+//!     let client = BuildKitClient::connect("http://localhost:1234")
+//!         .await?
+//!         .with_metrics(metrics.clone());
+//!
+//!     let result = client.build(BuildConfig::local("./my-app"), None).await?;
+//!     let stats = result.context_stats;
+//!     println!("{} files, {} bytes in {:?}", stats.files_sent, stats.bytes_sent, stats.duration);
+//!     print!("{}", metrics.to_prometheus());
+//!     Ok(())
+//! }
+//! ```
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds in seconds of the context upload duration histogram
+const UPLOAD_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// What a build's session sent BuildKit from local directories
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContextStats {
+    /// Files whose contents BuildKit requested and received
+    pub files_sent: u64,
+    /// Bytes of file contents sent
+    pub bytes_sent: u64,
+    /// Time spent in transfers, summed over the directories BuildKit read
+    pub duration: Duration,
+    /// Paths left out by `.dockerignore`, exclude patterns or a context
+    /// filter
+    pub skipped: u64,
+}
+
+/// Counters of the transfers of one file sync service, shared by its clones
+#[derive(Debug, Default)]
+pub(crate) struct TransferStats {
+    files_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    nanos: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl TransferStats {
+    /// Count the data of a file; empty data ends the file
+    pub(crate) fn data_sent(&self, len: usize) {
+        if len == 0 {
+            self.files_sent.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn paths_skipped(&self, count: u64) {
+        self.skipped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn transfer_finished(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> ContextStats {
+        ContextStats {
+            files_sent: self.files_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            duration: Duration::from_nanos(self.nanos.load(Ordering::Relaxed)),
+            skipped: self.skipped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl std::ops::Add for ContextStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            files_sent: self.files_sent + other.files_sent,
+            bytes_sent: self.bytes_sent + other.bytes_sent,
+            duration: self.duration + other.duration,
+            skipped: self.skipped + other.skipped,
+        }
+    }
+}
+
+/// Totals of the builds of a client, see the [module docs](self)
+#[derive(Debug, Default)]
+pub struct Metrics {
+    builds_succeeded: AtomicU64,
+    builds_failed: AtomicU64,
+    files_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    skipped: AtomicU64,
+    /// Uploads per bucket of [`UPLOAD_BUCKETS`], not cumulative
+    upload_buckets: [AtomicU64; UPLOAD_BUCKETS.len()],
+    /// Uploads longer than the last bucket
+    uploads_over: AtomicU64,
+    upload_nanos: AtomicU64,
+}
+
+impl Metrics {
+    /// Create a registry with every counter at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a finished build
+    pub fn record_build(&self, succeeded: bool) {
+        let counter = if succeeded {
+            &self.builds_succeeded
+        } else {
+            &self.builds_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add the context upload of a build
+    ///
+    /// Builds that sent nothing, e.g. of remote contexts, are not counted
+    /// as uploads.
+    pub fn record_context(&self, stats: &ContextStats) {
+        if *stats == ContextStats::default() {
+            return;
+        }
+        self.files_sent
+            .fetch_add(stats.files_sent, Ordering::Relaxed);
+        self.bytes_sent
+            .fetch_add(stats.bytes_sent, Ordering::Relaxed);
+        self.skipped.fetch_add(stats.skipped, Ordering::Relaxed);
+        self.upload_nanos
+            .fetch_add(stats.duration.as_nanos() as u64, Ordering::Relaxed);
+
+        let secs = stats.duration.as_secs_f64();
+        match UPLOAD_BUCKETS.iter().position(|&bound| secs <= bound) {
+            Some(bucket) => self.upload_buckets[bucket].fetch_add(1, Ordering::Relaxed),
+            None => self.uploads_over.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Context statistics of every recorded build added up
+    pub fn context_totals(&self) -> ContextStats {
+        ContextStats {
+            files_sent: self.files_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            duration: Duration::from_nanos(self.upload_nanos.load(Ordering::Relaxed)),
+            skipped: self.skipped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Render the metrics in the Prometheus text exposition format
+    ///
+    /// Counters are named `buildkit_client_*`; context upload durations
+    /// are a histogram in seconds.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        let _ = writeln!(
+            out,
+            "# HELP buildkit_client_builds_total Builds finished, by result."
+        );
+        let _ = writeln!(out, "# TYPE buildkit_client_builds_total counter");
+        let _ = writeln!(
+            out,
+            "buildkit_client_builds_total{{result=\"success\"}} {}",
+            load(&self.builds_succeeded)
+        );
+        let _ = writeln!(
+            out,
+            "buildkit_client_builds_total{{result=\"failure\"}} {}",
+            load(&self.builds_failed)
+        );
+
+        for (name, help, counter) in [
+            (
+                "context_files_sent_total",
+                "Context files sent to BuildKit.",
+                &self.files_sent,
+            ),
+            (
+                "context_bytes_sent_total",
+                "Bytes of context files sent to BuildKit.",
+                &self.bytes_sent,
+            ),
+            (
+                "context_paths_skipped_total",
+                "Context paths left out of uploads.",
+                &self.skipped,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP buildkit_client_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE buildkit_client_{} counter", name);
+            let _ = writeln!(out, "buildkit_client_{} {}", name, load(counter));
+        }
+
+        let name = "buildkit_client_context_upload_duration_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} Time builds spent uploading their context.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, bucket) in UPLOAD_BUCKETS.iter().zip(&self.upload_buckets) {
+            cumulative += load(bucket);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        cumulative += load(&self.uploads_over);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative);
+        let _ = writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            Duration::from_nanos(load(&self.upload_nanos)).as_secs_f64()
+        );
+        let _ = writeln!(out, "{}_count {}", name, cumulative);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfers_are_counted_per_file() {
+        let stats = TransferStats::default();
+        stats.data_sent(4096);
+        stats.data_sent(100);
+        stats.data_sent(0);
+        stats.data_sent(0);
+        stats.paths_skipped(1);
+        stats.transfer_finished(Duration::from_millis(250));
+
+        assert_eq!(
+            stats.stats(),
+            ContextStats {
+                files_sent: 2,
+                bytes_sent: 4196,
+                duration: Duration::from_millis(250),
+                skipped: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn prometheus_histogram_is_cumulative() {
+        let metrics = Metrics::new();
+        metrics.record_build(true);
+        metrics.record_build(false);
+        for millis in [50, 700, 90_000] {
+            metrics.record_context(&ContextStats {
+                files_sent: 1,
+                bytes_sent: 10,
+                duration: Duration::from_millis(millis),
+                skipped: 0,
+            });
+        }
+        // Builds that uploaded nothing are not uploads
+        metrics.record_context(&ContextStats::default());
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("buildkit_client_builds_total{result=\"success\"} 1\n"));
+        assert!(text.contains("buildkit_client_builds_total{result=\"failure\"} 1\n"));
+        assert!(text.contains("buildkit_client_context_files_sent_total 3\n"));
+        assert!(text.contains("buildkit_client_context_bytes_sent_total 30\n"));
+        assert!(
+            text.contains("buildkit_client_context_upload_duration_seconds_bucket{le=\"0.1\"} 1\n")
+        );
+        assert!(
+            text.contains("buildkit_client_context_upload_duration_seconds_bucket{le=\"1\"} 2\n")
+        );
+        assert!(
+            text.contains("buildkit_client_context_upload_duration_seconds_bucket{le=\"60\"} 2\n")
+        );
+        assert!(text
+            .contains("buildkit_client_context_upload_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("buildkit_client_context_upload_duration_seconds_count 3\n"));
+        assert_eq!(metrics.context_totals().files_sent, 3);
+    }
+}
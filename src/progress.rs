@@ -1,7 +1,24 @@
 //! Build progress monitoring and reporting
 
-use crate::error::Result;
-use crate::proto::moby::buildkit::v1::StatusResponse;
+use crate::error::{Error, Result, SolveErrorDetails, StepFailure};
+use crate::proto::moby::buildkit::v1::{StatusResponse, VertexStatus};
+use crate::report::{BuildReport, BuildWarning, VertexReport};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::SystemTime;
+
+mod logfile;
+mod plain;
+mod tty;
+
+pub(crate) use logfile::BuildLog;
+pub use plain::PlainProgressHandler;
+pub use tty::TtyProgressHandler;
+
+/// Number of trailing log lines kept per vertex for failure reports
+const LOG_TAIL_LINES: usize = 50;
+
+/// Minimum time between transfer speed reports of one step, in seconds
+const TRANSFER_REPORT_INTERVAL: f64 = 1.0;
 
 /// Trait for handling build progress updates
 pub trait ProgressHandler: Send {
@@ -16,17 +33,152 @@ pub trait ProgressHandler: Send {
 
     /// Called when an error occurs
     fn on_error(&mut self, error: &str) -> Result<()>;
+
+    /// Called for each warning BuildKit reports, e.g. a Dockerfile lint,
+    /// after the status update carrying it
+    fn on_warning(&mut self, _warning: &BuildWarning) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<H: ProgressHandler + ?Sized> ProgressHandler for Box<H> {
+    fn on_start(&mut self) -> Result<()> {
+        (**self).on_start()
+    }
+
+    fn on_status(&mut self, status: StatusResponse) -> Result<()> {
+        (**self).on_status(status)
+    }
+
+    fn on_complete(&mut self) -> Result<()> {
+        (**self).on_complete()
+    }
+
+    fn on_error(&mut self, error: &str) -> Result<()> {
+        (**self).on_error(error)
+    }
+
+    fn on_warning(&mut self, warning: &BuildWarning) -> Result<()> {
+        (**self).on_warning(warning)
+    }
+}
+
+/// Progress handler whose methods may await, e.g. to POST each update to
+/// a web service
+///
+/// Status updates keep being read from BuildKit while the handler awaits,
+/// and are delivered in order once it is ready. Every [`ProgressHandler`]
+/// is also an `AsyncProgressHandler`.
+///
+/// # Example
+/// ```no_run
+/// use buildkit_client::error::Result;
+/// use buildkit_client::progress::AsyncProgressHandler;
+/// use buildkit_client::proto::moby::buildkit::v1::StatusResponse;
+///
+/// struct StepCounter {
+///     steps: usize,
+/// }
+///
+/// #[tonic::async_trait]
+/// impl AsyncProgressHandler for StepCounter {
+///     async fn on_start(&mut self) -> Result<()> {
+///         Ok(())
+///     }
+///
+///     async fn on_status(&mut self, status: StatusResponse) -> Result<()> {
+///         self.steps += status.vertexes.iter().filter(|v| v.completed.is_some()).count();
+///         Ok(())
+///     }
+///
+///     async fn on_complete(&mut self) -> Result<()> {
+///         Ok(())
+///     }
+///
+///     async fn on_error(&mut self, _error: &str) -> Result<()> {
+///         Ok(())
+///     }
+/// }
+/// ```
+#[tonic::async_trait]
+pub trait AsyncProgressHandler: Send {
+    /// Called when the build starts
+    async fn on_start(&mut self) -> Result<()>;
+
+    /// Called for each status update
+    async fn on_status(&mut self, status: StatusResponse) -> Result<()>;
+
+    /// Called when the build completes successfully
+    async fn on_complete(&mut self) -> Result<()>;
+
+    /// Called when an error occurs
+    async fn on_error(&mut self, error: &str) -> Result<()>;
+
+    /// Called for each warning BuildKit reports, after the status update
+    /// carrying it
+    async fn on_warning(&mut self, _warning: &BuildWarning) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl<H: ProgressHandler + ?Sized> AsyncProgressHandler for H {
+    async fn on_start(&mut self) -> Result<()> {
+        ProgressHandler::on_start(self)
+    }
+
+    async fn on_status(&mut self, status: StatusResponse) -> Result<()> {
+        ProgressHandler::on_status(self, status)
+    }
+
+    async fn on_complete(&mut self) -> Result<()> {
+        ProgressHandler::on_complete(self)
+    }
+
+    async fn on_error(&mut self, error: &str) -> Result<()> {
+        ProgressHandler::on_error(self, error)
+    }
+
+    async fn on_warning(&mut self, warning: &BuildWarning) -> Result<()> {
+        ProgressHandler::on_warning(self, warning)
+    }
 }
 
 /// Console progress handler that prints to stdout
+///
+/// Steps BuildKit runs once per platform (`[linux/amd64 2/3] RUN make`,
+/// `[linux/arm64 2/3] RUN make`) are shown as one line with the status of
+/// each platform. Internal steps (`[internal] load build definition`) are
+/// hidden unless enabled with [`show_internal`](Self::show_internal).
+///
+/// Layer pulls, pushes and cache exports report the bytes transferred and
+/// the current speed about once a second, so stalled registries show up
+/// as `0 B/s`.
 pub struct ConsoleProgressHandler {
     verbose: bool,
+    show_internal: bool,
+    steps: HashMap<String, StepGroup>,
+    /// Names of the vertexes being shown, by digest
+    names: HashMap<String, String>,
+    transfers: TransferTracker,
 }
 
 impl ConsoleProgressHandler {
     /// Create a new console progress handler
     pub fn new(verbose: bool) -> Self {
-        Self { verbose }
+        Self {
+            verbose,
+            show_internal: false,
+            steps: HashMap::new(),
+            names: HashMap::new(),
+            transfers: TransferTracker::default(),
+        }
+    }
+
+    /// Also show BuildKit's internal steps
+    pub fn show_internal(mut self, show: bool) -> Self {
+        self.show_internal = show;
+        self
     }
 }
 
@@ -38,10 +190,28 @@ impl ProgressHandler for ConsoleProgressHandler {
 
     fn on_status(&mut self, status: StatusResponse) -> Result<()> {
         for vertex in status.vertexes {
-            if vertex.completed.is_some() {
-                println!("✅ {}", vertex.name);
+            let step = StepName::parse(&vertex.name);
+            if step.internal && !self.show_internal {
+                continue;
+            }
+            self.names
+                .insert(vertex.digest.clone(), vertex.name.clone());
+
+            let state = if !vertex.error.is_empty() {
+                StepState::Failed
+            } else if vertex.completed.is_some() {
+                StepState::Done
             } else if vertex.started.is_some() {
-                println!("⏳ {}...", vertex.name);
+                StepState::Running
+            } else {
+                StepState::Waiting
+            };
+            let group = self
+                .steps
+                .entry(step.name.clone())
+                .or_insert_with(|| StepGroup::new(step.name));
+            if let Some(line) = group.update(step.platform, state) {
+                println!("{}", line);
             }
 
             if self.verbose && !vertex.cached {
@@ -50,6 +220,15 @@ impl ProgressHandler for ConsoleProgressHandler {
             }
         }
 
+        for vertex_status in &status.statuses {
+            let Some(name) = self.names.get(&vertex_status.vertex) else {
+                continue;
+            };
+            if let Some(progress) = self.transfers.observe(vertex_status) {
+                println!("   {}: {}", name, progress);
+            }
+        }
+
         // Show logs
         for log in status.logs {
             if self.verbose {
@@ -71,6 +250,237 @@ impl ProgressHandler for ConsoleProgressHandler {
         eprintln!("❌ Build failed: {}", error);
         Ok(())
     }
+
+    fn on_warning(&mut self, warning: &BuildWarning) -> Result<()> {
+        println!("⚠️  {}", warning);
+        Ok(())
+    }
+}
+
+/// A vertex name split into its platform and the platform-independent rest
+#[derive(Debug, PartialEq, Eq)]
+struct StepName {
+    /// Name without the platform, e.g. `[2/3] RUN make`
+    name: String,
+    /// Platform from the name prefix, e.g. `linux/amd64`
+    platform: Option<String>,
+    /// Whether this is one of BuildKit's internal steps
+    internal: bool,
+}
+
+impl StepName {
+    /// Split a vertex name like `[linux/arm64 builder 2/3] RUN make`
+    fn parse(name: &str) -> Self {
+        let prefix = name.strip_prefix('[').and_then(|rest| rest.split_once(']'));
+        let Some((prefix, rest)) = prefix else {
+            return Self {
+                name: name.to_string(),
+                platform: None,
+                internal: false,
+            };
+        };
+
+        let mut tokens: Vec<&str> = prefix.split_whitespace().collect();
+        // Step counters like `2/3` also contain a slash, platforms start
+        // with the OS name
+        let platform = match tokens.first() {
+            Some(first)
+                if first.contains('/') && first.starts_with(|c: char| c.is_ascii_alphabetic()) =>
+            {
+                Some(tokens.remove(0).to_string())
+            }
+            _ => None,
+        };
+        let internal = tokens.contains(&"internal");
+
+        Self {
+            name: format!("[{}]{}", tokens.join(" "), rest),
+            platform,
+            internal,
+        }
+    }
+}
+
+/// Status of one step on one platform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepState {
+    Waiting,
+    Running,
+    Done,
+    Failed,
+}
+
+impl StepState {
+    fn label(self) -> &'static str {
+        match self {
+            StepState::Waiting => "waiting",
+            StepState::Running => "running",
+            StepState::Done => "done",
+            StepState::Failed => "failed",
+        }
+    }
+}
+
+/// A step and its status on each platform it runs for
+#[derive(Debug)]
+struct StepGroup {
+    name: String,
+    /// State of the step if it runs without a platform
+    state: Option<StepState>,
+    platforms: BTreeMap<String, StepState>,
+    /// Last line printed, to skip updates that change nothing
+    printed: Option<String>,
+}
+
+impl StepGroup {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            state: None,
+            platforms: BTreeMap::new(),
+            printed: None,
+        }
+    }
+
+    /// Record the state of one platform, returning the line to print if
+    /// the step's rendering changed
+    fn update(&mut self, platform: Option<String>, state: StepState) -> Option<String> {
+        match platform {
+            Some(platform) => {
+                self.platforms.insert(platform, state);
+            }
+            None => self.state = Some(state),
+        }
+        let line = self.render()?;
+        if self.printed.as_ref() == Some(&line) {
+            return None;
+        }
+        self.printed = Some(line.clone());
+        Some(line)
+    }
+
+    /// Render the step, or `None` while no platform has started it
+    fn render(&self) -> Option<String> {
+        let mut states = self.state.iter().chain(self.platforms.values());
+        let (icon, suffix) = if states.clone().any(|s| *s == StepState::Failed) {
+            ("❌", "")
+        } else if states.clone().all(|s| *s == StepState::Done) {
+            ("✅", "")
+        } else if states.any(|s| *s == StepState::Running) {
+            ("⏳", "...")
+        } else {
+            return None;
+        };
+
+        if self.platforms.is_empty() {
+            return Some(format!("{} {}{}", icon, self.name, suffix));
+        }
+        let platforms: Vec<String> = if icon == "✅" {
+            self.platforms.keys().cloned().collect()
+        } else {
+            self.platforms
+                .iter()
+                .map(|(platform, state)| format!("{}: {}", platform, state.label()))
+                .collect()
+        };
+        Some(format!(
+            "{} {}{} ({})",
+            icon,
+            self.name,
+            suffix,
+            platforms.join(", ")
+        ))
+    }
+}
+
+/// Bytes transferred by one step and the speed since the last report
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TransferProgress {
+    current: i64,
+    /// Sum of the known sizes, zero if none are known
+    total: i64,
+    bytes_per_second: f64,
+}
+
+impl std::fmt::Display for TransferProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format_bytes(self.current as f64))?;
+        if self.total > 0 {
+            write!(f, " / {}", format_bytes(self.total as f64))?;
+        }
+        write!(f, " ({}/s)", format_bytes(self.bytes_per_second))
+    }
+}
+
+/// Tracks the byte counts BuildKit reports for layer pulls, pushes and
+/// cache exports to derive transfer speeds
+#[derive(Debug, Default)]
+struct TransferTracker {
+    vertexes: HashMap<String, VertexTransfers>,
+}
+
+#[derive(Debug, Default)]
+struct VertexTransfers {
+    /// Current and total bytes by status ID
+    transfers: HashMap<String, (i64, i64)>,
+    /// Time and byte count of the last report
+    reported: Option<(f64, i64)>,
+}
+
+impl TransferTracker {
+    /// Record a status update, returning the step's progress when a new
+    /// report is due
+    fn observe(&mut self, status: &VertexStatus) -> Option<TransferProgress> {
+        // Byte transfers are keyed by content digest (`sha256:...`,
+        // `layer-sha256:...`, `writing layer sha256:...`); extraction
+        // reports no byte counts
+        if !status.id.contains("sha256:") || status.id.starts_with("extracting") {
+            return None;
+        }
+        let now = status
+            .timestamp
+            .as_ref()
+            .map(|t| t.seconds as f64 + t.nanos as f64 / 1e9)?;
+
+        let vertex = self.vertexes.entry(status.vertex.clone()).or_default();
+        vertex
+            .transfers
+            .insert(status.id.clone(), (status.current, status.total));
+        let current: i64 = vertex.transfers.values().map(|(current, _)| current).sum();
+        let total: i64 = vertex.transfers.values().map(|(_, total)| total).sum();
+
+        let Some((reported_at, reported_bytes)) = vertex.reported else {
+            vertex.reported = Some((now, current));
+            return None;
+        };
+        let elapsed = now - reported_at;
+        if elapsed < TRANSFER_REPORT_INTERVAL {
+            return None;
+        }
+        vertex.reported = Some((now, current));
+
+        Some(TransferProgress {
+            current,
+            total,
+            bytes_per_second: (current - reported_bytes).max(0) as f64 / elapsed,
+        })
+    }
+}
+
+/// Format a byte count with a decimal unit, e.g. `12.3 MB`
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", value as i64, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
 }
 
 /// JSON progress handler that outputs structured JSON
@@ -90,26 +500,7 @@ impl ProgressHandler for JsonProgressHandler {
     }
 
     fn on_status(&mut self, status: StatusResponse) -> Result<()> {
-        let json = serde_json::json!({
-            "vertexes": status.vertexes.iter().map(|v| {
-                serde_json::json!({
-                    "digest": v.digest,
-                    "name": v.name,
-                    "cached": v.cached,
-                    "started": v.started.as_ref().map(|t| t.seconds),
-                    "completed": v.completed.as_ref().map(|t| t.seconds),
-                    "error": v.error,
-                })
-            }).collect::<Vec<_>>(),
-            "statuses": status.statuses.iter().map(|s| {
-                serde_json::json!({
-                    "vertex": s.vertex,
-                    "current": s.current,
-                    "total": s.total,
-                    "timestamp": s.timestamp.as_ref().map(|t| t.seconds),
-                })
-            }).collect::<Vec<_>>(),
-        });
+        let json = status_json(&status);
 
         match serde_json::to_string(&json) {
             Ok(s) => println!("{}", s),
@@ -134,6 +525,45 @@ impl ProgressHandler for JsonProgressHandler {
         }
         Ok(())
     }
+
+    fn on_warning(&mut self, warning: &BuildWarning) -> Result<()> {
+        let json = serde_json::json!({
+            "warning": warning.message,
+            "vertex": warning.vertex,
+            "file": warning.file,
+            "line": warning.line,
+            "url": warning.url,
+        });
+        match serde_json::to_string(&json) {
+            Ok(s) => println!("{}", s),
+            Err(e) => tracing::error!("Failed to serialize warning JSON: {}", e),
+        }
+        Ok(())
+    }
+}
+
+/// JSON representation of a status update, as emitted by [`JsonProgressHandler`]
+pub(crate) fn status_json(status: &StatusResponse) -> serde_json::Value {
+    serde_json::json!({
+        "vertexes": status.vertexes.iter().map(|v| {
+            serde_json::json!({
+                "digest": v.digest,
+                "name": v.name,
+                "cached": v.cached,
+                "started": v.started.as_ref().map(|t| t.seconds),
+                "completed": v.completed.as_ref().map(|t| t.seconds),
+                "error": v.error,
+            })
+        }).collect::<Vec<_>>(),
+        "statuses": status.statuses.iter().map(|s| {
+            serde_json::json!({
+                "vertex": s.vertex,
+                "current": s.current,
+                "total": s.total,
+                "timestamp": s.timestamp.as_ref().map(|t| t.seconds),
+            })
+        }).collect::<Vec<_>>(),
+    })
 }
 
 /// Silent progress handler that doesn't output anything
@@ -163,3 +593,566 @@ impl ProgressHandler for SilentProgressHandler {
         Ok(())
     }
 }
+
+/// Per-vertex state collected from the status stream
+#[derive(Debug, Default)]
+struct VertexState {
+    name: String,
+    inputs: Vec<String>,
+    cached: bool,
+    started: Option<prost_types::Timestamp>,
+    completed: Option<prost_types::Timestamp>,
+    error: Option<String>,
+    log_tail: VecDeque<String>,
+    partial_line: String,
+}
+
+impl VertexState {
+    fn push_log(&mut self, msg: &[u8]) {
+        self.partial_line.push_str(&String::from_utf8_lossy(msg));
+
+        while let Some(idx) = self.partial_line.find('\n') {
+            let line = self.partial_line[..idx].trim_end_matches('\r').to_string();
+            self.partial_line.drain(..=idx);
+            self.log_tail.push_back(line);
+            if self.log_tail.len() > LOG_TAIL_LINES {
+                self.log_tail.pop_front();
+            }
+        }
+    }
+
+    fn log_tail(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.log_tail.iter().cloned().collect();
+        if !self.partial_line.is_empty() {
+            lines.push(self.partial_line.clone());
+        }
+        lines
+    }
+}
+
+/// Tracks vertex state from the status stream so build failures can be
+/// reported with the failing step and its log output, and the build graph
+/// with its timings
+#[derive(Debug, Default)]
+pub(crate) struct StatusTracker {
+    vertexes: HashMap<String, VertexState>,
+    /// Digests of reported vertexes, in the order first seen
+    order: Vec<String>,
+    /// Digests of vertexes that reported an error, in the order seen
+    failed: Vec<String>,
+    /// Warnings, in the order first reported
+    warnings: Vec<BuildWarning>,
+}
+
+impl StatusTracker {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a status update, returning the warnings it reported for the
+    /// first time
+    pub(crate) fn observe(&mut self, status: &StatusResponse) -> Vec<BuildWarning> {
+        for vertex in &status.vertexes {
+            if !self.order.contains(&vertex.digest) {
+                self.order.push(vertex.digest.clone());
+            }
+            let state = self.vertexes.entry(vertex.digest.clone()).or_default();
+            if !vertex.name.is_empty() {
+                state.name = vertex.name.clone();
+            }
+            if !vertex.inputs.is_empty() {
+                state.inputs = vertex.inputs.clone();
+            }
+            state.cached |= vertex.cached;
+            state.started = vertex.started.or(state.started);
+            state.completed = vertex.completed.or(state.completed);
+            if !vertex.error.is_empty() && state.error.is_none() {
+                state.error = Some(vertex.error.clone());
+                self.failed.push(vertex.digest.clone());
+            }
+        }
+
+        for log in &status.logs {
+            self.vertexes
+                .entry(log.vertex.clone())
+                .or_default()
+                .push_log(&log.msg);
+        }
+
+        // BuildKit repeats warnings when the status stream is resumed
+        let mut new_warnings = Vec::new();
+        for warning in &status.warnings {
+            let warning = BuildWarning::from(warning);
+            if !self.warnings.contains(&warning) {
+                self.warnings.push(warning.clone());
+                new_warnings.push(warning);
+            }
+        }
+        new_warnings
+    }
+
+    /// Warnings reported so far
+    pub(crate) fn warnings(&self) -> Vec<BuildWarning> {
+        self.warnings.clone()
+    }
+
+    /// The vertexes seen so far, with their inputs and timings
+    pub(crate) fn report(&self) -> BuildReport {
+        let time = |t: Option<prost_types::Timestamp>| t.and_then(|t| SystemTime::try_from(t).ok());
+        BuildReport {
+            vertexes: self
+                .order
+                .iter()
+                .map(|digest| {
+                    let state = &self.vertexes[digest];
+                    VertexReport {
+                        digest: digest.clone(),
+                        name: state.name.clone(),
+                        inputs: state.inputs.clone(),
+                        cached: state.cached,
+                        started: time(state.started),
+                        completed: time(state.completed),
+                        error: state.error.clone(),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Build a step failure error for the vertex that caused the build to fail
+    ///
+    /// Vertexes that were merely cancelled because another step failed are
+    /// skipped. The failed vertex, Dockerfile lines and stack trace BuildKit
+    /// attached to the solve's error `status` are used when present. Returns
+    /// `None` if no vertex reported an error.
+    pub(crate) fn failure(
+        &self,
+        session_id: &str,
+        status: Option<&tonic::Status>,
+    ) -> Option<Error> {
+        let details = status.map(SolveErrorDetails::decode).unwrap_or_default();
+        let digest = details
+            .vertex
+            .as_ref()
+            .filter(|d| self.vertexes.contains_key(*d))
+            .or_else(|| {
+                self.failed.iter().find(|d| {
+                    self.vertexes[*d]
+                        .error
+                        .as_deref()
+                        .is_some_and(|e| !is_cancellation(e))
+                })
+            })
+            .or_else(|| self.failed.first())?;
+
+        let state = &self.vertexes[digest];
+        let message = match (&state.error, status) {
+            (Some(error), _) => error.clone(),
+            (None, Some(status)) => status.message().to_string(),
+            (None, None) => String::new(),
+        };
+
+        Some(Error::BuildStepFailed(Box::new(StepFailure {
+            step: state.name.clone(),
+            digest: digest.clone(),
+            exit_code: parse_exit_code(&message),
+            message,
+            logs: state.log_tail(),
+            session_id: Some(session_id.to_string()),
+            source_location: details.source_location,
+            stack: details.stack,
+        })))
+    }
+}
+
+/// Whether a vertex error only reports cancellation caused by another failure
+fn is_cancellation(error: &str) -> bool {
+    error.contains("context canceled") || error.contains("context cancelled")
+}
+
+/// Extract the process exit code from a BuildKit vertex error message
+///
+/// BuildKit reports failed RUN steps as
+/// `process "/bin/sh -c ..." did not complete successfully: exit code: 1`.
+fn parse_exit_code(error: &str) -> Option<i32> {
+    let (_, rest) = error.rsplit_once("exit code: ")?;
+    let digits: String = rest
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '-')
+        .collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::moby::buildkit::v1::{Vertex, VertexLog, VertexWarning};
+    use crate::proto::pb::{Position, Range, SourceInfo};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn vertex(digest: &str, name: &str, error: &str) -> Vertex {
+        Vertex {
+            digest: digest.to_string(),
+            inputs: vec![],
+            name: name.to_string(),
+            cached: false,
+            started: None,
+            completed: None,
+            error: error.to_string(),
+            progress_group: None,
+        }
+    }
+
+    fn log(digest: &str, msg: &str) -> VertexLog {
+        VertexLog {
+            vertex: digest.to_string(),
+            timestamp: None,
+            stream: 2,
+            msg: msg.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn failure_reports_failing_vertex_with_log_tail() {
+        let mut tracker = StatusTracker::new();
+        tracker.observe(&StatusResponse {
+            vertexes: vec![vertex("sha256:a", "[1/2] RUN make", "")],
+            statuses: vec![],
+            logs: vec![
+                log("sha256:a", "compiling...\nerror: missing ;"),
+                log("sha256:a", "\n"),
+            ],
+            warnings: vec![],
+        });
+        tracker.observe(&StatusResponse {
+            vertexes: vec![
+                vertex("sha256:b", "[2/2] RUN other", "context canceled"),
+                vertex(
+                    "sha256:a",
+                    "[1/2] RUN make",
+                    "process \"/bin/sh -c make\" did not complete successfully: exit code: 2",
+                ),
+            ],
+            statuses: vec![],
+            logs: vec![],
+            warnings: vec![],
+        });
+
+        match tracker.failure("session", None) {
+            Some(Error::BuildStepFailed(failure)) => {
+                assert_eq!(failure.step, "[1/2] RUN make");
+                assert_eq!(failure.digest, "sha256:a");
+                assert_eq!(failure.exit_code, Some(2));
+                assert_eq!(failure.logs, vec!["compiling...", "error: missing ;"]);
+            }
+            other => panic!("unexpected failure: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failure_uses_solve_error_details() {
+        use crate::proto::errdefs::{Source, Vertex as VertexInfo};
+        use crate::proto::google::rpc::Status as RpcStatus;
+        use crate::proto::stack::{Frame, Stack};
+        use prost::Message;
+
+        let mut tracker = StatusTracker::new();
+        tracker.observe(&StatusResponse {
+            vertexes: vec![
+                vertex("sha256:a", "[1/3] RUN setup", "exit code: 1"),
+                vertex("sha256:b", "[2/3] RUN make", "exit code: 2"),
+            ],
+            statuses: vec![],
+            logs: vec![],
+            warnings: vec![],
+        });
+
+        let any = |type_url: &str, value: Vec<u8>| prost_types::Any {
+            type_url: format!("type.googleapis.com/{}", type_url),
+            value,
+        };
+        let position = |line| Some(Position { line, character: 0 });
+        let details = RpcStatus {
+            code: tonic::Code::Unknown as i32,
+            message: "exit code: 2".to_string(),
+            details: vec![
+                any(
+                    "errdefs.Vertex",
+                    VertexInfo {
+                        digest: "sha256:b".to_string(),
+                    }
+                    .encode_to_vec(),
+                ),
+                any(
+                    "errdefs.Source",
+                    Source {
+                        info: Some(SourceInfo {
+                            filename: "Dockerfile".to_string(),
+                            data: b"FROM alpine\nRUN setup\nRUN make \\\n  all\n".to_vec(),
+                            ..Default::default()
+                        }),
+                        ranges: vec![Range {
+                            start: position(3),
+                            end: position(4),
+                        }],
+                    }
+                    .encode_to_vec(),
+                ),
+                any(
+                    "stack.Stack",
+                    Stack {
+                        frames: vec![Frame {
+                            name: "main.run".to_string(),
+                            file: "main.go".to_string(),
+                            line: 12,
+                        }],
+                        ..Default::default()
+                    }
+                    .encode_to_vec(),
+                ),
+            ],
+        };
+        let status = tonic::Status::with_details(
+            tonic::Code::Unknown,
+            "exit code: 2",
+            details.encode_to_vec().into(),
+        );
+
+        let error = tracker.failure("session", Some(&status)).unwrap();
+        let Error::BuildStepFailed(failure) = &error else {
+            panic!("unexpected failure: {:?}", error);
+        };
+        assert_eq!(failure.step, "[2/3] RUN make");
+        assert_eq!(failure.exit_code, Some(2));
+        let location = failure.source_location.as_ref().unwrap();
+        assert_eq!(location.to_string(), "Dockerfile:3-4");
+        assert_eq!(location.lines, vec!["RUN make \\", "  all"]);
+        assert_eq!(failure.stack, vec!["main.run (main.go:12)"]);
+        assert!(error.to_string().contains("   3 | RUN make"));
+    }
+
+    #[test]
+    fn report_keeps_graph_and_timings() {
+        let mut tracker = StatusTracker::new();
+        let mut base = vertex("sha256:a", "[1/2] FROM alpine", "");
+        base.cached = true;
+        let mut run = vertex("sha256:b", "[2/2] RUN make", "");
+        run.inputs = vec!["sha256:a".to_string()];
+        run.started = Some(prost_types::Timestamp {
+            seconds: 10,
+            nanos: 0,
+        });
+        tracker.observe(&StatusResponse {
+            vertexes: vec![base, run.clone()],
+            statuses: vec![],
+            logs: vec![],
+            warnings: vec![],
+        });
+        // Later updates may omit fields reported earlier
+        run.inputs.clear();
+        run.completed = Some(prost_types::Timestamp {
+            seconds: 12,
+            nanos: 0,
+        });
+        tracker.observe(&StatusResponse {
+            vertexes: vec![run],
+            statuses: vec![],
+            logs: vec![],
+            warnings: vec![],
+        });
+
+        let report = tracker.report();
+        let digests: Vec<&str> = report.vertexes.iter().map(|v| v.digest.as_str()).collect();
+        assert_eq!(digests, vec!["sha256:a", "sha256:b"]);
+        assert!(report.vertexes[0].cached);
+        assert_eq!(report.vertexes[1].inputs, vec!["sha256:a"]);
+        assert_eq!(
+            report.vertexes[1].duration(),
+            Some(std::time::Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn log_tail_is_bounded() {
+        let mut state = VertexState::default();
+        for i in 0..(LOG_TAIL_LINES + 10) {
+            state.push_log(format!("line {}\n", i).as_bytes());
+        }
+        let tail = state.log_tail();
+        assert_eq!(tail.len(), LOG_TAIL_LINES);
+        assert_eq!(tail[0], "line 10");
+    }
+
+    #[test]
+    fn no_failure_without_vertex_errors() {
+        let mut tracker = StatusTracker::new();
+        tracker.observe(&StatusResponse {
+            vertexes: vec![vertex("sha256:a", "step", "")],
+            statuses: vec![],
+            logs: vec![],
+            warnings: vec![],
+        });
+        assert!(tracker.failure("session", None).is_none());
+    }
+
+    #[test]
+    fn step_names_split_platform_and_internal() {
+        assert_eq!(
+            StepName::parse("[linux/arm64 builder 2/3] RUN make"),
+            StepName {
+                name: "[builder 2/3] RUN make".to_string(),
+                platform: Some("linux/arm64".to_string()),
+                internal: false,
+            }
+        );
+        assert_eq!(StepName::parse("[2/3] RUN make").platform, None);
+        assert!(StepName::parse("[internal] load build definition").internal);
+        assert!(StepName::parse("[linux/amd64 internal] load metadata").internal);
+        assert_eq!(
+            StepName::parse("exporting to image").name,
+            "exporting to image"
+        );
+    }
+
+    #[test]
+    fn platform_steps_render_as_one_line() {
+        let mut group = StepGroup::new("[2/3] RUN make".to_string());
+        assert_eq!(
+            group.update(Some("linux/amd64".to_string()), StepState::Running),
+            Some("⏳ [2/3] RUN make... (linux/amd64: running)".to_string())
+        );
+        assert_eq!(
+            group.update(Some("linux/arm64".to_string()), StepState::Waiting),
+            Some("⏳ [2/3] RUN make... (linux/amd64: running, linux/arm64: waiting)".to_string())
+        );
+        assert_eq!(
+            group.update(Some("linux/arm64".to_string()), StepState::Waiting),
+            None
+        );
+        group.update(Some("linux/amd64".to_string()), StepState::Done);
+        assert_eq!(
+            group.update(Some("linux/arm64".to_string()), StepState::Done),
+            Some("✅ [2/3] RUN make (linux/amd64, linux/arm64)".to_string())
+        );
+    }
+
+    fn transfer(id: &str, current: i64, total: i64, seconds: i64) -> VertexStatus {
+        VertexStatus {
+            id: id.to_string(),
+            vertex: "sha256:pull".to_string(),
+            name: String::new(),
+            current,
+            total,
+            timestamp: Some(prost_types::Timestamp { seconds, nanos: 0 }),
+            started: None,
+            completed: None,
+        }
+    }
+
+    #[test]
+    fn transfer_speed_is_reported_per_step() {
+        let mut tracker = TransferTracker::default();
+        assert_eq!(
+            tracker.observe(&transfer("sha256:a", 0, 4_000_000, 10)),
+            None
+        );
+        assert_eq!(
+            tracker.observe(&transfer("sha256:b", 0, 6_000_000, 10)),
+            None
+        );
+        assert_eq!(
+            tracker.observe(&transfer("sha256:a", 3_000_000, 4_000_000, 12)),
+            Some(TransferProgress {
+                current: 3_000_000,
+                total: 10_000_000,
+                bytes_per_second: 1_500_000.0,
+            })
+        );
+        // A stalled transfer keeps reporting the same byte count
+        let stalled = tracker
+            .observe(&transfer("sha256:a", 3_000_000, 4_000_000, 14))
+            .unwrap();
+        assert_eq!(stalled.to_string(), "3.0 MB / 10.0 MB (0 B/s)");
+
+        assert_eq!(
+            tracker.observe(&transfer("extracting sha256:a", 1, 0, 20)),
+            None
+        );
+    }
+
+    #[test]
+    fn bytes_use_decimal_units() {
+        assert_eq!(format_bytes(512.0), "512 B");
+        assert_eq!(format_bytes(1_500_000.0), "1.5 MB");
+        assert_eq!(format_bytes(2_340_000_000.0), "2.3 GB");
+    }
+
+    struct Counter(Arc<AtomicUsize>);
+
+    impl ProgressHandler for Counter {
+        fn on_start(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn on_status(&mut self, _status: StatusResponse) -> Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn on_complete(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn on_error(&mut self, _error: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn sync_handlers_are_async_handlers() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let sync: Box<dyn ProgressHandler> = Box::new(Counter(count.clone()));
+        let mut handler: Box<dyn AsyncProgressHandler> = Box::new(sync);
+        handler.on_status(StatusResponse::default()).await.unwrap();
+        handler.on_status(StatusResponse::default()).await.unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn warnings_are_collected_once() {
+        let warning = VertexWarning {
+            vertex: "sha256:a".to_string(),
+            level: 1,
+            short: b"JSONArgsRecommended: JSON arguments recommended for CMD\n".to_vec(),
+            url: "https://docs.docker.com/go/dockerfile/rule/json-args-recommended/".to_string(),
+            info: Some(SourceInfo {
+                filename: "Dockerfile".to_string(),
+                ..Default::default()
+            }),
+            ranges: vec![Range {
+                start: Some(Position {
+                    line: 7,
+                    character: 0,
+                }),
+                end: None,
+            }],
+            ..Default::default()
+        };
+        let status = StatusResponse {
+            warnings: vec![warning],
+            ..Default::default()
+        };
+
+        let mut tracker = StatusTracker::new();
+        let new = tracker.observe(&status);
+        assert_eq!(new.len(), 1);
+        assert_eq!(
+            new[0].to_string(),
+            "Dockerfile:7: JSONArgsRecommended: JSON arguments recommended for CMD"
+        );
+        // A resumed stream repeats the warning
+        assert!(tracker.observe(&status).is_empty());
+        assert_eq!(tracker.warnings(), new);
+    }
+}
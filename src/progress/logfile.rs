@@ -0,0 +1,136 @@
+//! Build logs written alongside the progress handler
+
+use super::{PlainProgressHandler, ProgressHandler};
+use crate::builder::LogSink;
+use crate::error::{Error, Result};
+use crate::proto::moby::buildkit::v1::StatusResponse;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Log of a build written to a [`LogSink`], in the format of
+/// [`PlainProgressHandler`]
+///
+/// Failing to write only stops the log; the build goes on.
+pub(crate) struct BuildLog {
+    out: Output,
+    plain: PlainProgressHandler,
+    buffer: SharedBuffer,
+    failed: bool,
+}
+
+enum Output {
+    File(tokio::fs::File),
+    Writer(Arc<tokio::sync::Mutex<dyn AsyncWrite + Send + Unpin>>),
+}
+
+/// Lines rendered by the plain handler, waiting to be written
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl BuildLog {
+    /// Open the log of a build, creating its file
+    pub(crate) async fn open(sink: &LogSink) -> Result<Self> {
+        let out = match sink {
+            LogSink::File(path) => Output::File(
+                tokio::fs::File::create(path)
+                    .await
+                    .map_err(|e| Error::file_operation("create", path, e))?,
+            ),
+            LogSink::Writer(writer) => Output::Writer(writer.clone()),
+        };
+        let buffer = SharedBuffer::default();
+        Ok(Self {
+            out,
+            plain: PlainProgressHandler::with_writer(Box::new(buffer.clone())),
+            buffer,
+            failed: false,
+        })
+    }
+
+    /// Write the steps and logs of a status update
+    pub(crate) async fn record(&mut self, status: &StatusResponse) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.plain.on_status(status.clone()) {
+            tracing::warn!("Failed to render build log: {}", e);
+            return;
+        }
+        let pending = std::mem::take(&mut *self.buffer.0.lock().unwrap_or_else(|e| e.into_inner()));
+        if pending.is_empty() {
+            return;
+        }
+        if let Err(e) = self.write(&pending).await {
+            tracing::warn!("Failed to write build log, stopping it: {}", e);
+            self.failed = true;
+        }
+    }
+
+    async fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match &mut self.out {
+            Output::File(file) => {
+                file.write_all(data).await?;
+                file.flush().await
+            }
+            Output::Writer(writer) => {
+                let mut writer = writer.lock().await;
+                writer.write_all(data).await?;
+                writer.flush().await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::moby::buildkit::v1::{Vertex, VertexLog};
+
+    #[tokio::test]
+    async fn logs_are_written_to_the_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.log");
+        let mut log = BuildLog::open(&LogSink::file(&path)).await.unwrap();
+
+        log.record(&StatusResponse {
+            vertexes: vec![Vertex {
+                digest: "sha256:run".to_string(),
+                name: "[2/2] RUN make".to_string(),
+                started: Some(prost_types::Timestamp {
+                    seconds: 100,
+                    nanos: 0,
+                }),
+                ..Default::default()
+            }],
+            logs: vec![VertexLog {
+                vertex: "sha256:run".to_string(),
+                timestamp: Some(prost_types::Timestamp {
+                    seconds: 100,
+                    nanos: 500_000_000,
+                }),
+                msg: b"cc -c main.c\n".to_vec(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+        .await;
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, "#1 [2/2] RUN make\n#1 0.500 cc -c main.c\n");
+    }
+}
@@ -0,0 +1,307 @@
+//! Line-based progress output like `--progress=plain`
+
+use super::{format_bytes, ProgressHandler};
+use crate::error::Result;
+use crate::proto::moby::buildkit::v1::StatusResponse;
+use crate::report::BuildWarning;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+/// Progress handler printing every step and all of its logs, like
+/// `docker buildx build --progress=plain`
+///
+/// Steps are numbered in the order BuildKit reports them. Each line is
+/// prefixed with its step's number, and log lines with the time since the
+/// step started:
+///
+/// ```text
+/// #5 [2/3] RUN make
+/// #5 0.412 cc -c main.c
+/// #5 DONE 1.3s
+/// ```
+///
+/// A step is printed once however often BuildKit repeats it, e.g. when
+/// several builds or platforms share it.
+pub struct PlainProgressHandler {
+    out: Box<dyn Write + Send>,
+    steps: HashMap<String, PlainStep>,
+    /// Transfers and other sub-statuses already reported as done
+    statuses: HashSet<(String, String)>,
+    /// Number of the step that printed the last line
+    last: Option<usize>,
+}
+
+#[derive(Debug)]
+struct PlainStep {
+    number: usize,
+    name: String,
+    started: Option<SystemTime>,
+    announced: bool,
+    finished: bool,
+    partial_line: String,
+    /// Time of the last log output since the step started
+    log_time: Duration,
+}
+
+impl PlainStep {
+    /// Add log output, returning the lines it completed
+    fn push_log(&mut self, msg: &[u8]) -> Vec<String> {
+        self.partial_line.push_str(&String::from_utf8_lossy(msg));
+        let mut lines = Vec::new();
+        while let Some(idx) = self.partial_line.find('\n') {
+            let line = self.partial_line[..idx].trim_end_matches('\r').to_string();
+            self.partial_line.drain(..=idx);
+            lines.push(self.log_line(&line));
+        }
+        lines
+    }
+
+    /// The unterminated last line of the logs, once the step finished
+    fn flush_log(&mut self) -> Option<String> {
+        if self.partial_line.is_empty() {
+            return None;
+        }
+        let line = std::mem::take(&mut self.partial_line);
+        Some(self.log_line(&line))
+    }
+
+    fn log_line(&self, line: &str) -> String {
+        format!("{:.3} {}", self.log_time.as_secs_f64(), line)
+    }
+}
+
+impl PlainProgressHandler {
+    /// Create a handler printing to stdout
+    pub fn new() -> Self {
+        Self::with_writer(Box::new(std::io::stdout()))
+    }
+
+    pub(super) fn with_writer(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            out,
+            steps: HashMap::new(),
+            statuses: HashSet::new(),
+            last: None,
+        }
+    }
+
+    /// Print a line of a step, separating steps by a blank line
+    fn line(&mut self, number: usize, text: &str) -> Result<()> {
+        if self.last.is_some_and(|last| last != number) {
+            writeln!(self.out)?;
+        }
+        self.last = Some(number);
+        writeln!(self.out, "#{} {}", number, text)?;
+        Ok(())
+    }
+
+    /// A step, numbered when it is first seen
+    fn step(&mut self, digest: &str) -> &mut PlainStep {
+        let next = self.steps.len() + 1;
+        self.steps
+            .entry(digest.to_string())
+            .or_insert_with(|| PlainStep {
+                number: next,
+                name: String::new(),
+                started: None,
+                announced: false,
+                finished: false,
+                partial_line: String::new(),
+                log_time: Duration::ZERO,
+            })
+    }
+}
+
+impl Default for PlainProgressHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressHandler for PlainProgressHandler {
+    fn on_start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_status(&mut self, status: StatusResponse) -> Result<()> {
+        let time =
+            |t: &Option<prost_types::Timestamp>| (*t).and_then(|t| SystemTime::try_from(t).ok());
+        let since = |at: Option<SystemTime>, start: Option<SystemTime>| {
+            at.zip(start)
+                .and_then(|(at, start)| at.duration_since(start).ok())
+                .unwrap_or(Duration::ZERO)
+        };
+
+        for vertex in &status.vertexes {
+            let step = self.step(&vertex.digest);
+            if !vertex.name.is_empty() {
+                step.name = vertex.name.clone();
+            }
+            step.started = step.started.or(time(&vertex.started));
+            if step.finished {
+                continue;
+            }
+
+            let mut lines = Vec::new();
+            if !step.announced && (step.started.is_some() || vertex.cached) {
+                step.announced = true;
+                lines.push(step.name.clone());
+            }
+            if !step.announced {
+                continue;
+            }
+            let outcome = if !vertex.error.is_empty() {
+                Some(format!("ERROR: {}", vertex.error))
+            } else if vertex.cached {
+                Some("CACHED".to_string())
+            } else {
+                time(&vertex.completed).map(|completed| {
+                    format!(
+                        "DONE {:.1}s",
+                        since(Some(completed), step.started).as_secs_f64()
+                    )
+                })
+            };
+            if let Some(outcome) = outcome {
+                step.finished = true;
+                lines.extend(step.flush_log());
+                lines.push(outcome);
+            }
+
+            let number = step.number;
+            for line in lines {
+                self.line(number, &line)?;
+            }
+        }
+
+        for vertex_status in &status.statuses {
+            if vertex_status.completed.is_none()
+                || !self
+                    .statuses
+                    .insert((vertex_status.vertex.clone(), vertex_status.id.clone()))
+            {
+                continue;
+            }
+            let text = if vertex_status.total > 0 {
+                format!(
+                    "{} {} / {} done",
+                    vertex_status.id,
+                    format_bytes(vertex_status.current as f64),
+                    format_bytes(vertex_status.total as f64)
+                )
+            } else {
+                format!("{} done", vertex_status.id)
+            };
+            let number = self.step(&vertex_status.vertex).number;
+            self.line(number, &text)?;
+        }
+
+        for log in &status.logs {
+            let step = self.step(&log.vertex);
+            step.log_time = since(time(&log.timestamp), step.started);
+            let lines = step.push_log(&log.msg);
+            let number = step.number;
+            for line in lines {
+                self.line(number, &line)?;
+            }
+        }
+
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn on_complete(&mut self) -> Result<()> {
+        writeln!(self.out)?;
+        writeln!(self.out, "✨ Build completed successfully!")?;
+        Ok(())
+    }
+
+    fn on_error(&mut self, error: &str) -> Result<()> {
+        eprintln!("\n❌ Build failed: {}", error);
+        Ok(())
+    }
+
+    fn on_warning(&mut self, warning: &BuildWarning) -> Result<()> {
+        let number = self.step(&warning.vertex).number;
+        self.line(number, &format!("WARN: {}", warning))?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::moby::buildkit::v1::{Vertex, VertexLog};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn timestamp(seconds: i64, nanos: i32) -> Option<prost_types::Timestamp> {
+        Some(prost_types::Timestamp { seconds, nanos })
+    }
+
+    #[test]
+    fn steps_are_numbered_with_their_logs() {
+        let buffer = SharedBuffer::default();
+        let mut handler = PlainProgressHandler::with_writer(Box::new(buffer.clone()));
+
+        let load = Vertex {
+            digest: "sha256:load".to_string(),
+            name: "[internal] load build definition".to_string(),
+            cached: true,
+            ..Default::default()
+        };
+        let run = Vertex {
+            digest: "sha256:run".to_string(),
+            name: "[2/3] RUN make".to_string(),
+            started: timestamp(100, 0),
+            ..Default::default()
+        };
+        handler
+            .on_status(StatusResponse {
+                vertexes: vec![load.clone(), run.clone()],
+                logs: vec![VertexLog {
+                    vertex: "sha256:run".to_string(),
+                    timestamp: timestamp(100, 412_000_000),
+                    msg: b"cc -c main.c\nld".to_vec(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+            .unwrap();
+        // Repeated vertexes print nothing new
+        handler
+            .on_status(StatusResponse {
+                vertexes: vec![
+                    load,
+                    Vertex {
+                        completed: timestamp(101, 300_000_000),
+                        ..run
+                    },
+                ],
+                ..Default::default()
+            })
+            .unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "#1 [internal] load build definition\n#1 CACHED\n\n\
+             #2 [2/3] RUN make\n#2 0.412 cc -c main.c\n#2 0.412 ld\n#2 DONE 1.3s\n"
+        );
+    }
+}
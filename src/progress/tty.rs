@@ -0,0 +1,459 @@
+//! In-place progress display for terminals
+
+use super::{ConsoleProgressHandler, ProgressHandler, StepName};
+use crate::error::Result;
+use crate::proto::moby::buildkit::v1::StatusResponse;
+use crate::report::BuildWarning;
+use std::collections::{HashMap, VecDeque};
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Frames of the spinner shown next to running steps
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+/// Log lines shown under each running step
+const LOG_LINES: usize = 6;
+
+/// Minimum time between redraws that only advance timers
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Terminal width assumed when `COLUMNS` is not set
+const DEFAULT_WIDTH: usize = 80;
+
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// Progress handler redrawing the running steps in place, like buildx
+///
+/// Finished steps are printed once with their duration, while running
+/// steps are shown below them with a spinner, their elapsed time and the
+/// tail of their logs. Failed steps keep their log tail.
+///
+/// When stdout is not a terminal, output falls back to
+/// [`ConsoleProgressHandler`].
+pub struct TtyProgressHandler {
+    inner: Output,
+}
+
+enum Output {
+    Tty(Box<TtyDisplay>),
+    Plain(ConsoleProgressHandler),
+}
+
+impl TtyProgressHandler {
+    /// Create a handler drawing on stdout if it is a terminal
+    pub fn new() -> Self {
+        if std::io::stdout().is_terminal() {
+            Self::with_writer(Box::new(std::io::stdout()), terminal_width())
+        } else {
+            Self {
+                inner: Output::Plain(ConsoleProgressHandler::new(false)),
+            }
+        }
+    }
+
+    fn with_writer(out: Box<dyn Write + Send>, width: usize) -> Self {
+        Self {
+            inner: Output::Tty(Box::new(TtyDisplay {
+                out,
+                width,
+                show_internal: false,
+                started: Instant::now(),
+                order: Vec::new(),
+                steps: HashMap::new(),
+                drawn: 0,
+                frame: 0,
+                last_draw: None,
+                warnings: Vec::new(),
+            })),
+        }
+    }
+
+    /// Also show BuildKit's internal steps
+    pub fn show_internal(mut self, show: bool) -> Self {
+        self.inner = match self.inner {
+            Output::Tty(mut display) => {
+                display.show_internal = show;
+                Output::Tty(display)
+            }
+            Output::Plain(console) => Output::Plain(console.show_internal(show)),
+        };
+        self
+    }
+
+    /// Whether progress is drawn in place rather than printed line by line
+    pub fn is_interactive(&self) -> bool {
+        matches!(self.inner, Output::Tty(_))
+    }
+}
+
+impl Default for TtyProgressHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressHandler for TtyProgressHandler {
+    fn on_start(&mut self) -> Result<()> {
+        match &mut self.inner {
+            Output::Tty(display) => {
+                display.started = Instant::now();
+                display.draw(None)
+            }
+            Output::Plain(console) => console.on_start(),
+        }
+    }
+
+    fn on_status(&mut self, status: StatusResponse) -> Result<()> {
+        match &mut self.inner {
+            Output::Tty(display) => {
+                let changed = display.observe(status);
+                let due = display
+                    .last_draw
+                    .is_none_or(|at| at.elapsed() >= REDRAW_INTERVAL);
+                if changed || due {
+                    display.draw(None)?;
+                }
+                Ok(())
+            }
+            Output::Plain(console) => console.on_status(status),
+        }
+    }
+
+    fn on_complete(&mut self) -> Result<()> {
+        match &mut self.inner {
+            Output::Tty(display) => {
+                display.draw(Some("FINISHED"))?;
+                display.print_warnings()
+            }
+            Output::Plain(console) => console.on_complete(),
+        }
+    }
+
+    fn on_error(&mut self, error: &str) -> Result<()> {
+        match &mut self.inner {
+            Output::Tty(display) => {
+                display.draw(Some("ERROR"))?;
+                display.print_warnings()?;
+                eprintln!("❌ Build failed: {}", error);
+                Ok(())
+            }
+            Output::Plain(console) => console.on_error(error),
+        }
+    }
+
+    fn on_warning(&mut self, warning: &BuildWarning) -> Result<()> {
+        match &mut self.inner {
+            Output::Tty(display) => {
+                display.warnings.push(warning.clone());
+                Ok(())
+            }
+            Output::Plain(console) => console.on_warning(warning),
+        }
+    }
+}
+
+/// Steps of a build and the live area last drawn for them
+struct TtyDisplay {
+    out: Box<dyn Write + Send>,
+    width: usize,
+    show_internal: bool,
+    started: Instant,
+    /// Digests of the steps, in the order first seen
+    order: Vec<String>,
+    steps: HashMap<String, TtyStep>,
+    /// Lines of the live area, cleared before the next draw
+    drawn: usize,
+    frame: usize,
+    last_draw: Option<Instant>,
+    /// Warnings, printed below the steps once the build is done
+    warnings: Vec<BuildWarning>,
+}
+
+#[derive(Debug, Default)]
+struct TtyStep {
+    name: String,
+    hidden: bool,
+    cached: bool,
+    started: Option<SystemTime>,
+    completed: Option<SystemTime>,
+    error: Option<String>,
+    logs: VecDeque<String>,
+    partial_line: String,
+    /// Whether the finished step has been printed above the live area
+    printed: bool,
+}
+
+impl TtyStep {
+    fn finished(&self) -> bool {
+        self.completed.is_some() || self.error.is_some()
+    }
+
+    fn duration(&self) -> Duration {
+        let Some(started) = self.started else {
+            return Duration::ZERO;
+        };
+        let end = self.completed.unwrap_or_else(SystemTime::now);
+        end.duration_since(started).unwrap_or_default()
+    }
+
+    fn push_log(&mut self, msg: &[u8]) {
+        self.partial_line.push_str(&String::from_utf8_lossy(msg));
+        while let Some(idx) = self.partial_line.find('\n') {
+            let line = self.partial_line[..idx].trim_end_matches('\r').to_string();
+            self.partial_line.drain(..=idx);
+            self.logs.push_back(line);
+            if self.logs.len() > LOG_LINES {
+                self.logs.pop_front();
+            }
+        }
+    }
+}
+
+impl TtyDisplay {
+    /// Print the warnings below the final draw
+    fn print_warnings(&mut self) -> Result<()> {
+        if self.warnings.is_empty() {
+            return Ok(());
+        }
+        let count = self.warnings.len();
+        writeln!(
+            self.out,
+            "\n{} warning{} found:",
+            count,
+            if count == 1 { "" } else { "s" }
+        )?;
+        for warning in &self.warnings {
+            writeln!(self.out, " - {}", warning)?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// Record a status update, returning whether a step started or finished
+    fn observe(&mut self, status: StatusResponse) -> bool {
+        let time = |t: Option<prost_types::Timestamp>| t.and_then(|t| SystemTime::try_from(t).ok());
+        let mut changed = false;
+        for vertex in status.vertexes {
+            if !self.steps.contains_key(&vertex.digest) {
+                self.order.push(vertex.digest.clone());
+            }
+            let step = self.steps.entry(vertex.digest).or_default();
+            if !vertex.name.is_empty() {
+                step.hidden = StepName::parse(&vertex.name).internal && !self.show_internal;
+                step.name = vertex.name;
+            }
+            let was = (step.started.is_some(), step.finished());
+            step.cached |= vertex.cached;
+            step.started = time(vertex.started).or(step.started);
+            step.completed = time(vertex.completed).or(step.completed);
+            if !vertex.error.is_empty() {
+                step.error = Some(vertex.error);
+            }
+            changed |= was != (step.started.is_some(), step.finished());
+        }
+        for log in status.logs {
+            if let Some(step) = self.steps.get_mut(&log.vertex) {
+                step.push_log(&log.msg);
+            }
+        }
+        changed
+    }
+
+    /// Replace the live area: print newly finished steps above it and
+    /// redraw the running ones, or end the display with `outcome`
+    fn draw(&mut self, outcome: Option<&str>) -> Result<()> {
+        let mut output = String::new();
+        if self.drawn > 0 {
+            output.push_str(&format!("\x1b[{}A\x1b[J", self.drawn));
+        }
+
+        for digest in &self.order {
+            let Some(step) = self.steps.get_mut(digest) else {
+                continue;
+            };
+            if step.hidden || step.printed || !step.finished() {
+                continue;
+            }
+            step.printed = true;
+            let label = match (&step.error, step.cached) {
+                (Some(_), _) => format!("=> ERROR {}", step.name),
+                (None, true) => format!("=> CACHED {}", step.name),
+                (None, false) => format!("=> {}", step.name),
+            };
+            let line = fit(&label, &seconds(step.duration()), self.width);
+            if step.error.is_some() {
+                output.push_str(&format!("{}{}{}\n", RED, line, RESET));
+                for log in &step.logs {
+                    let log = fit(&format!("=> => # {}", log), "", self.width);
+                    output.push_str(&format!("{}{}{}\n", RED, log, RESET));
+                }
+            } else {
+                output.push_str(&line);
+                output.push('\n');
+            }
+        }
+
+        let visible: Vec<&TtyStep> = self
+            .order
+            .iter()
+            .map(|digest| &self.steps[digest])
+            .filter(|step| !step.hidden)
+            .collect();
+        let done = visible.iter().filter(|step| step.finished()).count();
+        let mut header = format!(
+            "[+] Building {} ({}/{})",
+            seconds(self.started.elapsed()),
+            done,
+            visible.len()
+        );
+
+        let mut live = Vec::new();
+        if let Some(outcome) = outcome {
+            header.push(' ');
+            header.push_str(outcome);
+            output.push_str(&fit(&header, "", self.width));
+            output.push('\n');
+        } else {
+            live.push(fit(&header, "", self.width));
+            let spinner = SPINNER[self.frame % SPINNER.len()];
+            for step in visible
+                .iter()
+                .filter(|step| step.started.is_some() && !step.finished())
+            {
+                let label = format!("{} {}", spinner, step.name);
+                live.push(fit(&label, &seconds(step.duration()), self.width));
+                for log in &step.logs {
+                    live.push(fit(&format!("=> => # {}", log), "", self.width));
+                }
+            }
+        }
+        for line in &live {
+            output.push_str(line);
+            output.push('\n');
+        }
+
+        self.out.write_all(output.as_bytes())?;
+        self.out.flush()?;
+        self.drawn = live.len();
+        self.frame += 1;
+        self.last_draw = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// Width of the terminal from `COLUMNS`
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .filter(|&c| c > 0)
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// A duration as buildx shows it, e.g. `3.4s`
+fn seconds(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+/// Fit `text` and a right-aligned `suffix` into one line of `width`
+/// columns, so no line wraps and breaks redrawing
+fn fit(text: &str, suffix: &str, width: usize) -> String {
+    let room = width.saturating_sub(suffix.chars().count() + 1).max(1);
+    let text: String = text.chars().take(room).collect();
+    if suffix.is_empty() {
+        return text;
+    }
+    let padding = room - text.chars().count() + 1;
+    format!("{}{}{}", text, " ".repeat(padding), suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::moby::buildkit::v1::{Vertex, VertexLog};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn take(&self) -> String {
+            String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+        }
+    }
+
+    fn timestamp(seconds: i64) -> Option<prost_types::Timestamp> {
+        Some(prost_types::Timestamp { seconds, nanos: 0 })
+    }
+
+    fn vertex(name: &str, started: Option<i64>, completed: Option<i64>) -> Vertex {
+        Vertex {
+            digest: format!("sha256:{}", name),
+            name: name.to_string(),
+            started: started.and_then(timestamp),
+            completed: completed.and_then(timestamp),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn running_steps_are_redrawn_in_place() {
+        let buffer = SharedBuffer::default();
+        let mut handler = TtyProgressHandler::with_writer(Box::new(buffer.clone()), 40);
+        assert!(handler.is_interactive());
+
+        handler.on_start().unwrap();
+        assert_eq!(buffer.take(), "[+] Building 0.0s (0/0)\n");
+
+        handler
+            .on_status(StatusResponse {
+                vertexes: vec![vertex("[1/2] RUN make", Some(100), None)],
+                logs: vec![VertexLog {
+                    vertex: "sha256:[1/2] RUN make".to_string(),
+                    msg: b"compiling\n".to_vec(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+            .unwrap();
+        let frame = buffer.take();
+        assert!(frame.starts_with("\x1b[1A\x1b[J[+] Building"));
+        assert!(frame.contains("⠙ [1/2] RUN make"));
+        assert!(frame.ends_with("=> => # compiling\n"));
+
+        handler
+            .on_status(StatusResponse {
+                vertexes: vec![vertex("[1/2] RUN make", Some(100), Some(103))],
+                ..Default::default()
+            })
+            .unwrap();
+        let frame = buffer.take();
+        assert!(frame.starts_with("\x1b[3A\x1b[J"));
+        assert!(frame.contains(&format!("{:<36}3.0s\n", "=> [1/2] RUN make")));
+
+        handler.on_complete().unwrap();
+        assert!(buffer.take().ends_with("(1/1) FINISHED\n"));
+    }
+
+    #[test]
+    fn lines_fit_the_terminal() {
+        assert_eq!(fit("=> RUN make", "1.0s", 20), "=> RUN make     1.0s");
+        assert_eq!(
+            fit("=> RUN make && make install", "1.0s", 20),
+            "=> RUN make &&  1.0s"
+        );
+        assert_eq!(fit("=> => # a long log line", "", 10), "=> => # a");
+    }
+}
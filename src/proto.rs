@@ -13,6 +13,15 @@ pub mod moby {
             pub mod sourcepolicy {
                 tonic::include_proto!("moby.buildkit.v1.sourcepolicy");
             }
+
+            pub mod apicaps {
+                tonic::include_proto!("moby.buildkit.v1.apicaps");
+            }
+
+            #[allow(clippy::large_enum_variant)]
+            pub mod frontend {
+                tonic::include_proto!("moby.buildkit.v1.frontend");
+            }
         }
     }
 
@@ -27,23 +36,55 @@ pub mod moby {
             tonic::include_proto!("moby.buildkit.secrets.v1");
         }
     }
+
+    pub mod sshforward {
+        pub mod v1 {
+            tonic::include_proto!("moby.sshforward.v1");
+        }
+    }
 }
 
 pub mod pb {
     tonic::include_proto!("pb");
 }
 
+pub mod errdefs {
+    tonic::include_proto!("errdefs");
+}
+
+pub mod stack {
+    tonic::include_proto!("stack");
+}
+
 pub mod fsutil {
     pub mod types {
         tonic::include_proto!("fsutil.types");
     }
 }
 
+pub mod containerd {
+    pub mod services {
+        pub mod content {
+            pub mod v1 {
+                tonic::include_proto!("containerd.services.content.v1");
+            }
+        }
+    }
+}
+
 pub mod google {
     pub mod rpc {
         tonic::include_proto!("google.rpc");
     }
 }
 
+pub mod grpc {
+    pub mod health {
+        pub mod v1 {
+            tonic::include_proto!("grpc.health.v1");
+        }
+    }
+}
+
 // Re-export commonly used types
 pub use moby::buildkit::v1::*;
@@ -0,0 +1,184 @@
+//! Low-level access to the BuildKit gRPC API
+//!
+//! The high-level [`BuildKitClient::build`](crate::BuildKitClient::build)
+//! covers Dockerfile builds. For RPCs it doesn't cover, this module exposes
+//! the generated clients and builders for the most common requests, so
+//! they can be issued over the same connection and with the same session
+//! services.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use buildkit_client::raw::{self, SolveRequestBuilder};
+//! use buildkit_client::session::Session;
+//! use buildkit_client::BuildKitClient;
+//!
+//! #[tokio::main]
+//! async fn main() -> anyhow::Result<()> {
+//!     let client = BuildKitClient::connect("http://localhost:1234").await?;
+//!     let mut control = raw::ControlClient::new(client.channel());
+//!
+//!     let mut session = Session::new();
+//!     session.add_file_sync("./my-app".into()).await;
+//!     session.start(control.clone()).await?;
+//!
+//!     let request = SolveRequestBuilder::new("my-build")
+//!         .session(&session)
+//!         .frontend_attr("context", format!("input:{}:context", session.get_id()))
+//!         .frontend_attr("dockerfile", format!("input:{}:dockerfile", session.get_id()))
+//!         .build();
+//!     let response = control
+//!         .solve(raw::with_session_metadata(request, &session))
+//!         .await?;
+//!     println!("{:?}", response.into_inner().exporter_response);
+//!     Ok(())
+//! }
+//! ```
+
+use crate::proto::moby::buildkit::v1::{
+    CacheOptions, CacheOptionsEntry, Exporter, SolveRequest, StatusRequest,
+};
+use crate::session::Session;
+use std::collections::HashMap;
+use tonic::metadata::{Ascii, MetadataKey, MetadataValue};
+
+pub use crate::proto::moby::buildkit::v1::control_client::ControlClient;
+pub use crate::proto::moby::buildkit::v1::frontend::llb_bridge_client::LlbBridgeClient;
+pub use crate::proto::moby::filesync::v1::auth_client::AuthClient;
+pub use crate::proto::moby::filesync::v1::file_sync_client::FileSyncClient;
+pub use crate::proto::moby::secrets::v1::secrets_client::SecretsClient;
+pub use crate::proto::pb::Definition;
+pub use tonic::transport::Channel;
+
+/// Frontend used for Dockerfile builds
+pub const DOCKERFILE_FRONTEND: &str = "dockerfile.v0";
+
+/// Frontend running a frontend image, named by the `source` option
+pub const GATEWAY_FRONTEND: &str = "gateway.v0";
+
+/// Builder for [`SolveRequest`]
+///
+/// Defaults to the Dockerfile frontend with no exporters.
+#[derive(Debug, Clone)]
+pub struct SolveRequestBuilder {
+    request: SolveRequest,
+    cache: CacheOptions,
+}
+
+impl SolveRequestBuilder {
+    /// Start a request for the build with the given reference
+    pub fn new(build_ref: impl Into<String>) -> Self {
+        Self {
+            request: SolveRequest {
+                r#ref: build_ref.into(),
+                frontend: DOCKERFILE_FRONTEND.to_string(),
+                ..Default::default()
+            },
+            cache: CacheOptions::default(),
+        }
+    }
+
+    /// Attach the build to a session, which serves the context, credentials
+    /// and secrets
+    pub fn session(mut self, session: &Session) -> Self {
+        self.request.session = session.get_id();
+        self
+    }
+
+    /// Set the frontend (e.g. `gateway.v0`), or none for an LLB definition
+    pub fn frontend(mut self, frontend: impl Into<String>) -> Self {
+        self.request.frontend = frontend.into();
+        self
+    }
+
+    /// Set a frontend option
+    pub fn frontend_attr(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.request.frontend_attrs.insert(key.into(), value.into());
+        self
+    }
+
+    /// Solve an LLB definition instead of a frontend
+    pub fn definition(mut self, definition: Definition) -> Self {
+        self.request.definition = Some(definition);
+        self.request.frontend.clear();
+        self
+    }
+
+    /// Add an exporter, e.g. `image` with `name` and `push` attributes
+    pub fn exporter(
+        mut self,
+        exporter_type: impl Into<String>,
+        attrs: HashMap<String, String>,
+    ) -> Self {
+        self.request.exporters.push(Exporter {
+            r#type: exporter_type.into(),
+            attrs,
+        });
+        self
+    }
+
+    /// Add a cache import source
+    pub fn cache_import(
+        mut self,
+        cache_type: impl Into<String>,
+        attrs: HashMap<String, String>,
+    ) -> Self {
+        self.cache.imports.push(CacheOptionsEntry {
+            r#type: cache_type.into(),
+            attrs,
+        });
+        self
+    }
+
+    /// Add a cache export destination
+    pub fn cache_export(
+        mut self,
+        cache_type: impl Into<String>,
+        attrs: HashMap<String, String>,
+    ) -> Self {
+        self.cache.exports.push(CacheOptionsEntry {
+            r#type: cache_type.into(),
+            attrs,
+        });
+        self
+    }
+
+    /// Grant an entitlement such as `network.host`
+    pub fn entitlement(mut self, entitlement: impl Into<String>) -> Self {
+        self.request.entitlements.push(entitlement.into());
+        self
+    }
+
+    /// Finish the request
+    pub fn build(mut self) -> SolveRequest {
+        self.request.cache = Some(self.cache);
+        self.request
+    }
+}
+
+/// Status request for a build reference
+pub fn status_request(build_ref: impl Into<String>) -> StatusRequest {
+    StatusRequest {
+        r#ref: build_ref.into(),
+    }
+}
+
+/// Wrap a message in a gRPC request carrying the session's headers
+///
+/// BuildKit uses these headers to find the session of a solve request.
+pub fn with_session_metadata<T>(message: T, session: &Session) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    let metadata = request.metadata_mut();
+
+    for (key, values) in session.metadata() {
+        if let Ok(k) = key.parse::<MetadataKey<Ascii>>() {
+            // Add each value for the key (supports multi-value headers)
+            for value in values {
+                if let Ok(v) = value.parse::<MetadataValue<Ascii>>() {
+                    metadata.append(k.clone(), v);
+                }
+            }
+        }
+    }
+    request
+}
@@ -0,0 +1,274 @@
+//! Container image reference parsing and normalization
+//!
+//! Implements the subset of the `distribution/reference` grammar that
+//! registries enforce on push, so invalid tags can be rejected before a
+//! build is started.
+
+use crate::error::{Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Default registry used when a reference has no domain component
+pub const DEFAULT_DOMAIN: &str = "docker.io";
+
+/// Repository prefix for official images on Docker Hub
+const OFFICIAL_REPO_PREFIX: &str = "library/";
+
+/// Default tag used when a reference has neither tag nor digest
+pub const DEFAULT_TAG: &str = "latest";
+
+/// Maximum length of the repository name (domain + path)
+const NAME_TOTAL_LENGTH_MAX: usize = 255;
+
+/// Maximum length of a tag
+const TAG_LENGTH_MAX: usize = 128;
+
+/// A normalized container image reference
+///
+/// # Example
+///
+/// ```
+/// use buildkit_client::Reference;
+///
+/// let reference = Reference::parse("myimg").unwrap();
+/// assert_eq!(reference.to_string(), "docker.io/library/myimg:latest");
+///
+/// let reference = Reference::parse("localhost:5000/team/app:v1").unwrap();
+/// assert_eq!(reference.domain, "localhost:5000");
+/// assert_eq!(reference.path, "team/app");
+/// assert_eq!(reference.tag.as_deref(), Some("v1"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Reference {
+    /// Registry domain (e.g., "docker.io", "localhost:5000")
+    pub domain: String,
+    /// Repository path within the registry (e.g., "library/alpine")
+    pub path: String,
+    /// Tag, if any
+    pub tag: Option<String>,
+    /// Content digest (e.g., "sha256:..."), if any
+    pub digest: Option<String>,
+}
+
+impl Reference {
+    /// Parse and normalize an image reference
+    ///
+    /// Shorthand names are expanded the same way Docker does: a missing
+    /// domain becomes `docker.io`, single-component Docker Hub names get the
+    /// `library/` prefix, and `latest` is used when neither a tag nor a
+    /// digest is given.
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidReference {
+            reference: s.to_string(),
+            reason: reason.to_string(),
+        };
+
+        if s.is_empty() {
+            return Err(invalid("reference is empty"));
+        }
+
+        // Split off digest first, then tag (a ':' after the last '/' is a tag)
+        let (remainder, digest) = match s.split_once('@') {
+            Some((name, digest)) => (name, Some(digest)),
+            None => (s, None),
+        };
+
+        let (name, tag) = match remainder.rfind(':') {
+            Some(idx) if !remainder[idx..].contains('/') => {
+                (&remainder[..idx], Some(&remainder[idx + 1..]))
+            }
+            _ => (remainder, None),
+        };
+
+        if name.is_empty() {
+            return Err(invalid("repository name is empty"));
+        }
+        if name.len() > NAME_TOTAL_LENGTH_MAX {
+            return Err(invalid("repository name must not exceed 255 characters"));
+        }
+
+        let (domain, path) = split_domain(name);
+
+        if let Some(domain) = &domain {
+            if !is_valid_domain(domain) {
+                return Err(invalid("invalid registry domain"));
+            }
+        }
+
+        if path.chars().any(|c| c.is_ascii_uppercase()) {
+            return Err(invalid("repository name must be lowercase"));
+        }
+        if !path.split('/').all(is_valid_path_component) {
+            return Err(invalid("invalid repository name"));
+        }
+
+        if let Some(tag) = tag {
+            if !is_valid_tag(tag) {
+                return Err(invalid("invalid tag format"));
+            }
+        }
+
+        if let Some(digest) = digest {
+            if !is_valid_digest(digest) {
+                return Err(invalid("invalid digest format"));
+            }
+        }
+
+        let domain = domain.unwrap_or_else(|| DEFAULT_DOMAIN.to_string());
+        let path = if domain == DEFAULT_DOMAIN && !path.contains('/') {
+            format!("{}{}", OFFICIAL_REPO_PREFIX, path)
+        } else {
+            path.to_string()
+        };
+
+        let tag = match (tag, digest) {
+            (None, None) => Some(DEFAULT_TAG.to_string()),
+            (tag, _) => tag.map(str::to_string),
+        };
+
+        Ok(Self {
+            domain,
+            path,
+            tag,
+            digest: digest.map(str::to_string),
+        })
+    }
+
+    /// Repository name including the domain (e.g., "docker.io/library/alpine")
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.domain, self.path)
+    }
+}
+
+impl FromStr for Reference {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
+
+/// Split a repository name into an optional domain and the remaining path
+///
+/// The first component is treated as a domain when it contains a '.' or ':',
+/// is "localhost", or contains uppercase letters (which paths never may).
+fn split_domain(name: &str) -> (Option<String>, &str) {
+    match name.split_once('/') {
+        Some((first, rest))
+            if first.contains('.')
+                || first.contains(':')
+                || first == "localhost"
+                || first.chars().any(|c| c.is_ascii_uppercase()) =>
+        {
+            // "index.docker.io" is an alias for the default registry
+            let domain = if first == "index.docker.io" {
+                DEFAULT_DOMAIN.to_string()
+            } else {
+                first.to_string()
+            };
+            (Some(domain), rest)
+        }
+        _ => (None, name),
+    }
+}
+
+/// Validate a registry domain with optional port
+fn is_valid_domain(domain: &str) -> bool {
+    let (host, port) = match domain.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (domain, None),
+    };
+
+    if let Some(port) = port {
+        if port.is_empty() || !port.chars().all(|c| c.is_ascii_digit()) {
+            return false;
+        }
+    }
+
+    !host.is_empty()
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Validate a single path component: `[a-z0-9]+(?:(?:[._]|__|[-]+)[a-z0-9]+)*`
+fn is_valid_path_component(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    if bytes.is_empty() {
+        return false;
+    }
+
+    let is_alnum = |b: u8| b.is_ascii_lowercase() || b.is_ascii_digit();
+    if !is_alnum(bytes[0]) || !is_alnum(bytes[bytes.len() - 1]) {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if is_alnum(bytes[i]) {
+            i += 1;
+            continue;
+        }
+
+        // Separator run: '.', '_', '__' or one or more '-'
+        let start = i;
+        while i < bytes.len() && !is_alnum(bytes[i]) {
+            i += 1;
+        }
+        let separator = &component[start..i];
+        let valid = matches!(separator, "." | "_" | "__") || separator.bytes().all(|b| b == b'-');
+        if !valid {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Validate a tag: `[\w][\w.-]{0,127}`
+fn is_valid_tag(tag: &str) -> bool {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut chars = tag.chars();
+    match chars.next() {
+        Some(first) if is_word(first) => {}
+        _ => return false,
+    }
+    tag.len() <= TAG_LENGTH_MAX && chars.all(|c| is_word(c) || c == '.' || c == '-')
+}
+
+/// Validate a digest: `algorithm:hex`, with length checks for sha256/sha512
+fn is_valid_digest(digest: &str) -> bool {
+    let Some((algorithm, hex)) = digest.split_once(':') else {
+        return false;
+    };
+
+    let valid_algorithm = !algorithm.is_empty()
+        && algorithm
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+._-".contains(c));
+    let valid_hex = hex.len() >= 32 && hex.chars().all(|c| c.is_ascii_hexdigit());
+
+    let expected_len = match algorithm {
+        "sha256" => Some(64),
+        "sha512" => Some(128),
+        _ => None,
+    };
+
+    valid_algorithm && valid_hex && expected_len.is_none_or(|len| hex.len() == len)
+}
@@ -0,0 +1,1227 @@
+//! Container registry client for inspecting pushed images
+//!
+//! Speaks the OCI distribution API directly, so the result of a push
+//! (platforms, labels, image config) can be verified without external tools.
+
+use crate::builder::{BuildConfig, CredentialScope, Platform, RegistryAuth};
+use crate::error::{Error, Result};
+use crate::redact::Redacted;
+use crate::reference::{Reference, DEFAULT_DOMAIN};
+use crate::solve::{guess_insecure_registry, registry_host};
+use bytes::Bytes;
+use reqwest::header::{HeaderMap, ACCEPT, CONTENT_TYPE, LOCATION, WWW_AUTHENTICATE};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// OCI image index media type
+pub const MEDIA_TYPE_OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+
+/// OCI image manifest media type
+pub const MEDIA_TYPE_OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// Docker manifest list media type
+pub const MEDIA_TYPE_DOCKER_MANIFEST_LIST: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+
+/// Docker image manifest media type
+pub const MEDIA_TYPE_DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+/// Registry API host serving Docker Hub repositories
+const DOCKER_HUB_REGISTRY: &str = "registry-1.docker.io";
+
+/// Annotation BuildKit sets on attestation manifests inside an index
+const REFERENCE_TYPE_ANNOTATION: &str = "vnd.docker.reference.type";
+
+/// Client ID sent when exchanging identity tokens
+const OAUTH_CLIENT_ID: &str = "buildkit-client";
+
+/// Response header carrying the digest of the returned content
+const CONTENT_DIGEST_HEADER: &str = "docker-content-digest";
+
+/// Content descriptor referencing a manifest, config or layer blob
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    /// Media type of the referenced content
+    pub media_type: String,
+    /// Content digest (e.g., "sha256:...")
+    pub digest: String,
+    /// Size of the content in bytes
+    pub size: i64,
+    /// Platform of the referenced manifest, for index entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<DescriptorPlatform>,
+    /// Arbitrary annotations
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub annotations: HashMap<String, String>,
+}
+
+impl Descriptor {
+    /// Whether this index entry is an attestation (provenance/SBOM) manifest
+    pub fn is_attestation(&self) -> bool {
+        self.annotations.contains_key(REFERENCE_TYPE_ANNOTATION)
+            || self
+                .platform
+                .as_ref()
+                .is_some_and(|p| p.os == "unknown" && p.architecture == "unknown")
+    }
+}
+
+/// Platform of a manifest referenced from an index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DescriptorPlatform {
+    /// CPU architecture (e.g., "amd64")
+    pub architecture: String,
+    /// Operating system (e.g., "linux")
+    pub os: String,
+    /// CPU variant (e.g., "v8")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+}
+
+impl From<&DescriptorPlatform> for Platform {
+    fn from(p: &DescriptorPlatform) -> Self {
+        Platform {
+            os: p.os.clone(),
+            arch: p.architecture.clone(),
+            variant: p.variant.clone(),
+        }
+    }
+}
+
+/// Image index (multi-platform manifest list)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageIndex {
+    /// Schema version, always 2
+    pub schema_version: u32,
+    /// Media type, if present in the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    /// Per-platform manifests
+    pub manifests: Vec<Descriptor>,
+    /// Arbitrary annotations
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub annotations: HashMap<String, String>,
+}
+
+/// Single-platform image manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageManifest {
+    /// Schema version, always 2
+    pub schema_version: u32,
+    /// Media type, if present in the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    /// Image config blob
+    pub config: Descriptor,
+    /// Layer blobs, base layer first
+    pub layers: Vec<Descriptor>,
+    /// Arbitrary annotations
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub annotations: HashMap<String, String>,
+}
+
+/// A manifest as returned by the registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Manifest {
+    /// Multi-platform index or Docker manifest list
+    Index(ImageIndex),
+    /// Single-platform image manifest
+    Image(ImageManifest),
+}
+
+impl Manifest {
+    /// Parse a manifest document
+    ///
+    /// `media_type` is the `Content-Type` returned by the registry; when it
+    /// is missing or generic the document's shape decides.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::registry::Manifest;
+    ///
+    /// let body = br#"{"schemaVersion":2,"manifests":[]}"#;
+    /// let manifest = Manifest::parse(body, None).unwrap();
+    /// assert!(matches!(manifest, Manifest::Index(_)));
+    /// ```
+    pub fn parse(data: &[u8], media_type: Option<&str>) -> Result<Self> {
+        let invalid = |e: serde_json::Error| Error::registry(format!("invalid manifest: {}", e));
+
+        let is_index = match media_type {
+            Some(MEDIA_TYPE_OCI_INDEX | MEDIA_TYPE_DOCKER_MANIFEST_LIST) => true,
+            Some(MEDIA_TYPE_OCI_MANIFEST | MEDIA_TYPE_DOCKER_MANIFEST) => false,
+            _ => serde_json::from_slice::<serde_json::Value>(data)
+                .map_err(invalid)?
+                .get("manifests")
+                .is_some(),
+        };
+
+        if is_index {
+            serde_json::from_slice(data)
+                .map(Manifest::Index)
+                .map_err(invalid)
+        } else {
+            serde_json::from_slice(data)
+                .map(Manifest::Image)
+                .map_err(invalid)
+        }
+    }
+}
+
+/// Image configuration blob
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImageConfig {
+    /// CPU architecture
+    #[serde(default)]
+    pub architecture: String,
+    /// Operating system
+    #[serde(default)]
+    pub os: String,
+    /// CPU variant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// Creation time (RFC 3339)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    /// Runtime configuration
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub config: ContainerConfig,
+    /// Layer diff IDs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rootfs: Option<RootFs>,
+}
+
+impl ImageConfig {
+    /// Platform the image was built for
+    pub fn platform(&self) -> Platform {
+        Platform {
+            os: self.os.clone(),
+            arch: self.architecture.clone(),
+            variant: self.variant.clone(),
+        }
+    }
+}
+
+/// Runtime configuration embedded in an image config
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerConfig {
+    /// User the container runs as
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub user: String,
+    /// Environment variables ("KEY=value")
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub env: Vec<String>,
+    /// Entrypoint
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub entrypoint: Vec<String>,
+    /// Default command
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub cmd: Vec<String>,
+    /// Working directory
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub working_dir: String,
+    /// Image labels
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub labels: HashMap<String, String>,
+    /// Exposed ports (e.g., "80/tcp")
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub exposed_ports: HashMap<String, serde_json::Value>,
+}
+
+/// Root filesystem description of an image config
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootFs {
+    /// Always "layers"
+    #[serde(rename = "type")]
+    pub fs_type: String,
+    /// Uncompressed layer digests
+    #[serde(default)]
+    pub diff_ids: Vec<String>,
+}
+
+/// Treat an explicit JSON `null` like a missing field
+fn null_as_default<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+/// One platform-specific image of an inspected reference
+#[derive(Debug, Clone)]
+pub struct PlatformImage {
+    /// Platform, from the index entry or the image config
+    pub platform: Platform,
+    /// Digest of the platform manifest
+    pub digest: String,
+    /// Platform manifest
+    pub manifest: ImageManifest,
+    /// Image configuration
+    pub config: ImageConfig,
+}
+
+/// Result of inspecting an image reference
+#[derive(Debug, Clone)]
+pub struct ImageInspect {
+    /// Normalized reference that was inspected
+    pub reference: Reference,
+    /// Digest of the top-level manifest or index
+    pub digest: String,
+    /// Top-level manifest, as returned by the registry
+    pub manifest: Manifest,
+    /// Images per platform; attestation manifests are omitted
+    pub images: Vec<PlatformImage>,
+}
+
+impl PlatformImage {
+    /// Compressed size of the image: its config and layers as stored in
+    /// the registry
+    pub fn size(&self) -> i64 {
+        self.manifest.config.size + self.manifest.layers.iter().map(|l| l.size).sum::<i64>()
+    }
+}
+
+impl ImageInspect {
+    /// Whether the reference points to a multi-platform index
+    pub fn is_multi_platform(&self) -> bool {
+        matches!(self.manifest, Manifest::Index(_))
+    }
+
+    /// Platforms available for this reference
+    pub fn platforms(&self) -> Vec<Platform> {
+        self.images.iter().map(|i| i.platform.clone()).collect()
+    }
+}
+
+/// Credentials obtained for a repository after an auth challenge
+#[derive(Debug, Clone)]
+enum Credential {
+    Basic {
+        username: String,
+        password: Redacted<String>,
+    },
+    Bearer(Redacted<String>),
+}
+
+/// Repository access requested from the registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Pull,
+    Push,
+}
+
+impl Access {
+    /// Credential scope used for this access level
+    fn scope(self) -> CredentialScope {
+        match self {
+            Access::Pull => CredentialScope::Pull,
+            Access::Push => CredentialScope::Push,
+        }
+    }
+
+    /// Token scope actions for this access level
+    fn actions(self) -> &'static str {
+        match self {
+            Access::Pull => "pull",
+            Access::Push => "pull,push",
+        }
+    }
+}
+
+/// Manifest fetched from a registry, with its raw content
+#[derive(Debug, Clone)]
+struct FetchedManifest {
+    digest: String,
+    media_type: String,
+    data: Bytes,
+    manifest: Manifest,
+}
+
+/// Parsed `WWW-Authenticate` challenge
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Challenge {
+    scheme: String,
+    params: HashMap<String, String>,
+}
+
+/// Token endpoint response
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+    expires_in: Option<i64>,
+}
+
+/// Credentials presented to a token endpoint
+#[derive(Clone, Copy)]
+pub(crate) struct TokenCredentials<'a> {
+    pub(crate) username: &'a str,
+    pub(crate) password: &'a str,
+    /// OAuth refresh token, used instead of username and password
+    pub(crate) identity_token: Option<&'a str>,
+}
+
+/// Bearer token issued by a registry's token endpoint
+#[derive(Debug, Clone)]
+pub(crate) struct BearerToken {
+    pub(crate) token: Redacted<String>,
+    /// Lifetime in seconds, 0 if unknown
+    pub(crate) expires_in: i64,
+}
+
+/// Exchange credentials for a bearer token at a registry's token endpoint
+///
+/// Identity tokens use the OAuth refresh-token grant; username and password
+/// use the basic-auth GET flow; without credentials an anonymous token is
+/// requested.
+pub(crate) async fn fetch_bearer_token(
+    http: &reqwest::Client,
+    realm: &str,
+    service: Option<&str>,
+    scopes: &[String],
+    credentials: Option<TokenCredentials<'_>>,
+) -> Result<BearerToken> {
+    let request = match credentials {
+        Some(TokenCredentials {
+            identity_token: Some(refresh_token),
+            ..
+        }) => {
+            let mut form = vec![
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+                ("client_id", OAUTH_CLIENT_ID),
+            ];
+            form.extend(service.map(|s| ("service", s)));
+            // Multiple scopes are space-separated in the OAuth form
+            let scope = scopes.join(" ");
+            form.push(("scope", scope.as_str()));
+            http.post(realm).form(&form)
+        }
+        _ => {
+            let mut query: Vec<(&str, &str)> =
+                scopes.iter().map(|s| ("scope", s.as_str())).collect();
+            query.extend(service.map(|s| ("service", s)));
+            let request = http.get(realm).query(&query);
+            match credentials {
+                Some(c) => request.basic_auth(c.username, Some(c.password)),
+                None => request,
+            }
+        }
+    };
+
+    let response: TokenResponse = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| Error::registry(format!("token request failed: {}", e)))?
+        .json()
+        .await
+        .map_err(|e| Error::registry(format!("invalid token response: {}", e)))?;
+
+    let token = response
+        .token
+        .or(response.access_token)
+        .ok_or_else(|| Error::registry("token response has no token".to_string()))?;
+    Ok(BearerToken {
+        token: Redacted::new(token),
+        expires_in: response.expires_in.unwrap_or(0),
+    })
+}
+
+/// Client for the OCI distribution (registry v2) API
+///
+/// # Example
+///
+/// ```no_run
+/// use buildkit_client::registry::RegistryClient;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let registry = RegistryClient::new().plain_http("localhost:5000");
+///     let image = registry.inspect("localhost:5000/my-app:latest").await?;
+///
+///     for platform in image.platforms() {
+///         println!("{}", platform);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct RegistryClient {
+    http: reqwest::Client,
+    auths: Vec<RegistryAuth>,
+    plain_http: HashSet<String>,
+    credentials: Mutex<HashMap<String, Credential>>,
+}
+
+impl RegistryClient {
+    /// Create a registry client without credentials
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add credentials for a registry host
+    pub fn with_auth(mut self, auth: RegistryAuth) -> Self {
+        self.auths.push(auth);
+        self
+    }
+
+    /// Talk plain HTTP instead of HTTPS to the given registry host
+    pub fn plain_http(mut self, host: impl Into<String>) -> Self {
+        self.plain_http.insert(host.into());
+        self
+    }
+
+    /// Registry client with the credentials of a build
+    ///
+    /// Uses the build's registry auths, and plain HTTP for the registries
+    /// it marks insecure, to read back what the build pushed. Registries of
+    /// the build's tags without an explicit setting are guessed as when
+    /// pushing, so that e.g. `localhost:5000` is read over plain HTTP.
+    pub fn for_build(config: &BuildConfig) -> Self {
+        let mut client = Self::new();
+        for auth in config.registry_auth.iter().chain(&config.registry_auths) {
+            client = client.with_auth(auth.clone());
+        }
+        for (host, insecure) in &config.insecure_registries {
+            if *insecure {
+                client = client.plain_http(host.clone());
+            }
+        }
+        for host in config.tags.iter().filter_map(|tag| registry_host(tag)) {
+            if !config.insecure_registries.contains_key(&host) && guess_insecure_registry(&host) {
+                client = client.plain_http(host);
+            }
+        }
+        client
+    }
+
+    /// Inspect an image reference
+    ///
+    /// Resolves the reference to its manifest or index and fetches the
+    /// manifest and config of every platform image it contains.
+    pub async fn inspect(&self, reference: &str) -> Result<ImageInspect> {
+        let reference = Reference::parse(reference)?;
+        let (digest, manifest) = self.manifest(&reference).await?;
+
+        let mut images = Vec::new();
+        match &manifest {
+            Manifest::Image(image) => {
+                let config = self.image_config(&reference, &image.config).await?;
+                images.push(PlatformImage {
+                    platform: config.platform(),
+                    digest: digest.clone(),
+                    manifest: image.clone(),
+                    config,
+                });
+            }
+            Manifest::Index(index) => {
+                for descriptor in index.manifests.iter().filter(|d| !d.is_attestation()) {
+                    let child = self.fetch_manifest(&reference, &descriptor.digest).await?;
+                    let Manifest::Image(image) = child.manifest else {
+                        return Err(Error::registry(format!(
+                            "nested index {} is not supported",
+                            descriptor.digest
+                        )));
+                    };
+                    let config = self.image_config(&reference, &image.config).await?;
+                    let platform = descriptor
+                        .platform
+                        .as_ref()
+                        .map(Platform::from)
+                        .unwrap_or_else(|| config.platform());
+                    images.push(PlatformImage {
+                        platform,
+                        digest: child.digest,
+                        manifest: image,
+                        config,
+                    });
+                }
+            }
+        }
+
+        Ok(ImageInspect {
+            reference,
+            digest,
+            manifest,
+            images,
+        })
+    }
+
+    /// Fetch the manifest or index a reference points to, with its digest
+    pub async fn manifest(&self, reference: &Reference) -> Result<(String, Manifest)> {
+        let fetched = self
+            .fetch_manifest(reference, manifest_target(reference))
+            .await?;
+        Ok((fetched.digest, fetched.manifest))
+    }
+
+    /// Fetch and decode an image config blob
+    pub async fn image_config(
+        &self,
+        reference: &Reference,
+        descriptor: &Descriptor,
+    ) -> Result<ImageConfig> {
+        let data = self.blob(reference, &descriptor.digest).await?;
+        serde_json::from_slice(&data)
+            .map_err(|e| Error::registry(format!("invalid image config: {}", e)))
+    }
+
+    /// Fetch a blob from the reference's repository, verifying its digest
+    pub async fn blob(&self, reference: &Reference, digest: &str) -> Result<Bytes> {
+        let url = self.blob_url(reference, digest);
+        let response = self.get(reference, &url, None).await?;
+        let data = read_body(response, &url).await?;
+        verify_digest(&data, digest)?;
+        Ok(data)
+    }
+
+    /// Copy an image to other references without rebuilding it
+    ///
+    /// Destinations may be on other repositories or registries. Blobs that
+    /// already exist at a destination are skipped, and blobs within the same
+    /// registry are mounted instead of re-uploaded. Multi-platform indexes
+    /// are copied with all their manifests. Returns the digest of the
+    /// copied manifest or index.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use buildkit_client::registry::RegistryClient;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let registry = RegistryClient::new();
+    ///     registry
+    ///         .copy_image("ghcr.io/org/app:sha-abc", &["ghcr.io/org/app:v1.2.0"])
+    ///         .await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn copy_image(&self, source: &str, destinations: &[&str]) -> Result<String> {
+        let source = Reference::parse(source)?;
+        let destinations = destinations
+            .iter()
+            .map(|d| Reference::parse(d))
+            .collect::<Result<Vec<_>>>()?;
+
+        let top = self
+            .fetch_manifest(&source, manifest_target(&source))
+            .await?;
+
+        // Platform manifests of an index are copied by digest before the index
+        let mut children = Vec::new();
+        if let Manifest::Index(index) = &top.manifest {
+            for descriptor in &index.manifests {
+                children.push(self.fetch_manifest(&source, &descriptor.digest).await?);
+            }
+        }
+
+        for destination in &destinations {
+            for child in &children {
+                self.copy_manifest_blobs(&source, destination, child)
+                    .await?;
+                self.put_manifest(destination, &child.digest, child).await?;
+            }
+            self.copy_manifest_blobs(&source, destination, &top).await?;
+            self.put_manifest(destination, manifest_target(destination), &top)
+                .await?;
+            tracing::info!("Copied {} to {}", source, destination);
+        }
+
+        Ok(top.digest)
+    }
+
+    /// Copy the config and layer blobs of an image manifest
+    async fn copy_manifest_blobs(
+        &self,
+        source: &Reference,
+        destination: &Reference,
+        fetched: &FetchedManifest,
+    ) -> Result<()> {
+        let Manifest::Image(image) = &fetched.manifest else {
+            return Ok(());
+        };
+        for descriptor in std::iter::once(&image.config).chain(&image.layers) {
+            self.copy_blob(source, destination, &descriptor.digest)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Copy a single blob, mounting or skipping it where possible
+    async fn copy_blob(
+        &self,
+        source: &Reference,
+        destination: &Reference,
+        digest: &str,
+    ) -> Result<()> {
+        let url = self.blob_url(destination, digest);
+        let exists = self
+            .send(destination, Access::Push, || self.http.head(&url))
+            .await?;
+        if exists.status().is_success() {
+            tracing::debug!("Blob {} already exists in {}", digest, destination.name());
+            return Ok(());
+        }
+
+        let uploads_url = format!(
+            "{}/{}/blobs/uploads/",
+            self.api_base(&destination.domain),
+            destination.path
+        );
+
+        // Try a cross-repository mount first; the registry answers with a
+        // regular upload session if it cannot mount the blob
+        let response = if source.domain == destination.domain && source.path != destination.path {
+            self.send(destination, Access::Push, || {
+                self.http
+                    .post(&uploads_url)
+                    .query(&[("mount", digest), ("from", source.path.as_str())])
+            })
+            .await?
+        } else {
+            self.send(destination, Access::Push, || self.http.post(&uploads_url))
+                .await?
+        };
+
+        if response.status() == StatusCode::CREATED {
+            tracing::debug!("Mounted blob {} into {}", digest, destination.name());
+            return Ok(());
+        }
+        let response = expect_success(response, "POST", &uploads_url)?;
+        let location = header_str(response.headers(), LOCATION.as_str()).ok_or_else(|| {
+            Error::registry(format!("upload to {} returned no location", uploads_url))
+        })?;
+        let location = self.resolve_location(&destination.domain, &location);
+
+        let data = self.blob(source, digest).await?;
+        let response = self
+            .send(destination, Access::Push, || {
+                self.http
+                    .put(&location)
+                    .query(&[("digest", digest)])
+                    .header(CONTENT_TYPE, "application/octet-stream")
+                    .body(data.clone())
+            })
+            .await?;
+        expect_success(response, "PUT", &location)?;
+        tracing::debug!("Uploaded blob {} to {}", digest, destination.name());
+        Ok(())
+    }
+
+    /// Upload a manifest under a tag or digest
+    async fn put_manifest(
+        &self,
+        destination: &Reference,
+        tag_or_digest: &str,
+        fetched: &FetchedManifest,
+    ) -> Result<()> {
+        let url = self.manifest_url(destination, tag_or_digest);
+        let response = self
+            .send(destination, Access::Push, || {
+                self.http
+                    .put(&url)
+                    .header(CONTENT_TYPE, &fetched.media_type)
+                    .body(fetched.data.clone())
+            })
+            .await?;
+        expect_success(response, "PUT", &url)?;
+        Ok(())
+    }
+
+    /// Fetch a manifest by tag or digest
+    async fn fetch_manifest(
+        &self,
+        reference: &Reference,
+        tag_or_digest: &str,
+    ) -> Result<FetchedManifest> {
+        let url = self.manifest_url(reference, tag_or_digest);
+        let accept = [
+            MEDIA_TYPE_OCI_INDEX,
+            MEDIA_TYPE_OCI_MANIFEST,
+            MEDIA_TYPE_DOCKER_MANIFEST_LIST,
+            MEDIA_TYPE_DOCKER_MANIFEST,
+        ]
+        .join(", ");
+
+        let response = self.get(reference, &url, Some(&accept)).await?;
+        let header_digest = header_str(response.headers(), CONTENT_DIGEST_HEADER);
+        let content_type = header_str(response.headers(), CONTENT_TYPE.as_str());
+        let data = read_body(response, &url).await?;
+
+        let digest = match header_digest {
+            Some(digest) => {
+                verify_digest(&data, &digest)?;
+                digest
+            }
+            None => sha256_digest(&data),
+        };
+        if tag_or_digest.contains(':') && tag_or_digest != digest {
+            verify_digest(&data, tag_or_digest)?;
+        }
+
+        let manifest = Manifest::parse(&data, content_type.as_deref())?;
+        let media_type = content_type
+            .filter(|t| t.starts_with("application/vnd."))
+            .unwrap_or_else(|| match &manifest {
+                Manifest::Index(index) => index
+                    .media_type
+                    .clone()
+                    .unwrap_or_else(|| MEDIA_TYPE_OCI_INDEX.to_string()),
+                Manifest::Image(image) => image
+                    .media_type
+                    .clone()
+                    .unwrap_or_else(|| MEDIA_TYPE_OCI_MANIFEST.to_string()),
+            });
+
+        tracing::debug!("Fetched manifest {} ({})", url, digest);
+        Ok(FetchedManifest {
+            digest,
+            media_type,
+            data,
+            manifest,
+        })
+    }
+
+    /// Base URL of the registry API for a domain
+    fn api_base(&self, domain: &str) -> String {
+        format!("{}/v2", self.origin(domain))
+    }
+
+    /// Scheme and host of the registry serving a domain
+    fn origin(&self, domain: &str) -> String {
+        let scheme = if self.plain_http.contains(domain) {
+            "http"
+        } else {
+            "https"
+        };
+        let host = if domain == DEFAULT_DOMAIN {
+            DOCKER_HUB_REGISTRY
+        } else {
+            domain
+        };
+        format!("{}://{}", scheme, host)
+    }
+
+    /// URL of a manifest in the reference's repository
+    fn manifest_url(&self, reference: &Reference, tag_or_digest: &str) -> String {
+        format!(
+            "{}/{}/manifests/{}",
+            self.api_base(&reference.domain),
+            reference.path,
+            tag_or_digest
+        )
+    }
+
+    /// URL of a blob in the reference's repository
+    fn blob_url(&self, reference: &Reference, digest: &str) -> String {
+        format!(
+            "{}/{}/blobs/{}",
+            self.api_base(&reference.domain),
+            reference.path,
+            digest
+        )
+    }
+
+    /// Resolve an upload location, which registries may return as a path
+    fn resolve_location(&self, domain: &str, location: &str) -> String {
+        if location.starts_with('/') {
+            format!("{}{}", self.origin(domain), location)
+        } else {
+            location.to_string()
+        }
+    }
+
+    /// Best-fitting credentials configured for a registry domain
+    fn auth_for(&self, domain: &str, scope: CredentialScope) -> Option<&RegistryAuth> {
+        self.auths
+            .iter()
+            .filter(|auth| normalize_host(&auth.host) == domain)
+            .filter_map(|auth| auth.scope.preference(scope).map(|rank| (rank, auth)))
+            .min_by_key(|(rank, _)| *rank)
+            .map(|(_, auth)| auth)
+    }
+
+    /// Send a pull request and require a successful response
+    async fn get(
+        &self,
+        reference: &Reference,
+        url: &str,
+        accept: Option<&str>,
+    ) -> Result<reqwest::Response> {
+        let response = self
+            .send(reference, Access::Pull, || {
+                let request = self.http.get(url);
+                match accept {
+                    Some(accept) => request.header(ACCEPT, accept),
+                    None => request,
+                }
+            })
+            .await?;
+        expect_success(response, "GET", url)
+    }
+
+    /// Send a request, answering an auth challenge once if needed
+    ///
+    /// `build` is called again for the authenticated retry, so request
+    /// bodies must be cheap to clone.
+    async fn send(
+        &self,
+        reference: &Reference,
+        access: Access,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let key = format!("{}#{}", reference.name(), access.actions());
+        let mut challenged = false;
+
+        loop {
+            let mut request = build();
+            let credential = self.credentials.lock().unwrap().get(&key).cloned();
+            if let Some(credential) = credential {
+                request = apply_credential(request, &credential);
+            }
+
+            let response = request.send().await.map_err(|e| {
+                Error::registry(format!("request to {} failed: {}", reference.domain, e))
+            })?;
+
+            if response.status() == StatusCode::UNAUTHORIZED && !challenged {
+                challenged = true;
+                let credential = self
+                    .authorize(reference, access, response.headers())
+                    .await?;
+                self.credentials
+                    .lock()
+                    .unwrap()
+                    .insert(key.clone(), credential);
+                continue;
+            }
+
+            return Ok(response);
+        }
+    }
+
+    /// Obtain credentials answering the registry's auth challenge
+    async fn authorize(
+        &self,
+        reference: &Reference,
+        access: Access,
+        headers: &HeaderMap,
+    ) -> Result<Credential> {
+        let challenge = header_str(headers, WWW_AUTHENTICATE.as_str())
+            .and_then(|h| parse_challenge(&h))
+            .ok_or_else(|| {
+                Error::registry(format!(
+                    "{} requires authentication but sent no challenge",
+                    reference.domain
+                ))
+            })?;
+        let auth = self.auth_for(&reference.domain, access.scope());
+
+        match challenge.scheme.to_ascii_lowercase().as_str() {
+            "basic" => {
+                let auth = auth.ok_or_else(|| {
+                    Error::registry(format!(
+                        "no credentials configured for {}",
+                        reference.domain
+                    ))
+                })?;
+                Ok(Credential::Basic {
+                    username: auth.username.clone(),
+                    password: Redacted::new(auth.password.clone()),
+                })
+            }
+            "bearer" => {
+                let realm = challenge.params.get("realm").ok_or_else(|| {
+                    Error::registry("bearer challenge is missing the realm".to_string())
+                })?;
+                let scope = format!("repository:{}:{}", reference.path, access.actions());
+                let service = challenge.params.get("service").map(String::as_str);
+
+                if let Some(token) = auth.and_then(|a| a.registry_token.as_ref()) {
+                    return Ok(Credential::Bearer(Redacted::new(token.clone())));
+                }
+
+                let credentials = auth.map(|a| TokenCredentials {
+                    username: &a.username,
+                    password: &a.password,
+                    identity_token: a.identity_token.as_deref(),
+                });
+                let token =
+                    fetch_bearer_token(&self.http, realm, service, &[scope], credentials).await?;
+                Ok(Credential::Bearer(token.token))
+            }
+            other => Err(Error::registry(format!(
+                "unsupported auth scheme '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Tag or digest to resolve for a reference
+fn manifest_target(reference: &Reference) -> &str {
+    reference
+        .digest
+        .as_deref()
+        .or(reference.tag.as_deref())
+        .unwrap_or(crate::reference::DEFAULT_TAG)
+}
+
+/// Fail with a registry error unless the response is successful
+fn expect_success(
+    response: reqwest::Response,
+    method: &str,
+    url: &str,
+) -> Result<reqwest::Response> {
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(Error::registry(format!(
+            "{} {} returned {}",
+            method,
+            url,
+            response.status()
+        )))
+    }
+}
+
+/// Attach credentials to a request
+fn apply_credential(request: RequestBuilder, credential: &Credential) -> RequestBuilder {
+    match credential {
+        Credential::Basic { username, password } => {
+            request.basic_auth(username, Some(password.expose()))
+        }
+        Credential::Bearer(token) => request.bearer_auth(token.expose()),
+    }
+}
+
+/// Read a response body as bytes
+async fn read_body(response: reqwest::Response, url: &str) -> Result<Bytes> {
+    response
+        .bytes()
+        .await
+        .map_err(|e| Error::registry(format!("failed to read {}: {}", url, e)))
+}
+
+/// Get a header value as a string
+fn header_str(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Map the various spellings of a registry host to a reference domain
+fn normalize_host(host: &str) -> &str {
+    let host = host
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .split('/')
+        .next()
+        .unwrap_or(host);
+    match host {
+        "index.docker.io" | "registry-1.docker.io" => DEFAULT_DOMAIN,
+        host => host,
+    }
+}
+
+/// Compute the sha256 digest of some content
+fn sha256_digest(data: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(data))
+}
+
+/// Check content against a digest; only sha256 digests can be verified
+fn verify_digest(data: &[u8], digest: &str) -> Result<()> {
+    if digest.starts_with("sha256:") && sha256_digest(data) != digest {
+        return Err(Error::registry(format!(
+            "content does not match digest {}",
+            digest
+        )));
+    }
+    Ok(())
+}
+
+/// Parse a `WWW-Authenticate` header value
+///
+/// Handles the single-challenge form registries send, e.g.
+/// `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`.
+fn parse_challenge(header: &str) -> Option<Challenge> {
+    let header = header.trim();
+    let (scheme, mut rest) = header.split_once(' ').unwrap_or((header, ""));
+    if scheme.is_empty() {
+        return None;
+    }
+
+    let mut params = HashMap::new();
+    rest = rest.trim();
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=')?;
+        let value = value.trim_start();
+        let (value, remainder) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => value.split_at(value.find(',').unwrap_or(value.len())),
+        };
+        params.insert(key.trim().to_ascii_lowercase(), value.to_string());
+        rest = remainder.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+    }
+
+    Some(Challenge {
+        scheme: scheme.to_string(),
+        params,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bearer_challenge() {
+        let challenge = parse_challenge(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/alpine:pull,push""#,
+        )
+        .unwrap();
+
+        assert_eq!(challenge.scheme, "Bearer");
+        assert_eq!(challenge.params["realm"], "https://auth.docker.io/token");
+        assert_eq!(challenge.params["service"], "registry.docker.io");
+        assert_eq!(
+            challenge.params["scope"],
+            "repository:library/alpine:pull,push"
+        );
+    }
+
+    #[test]
+    fn parses_basic_challenge() {
+        let challenge = parse_challenge(r#"Basic realm=Registry"#).unwrap();
+        assert_eq!(challenge.scheme, "Basic");
+        assert_eq!(challenge.params["realm"], "Registry");
+
+        assert!(parse_challenge("").is_none());
+    }
+
+    #[test]
+    fn api_base_uses_docker_hub_host_and_plain_http() {
+        let client = RegistryClient::new().plain_http("localhost:5000");
+        assert_eq!(
+            client.api_base("docker.io"),
+            "https://registry-1.docker.io/v2"
+        );
+        assert_eq!(
+            client.api_base("localhost:5000"),
+            "http://localhost:5000/v2"
+        );
+        assert_eq!(client.api_base("ghcr.io"), "https://ghcr.io/v2");
+    }
+
+    #[test]
+    fn resolves_relative_upload_locations() {
+        let client = RegistryClient::new().plain_http("localhost:5000");
+        assert_eq!(
+            client.resolve_location("localhost:5000", "/v2/app/blobs/uploads/123?_state=x"),
+            "http://localhost:5000/v2/app/blobs/uploads/123?_state=x"
+        );
+        assert_eq!(
+            client.resolve_location("ghcr.io", "https://uploads.example.com/abc"),
+            "https://uploads.example.com/abc"
+        );
+    }
+
+    #[test]
+    fn auth_lookup_normalizes_docker_hub_hosts() {
+        let client = RegistryClient::new().with_auth(RegistryAuth {
+            host: "https://index.docker.io/v1/".to_string(),
+            username: "user".to_string(),
+            password: "pass".to_string(),
+            ..Default::default()
+        });
+        assert!(client
+            .auth_for("docker.io", CredentialScope::Pull)
+            .is_some());
+        assert!(client.auth_for("ghcr.io", CredentialScope::Pull).is_none());
+    }
+
+    #[test]
+    fn auth_lookup_prefers_matching_scope() {
+        let auth = |username: &str, scope| RegistryAuth {
+            host: "ghcr.io".to_string(),
+            username: username.to_string(),
+            scope,
+            ..Default::default()
+        };
+        let client = RegistryClient::new()
+            .with_auth(auth("reader", CredentialScope::Pull))
+            .with_auth(auth("robot", CredentialScope::Push));
+
+        let user = |scope| client.auth_for("ghcr.io", scope).unwrap().username.as_str();
+        assert_eq!(user(CredentialScope::Pull), "reader");
+        assert_eq!(user(CredentialScope::Push), "robot");
+
+        // Pull-only credentials are never used for pushing
+        let client = RegistryClient::new().with_auth(auth("reader", CredentialScope::Pull));
+        assert!(client.auth_for("ghcr.io", CredentialScope::Push).is_none());
+    }
+
+    #[test]
+    fn build_clients_use_build_credentials() {
+        let config = BuildConfig::local(".")
+            .registry_auth(RegistryAuth {
+                host: "ghcr.io".to_string(),
+                username: "robot".to_string(),
+                password: "secret".to_string(),
+                ..Default::default()
+            })
+            .insecure_registry("localhost:5000", true)
+            .insecure_registry("registry.example.com", false);
+        let client = RegistryClient::for_build(&config);
+
+        assert!(client.auth_for("ghcr.io", CredentialScope::Pull).is_some());
+        assert_eq!(
+            client.api_base("localhost:5000"),
+            "http://localhost:5000/v2"
+        );
+        assert_eq!(
+            client.api_base("registry.example.com"),
+            "https://registry.example.com/v2"
+        );
+    }
+
+    #[test]
+    fn build_clients_guess_insecure_tag_registries() {
+        let config = BuildConfig::local(".")
+            .tag("localhost:5000/app:latest")
+            .tag("registry:5000/app:latest")
+            .tag("ghcr.io/org/app:latest")
+            .insecure_registry("registry:5000", false);
+        let client = RegistryClient::for_build(&config);
+
+        assert_eq!(
+            client.api_base("localhost:5000"),
+            "http://localhost:5000/v2"
+        );
+        // Explicit settings take precedence over the guess
+        assert_eq!(client.api_base("registry:5000"), "https://registry:5000/v2");
+        assert_eq!(client.api_base("ghcr.io"), "https://ghcr.io/v2");
+    }
+
+    #[test]
+    fn verifies_sha256_digests() {
+        let digest = sha256_digest(b"hello");
+        assert!(verify_digest(b"hello", &digest).is_ok());
+        assert!(verify_digest(b"other", &digest).is_err());
+        assert!(verify_digest(b"other", "sha512:abc").is_ok());
+    }
+}
@@ -0,0 +1,311 @@
+//! Build graphs collected from the status stream
+//!
+//! Every build records the vertexes BuildKit reported: their inputs,
+//! timings and whether they were cached. [`BuildReport::to_dot`] and
+//! [`BuildReport::to_mermaid`] render them as a graph annotated with
+//! durations and cache hits, to see where a slow build spends its time.
+//!
+//! # Example
+//! ```no_run
+//! use buildkit_client::{BuildConfig, BuildKitClient};
+//!
+//! #[tokio::main]
+//! async fn main() -> anyhow::Result<()> {
+//!     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+//!     let result = client.build(BuildConfig::local("./my-app"), None).await?;
+//!     std::fs::write("build.dot", result.report.to_dot())?;
+//!     Ok(())
+//! }
+//! ```
+
+use crate::proto::moby::buildkit::v1::VertexWarning;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime};
+
+/// Fill colour of cached vertexes
+const CACHED_COLOR: &str = "#e0e0e0";
+/// Fill colour of failed vertexes
+const FAILED_COLOR: &str = "#f4b6b6";
+/// Fill colour of the slowest vertexes
+const SLOW_COLOR: &str = "#fde2a7";
+/// Vertexes taking at least this share of the longest one are highlighted
+const SLOW_SHARE: f64 = 0.5;
+
+/// Vertexes of a build, in the order BuildKit first reported them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildReport {
+    /// Reported vertexes
+    pub vertexes: Vec<VertexReport>,
+}
+
+/// One step of a build
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VertexReport {
+    /// Vertex digest
+    pub digest: String,
+    /// Step name, e.g. `[2/3] RUN make`
+    pub name: String,
+    /// Digests of the vertexes this one depends on
+    pub inputs: Vec<String>,
+    /// Whether the result came from the cache
+    pub cached: bool,
+    /// When the step started
+    pub started: Option<SystemTime>,
+    /// When the step completed
+    pub completed: Option<SystemTime>,
+    /// Error the step failed with
+    pub error: Option<String>,
+}
+
+impl VertexReport {
+    /// Time between the step starting and completing
+    pub fn duration(&self) -> Option<Duration> {
+        self.completed?.duration_since(self.started?).ok()
+    }
+
+    /// Duration, `cached` or `failed`, as shown in graphs
+    fn annotation(&self) -> Option<String> {
+        if self.error.is_some() {
+            Some("failed".to_string())
+        } else if self.cached {
+            Some("cached".to_string())
+        } else {
+            self.duration().map(format_duration)
+        }
+    }
+}
+
+/// How long one step of a build took
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StepTiming {
+    /// Step name, e.g. `[2/3] RUN make`
+    pub name: String,
+    /// Vertex digest
+    pub digest: String,
+    /// Whether the result came from the cache
+    pub cached: bool,
+    /// Time between the step starting and completing, `None` while it runs
+    pub duration: Option<Duration>,
+}
+
+/// Warning BuildKit reported about a build, e.g. a Dockerfile lint or
+/// deprecated syntax
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildWarning {
+    /// Digest of the vertex the warning is about
+    pub vertex: String,
+    /// Severity; BuildKit reports lint warnings at level 1
+    pub level: i64,
+    /// Short description, e.g. `JSONArgsRecommended: JSON arguments
+    /// recommended for CMD to prevent unintended behavior`
+    pub message: String,
+    /// Longer explanation, one entry per paragraph
+    pub detail: Vec<String>,
+    /// Link to documentation of the warning
+    pub url: Option<String>,
+    /// Source file the warning is about, e.g. `Dockerfile`
+    pub file: Option<String>,
+    /// Line of the source file, counting from 1
+    pub line: Option<i32>,
+}
+
+impl From<&VertexWarning> for BuildWarning {
+    fn from(warning: &VertexWarning) -> Self {
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim_end().to_string();
+        Self {
+            vertex: warning.vertex.clone(),
+            level: warning.level,
+            message: text(&warning.short),
+            detail: warning.detail.iter().map(|d| text(d)).collect(),
+            url: Some(warning.url.clone()).filter(|u| !u.is_empty()),
+            file: warning
+                .info
+                .as_ref()
+                .map(|info| info.filename.clone())
+                .filter(|f| !f.is_empty()),
+            line: warning
+                .ranges
+                .first()
+                .and_then(|range| range.start.as_ref())
+                .map(|start| start.line),
+        }
+    }
+}
+
+impl std::fmt::Display for BuildWarning {
+    /// `Dockerfile:3: message`, or the message alone without a location
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{}: {}", file, line, self.message),
+            (Some(file), None) => write!(f, "{}: {}", file, self.message),
+            _ => f.write_str(&self.message),
+        }
+    }
+}
+
+impl BuildReport {
+    /// Total time from the first step starting to the last completing
+    pub fn duration(&self) -> Option<Duration> {
+        let started = self.vertexes.iter().filter_map(|v| v.started).min()?;
+        let completed = self.vertexes.iter().filter_map(|v| v.completed).max()?;
+        completed.duration_since(started).ok()
+    }
+
+    /// Timings of the steps that started or came from the cache, slowest
+    /// first
+    pub fn step_timings(&self) -> Vec<StepTiming> {
+        let mut timings: Vec<StepTiming> = self
+            .vertexes
+            .iter()
+            .filter(|v| v.started.is_some() || v.cached)
+            .map(|v| StepTiming {
+                name: v.name.clone(),
+                digest: v.digest.clone(),
+                cached: v.cached,
+                duration: v.duration(),
+            })
+            .collect();
+        timings.sort_by_key(|t| std::cmp::Reverse(t.duration));
+        timings
+    }
+
+    /// Per-step timings, one line per step that started, slowest first
+    ///
+    /// Unfinished steps are listed as `running`, e.g. for a report of a
+    /// cancelled build.
+    pub fn timing_summary(&self) -> String {
+        let mut steps: Vec<&VertexReport> = self
+            .vertexes
+            .iter()
+            .filter(|v| v.started.is_some() || v.cached)
+            .collect();
+        steps.sort_by_key(|v| std::cmp::Reverse(v.duration()));
+
+        let mut summary = String::new();
+        for step in steps {
+            let status = match step.annotation() {
+                Some(annotation) => annotation,
+                None => "running".to_string(),
+            };
+            let _ = writeln!(summary, "{:>8}  {}", status, step.name);
+        }
+        if let Some(total) = self.duration() {
+            let _ = writeln!(summary, "{:>8}  total", format_duration(total));
+        }
+        summary
+    }
+
+    /// Render the build graph in Graphviz DOT format
+    ///
+    /// Edges point from inputs to the steps using them. Cached steps are
+    /// grey, failed ones red and the slowest ones orange.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph build {\n");
+        dot.push_str("    rankdir=TB;\n");
+        dot.push_str("    node [shape=box, style=\"rounded,filled\", fillcolor=\"white\", fontname=\"monospace\"];\n");
+
+        for vertex in &self.vertexes {
+            let mut label = vertex.name.clone();
+            if let Some(annotation) = vertex.annotation() {
+                label.push('\n');
+                label.push_str(&annotation);
+            }
+            let _ = write!(
+                dot,
+                "    \"{}\" [label=\"{}\"",
+                vertex.digest,
+                dot_escape(&label)
+            );
+            if let Some(color) = self.fill_color(vertex) {
+                let _ = write!(dot, ", fillcolor=\"{}\"", color);
+            }
+            dot.push_str("];\n");
+        }
+        for (from, to) in self.edges() {
+            let _ = writeln!(dot, "    \"{}\" -> \"{}\";", from, to);
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render the build graph as a Mermaid flowchart
+    pub fn to_mermaid(&self) -> String {
+        let mut mermaid = String::from("flowchart TB\n");
+        let id = |digest: &str| {
+            self.vertexes
+                .iter()
+                .position(|v| v.digest == digest)
+                .map(|i| format!("v{}", i))
+        };
+
+        for (i, vertex) in self.vertexes.iter().enumerate() {
+            let mut label = mermaid_escape(&vertex.name);
+            if let Some(annotation) = vertex.annotation() {
+                label.push_str("<br/>");
+                label.push_str(&annotation);
+            }
+            let _ = writeln!(mermaid, "    v{}[\"{}\"]", i, label);
+        }
+        for (from, to) in self.edges() {
+            if let (Some(from), Some(to)) = (id(from), id(to)) {
+                let _ = writeln!(mermaid, "    {} --> {}", from, to);
+            }
+        }
+        for (i, vertex) in self.vertexes.iter().enumerate() {
+            if let Some(color) = self.fill_color(vertex) {
+                let _ = writeln!(mermaid, "    style v{} fill:{}", i, color);
+            }
+        }
+        mermaid
+    }
+
+    /// Edges between reported vertexes, from input to user
+    fn edges(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vertexes.iter().flat_map(move |vertex| {
+            vertex
+                .inputs
+                .iter()
+                .filter(|input| self.vertexes.iter().any(|v| &v.digest == *input))
+                .map(move |input| (input.as_str(), vertex.digest.as_str()))
+        })
+    }
+
+    fn fill_color(&self, vertex: &VertexReport) -> Option<&'static str> {
+        if vertex.error.is_some() {
+            return Some(FAILED_COLOR);
+        }
+        if vertex.cached {
+            return Some(CACHED_COLOR);
+        }
+        let longest = self.vertexes.iter().filter_map(|v| v.duration()).max()?;
+        let duration = vertex.duration()?;
+        (!longest.is_zero() && duration.as_secs_f64() >= longest.as_secs_f64() * SLOW_SHARE)
+            .then_some(SLOW_COLOR)
+    }
+}
+
+/// Format a duration like `12.3s` or `2m05s`
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+    if secs < 60.0 {
+        format!("{:.1}s", secs)
+    } else {
+        let secs = duration.as_secs();
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+fn dot_escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn mermaid_escape(label: &str) -> String {
+    label
+        .replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+}
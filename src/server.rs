@@ -0,0 +1,525 @@
+//! HTTP/JSON API for running builds on a shared BuildKit connection
+//!
+//! Enabled with the `serve` feature. Builds are submitted as JSON specs,
+//! the same shape as a [`BatchBuild`], with contexts resolved inside the
+//! server's context root. Progress is streamed as server-sent events using
+//! the documents of [`JsonProgressHandler`](crate::progress::JsonProgressHandler).
+//!
+//! | Method | Path                  | Description                             |
+//! |--------|-----------------------|-----------------------------------------|
+//! | `POST` | `/builds`             | Submit a build, returns its ID          |
+//! | `GET`  | `/builds`             | List builds, newest first               |
+//! | `GET`  | `/builds/:id`         | Status and result of a build            |
+//! | `GET`  | `/builds/:id/events`  | Progress as server-sent events          |
+//! | `GET`  | `/health`             | BuildKit health                         |
+//! | `GET`  | `/metrics`            | Build and context upload metrics        |
+//!
+//! Metrics are in the Prometheus text format, see [`crate::metrics`]. The
+//! client's [`Metrics`] are served if it has some, otherwise the server
+//! records its builds in new ones.
+//!
+//! # Example
+//! ```no_run
+//! use buildkit_client::server::BuildServer;
+//! use buildkit_client::BuildKitClient;
+//!
+//! #[tokio::main]
+//! async fn main() -> anyhow::Result<()> {
+//!     let client = BuildKitClient::connect("http://localhost:1234").await?;
+//!     let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+//!     BuildServer::new(client, "/srv/contexts")
+//!         .max_concurrent(4)
+//!         .serve(listener)
+//!         .await?;
+//!     Ok(())
+//! }
+//! ```
+
+use crate::batch::BatchBuild;
+use crate::client::BuildKitClient;
+use crate::error::{Error, Result};
+use crate::metrics::Metrics;
+use crate::progress::{status_json, ProgressHandler};
+use crate::proto::moby::buildkit::v1::StatusResponse;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use std::collections::VecDeque;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, Semaphore};
+use tokio_stream::Stream;
+use uuid::Uuid;
+
+/// Finished builds kept for queries; older ones are forgotten
+const MAX_RETAINED_BUILDS: usize = 1000;
+
+/// Live progress events buffered per subscriber before it lags
+const EVENT_BUFFER: usize = 256;
+
+/// HTTP server running builds submitted as JSON
+pub struct BuildServer {
+    client: BuildKitClient,
+    context_root: PathBuf,
+    max_concurrent: usize,
+}
+
+impl BuildServer {
+    /// Create a server building contexts found under `context_root`
+    pub fn new(client: BuildKitClient, context_root: impl Into<PathBuf>) -> Self {
+        Self {
+            client,
+            context_root: context_root.into(),
+            max_concurrent: 4,
+        }
+    }
+
+    /// Maximum number of builds running at once; later ones are queued
+    pub fn max_concurrent(mut self, max: usize) -> Self {
+        self.max_concurrent = max.max(1);
+        self
+    }
+
+    /// The API as a router, for mounting into a larger application
+    pub fn router(self) -> Router {
+        let client = match self.client.metrics() {
+            Some(_) => self.client,
+            None => self.client.with_metrics(Arc::new(Metrics::new())),
+        };
+        let state = Arc::new(ServerState {
+            client,
+            context_root: self.context_root,
+            permits: Arc::new(Semaphore::new(self.max_concurrent)),
+            jobs: Mutex::new(VecDeque::new()),
+        });
+
+        Router::new()
+            .route("/builds", get(list_builds).post(submit_build))
+            .route("/builds/:id", get(get_build))
+            .route("/builds/:id/events", get(build_events))
+            .route("/health", get(health))
+            .route("/metrics", get(metrics))
+            .with_state(state)
+    }
+
+    /// Serve the API until the listener fails
+    pub async fn serve(self, listener: tokio::net::TcpListener) -> Result<()> {
+        if let Ok(addr) = listener.local_addr() {
+            tracing::info!("Serving build API on http://{}", addr);
+        }
+        axum::serve(listener, self.router()).await?;
+        Ok(())
+    }
+}
+
+struct ServerState {
+    client: BuildKitClient,
+    context_root: PathBuf,
+    permits: Arc<Semaphore>,
+    /// Builds, newest first
+    jobs: Mutex<VecDeque<Arc<Job>>>,
+}
+
+impl ServerState {
+    fn job(&self, id: &str) -> Option<Arc<Job>> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|job| job.id == id)
+            .cloned()
+    }
+
+    fn add_job(&self, job: Arc<Job>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.push_front(job);
+        while jobs.len() > MAX_RETAINED_BUILDS {
+            // Running builds are kept even past the limit
+            match jobs.iter().rposition(|job| job.is_finished()) {
+                Some(oldest) => {
+                    jobs.remove(oldest);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// Lifecycle state of a submitted build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl JobStatus {
+    fn name(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+        }
+    }
+}
+
+/// A submitted build and everything it reported so far
+struct Job {
+    id: String,
+    name: String,
+    submitted_at: u64,
+    state: Mutex<JobState>,
+    live: broadcast::Sender<serde_json::Value>,
+}
+
+struct JobState {
+    status: JobStatus,
+    /// Every progress event, replayed to new subscribers
+    events: Vec<serde_json::Value>,
+    digest: Option<String>,
+    error: Option<serde_json::Value>,
+}
+
+impl Job {
+    fn new(name: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name,
+            submitted_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            state: Mutex::new(JobState {
+                status: JobStatus::Queued,
+                events: Vec::new(),
+                digest: None,
+                error: None,
+            }),
+            live: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(
+            self.state.lock().unwrap().status,
+            JobStatus::Succeeded | JobStatus::Failed
+        )
+    }
+
+    /// Record a progress event and send it to current subscribers
+    fn push(&self, event: serde_json::Value) {
+        self.update(|_| event);
+    }
+
+    /// Change the state and record the resulting event under one lock, so
+    /// subscribers never see the new status without its event
+    fn update(&self, change: impl FnOnce(&mut JobState) -> serde_json::Value) {
+        let mut state = self.state.lock().unwrap();
+        let event = change(&mut state);
+        state.events.push(event.clone());
+        // Nobody may be listening
+        let _ = self.live.send(event);
+    }
+
+    fn start(&self) {
+        self.update(|state| {
+            state.status = JobStatus::Running;
+            serde_json::json!({ "status": "started" })
+        });
+    }
+
+    fn finish(&self, result: Result<crate::BuildResult>) {
+        self.update(|state| match result {
+            Ok(result) => {
+                state.status = JobStatus::Succeeded;
+                state.digest = result.digest;
+                serde_json::json!({ "status": "completed", "digest": state.digest })
+            }
+            Err(e) => {
+                let report = serde_json::to_value(e.to_report()).unwrap_or_default();
+                state.status = JobStatus::Failed;
+                state.error = Some(report.clone());
+                serde_json::json!({ "status": "failed", "error": report })
+            }
+        });
+    }
+
+    /// Events so far, and a receiver for the ones after them
+    fn subscribe(
+        &self,
+    ) -> (
+        Vec<serde_json::Value>,
+        bool,
+        broadcast::Receiver<serde_json::Value>,
+    ) {
+        let state = self.state.lock().unwrap();
+        let finished = matches!(state.status, JobStatus::Succeeded | JobStatus::Failed);
+        (state.events.clone(), finished, self.live.subscribe())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        serde_json::json!({
+            "id": self.id,
+            "name": self.name,
+            "status": state.status.name(),
+            "submitted_at": self.submitted_at,
+            "digest": state.digest,
+            "error": state.error,
+        })
+    }
+}
+
+/// Progress handler recording status updates on a job
+///
+/// Start and end are recorded by the job itself, so they are reported even
+/// when the build fails before progress monitoring begins.
+struct JobProgress(Arc<Job>);
+
+impl ProgressHandler for JobProgress {
+    fn on_start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_status(&mut self, status: StatusResponse) -> Result<()> {
+        self.0.push(status_json(&status));
+        Ok(())
+    }
+
+    fn on_complete(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_error(&mut self, _error: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Resolve a build context inside the server's context root
+///
+/// Absolute paths and `..` components are rejected, so clients can only
+/// build what the server exposes.
+fn resolve_context(root: &Path, context: &Path) -> Result<PathBuf> {
+    let escapes = context
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if escapes {
+        return Err(Error::PathOutsideRoot {
+            path: context.display().to_string(),
+        });
+    }
+    Ok(root.join(context))
+}
+
+/// Resolve a Dockerfile path inside a resolved build context
+///
+/// The Dockerfile's directory is served to BuildKit, so the same rules as
+/// for contexts apply, and symlinks may not lead out of the server's
+/// context root either.
+fn resolve_dockerfile(root: &Path, context: &Path, dockerfile: &str) -> Result<PathBuf> {
+    let outside = || Error::PathOutsideRoot {
+        path: dockerfile.to_string(),
+    };
+    let escapes = Path::new(dockerfile)
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if escapes {
+        return Err(outside());
+    }
+
+    let path = context.join(dockerfile);
+    let resolved = path.canonicalize().map_err(|_| Error::PathNotFound(path))?;
+    if !resolved.starts_with(root.canonicalize()?) {
+        return Err(outside());
+    }
+    Ok(resolved)
+}
+
+/// Error response carrying an [`ErrorReport`](crate::ErrorReport)
+struct ApiError(StatusCode, Error);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(self.1.to_report())).into_response()
+    }
+}
+
+fn not_found(id: &str) -> ApiError {
+    ApiError(
+        StatusCode::NOT_FOUND,
+        Error::other(format!("no build with id {}", id)),
+    )
+}
+
+async fn submit_build(
+    State(state): State<Arc<ServerState>>,
+    Json(spec): Json<BatchBuild>,
+) -> std::result::Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    let bad_request = |e| ApiError(StatusCode::BAD_REQUEST, e);
+    let context = resolve_context(&state.context_root, &spec.context).map_err(bad_request)?;
+    if let Some(dockerfile) = &spec.dockerfile {
+        resolve_dockerfile(&state.context_root, &context, dockerfile).map_err(bad_request)?;
+    }
+    let config = BatchBuild {
+        context,
+        ..spec.clone()
+    }
+    .build_config(".")
+    .map_err(bad_request)?;
+
+    let job = Arc::new(Job::new(spec.name()));
+    state.add_job(job.clone());
+    tracing::info!("Build {} ({}) submitted", job.id, job.name);
+
+    let response = job.to_json();
+    let client = state.client.clone();
+    let permits = state.permits.clone();
+    tokio::spawn(async move {
+        // The semaphore is never closed
+        let _permit = permits.acquire_owned().await;
+        job.start();
+        let progress = Box::new(JobProgress(job.clone()));
+        let result = client.build(config, Some(progress)).await;
+        if let Err(e) = &result {
+            tracing::warn!("Build {} failed: {}", job.id, e);
+        }
+        job.finish(result);
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(response)))
+}
+
+async fn list_builds(State(state): State<Arc<ServerState>>) -> Json<serde_json::Value> {
+    let jobs: Vec<Arc<Job>> = state.jobs.lock().unwrap().iter().cloned().collect();
+    Json(serde_json::json!({
+        "builds": jobs.iter().map(|job| job.to_json()).collect::<Vec<_>>(),
+    }))
+}
+
+async fn get_build(
+    State(state): State<Arc<ServerState>>,
+    UrlPath(id): UrlPath<String>,
+) -> std::result::Result<Json<serde_json::Value>, ApiError> {
+    let job = state.job(&id).ok_or_else(|| not_found(&id))?;
+    Ok(Json(job.to_json()))
+}
+
+async fn build_events(
+    State(state): State<Arc<ServerState>>,
+    UrlPath(id): UrlPath<String>,
+) -> std::result::Result<Sse<impl Stream<Item = std::result::Result<Event, axum::Error>>>, ApiError>
+{
+    let job = state.job(&id).ok_or_else(|| not_found(&id))?;
+    let (history, finished, mut live) = job.subscribe();
+
+    let events = async_stream::stream! {
+        for event in history {
+            yield Event::default().json_data(event);
+        }
+        if finished {
+            return;
+        }
+        loop {
+            match live.recv().await {
+                Ok(event) => {
+                    let done = event.get("status").is_some_and(|s| s == "completed" || s == "failed");
+                    yield Event::default().json_data(event);
+                    if done {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::debug!("Event subscriber of {} skipped {} events", id, skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    };
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+async fn health(
+    State(state): State<Arc<ServerState>>,
+) -> std::result::Result<Json<serde_json::Value>, ApiError> {
+    state
+        .client
+        .clone()
+        .health_check()
+        .await
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e))?;
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
+
+async fn metrics(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
+    let text = state
+        .client
+        .metrics()
+        .map(Metrics::to_prometheus)
+        .unwrap_or_default();
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contexts_stay_inside_the_root() {
+        let root = Path::new("/srv/contexts");
+        assert_eq!(
+            resolve_context(root, Path::new("team/app")).unwrap(),
+            PathBuf::from("/srv/contexts/team/app")
+        );
+        assert!(resolve_context(root, Path::new("./app")).is_ok());
+        assert!(resolve_context(root, Path::new("../etc")).is_err());
+        assert!(resolve_context(root, Path::new("app/../../etc")).is_err());
+        assert!(resolve_context(root, Path::new("/etc")).is_err());
+    }
+
+    #[test]
+    fn dockerfiles_stay_inside_the_root() {
+        let root = tempfile::tempdir().unwrap();
+        let context = root.path().join("app");
+        std::fs::create_dir_all(context.join("docker")).unwrap();
+        std::fs::write(context.join("docker/Dockerfile"), "FROM scratch\n").unwrap();
+
+        assert!(resolve_dockerfile(root.path(), &context, "docker/Dockerfile").is_ok());
+        assert!(resolve_dockerfile(root.path(), &context, "../app/Dockerfile").is_err());
+        assert!(resolve_dockerfile(root.path(), &context, "/etc/passwd").is_err());
+        assert!(resolve_dockerfile(root.path(), &context, "missing/Dockerfile").is_err());
+
+        #[cfg(unix)]
+        {
+            let outside = tempfile::tempdir().unwrap();
+            std::fs::write(outside.path().join("Dockerfile"), "FROM scratch\n").unwrap();
+            std::os::unix::fs::symlink(outside.path(), context.join("linked")).unwrap();
+            assert!(matches!(
+                resolve_dockerfile(root.path(), &context, "linked/Dockerfile"),
+                Err(Error::PathOutsideRoot { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribers_get_history_then_live_events() {
+        let job = Job::new("app".to_string());
+        job.start();
+
+        let (history, finished, mut live) = job.subscribe();
+        assert_eq!(history, vec![serde_json::json!({ "status": "started" })]);
+        assert!(!finished);
+
+        job.finish(Err(Error::other("boom")));
+        let event = live.recv().await.unwrap();
+        assert_eq!(event["status"], "failed");
+        assert_eq!(event["error"]["kind"], "other");
+        assert!(job.is_finished());
+        assert_eq!(job.to_json()["status"], "failed");
+    }
+}
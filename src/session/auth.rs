@@ -1,16 +1,33 @@
 //! Authentication protocol implementation for BuildKit sessions
 
+use super::DockerConfig;
+use crate::builder::CredentialScope;
 use crate::proto::moby::filesync::v1::{
     auth_server::Auth, CredentialsRequest, CredentialsResponse, FetchTokenRequest,
     FetchTokenResponse, GetTokenAuthorityRequest, GetTokenAuthorityResponse,
     VerifyTokenAuthorityRequest, VerifyTokenAuthorityResponse,
 };
+use crate::redact::{redact_option, Redacted};
+use crate::registry::{fetch_bearer_token, TokenCredentials};
+use hmac::{Hmac, Mac};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use tonic::{Request, Response, Status};
 
+/// Token lifetime assumed when the token endpoint does not send one, as
+/// the distribution spec prescribes
+const DEFAULT_TOKEN_LIFETIME: i64 = 60;
+/// Cached tokens are fetched again this long before they expire
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(10);
+
 /// Registry authentication configuration
 ///
 /// Stores credentials for authenticating with container registries.
-#[derive(Debug, Clone)]
+/// The `Debug` output never includes the password or tokens.
+#[derive(Clone, Default)]
 pub struct RegistryAuthConfig {
     /// Registry hostname (e.g., "docker.io", "ghcr.io", "localhost:5000")
     pub host: String,
@@ -18,14 +35,60 @@ pub struct RegistryAuthConfig {
     pub username: String,
     /// Password or access token for registry authentication
     pub password: String,
+    /// OAuth refresh token, passed to BuildKit in place of the password
+    pub identity_token: Option<String>,
+    /// Pre-fetched bearer token returned from FetchToken
+    pub registry_token: Option<String>,
+    /// Operations these credentials are used for
+    pub scope: CredentialScope,
+}
+
+impl std::fmt::Debug for RegistryAuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegistryAuthConfig")
+            .field("host", &self.host)
+            .field("username", &self.username)
+            .field("password", &Redacted::new(&self.password))
+            .field("identity_token", &redact_option(&self.identity_token))
+            .field("registry_token", &redact_option(&self.registry_token))
+            .field("scope", &self.scope)
+            .finish()
+    }
 }
 
 /// Auth server implementation for BuildKit session
 ///
 /// Handles registry authentication requests during image push operations.
+/// Registries without explicit credentials fall back to the Docker CLI
+/// configuration, when one was added. Bearer tokens are exchanged on the
+/// client and reused until shortly before they expire; clones share them.
 #[derive(Debug, Clone, Default)]
 pub struct AuthServer {
     registries: Vec<RegistryAuthConfig>,
+    docker_config: Option<DockerConfig>,
+    http: reqwest::Client,
+    tokens: Arc<Mutex<HashMap<String, CachedToken>>>,
+}
+
+/// A bearer token issued by a registry's token endpoint
+#[derive(Debug, Clone)]
+struct CachedToken {
+    token: Redacted<String>,
+    /// Issue time in seconds since the Unix epoch
+    issued_at: i64,
+    /// Lifetime in seconds
+    expires_in: i64,
+    expires_at: SystemTime,
+}
+
+impl CachedToken {
+    fn response(&self) -> FetchTokenResponse {
+        FetchTokenResponse {
+            token: self.token.expose().clone(),
+            expires_in: self.expires_in,
+            issued_at: self.issued_at,
+        }
+    }
 }
 
 impl AuthServer {
@@ -39,9 +102,7 @@ impl AuthServer {
     /// let auth = AuthServer::new();
     /// ```
     pub fn new() -> Self {
-        Self {
-            registries: Vec::new(),
-        }
+        Self::default()
     }
 
     /// Add registry credentials
@@ -60,19 +121,105 @@ impl AuthServer {
     ///     host: "docker.io".to_string(),
     ///     username: "myuser".to_string(),
     ///     password: "mytoken".to_string(),
+    ///     ..Default::default()
     /// });
     /// ```
     pub fn add_registry(&mut self, config: RegistryAuthConfig) {
         self.registries.push(config);
     }
 
-    fn find_credentials(&self, host: &str) -> Option<&RegistryAuthConfig> {
-        self.registries.iter().find(|r| {
-            r.host == host ||
-            host.contains(&r.host) ||
-            // Handle docker.io specially
-            (r.host == "docker.io" && (host == "registry-1.docker.io" || host == "index.docker.io"))
-        })
+    /// Look up registries without explicit credentials in a Docker CLI
+    /// configuration
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use buildkit_client::session::{AuthServer, DockerConfig};
+    ///
+    /// let mut auth = AuthServer::new();
+    /// auth.add_docker_config(DockerConfig::load()?);
+    /// # Ok::<(), buildkit_client::Error>(())
+    /// ```
+    pub fn add_docker_config(&mut self, config: DockerConfig) {
+        self.docker_config = Some(config);
+    }
+
+    /// Find the best-fitting credentials for a host and requested scope
+    fn find_credentials(&self, host: &str, scope: CredentialScope) -> Option<&RegistryAuthConfig> {
+        self.registries
+            .iter()
+            .filter(|r| {
+                r.host == host ||
+                host.contains(&r.host) ||
+                // Handle docker.io specially
+                (r.host == "docker.io" && (host == "registry-1.docker.io" || host == "index.docker.io"))
+            })
+            .filter_map(|r| r.scope.preference(scope).map(|rank| (rank, r)))
+            .min_by_key(|(rank, _)| *rank)
+            .map(|(_, r)| r)
+    }
+
+    /// A cached token for `key` that is not about to expire
+    fn cached_token(&self, key: &str, now: SystemTime) -> Option<CachedToken> {
+        let tokens = self.tokens.lock().unwrap();
+        tokens
+            .get(key)
+            .filter(|token| now + TOKEN_EXPIRY_MARGIN < token.expires_at)
+            .cloned()
+    }
+
+    /// Cache a token, dropping expired ones
+    fn cache_token(&self, key: String, token: CachedToken, now: SystemTime) {
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.retain(|_, cached| cached.expires_at > now);
+        tokens.insert(key, token);
+    }
+
+    /// Key BuildKit has the client sign registry tokens with
+    ///
+    /// Derived like the Docker CLI does, from an HMAC of the registry
+    /// secret keyed by BuildKit's salt: the same credentials give the same
+    /// key in every session, and the secret never leaves the client. Hosts
+    /// without credentials, or `BUILDKIT_NO_CLIENT_TOKEN=1`, make BuildKit
+    /// fall back to the Credentials flow.
+    async fn authority_key(&self, host: &str, salt: &[u8]) -> Result<Ed25519KeyPair, Status> {
+        let disabled = std::env::var("BUILDKIT_NO_CLIENT_TOKEN")
+            .is_ok_and(|v| matches!(v.as_str(), "1" | "true" | "TRUE" | "True"));
+        if disabled {
+            return Err(Status::unavailable("client side tokens disabled"));
+        }
+
+        let config = match self.find_credentials(host, CredentialScope::Any) {
+            Some(config) => Some(config.clone()),
+            None => self.docker_credentials(host).await,
+        };
+        let secret = config
+            .as_ref()
+            .map(|c| c.identity_token.as_deref().unwrap_or(&c.password))
+            .filter(|secret| !secret.is_empty())
+            .ok_or_else(|| Status::unavailable("no credentials available"))?;
+
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(salt).expect("HMAC accepts keys of any length");
+        mac.update(secret.as_bytes());
+        let seed = mac.finalize().into_bytes();
+        Ed25519KeyPair::from_seed_unchecked(&seed[..32])
+            .map_err(|_| Status::internal("failed to derive token authority key"))
+    }
+
+    /// Credentials for a host from the Docker CLI configuration
+    ///
+    /// Failing credential helpers are logged and treated as anonymous
+    /// access, so public images can still be pulled.
+    async fn docker_credentials(&self, host: &str) -> Option<RegistryAuthConfig> {
+        let docker_config = self.docker_config.as_ref()?;
+        match docker_config.credentials(host).await {
+            Ok(credentials) => credentials,
+            Err(e) => {
+                tracing::warn!("Docker credentials for {} unavailable: {}", host, e);
+                None
+            }
+        }
     }
 }
 
@@ -85,12 +232,26 @@ impl Auth for AuthServer {
         let req = request.into_inner();
         tracing::debug!("Credentials requested for host: {}", req.host);
 
-        if let Some(config) = self.find_credentials(&req.host) {
+        // The request does not say whether BuildKit pulls or pushes
+        let found = match self.find_credentials(&req.host, CredentialScope::Any) {
+            Some(config) => Some(config.clone()),
+            None => self.docker_credentials(&req.host).await,
+        };
+        if let Some(config) = found {
             tracing::debug!("Found credentials for host: {}", req.host);
-            Ok(Response::new(CredentialsResponse {
-                username: config.username.clone(),
-                secret: config.password.clone(),
-            }))
+            // An empty username tells BuildKit the secret is an identity
+            // token to be exchanged through the OAuth refresh-token flow
+            let response = match &config.identity_token {
+                Some(token) => CredentialsResponse {
+                    username: String::new(),
+                    secret: token.clone(),
+                },
+                None => CredentialsResponse {
+                    username: config.username.clone(),
+                    secret: config.password.clone(),
+                },
+            };
+            Ok(Response::new(response))
         } else {
             tracing::debug!("No credentials found for host: {}", req.host);
             // Return empty credentials (anonymous access)
@@ -114,32 +275,128 @@ impl Auth for AuthServer {
             req.scopes
         );
 
-        // For most cases, BuildKit will handle token exchange
-        // We just need to provide basic auth credentials via the Credentials RPC
-        Ok(Response::new(FetchTokenResponse {
-            token: String::new(),
-            expires_in: 0,
-            issued_at: 0,
-        }))
+        let scope = requested_scope(&req.scopes);
+        let config = match self.find_credentials(&req.host, scope) {
+            Some(config) => Some(config.clone()),
+            None => self.docker_credentials(&req.host).await,
+        };
+
+        let now = SystemTime::now();
+        let issued_at = now
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        // Hand out a pre-fetched registry token when one is configured
+        if let Some(token) = config.as_ref().and_then(|c| c.registry_token.as_ref()) {
+            tracing::debug!("Using registry token for host: {}", req.host);
+            return Ok(Response::new(FetchTokenResponse {
+                token: token.clone(),
+                expires_in: 0,
+                issued_at,
+            }));
+        }
+
+        // Without a token endpoint there is nothing to exchange
+        if req.realm.is_empty() {
+            return Ok(Response::new(FetchTokenResponse::default()));
+        }
+
+        let key = token_cache_key(&req);
+        if let Some(cached) = self.cached_token(&key, now) {
+            tracing::debug!("Reusing cached token for host: {}", req.host);
+            return Ok(Response::new(cached.response()));
+        }
+
+        // Hosts without credentials get an anonymous token
+        let credentials = config
+            .as_ref()
+            .filter(|c| !c.password.is_empty() || c.identity_token.is_some())
+            .map(|c| TokenCredentials {
+                username: &c.username,
+                password: &c.password,
+                identity_token: c.identity_token.as_deref(),
+            });
+        tracing::debug!(
+            "Fetching {} token for host: {}",
+            if credentials.is_some() {
+                "authenticated"
+            } else {
+                "anonymous"
+            },
+            req.host
+        );
+        let service = Some(req.service.as_str()).filter(|s| !s.is_empty());
+        let token = fetch_bearer_token(&self.http, &req.realm, service, &req.scopes, credentials)
+            .await
+            .map_err(|e| Status::unauthenticated(e.to_string()))?;
+
+        let expires_in = if token.expires_in > 0 {
+            token.expires_in
+        } else {
+            DEFAULT_TOKEN_LIFETIME
+        };
+        let cached = CachedToken {
+            token: token.token,
+            issued_at,
+            expires_in,
+            expires_at: now + Duration::from_secs(expires_in as u64),
+        };
+        let response = cached.response();
+        self.cache_token(key, cached, now);
+        Ok(Response::new(response))
     }
 
     async fn get_token_authority(
         &self,
-        _request: Request<GetTokenAuthorityRequest>,
+        request: Request<GetTokenAuthorityRequest>,
     ) -> Result<Response<GetTokenAuthorityResponse>, Status> {
-        // Not implementing token authority for now
+        let req = request.into_inner();
+        let key = self.authority_key(&req.host, &req.salt).await?;
         Ok(Response::new(GetTokenAuthorityResponse {
-            public_key: vec![],
+            public_key: key.public_key().as_ref().to_vec(),
         }))
     }
 
     async fn verify_token_authority(
         &self,
-        _request: Request<VerifyTokenAuthorityRequest>,
+        request: Request<VerifyTokenAuthorityRequest>,
     ) -> Result<Response<VerifyTokenAuthorityResponse>, Status> {
-        // Not implementing token authority for now
+        let req = request.into_inner();
+        let key = self.authority_key(&req.host, &req.salt).await?;
         Ok(Response::new(VerifyTokenAuthorityResponse {
-            signed: vec![],
+            signed: key.sign(&req.payload).as_ref().to_vec(),
         }))
     }
 }
+
+/// Tokens are issued per host, endpoint and set of scopes
+fn token_cache_key(req: &FetchTokenRequest) -> String {
+    let mut scopes: Vec<&str> = req.scopes.iter().map(String::as_str).collect();
+    scopes.sort_unstable();
+    format!(
+        "{}\n{}\n{}\n{}",
+        req.host,
+        req.realm,
+        req.service,
+        scopes.join(" ")
+    )
+}
+
+/// Determine whether a token request is for pulling or pushing
+///
+/// Scopes have the form `repository:<name>:<actions>`, e.g.
+/// `repository:library/alpine:pull,push`.
+fn requested_scope(scopes: &[String]) -> CredentialScope {
+    let push = scopes.iter().any(|scope| {
+        scope
+            .rsplit(':')
+            .next()
+            .is_some_and(|actions| actions.split(',').any(|a| a == "push" || a == "*"))
+    });
+    if push {
+        CredentialScope::Push
+    } else {
+        CredentialScope::Pull
+    }
+}
@@ -0,0 +1,235 @@
+//! Content hashes of context files, kept between builds
+//!
+//! Editors, `git checkout` and code generators often rewrite files without
+//! changing them. BuildKit decides which files to request by their size and
+//! modification time, so such a file is uploaded again although BuildKit
+//! already has its content. A [`ChangeCache`] remembers the size,
+//! modification time and SHA-256 of every context file sent. A file whose
+//! size and time match is not hashed again; one that was only touched is
+//! sent with the modification time BuildKit already knows, so it is not
+//! requested at all.
+//!
+//! One cache file per context root is kept in the cache directory, see
+//! [`FileSyncServer::with_change_cache`](super::FileSyncServer::with_change_cache).
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// A context file as it was last sent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedFile {
+    size: i64,
+    /// Modification time on disk, in nanoseconds since the Unix epoch
+    mod_time: i64,
+    /// Modification time sent to BuildKit for this content
+    sent_mod_time: i64,
+    /// Hex SHA-256 of the content
+    digest: String,
+}
+
+/// Cached content hashes of one context directory
+#[derive(Debug)]
+pub struct ChangeCache {
+    path: PathBuf,
+    files: HashMap<String, CachedFile>,
+    /// Paths looked up since the cache was opened
+    seen: HashSet<String>,
+}
+
+impl ChangeCache {
+    /// Open the cache of context `root` in `dir`
+    ///
+    /// A missing or unreadable cache file starts an empty cache.
+    pub fn open(dir: impl AsRef<Path>, root: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)
+            .map_err(|e| Error::file_operation("create directory", dir, e))?;
+
+        let root = root.as_ref();
+        let root = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let key = Sha256::digest(root.to_string_lossy().as_bytes());
+        let path = dir.join(format!("{:x}.json", key));
+
+        let files = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring malformed change cache {}: {}", path.display(), e);
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(Error::file_operation("read", &path, e)),
+        };
+        tracing::debug!(
+            "Loaded {} cached context files from {}",
+            files.len(),
+            path.display()
+        );
+        Ok(Self {
+            path,
+            files,
+            seen: HashSet::new(),
+        })
+    }
+
+    /// Path of the cache file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of cached files
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Whether no file is cached
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Digest and modification time to send for a file whose size and
+    /// modification time are unchanged, or `None` if it must be hashed
+    pub(super) fn lookup(
+        &mut self,
+        rel_path: &str,
+        size: i64,
+        mod_time: i64,
+    ) -> Option<(&str, i64)> {
+        self.seen.insert(rel_path.to_string());
+        self.files
+            .get(rel_path)
+            .filter(|cached| cached.size == size && cached.mod_time == mod_time)
+            .map(|cached| (cached.digest.as_str(), cached.sent_mod_time))
+    }
+
+    /// Record a freshly hashed file, returning the modification time to send
+    ///
+    /// Content that did not change keeps the time it was sent with before.
+    pub(super) fn update(
+        &mut self,
+        rel_path: &str,
+        size: i64,
+        mod_time: i64,
+        digest: String,
+    ) -> i64 {
+        self.seen.insert(rel_path.to_string());
+        let sent_mod_time = match self.files.get(rel_path) {
+            Some(cached) if cached.size == size && cached.digest == digest => cached.sent_mod_time,
+            _ => mod_time,
+        };
+        self.files.insert(
+            rel_path.to_string(),
+            CachedFile {
+                size,
+                mod_time,
+                sent_mod_time,
+                digest,
+            },
+        );
+        sent_mod_time
+    }
+
+    /// Modification time to send for a regular file, hashing it if its size
+    /// or modification time changed
+    pub(super) async fn settle(
+        &mut self,
+        rel_path: &str,
+        path: &Path,
+        size: i64,
+        mod_time: i64,
+    ) -> Result<i64> {
+        if let Some((_, sent_mod_time)) = self.lookup(rel_path, size, mod_time) {
+            return Ok(sent_mod_time);
+        }
+        let path = path.to_path_buf();
+        let digest = tokio::task::spawn_blocking(move || file_digest(&path))
+            .await
+            .map_err(|e| Error::other(format!("hash task failed: {}", e)))??;
+        Ok(self.update(rel_path, size, mod_time, digest))
+    }
+
+    /// Write the cache back to disk
+    ///
+    /// With `prune`, files not looked up since the cache was opened are
+    /// dropped; pass it after walking the whole context. The file is
+    /// replaced atomically, so concurrent builds of the same context never
+    /// read a partial cache.
+    pub fn save(&mut self, prune: bool) -> Result<()> {
+        if prune {
+            let seen = &self.seen;
+            self.files.retain(|path, _| seen.contains(path));
+        }
+        let content = serde_json::to_vec(&self.files)
+            .map_err(|e| Error::other(format!("Failed to serialize change cache: {}", e)))?;
+        let partial = self
+            .path
+            .with_extension(format!("json.{}", std::process::id()));
+        std::fs::write(&partial, content)
+            .map_err(|e| Error::file_operation("write", &partial, e))?;
+        std::fs::rename(&partial, &self.path)
+            .map_err(|e| Error::file_operation("write", &self.path, e))
+    }
+}
+
+/// Hex SHA-256 of a file's content
+pub(super) fn file_digest(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path).map_err(|e| Error::file_operation("open", path, e))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(|e| Error::file_operation("read", path, e))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn touched_files_keep_their_sent_time() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let context = tempfile::tempdir().unwrap();
+
+        let mut cache = ChangeCache::open(cache_dir.path(), context.path()).unwrap();
+        assert_eq!(cache.update("a.txt", 4, 100, "abc".to_string()), 100);
+        assert_eq!(cache.lookup("a.txt", 4, 100), Some(("abc", 100)));
+        // Rewritten with the same content
+        assert_eq!(cache.lookup("a.txt", 4, 200), None);
+        assert_eq!(cache.update("a.txt", 4, 200, "abc".to_string()), 100);
+        assert_eq!(cache.lookup("a.txt", 4, 200), Some(("abc", 100)));
+        // Changed
+        assert_eq!(cache.update("a.txt", 4, 300, "def".to_string()), 300);
+        cache.save(false).unwrap();
+
+        let mut reopened = ChangeCache::open(cache_dir.path(), context.path()).unwrap();
+        assert_eq!(reopened.lookup("a.txt", 4, 300), Some(("def", 300)));
+    }
+
+    #[test]
+    fn pruning_drops_files_not_seen() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let context = tempfile::tempdir().unwrap();
+
+        let mut cache = ChangeCache::open(cache_dir.path(), context.path()).unwrap();
+        cache.update("kept", 1, 1, "k".to_string());
+        cache.update("removed", 1, 1, "r".to_string());
+        cache.save(false).unwrap();
+
+        let mut cache = ChangeCache::open(cache_dir.path(), context.path()).unwrap();
+        assert!(cache.lookup("kept", 1, 1).is_some());
+        cache.save(true).unwrap();
+
+        let cache = ChangeCache::open(cache_dir.path(), context.path()).unwrap();
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn malformed_cache_starts_empty() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let context = tempfile::tempdir().unwrap();
+        let cache = ChangeCache::open(cache_dir.path(), context.path()).unwrap();
+        std::fs::write(cache.path(), "not json").unwrap();
+
+        let cache = ChangeCache::open(cache_dir.path(), context.path()).unwrap();
+        assert!(cache.is_empty());
+    }
+}
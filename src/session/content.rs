@@ -0,0 +1,781 @@
+//! Content stores for local cache import and export
+//!
+//! A `type=local` cache lives in an OCI image layout on the client. BuildKit
+//! reads and writes its blobs through the containerd content API over the
+//! session, naming the store `local:<dir>` after the cache's `src` or
+//! `dest` attribute. After an export, the client tags the new cache
+//! manifest in the layout's `index.json` so the next import can find it.
+
+use crate::error::{Error, Result};
+use crate::proto::containerd::services::content::v1::{
+    AbortRequest, DeleteContentRequest, Info, InfoRequest, InfoResponse, ListContentResponse,
+    ListStatusesResponse, ReadContentRequest, ReadContentResponse, Status as IngestStatus,
+    StatusRequest, StatusResponse, UpdateRequest, UpdateResponse, WriteAction, WriteContentRequest,
+    WriteContentResponse,
+};
+use bytes::Bytes;
+use h2::server::SendResponse;
+use http::{HeaderMap, HeaderValue, Response, StatusCode};
+use prost::Message as ProstMessage;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tonic::Status;
+
+use super::diffcopy::send_with_capacity;
+use super::filesend::{grpc_message, MessageReader};
+
+/// Header naming the content store a request is for
+pub(super) const STORE_ID_HEADER: &str = "buildkit-attachable-content-store-id";
+
+/// Bytes sent per message of a Read stream
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Annotation tagging a manifest in an OCI index
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+/// Tag of the cache manifest in a local cache, as buildx uses
+const LOCAL_CACHE_TAG: &str = "latest";
+
+type ContentResult<T> = std::result::Result<T, Status>;
+
+/// Content store server for local caches
+///
+/// Holds the OCI layout directories of a build's `type=local` cache
+/// imports and exports, by store ID.
+#[derive(Debug, Clone, Default)]
+pub struct ContentStoreServer {
+    stores: HashMap<String, PathBuf>,
+}
+
+impl ContentStoreServer {
+    /// Create a server without stores
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve the OCI layout in `dir`, as given in a local cache's `src` or
+    /// `dest` attribute
+    ///
+    /// The directory is created when BuildKit first writes to it.
+    pub fn with_local_store(mut self, dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        self.stores.insert(format!("local:{}", dir.display()), dir);
+        self
+    }
+
+    /// Directory of the store with the given ID
+    pub fn store(&self, id: &str) -> Option<&Path> {
+        self.stores.get(id).map(PathBuf::as_path)
+    }
+
+    /// Whether no stores are served
+    pub fn is_empty(&self) -> bool {
+        self.stores.is_empty()
+    }
+}
+
+/// Handle a call to the containerd content service
+///
+/// Unary and streaming methods alike answer with a stream of messages
+/// followed by the call's status in the trailers.
+pub(super) async fn handle_content_stream(
+    content: &ContentStoreServer,
+    method: &str,
+    request_stream: h2::RecvStream,
+    mut respond: SendResponse<Bytes>,
+    store_id: Option<String>,
+) -> Result<()> {
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/grpc")
+        .body(())
+        .unwrap();
+
+    let mut send_stream = respond
+        .send_response(response, false)
+        .map_err(|e| Error::Http2Stream { source: e })?;
+
+    let mut reader = MessageReader::new(request_stream);
+    let store = store_id
+        .as_deref()
+        .and_then(|id| content.store(id))
+        .map(|root| LocalStore {
+            root: root.to_path_buf(),
+        });
+    let result = match store {
+        Some(store) => {
+            let name = method.rsplit('/').next().unwrap_or_default();
+            store.call(name, &mut reader, &mut send_stream).await
+        }
+        None => Err(Status::not_found(format!(
+            "content store {} not found",
+            store_id.unwrap_or_default()
+        ))),
+    };
+
+    let mut trailers = HeaderMap::new();
+    match &result {
+        Ok(()) => {
+            trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        }
+        Err(status) => {
+            // Missing blobs are expected, e.g. when checking before a write
+            if status.code() == tonic::Code::NotFound {
+                tracing::debug!("{} failed: {}", method, status.message());
+            } else {
+                tracing::error!("{} failed: {}", method, status.message());
+            }
+            trailers.insert("grpc-status", HeaderValue::from(status.code() as i32));
+            if let Ok(message) = HeaderValue::from_str(&grpc_message(status.message())) {
+                trailers.insert("grpc-message", message);
+            }
+        }
+    }
+    send_stream
+        .send_trailers(trailers)
+        .map_err(|e| Error::Http2Stream { source: e })?;
+
+    Ok(())
+}
+
+/// Digest of the cache manifest tagged in the OCI layout at `dir`, if any
+pub(crate) fn local_cache_digest(dir: &Path) -> Option<String> {
+    let index = std::fs::read(dir.join("index.json")).ok()?;
+    let index: serde_json::Value = serde_json::from_slice(&index).ok()?;
+    index["manifests"]
+        .as_array()?
+        .iter()
+        .find(|manifest| manifest["annotations"][REF_NAME_ANNOTATION] == LOCAL_CACHE_TAG)
+        .and_then(|manifest| manifest["digest"].as_str())
+        .map(str::to_string)
+}
+
+/// Tag the cache manifest `descriptor` in the OCI layout at `dir`,
+/// replacing the previously tagged one
+pub(crate) fn tag_local_cache(dir: &Path, descriptor: &serde_json::Value) -> Result<()> {
+    let index_path = dir.join("index.json");
+    let mut index = match std::fs::read(&index_path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .map_err(|e| Error::other(format!("Invalid {}: {}", index_path.display(), e)))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": [],
+        }),
+        Err(e) => return Err(Error::file_operation("read", index_path, e)),
+    };
+
+    let mut descriptor = descriptor.clone();
+    descriptor["annotations"][REF_NAME_ANNOTATION] = LOCAL_CACHE_TAG.into();
+    let mut manifests: Vec<serde_json::Value> = index["manifests"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|manifest| manifest["annotations"][REF_NAME_ANNOTATION] != LOCAL_CACHE_TAG)
+        .collect();
+    manifests.push(descriptor);
+    index["manifests"] = manifests.into();
+
+    std::fs::create_dir_all(dir).map_err(|e| Error::file_operation("create", dir, e))?;
+    let layout_path = dir.join("oci-layout");
+    if !layout_path.exists() {
+        std::fs::write(&layout_path, r#"{"imageLayoutVersion":"1.0.0"}"#)
+            .map_err(|e| Error::file_operation("write", &layout_path, e))?;
+    }
+    let index = serde_json::to_vec(&index)
+        .map_err(|e| Error::other(format!("Failed to serialize OCI index: {}", e)))?;
+    std::fs::write(&index_path, index).map_err(|e| Error::file_operation("write", &index_path, e))
+}
+
+/// An OCI image layout, with blobs under `blobs/sha256` and unfinished
+/// writes under `ingest`
+struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    async fn call(
+        &self,
+        method: &str,
+        reader: &mut MessageReader,
+        send_stream: &mut h2::SendStream<Bytes>,
+    ) -> ContentResult<()> {
+        match method {
+            "Info" => {
+                let request: InfoRequest = unary_request(reader).await?;
+                let info = self.info(&request.digest).await?;
+                send(send_stream, &InfoResponse { info: Some(info) }).await
+            }
+            "Update" => {
+                // Labels are not kept
+                let request: UpdateRequest = unary_request(reader).await?;
+                let digest = request.info.map(|info| info.digest).unwrap_or_default();
+                let info = self.info(&digest).await?;
+                send(send_stream, &UpdateResponse { info: Some(info) }).await
+            }
+            "List" => {
+                let info = self.list().await?;
+                send(send_stream, &ListContentResponse { info }).await
+            }
+            "Delete" => {
+                let request: DeleteContentRequest = unary_request(reader).await?;
+                let path = self
+                    .blob_path(&request.digest)
+                    .ok_or_else(|| invalid_digest(&request.digest))?;
+                tokio::fs::remove_file(&path)
+                    .await
+                    .map_err(|e| io_status(e, &request.digest))?;
+                send(send_stream, &()).await
+            }
+            "Read" => {
+                let request: ReadContentRequest = unary_request(reader).await?;
+                self.read(request, send_stream).await
+            }
+            "Status" => {
+                let request: StatusRequest = unary_request(reader).await?;
+                let status = self.status(&request.r#ref).await?;
+                send(
+                    send_stream,
+                    &StatusResponse {
+                        status: Some(status),
+                    },
+                )
+                .await
+            }
+            "ListStatuses" => {
+                let statuses = self.list_statuses().await?;
+                send(send_stream, &ListStatusesResponse { statuses }).await
+            }
+            "Write" => self.write(reader, send_stream).await,
+            "Abort" => {
+                let request: AbortRequest = unary_request(reader).await?;
+                tokio::fs::remove_dir_all(self.ingest_dir(&request.r#ref))
+                    .await
+                    .map_err(|e| io_status(e, &request.r#ref))?;
+                send(send_stream, &()).await
+            }
+            _ => Err(Status::unimplemented(format!(
+                "content method {} is not supported",
+                method
+            ))),
+        }
+    }
+
+    /// Path of the blob `digest`, if it is a valid sha256 digest
+    fn blob_path(&self, digest: &str) -> Option<PathBuf> {
+        let hex = digest.strip_prefix("sha256:").filter(|hex| {
+            hex.len() == 64
+                && hex
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        })?;
+        Some(self.root.join("blobs").join("sha256").join(hex))
+    }
+
+    fn ingest_dir(&self, reference: &str) -> PathBuf {
+        self.root
+            .join("ingest")
+            .join(format!("{:x}", Sha256::digest(reference)))
+    }
+
+    async fn info(&self, digest: &str) -> ContentResult<Info> {
+        let path = self
+            .blob_path(digest)
+            .ok_or_else(|| invalid_digest(digest))?;
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .map_err(|e| io_status(e, digest))?;
+        let modified = metadata.modified().ok().map(Into::into);
+        Ok(Info {
+            digest: digest.to_string(),
+            size: metadata.len() as i64,
+            created_at: modified,
+            updated_at: modified,
+            labels: HashMap::new(),
+        })
+    }
+
+    async fn list(&self) -> ContentResult<Vec<Info>> {
+        let mut infos = Vec::new();
+        let mut entries = match tokio::fs::read_dir(self.root.join("blobs").join("sha256")).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(infos),
+            Err(e) => return Err(internal(e)),
+        };
+        while let Some(entry) = entries.next_entry().await.map_err(internal)? {
+            let digest = format!("sha256:{}", entry.file_name().to_string_lossy());
+            if let Ok(info) = self.info(&digest).await {
+                infos.push(info);
+            }
+        }
+        Ok(infos)
+    }
+
+    async fn read(
+        &self,
+        request: ReadContentRequest,
+        send_stream: &mut h2::SendStream<Bytes>,
+    ) -> ContentResult<()> {
+        let path = self
+            .blob_path(&request.digest)
+            .ok_or_else(|| invalid_digest(&request.digest))?;
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| io_status(e, &request.digest))?;
+        let mut offset = request.offset.max(0);
+        file.seek(SeekFrom::Start(offset as u64))
+            .await
+            .map_err(internal)?;
+
+        let mut remaining = if request.size > 0 {
+            request.size as u64
+        } else {
+            u64::MAX
+        };
+        let mut buffer = vec![0u8; READ_CHUNK_SIZE];
+        while remaining > 0 {
+            let len = buffer.len().min(remaining.try_into().unwrap_or(usize::MAX));
+            let n = file.read(&mut buffer[..len]).await.map_err(internal)?;
+            if n == 0 {
+                break;
+            }
+            let response = ReadContentResponse {
+                offset,
+                data: buffer[..n].to_vec(),
+            };
+            send(send_stream, &response).await?;
+            offset += n as i64;
+            remaining -= n as u64;
+        }
+        Ok(())
+    }
+
+    async fn status(&self, reference: &str) -> ContentResult<IngestStatus> {
+        let data = self.ingest_dir(reference).join("data");
+        let metadata = tokio::fs::metadata(&data)
+            .await
+            .map_err(|e| io_status(e, reference))?;
+        Ok(IngestStatus {
+            started_at: metadata.created().ok().map(Into::into),
+            updated_at: metadata.modified().ok().map(Into::into),
+            r#ref: reference.to_string(),
+            offset: metadata.len() as i64,
+            total: 0,
+            expected: String::new(),
+        })
+    }
+
+    async fn list_statuses(&self) -> ContentResult<Vec<IngestStatus>> {
+        let mut statuses = Vec::new();
+        let mut entries = match tokio::fs::read_dir(self.root.join("ingest")).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(statuses),
+            Err(e) => return Err(internal(e)),
+        };
+        while let Some(entry) = entries.next_entry().await.map_err(internal)? {
+            let Ok(reference) = tokio::fs::read_to_string(entry.path().join("ref")).await else {
+                continue;
+            };
+            if let Ok(status) = self.status(&reference).await {
+                statuses.push(status);
+            }
+        }
+        Ok(statuses)
+    }
+
+    /// Serve a Write stream: the first message names the ref, later ones
+    /// write data until a commit moves it to its digest
+    async fn write(
+        &self,
+        reader: &mut MessageReader,
+        send_stream: &mut h2::SendStream<Bytes>,
+    ) -> ContentResult<()> {
+        let mut ingest: Option<Ingest> = None;
+        while let Some(request) = reader
+            .next::<WriteContentRequest>()
+            .await
+            .map_err(internal)?
+        {
+            let action = WriteAction::try_from(request.action).map_err(|_| {
+                Status::invalid_argument(format!("unknown write action {}", request.action))
+            })?;
+            if ingest.is_none() {
+                if request.r#ref.is_empty() {
+                    return Err(Status::invalid_argument("first write must name a ref"));
+                }
+                ingest = Some(self.open_ingest(&request.r#ref, &request.expected).await?);
+            }
+            let Some(current) = ingest.as_mut() else {
+                continue;
+            };
+            if request.total > 0 {
+                current.total = request.total;
+            }
+            if !request.expected.is_empty() {
+                current.expected = request.expected.clone();
+            }
+            if action != WriteAction::Stat && !request.data.is_empty() {
+                current.write_at(request.offset, &request.data).await?;
+            }
+
+            let digest = match action {
+                WriteAction::Commit => self.commit(current).await?,
+                _ => current.digest(),
+            };
+            let response = WriteContentResponse {
+                action: action as i32,
+                started_at: Some(current.started.into()),
+                updated_at: Some(current.updated.into()),
+                offset: current.offset,
+                total: current.total,
+                digest,
+            };
+            send(send_stream, &response).await?;
+            if action == WriteAction::Commit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start or resume the write of `reference`
+    async fn open_ingest(&self, reference: &str, expected: &str) -> ContentResult<Ingest> {
+        let existing = match expected {
+            "" => None,
+            expected => self.blob_path(expected),
+        };
+        if existing.is_some_and(|path| path.exists()) {
+            return Err(Status::already_exists(format!(
+                "content {} already exists",
+                expected
+            )));
+        }
+
+        let dir = self.ingest_dir(reference);
+        tokio::fs::create_dir_all(&dir).await.map_err(internal)?;
+        tokio::fs::write(dir.join("ref"), reference)
+            .await
+            .map_err(internal)?;
+        let mut file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join("data"))
+            .await
+            .map_err(internal)?;
+        let offset = file.metadata().await.map_err(internal)?.len() as i64;
+        let hasher = hash_prefix(&mut file, offset).await?;
+
+        let now = SystemTime::now();
+        Ok(Ingest {
+            dir,
+            file,
+            offset,
+            total: 0,
+            expected: expected.to_string(),
+            hasher,
+            started: now,
+            updated: now,
+        })
+    }
+
+    /// Verify a finished write and move it to its digest
+    async fn commit(&self, ingest: &mut Ingest) -> ContentResult<String> {
+        ingest.file.flush().await.map_err(internal)?;
+        if ingest.total > 0 && ingest.offset != ingest.total {
+            return Err(Status::failed_precondition(format!(
+                "unexpected commit size {}, expected {}",
+                ingest.offset, ingest.total
+            )));
+        }
+        let digest = ingest.digest();
+        if !ingest.expected.is_empty() && digest != ingest.expected {
+            return Err(Status::failed_precondition(format!(
+                "unexpected commit digest {}, expected {}",
+                digest, ingest.expected
+            )));
+        }
+
+        let target = self
+            .blob_path(&digest)
+            .ok_or_else(|| invalid_digest(&digest))?;
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(internal)?;
+        }
+        tokio::fs::rename(ingest.dir.join("data"), &target)
+            .await
+            .map_err(internal)?;
+        let _ = tokio::fs::remove_dir_all(&ingest.dir).await;
+        Ok(digest)
+    }
+}
+
+/// An unfinished write
+struct Ingest {
+    dir: PathBuf,
+    file: tokio::fs::File,
+    offset: i64,
+    total: i64,
+    expected: String,
+    /// Hash of the data up to `offset`
+    hasher: Sha256,
+    started: SystemTime,
+    updated: SystemTime,
+}
+
+impl Ingest {
+    /// Write `data` at `offset`, dropping anything written after it
+    async fn write_at(&mut self, offset: i64, data: &[u8]) -> ContentResult<()> {
+        if offset < 0 || offset > self.offset {
+            return Err(Status::out_of_range(format!(
+                "write at offset {} beyond {} bytes written",
+                offset, self.offset
+            )));
+        }
+        if offset < self.offset {
+            self.file.set_len(offset as u64).await.map_err(internal)?;
+            self.hasher = hash_prefix(&mut self.file, offset).await?;
+            self.offset = offset;
+        }
+
+        self.file
+            .seek(SeekFrom::Start(offset as u64))
+            .await
+            .map_err(internal)?;
+        self.file.write_all(data).await.map_err(internal)?;
+        self.hasher.update(data);
+        self.offset += data.len() as i64;
+        self.updated = SystemTime::now();
+        Ok(())
+    }
+
+    fn digest(&self) -> String {
+        format!("sha256:{:x}", self.hasher.clone().finalize())
+    }
+}
+
+/// Hash of the first `len` bytes of `file`
+async fn hash_prefix(file: &mut tokio::fs::File, len: i64) -> ContentResult<Sha256> {
+    let mut hasher = Sha256::new();
+    file.seek(SeekFrom::Start(0)).await.map_err(internal)?;
+    let mut remaining = len as u64;
+    let mut buffer = vec![0u8; READ_CHUNK_SIZE];
+    while remaining > 0 {
+        let chunk = buffer.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let n = file.read(&mut buffer[..chunk]).await.map_err(internal)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        remaining -= n as u64;
+    }
+    Ok(hasher)
+}
+
+async fn unary_request<M: ProstMessage + Default>(reader: &mut MessageReader) -> ContentResult<M> {
+    reader
+        .next()
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| Status::invalid_argument("missing request message"))
+}
+
+async fn send(
+    send_stream: &mut h2::SendStream<Bytes>,
+    message: &impl ProstMessage,
+) -> ContentResult<()> {
+    let payload = message.encode_to_vec();
+    let mut framed = Vec::with_capacity(5 + payload.len());
+    framed.push(0); // No compression
+    framed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    framed.extend_from_slice(&payload);
+    send_with_capacity(send_stream, Bytes::from(framed))
+        .await
+        .map_err(internal)
+}
+
+fn invalid_digest(digest: &str) -> Status {
+    Status::invalid_argument(format!("unsupported digest {}", digest))
+}
+
+fn io_status(error: std::io::Error, what: &str) -> Status {
+    if error.kind() == std::io::ErrorKind::NotFound {
+        Status::not_found(format!("content {} not found", what))
+    } else {
+        internal(error)
+    }
+}
+
+fn internal(error: impl std::fmt::Display) -> Status {
+    Status::internal(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message: &impl ProstMessage) -> Bytes {
+        let payload = message.encode_to_vec();
+        let mut framed = vec![0];
+        framed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        framed.extend_from_slice(&payload);
+        Bytes::from(framed)
+    }
+
+    /// Call `method` of the store in `dir` with `requests`, returning the
+    /// responses and the grpc-status
+    async fn call<M: ProstMessage + Default>(
+        dir: &Path,
+        method: &'static str,
+        requests: Vec<Bytes>,
+    ) -> (Vec<M>, String) {
+        let content = ContentStoreServer::new().with_local_store(dir);
+        let store_id = format!("local:{}", dir.display());
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+
+        let server = tokio::spawn(async move {
+            let mut connection = h2::server::handshake(server_io).await.unwrap();
+            let (request, respond) = connection.accept().await.unwrap().unwrap();
+            tokio::spawn(async move { while connection.accept().await.is_some() {} });
+            let store_id = request
+                .headers()
+                .get(STORE_ID_HEADER)
+                .map(|v| v.to_str().unwrap().to_string());
+            handle_content_stream(&content, method, request.into_body(), respond, store_id).await
+        });
+
+        let (client, connection) = h2::client::handshake(client_io).await.unwrap();
+        tokio::spawn(connection);
+        let request = http::Request::builder()
+            .uri(method)
+            .header(STORE_ID_HEADER, store_id)
+            .body(())
+            .unwrap();
+        let (response, mut send) = client
+            .ready()
+            .await
+            .unwrap()
+            .send_request(request, false)
+            .unwrap();
+        for request in requests {
+            send.send_data(request, false).unwrap();
+        }
+        let _ = send.send_data(Bytes::new(), true);
+
+        let mut reader = MessageReader::new(response.await.unwrap().into_body());
+        let mut responses = Vec::new();
+        while let Some(response) = reader.next::<M>().await.unwrap() {
+            responses.push(response);
+        }
+        let trailers = reader.stream.trailers().await.unwrap().unwrap();
+        server.await.unwrap().unwrap();
+        let status = trailers["grpc-status"].to_str().unwrap().to_string();
+        (responses, status)
+    }
+
+    fn write_request(action: WriteAction, offset: i64, data: &[u8]) -> Bytes {
+        frame(&WriteContentRequest {
+            action: action as i32,
+            r#ref: "layer".to_string(),
+            offset,
+            data: data.to_vec(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn written_blobs_are_committed_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let digest = format!("sha256:{:x}", Sha256::digest(b"hello world"));
+
+        let (responses, status) = call::<WriteContentResponse>(
+            dir.path(),
+            "/containerd.services.content.v1.Content/Write",
+            vec![
+                write_request(WriteAction::Stat, 0, b""),
+                write_request(WriteAction::Write, 0, b"hello"),
+                write_request(WriteAction::Commit, 5, b" world"),
+            ],
+        )
+        .await;
+        assert_eq!(status, "0");
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[1].offset, 5);
+        assert_eq!(responses[2].digest, digest);
+        let mut ingests = std::fs::read_dir(dir.path().join("ingest")).unwrap();
+        assert!(ingests.next().is_none());
+
+        let (responses, status) = call::<ReadContentResponse>(
+            dir.path(),
+            "/containerd.services.content.v1.Content/Read",
+            vec![frame(&ReadContentRequest {
+                digest: digest.clone(),
+                offset: 6,
+                size: 0,
+            })],
+        )
+        .await;
+        assert_eq!(status, "0");
+        assert_eq!(responses[0].offset, 6);
+        assert_eq!(responses[0].data, b"world");
+
+        // Writing the blob again is refused so BuildKit skips it
+        let request = WriteContentRequest {
+            r#ref: "again".to_string(),
+            expected: digest,
+            ..Default::default()
+        };
+        let (_, status) = call::<WriteContentResponse>(
+            dir.path(),
+            "/containerd.services.content.v1.Content/Write",
+            vec![frame(&request)],
+        )
+        .await;
+        assert_eq!(status, (tonic::Code::AlreadyExists as i32).to_string());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn missing_blobs_are_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let request = InfoRequest {
+            digest: format!("sha256:{:x}", Sha256::digest(b"missing")),
+        };
+        let (responses, status) = call::<InfoResponse>(
+            dir.path(),
+            "/containerd.services.content.v1.Content/Info",
+            vec![frame(&request)],
+        )
+        .await;
+        assert!(responses.is_empty());
+        assert_eq!(status, (tonic::Code::NotFound as i32).to_string());
+    }
+
+    #[test]
+    fn cache_manifest_is_tagged_in_index() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(local_cache_digest(dir.path()), None);
+
+        for digest in ["sha256:aaa", "sha256:bbb"] {
+            let descriptor = serde_json::json!({
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": digest,
+                "size": 100,
+            });
+            tag_local_cache(dir.path(), &descriptor).unwrap();
+        }
+
+        assert_eq!(
+            local_cache_digest(dir.path()).as_deref(),
+            Some("sha256:bbb")
+        );
+        let index: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join("index.json")).unwrap()).unwrap();
+        assert_eq!(index["manifests"].as_array().unwrap().len(), 1);
+        assert!(dir.path().join("oci-layout").exists());
+    }
+}
@@ -0,0 +1,536 @@
+//! Filtering of the build context sent to BuildKit
+
+use crate::dockerfile::context_sources;
+use crate::error::{Error, Result};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Restricts which context paths are sent to BuildKit
+///
+/// A filter combines an optional set of included paths (e.g. the sources
+/// of a Dockerfile's `COPY` instructions) with `.dockerignore` patterns.
+/// Directories outside the included paths are never read, which avoids
+/// walking the whole tree of large repositories.
+///
+/// # Example
+///
+/// ```
+/// use buildkit_client::session::ContextFilter;
+///
+/// let filter = ContextFilter::new()
+///     .include(["src", "Cargo.toml"])
+///     .ignore_patterns(["**/*.log", "src/generated"]);
+///
+/// assert!(filter.allows("src/main.rs", false));
+/// assert!(!filter.allows("src/debug.log", false));
+/// assert!(!filter.allows("src/generated", true));
+/// assert!(!filter.allows("docs", true));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ContextFilter {
+    include: Option<Vec<String>>,
+    ignore: Vec<IgnorePattern>,
+}
+
+/// Single `.dockerignore` pattern
+#[derive(Debug, Clone)]
+struct IgnorePattern {
+    segments: Vec<String>,
+    negated: bool,
+}
+
+impl ContextFilter {
+    /// Create a filter that allows every path
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only send these paths (files or directories, relative to the context root)
+    pub fn include<I, S>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let include = self.include.get_or_insert_with(Vec::new);
+        include.extend(
+            paths
+                .into_iter()
+                .map(|p| p.into().trim_matches('/').to_string()),
+        );
+        self
+    }
+
+    /// Exclude paths matching `.dockerignore`-style patterns
+    ///
+    /// Later patterns take precedence; patterns starting with `!` re-include
+    /// paths excluded by earlier ones.
+    pub fn ignore_patterns<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.ignore.extend(
+            patterns
+                .into_iter()
+                .filter_map(|p| IgnorePattern::parse(p.as_ref())),
+        );
+        self
+    }
+
+    /// Exclude paths matching the patterns of a `.dockerignore` file's content
+    pub fn dockerignore(self, content: &str) -> Self {
+        self.ignore_patterns(content.lines())
+    }
+
+    /// Filter for a build of `dockerfile` in `context`
+    ///
+    /// Includes the context paths the Dockerfile reads (when they can be
+    /// determined) and applies the context's `.dockerignore`, if present.
+    pub fn for_dockerfile(context: &Path, dockerfile: &str) -> Result<Self> {
+        let mut filter = Self::new();
+        if let Some(sources) = context_sources(dockerfile) {
+            filter = filter.include(sources);
+        }
+
+        if let Some(content) = read_dockerignore(context)? {
+            filter = filter.dockerignore(&content);
+        }
+        Ok(filter)
+    }
+
+    /// Whether a context path (relative, `/`-separated) should be sent
+    ///
+    /// Directories that lead to an included path are allowed so the walk
+    /// can reach it.
+    pub fn allows(&self, rel_path: &str, is_dir: bool) -> bool {
+        let rel_path = rel_path.trim_matches('/');
+
+        if let Some(include) = &self.include {
+            let included = include.iter().any(|root| {
+                is_same_or_under(rel_path, root) || (is_dir && is_same_or_under(root, rel_path))
+            });
+            if !included {
+                return false;
+            }
+        }
+
+        if !self.is_ignored(rel_path) {
+            return true;
+        }
+        // An excluded directory still has to be walked if a `!` pattern may
+        // re-include something below it
+        let segments: Vec<&str> = rel_path.split('/').collect();
+        is_dir
+            && self
+                .ignore
+                .iter()
+                .any(|p| p.negated && p.may_match_below(&segments))
+    }
+
+    fn is_ignored(&self, rel_path: &str) -> bool {
+        let segments: Vec<&str> = rel_path.split('/').collect();
+        let mut ignored = false;
+        for pattern in &self.ignore {
+            if pattern.negated == ignored && pattern.matches_or_parent_matches(&segments) {
+                ignored = !pattern.negated;
+            }
+        }
+        ignored
+    }
+}
+
+/// Paths BuildKit asked for in a context walk (its followpaths)
+///
+/// Paths may be patterns such as `**/package.json`, matched like
+/// `.dockerignore` patterns. A matched directory includes everything below
+/// it; other directories are only walked if a pattern may match below them.
+#[derive(Debug, Clone)]
+pub(super) struct FollowPaths {
+    patterns: Vec<IgnorePattern>,
+}
+
+impl FollowPaths {
+    /// Follow the paths as given, without resolving symlinks
+    pub(super) fn new<S: AsRef<str>>(paths: &[S]) -> Self {
+        let patterns = paths
+            .iter()
+            .map(|path| {
+                let segments = clean_segments(path.as_ref());
+                IgnorePattern {
+                    // `.` asks for the whole context
+                    segments: if segments.is_empty() {
+                        vec!["**".to_string()]
+                    } else {
+                        segments
+                    },
+                    negated: false,
+                }
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    /// Follow the paths of the context at `root`, and the targets of the
+    /// symlinks leading to them
+    ///
+    /// Like fsutil's `FollowLinks`, a symlink matched by a path or on the
+    /// way to it is sent along with its target, so BuildKit can resolve it.
+    pub(super) fn resolve<S: AsRef<str>>(root: &Path, paths: &[S]) -> Result<Self> {
+        let mut resolver = LinkResolver {
+            root,
+            resolved: BTreeSet::new(),
+        };
+        for path in paths {
+            resolver.append(clean_segments(path.as_ref()).join("/"))?;
+        }
+        let resolved: Vec<String> = resolver.resolved.into_iter().collect();
+        tracing::debug!("Resolved followpaths: {:?}", resolved);
+        Ok(Self::new(&resolved))
+    }
+
+    /// Whether a context path (relative, `/`-separated) or one of its parent
+    /// directories was asked for
+    pub(super) fn matches(&self, rel_path: &str) -> bool {
+        let segments: Vec<&str> = rel_path.trim_matches('/').split('/').collect();
+        self.patterns
+            .iter()
+            .any(|p| p.matches_or_parent_matches(&segments))
+    }
+
+    /// Whether a path below the directory `rel_path` may have been asked for
+    pub(super) fn may_match_below(&self, rel_path: &str) -> bool {
+        let segments: Vec<&str> = rel_path.trim_matches('/').split('/').collect();
+        self.patterns.iter().any(|p| p.may_match_below(&segments))
+    }
+}
+
+/// Resolves the symlinks on the way to followpaths
+struct LinkResolver<'a> {
+    root: &'a Path,
+    /// Resolved paths, symlinks included
+    resolved: BTreeSet<String>,
+}
+
+impl LinkResolver<'_> {
+    /// Walk `path` one segment at a time, continuing at the target of the
+    /// first symlink found
+    fn append(&mut self, path: String) -> Result<()> {
+        let mut current = String::new();
+        let mut rest = path.as_str();
+        loop {
+            let (segment, remaining) = rest.split_once('/').unwrap_or((rest, ""));
+            if !current.is_empty() {
+                current.push('/');
+            }
+            current.push_str(segment);
+            rest = remaining;
+
+            let targets = self.read_links(&current, true)?;
+            if (rest.is_empty() || !targets.is_empty()) && self.resolved.contains(&current) {
+                return Ok(());
+            }
+            if !targets.is_empty() {
+                self.resolved.insert(current);
+                for target in targets {
+                    self.append(clean_segments(&format!("{}/{}", target, rest)).join("/"))?;
+                }
+                return Ok(());
+            }
+            if rest.is_empty() {
+                self.resolved.insert(current);
+                return Ok(());
+            }
+        }
+    }
+
+    /// Context-relative targets of the symlink at `rel_path`, or of the
+    /// symlinks its last segment matches if it is a pattern
+    fn read_links(&self, rel_path: &str, allow_wildcard: bool) -> Result<Vec<String>> {
+        let (dir, name) = rel_path.rsplit_once('/').unwrap_or(("", rel_path));
+        if allow_wildcard && name.contains(['*', '?', '[', '\\']) {
+            let dir_path = self.root.join(dir);
+            let entries = match std::fs::read_dir(&dir_path) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(Error::file_operation("read directory", dir_path, e)),
+            };
+            let mut targets = Vec::new();
+            for entry in entries {
+                let entry =
+                    entry.map_err(|e| Error::file_operation("read directory", &dir_path, e))?;
+                let entry_name = entry.file_name().to_string_lossy().into_owned();
+                if match_segment(name.as_bytes(), entry_name.as_bytes()) {
+                    let entry_path = if dir.is_empty() {
+                        entry_name
+                    } else {
+                        format!("{}/{}", dir, entry_name)
+                    };
+                    targets.extend(self.read_links(&entry_path, false)?);
+                }
+            }
+            return Ok(targets);
+        }
+
+        let path = self.root.join(rel_path);
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_symlink() => {}
+            Ok(_) => return Ok(Vec::new()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::file_operation("stat", path, e)),
+        }
+        let link = std::fs::read_link(&path)
+            .map_err(|e| Error::file_operation("read symlink", &path, e))?;
+        let link = link.to_string_lossy();
+        // Absolute targets are relative to the context root
+        let target = if link.starts_with('/') {
+            clean_segments(&link)
+        } else {
+            clean_segments(&format!("{}/{}", dir, link))
+        };
+        Ok(vec![target.join("/")])
+    }
+}
+
+/// Read the `.dockerignore` file of a context, if it has one
+pub(super) fn read_dockerignore(context: &Path) -> Result<Option<String>> {
+    let dockerignore = context.join(".dockerignore");
+    match std::fs::read_to_string(&dockerignore) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::file_operation("read", dockerignore, e)),
+    }
+}
+
+/// Whether `path` is `root` or lies below it
+fn is_same_or_under(path: &str, root: &str) -> bool {
+    root.is_empty()
+        || path == root
+        || (path.starts_with(root) && path.as_bytes().get(root.len()) == Some(&b'/'))
+}
+
+impl IgnorePattern {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest.trim()),
+            None => (false, line),
+        };
+
+        let segments = clean_segments(pattern);
+        if segments.is_empty() {
+            return None;
+        }
+        Some(Self { segments, negated })
+    }
+
+    /// Docker semantics: a pattern excludes a path if it matches the path or
+    /// any of its parent directories
+    fn matches_or_parent_matches(&self, path: &[&str]) -> bool {
+        (1..=path.len()).any(|len| match_segments(&self.segments, &path[..len]))
+    }
+
+    /// Whether the pattern can match a path below the directory `dir`
+    fn may_match_below(&self, dir: &[&str]) -> bool {
+        for (i, name) in dir.iter().enumerate() {
+            match self.segments.get(i) {
+                Some(segment) if segment == "**" => return true,
+                Some(segment) if match_segment(segment.as_bytes(), name.as_bytes()) => {}
+                _ => return false,
+            }
+        }
+        self.segments.len() > dir.len()
+    }
+}
+
+/// Segments of a `/`-separated path with `.` and `..` resolved, never
+/// leaving the root
+fn clean_segments(path: &str) -> Vec<String> {
+    let mut segments: Vec<String> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s.to_string()),
+        }
+    }
+    segments
+}
+
+fn match_segments(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| match_segments(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                match_segment(first.as_bytes(), name.as_bytes()) && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Match one path segment against a glob with `*`, `?`, `[...]` and `\` escapes
+fn match_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_segment(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_segment(rest, &name[1..]),
+        Some((b'[', rest)) => {
+            let Some((&c, name_rest)) = name.split_first() else {
+                return false;
+            };
+            match match_class(rest, c) {
+                Some((matched, after)) => matched && match_segment(after, name_rest),
+                // Unterminated class: treat '[' literally
+                None => c == b'[' && match_segment(rest, name_rest),
+            }
+        }
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            name.first() == Some(&rest[0]) && match_segment(&rest[1..], &name[1..])
+        }
+        Some((&p, rest)) => name.first() == Some(&p) && match_segment(rest, &name[1..]),
+    }
+}
+
+/// Match a character class body (after `[`), returning the match result and
+/// the pattern after the closing `]`
+fn match_class(class: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negated, mut rest) = match class.first() {
+        Some(b'^' | b'!') => (true, &class[1..]),
+        _ => (false, class),
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match rest {
+            [] => return None,
+            [b']', after @ ..] if !first => return Some((matched != negated, after)),
+            [lo, b'-', hi, after @ ..] if *hi != b']' => {
+                matched |= (*lo..=*hi).contains(&c);
+                rest = after;
+            }
+            [ch, after @ ..] => {
+                matched |= *ch == c;
+                rest = after;
+            }
+        }
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignored(patterns: &[&str], path: &str) -> bool {
+        !ContextFilter::new()
+            .ignore_patterns(patterns)
+            .allows(path, false)
+    }
+
+    #[test]
+    fn dockerignore_patterns_match_like_docker() {
+        assert!(ignored(&["*.md"], "README.md"));
+        assert!(!ignored(&["*.md"], "docs/README.md"));
+        assert!(ignored(&["**/*.md"], "docs/README.md"));
+        assert!(ignored(&["target"], "target/debug/app"));
+        assert!(ignored(&["/node_modules/"], "node_modules/x/index.js"));
+        assert!(ignored(&["file?.txt"], "file1.txt"));
+        assert!(ignored(&["[a-c].txt"], "b.txt"));
+        assert!(!ignored(&["[!a-c].txt"], "b.txt"));
+        assert!(!ignored(&["# comment", ""], "anything"));
+    }
+
+    #[test]
+    fn negation_reincludes_paths() {
+        let patterns = ["*.md", "!README.md"];
+        assert!(ignored(&patterns, "CHANGELOG.md"));
+        assert!(!ignored(&patterns, "README.md"));
+
+        // Directories stay walkable when a negation may apply below them
+        let filter = ContextFilter::new().ignore_patterns(["docs", "!docs/keep.txt"]);
+        assert!(filter.allows("docs", true));
+        assert!(filter.allows("docs/keep.txt", false));
+        assert!(!filter.allows("docs/other.txt", false));
+
+        // Other excluded directories are not walked
+        let filter = ContextFilter::new().ignore_patterns(["target", "*.md", "!README.md"]);
+        assert!(!filter.allows("target", true));
+        let filter = ContextFilter::new().ignore_patterns(["vendor", "!**/LICENSE"]);
+        assert!(filter.allows("vendor", true));
+        assert!(filter.allows("vendor/lib/LICENSE", false));
+    }
+
+    #[test]
+    fn include_allows_paths_and_their_parents() {
+        let filter = ContextFilter::new().include(["app/src", "Cargo.toml"]);
+        assert!(filter.allows("app", true));
+        assert!(filter.allows("app/src", true));
+        assert!(filter.allows("app/src/main.rs", false));
+        assert!(filter.allows("Cargo.toml", false));
+        assert!(!filter.allows("app/tests", true));
+        assert!(!filter.allows("app/srcs", true));
+        assert!(!filter.allows("README.md", false));
+        // A parent directory name as a file does not lead anywhere
+        assert!(!filter.allows("app", false));
+    }
+
+    #[test]
+    fn follow_paths_match_patterns_and_lead_to_them() {
+        let follow = FollowPaths::new(&["**/package.json", "src/*.rs", "vendor"]);
+        assert!(follow.matches("package.json"));
+        assert!(follow.matches("web/app/package.json"));
+        assert!(follow.matches("src/main.rs"));
+        assert!(!follow.matches("src/bin/tool.rs"));
+        assert!(follow.matches("vendor/lib/LICENSE"));
+        assert!(!follow.matches("README.md"));
+
+        assert!(follow.may_match_below("src"));
+        assert!(follow.may_match_below("docs"));
+        let exact = FollowPaths::new(&["app/subdir/data.txt"]);
+        assert!(exact.may_match_below("app/subdir"));
+        assert!(!exact.may_match_below("docs"));
+
+        assert!(FollowPaths::new(&["."]).matches("anything/at/all"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_paths_resolve_symlinks_to_their_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("real/conf")).unwrap();
+        std::fs::write(dir.path().join("real/conf/app.toml"), "").unwrap();
+        std::os::unix::fs::symlink("real", dir.path().join("current")).unwrap();
+        std::os::unix::fs::symlink("/real/conf/app.toml", dir.path().join("app.toml")).unwrap();
+
+        let follow = FollowPaths::resolve(dir.path(), &["current/conf", "*.toml"]).unwrap();
+        assert!(follow.matches("current"));
+        assert!(follow.matches("real/conf/app.toml"));
+        assert!(follow.matches("app.toml"));
+        assert!(!follow.matches("real/other"));
+    }
+
+    #[test]
+    fn for_dockerfile_combines_sources_and_dockerignore() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".dockerignore"), "src/**/*.tmp\n").unwrap();
+
+        let filter =
+            ContextFilter::for_dockerfile(dir.path(), "FROM alpine\nCOPY src /src\n").unwrap();
+        assert!(filter.allows("src/lib.rs", false));
+        assert!(!filter.allows("src/cache/x.tmp", false));
+        assert!(!filter.allows("vendor", true));
+
+        let unprunable =
+            ContextFilter::for_dockerfile(dir.path(), "FROM alpine\nCOPY . /src\n").unwrap();
+        assert!(unprunable.allows("vendor", true));
+    }
+}
@@ -7,7 +7,7 @@
 //! ## Protocol Overview
 //!
 //! DiffCopy follows this flow:
-//! 1. Client sends headers (dir_name, followpaths)
+//! 1. Client sends headers (dir_name, followpaths, exclude-patterns)
 //! 2. Server sends STAT packets for all files/dirs (depth-first, sorted alphabetically)
 //! 3. Server sends empty STAT packet to signal end of listing
 //! 4. Client sends REQ packets for files it needs
@@ -21,6 +21,8 @@
 //! - Within each directory, entries must be **sorted alphabetically**
 //! - Directory sizes must be 0 (fsutil protocol requirement)
 //! - File modes must be in Go FileMode format (use `filemode` crate)
+//! - Excluded paths (`.dockerignore`) are filtered by the sender, i.e. here
+//! - Hard-linked files are sent once; later links name the first as `linkname`
 //!
 //! ## References
 //!
@@ -28,41 +30,44 @@
 //! - fsutil reference: `github.com/tonistiigi/fsutil` (send.go, receive.go)
 
 use crate::error::{Error, Result};
+use crate::metrics::TransferStats;
 use crate::proto::fsutil::types::{packet::PacketType, Packet, Stat};
 use bytes::Bytes;
 use filemode::{GoFileMode, UnixMode};
 use h2::server::SendResponse;
 use http::{Response, StatusCode};
 use prost::Message as ProstMessage;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::io::AsyncReadExt;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
 
-use super::FileSyncServer;
+use super::context_filter::FollowPaths;
+use super::snapshot::{ContextSnapshot, SnapshotFile};
+use super::{xattrs, ChangeCache, ContextFilter, FileSyncServer, Ownership};
 
 /// Handle a DiffCopy streaming request from BuildKit
 ///
 /// This is the main entry point for the DiffCopy protocol. It handles the complete
 /// bidirectional streaming session including sending file metadata and responding
 /// to file data requests.
+///
+/// When `shutdown` is cancelled, the transfer stops and the call ends with a
+/// `CANCELLED` status.
 pub(super) async fn handle_diff_copy_stream(
     file_sync: &FileSyncServer,
     mut request_stream: h2::RecvStream,
     mut respond: SendResponse<Bytes>,
     dir_name: Option<String>,
     followpaths: Vec<String>,
+    exclude_patterns: Vec<String>,
+    shutdown: &CancellationToken,
 ) -> Result<()> {
-    static CALL_COUNTER: AtomicU32 = AtomicU32::new(0);
-    let call_id = CALL_COUNTER.fetch_add(1, Ordering::SeqCst);
-
-    tracing::info!(
-        "handle_diff_copy_stream called (call #{}, dir_name: {:?}, followpaths: {:?})",
-        call_id,
-        dir_name,
-        followpaths
-    );
-    tracing::info!("FileSync.DiffCopy streaming started (call #{})", call_id);
+    tracing::debug!(?dir_name, ?followpaths, "DiffCopy started");
 
     // Build response headers
     let response = Response::builder()
@@ -75,34 +80,129 @@ pub(super) async fn handle_diff_copy_stream(
         .send_response(response, false)
         .map_err(|e| Error::Http2Stream { source: e })?;
 
-    tracing::info!("Sent response headers for DiffCopy");
+    let started = Instant::now();
+    let transfer = transfer_context(
+        file_sync,
+        &mut request_stream,
+        &mut send_stream,
+        dir_name.as_deref(),
+        &followpaths,
+        &exclude_patterns,
+    );
+    let transferred = shutdown.run_until_cancelled(transfer).await;
+    file_sync
+        .transfer_stats()
+        .transfer_finished(started.elapsed());
+    let trailers = match transferred {
+        Some(result) => {
+            result?;
+            Response::builder().header("grpc-status", "0")
+        }
+        None => {
+            tracing::info!("DiffCopy cancelled, session closing");
+            Response::builder()
+                .header("grpc-status", "1") // CANCELLED
+                .header("grpc-message", "session closed")
+        }
+    };
+
+    // Send the status trailers
+    let trailers = trailers.body(()).unwrap();
+    send_stream
+        .send_trailers(trailers.headers().clone())
+        .map_err(|e| Error::Http2Stream { source: e })?;
+
+    Ok(())
+}
 
+/// Send the STAT packets of the files BuildKit asked for, serve their data
+/// and acknowledge the end of the transfer with a FIN packet
+async fn transfer_context(
+    file_sync: &FileSyncServer,
+    request_stream: &mut h2::RecvStream,
+    send_stream: &mut h2::SendStream<Bytes>,
+    dir_name: Option<&str>,
+    followpaths: &[String],
+    exclude_patterns: &[String],
+) -> Result<()> {
     // Get the root path from FileSyncServer
     let root_path = file_sync.get_root_path();
-    tracing::info!(
-        "Starting to send STAT packets from: {} (call #{})",
-        root_path.display(),
-        call_id
+    tracing::debug!(
+        "Starting to send STAT packets from: {}",
+        root_path.display()
     );
     // Determine what to send based on dir_name header
     let mut file_map = HashMap::new();
+    let mut inline_files = HashMap::new();
+    let mut snapshot_files = HashMap::new();
     let mut id_counter = 0u32;
 
-    let send_only_dockerfile = dir_name.as_deref() == Some("dockerfile");
+    let send_only_dockerfile = dir_name == Some("dockerfile");
 
     if send_only_dockerfile {
-        // BuildKit only wants the Dockerfile
-        send_dockerfile_only(&root_path, &followpaths, &mut send_stream, &mut file_map).await?;
+        if let Some(content) = file_sync.dockerfile_content() {
+            // Dockerfile is held in memory, serve it as a virtual file
+            send_inline_dockerfile(content.clone(), followpaths, send_stream, &mut inline_files)
+                .await?;
+        } else {
+            // BuildKit only wants the Dockerfile
+            send_dockerfile_only(
+                file_sync.dockerfile_dir(),
+                followpaths,
+                file_sync.ownership(),
+                file_sync.xattrs(),
+                send_stream,
+                &mut file_map,
+            )
+            .await?;
+        }
+    } else if let Some(snapshot) = file_sync.snapshot() {
+        // BuildKit wants the full context, as it was when the build started
+        let skipped = send_snapshot_context(
+            snapshot,
+            followpaths,
+            &file_sync.ignore_filter(exclude_patterns)?,
+            file_sync.ownership(),
+            file_sync.xattrs(),
+            send_stream,
+            &mut snapshot_files,
+        )
+        .await?;
+        file_sync.transfer_stats().paths_skipped(skipped);
     } else {
         // BuildKit wants the full context
+        let ignore = file_sync.ignore_filter(exclude_patterns)?;
+        let filters: Vec<&ContextFilter> = file_sync
+            .context_filter()
+            .into_iter()
+            .chain([&ignore])
+            .collect();
+        let options = WalkOptions {
+            // Symlinks leading to followpaths are sent with their targets
+            include_paths: follow_paths(&root_path, followpaths)?,
+            xattrs: file_sync.xattrs(),
+            stats: Some(file_sync.transfer_stats()),
+            ..WalkOptions::new(followpaths, &filters, file_sync.ownership())
+        };
+        let mut change_cache = file_sync
+            .change_cache_dir()
+            .map(|dir| ChangeCache::open(dir, &root_path))
+            .transpose()?;
         send_full_context(
             &root_path,
-            &followpaths,
-            &mut send_stream,
+            &options,
+            change_cache.as_mut(),
+            send_stream,
             &mut file_map,
             &mut id_counter,
         )
         .await?;
+        if let Some(cache) = &mut change_cache {
+            // A cache that cannot be written only costs time on the next build
+            if let Err(e) = cache.save(options.include_paths.is_none()) {
+                tracing::warn!("Failed to save change cache: {}", e);
+            }
+        }
     }
 
     // Send final empty STAT packet to indicate end of stats
@@ -112,14 +212,22 @@ pub(super) async fn handle_diff_copy_stream(
         id: 0,
         data: vec![],
     };
-    send_grpc_packet(&mut send_stream, &final_stat_packet).await?;
+    send_grpc_packet(send_stream, &final_stat_packet).await?;
 
-    tracing::info!("Sent all STAT packets (including final empty STAT), now waiting for REQ packets from BuildKit");
+    tracing::debug!("Sent all STAT packets, waiting for REQ packets from BuildKit");
 
     // Process REQ packets from BuildKit
-    process_file_requests(&mut request_stream, &mut send_stream, &file_map).await?;
+    process_file_requests(
+        request_stream,
+        send_stream,
+        &file_map,
+        &inline_files,
+        &snapshot_files,
+        file_sync.transfer_stats(),
+    )
+    .await?;
 
-    tracing::info!("DiffCopy completed, sending FIN packet");
+    tracing::debug!("DiffCopy completed, sending FIN packet");
 
     // Send FIN packet to indicate all transfers are complete
     let fin_packet = Packet {
@@ -129,38 +237,61 @@ pub(super) async fn handle_diff_copy_stream(
         data: vec![],
     };
 
-    send_grpc_packet(&mut send_stream, &fin_packet).await?;
+    send_grpc_packet(send_stream, &fin_packet).await?;
     tracing::debug!("Sent final FIN packet");
 
-    // Send success trailers
-    let trailers = Response::builder()
-        .header("grpc-status", "0")
-        .body(())
-        .unwrap();
+    Ok(())
+}
 
-    send_stream
-        .send_trailers(trailers.headers().clone())
-        .map_err(|e| Error::Http2Stream { source: e })?;
+/// Pick the Dockerfile name BuildKit asked for in the "dockerfile" directory
+///
+/// Ignore files requested alongside it, such as `Dockerfile.dockerignore`,
+/// are not the Dockerfile.
+fn requested_dockerfile_name(followpaths: &[String]) -> String {
+    followpaths
+        .iter()
+        .find(|path| !path.ends_with(".dockerignore"))
+        .cloned()
+        .unwrap_or_else(|| "Dockerfile".to_string())
+}
 
-    Ok(())
+/// Paths BuildKit asked for in the "dockerfile" directory, in the
+/// lexicographic order fsutil walks them
+fn requested_dockerfile_paths(followpaths: &[String]) -> Vec<String> {
+    let mut paths: Vec<String> = followpaths
+        .iter()
+        .map(|path| path.trim_start_matches("./").to_string())
+        .filter(|path| !path.is_empty())
+        .collect();
+    if paths.is_empty() {
+        paths.push("Dockerfile".to_string());
+    }
+    paths.sort();
+    paths.dedup();
+    paths
 }
 
-/// Send only the Dockerfile (when dir_name="dockerfile")
+/// Send the Dockerfile and its ignore files (when dir_name="dockerfile")
+///
+/// Every requested path that exists is sent; missing ones are left out as
+/// fsutil does, so BuildKit sees no ignore file rather than an error. Only
+/// a missing Dockerfile fails.
 async fn send_dockerfile_only(
-    root_path: &Path,
+    dockerfile_dir: &Path,
     followpaths: &[String],
+    ownership: Ownership,
+    xattrs: bool,
     send_stream: &mut h2::SendStream<Bytes>,
     file_map: &mut HashMap<u32, PathBuf>,
 ) -> Result<()> {
-    let dockerfile_name = if !followpaths.is_empty() && followpaths[0].ends_with(".Dockerfile") {
-        followpaths[0].clone()
-    } else {
-        "Dockerfile".to_string()
-    };
+    let dockerfile_name = requested_dockerfile_name(followpaths);
 
-    tracing::debug!("BuildKit requested 'dockerfile' - sending only {}", dockerfile_name);
+    tracing::debug!(
+        "BuildKit requested 'dockerfile' - sending {:?}",
+        requested_dockerfile_paths(followpaths)
+    );
 
-    let dockerfile_path = root_path.join(&dockerfile_name);
+    let dockerfile_path = dockerfile_dir.join(&dockerfile_name);
     if !dockerfile_path.exists() {
         tracing::error!(
             "{} not found at {}",
@@ -170,14 +301,61 @@ async fn send_dockerfile_only(
         return Err(Error::PathNotFound(dockerfile_path));
     }
 
-    let metadata = tokio::fs::metadata(&dockerfile_path).await?;
+    let mut id = 0;
+    for name in requested_dockerfile_paths(followpaths) {
+        let path = dockerfile_dir.join(&name);
+        // A symlinked Dockerfile is sent as the file it points to
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::debug!("{} not found, not sending it", path.display());
+                continue;
+            }
+            Err(e) => return Err(Error::file_operation("stat", &path, e)),
+        };
+        let mut stat = stat_for(name, &metadata, String::new());
+        ownership.apply(&mut stat);
+        if xattrs {
+            stat.xattrs = xattrs::read(path.clone()).await?;
+        }
+
+        let stat_packet = Packet {
+            r#type: PacketType::PacketStat as i32,
+            stat: Some(stat),
+            id,
+            data: vec![],
+        };
+
+        send_grpc_packet(send_stream, &stat_packet).await?;
+
+        file_map.insert(id, path);
+        id += 1;
+    }
+    Ok(())
+}
+
+/// Send an in-memory Dockerfile as a virtual regular file
+async fn send_inline_dockerfile(
+    content: Bytes,
+    followpaths: &[String],
+    send_stream: &mut h2::SendStream<Bytes>,
+    inline_files: &mut HashMap<u32, Bytes>,
+) -> Result<()> {
+    let dockerfile_name = requested_dockerfile_name(followpaths);
 
-    let mut stat = Stat {
-        path: dockerfile_name.clone(),
-        mode: 0,
+    tracing::debug!(
+        "BuildKit requested 'dockerfile' - sending in-memory {} ({} bytes)",
+        dockerfile_name,
+        content.len()
+    );
+
+    let stat = Stat {
+        path: dockerfile_name,
+        mode: 0o644, // Regular file in Go FileMode format
         uid: 0,
         gid: 0,
-        size: metadata.len() as i64,
+        size: content.len() as i64,
         mod_time: 0,
         linkname: String::new(),
         devmajor: 0,
@@ -185,18 +363,6 @@ async fn send_dockerfile_only(
         xattrs: HashMap::new(),
     };
 
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let unix_mode = metadata.permissions().mode();
-        stat.mode = GoFileMode::from(UnixMode::from(unix_mode)).as_u32();
-    }
-
-    #[cfg(not(unix))]
-    {
-        stat.mode = 0o644; // Regular file in Go FileMode format
-    }
-
     let stat_packet = Packet {
         r#type: PacketType::PacketStat as i32,
         stat: Some(stat),
@@ -206,342 +372,720 @@ async fn send_dockerfile_only(
 
     send_grpc_packet(send_stream, &stat_packet).await?;
 
-    file_map.insert(0, dockerfile_path);
+    inline_files.insert(0, content);
     Ok(())
 }
 
 /// Send full directory tree using depth-first traversal
 async fn send_full_context(
     root_path: &Path,
-    followpaths: &[String],
+    options: &WalkOptions<'_>,
+    change_cache: Option<&mut ChangeCache>,
     send_stream: &mut h2::SendStream<Bytes>,
     file_map: &mut HashMap<u32, PathBuf>,
     id_counter: &mut u32,
 ) -> Result<()> {
-    if followpaths.is_empty() {
-        tracing::debug!("BuildKit requested full context - sending entire directory tree");
-    } else {
-        tracing::debug!("BuildKit requested filtered context - followpaths: {:?}", followpaths);
+    match &options.include_paths {
+        None => {
+            tracing::debug!("BuildKit requested full context - sending entire directory tree")
+        }
+        Some(paths) => tracing::debug!(
+            "BuildKit requested filtered context - include paths: {:?}",
+            paths
+        ),
     }
 
     send_stat_packets_dfs(
-        root_path.to_path_buf(),
-        String::new(),
+        root_path,
         send_stream,
         file_map,
         id_counter,
-        if followpaths.is_empty() {
-            None
-        } else {
-            Some(followpaths)
-        },
+        change_cache,
+        options,
     )
     .await
 }
 
-/// Send STAT packets using depth-first traversal
-///
-/// This function recursively traverses the directory tree in depth-first order,
-/// sending STAT packets for each file and directory. This is the correct way to
-/// send files to BuildKit's fsutil validator, which requires files in depth-first
-/// order with entries sorted alphabetically within each directory.
-///
-/// If `followpaths` is Some, only sends files in the list and their parent directories.
-fn send_stat_packets_dfs<'a>(
-    path: PathBuf,
-    prefix: String,
-    stream: &'a mut h2::SendStream<Bytes>,
-    file_map: &'a mut HashMap<u32, PathBuf>,
-    id_counter: &'a mut u32,
-    followpaths: Option<&'a [String]>,
-) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send + 'a>> {
-    Box::pin(async move {
-        tracing::debug!(
-            "send_stat_packets_dfs: {} (prefix: {}, followpaths: {:?})",
-            path.display(),
-            prefix,
-            followpaths
-        );
+/// What a walk of the context sends
+struct WalkOptions<'a> {
+    /// Paths BuildKit asked for, or `None` for everything
+    include_paths: Option<FollowPaths>,
+    /// Filters every sent entry must pass
+    filters: &'a [&'a ContextFilter],
+    /// Ownership recorded in STAT packets
+    ownership: Ownership,
+    /// Whether extended attributes are recorded in STAT packets
+    xattrs: bool,
+    /// Counts the entries the filters skip
+    stats: Option<&'a TransferStats>,
+}
 
-        // Build set of paths to include if followpaths is specified
-        let include_paths = if let Some(paths) = followpaths {
-            let mut set = HashSet::new();
-            for p in paths {
-                set.insert(p.clone());
-                // Add all parent directories
-                let mut parent = p.as_str();
-                while let Some(idx) = parent.rfind('/') {
-                    parent = &parent[..idx];
-                    set.insert(parent.to_string());
+impl<'a> WalkOptions<'a> {
+    fn new(followpaths: &[String], filters: &'a [&'a ContextFilter], ownership: Ownership) -> Self {
+        Self {
+            include_paths: (!followpaths.is_empty()).then(|| FollowPaths::new(followpaths)),
+            filters,
+            ownership,
+            xattrs: false,
+            stats: None,
+        }
+    }
+}
+
+/// Send STAT packets for the entries of a context snapshot
+///
+/// Snapshot entries are already in depth-first order and filtered by the
+/// context filter; ignored paths are skipped here, and their number
+/// returned. Extended attributes are not part of the snapshot and are read
+/// when the entry is sent.
+async fn send_snapshot_context<'a>(
+    snapshot: &'a ContextSnapshot,
+    followpaths: &[String],
+    ignore: &ContextFilter,
+    ownership: Ownership,
+    xattrs: bool,
+    send_stream: &mut h2::SendStream<Bytes>,
+    snapshot_files: &mut HashMap<u32, &'a SnapshotFile>,
+) -> Result<u64> {
+    let include_paths = follow_paths(snapshot.root(), followpaths)?;
+    let mut skipped = 0;
+
+    // Directories leading to followpaths are only sent once something
+    // below them is
+    let mut included = vec![false; snapshot.entries().len()];
+    let mut pending: Vec<usize> = Vec::new();
+    for (i, entry) in snapshot.entries().iter().enumerate() {
+        let path = &entry.stat.path;
+        while pending
+            .last()
+            .is_some_and(|&dir| !is_below(path, &snapshot.entries()[dir].stat.path))
+        {
+            pending.pop();
+        }
+        let is_dir = GoFileMode::from(entry.stat.mode).is_dir();
+        if !ignore.allows(path, is_dir) {
+            skipped += 1;
+            continue;
+        }
+        match &include_paths {
+            Some(paths) if !paths.matches(path) => {
+                if is_dir && paths.may_match_below(path) {
+                    pending.push(i);
                 }
             }
-            tracing::debug!(
-                "Built include_paths set with {} entries: {:?}",
-                set.len(),
-                set
-            );
-            Some(set)
-        } else {
-            None
-        };
-
-        // Read all entries in this directory
-        let mut entries = Vec::new();
-        let mut dir_entries = tokio::fs::read_dir(&path).await?;
+            _ => {
+                for dir in pending.drain(..) {
+                    included[dir] = true;
+                }
+                included[i] = true;
+            }
+        }
+    }
 
-        while let Some(entry) = dir_entries.next_entry().await? {
-            let file_name = entry.file_name();
-            let name = file_name.to_string_lossy().to_string();
-            let entry_path = entry.path();
-            let metadata = entry.metadata().await?;
+    let mut sent_links = SentLinks::default();
+    for (entry_id, (entry, _)) in snapshot
+        .entries()
+        .iter()
+        .zip(included)
+        .filter(|(_, included)| *included)
+        .enumerate()
+    {
+        let entry_id = entry_id as u32;
+        let mut stat = entry.stat.clone();
+        ownership.apply(&mut stat);
+        if xattrs {
+            stat.xattrs = xattrs::read(snapshot.root().join(&entry.stat.path)).await?;
+        }
+        let hard_link = sent_links.link(entry.hard_link_key, &entry.stat.path);
+        if let Some(target) = &hard_link {
+            stat.linkname = target.clone();
+        }
+        let stat_packet = Packet {
+            r#type: PacketType::PacketStat as i32,
+            stat: Some(stat),
+            id: entry_id,
+            data: vec![],
+        };
+        tracing::debug!(
+            "Sending snapshot STAT packet for: {} (id: {})",
+            entry.stat.path,
+            entry_id
+        );
+        send_grpc_packet(send_stream, &stat_packet).await?;
 
-            entries.push((name, entry_path, metadata));
+        if let (Some(file), None) = (&entry.file, &hard_link) {
+            snapshot_files.insert(entry_id, file);
         }
+    }
+    Ok(skipped)
+}
 
-        // Sort entries alphabetically by name (fsutil requirement)
-        entries.sort_by(|a, b| a.0.cmp(&b.0));
+/// Send STAT packets using depth-first traversal
+///
+/// fsutil's validator requires entries in depth-first order, sorted
+/// alphabetically within each directory. The walk is iterative and only
+/// holds the sorted names of the directories on the current path, so the
+/// memory it needs depends on the depth and width of the tree rather than
+/// on the number of files.
+///
+/// If BuildKit sent followpaths, only sends the entries they match and the
+/// directories leading to them; a directory is sent once something below it
+/// is, as fsutil does for patterns. Entries outside them or rejected by any
+/// of the filters are skipped without being read. Further links to a file already sent are sent
+/// as hard links. With a change cache, regular files are sent with the
+/// modification time it settles on.
+async fn send_stat_packets_dfs(
+    root: &Path,
+    stream: &mut h2::SendStream<Bytes>,
+    file_map: &mut HashMap<u32, PathBuf>,
+    id_counter: &mut u32,
+    mut change_cache: Option<&mut ChangeCache>,
+    options: &WalkOptions<'_>,
+) -> Result<()> {
+    let mut sent_links = SentLinks::default();
+    let mut stack = vec![DirFrame::read(root.to_path_buf(), String::new(), None).await?];
 
-        // Process entries in sorted order (depth-first)
-        for (name, entry_path, metadata) in entries {
-            let rel_path = if prefix.is_empty() {
-                name.clone()
-            } else {
-                format!("{}/{}", prefix, name)
-            };
+    while let Some(frame) = stack.last_mut() {
+        let Some((name, file_name)) = frame.names.next() else {
+            stack.pop();
+            continue;
+        };
+        let rel_path = if frame.prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", frame.prefix, name)
+        };
 
-            // Skip if not in include_paths (when filtering is enabled)
-            if let Some(ref paths) = include_paths {
-                if !paths.contains(&rel_path) {
-                    tracing::debug!("Skipping {} (not in followpaths)", rel_path);
+        let entry_path = frame.path.join(file_name);
+        // Symlinks are sent as links, never followed
+        let metadata = tokio::fs::symlink_metadata(&entry_path)
+            .await
+            .map_err(|e| Error::file_operation("stat", &entry_path, e))?;
+
+        // Skip if not asked for (when filtering is enabled), but walk the
+        // directories something asked for may be in
+        let leads_to_followpath = match &options.include_paths {
+            Some(paths) if !paths.matches(&rel_path) => {
+                if !(metadata.is_dir() && paths.may_match_below(&rel_path)) {
+                    tracing::trace!("Skipping {} (not in followpaths)", rel_path);
                     continue;
-                } else {
-                    tracing::debug!("Including {} (found in followpaths)", rel_path);
                 }
+                true
             }
+            _ => false,
+        };
 
-            let entry_id = *id_counter;
-            *id_counter += 1;
-
-            // Create and send STAT packet for this entry
-            let mut stat = Stat {
-                path: rel_path.clone(),
-                mode: 0,
-                uid: 0,
-                gid: 0,
-                // For directories, size must be 0 (fsutil protocol requirement)
-                size: if metadata.is_dir() {
-                    0
-                } else {
-                    metadata.len() as i64
-                },
-                mod_time: 0,
-                linkname: String::new(),
-                devmajor: 0,
-                devminor: 0,
-                xattrs: HashMap::new(),
-            };
-
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::PermissionsExt;
-                let unix_mode = metadata.permissions().mode();
-                stat.mode = GoFileMode::from(UnixMode::from(unix_mode)).as_u32();
+        if options
+            .filters
+            .iter()
+            .any(|f| !f.allows(&rel_path, metadata.is_dir()))
+        {
+            tracing::debug!("Skipping {} (excluded by context filter)", rel_path);
+            if let Some(stats) = options.stats {
+                stats.paths_skipped(1);
             }
+            continue;
+        }
 
-            #[cfg(not(unix))]
-            {
-                // On non-Unix platforms, construct mode in Go FileMode format directly
-                stat.mode = if metadata.is_dir() {
-                    0x80000000 | 0o755 // GO_MODE_DIR | 0o755
-                } else {
-                    0o644 // Just permissions for regular files
-                };
+        let linkname = if metadata.is_symlink() {
+            let target = tokio::fs::read_link(&entry_path)
+                .await
+                .map_err(|e| Error::file_operation("read symlink", &entry_path, e))?;
+            target.to_string_lossy().into_owned()
+        } else {
+            String::new()
+        };
+
+        // Create and send STAT packet for this entry
+        let mut stat = stat_for(rel_path.clone(), &metadata, linkname);
+        options.ownership.apply(&mut stat);
+        if options.xattrs {
+            stat.xattrs = xattrs::read(entry_path.clone()).await?;
+        }
+        if leads_to_followpath {
+            // Sent once something below it is
+            stack.push(DirFrame::read(entry_path, rel_path, Some(stat)).await?);
+            continue;
+        }
+        for frame in stack.iter_mut() {
+            if let Some(stat) = frame.pending.take() {
+                send_stat_packet(stream, stat, id_counter).await?;
             }
+        }
 
-            let path_sent = stat.path.clone();
-            let stat_mode = stat.mode;
-            let stat_packet = Packet {
-                r#type: PacketType::PacketStat as i32,
-                stat: Some(stat),
-                id: entry_id,
-                data: vec![],
-            };
+        let entry_id = *id_counter;
+        *id_counter += 1;
+        if let (Some(cache), true) = (change_cache.as_deref_mut(), metadata.is_file()) {
+            stat.mod_time = cache
+                .settle(&rel_path, &entry_path, stat.size, stat.mod_time)
+                .await?;
+        }
+        let hard_link = sent_links.link(hard_link_key(&metadata), &rel_path);
+        if let Some(target) = &hard_link {
+            tracing::debug!("Sending {} as a hard link to {}", rel_path, target);
+            stat.linkname = target.clone();
+        }
+        tracing::debug!(
+            "Sending STAT packet for: {} (id: {}, mode: 0o{:o})",
+            stat.path,
+            entry_id,
+            stat.mode
+        );
+        let stat_packet = Packet {
+            r#type: PacketType::PacketStat as i32,
+            stat: Some(stat),
+            id: entry_id,
+            data: vec![],
+        };
+        send_grpc_packet(stream, &stat_packet).await?;
+
+        if metadata.is_dir() {
+            // Descend before the remaining siblings
+            stack.push(DirFrame::read(entry_path, rel_path, None).await?);
+        } else if metadata.is_file() && hard_link.is_none() {
+            // Store file path in map for later data requests
+            file_map.insert(entry_id, entry_path);
+        }
+    }
 
-            tracing::info!(
-                "Sending STAT packet for: {} (id: {}, mode: 0o{:o})",
-                path_sent,
-                entry_id,
-                stat_mode
-            );
-            send_grpc_packet(stream, &stat_packet).await?;
+    Ok(())
+}
 
-            // Store file path in map for later data requests (only for files)
-            if metadata.is_file() {
-                file_map.insert(entry_id, entry_path.clone());
-            }
+/// A directory being walked, with the names left to visit
+struct DirFrame {
+    path: PathBuf,
+    /// Context-relative path of the directory, empty for the root
+    prefix: String,
+    /// Remaining entries, as sorted display name and file name
+    names: std::vec::IntoIter<(String, std::ffi::OsString)>,
+    /// STAT entry of the directory, if it is yet to be sent
+    pending: Option<Stat>,
+}
 
-            // Recursively process directories
-            if metadata.is_dir() {
-                send_stat_packets_dfs(
-                    entry_path,
-                    rel_path,
-                    stream,
-                    file_map,
-                    id_counter,
-                    followpaths,
-                )
-                .await?;
+impl DirFrame {
+    /// List a directory, sorted by name (fsutil requirement)
+    async fn read(path: PathBuf, prefix: String, pending: Option<Stat>) -> Result<Self> {
+        let mut names = Vec::new();
+        let mut dir_entries = tokio::fs::read_dir(&path)
+            .await
+            .map_err(|e| Error::file_operation("read directory", &path, e))?;
+        while let Some(entry) = dir_entries
+            .next_entry()
+            .await
+            .map_err(|e| Error::file_operation("read directory", &path, e))?
+        {
+            let file_name = entry.file_name();
+            names.push((file_name.to_string_lossy().into_owned(), file_name));
+        }
+        names.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(Self {
+            path,
+            prefix,
+            names: names.into_iter(),
+            pending,
+        })
+    }
+}
+
+/// Regular files with several links sent so far, by device and inode
+///
+/// The first link sent carries the data; later ones are sent with it as
+/// their `linkname`, which BuildKit turns into a hard link without
+/// requesting the data again.
+#[derive(Debug, Default)]
+struct SentLinks(HashMap<(u64, u64), String>);
+
+impl SentLinks {
+    /// Path of a link to the same file sent before, recording `rel_path`
+    /// as the first link otherwise
+    fn link(&mut self, key: Option<(u64, u64)>, rel_path: &str) -> Option<String> {
+        let key = key?;
+        match self.0.get(&key) {
+            Some(target) => Some(target.clone()),
+            None => {
+                self.0.insert(key, rel_path.to_string());
+                None
             }
         }
+    }
+}
+
+/// Device and inode of a regular file with more than one link
+pub(super) fn hard_link_key(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        (metadata.is_file() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Followpaths of a walk of the context at `root`, or `None` for everything
+fn follow_paths(root: &Path, followpaths: &[String]) -> Result<Option<FollowPaths>> {
+    if followpaths.is_empty() {
+        return Ok(None);
+    }
+    FollowPaths::resolve(root, followpaths).map(Some)
+}
+
+/// Whether `path` lies below the directory `dir`
+fn is_below(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Send the STAT packet of a directory whose sending was held back
+async fn send_stat_packet(
+    stream: &mut h2::SendStream<Bytes>,
+    stat: Stat,
+    id_counter: &mut u32,
+) -> Result<()> {
+    let entry_id = *id_counter;
+    *id_counter += 1;
+    tracing::debug!(
+        "Sending STAT packet for: {} (id: {}, leads to followpaths)",
+        stat.path,
+        entry_id
+    );
+    let stat_packet = Packet {
+        r#type: PacketType::PacketStat as i32,
+        stat: Some(stat),
+        id: entry_id,
+        data: vec![],
+    };
+    send_grpc_packet(stream, &stat_packet).await
+}
+
+/// STAT entry for a context path
+///
+/// `metadata` must not follow symlinks; `linkname` is the target of a
+/// symlink, which BuildKit recreates as is. Ownership is read from disk,
+/// before any [`Ownership`] policy applies.
+pub(super) fn stat_for(rel_path: String, metadata: &std::fs::Metadata, linkname: String) -> Stat {
+    // fsutil compares modification times to find changed files
+    let mod_time = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |since_epoch| since_epoch.as_nanos() as i64);
+
+    let mut stat = Stat {
+        path: rel_path,
+        mode: 0,
+        uid: 0,
+        gid: 0,
+        // For directories, size must be 0 (fsutil protocol requirement)
+        size: if metadata.is_dir() {
+            0
+        } else {
+            metadata.len() as i64
+        },
+        mod_time,
+        linkname,
+        devmajor: 0,
+        devminor: 0,
+        xattrs: HashMap::new(),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let unix_mode = metadata.permissions().mode();
+        stat.mode = GoFileMode::from(UnixMode::from(unix_mode)).as_u32();
+        stat.uid = metadata.uid();
+        stat.gid = metadata.gid();
+    }
+
+    #[cfg(not(unix))]
+    {
+        // On non-Unix platforms, construct mode in Go FileMode format directly
+        stat.mode = if metadata.is_dir() {
+            0x80000000 | 0o755 // GO_MODE_DIR | 0o755
+        } else if metadata.is_symlink() {
+            0x08000000 | 0o777 // GO_MODE_SYMLINK | 0o777
+        } else {
+            0o644 // Just permissions for regular files
+        };
+    }
 
-        Ok(())
-    })
+    stat
 }
 
+/// Files read at once while answering REQ packets
+const MAX_CONCURRENT_READS: usize = 8;
+
+/// DATA packets buffered while earlier ones are written to the stream
+const DATA_PACKET_BUFFER: usize = 32;
+
 /// Process incoming REQ packets from BuildKit and send file data
+///
+/// File data is looked up in `file_map` (files on disk) first, then in
+/// `inline_files` (virtual files held in memory) and `snapshot_files`.
+/// Requested files are read concurrently, up to [`MAX_CONCURRENT_READS`]
+/// at a time, while their DATA packets are written to the stream one at a
+/// time; BuildKit tells the files apart by packet ID.
 async fn process_file_requests(
     request_stream: &mut h2::RecvStream,
     send_stream: &mut h2::SendStream<Bytes>,
     file_map: &HashMap<u32, PathBuf>,
+    inline_files: &HashMap<u32, Bytes>,
+    snapshot_files: &HashMap<u32, &SnapshotFile>,
+    stats: &TransferStats,
 ) -> Result<()> {
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_READS));
+    let (packet_tx, mut packet_rx) = mpsc::channel(DATA_PACKET_BUFFER);
+    let mut serving = JoinSet::new();
     let mut buffer = Vec::new();
-    let mut received_fin = false;
+    let mut requests_done = false;
 
     loop {
-        match request_stream.data().await {
-            Some(Ok(chunk)) => {
-                buffer.extend_from_slice(&chunk);
-                let _ = request_stream.flow_control().release_capacity(chunk.len());
-
-                // Try to parse complete gRPC messages from buffer
-                while buffer.len() >= 5 {
-                    // Read gRPC frame header (5 bytes)
-                    let compressed = buffer[0];
-                    let length =
-                        u32::from_be_bytes([buffer[1], buffer[2], buffer[3], buffer[4]]) as usize;
-
-                    if buffer.len() < 5 + length {
-                        break; // Not enough data yet
-                    }
-
-                    // Extract the complete message
-                    let message_data = buffer[5..5 + length].to_vec();
-                    buffer.drain(0..5 + length);
-
-                    if compressed != 0 {
-                        tracing::warn!("Received compressed message, skipping");
-                        continue;
-                    }
-
-                    // Decode the packet
-                    let packet = match Packet::decode(Bytes::from(message_data)) {
-                        Ok(p) => p,
-                        Err(e) => {
-                            tracing::error!("Failed to decode packet: {}", e);
-                            continue;
-                        }
-                    };
-
-                    let packet_type =
-                        PacketType::try_from(packet.r#type).unwrap_or(PacketType::PacketStat);
-                    tracing::debug!(
-                        "Received packet type: {:?}, id: {}, has_stat: {}",
-                        packet_type,
-                        packet.id,
-                        packet.stat.is_some()
-                    );
-
-                    match packet_type {
-                        PacketType::PacketReq => {
-                            tracing::info!("Received REQ packet with id: {}", packet.id);
-
-                            if let Some(file_path) = file_map.get(&packet.id) {
-                                tracing::info!(
-                                    "Sending file data for id {}: {}",
+        tokio::select! {
+            chunk = request_stream.data(), if !requests_done => match chunk {
+                Some(Ok(chunk)) => {
+                    buffer.extend_from_slice(&chunk);
+                    let _ = request_stream.flow_control().release_capacity(chunk.len());
+
+                    while let Some(packet) = next_packet(&mut buffer) {
+                        let packet_type =
+                            PacketType::try_from(packet.r#type).unwrap_or(PacketType::PacketStat);
+                        #[cfg(feature = "debug_wire")]
+                        tracing::trace!(
+                            "Received packet: type={:?}, id={}, has_stat={}",
+                            packet_type,
+                            packet.id,
+                            packet.stat.is_some()
+                        );
+
+                        match packet_type {
+                            PacketType::PacketReq => {
+                                let source = if let Some(path) = file_map.get(&packet.id) {
+                                    FileSource::Disk(path.clone())
+                                } else if let Some(content) = inline_files.get(&packet.id) {
+                                    FileSource::Inline(content.clone())
+                                } else if let Some(file) = snapshot_files.get(&packet.id) {
+                                    FileSource::Snapshot((*file).clone())
+                                } else {
+                                    tracing::warn!(
+                                        "File ID {} not found in map (probably a directory, ignoring)",
+                                        packet.id
+                                    );
+                                    continue;
+                                };
+                                serving.spawn(serve_file(
+                                    source,
                                     packet.id,
-                                    file_path.display()
-                                );
-                                send_file_data_packets(file_path.clone(), packet.id, send_stream)
-                                    .await?;
-                            } else {
-                                tracing::warn!(
-                                    "File ID {} not found in map (probably a directory, ignoring)",
-                                    packet.id
-                                );
+                                    permits.clone(),
+                                    packet_tx.clone(),
+                                ));
+                            }
+                            PacketType::PacketFin => {
+                                tracing::debug!("Received FIN packet from BuildKit, ending transfer");
+                                requests_done = true;
+                                break;
+                            }
+                            _ => {
+                                tracing::debug!("Ignoring packet type: {:?}", packet_type);
                             }
-                        }
-                        PacketType::PacketFin => {
-                            tracing::info!("Received FIN packet from BuildKit, ending transfer");
-                            received_fin = true;
-                            break;
-                        }
-                        _ => {
-                            tracing::debug!("Ignoring packet type: {:?}", packet_type);
                         }
                     }
                 }
-
-                if received_fin {
-                    break;
+                Some(Err(e)) => {
+                    tracing::error!("Error reading request stream: {}", e);
+                    requests_done = true;
                 }
+                None => {
+                    tracing::debug!("Request stream ended");
+                    requests_done = true;
+                }
+            },
+            Some(packet) = packet_rx.recv() => {
+                stats.data_sent(packet.data.len());
+                send_grpc_packet(send_stream, &packet).await?;
             }
-            Some(Err(e)) => {
-                tracing::error!("Error reading request stream: {}", e);
-                break;
-            }
-            None => {
-                tracing::info!("Request stream ended");
-                break;
+            Some(joined) = serving.join_next() => {
+                joined.map_err(|e| Error::other(format!("file read task failed: {}", e)))??;
             }
         }
+
+        if requests_done && serving.is_empty() {
+            break;
+        }
     }
 
+    // Every reader is done; send what is still buffered
+    while let Ok(packet) = packet_rx.try_recv() {
+        stats.data_sent(packet.data.len());
+        send_grpc_packet(send_stream, &packet).await?;
+    }
     Ok(())
 }
 
-/// Send file data as DATA packets in response to a REQ
-async fn send_file_data_packets(
-    path: PathBuf,
-    req_id: u32,
-    stream: &mut h2::SendStream<Bytes>,
-) -> Result<()> {
-    tracing::info!("Sending file data for: {} (id: {})", path.display(), req_id);
-
-    let mut file = tokio::fs::File::open(&path).await?;
-    let mut buffer = vec![0u8; 32 * 1024]; // 32KB chunks
+/// Take the next complete gRPC message off `buffer` as a packet
+///
+/// Compressed and undecodable messages are skipped.
+fn next_packet(buffer: &mut Vec<u8>) -> Option<Packet> {
+    while buffer.len() >= 5 {
+        // Read gRPC frame header (5 bytes)
+        let compressed = buffer[0];
+        let length = u32::from_be_bytes([buffer[1], buffer[2], buffer[3], buffer[4]]) as usize;
+        if buffer.len() < 5 + length {
+            return None; // Not enough data yet
+        }
 
-    loop {
-        let n = file.read(&mut buffer).await?;
-        if n == 0 {
-            break;
+        // Extract the complete message
+        let message_data: Vec<u8> = buffer.drain(0..5 + length).skip(5).collect();
+        if compressed != 0 {
+            tracing::warn!("Received compressed message, skipping");
+            continue;
+        }
+        match Packet::decode(Bytes::from(message_data)) {
+            Ok(packet) => return Some(packet),
+            Err(e) => tracing::error!("Failed to decode packet: {}", e),
         }
+    }
+    None
+}
 
-        let data_packet = Packet {
-            r#type: PacketType::PacketData as i32,
-            stat: None,
-            id: req_id,
-            data: buffer[..n].to_vec(),
-        };
+/// Where the data of a requested file comes from
+enum FileSource {
+    /// File in the context directory
+    Disk(PathBuf),
+    /// Virtual file held in memory
+    Inline(Bytes),
+    /// File of a context snapshot
+    Snapshot(SnapshotFile),
+}
 
-        send_grpc_packet(stream, &data_packet).await?;
+/// Send the data of one requested file once a read permit is free
+async fn serve_file(
+    source: FileSource,
+    req_id: u32,
+    permits: Arc<Semaphore>,
+    packets: mpsc::Sender<Packet>,
+) -> Result<()> {
+    // The semaphore is never closed
+    let _permit = permits.acquire_owned().await;
+    match source {
+        FileSource::Disk(path) => {
+            tracing::debug!("Sending file data for id {}: {}", req_id, path.display());
+            send_file_data_packets(path, req_id, &packets).await
+        }
+        FileSource::Inline(content) => {
+            tracing::debug!(
+                "Sending in-memory file data for id {} ({} bytes)",
+                req_id,
+                content.len()
+            );
+            send_inline_data_packets(content, req_id, &packets).await
+        }
+        FileSource::Snapshot(file) => {
+            tracing::debug!(
+                "Sending snapshot file data for id {}: {}",
+                req_id,
+                file.path().display()
+            );
+            send_snapshot_data_packets(&file, req_id, &packets).await
+        }
     }
+}
 
-    // Send empty DATA packet to indicate end of this file
-    let eof_packet = Packet {
+/// Queue a DATA packet for the send stream; empty data ends the file
+async fn send_data_packet(
+    packets: &mpsc::Sender<Packet>,
+    req_id: u32,
+    data: Vec<u8>,
+) -> Result<()> {
+    let packet = Packet {
         r#type: PacketType::PacketData as i32,
         stat: None,
         id: req_id,
-        data: vec![],
+        data,
     };
+    packets
+        .send(packet)
+        .await
+        .map_err(|_| Error::other("DiffCopy stream closed while sending file data"))
+}
+
+/// Send file data as DATA packets in response to a REQ
+async fn send_file_data_packets(
+    path: PathBuf,
+    req_id: u32,
+    packets: &mpsc::Sender<Packet>,
+) -> Result<()> {
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|e| Error::file_operation("open", &path, e))?;
+    let mut buffer = vec![0u8; 32 * 1024]; // 32KB chunks
+
+    loop {
+        let n = file
+            .read(&mut buffer)
+            .await
+            .map_err(|e| Error::file_operation("read", &path, e))?;
+        if n == 0 {
+            break;
+        }
+        send_data_packet(packets, req_id, buffer[..n].to_vec()).await?;
+    }
+
+    // Send empty DATA packet to indicate end of this file
+    send_data_packet(packets, req_id, vec![]).await?;
+    tracing::debug!("Sent EOF (empty DATA) packet for id: {}", req_id);
+
+    Ok(())
+}
+
+/// Send in-memory file data as DATA packets in response to a REQ
+async fn send_inline_data_packets(
+    content: Bytes,
+    req_id: u32,
+    packets: &mpsc::Sender<Packet>,
+) -> Result<()> {
+    for chunk in content.chunks(32 * 1024) {
+        send_data_packet(packets, req_id, chunk.to_vec()).await?;
+    }
+
+    // Send empty DATA packet to indicate end of this file
+    send_data_packet(packets, req_id, vec![]).await?;
+    tracing::debug!("Sent EOF (empty DATA) packet for id: {}", req_id);
+
+    Ok(())
+}
+
+/// Send snapshotted file data as DATA packets in response to a REQ
+///
+/// Fails with [`Error::ContextChanged`] if the content no longer matches
+/// the snapshot.
+async fn send_snapshot_data_packets(
+    file: &SnapshotFile,
+    req_id: u32,
+    packets: &mpsc::Sender<Packet>,
+) -> Result<()> {
+    let mut reader = file.reader()?;
+    while let Some(data) = reader.next_chunk().await? {
+        send_data_packet(packets, req_id, data).await?;
+    }
 
-    send_grpc_packet(stream, &eof_packet).await?;
+    // Send empty DATA packet to indicate end of this file
+    send_data_packet(packets, req_id, vec![]).await?;
     tracing::debug!("Sent EOF (empty DATA) packet for id: {}", req_id);
 
     Ok(())
 }
 
 /// Send a single gRPC-framed packet over the h2 stream
-async fn send_grpc_packet(stream: &mut h2::SendStream<Bytes>, packet: &Packet) -> Result<()> {
+///
+/// Waits for the peer's flow control window, see [`send_with_capacity`].
+pub(super) async fn send_grpc_packet(
+    stream: &mut h2::SendStream<Bytes>,
+    packet: &Packet,
+) -> Result<()> {
     let mut payload = Vec::new();
     packet.encode(&mut payload)?;
 
@@ -551,22 +1095,44 @@ async fn send_grpc_packet(stream: &mut h2::SendStream<Bytes>, packet: &Packet) -
     framed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
     framed.extend_from_slice(&payload);
 
-    let packet_type = PacketType::try_from(packet.r#type).ok();
+    #[cfg(feature = "debug_wire")]
     tracing::trace!(
         "Sending packet: type={:?}, id={}, data_len={}, total_frame_len={}",
-        packet_type,
+        PacketType::try_from(packet.r#type).ok(),
         packet.id,
         packet.data.len(),
         framed.len()
     );
 
-    stream
-        .send_data(Bytes::from(framed), false)
-        .map_err(|e| Error::Http2Stream { source: e })?;
+    send_with_capacity(stream, Bytes::from(framed)).await
+}
 
-    // Give the h2 stream a chance to flush
-    tokio::task::yield_now().await;
+/// Send data as the peer's flow control window allows
+///
+/// Capacity is reserved for the whole buffer and the data is sent in the
+/// pieces h2 assigns, so a peer that stops reading suspends the sender
+/// instead of data piling up in the connection's send buffer. The
+/// connection must be driven by another task.
+pub(super) async fn send_with_capacity(
+    stream: &mut h2::SendStream<Bytes>,
+    mut data: Bytes,
+) -> Result<()> {
+    while !data.is_empty() {
+        stream.reserve_capacity(data.len());
+        let mut capacity = stream.capacity();
+        while capacity == 0 {
+            capacity = match std::future::poll_fn(|cx| stream.poll_capacity(cx)).await {
+                Some(Ok(capacity)) => capacity,
+                Some(Err(e)) => return Err(Error::Http2Stream { source: e }),
+                None => return Err(Error::protocol("stream closed while sending data")),
+            };
+        }
 
+        let chunk = data.split_to(capacity.min(data.len()));
+        stream
+            .send_data(chunk, false)
+            .map_err(|e| Error::Http2Stream { source: e })?;
+    }
     Ok(())
 }
 
@@ -690,6 +1256,21 @@ mod tests {
         packets
     }
 
+    /// Run a sender of DATA packets, collecting the packets it queued
+    async fn queued_packets<F, Fut>(send: F) -> (Vec<Packet>, Result<()>)
+    where
+        F: FnOnce(mpsc::Sender<Packet>) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let (tx, mut rx) = mpsc::channel(DATA_PACKET_BUFFER);
+        let sender = tokio::spawn(send(tx));
+        let mut packets = Vec::new();
+        while let Some(packet) = rx.recv().await {
+            packets.push(packet);
+        }
+        (packets, sender.await.unwrap())
+    }
+
     fn create_test_context(root: &Path) {
         std::fs::write(root.join("Dockerfile"), "FROM alpine\n").unwrap();
 
@@ -717,12 +1298,12 @@ mod tests {
                 let mut counter = 0u32;
 
                 send_stat_packets_dfs(
-                    root,
-                    String::new(),
+                    &root,
                     send_stream,
                     &mut file_map,
                     &mut counter,
                     None,
+                    &WalkOptions::new(&[], &[], Ownership::default()),
                 )
                 .await?;
 
@@ -783,12 +1364,12 @@ mod tests {
                 let mut counter = 0u32;
 
                 send_stat_packets_dfs(
-                    root,
-                    String::new(),
+                    &root,
                     send_stream,
                     &mut file_map,
                     &mut counter,
-                    Some(&follow),
+                    None,
+                    &WalkOptions::new(&follow, &[], Ownership::default()),
                 )
                 .await?;
 
@@ -818,6 +1399,454 @@ mod tests {
         );
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stat_packets_follow_wildcard_followpaths() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_path = temp_dir.path().to_path_buf();
+        create_test_context(&root_path);
+        std::fs::create_dir_all(root_path.join("docs/empty")).unwrap();
+        std::fs::write(root_path.join("app/subdir/package.json"), "{}").unwrap();
+        std::fs::write(root_path.join("package.json"), "{}").unwrap();
+
+        let (packets, _) = capture_packets(move |send_stream| {
+            Box::pin(async move {
+                let follow = vec!["**/package.json".to_string(), "app/*.txt".to_string()];
+                let mut file_map = HashMap::new();
+                let mut counter = 0u32;
+                send_stat_packets_dfs(
+                    &root_path,
+                    send_stream,
+                    &mut file_map,
+                    &mut counter,
+                    None,
+                    &WalkOptions::new(&follow, &[], Ownership::default()),
+                )
+                .await
+            })
+        })
+        .await;
+
+        let paths: Vec<&str> = packets
+            .iter()
+            .map(|packet| packet.stat.as_ref().unwrap().path.as_str())
+            .collect();
+        // Directories without a match, like docs, are not sent
+        assert_eq!(
+            paths,
+            vec![
+                "app",
+                "app/config.txt",
+                "app/main.txt",
+                "app/subdir",
+                "app/subdir/package.json",
+                "package.json",
+            ]
+        );
+        let ids: Vec<u32> = packets.iter().map(|packet| packet.id).collect();
+        assert_eq!(ids, (0..6).collect::<Vec<_>>());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stat_packets_skip_dockerignored_paths() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_path = temp_dir.path().to_path_buf();
+        create_test_context(&root_path);
+        std::fs::write(
+            root_path.join(".dockerignore"),
+            ".dockerignore\napp/**/*.txt\n!app/main.txt\n",
+        )
+        .unwrap();
+
+        let file_sync = FileSyncServer::new(&root_path);
+        let ignore = file_sync.ignore_filter(&[]).unwrap();
+        let walker = file_sync.clone();
+        let (packets, _) = capture_packets(move |send_stream| {
+            Box::pin(async move {
+                let mut file_map = HashMap::new();
+                let mut counter = 0u32;
+                let filters = [&ignore];
+                send_stat_packets_dfs(
+                    &root_path,
+                    send_stream,
+                    &mut file_map,
+                    &mut counter,
+                    None,
+                    &WalkOptions {
+                        stats: Some(walker.transfer_stats()),
+                        ..WalkOptions::new(&[], &filters, Ownership::default())
+                    },
+                )
+                .await
+            })
+        })
+        .await;
+
+        let paths: Vec<&str> = packets
+            .iter()
+            .map(|packet| packet.stat.as_ref().unwrap().path.as_str())
+            .collect();
+        // `.dockerignore` itself is always sent
+        assert_eq!(
+            paths,
+            vec![
+                ".dockerignore",
+                "Dockerfile",
+                "app",
+                "app/main.txt",
+                "app/subdir"
+            ]
+        );
+        // Clones of the server share its counters
+        assert_eq!(file_sync.context_stats().skipped, 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn symlinks_are_sent_as_links() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_path = temp_dir.path().to_path_buf();
+        create_test_context(&root_path);
+        std::os::unix::fs::symlink("app/main.txt", root_path.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink("missing", root_path.join("broken")).unwrap();
+        std::os::unix::fs::symlink("app", root_path.join("linkdir")).unwrap();
+
+        let (packets, file_map) = capture_packets(move |send_stream| {
+            Box::pin(async move {
+                let mut file_map = HashMap::new();
+                let mut counter = 0u32;
+                send_stat_packets_dfs(
+                    &root_path,
+                    send_stream,
+                    &mut file_map,
+                    &mut counter,
+                    None,
+                    &WalkOptions::new(&[], &[], Ownership::default()),
+                )
+                .await?;
+                Ok(file_map)
+            })
+        })
+        .await;
+
+        let links: Vec<(&str, &str)> = packets
+            .iter()
+            .map(|packet| packet.stat.as_ref().unwrap())
+            .filter(|stat| GoFileMode::from(stat.mode).is_symlink())
+            .map(|stat| (stat.path.as_str(), stat.linkname.as_str()))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                ("broken", "missing"),
+                ("link.txt", "app/main.txt"),
+                ("linkdir", "app")
+            ]
+        );
+        // Links are neither followed into nor served as file data
+        assert_eq!(packets.len(), 9);
+        assert_eq!(file_map.len(), 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn touched_files_are_sent_with_their_cached_time() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let root_path = temp_dir.path().to_path_buf();
+        let file_path = root_path.join("file.txt");
+        std::fs::write(&file_path, "same").unwrap();
+
+        let mut sent_times = Vec::new();
+        for content in ["same", "same", "changed"] {
+            std::fs::write(&file_path, content).unwrap();
+            let modified = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+            std::fs::File::options()
+                .write(true)
+                .open(&file_path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+
+            let root = root_path.clone();
+            let cache_path = cache_dir.path().to_path_buf();
+            let (packets, ()) = capture_packets(move |send_stream| {
+                Box::pin(async move {
+                    let mut cache = ChangeCache::open(&cache_path, &root)?;
+                    send_stat_packets_dfs(
+                        &root,
+                        send_stream,
+                        &mut HashMap::new(),
+                        &mut 0,
+                        Some(&mut cache),
+                        &WalkOptions::new(&[], &[], Ownership::default()),
+                    )
+                    .await?;
+                    cache.save(true)
+                })
+            })
+            .await;
+            let stat = packets[0].stat.as_ref().unwrap();
+            sent_times.push((stat.mod_time, stat_for_disk_time(&file_path)));
+        }
+
+        // The rewrite with equal content keeps the first time
+        assert_eq!(sent_times[0].0, sent_times[0].1);
+        assert_eq!(sent_times[1].0, sent_times[0].0);
+        assert_ne!(sent_times[1].1, sent_times[0].1);
+        assert_eq!(sent_times[2].0, sent_times[2].1);
+    }
+
+    fn stat_for_disk_time(path: &Path) -> i64 {
+        let metadata = std::fs::metadata(path).unwrap();
+        stat_for(String::new(), &metadata, String::new()).mod_time
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deep_trees_are_walked_before_siblings() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_path = temp_dir.path().to_path_buf();
+        let mut deepest = root_path.join("a");
+        for _ in 0..100 {
+            deepest = deepest.join("d");
+        }
+        std::fs::create_dir_all(&deepest).unwrap();
+        std::fs::write(deepest.join("leaf"), "leaf").unwrap();
+        std::fs::write(root_path.join("b"), "sibling").unwrap();
+
+        let (packets, _) = capture_packets(move |send_stream| {
+            Box::pin(async move {
+                let mut file_map = HashMap::new();
+                let mut counter = 0u32;
+                send_stat_packets_dfs(
+                    &root_path,
+                    send_stream,
+                    &mut file_map,
+                    &mut counter,
+                    None,
+                    &WalkOptions::new(&[], &[], Ownership::default()),
+                )
+                .await
+            })
+        })
+        .await;
+
+        let paths: Vec<&str> = packets
+            .iter()
+            .map(|packet| packet.stat.as_ref().unwrap().path.as_str())
+            .collect();
+        assert_eq!(paths.len(), 103);
+        assert!(paths[101].ends_with("/d/leaf"));
+        assert_eq!(paths[102], "b");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hard_links_are_sent_once() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_path = temp_dir.path().to_path_buf();
+        std::fs::create_dir(root_path.join("a")).unwrap();
+        std::fs::write(root_path.join("a/data"), "shared").unwrap();
+        std::fs::hard_link(root_path.join("a/data"), root_path.join("b")).unwrap();
+        std::fs::hard_link(root_path.join("a/data"), root_path.join("c")).unwrap();
+
+        let (packets, file_map) = capture_packets(move |send_stream| {
+            Box::pin(async move {
+                let mut file_map = HashMap::new();
+                let mut counter = 0u32;
+                send_stat_packets_dfs(
+                    &root_path,
+                    send_stream,
+                    &mut file_map,
+                    &mut counter,
+                    None,
+                    &WalkOptions::new(&[], &[], Ownership::default()),
+                )
+                .await?;
+                Ok(file_map)
+            })
+        })
+        .await;
+
+        let links: Vec<(&str, &str)> = packets
+            .iter()
+            .map(|packet| packet.stat.as_ref().unwrap())
+            .map(|stat| (stat.path.as_str(), stat.linkname.as_str()))
+            .collect();
+        assert_eq!(
+            links,
+            vec![("a", ""), ("a/data", ""), ("b", "a/data"), ("c", "a/data")]
+        );
+        // Only the first link is served as file data
+        assert_eq!(file_map.len(), 1);
+        assert!(file_map.contains_key(&1));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stat_packets_carry_ownership_and_mod_time() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_path = temp_dir.path().to_path_buf();
+        std::fs::write(root_path.join("file.txt"), "data").unwrap();
+        let metadata = std::fs::metadata(root_path.join("file.txt")).unwrap();
+
+        for ownership in [Ownership::Preserve, Ownership::Root] {
+            let root = root_path.clone();
+            let (packets, _) = capture_packets(move |send_stream| {
+                Box::pin(async move {
+                    let mut file_map = HashMap::new();
+                    let mut counter = 0u32;
+                    send_stat_packets_dfs(
+                        &root,
+                        send_stream,
+                        &mut file_map,
+                        &mut counter,
+                        None,
+                        &WalkOptions::new(&[], &[], ownership),
+                    )
+                    .await
+                })
+            })
+            .await;
+
+            let stat = packets[0].stat.as_ref().unwrap();
+            assert_eq!(
+                stat.mod_time,
+                metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec()
+            );
+            if ownership == Ownership::Preserve {
+                assert_eq!((stat.uid, stat.gid), (metadata.uid(), metadata.gid()));
+            } else {
+                assert_eq!((stat.uid, stat.gid), (0, 0));
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stat_packets_carry_xattrs_when_enabled() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        if !xattrs::supported_in(temp_dir.path()) {
+            return;
+        }
+        let root_path = temp_dir.path().to_path_buf();
+        std::fs::write(root_path.join("file.txt"), "data").unwrap();
+        xattr::set(root_path.join("file.txt"), "user.origin", b"context").unwrap();
+
+        for enabled in [true, false] {
+            let root = root_path.clone();
+            let (packets, _) = capture_packets(move |send_stream| {
+                Box::pin(async move {
+                    let mut file_map = HashMap::new();
+                    let mut counter = 0u32;
+                    send_stat_packets_dfs(
+                        &root,
+                        send_stream,
+                        &mut file_map,
+                        &mut counter,
+                        None,
+                        &WalkOptions {
+                            xattrs: enabled,
+                            ..WalkOptions::new(&[], &[], Ownership::default())
+                        },
+                    )
+                    .await
+                })
+            })
+            .await;
+
+            let stat = packets[0].stat.as_ref().unwrap();
+            if enabled {
+                assert_eq!(stat.xattrs["user.origin"], b"context");
+            } else {
+                assert!(stat.xattrs.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn ignore_patterns_override_dockerignore() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(temp_dir.path().join(".dockerignore"), "target\n").unwrap();
+        let file_sync = FileSyncServer::new(temp_dir.path());
+
+        // Patterns sent by BuildKit take precedence over the file
+        let requested = vec!["*.log".to_string()];
+        assert!(file_sync
+            .ignore_filter(&[])
+            .unwrap()
+            .allows("app.log", false));
+        assert!(!file_sync.ignore_filter(&[]).unwrap().allows("target", true));
+        let filter = file_sync.ignore_filter(&requested).unwrap();
+        assert!(!filter.allows("app.log", false));
+        assert!(filter.allows("target", true));
+
+        // The override replaces both
+        let file_sync = file_sync.with_ignore_patterns(Vec::<String>::new());
+        let filter = file_sync.ignore_filter(&requested).unwrap();
+        assert!(filter.allows("app.log", false));
+        assert!(filter.allows("target", true));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn snapshot_stat_packets_match_live_walk() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_path = temp_dir.path().to_path_buf();
+        create_test_context(&root_path);
+        let snapshot = std::sync::Arc::new(ContextSnapshot::capture(&root_path, None).unwrap());
+
+        let live_root = root_path.clone();
+        let (live_packets, _) = capture_packets(move |send_stream| {
+            let root = live_root.clone();
+            Box::pin(async move {
+                let mut file_map = HashMap::new();
+                let mut counter = 0u32;
+                send_stat_packets_dfs(
+                    &root,
+                    send_stream,
+                    &mut file_map,
+                    &mut counter,
+                    None,
+                    &WalkOptions::new(&[], &[], Ownership::default()),
+                )
+                .await
+            })
+        })
+        .await;
+
+        // Files created after the snapshot are not listed
+        std::fs::write(root_path.join("new.txt"), "new").unwrap();
+
+        let (packets, file_ids) = capture_packets(move |send_stream| {
+            let snapshot = snapshot.clone();
+            Box::pin(async move {
+                let mut snapshot_files = HashMap::new();
+                send_snapshot_context(
+                    &snapshot,
+                    &[],
+                    &ContextFilter::new(),
+                    Ownership::default(),
+                    false,
+                    send_stream,
+                    &mut snapshot_files,
+                )
+                .await?;
+                let mut ids: Vec<u32> = snapshot_files.keys().copied().collect();
+                ids.sort();
+                Ok(ids)
+            })
+        })
+        .await;
+
+        assert_eq!(packets, live_packets);
+        let file_ids_live: Vec<u32> = live_packets
+            .iter()
+            .filter(|p| p.stat.as_ref().unwrap().size > 0)
+            .map(|p| p.id)
+            .collect();
+        assert_eq!(file_ids, file_ids_live);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn file_data_packets_stream_contents_and_eof() {
         let temp_dir = tempfile::tempdir().unwrap();
@@ -829,13 +1858,12 @@ mod tests {
         std::fs::write(&file_path, &expected_content).unwrap();
 
         let req_id = 42u32;
-        let file_for_closure = file_path.clone();
-
-        let (packets, ()) = capture_packets(move |send_stream| {
-            let path = file_for_closure.clone();
-            Box::pin(async move { send_file_data_packets(path, req_id, send_stream).await })
-        })
-        .await;
+        let (packets, result) =
+            queued_packets(
+                |tx| async move { send_file_data_packets(file_path, req_id, &tx).await },
+            )
+            .await;
+        result.unwrap();
 
         let mut offset = 0usize;
         let mut expected_sizes = Vec::new();
@@ -871,4 +1899,226 @@ mod tests {
 
         assert_eq!(offset, expected_content.len());
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn file_data_error_reports_offending_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let missing = temp_dir.path().join("missing.txt");
+        let path = missing.clone();
+
+        let (packets, result) =
+            queued_packets(|tx| async move { send_file_data_packets(path, 1, &tx).await }).await;
+
+        assert!(packets.is_empty());
+        match result.err() {
+            Some(Error::FileOperation {
+                operation, path, ..
+            }) => {
+                assert_eq!(operation, "open");
+                assert_eq!(path, missing);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn dockerfile_mount_serves_existing_ignore_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(temp_dir.path().join("Dockerfile.prod"), "FROM alpine\n").unwrap();
+        std::fs::write(temp_dir.path().join(".dockerignore"), "target\n").unwrap();
+        let dir = temp_dir.path().to_path_buf();
+
+        let (packets, file_map) = capture_packets(move |send_stream| {
+            Box::pin(async move {
+                let followpaths = vec![
+                    "Dockerfile.prod".to_string(),
+                    "Dockerfile.prod.dockerignore".to_string(),
+                    ".dockerignore".to_string(),
+                ];
+                let mut file_map = HashMap::new();
+                send_dockerfile_only(
+                    &dir,
+                    &followpaths,
+                    Ownership::default(),
+                    false,
+                    send_stream,
+                    &mut file_map,
+                )
+                .await?;
+                Ok(file_map)
+            })
+        })
+        .await;
+
+        let paths: Vec<&str> = packets
+            .iter()
+            .map(|p| p.stat.as_ref().unwrap().path.as_str())
+            .collect();
+        assert_eq!(paths, vec![".dockerignore", "Dockerfile.prod"]);
+        assert_eq!(packets[1].id, 1);
+        assert_eq!(
+            file_map.get(&1),
+            Some(&temp_dir.path().join("Dockerfile.prod"))
+        );
+    }
+
+    #[test]
+    fn requested_dockerfile_skips_ignore_files() {
+        let follow = |paths: &[&str]| paths.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        assert_eq!(requested_dockerfile_name(&[]), "Dockerfile");
+        assert_eq!(
+            requested_dockerfile_name(&follow(&[".dockerignore", "Dockerfile.prod"])),
+            "Dockerfile.prod"
+        );
+        assert_eq!(
+            requested_dockerfile_paths(&follow(&["Dockerfile", "./.dockerignore", "Dockerfile"])),
+            vec![".dockerignore", "Dockerfile"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn inline_dockerfile_is_sent_as_virtual_file() {
+        let content = Bytes::from_static(b"FROM alpine\nRUN echo inline\n");
+        let content_for_closure = content.clone();
+
+        let (packets, inline_files) = capture_packets(move |send_stream| {
+            let content = content_for_closure.clone();
+            Box::pin(async move {
+                let mut inline_files = HashMap::new();
+                send_inline_dockerfile(content, &[], send_stream, &mut inline_files).await?;
+                Ok(inline_files)
+            })
+        })
+        .await;
+
+        assert_eq!(packets.len(), 1);
+        let stat = packets[0].stat.as_ref().unwrap();
+        assert_eq!(stat.path, "Dockerfile");
+        assert_eq!(stat.mode, 0o644);
+        assert_eq!(stat.size, content.len() as i64);
+        assert_eq!(inline_files.get(&0), Some(&content));
+
+        let data = content.clone();
+        let (packets, result) =
+            queued_packets(|tx| async move { send_inline_data_packets(data, 0, &tx).await }).await;
+        result.unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].data, content.to_vec());
+        assert!(packets[1].data.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn packets_larger_than_the_window_are_sent_in_pieces() {
+        // Several times h2's default 64 KiB window
+        let data = vec![b'w'; 300 * 1024];
+        let expected = data.clone();
+
+        let (packets, ()) = capture_packets(move |send_stream| {
+            Box::pin(async move {
+                let packet = Packet {
+                    r#type: PacketType::PacketData as i32,
+                    stat: None,
+                    id: 7,
+                    data,
+                };
+                send_grpc_packet(send_stream, &packet).await
+            })
+        })
+        .await;
+
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].id, 7);
+        assert_eq!(packets[0].data, expected);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn requested_files_are_served_concurrently() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let large = vec![b'l'; 200 * 1024];
+        std::fs::write(temp_dir.path().join("large"), &large).unwrap();
+        std::fs::write(temp_dir.path().join("small"), "small").unwrap();
+
+        let root = temp_dir.path().to_path_buf();
+        let (packets, result) = queued_packets(|tx| async move {
+            let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_READS));
+            let mut serving = JoinSet::new();
+            serving.spawn(serve_file(
+                FileSource::Disk(root.join("large")),
+                1,
+                permits.clone(),
+                tx.clone(),
+            ));
+            serving.spawn(serve_file(
+                FileSource::Disk(root.join("small")),
+                2,
+                permits,
+                tx,
+            ));
+            while let Some(joined) = serving.join_next().await {
+                joined.unwrap()?;
+            }
+            Ok(())
+        })
+        .await;
+        result.unwrap();
+
+        // Each file arrives whole and in order, ended by an empty packet
+        for (id, content) in [(1, large), (2, b"small".to_vec())] {
+            let data: Vec<&Packet> = packets.iter().filter(|p| p.id == id).collect();
+            assert!(data.last().unwrap().data.is_empty());
+            let received: Vec<u8> = data.iter().flat_map(|p| p.data.clone()).collect();
+            assert_eq!(received, content);
+        }
+    }
+
+    #[tokio::test]
+    async fn transfers_end_cancelled_on_shutdown() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        create_test_context(temp_dir.path());
+        let file_sync = FileSyncServer::new(temp_dir.path());
+        let (client_io, server_io) = duplex(256 * 1024);
+
+        let server_task = tokio::spawn(async move {
+            let mut connection = h2::server::handshake(server_io).await.unwrap();
+            let (request, respond) = connection.accept().await.unwrap().unwrap();
+            let drive = tokio::spawn(async move { while connection.accept().await.is_some() {} });
+            let shutdown = CancellationToken::new();
+            shutdown.cancel();
+            let result = handle_diff_copy_stream(
+                &file_sync,
+                request.into_body(),
+                respond,
+                None,
+                Vec::new(),
+                Vec::new(),
+                &shutdown,
+            )
+            .await;
+            (result, drive)
+        });
+
+        let (client, connection) = h2::client::handshake(client_io).await.unwrap();
+        let client_task = tokio::spawn(async move {
+            let _ = connection.await;
+        });
+        // The request stays open, as BuildKit's would while it sends REQs
+        let (response_future, _request_stream) = client
+            .ready()
+            .await
+            .unwrap()
+            .send_request(Request::builder().uri("/").body(()).unwrap(), false)
+            .unwrap();
+
+        let mut body = response_future.await.unwrap().into_body();
+        while let Some(chunk) = body.data().await {
+            let _ = body.flow_control().release_capacity(chunk.unwrap().len());
+        }
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["grpc-status"], "1");
+
+        let (result, drive) = server_task.await.unwrap();
+        result.unwrap();
+        drive.abort();
+        client_task.abort();
+    }
 }
@@ -0,0 +1,383 @@
+//! Registry credentials from the Docker CLI configuration
+//!
+//! `docker login` stores credentials in `~/.docker/config.json`, either
+//! inline under `auths` or in a credential helper named by `credsStore`
+//! or, per registry, `credHelpers`. [`DockerConfig`] reads that file and
+//! looks credentials up the same way, running helpers such as
+//! `docker-credential-ecr-login` only when BuildKit asks for a registry.
+
+use super::RegistryAuthConfig;
+use crate::error::{Error, Result};
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+
+/// Server URL Docker Hub credentials are stored under
+const DOCKER_HUB_SERVER: &str = "https://index.docker.io/v1/";
+/// Host Docker Hub credentials are looked up by
+const DOCKER_HUB_HOST: &str = "docker.io";
+/// Username a credential helper returns for identity tokens
+const TOKEN_USERNAME: &str = "<token>";
+
+/// `config.json` as written by the Docker CLI
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct ConfigFile {
+    #[serde(default)]
+    auths: HashMap<String, AuthEntry>,
+    #[serde(default)]
+    cred_helpers: HashMap<String, String>,
+    #[serde(default)]
+    creds_store: Option<String>,
+}
+
+/// Credentials stored inline for one registry
+#[derive(Deserialize, Default, Clone)]
+struct AuthEntry {
+    /// Base64 of `username:password`
+    #[serde(default)]
+    auth: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    identitytoken: Option<String>,
+    #[serde(default)]
+    registrytoken: Option<String>,
+}
+
+/// Output of `docker-credential-<helper> get`
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredentials {
+    username: String,
+    secret: String,
+}
+
+/// Credentials of a Docker CLI configuration
+///
+/// Credential helpers are run at most once per registry; clones share the
+/// results. The `Debug` output lists registry hosts only.
+#[derive(Clone, Default)]
+pub struct DockerConfig {
+    auths: HashMap<String, AuthEntry>,
+    cred_helpers: HashMap<String, String>,
+    creds_store: Option<String>,
+    resolved: Arc<Mutex<HashMap<String, Option<RegistryAuthConfig>>>>,
+}
+
+impl std::fmt::Debug for DockerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DockerConfig")
+            .field("auths", &self.auths.keys().collect::<Vec<_>>())
+            .field("cred_helpers", &self.cred_helpers)
+            .field("creds_store", &self.creds_store)
+            .finish()
+    }
+}
+
+impl DockerConfig {
+    /// Load the configuration of the current user
+    ///
+    /// Reads `$DOCKER_CONFIG/config.json`, or `~/.docker/config.json`
+    /// without `DOCKER_CONFIG`. A missing file gives an empty configuration.
+    pub fn load() -> Result<Self> {
+        let dir = match std::env::var_os("DOCKER_CONFIG") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".docker"),
+                None => return Ok(Self::default()),
+            },
+        };
+        let path = dir.join("config.json");
+        if !path.exists() {
+            tracing::debug!("No Docker config at {}", path.display());
+            return Ok(Self::default());
+        }
+        Self::from_file(path)
+    }
+
+    /// Read a `config.json` file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read(path).map_err(|e| Error::file_operation("read", path, e))?;
+        let file = serde_json::from_slice(&content).map_err(|e| {
+            Error::InvalidConfig(format!("invalid Docker config {}: {}", path.display(), e))
+        })?;
+        Ok(Self::from_config_file(file))
+    }
+
+    /// Parse the content of a `config.json` file
+    pub fn parse(content: &[u8]) -> Result<Self> {
+        let file = serde_json::from_slice(content)
+            .map_err(|e| Error::InvalidConfig(format!("invalid Docker config: {}", e)))?;
+        Ok(Self::from_config_file(file))
+    }
+
+    fn from_config_file(file: ConfigFile) -> Self {
+        Self {
+            auths: file
+                .auths
+                .into_iter()
+                .map(|(server, entry)| (registry_host(&server), entry))
+                .collect(),
+            cred_helpers: file
+                .cred_helpers
+                .into_iter()
+                .map(|(server, helper)| (registry_host(&server), helper))
+                .collect(),
+            creds_store: file.creds_store.filter(|store| !store.is_empty()),
+            resolved: Arc::default(),
+        }
+    }
+
+    /// Credentials for a registry host, e.g. `ghcr.io` or
+    /// `registry-1.docker.io`
+    ///
+    /// A registry-specific helper is used first, then credentials stored
+    /// inline, then the default helper.
+    pub async fn credentials(&self, host: &str) -> Result<Option<RegistryAuthConfig>> {
+        let key = registry_host(host);
+        if let Some(resolved) = self.resolved.lock().unwrap().get(&key) {
+            return Ok(resolved.clone());
+        }
+
+        let credentials = if let Some(helper) = self.cred_helpers.get(&key) {
+            helper_credentials(helper, &key).await?
+        } else if let Some(entry) = self.auths.get(&key).filter(|e| e.has_credentials()) {
+            Some(entry.credentials()?)
+        } else if let Some(store) = &self.creds_store {
+            helper_credentials(store, &key).await?
+        } else {
+            None
+        };
+        let credentials = credentials.map(|c| RegistryAuthConfig {
+            host: host.to_string(),
+            ..c
+        });
+
+        self.resolved
+            .lock()
+            .unwrap()
+            .insert(key, credentials.clone());
+        Ok(credentials)
+    }
+}
+
+impl AuthEntry {
+    fn has_credentials(&self) -> bool {
+        [
+            &self.auth,
+            &self.username,
+            &self.identitytoken,
+            &self.registrytoken,
+        ]
+        .iter()
+        .any(|value| value.as_deref().is_some_and(|v| !v.is_empty()))
+    }
+
+    fn credentials(&self) -> Result<RegistryAuthConfig> {
+        let (username, password) = match self.auth.as_deref().filter(|a| !a.is_empty()) {
+            Some(auth) => {
+                let decoded = base64::engine::general_purpose::STANDARD
+                    .decode(auth.trim())
+                    .ok()
+                    .and_then(|d| String::from_utf8(d).ok())
+                    .ok_or_else(|| {
+                        Error::InvalidConfig("invalid auth in Docker config".to_string())
+                    })?;
+                let (username, password) = decoded.split_once(':').ok_or_else(|| {
+                    Error::InvalidConfig("invalid auth in Docker config".to_string())
+                })?;
+                (username.to_string(), password.to_string())
+            }
+            None => (
+                self.username.clone().unwrap_or_default(),
+                self.password.clone().unwrap_or_default(),
+            ),
+        };
+        Ok(RegistryAuthConfig {
+            username,
+            password,
+            identity_token: self.identitytoken.clone().filter(|t| !t.is_empty()),
+            registry_token: self.registrytoken.clone().filter(|t| !t.is_empty()),
+            ..Default::default()
+        })
+    }
+}
+
+/// Credentials from `docker-credential-<helper>` for a registry host
+async fn helper_credentials(helper: &str, host: &str) -> Result<Option<RegistryAuthConfig>> {
+    let program = format!("docker-credential-{}", helper);
+    let server = if host == DOCKER_HUB_HOST {
+        DOCKER_HUB_SERVER
+    } else {
+        host
+    };
+    tracing::debug!("Asking {} for credentials for {}", program, server);
+    run_helper(Path::new(&program), server).await
+}
+
+/// Run a credential helper's `get` command for `server`
+async fn run_helper(program: &Path, server: &str) -> Result<Option<RegistryAuthConfig>> {
+    let mut child = tokio::process::Command::new(program)
+        .arg("get")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| {
+            Error::registry(format!(
+                "failed to run credential helper {}: {}",
+                program.display(),
+                e
+            ))
+        })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(server.as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        // Helpers report unknown servers on stdout and exit with an error
+        if message.contains("credentials not found") {
+            return Ok(None);
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::registry(format!(
+            "credential helper {} failed for {}: {}",
+            program.display(),
+            server,
+            if message.is_empty() {
+                stderr.trim()
+            } else {
+                &message
+            }
+        )));
+    }
+
+    let credentials: HelperCredentials = serde_json::from_slice(&output.stdout).map_err(|e| {
+        Error::registry(format!(
+            "invalid output from credential helper {}: {}",
+            program.display(),
+            e
+        ))
+    })?;
+    Ok(Some(if credentials.username == TOKEN_USERNAME {
+        RegistryAuthConfig {
+            identity_token: Some(credentials.secret),
+            ..Default::default()
+        }
+    } else {
+        RegistryAuthConfig {
+            username: credentials.username,
+            password: credentials.secret,
+            ..Default::default()
+        }
+    }))
+}
+
+/// Host a `config.json` key or a requested registry is looked up by
+///
+/// Keys may be URLs such as `https://ghcr.io/v2/`; Docker Hub has several
+/// names.
+fn registry_host(server: &str) -> String {
+    let host = server
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let host = host.split('/').next().unwrap_or(host);
+    match host {
+        "index.docker.io" | "registry-1.docker.io" | "docker.io" => DOCKER_HUB_HOST.to_string(),
+        host => host.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn inline_credentials_are_found_by_host() {
+        let auth = base64::engine::general_purpose::STANDARD.encode("hubuser:hubpass");
+        let config = DockerConfig::parse(
+            format!(
+                r#"{{
+                    "auths": {{
+                        "https://index.docker.io/v1/": {{"auth": "{}"}},
+                        "ghcr.io": {{"username": "octo", "identitytoken": "refresh"}},
+                        "quay.io": {{}}
+                    }}
+                }}"#,
+                auth
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let hub = config
+            .credentials("registry-1.docker.io")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(hub.host, "registry-1.docker.io");
+        assert_eq!(hub.username, "hubuser");
+        assert_eq!(hub.password, "hubpass");
+
+        let ghcr = config.credentials("ghcr.io").await.unwrap().unwrap();
+        assert_eq!(ghcr.identity_token.as_deref(), Some("refresh"));
+
+        assert!(config.credentials("quay.io").await.unwrap().is_none());
+        assert!(config
+            .credentials("localhost:5000")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(!format!("{:?}", config).contains("hubpass"));
+    }
+
+    #[test]
+    fn registry_hosts_are_normalized() {
+        assert_eq!(registry_host("https://index.docker.io/v1/"), "docker.io");
+        assert_eq!(registry_host("registry-1.docker.io"), "docker.io");
+        assert_eq!(registry_host("https://ghcr.io/v2/"), "ghcr.io");
+        assert_eq!(registry_host("localhost:5000"), "localhost:5000");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn credential_helpers_are_run_for_the_server() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let helper = dir.path().join("docker-credential-test");
+        std::fs::write(
+            &helper,
+            r#"#!/bin/sh
+read server
+case "$server" in
+  https://index.docker.io/v1/) echo '{"ServerURL":"hub","Username":"<token>","Secret":"refresh"}' ;;
+  *) echo "credentials not found in native keychain"; exit 1 ;;
+esac
+"#,
+        )
+        .unwrap();
+        std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let hub = run_helper(&helper, DOCKER_HUB_SERVER)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(hub.identity_token.as_deref(), Some("refresh"));
+        assert!(hub.username.is_empty());
+        assert!(run_helper(&helper, "ghcr.io").await.unwrap().is_none());
+        assert!(run_helper(&dir.path().join("missing"), "ghcr.io")
+            .await
+            .is_err());
+    }
+}
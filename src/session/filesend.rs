@@ -0,0 +1,1068 @@
+//! FileSend Protocol Implementation
+//!
+//! Exporters that write to the client call the session's
+//! `FileSend.DiffCopy` method, naming the exporter in the
+//! `buildkit-attachable-exporter-id` header (its index in the solve
+//! request's exporters). What follows depends on the exporter:
+//!
+//! - `type=local` sends a filesystem with the fsutil protocol. The roles of
+//!   [`diffcopy`](super::diffcopy) are reversed: BuildKit is the sender
+//!   and the client receives.
+//! - Tarball exporters such as `type=oci` send the tarball as a stream of
+//!   `BytesMessage` chunks, ending when BuildKit closes its side. The
+//!   tarball is written to a file, or streamed into a Docker daemon.
+//!
+//! ## Directory Protocol Overview
+//!
+//! 1. BuildKit opens the stream
+//! 2. BuildKit sends STAT packets for all files/dirs, parents first
+//! 3. BuildKit sends an empty STAT packet to signal the end of the listing
+//! 4. Client sends REQ packets for the regular files, identified by the
+//!    index of their STAT packet
+//! 5. BuildKit sends DATA packets, an empty one ending each file
+//! 6. Client sends FIN once all files are written
+//! 7. BuildKit sends FIN to acknowledge completion
+//!
+//! Entries are written under the target directory. Files already there
+//! and not part of the output are kept, as with `buildctl`. Ownership is
+//! not restored: the files belong to the user running the client.
+
+use crate::docker::DockerDaemon;
+use crate::error::{Error, Result};
+use crate::proto::fsutil::types::{packet::PacketType, Packet, Stat};
+use crate::proto::moby::filesync::v1::BytesMessage;
+use bytes::Bytes;
+use filemode::GoFileMode;
+use h2::server::SendResponse;
+use http::{HeaderMap, HeaderValue, Response, StatusCode};
+use prost::Message as ProstMessage;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::diffcopy::send_grpc_packet;
+use super::xattrs;
+
+/// Header naming the exporter a FileSend stream is for
+pub(super) const EXPORTER_ID_HEADER: &str = "buildkit-attachable-exporter-id";
+
+/// Tarball chunks buffered while the Docker daemon reads the previous ones
+const DOCKER_LOAD_BUFFER: usize = 16;
+
+/// File send server implementation
+///
+/// Holds the client-side targets of a build's exporters, by exporter
+/// index.
+#[derive(Debug, Clone, Default)]
+pub struct FileSendServer {
+    targets: HashMap<u32, ExportTarget>,
+    xattrs: bool,
+}
+
+/// Where the output of one exporter is written
+#[derive(Debug, Clone)]
+enum ExportTarget {
+    /// Directory receiving the exported filesystem
+    Directory(PathBuf),
+    /// File receiving an exported tarball
+    File(PathBuf),
+    /// Docker daemon loading an exported tarball
+    DockerLoad(DockerDaemon),
+}
+
+impl FileSendServer {
+    /// Create a file send server without targets
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write the output of exporter `id` into a directory
+    ///
+    /// The directory is created if needed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::session::FileSendServer;
+    /// use std::path::Path;
+    ///
+    /// let send = FileSendServer::new().with_directory(0, "./out");
+    /// assert_eq!(send.directory(0), Some(Path::new("./out")));
+    /// ```
+    pub fn with_directory(mut self, id: u32, path: impl Into<PathBuf>) -> Self {
+        self.targets
+            .insert(id, ExportTarget::Directory(path.into()));
+        self
+    }
+
+    /// Write the tarball of exporter `id` to a file
+    ///
+    /// The file only appears once the whole tarball has been received.
+    pub fn with_file(mut self, id: u32, path: impl Into<PathBuf>) -> Self {
+        self.targets.insert(id, ExportTarget::File(path.into()));
+        self
+    }
+
+    /// Stream the tarball of exporter `id` into a Docker daemon
+    ///
+    /// The exporter must produce a docker-format tarball. A failed load
+    /// fails the export.
+    pub fn with_docker_load(mut self, id: u32, daemon: DockerDaemon) -> Self {
+        self.targets.insert(id, ExportTarget::DockerLoad(daemon));
+        self
+    }
+
+    /// Set the extended attributes BuildKit sends on exported files
+    ///
+    /// Off by default, since setting `security.*` attributes usually
+    /// requires root. Filesystems without xattr support are written
+    /// without any.
+    pub fn with_xattrs(mut self, enabled: bool) -> Self {
+        self.xattrs = enabled;
+        self
+    }
+
+    /// Whether extended attributes of exported files are set
+    pub fn xattrs(&self) -> bool {
+        self.xattrs
+    }
+
+    /// Get the directory exporter `id` writes to, if any
+    pub fn directory(&self, id: u32) -> Option<&Path> {
+        match self.targets.get(&id) {
+            Some(ExportTarget::Directory(path)) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Get the file exporter `id` writes to, if any
+    pub fn file(&self, id: u32) -> Option<&Path> {
+        match self.targets.get(&id) {
+            Some(ExportTarget::File(path)) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Get the Docker daemon exporter `id` loads into, if any
+    pub fn docker_daemon(&self, id: u32) -> Option<&DockerDaemon> {
+        match self.targets.get(&id) {
+            Some(ExportTarget::DockerLoad(daemon)) => Some(daemon),
+            _ => None,
+        }
+    }
+}
+
+/// Handle a FileSend.DiffCopy streaming request from BuildKit
+///
+/// Failures are reported to BuildKit in the gRPC status, which fails the
+/// export with the client's error message.
+pub(super) async fn handle_file_send_stream(
+    file_send: &FileSendServer,
+    request_stream: h2::RecvStream,
+    mut respond: SendResponse<Bytes>,
+    exporter_id: Option<String>,
+) -> Result<()> {
+    tracing::info!(
+        "FileSend.DiffCopy streaming started (exporter: {:?})",
+        exporter_id
+    );
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/grpc")
+        .body(())
+        .unwrap();
+
+    let mut send_stream = respond
+        .send_response(response, false)
+        .map_err(|e| Error::Http2Stream { source: e })?;
+
+    let mut reader = MessageReader::new(request_stream);
+    let result = match target_for(file_send, exporter_id.as_deref()) {
+        Ok(ExportTarget::Directory(dest)) => {
+            receive_directory(dest, file_send.xattrs, &mut reader, &mut send_stream).await
+        }
+        Ok(ExportTarget::File(path)) => receive_file(path, &mut reader).await,
+        Ok(ExportTarget::DockerLoad(daemon)) => load_into_docker(daemon, &mut reader).await,
+        Err(e) => Err(e),
+    };
+
+    let mut trailers = HeaderMap::new();
+    match &result {
+        Ok(()) => {
+            trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        }
+        Err(e) => {
+            tracing::error!("Failed to receive exported files: {}", e);
+            trailers.insert("grpc-status", HeaderValue::from_static("2")); // UNKNOWN
+            if let Ok(message) = HeaderValue::from_str(&grpc_message(&e.to_string())) {
+                trailers.insert("grpc-message", message);
+            }
+        }
+    }
+    send_stream
+        .send_trailers(trailers)
+        .map_err(|e| Error::Http2Stream { source: e })?;
+
+    result
+}
+
+/// Pick the target of the exporter named in the request headers
+///
+/// BuildKit versions without multiple exporters send no ID; their single
+/// exporter has index 0.
+fn target_for<'a>(
+    file_send: &'a FileSendServer,
+    exporter_id: Option<&str>,
+) -> Result<&'a ExportTarget> {
+    let id = match exporter_id {
+        Some(id) => id
+            .parse::<u32>()
+            .map_err(|_| Error::protocol(format!("invalid exporter ID: {}", id)))?,
+        None => 0,
+    };
+    file_send
+        .targets
+        .get(&id)
+        .ok_or_else(|| Error::protocol(format!("no client-side target for exporter {}", id)))
+}
+
+/// Receive a filesystem into `dest`
+async fn receive_directory(
+    dest: &Path,
+    xattrs: bool,
+    reader: &mut MessageReader,
+    send_stream: &mut h2::SendStream<Bytes>,
+) -> Result<()> {
+    tokio::fs::create_dir_all(dest)
+        .await
+        .map_err(|e| Error::file_operation("create directory", dest, e))?;
+    tracing::info!("Receiving exported files into {}", dest.display());
+
+    let mut receiver = DirectoryReceiver::new(dest.to_path_buf(), xattrs);
+    let mut listing_done = false;
+    let mut fin_sent = false;
+
+    while let Some(packet) = reader.next::<Packet>().await? {
+        let packet_type = PacketType::try_from(packet.r#type)
+            .map_err(|_| Error::protocol(format!("unknown packet type {}", packet.r#type)))?;
+
+        match packet_type {
+            PacketType::PacketStat => match packet.stat {
+                Some(stat) => {
+                    if listing_done {
+                        return Err(Error::protocol("STAT packet after end of listing"));
+                    }
+                    if let Some(id) = receiver.add(stat).await? {
+                        send_packet(send_stream, PacketType::PacketReq, id).await?;
+                    }
+                }
+                None => listing_done = true,
+            },
+            PacketType::PacketData => receiver.write(packet.id, packet.data).await?,
+            PacketType::PacketErr => {
+                return Err(Error::protocol(format!(
+                    "BuildKit failed to send exported files: {}",
+                    String::from_utf8_lossy(&packet.data)
+                )));
+            }
+            PacketType::PacketFin => {
+                if !fin_sent {
+                    return Err(Error::protocol("BuildKit ended the export early"));
+                }
+                tracing::debug!("Received FIN packet from BuildKit");
+                return receiver.finish().await;
+            }
+            PacketType::PacketReq => {
+                tracing::debug!("Ignoring REQ packet sent by BuildKit");
+            }
+        }
+
+        // All data received: tell BuildKit, which acknowledges with FIN
+        if listing_done && !fin_sent && receiver.pending.is_empty() {
+            send_packet(send_stream, PacketType::PacketFin, 0).await?;
+            fin_sent = true;
+        }
+    }
+
+    Err(Error::protocol("export stream ended before completion"))
+}
+
+/// Receive a tarball into the file at `path`
+///
+/// The data goes to a temporary file next to `path`, renamed once the
+/// stream ends, so a failed export leaves no truncated tarball behind.
+async fn receive_file(path: &Path, reader: &mut MessageReader) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::NotADirectory(path.to_path_buf()))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    tokio::fs::create_dir_all(&parent)
+        .await
+        .map_err(|e| Error::file_operation("create directory", &parent, e))?;
+    let partial = parent.join(format!(
+        ".{}.{}.partial",
+        file_name.to_string_lossy(),
+        uuid::Uuid::new_v4()
+    ));
+    tracing::info!("Receiving exported tarball into {}", path.display());
+
+    let result = async {
+        let mut file = tokio::fs::File::create(&partial)
+            .await
+            .map_err(|e| Error::file_operation("create", &partial, e))?;
+        let mut size = 0u64;
+        while let Some(message) = reader.next::<BytesMessage>().await? {
+            file.write_all(&message.data)
+                .await
+                .map_err(|e| Error::file_operation("write", &partial, e))?;
+            size += message.data.len() as u64;
+        }
+        file.sync_all()
+            .await
+            .map_err(|e| Error::file_operation("write", &partial, e))?;
+        tokio::fs::rename(&partial, path)
+            .await
+            .map_err(|e| Error::file_operation("rename", &partial, e))?;
+        Ok(size)
+    }
+    .await;
+
+    match result {
+        Ok(size) => {
+            tracing::info!("Received {} byte tarball into {}", size, path.display());
+            Ok(())
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            Err(e)
+        }
+    }
+}
+
+/// Stream a tarball into a Docker daemon while receiving it
+async fn load_into_docker(daemon: &DockerDaemon, reader: &mut MessageReader) -> Result<()> {
+    let (tx, rx) = mpsc::channel(DOCKER_LOAD_BUFFER);
+    let load = daemon.load(ReceiverStream::new(rx));
+    let forward = async move {
+        let mut size = 0u64;
+        loop {
+            let message = match reader.next::<BytesMessage>().await {
+                Ok(Some(message)) => message,
+                Ok(None) => return Ok(size),
+                Err(e) => {
+                    // Abort the upload rather than loading a truncated tarball
+                    let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                    return Err(e);
+                }
+            };
+            size += message.data.len() as u64;
+            if tx.send(Ok(Bytes::from(message.data))).await.is_err() {
+                // The daemon stopped reading; its response tells why
+                return Ok(size);
+            }
+        }
+    };
+
+    let (loaded, forwarded) = tokio::join!(load, forward);
+    let size = forwarded?;
+    let images = loaded?;
+    tracing::info!(
+        "Loaded {} byte tarball into Docker: {}",
+        size,
+        images.join(", ")
+    );
+    Ok(())
+}
+
+/// Writes received entries below a destination directory
+struct DirectoryReceiver {
+    dest: PathBuf,
+    /// Whether extended attributes are set on received entries
+    xattrs: bool,
+    /// Index of the next STAT packet, which is the ID of its data
+    next_id: u32,
+    /// Directories received, by relative path; children must be in one
+    known_dirs: HashSet<String>,
+    /// Directories whose metadata is applied once their contents are written
+    dirs: Vec<(PathBuf, Stat)>,
+    /// Regular files whose data was requested, by ID
+    pending: HashMap<u32, PendingFile>,
+    entries: usize,
+}
+
+/// A regular file waiting for its data
+struct PendingFile {
+    path: PathBuf,
+    stat: Stat,
+    /// Opened on the first DATA packet
+    file: Option<tokio::fs::File>,
+}
+
+impl DirectoryReceiver {
+    fn new(dest: PathBuf, xattrs: bool) -> Self {
+        Self {
+            dest,
+            xattrs,
+            next_id: 0,
+            known_dirs: HashSet::new(),
+            dirs: Vec::new(),
+            pending: HashMap::new(),
+            entries: 0,
+        }
+    }
+
+    /// Create the entry of a STAT packet
+    ///
+    /// Returns the ID to request data for, for regular files.
+    async fn add(&mut self, stat: Stat) -> Result<Option<u32>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries += 1;
+
+        let path = self.dest.join(self.checked_path(&stat.path)?);
+        let mode = GoFileMode::from(stat.mode);
+        tracing::debug!(
+            "Receiving STAT packet for: {} (id: {}, mode: 0o{:o})",
+            stat.path,
+            id,
+            stat.mode
+        );
+
+        if mode.is_dir() {
+            match tokio::fs::symlink_metadata(&path).await {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => {
+                    remove_existing(&path).await?;
+                    create_dir(&path).await?;
+                }
+                Err(_) => create_dir(&path).await?,
+            }
+            self.known_dirs.insert(stat.path.clone());
+            self.dirs.push((path, stat));
+            return Ok(None);
+        }
+
+        // A directory replaced by another entry can no longer hold children
+        self.forget(&stat.path);
+        remove_existing(&path).await?;
+
+        if mode.is_symlink() {
+            create_symlink(&stat.linkname, &path).await?;
+        } else if !stat.linkname.is_empty() {
+            // Hard link to an entry received earlier
+            let target = self.dest.join(self.checked_path(&stat.linkname)?);
+            tokio::fs::hard_link(&target, &path)
+                .await
+                .map_err(|e| Error::file_operation("create hard link", &path, e))?;
+        } else if mode.is_regular() {
+            // Created now so that hard links to it can be made before its
+            // data arrives
+            tokio::fs::File::create(&path)
+                .await
+                .map_err(|e| Error::file_operation("create", &path, e))?;
+            self.pending.insert(
+                id,
+                PendingFile {
+                    path,
+                    stat,
+                    file: None,
+                },
+            );
+            return Ok(Some(id));
+        } else {
+            tracing::warn!(
+                "Skipping {}: device files, pipes and sockets are not exported",
+                stat.path
+            );
+        }
+        Ok(None)
+    }
+
+    /// Write a DATA packet; an empty one completes the file
+    async fn write(&mut self, id: u32, data: Vec<u8>) -> Result<()> {
+        let pending = self
+            .pending
+            .get_mut(&id)
+            .ok_or_else(|| Error::protocol(format!("DATA packet for unrequested file {}", id)))?;
+
+        if data.is_empty() {
+            let pending = self.pending.remove(&id).expect("pending file");
+            return pending.complete(self.xattrs).await;
+        }
+
+        let file = match &mut pending.file {
+            Some(file) => file,
+            None => {
+                let file = tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&pending.path)
+                    .await
+                    .map_err(|e| Error::file_operation("open", &pending.path, e))?;
+                pending.file.insert(file)
+            }
+        };
+        file.write_all(&data)
+            .await
+            .map_err(|e| Error::file_operation("write", &pending.path, e))
+    }
+
+    /// Apply directory metadata, innermost directories first
+    async fn finish(self) -> Result<()> {
+        for (path, stat) in self.dirs.iter().rev() {
+            apply_metadata(path, stat, None, self.xattrs).await?;
+        }
+        tracing::info!(
+            "Received {} exported entries into {}",
+            self.entries,
+            self.dest.display()
+        );
+        Ok(())
+    }
+
+    /// Forget the entries at or below `rel_path`, which is being replaced
+    ///
+    /// Children received later must not pass the parent check, nor may
+    /// pending files or directory metadata be written, through whatever
+    /// takes the directory's place, e.g. a symlink leaving the destination.
+    fn forget(&mut self, rel_path: &str) {
+        let prefix = format!("{}/", rel_path);
+        let below = |path: &str| path == rel_path || path.starts_with(&prefix);
+        self.known_dirs.retain(|dir| !below(dir));
+        self.dirs.retain(|(_, stat)| !below(&stat.path));
+        self.pending.retain(|_, pending| !below(&pending.stat.path));
+    }
+
+    /// Relative path of an entry, checked to stay below the destination
+    ///
+    /// Besides rejecting absolute paths and `..`, the parent must be a
+    /// directory received earlier, so that no entry is written through a
+    /// symlink.
+    fn checked_path(&self, path: &str) -> Result<PathBuf> {
+        let relative = Path::new(path);
+        let valid = !path.is_empty()
+            && relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+        let parent_known = match path.rsplit_once('/') {
+            Some((parent, _)) => self.known_dirs.contains(parent),
+            None => true,
+        };
+        if !valid || !parent_known {
+            return Err(Error::PathOutsideRoot {
+                path: path.to_string(),
+            });
+        }
+        Ok(relative.to_path_buf())
+    }
+}
+
+impl PendingFile {
+    /// Flush the file and apply its metadata
+    async fn complete(self, xattrs: bool) -> Result<()> {
+        let file = match self.file {
+            Some(mut file) => {
+                file.flush()
+                    .await
+                    .map_err(|e| Error::file_operation("write", &self.path, e))?;
+                Some(file.into_std().await)
+            }
+            None => None,
+        };
+        apply_metadata(&self.path, &self.stat, file, xattrs).await
+    }
+}
+
+/// Set the permissions and modification time of a received entry, and its
+/// extended attributes if `xattrs` is set
+async fn apply_metadata(
+    path: &Path,
+    stat: &Stat,
+    file: Option<std::fs::File>,
+    xattrs: bool,
+) -> Result<()> {
+    let path = path.to_path_buf();
+    let attributes = (xattrs && !stat.xattrs.is_empty()).then(|| stat.xattrs.clone());
+    let permissions = GoFileMode::from(stat.mode).permissions();
+    let modified = u64::try_from(stat.mod_time)
+        .ok()
+        .filter(|nanos| *nanos > 0)
+        .map(|nanos| SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos));
+
+    tokio::task::spawn_blocking(move || {
+        let file = match file {
+            Some(file) => file,
+            None => {
+                std::fs::File::open(&path).map_err(|e| Error::file_operation("open", &path, e))?
+            }
+        };
+        // Set before the mode, which may make the entry read-only
+        if let Some(attributes) = &attributes {
+            xattrs::write(&path, attributes)?;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(permissions))
+                .map_err(|e| Error::file_operation("set permissions of", &path, e))?;
+        }
+        #[cfg(not(unix))]
+        let _ = permissions;
+        if let Some(modified) = modified {
+            file.set_modified(modified)
+                .map_err(|e| Error::file_operation("set modification time of", &path, e))?;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| Error::other(format!("metadata task failed: {}", e)))?
+}
+
+async fn create_dir(path: &Path) -> Result<()> {
+    tokio::fs::create_dir(path)
+        .await
+        .map_err(|e| Error::file_operation("create directory", path, e))
+}
+
+/// Remove whatever exists at `path`, without following symlinks
+async fn remove_existing(path: &Path) -> Result<()> {
+    let result = match tokio::fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_dir() => tokio::fs::remove_dir_all(path).await,
+        Ok(_) => tokio::fs::remove_file(path).await,
+        Err(_) => return Ok(()),
+    };
+    result.map_err(|e| Error::file_operation("remove", path, e))
+}
+
+#[cfg(unix)]
+async fn create_symlink(target: &str, path: &Path) -> Result<()> {
+    tokio::fs::symlink(target, path)
+        .await
+        .map_err(|e| Error::file_operation("create symlink", path, e))
+}
+
+#[cfg(not(unix))]
+async fn create_symlink(_target: &str, path: &Path) -> Result<()> {
+    tracing::warn!("Skipping symlink {}: not supported", path.display());
+    Ok(())
+}
+
+/// Send a packet without payload
+async fn send_packet(
+    stream: &mut h2::SendStream<Bytes>,
+    packet_type: PacketType,
+    id: u32,
+) -> Result<()> {
+    let packet = Packet {
+        r#type: packet_type as i32,
+        stat: None,
+        id,
+        data: vec![],
+    };
+    send_grpc_packet(stream, &packet).await
+}
+
+/// Reads gRPC-framed messages from an h2 stream
+pub(super) struct MessageReader {
+    pub(super) stream: h2::RecvStream,
+    buffer: Vec<u8>,
+}
+
+impl MessageReader {
+    pub(super) fn new(stream: h2::RecvStream) -> Self {
+        Self {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Next message, or `None` once the stream ended
+    pub(super) async fn next<M: ProstMessage + Default>(&mut self) -> Result<Option<M>> {
+        loop {
+            if self.buffer.len() >= 5 {
+                let length = u32::from_be_bytes([
+                    self.buffer[1],
+                    self.buffer[2],
+                    self.buffer[3],
+                    self.buffer[4],
+                ]) as usize;
+                if self.buffer.len() >= 5 + length {
+                    if self.buffer[0] != 0 {
+                        return Err(Error::protocol("compressed messages are not supported"));
+                    }
+                    let message = M::decode(&self.buffer[5..5 + length]).map_err(|e| {
+                        let name = std::any::type_name::<M>();
+                        Error::decode(name.rsplit("::").next().unwrap_or(name), e)
+                    })?;
+                    self.buffer.drain(..5 + length);
+                    return Ok(Some(message));
+                }
+            }
+
+            match self.stream.data().await {
+                Some(chunk) => {
+                    let chunk = chunk.map_err(|e| Error::Http2Stream { source: e })?;
+                    let _ = self.stream.flow_control().release_capacity(chunk.len());
+                    self.buffer.extend_from_slice(&chunk);
+                }
+                None if self.buffer.is_empty() => return Ok(None),
+                None => return Err(Error::protocol("stream ended mid-message")),
+            }
+        }
+    }
+}
+
+/// Percent-encode a gRPC status message
+pub(super) fn grpc_message(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        if (0x20..0x7f).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(path: &str, mode: u32) -> Stat {
+        Stat {
+            path: path.to_string(),
+            mode,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn entries_are_written_with_their_modes() {
+        let dest = tempfile::tempdir().unwrap();
+        let mut receiver = DirectoryReceiver::new(dest.path().to_path_buf(), false);
+
+        assert_eq!(
+            receiver.add(stat("bin", 0x80000000 | 0o755)).await.unwrap(),
+            None
+        );
+        let id = receiver.add(stat("bin/app", 0o755)).await.unwrap().unwrap();
+        assert_eq!(id, 1);
+        let mut link = stat("app", 0x08000000 | 0o777);
+        link.linkname = "bin/app".to_string();
+        assert_eq!(receiver.add(link).await.unwrap(), None);
+        let mut hardlink = stat("bin/app2", 0o755);
+        hardlink.linkname = "bin/app".to_string();
+        assert_eq!(receiver.add(hardlink).await.unwrap(), None);
+
+        receiver.write(id, b"#!/bin/sh\n".to_vec()).await.unwrap();
+        receiver.write(id, vec![]).await.unwrap();
+        assert!(receiver.pending.is_empty());
+        receiver.finish().await.unwrap();
+
+        let app = dest.path().join("bin/app");
+        assert_eq!(std::fs::read(&app).unwrap(), b"#!/bin/sh\n");
+        assert_eq!(
+            std::fs::read(dest.path().join("bin/app2")).unwrap(),
+            b"#!/bin/sh\n"
+        );
+        assert_eq!(
+            std::fs::read_link(dest.path().join("app")).unwrap(),
+            Path::new("bin/app")
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&app).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn xattrs_are_set_before_the_mode() {
+        let dest = tempfile::tempdir().unwrap();
+        if !xattrs::supported_in(dest.path()) {
+            return;
+        }
+        let mut receiver = DirectoryReceiver::new(dest.path().to_path_buf(), true);
+
+        let mut readonly = stat("readonly", 0o444);
+        readonly
+            .xattrs
+            .insert("user.origin".to_string(), b"build".to_vec());
+        let id = receiver.add(readonly).await.unwrap().unwrap();
+        receiver.write(id, b"data".to_vec()).await.unwrap();
+        receiver.write(id, vec![]).await.unwrap();
+        receiver.finish().await.unwrap();
+
+        let path = dest.path().join("readonly");
+        assert_eq!(
+            xattr::get(&path, "user.origin").unwrap(),
+            Some(b"build".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn paths_escaping_the_destination_are_rejected() {
+        let dest = tempfile::tempdir().unwrap();
+        let mut receiver = DirectoryReceiver::new(dest.path().to_path_buf(), false);
+
+        let mut link = stat("escape", 0x08000000 | 0o777);
+        link.linkname = "/etc".to_string();
+        receiver.add(link).await.unwrap();
+
+        for path in ["../evil", "/etc/passwd", "escape/passwd", "missing/file"] {
+            assert!(
+                matches!(
+                    receiver.add(stat(path, 0o644)).await,
+                    Err(Error::PathOutsideRoot { .. })
+                ),
+                "{} was accepted",
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn children_of_a_replaced_directory_are_rejected() {
+        let dest = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let mut receiver = DirectoryReceiver::new(dest.path().to_path_buf(), false);
+
+        receiver.add(stat("a", 0x80000000 | 0o755)).await.unwrap();
+        receiver.add(stat("a/b", 0x80000000 | 0o755)).await.unwrap();
+        let mut link = stat("a", 0x08000000 | 0o777);
+        link.linkname = outside.path().to_string_lossy().into_owned();
+        receiver.add(link).await.unwrap();
+
+        assert!(matches!(
+            receiver.add(stat("a/b/x", 0o644)).await,
+            Err(Error::PathOutsideRoot { .. })
+        ));
+        receiver.finish().await.unwrap();
+        assert!(!outside.path().join("b").exists());
+    }
+
+    fn packet(packet_type: PacketType, stat: Option<Stat>, id: u32, data: &[u8]) -> Packet {
+        Packet {
+            r#type: packet_type as i32,
+            stat,
+            id,
+            data: data.to_vec(),
+        }
+    }
+
+    fn frame(message: &impl ProstMessage) -> Bytes {
+        let payload = message.encode_to_vec();
+        let mut framed = vec![0];
+        framed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        framed.extend_from_slice(&payload);
+        Bytes::from(framed)
+    }
+
+    /// Open a FileSend stream for `exporter_id` against an in-memory server
+    async fn open_stream(
+        file_send: FileSendServer,
+        exporter_id: &str,
+    ) -> (
+        h2::SendStream<Bytes>,
+        h2::client::ResponseFuture,
+        tokio::task::JoinHandle<Result<()>>,
+    ) {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+
+        let server = tokio::spawn(async move {
+            let mut connection = h2::server::handshake(server_io).await.unwrap();
+            let (request, respond) = connection.accept().await.unwrap().unwrap();
+            tokio::spawn(async move { while connection.accept().await.is_some() {} });
+            let exporter_id = request
+                .headers()
+                .get(EXPORTER_ID_HEADER)
+                .map(|v| v.to_str().unwrap().to_string());
+            handle_file_send_stream(&file_send, request.into_body(), respond, exporter_id).await
+        });
+
+        let (client, connection) = h2::client::handshake(client_io).await.unwrap();
+        tokio::spawn(connection);
+        let request = http::Request::builder()
+            .uri("/moby.filesync.v1.FileSend/DiffCopy")
+            .header(EXPORTER_ID_HEADER, exporter_id)
+            .body(())
+            .unwrap();
+        let (response, send) = client
+            .ready()
+            .await
+            .unwrap()
+            .send_request(request, false)
+            .unwrap();
+        (send, response, server)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn export_stream_is_received_into_target_directory() {
+        let dest = tempfile::tempdir().unwrap();
+        let file_send = FileSendServer::new().with_directory(1, dest.path());
+        let (mut send, response, server) = open_stream(file_send, "1").await;
+
+        for stat in [
+            Some(stat("etc", 0x80000000 | 0o755)),
+            Some(stat("etc/motd", 0o600)),
+            None,
+        ] {
+            let listing = packet(PacketType::PacketStat, stat, 0, b"");
+            send.send_data(frame(&listing), false).unwrap();
+        }
+
+        let mut reader = MessageReader::new(response.await.unwrap().into_body());
+        let request = reader.next::<Packet>().await.unwrap().unwrap();
+        assert_eq!(request.r#type, PacketType::PacketReq as i32);
+        assert_eq!(request.id, 1);
+
+        for data in [&b"hello"[..], b""] {
+            let data = packet(PacketType::PacketData, None, 1, data);
+            send.send_data(frame(&data), false).unwrap();
+        }
+        let fin = reader.next::<Packet>().await.unwrap().unwrap();
+        assert_eq!(fin.r#type, PacketType::PacketFin as i32);
+        let fin = packet(PacketType::PacketFin, None, 0, b"");
+        send.send_data(frame(&fin), true).unwrap();
+
+        assert!(reader.next::<Packet>().await.unwrap().is_none());
+        let trailers = reader.stream.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+        server.await.unwrap().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(dest.path().join("etc/motd")).unwrap(),
+            "hello"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tarball_stream_is_written_to_target_file() {
+        let dest = tempfile::tempdir().unwrap();
+        let path = dest.path().join("out/image.tar");
+        let file_send = FileSendServer::new()
+            .with_directory(0, dest.path().join("rootfs"))
+            .with_file(1, &path);
+        let (mut send, response, server) = open_stream(file_send, "1").await;
+
+        for chunk in [&b"oci-"[..], b"layout"] {
+            let message = BytesMessage {
+                data: chunk.to_vec(),
+            };
+            send.send_data(frame(&message), false).unwrap();
+        }
+        send.send_data(Bytes::new(), true).unwrap();
+
+        let mut reader = MessageReader::new(response.await.unwrap().into_body());
+        assert!(reader.next::<BytesMessage>().await.unwrap().is_none());
+        let trailers = reader.stream.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+        server.await.unwrap().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"oci-layout");
+        assert_eq!(
+            std::fs::read_dir(dest.path().join("out")).unwrap().count(),
+            1
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unknown_exporter_fails_the_stream() {
+        let file_send = FileSendServer::new().with_file(0, "image.tar");
+        let (mut send, response, server) = open_stream(file_send, "3").await;
+        // The server may already have reset the stream
+        let _ = send.send_data(Bytes::new(), true);
+
+        let mut reader = MessageReader::new(response.await.unwrap().into_body());
+        assert!(reader.next::<BytesMessage>().await.unwrap().is_none());
+        let trailers = reader.stream.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["grpc-status"], "2");
+        assert!(server.await.unwrap().is_err());
+    }
+
+    /// Serve one `/images/load` request on a Unix socket, returning the
+    /// request line and the uploaded tarball
+    #[cfg(unix)]
+    async fn fake_docker_daemon(
+        socket: PathBuf,
+        response: &'static str,
+    ) -> tokio::task::JoinHandle<(String, Vec<u8>)> {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !request.ends_with(b"0\r\n\r\n") {
+                let n = conn.read(&mut buf).await.unwrap();
+                assert!(n > 0, "request ended early");
+                request.extend_from_slice(&buf[..n]);
+            }
+
+            let request = String::from_utf8(request).unwrap();
+            let (head, mut chunks) = request.split_once("\r\n\r\n").unwrap();
+            let mut tarball = Vec::new();
+            while let Some((size, rest)) = chunks.split_once("\r\n") {
+                let size = usize::from_str_radix(size, 16).unwrap();
+                tarball.extend_from_slice(&rest.as_bytes()[..size]);
+                chunks = &rest[size + 2..];
+            }
+
+            let reply = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            );
+            conn.write_all(reply.as_bytes()).await.unwrap();
+            let request_line = head.lines().next().unwrap().to_string();
+            (request_line, tarball)
+        })
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tarball_stream_is_loaded_into_docker() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("docker.sock");
+        let daemon = fake_docker_daemon(
+            socket.clone(),
+            "{\"stream\":\"Loaded image: app:dev\\n\"}\n",
+        )
+        .await;
+        let file_send = FileSendServer::new().with_docker_load(0, DockerDaemon::Unix(socket));
+        let (mut send, response, server) = open_stream(file_send, "0").await;
+
+        for chunk in [&b"docker-"[..], b"archive"] {
+            let message = BytesMessage {
+                data: chunk.to_vec(),
+            };
+            send.send_data(frame(&message), false).unwrap();
+        }
+        send.send_data(Bytes::new(), true).unwrap();
+
+        let mut reader = MessageReader::new(response.await.unwrap().into_body());
+        assert!(reader.next::<BytesMessage>().await.unwrap().is_none());
+        let trailers = reader.stream.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+        server.await.unwrap().unwrap();
+
+        let (request_line, tarball) = daemon.await.unwrap();
+        assert!(request_line.starts_with("POST /images/load"));
+        assert_eq!(tarball, b"docker-archive");
+    }
+
+    #[test]
+    fn grpc_messages_are_percent_encoded() {
+        assert_eq!(grpc_message("100% done\n"), "100%25 done%0A");
+    }
+}
@@ -1,7 +1,12 @@
 //! File synchronization protocol implementation for BuildKit sessions
 
+use super::context_filter::read_dockerignore;
+use super::{ContextFilter, ContextSnapshot};
 use crate::error::{Error, Result};
+use crate::metrics::{ContextStats, TransferStats};
+use bytes::Bytes;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::fs;
 use tokio::io::AsyncReadExt;
 use tokio_stream::wrappers::ReceiverStream;
@@ -17,6 +22,39 @@ use crate::proto::moby::filesync::v1::file_sync_server::FileSync;
 #[derive(Debug, Clone)]
 pub struct FileSyncServer {
     root_path: PathBuf,
+    dockerfile_dir: Option<PathBuf>,
+    dockerfile_content: Option<Bytes>,
+    context_filter: Option<ContextFilter>,
+    ignore_patterns: Option<Vec<String>>,
+    ownership: Ownership,
+    xattrs: bool,
+    change_cache: Option<PathBuf>,
+    snapshot: Option<Arc<ContextSnapshot>>,
+    /// Shared by clones, which serve the transfers of a session
+    stats: Arc<TransferStats>,
+}
+
+/// Owner and group recorded for the context files sent to BuildKit
+///
+/// `COPY` without `--chown` keeps the ownership BuildKit received, so this
+/// decides who owns copied files in the image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Ownership {
+    /// Send every file as owned by root, as `docker build` does (default)
+    #[default]
+    Root,
+    /// Send the uid and gid of each file
+    Preserve,
+}
+
+impl Ownership {
+    /// Apply the policy to a STAT entry read from disk
+    pub(super) fn apply(self, stat: &mut Stat) {
+        if self == Ownership::Root {
+            stat.uid = 0;
+            stat.gid = 0;
+        }
+    }
 }
 
 impl FileSyncServer {
@@ -37,7 +75,223 @@ impl FileSyncServer {
     pub fn new(root_path: impl Into<PathBuf>) -> Self {
         Self {
             root_path: root_path.into(),
+            dockerfile_dir: None,
+            dockerfile_content: None,
+            context_filter: None,
+            ignore_patterns: None,
+            ownership: Ownership::default(),
+            xattrs: false,
+            change_cache: None,
+            snapshot: None,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Serve the Dockerfile from memory instead of reading it from the root path
+    ///
+    /// When BuildKit requests the `dockerfile` directory, the given content is
+    /// sent as a virtual file; the context directory is still read from disk.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::session::FileSyncServer;
+    ///
+    /// let sync = FileSyncServer::new(".").with_dockerfile_content("FROM alpine\n");
+    /// assert!(sync.dockerfile_content().is_some());
+    /// ```
+    pub fn with_dockerfile_content(mut self, content: impl Into<Bytes>) -> Self {
+        self.dockerfile_content = Some(content.into());
+        self
+    }
+
+    /// Serve the Dockerfile from another directory than the root path
+    ///
+    /// When BuildKit requests the `dockerfile` directory, it is read from
+    /// `dir`, which may be outside the context.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::session::FileSyncServer;
+    /// use std::path::Path;
+    ///
+    /// let sync = FileSyncServer::new("./app").with_dockerfile_dir("./docker");
+    /// assert_eq!(sync.dockerfile_dir(), Path::new("./docker"));
+    /// ```
+    pub fn with_dockerfile_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dockerfile_dir = Some(dir.into());
+        self
+    }
+
+    /// Only send context paths allowed by the filter
+    ///
+    /// Applies to the build context; the Dockerfile is always served.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::session::{ContextFilter, FileSyncServer};
+    ///
+    /// let sync = FileSyncServer::new(".")
+    ///     .with_context_filter(ContextFilter::new().include(["src"]));
+    /// assert!(sync.context_filter().is_some());
+    /// ```
+    pub fn with_context_filter(mut self, filter: ContextFilter) -> Self {
+        self.context_filter = Some(filter);
+        self
+    }
+
+    /// Get the context filter, if any
+    pub fn context_filter(&self) -> Option<&ContextFilter> {
+        self.context_filter.as_ref()
+    }
+
+    /// Exclude context paths matching these patterns instead of the
+    /// context's `.dockerignore`
+    ///
+    /// By default, the exclude patterns BuildKit sends with its request are
+    /// applied, falling back to the `.dockerignore` file in the root path.
+    /// Patterns set here replace both; an empty list sends the whole
+    /// context.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::session::FileSyncServer;
+    ///
+    /// let sync = FileSyncServer::new(".").with_ignore_patterns(["target", "**/*.log"]);
+    /// assert_eq!(sync.ignore_patterns().unwrap().len(), 2);
+    /// ```
+    pub fn with_ignore_patterns<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.ignore_patterns = Some(patterns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Get the patterns overriding `.dockerignore`, if any
+    pub fn ignore_patterns(&self) -> Option<&[String]> {
+        self.ignore_patterns.as_deref()
+    }
+
+    /// Filter excluding the ignored context paths
+    ///
+    /// `requested` are the exclude patterns BuildKit sent for the transfer.
+    pub(crate) fn ignore_filter(&self, requested: &[String]) -> Result<ContextFilter> {
+        if let Some(patterns) = &self.ignore_patterns {
+            return Ok(ContextFilter::new().ignore_patterns(patterns));
+        }
+        if !requested.is_empty() {
+            return Ok(ContextFilter::new().ignore_patterns(requested));
         }
+        match read_dockerignore(&self.root_path)? {
+            // As with `docker build`, the file itself is always sent
+            Some(content) => Ok(ContextFilter::new()
+                .dockerignore(&content)
+                .ignore_patterns(["!.dockerignore"])),
+            None => Ok(ContextFilter::new()),
+        }
+    }
+
+    /// Set the ownership recorded for context files
+    ///
+    /// Modification times are always sent, so that BuildKit notices
+    /// changed files.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::session::{FileSyncServer, Ownership};
+    ///
+    /// let sync = FileSyncServer::new(".").with_ownership(Ownership::Preserve);
+    /// assert_eq!(sync.ownership(), Ownership::Preserve);
+    /// ```
+    pub fn with_ownership(mut self, ownership: Ownership) -> Self {
+        self.ownership = ownership;
+        self
+    }
+
+    /// Get the ownership recorded for context files
+    pub fn ownership(&self) -> Ownership {
+        self.ownership
+    }
+
+    /// Send the extended attributes of context files
+    ///
+    /// Off by default. When enabled, attributes such as
+    /// `security.capability` and `user.*` are recorded in STAT packets;
+    /// filesystems without xattr support are sent without any.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::session::FileSyncServer;
+    ///
+    /// let sync = FileSyncServer::new(".").with_xattrs(true);
+    /// assert!(sync.xattrs());
+    /// ```
+    pub fn with_xattrs(mut self, enabled: bool) -> Self {
+        self.xattrs = enabled;
+        self
+    }
+
+    /// Whether extended attributes of context files are sent
+    pub fn xattrs(&self) -> bool {
+        self.xattrs
+    }
+
+    /// Keep content hashes of context files in `dir` between builds
+    ///
+    /// Files rewritten without changing are sent with the modification
+    /// time BuildKit already has, so they are not uploaded again, and
+    /// snapshots skip hashing unchanged files. See [`ChangeCache`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use buildkit_client::session::FileSyncServer;
+    /// use std::path::Path;
+    ///
+    /// let sync = FileSyncServer::new(".").with_change_cache("/tmp/context-cache");
+    /// assert_eq!(sync.change_cache_dir(), Some(Path::new("/tmp/context-cache")));
+    /// ```
+    ///
+    /// [`ChangeCache`]: super::ChangeCache
+    pub fn with_change_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.change_cache = Some(dir.into());
+        self
+    }
+
+    /// Get the directory content hashes are kept in, if any
+    pub fn change_cache_dir(&self) -> Option<&Path> {
+        self.change_cache.as_deref()
+    }
+
+    /// Serve the context from a snapshot instead of the live directory
+    ///
+    /// The snapshot should be captured from the same root path; any context
+    /// filter was already applied when it was captured.
+    pub fn with_snapshot(mut self, snapshot: Arc<ContextSnapshot>) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Get the context snapshot, if any
+    pub fn snapshot(&self) -> Option<&ContextSnapshot> {
+        self.snapshot.as_deref()
+    }
+
+    /// What the transfers of this server and its clones sent so far
+    pub fn context_stats(&self) -> ContextStats {
+        self.stats.stats()
+    }
+
+    /// Counters the transfers of this server update
+    pub(super) fn transfer_stats(&self) -> &TransferStats {
+        &self.stats
     }
 
     /// Get the root path
@@ -45,10 +299,21 @@ impl FileSyncServer {
         self.root_path.clone()
     }
 
+    /// Directory the Dockerfile is served from, the root path by default
+    pub fn dockerfile_dir(&self) -> &Path {
+        self.dockerfile_dir.as_deref().unwrap_or(&self.root_path)
+    }
+
+    /// Get the in-memory Dockerfile content, if any
+    pub fn dockerfile_content(&self) -> Option<&Bytes> {
+        self.dockerfile_content.as_ref()
+    }
+
     /// Check if a path is within the allowed root directory
     fn validate_path(&self, rel_path: &str) -> Result<PathBuf> {
         let full_path = self.root_path.join(rel_path);
-        let canonical = std::fs::canonicalize(&full_path)?;
+        let canonical = std::fs::canonicalize(&full_path)
+            .map_err(|e| Error::file_operation("resolve", &full_path, e))?;
 
         if !canonical.starts_with(&self.root_path) {
             return Err(Error::PathOutsideRoot {
@@ -61,7 +326,9 @@ impl FileSyncServer {
 
     /// Create a stat packet from file metadata
     async fn create_stat_packet(path: &Path, rel_path: &str) -> Result<Packet> {
-        let metadata = fs::metadata(path).await?;
+        let metadata = fs::metadata(path)
+            .await
+            .map_err(|e| Error::file_operation("stat", path, e))?;
 
         let mut stat = Stat {
             path: rel_path.to_string(),
@@ -103,9 +370,15 @@ impl FileSyncServer {
         tx: &'a tokio::sync::mpsc::Sender<std::result::Result<Packet, Status>>,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send + 'a>> {
         Box::pin(async move {
-            let mut entries = fs::read_dir(path).await?;
+            let mut entries = fs::read_dir(path)
+                .await
+                .map_err(|e| Error::file_operation("read directory", path, e))?;
 
-            while let Some(entry) = entries.next_entry().await? {
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .map_err(|e| Error::file_operation("read directory", path, e))?
+            {
                 let file_name = entry.file_name();
                 let name = file_name.to_string_lossy();
                 let rel_path = if prefix.is_empty() {
@@ -138,12 +411,17 @@ impl FileSyncServer {
         id: u32,
         tx: &tokio::sync::mpsc::Sender<std::result::Result<Packet, Status>>,
     ) -> Result<()> {
-        let mut file = fs::File::open(path).await?;
+        let mut file = fs::File::open(path)
+            .await
+            .map_err(|e| Error::file_operation("open", path, e))?;
 
         let mut buffer = vec![0u8; 1024 * 1024]; // 1MB chunks
 
         loop {
-            let n = file.read(&mut buffer).await?;
+            let n = file
+                .read(&mut buffer)
+                .await
+                .map_err(|e| Error::file_operation("read", path, e))?;
             if n == 0 {
                 break;
             }
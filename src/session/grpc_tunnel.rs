@@ -8,20 +8,40 @@ use bytes::Bytes;
 use h2::server::{self, SendResponse};
 use http::{Request, Response, StatusCode};
 use prost::Message as ProstMessage;
+use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::task::{Context as TaskContext, Poll};
+use std::task::{ready, Context as TaskContext, Poll};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::sync::mpsc;
-
-use super::{AuthServer, FileSyncServer, SecretsServer};
+use tokio::task::JoinSet;
+use tokio_util::sync::{CancellationToken, PollSender};
+use tracing::Instrument;
+
+use super::{
+    AuthServer, ContentStoreServer, FileSendServer, FileSyncServer, SecretsServer, SshForwardServer,
+};
+use crate::proto::grpc::health::v1::health_check_response::ServingStatus;
+use crate::proto::grpc::health::v1::HealthCheckRequest;
 use crate::proto::moby::buildkit::v1::BytesMessage;
 
+/// DiffCopy of the file sync service, which ends its own transfer on shutdown
+const FILE_SYNC_DIFF_COPY: &str = "/moby.filesync.v1.FileSync/DiffCopy";
+
 /// Stream multiplexer for handling gRPC tunneled through session
 pub struct GrpcTunnel {
     file_sync: Option<FileSyncServer>,
-    auth: Option<AuthServer>,
+    file_sync_dirs: HashMap<String, FileSyncServer>,
+    file_send: Option<FileSendServer>,
+    /// Without configured credentials, tokens are fetched anonymously
+    auth: AuthServer,
     secrets: Option<SecretsServer>,
+    ssh_forward: Option<SshForwardServer>,
+    content: Option<ContentStoreServer>,
+    /// Stops accepting calls, letting those in flight finish
+    drain: CancellationToken,
+    /// Abandons the calls in flight
+    shutdown: CancellationToken,
 }
 
 impl GrpcTunnel {
@@ -29,22 +49,59 @@ impl GrpcTunnel {
     pub fn new(
         _response_tx: mpsc::Sender<BytesMessage>,
         file_sync: Option<FileSyncServer>,
+        file_send: Option<FileSendServer>,
         auth: Option<AuthServer>,
         secrets: Option<SecretsServer>,
+        ssh_forward: Option<SshForwardServer>,
     ) -> Self {
         Self {
             file_sync,
-            auth,
+            file_sync_dirs: HashMap::new(),
+            file_send,
+            auth: auth.unwrap_or_default(),
             secrets,
+            ssh_forward,
+            content: None,
+            drain: CancellationToken::new(),
+            shutdown: CancellationToken::new(),
         }
     }
 
+    /// Serve additional directories, by the `dir-name` BuildKit asks for
+    pub fn with_file_sync_dirs(mut self, dirs: HashMap<String, FileSyncServer>) -> Self {
+        self.file_sync_dirs = dirs;
+        self
+    }
+
+    /// Serve the content stores of local caches
+    pub fn with_content_store(mut self, content: Option<ContentStoreServer>) -> Self {
+        self.content = content;
+        self
+    }
+
+    /// Stop serving when `drain` or `shutdown` is cancelled
+    ///
+    /// Once `drain` is cancelled, BuildKit can start no more calls and
+    /// [`Self::serve`] returns when those in flight have finished and the
+    /// HTTP/2 connection is closed. Cancelling `shutdown` drains the tunnel
+    /// too, and abandons the calls in flight: DiffCopy transfers end with a
+    /// `CANCELLED` status, other calls have their stream reset.
+    pub fn with_shutdown(mut self, drain: CancellationToken, shutdown: CancellationToken) -> Self {
+        self.drain = drain;
+        self.shutdown = shutdown;
+        self
+    }
+
     /// Start HTTP/2 server over the session stream
+    ///
+    /// Returns once the connection is closed and every call has finished.
     pub async fn serve(
         self,
         inbound_rx: mpsc::Receiver<BytesMessage>,
         outbound_tx: mpsc::Sender<BytesMessage>,
     ) -> Result<()> {
+        let drain = self.drain.clone();
+        let shutdown = self.shutdown.clone();
         let tunnel = Arc::new(self);
 
         // Create a wrapper that implements AsyncRead + AsyncWrite
@@ -58,17 +115,58 @@ impl GrpcTunnel {
         tracing::info!("HTTP/2 server started in session tunnel");
 
         // Accept incoming HTTP/2 streams
-        while let Some(result) = h2_conn.accept().await {
-            let (request, respond) = result.map_err(|e| Error::Http2Stream { source: e })?;
-            let tunnel_ref = Arc::clone(&tunnel);
-
-            tokio::spawn(async move {
-                if let Err(e) = tunnel_ref.handle_request(request, respond).await {
-                    tracing::error!("Failed to handle gRPC request: {}", e);
+        let mut calls = JoinSet::new();
+        let mut call_id = 0u64;
+        let mut draining = false;
+        let stopping = async {
+            tokio::select! {
+                _ = drain.cancelled() => {}
+                _ = shutdown.cancelled() => {}
+            }
+        };
+        tokio::pin!(stopping);
+        loop {
+            tokio::select! {
+                accepted = h2_conn.accept() => {
+                    let Some(result) = accepted else { break };
+                    let (request, respond) = result.map_err(|e| Error::Http2Stream { source: e })?;
+                    let method = request.uri().path().to_string();
+                    let tunnel_ref = Arc::clone(&tunnel);
+                    let shutdown = shutdown.clone();
+                    call_id += 1;
+                    // Logs of the call carry the session's fields too
+                    let span = tracing::info_span!("grpc_call", call_id, method = %method);
+
+                    let handle = async move {
+                        let call = tunnel_ref.handle_request(request, respond);
+                        // DiffCopy ends its own transfer on shutdown; other
+                        // calls are dropped, which resets their streams
+                        let handled = if method == FILE_SYNC_DIFF_COPY {
+                            Some(call.await)
+                        } else {
+                            shutdown.run_until_cancelled(call).await
+                        };
+                        match handled {
+                            Some(Ok(())) => {}
+                            Some(Err(e)) => tracing::error!("Failed to handle gRPC request: {}", e),
+                            None => tracing::debug!("Abandoned gRPC call"),
+                        }
+                    };
+                    calls.spawn(handle.instrument(span));
+                }
+                _ = &mut stopping, if !draining => {
+                    tracing::debug!("Draining session tunnel, {} calls in flight", calls.len());
+                    draining = true;
+                    h2_conn.graceful_shutdown();
                 }
-            });
+                Some(_) = calls.join_next() => {}
+            }
         }
 
+        // Calls whose streams outlived the connection fail on their own
+        while calls.join_next().await.is_some() {}
+        tracing::info!("HTTP/2 server in session tunnel stopped");
+
         Ok(())
     }
 
@@ -79,7 +177,7 @@ impl GrpcTunnel {
         respond: SendResponse<Bytes>,
     ) -> Result<()> {
         let method = req.uri().path().to_string();
-        tracing::info!("Received gRPC call: {}", method);
+        tracing::debug!("Received gRPC call");
 
         // Extract dir-name header before consuming req
         let dir_name = req
@@ -97,19 +195,77 @@ impl GrpcTunnel {
             .map(|s| s.to_string())
             .collect();
 
+        // Extract the patterns of paths to leave out of a transfer
+        let exclude_patterns: Vec<String> = req
+            .headers()
+            .get_all("exclude-patterns")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .map(|s| s.to_string())
+            .collect();
+
+        // Extract the exporter a FileSend stream is for
+        let exporter_id = req
+            .headers()
+            .get(super::filesend::EXPORTER_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+
+        // Extract the agent a ForwardAgent stream is for
+        let ssh_id = req
+            .headers()
+            .get(super::ssh::SSH_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+
+        // Extract the content store a content call is for
+        let store_id = req
+            .headers()
+            .get(super::content::STORE_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+
         let body = req.into_body();
 
         // Dispatch to appropriate service
         match method.as_str() {
             "/grpc.health.v1.Health/Check" => {
-                // Read request body for unary RPC
                 let payload = Self::read_unary_request(body).await?;
-                let response_payload = self.handle_health_check(payload).await?;
-                self.send_success_response(respond, response_payload).await
+                let request = HealthCheckRequest::decode(payload)
+                    .map_err(|e| Error::decode("HealthCheckRequest", e))?;
+                tracing::debug!("Health.Check for service '{}'", request.service);
+                match self.serving_status(&request.service) {
+                    Some(status) => {
+                        let response_payload = super::health::encode_status(status)?;
+                        self.send_success_response(respond, response_payload).await
+                    }
+                    None => {
+                        let status = tonic::Status::not_found(format!(
+                            "unknown service {}",
+                            request.service
+                        ));
+                        self.send_status_response(respond, status).await
+                    }
+                }
             }
-            "/moby.filesync.v1.FileSync/DiffCopy" => {
+            "/grpc.health.v1.Health/Watch" => {
+                // The request is a single message ending BuildKit's side
+                let payload = Self::read_unary_request(body).await?;
+                let request = HealthCheckRequest::decode(payload)
+                    .map_err(|e| Error::decode("HealthCheckRequest", e))?;
+                tracing::debug!("Health.Watch for service '{}'", request.service);
+                // Unknown services are reported, not refused
+                let status = self
+                    .serving_status(&request.service)
+                    .unwrap_or(ServingStatus::ServiceUnknown);
+                super::health::handle_watch_stream(status, respond, &self.drain).await
+            }
+            FILE_SYNC_DIFF_COPY => {
                 // DiffCopy is a bidirectional streaming RPC - delegate to diffcopy module
-                let file_sync = match &self.file_sync {
+                let named = dir_name
+                    .as_deref()
+                    .and_then(|name| self.file_sync_dirs.get(name));
+                let file_sync = match named.or(self.file_sync.as_ref()) {
                     Some(fs) => fs,
                     None => {
                         tracing::error!("FileSync not available");
@@ -124,16 +280,44 @@ impl GrpcTunnel {
                     respond,
                     dir_name,
                     followpaths,
+                    exclude_patterns,
+                    &self.shutdown,
                 )
                 .await
             }
-            "/moby.filesync.v1.Auth/GetTokenAuthority" => {
-                // Token-based auth not supported - return error to make BuildKit fall back
-                // BuildKit requires either a valid pubkey or error to properly fallback to Credentials
-                tracing::info!("Auth.GetTokenAuthority called - returning not implemented");
-                self.send_error_response(respond, "Token auth not implemented")
+            "/moby.filesync.v1.FileSend/DiffCopy" => {
+                // Exporters stream their output back to the client
+                let file_send = match &self.file_send {
+                    Some(fs) => fs,
+                    None => {
+                        tracing::error!("FileSend not available");
+                        return self
+                            .send_error_response(respond, "FileSend not available")
+                            .await;
+                    }
+                };
+                super::filesend::handle_file_send_stream(file_send, body, respond, exporter_id)
                     .await
             }
+            "/moby.filesync.v1.Auth/GetTokenAuthority" => {
+                let payload = Self::read_unary_request(body).await?;
+                match self.handle_auth_get_token_authority(payload).await? {
+                    Ok(response_payload) => {
+                        self.send_success_response(respond, response_payload).await
+                    }
+                    // Unavailable makes BuildKit fall back to Credentials
+                    Err(status) => self.send_status_response(respond, status).await,
+                }
+            }
+            "/moby.filesync.v1.Auth/VerifyTokenAuthority" => {
+                let payload = Self::read_unary_request(body).await?;
+                match self.handle_auth_verify_token_authority(payload).await? {
+                    Ok(response_payload) => {
+                        self.send_success_response(respond, response_payload).await
+                    }
+                    Err(status) => self.send_status_response(respond, status).await,
+                }
+            }
             "/moby.filesync.v1.Auth/Credentials" => {
                 let payload = Self::read_unary_request(body).await?;
                 let response_payload = self.handle_auth_credentials(payload).await?;
@@ -141,14 +325,72 @@ impl GrpcTunnel {
             }
             "/moby.filesync.v1.Auth/FetchToken" => {
                 let payload = Self::read_unary_request(body).await?;
-                let response_payload = self.handle_auth_fetch_token(payload).await?;
-                self.send_success_response(respond, response_payload).await
+                match self.handle_auth_fetch_token(payload).await? {
+                    Ok(response_payload) => {
+                        self.send_success_response(respond, response_payload).await
+                    }
+                    Err(status) => {
+                        tracing::warn!("Auth.FetchToken failed: {}", status.message());
+                        self.send_status_response(respond, status).await
+                    }
+                }
             }
             "/moby.buildkit.secrets.v1.Secrets/GetSecret" => {
                 let payload = Self::read_unary_request(body).await?;
                 let response_payload = self.handle_secrets_get_secret(payload).await?;
                 self.send_success_response(respond, response_payload).await
             }
+            "/moby.sshforward.v1.SSH/CheckAgent" => {
+                let payload = Self::read_unary_request(body).await?;
+                let checked = match &self.ssh_forward {
+                    Some(ssh) => ssh.check_agent(payload),
+                    None => Err(Error::protocol("SSH forwarding not available")),
+                };
+                match checked {
+                    Ok(response_payload) => {
+                        self.send_success_response(respond, response_payload).await
+                    }
+                    Err(e) => {
+                        tracing::error!("SSH.CheckAgent failed: {}", e);
+                        let message = super::filesend::grpc_message(&e.to_string());
+                        self.send_error_response(respond, &message).await
+                    }
+                }
+            }
+            "/moby.sshforward.v1.SSH/ForwardAgent" => {
+                // ForwardAgent is a bidirectional stream of agent protocol bytes
+                let ssh = match &self.ssh_forward {
+                    Some(ssh) => ssh,
+                    None => {
+                        tracing::error!("SSH forwarding not available");
+                        return self
+                            .send_error_response(respond, "SSH forwarding not available")
+                            .await;
+                    }
+                };
+                super::ssh::handle_forward_agent_stream(ssh, body, respond, ssh_id).await
+            }
+            content_method
+                if content_method.starts_with("/containerd.services.content.v1.Content/") =>
+            {
+                let content = match &self.content {
+                    Some(content) => content,
+                    None => {
+                        tracing::error!("Content store not available");
+                        return self
+                            .send_error_response(respond, "Content store not available")
+                            .await;
+                    }
+                };
+                super::content::handle_content_stream(
+                    content,
+                    content_method,
+                    body,
+                    respond,
+                    store_id,
+                )
+                .await
+            }
             _ => {
                 tracing::warn!("Unknown gRPC method: {}", method);
                 self.send_error_response(respond, "Unimplemented").await
@@ -216,6 +458,29 @@ impl GrpcTunnel {
         Ok(())
     }
 
+    /// Send a failed gRPC response with the status of a service
+    async fn send_status_response(
+        &self,
+        mut respond: SendResponse<Bytes>,
+        status: tonic::Status,
+    ) -> Result<()> {
+        let code = (status.code() as i32).to_string();
+        let message = super::filesend::grpc_message(status.message());
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "application/grpc")
+            .header("grpc-status", code)
+            .header("grpc-message", message)
+            .body(())
+            .unwrap();
+
+        respond
+            .send_response(response, true)
+            .map_err(|e| Error::Http2Stream { source: e })?;
+
+        Ok(())
+    }
+
     /// Send error gRPC response
     async fn send_error_response(
         &self,
@@ -238,24 +503,61 @@ impl GrpcTunnel {
     }
 
     /// Handle Auth.GetTokenAuthority request
-    #[allow(dead_code)]
-    async fn handle_auth_get_token_authority(&self, payload: Bytes) -> Result<Bytes> {
-        use crate::proto::moby::filesync::v1::{
-            GetTokenAuthorityRequest, GetTokenAuthorityResponse,
-        };
+    async fn handle_auth_get_token_authority(
+        &self,
+        payload: Bytes,
+    ) -> Result<std::result::Result<Bytes, tonic::Status>> {
+        use crate::proto::moby::filesync::v1::auth_server::Auth;
+        use crate::proto::moby::filesync::v1::GetTokenAuthorityRequest;
+        use tonic::Request;
 
         let request = GetTokenAuthorityRequest::decode(payload)
             .map_err(|e| Error::decode("GetTokenAuthorityRequest", e))?;
 
         tracing::info!("Auth.GetTokenAuthority request for host: {}", request.host);
 
-        // Return empty response - we don't implement token-based auth
-        // BuildKit will detect empty public_key and fall back to Credentials method
-        let response = GetTokenAuthorityResponse { public_key: vec![] };
+        let response = match self.auth.get_token_authority(Request::new(request)).await {
+            Ok(response) => response.into_inner(),
+            Err(status) => {
+                tracing::debug!("No token authority: {}", status.message());
+                return Ok(Err(status));
+            }
+        };
 
         let mut buf = Vec::new();
         response.encode(&mut buf)?;
-        Ok(Bytes::from(buf))
+        Ok(Ok(Bytes::from(buf)))
+    }
+
+    /// Handle Auth.VerifyTokenAuthority request
+    async fn handle_auth_verify_token_authority(
+        &self,
+        payload: Bytes,
+    ) -> Result<std::result::Result<Bytes, tonic::Status>> {
+        use crate::proto::moby::filesync::v1::auth_server::Auth;
+        use crate::proto::moby::filesync::v1::VerifyTokenAuthorityRequest;
+        use tonic::Request;
+
+        let request = VerifyTokenAuthorityRequest::decode(payload)
+            .map_err(|e| Error::decode("VerifyTokenAuthorityRequest", e))?;
+
+        tracing::info!(
+            "Auth.VerifyTokenAuthority request for host: {}",
+            request.host
+        );
+
+        let response = match self
+            .auth
+            .verify_token_authority(Request::new(request))
+            .await
+        {
+            Ok(response) => response.into_inner(),
+            Err(status) => return Ok(Err(status)),
+        };
+
+        let mut buf = Vec::new();
+        response.encode(&mut buf)?;
+        Ok(Ok(Bytes::from(buf)))
     }
 
     /// Handle Auth.Credentials request
@@ -269,43 +571,33 @@ impl GrpcTunnel {
 
         tracing::info!("Auth.Credentials request for host: {}", request.host);
 
-        // Use AuthServer if configured, otherwise return empty credentials
-        let response = if let Some(auth) = &self.auth {
-            match auth.credentials(Request::new(request.clone())).await {
-                Ok(resp) => {
-                    let inner = resp.into_inner();
-                    if !inner.username.is_empty() {
-                        tracing::debug!(
-                            "Returning credentials for host: {} (username: {})",
-                            request.host,
-                            inner.username
-                        );
-                    } else {
-                        tracing::debug!(
-                            "No credentials found for host: {}, returning empty",
-                            request.host
-                        );
-                    }
-                    inner
-                }
-                Err(status) => {
-                    tracing::warn!(
-                        "Failed to get credentials: {}, returning empty",
-                        status.message()
+        let response = match self.auth.credentials(Request::new(request.clone())).await {
+            Ok(resp) => {
+                let inner = resp.into_inner();
+                if !inner.username.is_empty() {
+                    tracing::debug!(
+                        "Returning credentials for host: {} (username: {})",
+                        request.host,
+                        inner.username
+                    );
+                } else {
+                    tracing::debug!(
+                        "No credentials found for host: {}, returning empty",
+                        request.host
                     );
-                    use crate::proto::moby::filesync::v1::CredentialsResponse;
-                    CredentialsResponse {
-                        username: String::new(),
-                        secret: String::new(),
-                    }
                 }
+                inner
             }
-        } else {
-            tracing::debug!("No auth configured, returning empty credentials");
-            use crate::proto::moby::filesync::v1::CredentialsResponse;
-            CredentialsResponse {
-                username: String::new(),
-                secret: String::new(),
+            Err(status) => {
+                tracing::warn!(
+                    "Failed to get credentials: {}, returning empty",
+                    status.message()
+                );
+                use crate::proto::moby::filesync::v1::CredentialsResponse;
+                CredentialsResponse {
+                    username: String::new(),
+                    secret: String::new(),
+                }
             }
         };
 
@@ -315,20 +607,30 @@ impl GrpcTunnel {
     }
 
     /// Handle Auth.FetchToken request
-    async fn handle_auth_fetch_token(&self, _payload: Bytes) -> Result<Bytes> {
-        use crate::proto::moby::filesync::v1::FetchTokenResponse;
+    ///
+    /// Returns the status of a failed token exchange separately, so that
+    /// BuildKit reports why the registry refused the credentials.
+    async fn handle_auth_fetch_token(
+        &self,
+        payload: Bytes,
+    ) -> Result<std::result::Result<Bytes, tonic::Status>> {
+        use crate::proto::moby::filesync::v1::auth_server::Auth;
+        use crate::proto::moby::filesync::v1::FetchTokenRequest;
+        use tonic::Request;
+
+        let request = FetchTokenRequest::decode(payload)
+            .map_err(|e| Error::decode("FetchTokenRequest", e))?;
 
-        tracing::info!("Auth.FetchToken called");
+        tracing::info!("Auth.FetchToken request for host: {}", request.host);
 
-        let response = FetchTokenResponse {
-            token: String::new(),
-            expires_in: 0,
-            issued_at: 0,
+        let response = match self.auth.fetch_token(Request::new(request)).await {
+            Ok(response) => response.into_inner(),
+            Err(status) => return Ok(Err(status)),
         };
 
         let mut buf = Vec::new();
         response.encode(&mut buf)?;
-        Ok(Bytes::from(buf))
+        Ok(Ok(Bytes::from(buf)))
     }
 
     /// Handle Secrets.GetSecret request
@@ -371,36 +673,44 @@ impl GrpcTunnel {
         Ok(Bytes::from(buf))
     }
 
-    /// Handle Health.Check request
-    async fn handle_health_check(&self, _payload: Bytes) -> Result<Bytes> {
-        tracing::info!("Health check called");
-
-        // Health check response: status = SERVING (1)
-        // The proto definition is:
-        // message HealthCheckResponse {
-        //   enum ServingStatus {
-        //     UNKNOWN = 0;
-        //     SERVING = 1;
-        //     NOT_SERVING = 2;
-        //   }
-        //   ServingStatus status = 1;
-        // }
-
-        // Manually encode: field 1, varint type, value 1
-        let response = vec![0x08, 0x01]; // field 1 (0x08 = 0001|000) = value 1
-        Ok(Bytes::from(response))
+    /// Health of a service of the session, `None` if the session has no
+    /// such service
+    ///
+    /// Services that are always available, like auth, are serving; those
+    /// that depend on the build's configuration are serving if it set them
+    /// up. A draining session serves nothing.
+    fn serving_status(&self, service: &str) -> Option<ServingStatus> {
+        let available = match service {
+            "" | "grpc.health.v1.Health" | "moby.filesync.v1.Auth" => true,
+            "moby.filesync.v1.FileSync" => {
+                self.file_sync.is_some() || !self.file_sync_dirs.is_empty()
+            }
+            "moby.filesync.v1.FileSend" => self.file_send.is_some(),
+            "moby.buildkit.secrets.v1.Secrets" => self.secrets.is_some(),
+            "moby.sshforward.v1.SSH" => self.ssh_forward.is_some(),
+            "containerd.services.content.v1.Content" => self.content.is_some(),
+            _ => return None,
+        };
+        if available && !self.drain.is_cancelled() {
+            Some(ServingStatus::Serving)
+        } else {
+            Some(ServingStatus::NotServing)
+        }
     }
 }
 
 /// A stream that wraps BytesMessage channels to implement AsyncRead + AsyncWrite
 ///
-/// h2 takes exclusive ownership of this stream and does NOT split it,
-/// so no Arc<Mutex> is needed on the receiver.
+/// h2 takes exclusive ownership of this stream and does NOT split it, so
+/// the receiver is owned outright. A message larger than the read buffer
+/// is kept and handed out over several reads. Writes wait for room in the
+/// outbound channel, so a slow BuildKit holds h2 back rather than the
+/// messages piling up.
 struct MessageStream {
     inbound_rx: mpsc::Receiver<BytesMessage>,
-    outbound_tx: mpsc::Sender<BytesMessage>,
-    read_buffer: Vec<u8>,
-    read_pos: usize,
+    outbound_tx: PollSender<BytesMessage>,
+    /// Rest of the last message received, not read yet
+    read_buffer: Bytes,
     read_count: u64,
     write_count: u64,
 }
@@ -412,9 +722,8 @@ impl MessageStream {
     ) -> Self {
         Self {
             inbound_rx,
-            outbound_tx,
-            read_buffer: Vec::new(),
-            read_pos: 0,
+            outbound_tx: PollSender::new(outbound_tx),
+            read_buffer: Bytes::new(),
             read_count: 0,
             write_count: 0,
         }
@@ -423,53 +732,39 @@ impl MessageStream {
 
 impl AsyncRead for MessageStream {
     fn poll_read(
-        mut self: Pin<&mut Self>,
+        self: Pin<&mut Self>,
         cx: &mut TaskContext<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
-        // If we have buffered data, return it
-        if self.read_pos < self.read_buffer.len() {
-            let remaining = &self.read_buffer[self.read_pos..];
-            let to_copy = remaining.len().min(buf.remaining());
-            buf.put_slice(&remaining[..to_copy]);
-            self.read_pos += to_copy;
-
-            // Clear buffer if fully consumed
-            if self.read_pos >= self.read_buffer.len() {
-                self.read_buffer.clear();
-                self.read_pos = 0;
-            }
-
-            return Poll::Ready(Ok(()));
-        }
-
-        // Poll the receiver directly (no mutex needed)
-        match self.inbound_rx.poll_recv(cx) {
-            Poll::Ready(Some(msg)) => {
-                self.read_count += 1;
-                tracing::debug!(
-                    read_count = self.read_count,
-                    data_len = msg.data.len(),
-                    "MessageStream: poll_read got data"
-                );
-                self.read_buffer = msg.data;
-                self.read_pos = 0;
-
-                let to_copy = self.read_buffer.len().min(buf.remaining());
-                buf.put_slice(&self.read_buffer[..to_copy]);
-                self.read_pos = to_copy;
+        let this = self.get_mut();
 
-                Poll::Ready(Ok(()))
-            }
-            Poll::Ready(None) => {
-                tracing::info!(
-                    total_reads = self.read_count,
-                    "MessageStream: poll_read EOF (channel closed)"
-                );
-                Poll::Ready(Ok(()))
+        // An empty read means EOF, so skip empty messages
+        while this.read_buffer.is_empty() {
+            // The receiver registers the waker when no message is ready
+            match ready!(this.inbound_rx.poll_recv(cx)) {
+                Some(msg) => {
+                    this.read_count += 1;
+                    #[cfg(feature = "debug_wire")]
+                    tracing::trace!(
+                        read_count = this.read_count,
+                        data_len = msg.data.len(),
+                        "MessageStream: poll_read got data"
+                    );
+                    this.read_buffer = Bytes::from(msg.data);
+                }
+                None => {
+                    tracing::debug!(
+                        total_reads = this.read_count,
+                        "MessageStream: poll_read EOF (channel closed)"
+                    );
+                    return Poll::Ready(Ok(()));
+                }
             }
-            Poll::Pending => Poll::Pending,
         }
+
+        let to_copy = this.read_buffer.len().min(buf.remaining());
+        buf.put_slice(&this.read_buffer.split_to(to_copy));
+        Poll::Ready(Ok(()))
     }
 }
 
@@ -480,32 +775,25 @@ impl AsyncWrite for MessageStream {
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
         let this = self.get_mut();
-        let msg = BytesMessage { data: buf.to_vec() };
 
-        match this.outbound_tx.try_send(msg) {
-            Ok(()) => {
-                this.write_count += 1;
-                tracing::debug!(
-                    write_count = this.write_count,
-                    data_len = buf.len(),
-                    "MessageStream: poll_write sent data"
-                );
-                Poll::Ready(Ok(buf.len()))
-            }
-            Err(mpsc::error::TrySendError::Full(_)) => {
-                tracing::warn!("MessageStream: outbound channel full, scheduling retry");
-                // Register waker so we get polled again
-                cx.waker().wake_by_ref();
-                Poll::Pending
-            }
-            Err(mpsc::error::TrySendError::Closed(_)) => {
-                tracing::error!("MessageStream: outbound channel closed");
-                Poll::Ready(Err(std::io::Error::new(
-                    std::io::ErrorKind::BrokenPipe,
-                    "Channel closed",
-                )))
-            }
+        // Wait for room in the channel; the sender registers the waker
+        if ready!(this.outbound_tx.poll_reserve(cx)).is_err() {
+            tracing::error!("MessageStream: outbound channel closed");
+            return Poll::Ready(Err(channel_closed()));
         }
+        let msg = BytesMessage { data: buf.to_vec() };
+        this.outbound_tx
+            .send_item(msg)
+            .map_err(|_| channel_closed())?;
+
+        this.write_count += 1;
+        #[cfg(feature = "debug_wire")]
+        tracing::trace!(
+            write_count = this.write_count,
+            data_len = buf.len(),
+            "MessageStream: poll_write sent data"
+        );
+        Poll::Ready(Ok(buf.len()))
     }
 
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
@@ -513,6 +801,92 @@ impl AsyncWrite for MessageStream {
     }
 
     fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        tracing::debug!(total_writes = this.write_count, "MessageStream: shutdown");
+        this.outbound_tx.close();
         Poll::Ready(Ok(()))
     }
 }
+
+fn channel_closed() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Channel closed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn large_messages_are_read_in_pieces() {
+        let (inbound_tx, inbound_rx) = mpsc::channel(4);
+        let (outbound_tx, _outbound_rx) = mpsc::channel(4);
+        let mut stream = MessageStream::new(inbound_rx, outbound_tx);
+
+        for data in [b"hello".to_vec(), Vec::new(), b" world".to_vec()] {
+            inbound_tx.send(BytesMessage { data }).await.unwrap();
+        }
+        drop(inbound_tx);
+
+        let mut piece = [0u8; 3];
+        stream.read_exact(&mut piece).await.unwrap();
+        assert_eq!(&piece, b"hel");
+        // The empty message is not taken for the end of the stream
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"lo world");
+    }
+
+    #[tokio::test]
+    async fn writes_wait_for_room_in_the_channel() {
+        let (_inbound_tx, inbound_rx) = mpsc::channel(1);
+        let (outbound_tx, mut outbound_rx) = mpsc::channel(1);
+        let mut stream = MessageStream::new(inbound_rx, outbound_tx);
+
+        stream.write_all(b"first").await.unwrap();
+        let writer = tokio::spawn(async move {
+            stream.write_all(b"second").await.unwrap();
+            stream
+        });
+        tokio::task::yield_now().await;
+        assert!(!writer.is_finished());
+
+        // Receiving makes room and wakes the writer
+        assert_eq!(outbound_rx.recv().await.unwrap().data, b"first");
+        let mut stream = writer.await.unwrap();
+        assert_eq!(outbound_rx.recv().await.unwrap().data, b"second");
+
+        drop(outbound_rx);
+        assert!(stream.write_all(b"third").await.is_err());
+    }
+
+    #[test]
+    fn services_are_serving_when_configured() {
+        let (response_tx, _response_rx) = mpsc::channel(1);
+        let drain = CancellationToken::new();
+        let tunnel = GrpcTunnel::new(
+            response_tx,
+            Some(FileSyncServer::new(".")),
+            None,
+            None,
+            Some(SecretsServer::new()),
+            None,
+        )
+        .with_shutdown(drain.clone(), CancellationToken::new());
+
+        let serving = Some(ServingStatus::Serving);
+        let not_serving = Some(ServingStatus::NotServing);
+        assert_eq!(tunnel.serving_status(""), serving);
+        assert_eq!(tunnel.serving_status("moby.filesync.v1.FileSync"), serving);
+        assert_eq!(tunnel.serving_status("moby.filesync.v1.Auth"), serving);
+        assert_eq!(
+            tunnel.serving_status("moby.buildkit.secrets.v1.Secrets"),
+            serving
+        );
+        assert_eq!(tunnel.serving_status("moby.sshforward.v1.SSH"), not_serving);
+        assert_eq!(tunnel.serving_status("moby.example.v1.Unknown"), None);
+
+        drain.cancel();
+        assert_eq!(tunnel.serving_status(""), not_serving);
+    }
+}
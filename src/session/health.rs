@@ -0,0 +1,135 @@
+//! gRPC health checking of the session's services
+//!
+//! BuildKit asks `grpc.health.v1.Health` whether the session is alive,
+//! either once with `Check` or by keeping a `Watch` stream open. Services
+//! are named by their full gRPC name, e.g. `moby.filesync.v1.FileSync`;
+//! the empty name stands for the session as a whole.
+
+use crate::error::{Error, Result};
+use crate::proto::grpc::health::v1::health_check_response::ServingStatus;
+use crate::proto::grpc::health::v1::HealthCheckResponse;
+use bytes::Bytes;
+use h2::server::SendResponse;
+use http::{HeaderMap, HeaderValue, Response, StatusCode};
+use prost::Message as ProstMessage;
+use tokio_util::sync::CancellationToken;
+
+use super::diffcopy::send_with_capacity;
+
+/// Encode the response reporting `status`
+pub(super) fn encode_status(status: ServingStatus) -> Result<Bytes> {
+    let response = HealthCheckResponse {
+        status: status as i32,
+    };
+    let mut buf = Vec::new();
+    response.encode(&mut buf)?;
+    Ok(Bytes::from(buf))
+}
+
+/// Handle a Health.Watch stream from BuildKit
+///
+/// Sends `status` right away and keeps the stream open until BuildKit
+/// resets it or `drain` is cancelled. A draining session reports
+/// `NOT_SERVING` and ends the stream with `OK`, after which BuildKit's
+/// watch retries against a new session.
+pub(super) async fn handle_watch_stream(
+    status: ServingStatus,
+    mut respond: SendResponse<Bytes>,
+    drain: &CancellationToken,
+) -> Result<()> {
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/grpc")
+        .body(())
+        .unwrap();
+
+    let mut send_stream = respond
+        .send_response(response, false)
+        .map_err(|e| Error::Http2Stream { source: e })?;
+
+    send_status(&mut send_stream, status).await?;
+
+    tokio::select! {
+        _ = drain.cancelled() => {}
+        // Resets end the call without a status to send
+        _ = std::future::poll_fn(|cx| send_stream.poll_reset(cx)) => {
+            tracing::debug!("Health.Watch stream closed by BuildKit");
+            return Ok(());
+        }
+    }
+
+    if status != ServingStatus::NotServing {
+        send_status(&mut send_stream, ServingStatus::NotServing).await?;
+    }
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from_static("0"));
+    send_stream
+        .send_trailers(trailers)
+        .map_err(|e| Error::Http2Stream { source: e })?;
+
+    Ok(())
+}
+
+/// Send one gRPC-framed status message
+async fn send_status(send_stream: &mut h2::SendStream<Bytes>, status: ServingStatus) -> Result<()> {
+    let payload = encode_status(status)?;
+    let mut framed = Vec::with_capacity(5 + payload.len());
+    framed.push(0); // No compression
+    framed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    framed.extend_from_slice(&payload);
+    send_with_capacity(send_stream, Bytes::from(framed)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Request;
+    use tokio::io::duplex;
+
+    #[tokio::test]
+    async fn watch_reports_not_serving_when_draining() {
+        let (client_io, server_io) = duplex(64 * 1024);
+        let drain = CancellationToken::new();
+
+        let server_drain = drain.clone();
+        let server_task = tokio::spawn(async move {
+            let mut connection = h2::server::handshake(server_io).await.unwrap();
+            let (_request, respond) = connection.accept().await.unwrap().unwrap();
+            let drive = tokio::spawn(async move { while connection.accept().await.is_some() {} });
+            let result = handle_watch_stream(ServingStatus::Serving, respond, &server_drain).await;
+            (result, drive)
+        });
+
+        let (client, connection) = h2::client::handshake(client_io).await.unwrap();
+        let client_task = tokio::spawn(async move {
+            let _ = connection.await;
+        });
+        let (response_future, _request_stream) = client
+            .ready()
+            .await
+            .unwrap()
+            .send_request(Request::builder().uri("/").body(()).unwrap(), true)
+            .unwrap();
+        let mut body = response_future.await.unwrap().into_body();
+
+        let first = body.data().await.unwrap().unwrap();
+        assert_eq!(
+            &first[5..],
+            &encode_status(ServingStatus::Serving).unwrap()[..]
+        );
+
+        drain.cancel();
+        let second = body.data().await.unwrap().unwrap();
+        assert_eq!(
+            &second[5..],
+            &encode_status(ServingStatus::NotServing).unwrap()[..]
+        );
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+
+        let (result, drive) = server_task.await.unwrap();
+        result.unwrap();
+        drive.abort();
+        client_task.abort();
+    }
+}
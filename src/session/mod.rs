@@ -1,30 +1,69 @@
 //! BuildKit session implementation for file access and streaming
 
 pub mod auth;
+pub mod change_cache;
+mod content;
+pub mod context_filter;
 mod diffcopy;
+pub mod docker_config;
+pub mod filesend;
 pub mod filesync;
 pub mod grpc_tunnel;
+mod health;
 pub mod secrets;
+pub mod snapshot;
+pub mod ssh;
+mod xattrs;
 
 use crate::error::{Error, Result};
+use crate::metrics::ContextStats;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use tonic::transport::Channel;
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::proto::moby::buildkit::v1::{control_client::ControlClient, BytesMessage};
 use grpc_tunnel::GrpcTunnel;
 
 pub use auth::{AuthServer, RegistryAuthConfig};
-pub use filesync::FileSyncServer;
+pub use change_cache::ChangeCache;
+pub use content::ContentStoreServer;
+pub(crate) use content::{local_cache_digest, tag_local_cache};
+pub use context_filter::ContextFilter;
+pub use docker_config::DockerConfig;
+pub use filesend::FileSendServer;
+pub use filesync::{FileSyncServer, Ownership};
 pub use secrets::SecretsServer;
+pub use snapshot::ContextSnapshot;
+pub use ssh::{SshAgent, SshForwardServer, SshKey};
+
+/// Methods of the containerd content service, used for local caches
+const CONTENT_METHODS: &[&str] = &[
+    "Info",
+    "Update",
+    "List",
+    "Delete",
+    "Read",
+    "Status",
+    "ListStatuses",
+    "Write",
+    "Abort",
+];
+
+/// How long [`Session::stop`] waits for the calls in flight to finish
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Session manager for BuildKit
 ///
 /// Manages a BuildKit session lifecycle including file synchronization,
-/// authentication, and bidirectional gRPC streaming.
+/// authentication, and bidirectional gRPC streaming. The session is closed
+/// when it is dropped.
 pub struct Session {
     /// Unique session identifier (UUID format)
     pub id: String,
@@ -32,13 +71,24 @@ pub struct Session {
     pub shared_key: String,
     tx: Option<mpsc::Sender<BytesMessage>>,
     services: Arc<Mutex<SessionServices>>,
+    /// Ends the stream to BuildKit, which then tears the session down
+    shutdown: CancellationToken,
+    /// Stops the tunnel accepting calls; a child of `shutdown`
+    drain: CancellationToken,
+    /// Task serving the calls of BuildKit, once started
+    tunnel: Option<JoinHandle<()>>,
 }
 
 /// Session service handlers
 struct SessionServices {
     file_sync: Option<FileSyncServer>,
+    /// Directories besides the build context, by the name BuildKit asks for
+    file_sync_dirs: HashMap<String, FileSyncServer>,
+    file_send: Option<FileSendServer>,
     auth: Option<AuthServer>,
     secrets: Option<SecretsServer>,
+    ssh_forward: Option<SshForwardServer>,
+    content: Option<ContentStoreServer>,
 }
 
 impl Session {
@@ -46,6 +96,7 @@ impl Session {
     pub fn new() -> Self {
         let id = Uuid::new_v4().to_string();
         let shared_key = format!("session-{}", Uuid::new_v4());
+        let shutdown = CancellationToken::new();
 
         Self {
             id,
@@ -53,9 +104,16 @@ impl Session {
             tx: None,
             services: Arc::new(Mutex::new(SessionServices {
                 file_sync: None,
+                file_sync_dirs: HashMap::new(),
+                file_send: None,
                 auth: None,
                 secrets: None,
+                ssh_forward: None,
+                content: None,
             })),
+            drain: shutdown.child_token(),
+            shutdown,
+            tunnel: None,
         }
     }
 
@@ -66,6 +124,31 @@ impl Session {
         tracing::debug!("Added FileSync service");
     }
 
+    /// Add a preconfigured file sync service
+    pub async fn add_file_sync_server(&mut self, file_sync: FileSyncServer) {
+        let mut services = self.services.lock().await;
+        services.file_sync = Some(file_sync);
+        tracing::debug!("Added FileSync service");
+    }
+
+    /// Serve another directory under `name`, e.g. for a named build context
+    ///
+    /// BuildKit asks for it by name, as in `local:<name>`; requests for
+    /// other names go to the file sync service of the build context.
+    pub async fn add_file_sync_dir(&mut self, name: impl Into<String>, file_sync: FileSyncServer) {
+        let name = name.into();
+        let mut services = self.services.lock().await;
+        tracing::debug!("Added FileSync directory {}", name);
+        services.file_sync_dirs.insert(name, file_sync);
+    }
+
+    /// Add file send service receiving exporter outputs
+    pub async fn add_file_send(&mut self, file_send: FileSendServer) {
+        let mut services = self.services.lock().await;
+        services.file_send = Some(file_send);
+        tracing::debug!("Added FileSend service");
+    }
+
     /// Add authentication service
     pub async fn add_auth(&mut self, auth: AuthServer) {
         let mut services = self.services.lock().await;
@@ -80,6 +163,20 @@ impl Session {
         tracing::debug!("Added Secrets service");
     }
 
+    /// Add SSH agent forwarding service
+    pub async fn add_ssh_forward(&mut self, ssh_forward: SshForwardServer) {
+        let mut services = self.services.lock().await;
+        services.ssh_forward = Some(ssh_forward);
+        tracing::debug!("Added SSH forwarding service");
+    }
+
+    /// Add content store service for local cache imports and exports
+    pub async fn add_content_store(&mut self, content: ContentStoreServer) {
+        let mut services = self.services.lock().await;
+        services.content = Some(content);
+        tracing::debug!("Added content store service");
+    }
+
     /// Start a session with BuildKit
     pub async fn start(&mut self, mut control: ControlClient<Channel>) -> Result<()> {
         let (tx, mut rx) = mpsc::channel::<BytesMessage>(128);
@@ -87,11 +184,21 @@ impl Session {
         let services = Arc::clone(&self.services);
 
         tracing::info!("Starting session: {}", session_id);
+        // Logs of the session's tasks and calls carry its ID
+        let span = tracing::info_span!("session", session_id = %session_id);
 
-        // Create the outbound stream
+        // Create the outbound stream, ending it when the session is closed
+        let shutdown = self.shutdown.clone();
         let outbound = async_stream::stream! {
-            while let Some(msg) = rx.recv().await {
-                yield msg;
+            loop {
+                let msg = tokio::select! {
+                    msg = rx.recv() => msg,
+                    _ = shutdown.cancelled() => None,
+                };
+                match msg {
+                    Some(msg) => yield msg,
+                    None => break,
+                }
             }
         };
 
@@ -124,75 +231,93 @@ impl Session {
         // Get services for tunnel
         let services_guard = services.lock().await;
         let file_sync = services_guard.file_sync.clone();
+        let file_sync_dirs = services_guard.file_sync_dirs.clone();
+        let file_send = services_guard.file_send.clone();
         let auth = services_guard.auth.clone();
         let secrets = services_guard.secrets.clone();
+        let ssh_forward = services_guard.ssh_forward.clone();
+        let content = services_guard.content.clone();
         drop(services_guard);
 
         // Spawn task to receive from BuildKit and forward to tunnel
-        tokio::spawn(async move {
-            let mut msg_count = 0u64;
-            loop {
-                match inbound.message().await {
-                    Ok(Some(msg)) => {
-                        msg_count += 1;
-                        tracing::info!(
-                            session_id = %session_id,
-                            msg_count = msg_count,
-                            data_len = msg.data.len(),
-                            "inbound: received message from BuildKit"
-                        );
-                        if let Err(e) = inbound_tx.send(msg).await {
-                            tracing::error!("Failed to forward inbound message: {}", e);
+        tokio::spawn(
+            async move {
+                let mut msg_count = 0u64;
+                loop {
+                    match inbound.message().await {
+                        Ok(Some(msg)) => {
+                            msg_count += 1;
+                            #[cfg(feature = "debug_wire")]
+                            tracing::trace!(
+                                msg_count = msg_count,
+                                data_len = msg.data.len(),
+                                "inbound: received message from BuildKit"
+                            );
+                            if let Err(e) = inbound_tx.send(msg).await {
+                                tracing::error!("Failed to forward inbound message: {}", e);
+                                break;
+                            }
+                        }
+                        Ok(None) => {
+                            tracing::debug!(
+                                total_messages = msg_count,
+                                "inbound: BuildKit stream ended (None)"
+                            );
+                            break;
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                error = %e,
+                                total_messages = msg_count,
+                                "inbound: BuildKit stream error"
+                            );
                             break;
                         }
-                    }
-                    Ok(None) => {
-                        tracing::info!(
-                            session_id = %session_id,
-                            total_messages = msg_count,
-                            "inbound: BuildKit stream ended (None)"
-                        );
-                        break;
-                    }
-                    Err(e) => {
-                        tracing::error!(
-                            session_id = %session_id,
-                            error = %e,
-                            total_messages = msg_count,
-                            "inbound: BuildKit stream error"
-                        );
-                        break;
                     }
                 }
             }
-        });
+            .instrument(span.clone()),
+        );
 
         // Spawn task to receive from tunnel and forward to BuildKit
         let tx_clone = tx.clone();
-        tokio::spawn(async move {
-            let mut msg_count = 0u64;
-            while let Some(msg) = outbound_rx.recv().await {
-                msg_count += 1;
-                tracing::info!(
-                    msg_count = msg_count,
-                    data_len = msg.data.len(),
-                    "outbound: forwarding message to BuildKit"
-                );
-                if let Err(e) = tx_clone.send(msg).await {
-                    tracing::error!("Failed to forward outbound message: {}", e);
-                    break;
+        tokio::spawn(
+            async move {
+                let mut msg_count = 0u64;
+                while let Some(msg) = outbound_rx.recv().await {
+                    msg_count += 1;
+                    #[cfg(feature = "debug_wire")]
+                    tracing::trace!(
+                        msg_count = msg_count,
+                        data_len = msg.data.len(),
+                        "outbound: forwarding message to BuildKit"
+                    );
+                    if let Err(e) = tx_clone.send(msg).await {
+                        tracing::error!("Failed to forward outbound message: {}", e);
+                        break;
+                    }
                 }
+                tracing::debug!(
+                    total_messages = msg_count,
+                    "outbound: tunnel→BuildKit task ended"
+                );
             }
-            tracing::info!(total_messages = msg_count, "outbound: tunnel→BuildKit task ended");
-        });
+            .instrument(span.clone()),
+        );
 
         // Start the HTTP/2 server in the tunnel
-        let tunnel = GrpcTunnel::new(tx.clone(), file_sync, auth, secrets);
-        tokio::spawn(async move {
-            if let Err(e) = tunnel.serve(inbound_rx, outbound_tx).await {
-                tracing::error!("HTTP/2 tunnel error: {}", e);
+        let tunnel = GrpcTunnel::new(tx.clone(), file_sync, file_send, auth, secrets, ssh_forward)
+            .with_file_sync_dirs(file_sync_dirs)
+            .with_content_store(content)
+            .with_shutdown(self.drain.clone(), self.shutdown.clone());
+        self.tunnel = Some(tokio::spawn(
+            async move {
+                if let Err(e) = tunnel.serve(inbound_rx, outbound_tx).await {
+                    tracing::error!("HTTP/2 tunnel error: {}", e);
+                }
             }
-        });
+            .instrument(span),
+        ));
 
         self.tx = Some(tx);
         Ok(())
@@ -217,19 +342,75 @@ impl Session {
         // Add supported gRPC methods
         let methods = vec![
             "/grpc.health.v1.Health/Check".to_string(),
+            "/grpc.health.v1.Health/Watch".to_string(),
             "/moby.filesync.v1.FileSync/DiffCopy".to_string(),
             "/moby.filesync.v1.FileSync/TarStream".to_string(),
+            "/moby.filesync.v1.FileSend/DiffCopy".to_string(),
             "/moby.filesync.v1.Auth/Credentials".to_string(),
             "/moby.filesync.v1.Auth/FetchToken".to_string(),
             "/moby.filesync.v1.Auth/GetTokenAuthority".to_string(),
             "/moby.filesync.v1.Auth/VerifyTokenAuthority".to_string(),
             "/moby.buildkit.secrets.v1.Secrets/GetSecret".to_string(),
+            "/moby.sshforward.v1.SSH/CheckAgent".to_string(),
+            "/moby.sshforward.v1.SSH/ForwardAgent".to_string(),
         ];
+        let methods = methods
+            .into_iter()
+            .chain(
+                CONTENT_METHODS
+                    .iter()
+                    .map(|method| format!("/containerd.services.content.v1.Content/{}", method)),
+            )
+            .collect();
         meta.insert("X-Docker-Expose-Session-Grpc-Method".to_string(), methods);
 
         meta
     }
 
+    /// Close the session
+    ///
+    /// Ends the stream to BuildKit, which releases the session's resources,
+    /// and abandons the calls in flight; builds still using it fail. See
+    /// [`Self::stop`] to let the calls finish first. Closing twice has no
+    /// effect.
+    pub fn close(&self) {
+        if !self.shutdown.is_cancelled() {
+            tracing::debug!("Closing session: {}", self.id);
+            self.shutdown.cancel();
+        }
+    }
+
+    /// Stop the session once the calls in flight have finished
+    ///
+    /// BuildKit can start no more calls, while DiffCopy transfers and other
+    /// streams already running finish; then the session is closed as by
+    /// [`Self::close`]. Calls still running after 10 seconds are abandoned.
+    pub async fn stop(&mut self) {
+        self.drain.cancel();
+        if let Some(tunnel) = self.tunnel.take() {
+            tracing::debug!("Draining session: {}", self.id);
+            if tokio::time::timeout(STOP_TIMEOUT, tunnel).await.is_err() {
+                tracing::warn!(
+                    "Session {} still has calls in flight after {:?}, abandoning them",
+                    self.id,
+                    STOP_TIMEOUT
+                );
+            }
+        }
+        self.close();
+    }
+
+    /// What the session sent BuildKit from local directories so far
+    pub async fn context_stats(&self) -> ContextStats {
+        let services = self.services.lock().await;
+        services
+            .file_sync
+            .iter()
+            .chain(services.file_sync_dirs.values())
+            .map(FileSyncServer::context_stats)
+            .fold(ContextStats::default(), |total, stats| total + stats)
+    }
+
     /// Send a message to the session stream
     pub async fn send(&self, msg: BytesMessage) -> Result<()> {
         if let Some(ref tx) = self.tx {
@@ -254,6 +435,12 @@ impl Default for Session {
     }
 }
 
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
 /// File sync helper for sending local files to BuildKit
 pub struct FileSync {
     context_path: PathBuf,
@@ -1,5 +1,6 @@
 //! Secrets protocol implementation for BuildKit sessions
 
+use crate::error::{Error, Result};
 use crate::proto::moby::secrets::v1::{
     secrets_server::Secrets, GetSecretRequest, GetSecretResponse,
 };
@@ -12,12 +13,21 @@ const MAX_SECRET_SIZE: usize = 500 * 1024;
 /// Secrets server implementation for BuildKit session
 ///
 /// Provides secrets to BuildKit during build operations when using
-/// `RUN --mount=type=secret,id=<secret_id>` in Dockerfiles.
-#[derive(Debug, Clone, Default)]
+/// `RUN --mount=type=secret,id=<secret_id>` in Dockerfiles. The `Debug`
+/// output lists secret IDs only.
+#[derive(Clone, Default)]
 pub struct SecretsServer {
     secrets: HashMap<String, Vec<u8>>,
 }
 
+impl std::fmt::Debug for SecretsServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretsServer")
+            .field("secrets", &self.secrets.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
 impl SecretsServer {
     /// Create a new secrets server
     ///
@@ -43,7 +53,8 @@ impl SecretsServer {
     ///
     /// # Returns
     ///
-    /// Returns `Err` if the secret data exceeds MAX_SECRET_SIZE (500KB)
+    /// Returns [`Error::SecretTooLarge`] if the secret data exceeds
+    /// MAX_SECRET_SIZE (500KB)
     ///
     /// # Example
     ///
@@ -53,15 +64,16 @@ impl SecretsServer {
     /// let mut secrets = SecretsServer::new();
     /// secrets.add_secret("api_key", "secret_value".as_bytes().to_vec()).unwrap();
     /// ```
-    pub fn add_secret(&mut self, id: impl Into<String>, data: Vec<u8>) -> Result<(), String> {
+    pub fn add_secret(&mut self, id: impl Into<String>, data: Vec<u8>) -> Result<()> {
+        let id = id.into();
         if data.len() > MAX_SECRET_SIZE {
-            return Err(format!(
-                "Secret size {} exceeds maximum of {}",
-                data.len(),
-                MAX_SECRET_SIZE
-            ));
+            return Err(Error::SecretTooLarge {
+                id,
+                size: data.len(),
+                max: MAX_SECRET_SIZE,
+            });
         }
-        self.secrets.insert(id.into(), data);
+        self.secrets.insert(id, data);
         Ok(())
     }
 
@@ -84,7 +96,7 @@ impl SecretsServer {
         &mut self,
         id: impl Into<String>,
         value: impl AsRef<str>,
-    ) -> Result<(), String> {
+    ) -> Result<()> {
         self.add_secret(id, value.as_ref().as_bytes().to_vec())
     }
 
@@ -104,7 +116,7 @@ impl SecretsServer {
     /// map.insert("api_key".to_string(), "secret_value".to_string());
     /// let secrets = SecretsServer::from_map(map).unwrap();
     /// ```
-    pub fn from_map(secrets: HashMap<String, String>) -> Result<Self, String> {
+    pub fn from_map(secrets: HashMap<String, String>) -> Result<Self> {
         let mut server = Self::new();
         for (id, value) in secrets {
             server.add_secret_string(id, value)?;
@@ -118,7 +130,7 @@ impl Secrets for SecretsServer {
     async fn get_secret(
         &self,
         request: Request<GetSecretRequest>,
-    ) -> Result<Response<GetSecretResponse>, Status> {
+    ) -> std::result::Result<Response<GetSecretResponse>, Status> {
         let req = request.into_inner();
         tracing::debug!(
             "Secret requested - ID: {}, Annotations: {:?}",
@@ -0,0 +1,477 @@
+//! Point-in-time snapshots of the build context
+
+use super::diffcopy::{hard_link_key, stat_for};
+use super::{ChangeCache, ContextFilter};
+use crate::error::{Error, Result};
+use crate::proto::fsutil::types::Stat;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Files whose handles are kept open; later files are reopened by path
+const MAX_OPEN_FILES: usize = 512;
+
+/// Size of the chunks file data is read in
+const CHUNK_SIZE: usize = 32 * 1024;
+
+/// File list and content hashes of a context, taken at build start
+///
+/// A snapshotted context is served exactly as it was captured: the listing
+/// sent to BuildKit comes from the snapshot, and file data is read from
+/// handles opened during the capture, so files replaced or deleted by an
+/// editor mid-build are still served with their original content. Content
+/// that cannot be served as captured (a file modified in place, or removed
+/// after its handle was closed) fails the upload with
+/// [`Error::ContextChanged`] instead of sending half-saved data.
+///
+/// # Example
+///
+/// ```no_run
+/// use buildkit_client::session::{ContextSnapshot, FileSyncServer};
+/// use std::sync::Arc;
+///
+/// let snapshot = ContextSnapshot::capture("./my-app", None)?;
+/// let sync = FileSyncServer::new("./my-app").with_snapshot(Arc::new(snapshot));
+/// # Ok::<(), buildkit_client::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct ContextSnapshot {
+    root: PathBuf,
+    entries: Vec<SnapshotEntry>,
+}
+
+/// Entry of a snapshot, in the depth-first order it is sent in
+#[derive(Debug)]
+pub(super) struct SnapshotEntry {
+    pub(super) stat: Stat,
+    pub(super) file: Option<SnapshotFile>,
+    /// Device and inode of a file with several links
+    pub(super) hard_link_key: Option<(u64, u64)>,
+}
+
+/// Regular file of a snapshot
+#[derive(Debug, Clone)]
+pub(super) struct SnapshotFile {
+    path: PathBuf,
+    size: u64,
+    /// Hex SHA-256 of the content
+    digest: String,
+    handle: Option<Arc<File>>,
+}
+
+impl ContextSnapshot {
+    /// Walk `root` and hash every file allowed by `filter`
+    ///
+    /// This reads the whole context and blocks; call it from
+    /// [`tokio::task::spawn_blocking`] in async code.
+    pub fn capture(root: impl AsRef<Path>, filter: Option<&ContextFilter>) -> Result<Self> {
+        Self::capture_filtered(root.as_ref(), filter.as_slice(), None)
+    }
+
+    /// Like [`capture`](Self::capture), but only hash files whose size or
+    /// modification time differ from `cache`
+    ///
+    /// Files are recorded with the modification times the cache settles
+    /// on. Save the cache afterwards to keep new hashes.
+    pub fn capture_with_cache(
+        root: impl AsRef<Path>,
+        filter: Option<&ContextFilter>,
+        cache: &mut ChangeCache,
+    ) -> Result<Self> {
+        Self::capture_filtered(root.as_ref(), filter.as_slice(), Some(cache))
+    }
+
+    /// Capture the paths every one of `filters` allows, e.g. a context
+    /// filter and the `.dockerignore` filter, so that ignored trees are
+    /// never read
+    pub(crate) fn capture_filtered(
+        root: &Path,
+        filters: &[&ContextFilter],
+        cache: Option<&mut ChangeCache>,
+    ) -> Result<Self> {
+        let root = root.to_path_buf();
+        let mut entries = Vec::new();
+        let mut open_files = 0;
+        capture_dir(&root, "", filters, cache, &mut entries, &mut open_files)?;
+        tracing::debug!(
+            "Snapshotted {} context entries of {}",
+            entries.len(),
+            root.display()
+        );
+        Ok(Self { root, entries })
+    }
+
+    /// Root directory of the snapshot
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Number of files and directories in the snapshot
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the snapshot is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Context-relative paths in the snapshot, depth-first
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|e| e.stat.path.as_str())
+    }
+
+    /// Hex SHA-256 of a file's content at capture time
+    pub fn file_digest(&self, rel_path: &str) -> Option<String> {
+        self.entries
+            .iter()
+            .find(|e| e.stat.path == rel_path)
+            .and_then(|e| e.file.as_ref())
+            .map(|f| f.digest.clone())
+    }
+
+    pub(super) fn entries(&self) -> &[SnapshotEntry] {
+        &self.entries
+    }
+}
+
+fn capture_dir(
+    dir: &Path,
+    prefix: &str,
+    filters: &[&ContextFilter],
+    mut cache: Option<&mut ChangeCache>,
+    entries: &mut Vec<SnapshotEntry>,
+    open_files: &mut usize,
+) -> Result<()> {
+    let mut children = Vec::new();
+    for entry in
+        std::fs::read_dir(dir).map_err(|e| Error::file_operation("read directory", dir, e))?
+    {
+        let entry = entry.map_err(|e| Error::file_operation("read directory", dir, e))?;
+        let path = entry.path();
+        let metadata = std::fs::symlink_metadata(&path)
+            .map_err(|e| Error::file_operation("stat", &path, e))?;
+        let linkname = if metadata.is_symlink() {
+            std::fs::read_link(&path)
+                .map_err(|e| Error::file_operation("read symlink", &path, e))?
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            String::new()
+        };
+        children.push((
+            entry.file_name().to_string_lossy().to_string(),
+            path,
+            metadata,
+            linkname,
+        ));
+    }
+    // Sort entries alphabetically by name (fsutil requirement)
+    children.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (name, path, metadata, linkname) in children {
+        let rel_path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        if filters
+            .iter()
+            .any(|f| !f.allows(&rel_path, metadata.is_dir()))
+        {
+            continue;
+        }
+
+        let mut stat = stat_for(rel_path.clone(), &metadata, linkname);
+        let file = if metadata.is_file() {
+            let keep_open = *open_files < MAX_OPEN_FILES;
+            let cached = cache.as_deref_mut().and_then(|cache| {
+                cache
+                    .lookup(&rel_path, stat.size, stat.mod_time)
+                    .map(|(digest, sent_mod_time)| (digest.to_string(), sent_mod_time))
+            });
+            let file = match cached {
+                Some((digest, sent_mod_time)) => {
+                    stat.mod_time = sent_mod_time;
+                    SnapshotFile::open(path.clone(), stat.size as u64, digest, keep_open)?
+                }
+                None => {
+                    let file = SnapshotFile::capture(path.clone(), keep_open)?;
+                    // The size actually hashed wins if the file was being written
+                    stat.size = file.size as i64;
+                    if let Some(cache) = cache.as_deref_mut() {
+                        stat.mod_time =
+                            cache.update(&rel_path, stat.size, stat.mod_time, file.digest.clone());
+                    }
+                    file
+                }
+            };
+            if file.handle.is_some() {
+                *open_files += 1;
+            }
+            Some(file)
+        } else {
+            None
+        };
+        entries.push(SnapshotEntry {
+            stat,
+            file,
+            hard_link_key: hard_link_key(&metadata),
+        });
+
+        if metadata.is_dir() {
+            capture_dir(
+                &path,
+                &rel_path,
+                filters,
+                cache.as_deref_mut(),
+                entries,
+                open_files,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+impl SnapshotFile {
+    fn capture(path: PathBuf, keep_open: bool) -> Result<Self> {
+        let mut file = File::open(&path).map_err(|e| Error::file_operation("open", &path, e))?;
+        let mut hasher = Sha256::new();
+        let size = std::io::copy(&mut file, &mut hasher)
+            .map_err(|e| Error::file_operation("read", &path, e))?;
+        Ok(Self {
+            path,
+            size,
+            digest: format!("{:x}", hasher.finalize()),
+            handle: keep_open.then(|| Arc::new(file)),
+        })
+    }
+
+    /// A file whose content is known from a change cache
+    fn open(path: PathBuf, size: u64, digest: String, keep_open: bool) -> Result<Self> {
+        let handle = if keep_open {
+            let file = File::open(&path).map_err(|e| Error::file_operation("open", &path, e))?;
+            Some(Arc::new(file))
+        } else {
+            None
+        };
+        Ok(Self {
+            path,
+            size,
+            digest,
+            handle,
+        })
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reader verifying the content against the snapshot
+    pub(super) fn reader(&self) -> Result<SnapshotReader<'_>> {
+        let handle = match &self.handle {
+            Some(handle) => handle.clone(),
+            None => Arc::new(File::open(&self.path).map_err(|e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    Error::ContextChanged(self.path.clone())
+                } else {
+                    Error::file_operation("open", &self.path, e)
+                }
+            })?),
+        };
+        Ok(SnapshotReader {
+            file: self,
+            handle,
+            offset: 0,
+            hasher: Sha256::new(),
+        })
+    }
+}
+
+/// Chunked reader over a snapshotted file
+pub(super) struct SnapshotReader<'a> {
+    file: &'a SnapshotFile,
+    handle: Arc<File>,
+    offset: u64,
+    hasher: Sha256,
+}
+
+impl SnapshotReader<'_> {
+    /// Next chunk of data, or `None` at the end of a file that matches the
+    /// snapshot
+    pub(super) async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        let handle = self.handle.clone();
+        let offset = self.offset;
+        let chunk = tokio::task::spawn_blocking(move || {
+            let mut buf = vec![0u8; CHUNK_SIZE];
+            let n = read_at(&handle, &mut buf, offset)?;
+            buf.truncate(n);
+            Ok::<_, std::io::Error>(buf)
+        })
+        .await
+        .map_err(|e| Error::other(format!("snapshot read task failed: {}", e)))?
+        .map_err(|e| Error::file_operation("read", &self.file.path, e))?;
+
+        self.offset += chunk.len() as u64;
+        if self.offset > self.file.size {
+            return Err(Error::ContextChanged(self.file.path.clone()));
+        }
+        if chunk.is_empty() {
+            let digest = format!("{:x}", std::mem::take(&mut self.hasher).finalize());
+            if self.offset != self.file.size || digest != self.file.digest {
+                return Err(Error::ContextChanged(self.file.path.clone()));
+            }
+            return Ok(None);
+        }
+        self.hasher.update(&chunk);
+        Ok(Some(chunk))
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read_all(file: &SnapshotFile) -> Result<Vec<u8>> {
+        let mut reader = file.reader()?;
+        let mut data = Vec::new();
+        while let Some(chunk) = reader.next_chunk().await? {
+            data.extend(chunk);
+        }
+        Ok(data)
+    }
+
+    fn file<'a>(snapshot: &'a ContextSnapshot, rel_path: &str) -> &'a SnapshotFile {
+        snapshot
+            .entries()
+            .iter()
+            .find(|e| e.stat.path == rel_path)
+            .and_then(|e| e.file.as_ref())
+            .unwrap()
+    }
+
+    #[test]
+    fn capture_lists_entries_depth_first_and_applies_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("src/nested")).unwrap();
+        std::fs::write(dir.path().join("src/nested/b.rs"), "b").unwrap();
+        std::fs::write(dir.path().join("src/a.rs"), "a").unwrap();
+        std::fs::write(dir.path().join("README.md"), "readme").unwrap();
+        std::fs::write(dir.path().join("debug.log"), "log").unwrap();
+
+        let filter = ContextFilter::new().ignore_patterns(["*.log"]);
+        let snapshot = ContextSnapshot::capture(dir.path(), Some(&filter)).unwrap();
+
+        let paths: Vec<&str> = snapshot.paths().collect();
+        assert_eq!(
+            paths,
+            [
+                "README.md",
+                "src",
+                "src/a.rs",
+                "src/nested",
+                "src/nested/b.rs"
+            ]
+        );
+        assert_eq!(
+            snapshot.file_digest("src/a.rs").unwrap(),
+            format!("{:x}", Sha256::digest(b"a"))
+        );
+        assert_eq!(snapshot.file_digest("src"), None);
+    }
+
+    #[test]
+    fn every_filter_applies_to_a_capture() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("node_modules/dep")).unwrap();
+        std::fs::write(dir.path().join("node_modules/dep/index.js"), "dep").unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src/main.js"), "main").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+
+        let context = ContextFilter::new().include(["src", "node_modules"]);
+        let ignore = ContextFilter::new().dockerignore("node_modules\n");
+        let snapshot =
+            ContextSnapshot::capture_filtered(dir.path(), &[&context, &ignore], None).unwrap();
+
+        let paths: Vec<&str> = snapshot.paths().collect();
+        assert_eq!(paths, ["src", "src/main.js"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn replaced_and_deleted_files_are_served_as_captured() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("config.txt"), "original").unwrap();
+        std::fs::write(dir.path().join("gone.txt"), "still here").unwrap();
+        let snapshot = ContextSnapshot::capture(dir.path(), None).unwrap();
+
+        // Editors save by writing a new file and renaming it over the old one
+        std::fs::write(dir.path().join("config.txt.tmp"), "half-sav").unwrap();
+        std::fs::rename(
+            dir.path().join("config.txt.tmp"),
+            dir.path().join("config.txt"),
+        )
+        .unwrap();
+        std::fs::remove_file(dir.path().join("gone.txt")).unwrap();
+
+        assert_eq!(
+            read_all(file(&snapshot, "config.txt")).await.unwrap(),
+            b"original"
+        );
+        assert_eq!(
+            read_all(file(&snapshot, "gone.txt")).await.unwrap(),
+            b"still here"
+        );
+    }
+
+    #[tokio::test]
+    async fn in_place_modification_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.txt");
+        std::fs::write(&path, "original").unwrap();
+        let snapshot = ContextSnapshot::capture(dir.path(), None).unwrap();
+
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|mut f| std::io::Write::write_all(&mut f, b"ORIG"))
+            .unwrap();
+
+        let err = read_all(file(&snapshot, "data.txt")).await.unwrap_err();
+        assert!(matches!(err, Error::ContextChanged(p) if p == path));
+    }
+
+    #[tokio::test]
+    async fn files_reopened_by_path_are_verified() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.txt");
+        std::fs::write(&path, "original").unwrap();
+
+        let captured = SnapshotFile::capture(path.clone(), false).unwrap();
+        assert_eq!(read_all(&captured).await.unwrap(), b"original");
+
+        std::fs::write(&path, "changed and longer").unwrap();
+        assert!(matches!(
+            read_all(&captured).await,
+            Err(Error::ContextChanged(_))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            read_all(&captured).await,
+            Err(Error::ContextChanged(_))
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+//! Extended attributes of transferred files
+//!
+//! Attributes such as `security.capability` or `user.*` are carried in the
+//! `xattrs` of STAT packets. Symlinks are never followed, and filesystems
+//! without xattr support are treated as having none.
+
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Read the extended attributes of an entry
+pub(super) async fn read(path: PathBuf) -> Result<HashMap<String, Vec<u8>>> {
+    tokio::task::spawn_blocking(move || read_blocking(&path))
+        .await
+        .map_err(|e| Error::other(format!("xattr task failed: {}", e)))?
+}
+
+#[cfg(unix)]
+fn read_blocking(path: &Path) -> Result<HashMap<String, Vec<u8>>> {
+    let names = match xattr::list(path) {
+        Ok(names) => names,
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => return Ok(HashMap::new()),
+        Err(e) => {
+            return Err(Error::file_operation(
+                "list extended attributes of",
+                path,
+                e,
+            ))
+        }
+    };
+
+    let mut xattrs = HashMap::new();
+    for name in names {
+        let Some(key) = name.to_str() else {
+            tracing::warn!(
+                "Skipping extended attribute {:?} of {}: name is not UTF-8",
+                name,
+                path.display()
+            );
+            continue;
+        };
+        // An attribute removed since the listing is skipped
+        if let Some(value) = xattr::get(path, &name)
+            .map_err(|e| Error::file_operation("read extended attributes of", path, e))?
+        {
+            xattrs.insert(key.to_string(), value);
+        }
+    }
+    Ok(xattrs)
+}
+
+#[cfg(not(unix))]
+fn read_blocking(_path: &Path) -> Result<HashMap<String, Vec<u8>>> {
+    Ok(HashMap::new())
+}
+
+/// Set extended attributes on a received entry
+///
+/// Setting `security.*` or `trusted.*` attributes usually requires root.
+#[cfg(unix)]
+pub(super) fn write(path: &Path, xattrs: &HashMap<String, Vec<u8>>) -> Result<()> {
+    for (name, value) in xattrs {
+        match xattr::set(path, name, value) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                tracing::warn!(
+                    "Skipping extended attributes of {}: not supported by the filesystem",
+                    path.display()
+                );
+                return Ok(());
+            }
+            Err(e) => return Err(Error::file_operation("set extended attributes of", path, e)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(super) fn write(path: &Path, xattrs: &HashMap<String, Vec<u8>>) -> Result<()> {
+    if !xattrs.is_empty() {
+        tracing::warn!(
+            "Skipping extended attributes of {}: not supported",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Whether `user.*` attributes can be set on files in `dir`
+#[cfg(all(test, unix))]
+pub(super) fn supported_in(dir: &Path) -> bool {
+    let probe = dir.join(".xattr-probe");
+    std::fs::write(&probe, "").unwrap();
+    let supported = xattr::set(&probe, "user.probe", b"").is_ok();
+    std::fs::remove_file(&probe).unwrap();
+    supported
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn attributes_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        if !supported_in(dir.path()) {
+            return;
+        }
+        let path = dir.path().join("file");
+        std::fs::write(&path, "data").unwrap();
+
+        let xattrs = HashMap::from([("user.origin".to_string(), b"context".to_vec())]);
+        write(&path, &xattrs).unwrap();
+        assert_eq!(read(path).await.unwrap(), xattrs);
+    }
+}
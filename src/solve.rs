@@ -1,24 +1,205 @@
 //! BuildKit solve operation implementation
 
-use crate::builder::{BuildConfig, DockerfileSource};
-use crate::client::BuildKitClient;
+use crate::builder::{
+    BuildConfig, CacheImport, DockerfileSource, Export, NamedContext, Platform, TARBALL_AUTH_HEADER,
+};
+use crate::client::{BuildKitClient, ClientOptions};
 use crate::error::{Error, Result};
-use crate::progress::ProgressHandler;
+use crate::events::BuildEvents;
+use crate::metrics::ContextStats;
+use crate::progress::{AsyncProgressHandler, BuildLog, ProgressHandler, StatusTracker};
+use crate::proto::moby::buildkit::v1::sourcepolicy::Policy;
 use crate::proto::moby::buildkit::v1::{
-    CacheOptions, CacheOptionsEntry, Exporter, SolveRequest, StatusRequest,
+    control_client::ControlClient, CacheOptions, CacheOptionsEntry, Descriptor, Exporter,
+    InfoRequest, SolveRequest, StatusRequest, StatusResponse,
+};
+use crate::proto::pb::Definition;
+use crate::raw::with_session_metadata;
+use crate::redact::Scrubber;
+use crate::reference::Reference;
+use crate::registry::{self, ImageInspect, Manifest, RegistryClient};
+use crate::report::{BuildReport, BuildWarning, StepTiming};
+use crate::session::{
+    local_cache_digest, tag_local_cache, ChangeCache, ContentStoreServer, ContextFilter,
+    ContextSnapshot, FileSendServer, FileSync, FileSyncServer, Session,
 };
-use crate::session::{FileSync, Session};
-use std::collections::HashMap;
+use base64::Engine;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
 use tokio_stream::StreamExt;
+use tonic::transport::Channel;
 use uuid::Uuid;
 
+/// How long to wait for BuildKit to acknowledge a cancelled build
+const CANCEL_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
 /// Build result containing the image digest and metadata
 #[derive(Debug)]
 pub struct BuildResult {
+    /// Build reference the solve ran under
+    pub build_ref: String,
     /// Container image digest
     pub digest: Option<String>,
     /// Export metadata
     pub metadata: HashMap<String, String>,
+    /// Steps of the build with their timings
+    pub report: BuildReport,
+    /// Warnings BuildKit reported, e.g. Dockerfile lints
+    pub warnings: Vec<BuildWarning>,
+    /// Platforms in the image index of a multi-platform build; empty for
+    /// single-platform builds and builds exporting no image
+    pub platforms: Vec<Platform>,
+    /// What the session sent BuildKit from local directories; empty for
+    /// remote contexts
+    pub context_stats: ContextStats,
+}
+
+/// Output format for [`BuildResult::write_metadata`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetadataFormat {
+    /// The JSON `docker buildx build --metadata-file` writes
+    ///
+    /// Base64-encoded JSON values from the exporter response (such as
+    /// `containerimage.descriptor` and attestation references) are decoded
+    /// into objects, and the build reference is added as `buildx.build.ref`.
+    #[default]
+    Buildx,
+    /// The exporter response as returned by BuildKit
+    Raw,
+}
+
+impl BuildResult {
+    /// Result of a build from BuildKit's exporter response
+    pub(crate) fn from_exporter_response(
+        build_ref: String,
+        exporter_response: HashMap<String, String>,
+    ) -> Self {
+        let digest = exporter_response.get("containerimage.digest").cloned();
+        if let Some(ref d) = digest {
+            tracing::info!("Image digest: {}", d);
+        }
+        Self {
+            build_ref,
+            digest,
+            metadata: exporter_response,
+            report: BuildReport::default(),
+            warnings: Vec::new(),
+            platforms: Vec::new(),
+            context_stats: ContextStats::default(),
+        }
+    }
+
+    /// Build metadata as a JSON object in the given format
+    pub fn metadata_json(&self, format: MetadataFormat) -> serde_json::Value {
+        let mut out = serde_json::Map::new();
+        for (key, value) in &self.metadata {
+            let value = match format {
+                MetadataFormat::Buildx => decode_exporter_value(key, value),
+                MetadataFormat::Raw => serde_json::Value::String(value.clone()),
+            };
+            out.insert(key.clone(), value);
+        }
+        if format == MetadataFormat::Buildx {
+            out.insert(
+                "buildx.build.ref".to_string(),
+                serde_json::Value::String(self.build_ref.clone()),
+            );
+        }
+        serde_json::Value::Object(out)
+    }
+
+    /// How long each step took, slowest first
+    pub fn step_timings(&self) -> Vec<StepTiming> {
+        self.report.step_timings()
+    }
+
+    /// Read back the pushed image from its registry
+    ///
+    /// Inspects the first pushed tag, pinned to the build's digest, for the
+    /// layers, size and config (entrypoint, environment) of every platform.
+    /// [`RegistryClient::for_build`] gives a client with the build's
+    /// credentials.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use buildkit_client::registry::RegistryClient;
+    /// use buildkit_client::{BuildConfig, BuildKitClient};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     let config = BuildConfig::local("./app").tag("localhost:5000/app:latest");
+    ///     let registry = RegistryClient::for_build(&config);
+    ///     let result = client.build(config, None).await?;
+    ///
+    ///     for image in result.inspect(&registry).await?.images {
+    ///         println!("{}: {} bytes", image.platform, image.size());
+    ///         println!("entrypoint: {:?}", image.config.config.entrypoint);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn inspect(&self, registry: &RegistryClient) -> Result<ImageInspect> {
+        let reference = self
+            .pushed_reference()
+            .ok_or_else(|| Error::other("build did not export an image"))?;
+        registry.inspect(&reference).await
+    }
+
+    /// First image name of the export, pinned to the image digest
+    fn pushed_reference(&self) -> Option<String> {
+        let name = self.metadata.get("image.name")?.split(',').next()?.trim();
+        let reference = Reference::parse(name).ok()?;
+        match &self.digest {
+            Some(digest) => Some(format!("{}@{}", reference.name(), digest)),
+            None => Some(reference.to_string()),
+        }
+    }
+
+    /// Write build metadata to a file, e.g. for tools that consume buildx's
+    /// `--metadata-file` output
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use buildkit_client::{BuildConfig, BuildKitClient, MetadataFormat};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     let result = client.build(BuildConfig::local("./app"), None).await?;
+    ///     result.write_metadata("metadata.json", MetadataFormat::Buildx)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn write_metadata(&self, path: impl AsRef<Path>, format: MetadataFormat) -> Result<()> {
+        let path = path.as_ref();
+        let json = format!("{:#}", self.metadata_json(format));
+        std::fs::write(path, json).map_err(|e| Error::file_operation("write", path, e))
+    }
+}
+
+/// Decode an exporter response value the way buildx does
+///
+/// Values holding base64-encoded JSON objects are expanded (`result.json`
+/// is plain JSON); everything else is kept as a string.
+fn decode_exporter_value(key: &str, value: &str) -> serde_json::Value {
+    use base64::Engine;
+
+    let decoded = if key == "result.json" {
+        Some(value.as_bytes().to_vec())
+    } else {
+        base64::engine::general_purpose::STANDARD.decode(value).ok()
+    };
+
+    decoded
+        .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).ok())
+        .filter(|json| json.as_object().is_some_and(|o| !o.is_empty()))
+        .unwrap_or_else(|| serde_json::Value::String(value.to_string()))
 }
 
 impl BuildKitClient {
@@ -31,52 +212,528 @@ impl BuildKitClient {
     /// # Returns
     /// Build result containing digest and metadata
     pub async fn build(
-        &mut self,
+        &self,
         config: BuildConfig,
-        mut progress_handler: Option<Box<dyn ProgressHandler>>,
+        progress_handler: Option<Box<dyn ProgressHandler>>,
+    ) -> Result<BuildResult> {
+        self.build_with_async_progress(config, progress_handler.map(asynchronous))
+            .await
+    }
+
+    /// Execute a build, reporting its progress to a handler whose methods
+    /// may await
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::progress::AsyncProgressHandler;
+    /// use buildkit_client::{BuildConfig, BuildKitClient};
+    ///
+    /// async fn build(
+    ///     client: &BuildKitClient,
+    ///     progress: Box<dyn AsyncProgressHandler>,
+    /// ) -> anyhow::Result<()> {
+    ///     let config = BuildConfig::local("./my-app");
+    ///     client.build_with_async_progress(config, Some(progress)).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn build_with_async_progress(
+        &self,
+        config: BuildConfig,
+        progress_handler: Option<Box<dyn AsyncProgressHandler>>,
     ) -> Result<BuildResult> {
         // Generate unique build reference
         let build_ref = format!("build-{}", Uuid::new_v4());
         tracing::info!("Starting build with ref: {}", build_ref);
 
-        // Create and start session
+        let mut events = BuildEvents::new(self.event_sinks().to_vec(), &build_ref, &config);
+        events.queued();
+
+        // Each build runs on its own handle to the shared connection
+        let result = self
+            .clone()
+            .run_build(build_ref, config, None, progress_handler, &mut events)
+            .await;
+        match &result {
+            Ok(result) => events.completed(result.digest.clone()),
+            Err(e) => events.failed(e),
+        }
+        if let Some(metrics) = self.metrics() {
+            metrics.record_build(result.is_ok());
+        }
+        result
+    }
+
+    /// Build an LLB definition instead of a Dockerfile
+    ///
+    /// `config` provides the session, i.e. the local context (served as
+    /// `context`), credentials, secrets and SSH agents, and the tags, outputs
+    /// and cache options. Its Dockerfile settings are ignored. See the
+    /// [`llb`](crate::llb) module for building definitions.
+    pub async fn solve_llb(
+        &self,
+        config: BuildConfig,
+        definition: Definition,
+        progress_handler: Option<Box<dyn ProgressHandler>>,
+    ) -> Result<BuildResult> {
+        let build_ref = format!("build-{}", Uuid::new_v4());
+        tracing::info!("Starting LLB build with ref: {}", build_ref);
+
+        let mut events = BuildEvents::new(self.event_sinks().to_vec(), &build_ref, &config);
+        events.queued();
+
+        let result = self
+            .clone()
+            .run_build(
+                build_ref,
+                config,
+                Some(definition),
+                progress_handler.map(asynchronous),
+                &mut events,
+            )
+            .await;
+        match &result {
+            Ok(result) => events.completed(result.digest.clone()),
+            Err(e) => events.failed(e),
+        }
+        if let Some(metrics) = self.metrics() {
+            metrics.record_build(result.is_ok());
+        }
+        result
+    }
+
+    /// Run a build under the given reference, reporting lifecycle events
+    ///
+    /// Without a definition, the Dockerfile frontend builds the config's
+    /// Dockerfile.
+    async fn run_build(
+        &mut self,
+        build_ref: String,
+        config: BuildConfig,
+        definition: Option<Definition>,
+        mut progress_handler: Option<Box<dyn AsyncProgressHandler>>,
+        events: &mut BuildEvents,
+    ) -> Result<BuildResult> {
+        let _permit = self.build_permit().await?;
+        let session = self.start_session(&config).await?;
+
+        tracing::info!("Session started: {}", session.get_id());
+        events.started(&session.get_id());
+
+        let (frontend, frontend_attrs) = match definition {
+            Some(_) => (String::new(), HashMap::new()),
+            None => (
+                config.frontend.name().to_string(),
+                self.frontend_attrs(&config, &session).await?,
+            ),
+        };
+
+        // Prepare exports (client-side outputs and registry pushes)
+        let exports = exporters(&config)?;
+
+        // Debug: Log exporter configuration
+        tracing::debug!("Configured {} exporters", exports.len());
+        for (i, exporter) in exports.iter().enumerate() {
+            tracing::debug!(
+                "Exporter {}: type={}, attrs={:?}",
+                i,
+                exporter.r#type,
+                exporter.attrs
+            );
+        }
+
+        // Create solve request with session
+        let request = SolveRequest {
+            r#ref: build_ref.clone(),
+            definition,
+            exporter_deprecated: String::new(),
+            exporter_attrs_deprecated: HashMap::new(),
+            session: session.get_id(), // Use session ID
+            frontend,
+            frontend_attrs,
+            cache: Some(cache_options(&config)),
+            entitlements: entitlements(&config),
+            frontend_inputs: HashMap::new(),
+            internal: false,
+            source_policy: source_policy(&config)?,
+            exporters: exports,
+            enable_session_exporter: false,
+            // source_policy_session: String::new(),
+        };
+
+        // Start the build
+        tracing::info!("Sending solve request to buildkit");
+
+        // Create request with session metadata headers
+        let grpc_request = with_session_metadata(request, &session);
+
+        // Watch the status stream while the solve runs, so that progress is
+        // reported live and a failing step can be identified
+        let mut tracker = StatusTracker::new();
+        let scrubber = config.scrubber();
+        let mut log = build_log(&config).await?;
+        if let Some(ref mut handler) = progress_handler {
+            handler.on_start().await?;
+        }
+
+        let status_control = self.control().clone();
+        let outcome = self
+            .unless_cancelled(Self::within(config.timeout, async {
+                // The status call may reach BuildKit before or after the
+                // solve. Either way no update is missed: BuildKit holds the
+                // call until the build's job exists, then replays the
+                // updates made so far
+                tokio::join!(
+                    Self::monitor_progress(
+                        status_control,
+                        self.options(),
+                        &build_ref,
+                        ProgressContext {
+                            handler: progress_handler.as_deref_mut(),
+                            log: log.as_mut(),
+                            tracker: &mut tracker,
+                            events,
+                            scrubber: &scrubber,
+                        },
+                    ),
+                    self.solve(grpc_request),
+                )
+            }))
+            .await;
+        let context_stats = session.context_stats().await;
+        if let Some(metrics) = self.metrics() {
+            metrics.record_context(&context_stats);
+        }
+        let (monitor_result, solve_result) = match outcome {
+            Some(Some(results)) => results,
+            stopped => {
+                session.close();
+                let error = match stopped {
+                    None => self.build_cancelled(&tracker).await,
+                    Some(_) => self.build_timed_out(config.timeout).await,
+                };
+                if let Some(ref mut handler) = progress_handler {
+                    handler.on_error(&error.to_string()).await?;
+                }
+                return Err(error);
+            }
+        };
+
+        // A step that failed on the status stream fails the build even when
+        // the solve itself returned successfully
+        let solve_result = match tracker.failure(&session.get_id(), solve_result.as_ref().err()) {
+            Some(failure) => Err(failure),
+            None => solve_result.map_err(Error::from),
+        };
+        let solve_response = match solve_result {
+            Ok(response) => response.into_inner(),
+            Err(error) => {
+                let error = error.scrub(&scrubber);
+                if let Some(ref mut handler) = progress_handler {
+                    handler.on_error(&error.to_string()).await?;
+                }
+                return Err(error);
+            }
+        };
+        monitor_result.map_err(|e| e.scrub(&scrubber))?;
+        tag_local_cache_exports(&config, &solve_response.exporter_response)?;
+        let platforms = match self
+            .image_platforms(&config, &solve_response.exporter_response)
+            .await
+        {
+            Ok(platforms) => platforms,
+            Err(error) => {
+                if let Some(ref mut handler) = progress_handler {
+                    handler.on_error(&error.to_string()).await?;
+                }
+                return Err(error);
+            }
+        };
+
+        if let Some(ref mut handler) = progress_handler {
+            handler.on_complete().await?;
+        }
+
+        tracing::info!("Build completed successfully");
+        let mut result =
+            BuildResult::from_exporter_response(build_ref, solve_response.exporter_response);
+        result.report = tracker.report();
+        result.warnings = tracker.warnings();
+        result.platforms = platforms;
+        result.context_stats = context_stats;
+        Ok(result)
+    }
+
+    /// Platforms in the image index a multi-platform build exported,
+    /// failing the build if a requested platform is missing
+    ///
+    /// The index is read from BuildKit's content store; if it cannot be
+    /// read, the platforms are not checked.
+    async fn image_platforms(
+        &mut self,
+        config: &BuildConfig,
+        exporter_response: &HashMap<String, String>,
+    ) -> Result<Vec<Platform>> {
+        const DESCRIPTOR_KEY: &str = "containerimage.descriptor";
+        if config.platforms.len() < 2 {
+            return Ok(Vec::new());
+        }
+        let Some(value) = exporter_response.get(DESCRIPTOR_KEY) else {
+            return Ok(Vec::new());
+        };
+        let descriptor: registry::Descriptor =
+            match serde_json::from_value(decode_exporter_value(DESCRIPTOR_KEY, value)) {
+                Ok(descriptor) => descriptor,
+                Err(e) => {
+                    tracing::warn!("Invalid image descriptor, not checking platforms: {}", e);
+                    return Ok(Vec::new());
+                }
+            };
+
+        let blob = match self
+            .read_content(&Descriptor {
+                media_type: descriptor.media_type.clone(),
+                digest: descriptor.digest.clone(),
+                size: descriptor.size,
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(blob) => blob,
+            Err(e) => {
+                tracing::warn!(
+                    "Could not read image index {}, not checking platforms: {}",
+                    descriptor.digest,
+                    e
+                );
+                return Ok(Vec::new());
+            }
+        };
+        let Manifest::Index(index) = Manifest::parse(&blob, Some(&descriptor.media_type))? else {
+            return Err(Error::Build(format!(
+                "multi-platform build exported a single-platform image {}",
+                descriptor.digest
+            )));
+        };
+
+        let platforms: Vec<Platform> = index
+            .manifests
+            .iter()
+            .filter(|d| !d.is_attestation())
+            .filter_map(|d| d.platform.as_ref().map(Platform::from))
+            .collect();
+        let missing = missing_platforms(&config.platforms, &platforms);
+        if !missing.is_empty() {
+            return Err(Error::Build(format!(
+                "image index {} has no manifest for {}",
+                descriptor.digest,
+                missing.join(", ")
+            )));
+        }
+        Ok(platforms)
+    }
+
+    /// Error for a build abandoned through the cancellation token
+    ///
+    /// The solve call has been dropped, which resets its stream and makes
+    /// BuildKit cancel the build. A round trip on the same connection
+    /// ensures the reset has been sent before the caller goes on, e.g. to
+    /// exit the process.
+    pub(crate) async fn build_cancelled(&mut self, tracker: &StatusTracker) -> Error {
+        tracing::info!("Build cancelled");
+        self.flush_cancel().await;
+        Error::BuildCancelled(Box::new(tracker.report()))
+    }
+
+    /// Error for a build abandoned when its timeout expired
+    ///
+    /// BuildKit cancels the build as for [`Self::build_cancelled`].
+    pub(crate) async fn build_timed_out(&mut self, timeout: Option<Duration>) -> Error {
+        let timeout = timeout.unwrap_or_default();
+        tracing::warn!("Build timed out after {:?}", timeout);
+        self.flush_cancel().await;
+        Error::Build(format!("timed out after {:?}", timeout))
+    }
+
+    /// Wait for the reset of an abandoned solve call to reach BuildKit
+    async fn flush_cancel(&mut self) {
+        let flushed =
+            tokio::time::timeout(CANCEL_FLUSH_TIMEOUT, self.control().info(InfoRequest {})).await;
+        if !matches!(flushed, Ok(Ok(_))) {
+            tracing::debug!("BuildKit did not answer after cancelling the build");
+        }
+    }
+
+    /// Create a session serving the build's context, credentials and
+    /// secrets, and start it
+    pub(crate) async fn start_session(&mut self, config: &BuildConfig) -> Result<Session> {
+        config.validate()?;
+        self.check_worker_constraints(&config.worker_constraints)
+            .await?;
         let mut session = Session::new();
 
         // Add file sync for local builds
-        if let DockerfileSource::Local { context_path, .. } = &config.source {
-            let abs_path =
-                std::fs::canonicalize(context_path).map_err(|e| Error::PathResolution {
-                    path: context_path.clone(),
+        match &config.source {
+            DockerfileSource::Local {
+                context_path,
+                dockerfile_path,
+            } => {
+                let abs_path =
+                    std::fs::canonicalize(context_path).map_err(|e| Error::PathResolution {
+                        path: context_path.clone(),
+                        source: e,
+                    })?;
+                let dockerfile = abs_path.join(
+                    dockerfile_path
+                        .as_deref()
+                        .unwrap_or(Path::new("Dockerfile")),
+                );
+                let mut file_sync = FileSyncServer::new(&abs_path)
+                    .with_dockerfile_dir(dockerfile_dir(&dockerfile)?);
+                if config.prune_context {
+                    match std::fs::read_to_string(&dockerfile) {
+                        Ok(content) => {
+                            file_sync = file_sync.with_context_filter(
+                                ContextFilter::for_dockerfile(&abs_path, &content)?,
+                            );
+                        }
+                        Err(e) => tracing::warn!(
+                            "Not pruning context, cannot read {}: {}",
+                            dockerfile.display(),
+                            e
+                        ),
+                    }
+                }
+                if let Some(dir) = &config.change_cache {
+                    file_sync = file_sync.with_change_cache(dir.clone());
+                }
+                if config.snapshot_context {
+                    file_sync = snapshot_context(file_sync).await?;
+                }
+                session.add_file_sync_server(file_sync).await;
+            }
+            DockerfileSource::Inline {
+                content,
+                context_path,
+            } => {
+                let abs_path =
+                    std::fs::canonicalize(context_path).map_err(|e| Error::PathResolution {
+                        path: context_path.clone(),
+                        source: e,
+                    })?;
+                let mut file_sync =
+                    FileSyncServer::new(&abs_path).with_dockerfile_content(content.clone());
+                if config.prune_context {
+                    file_sync = file_sync.with_context_filter(ContextFilter::for_dockerfile(
+                        &abs_path,
+                        &String::from_utf8_lossy(content),
+                    )?);
+                }
+                if let Some(dir) = &config.change_cache {
+                    file_sync = file_sync.with_change_cache(dir.clone());
+                }
+                if config.snapshot_context {
+                    file_sync = snapshot_context(file_sync).await?;
+                }
+                session.add_file_sync_server(file_sync).await;
+            }
+            // BuildKit fetches remote contexts itself
+            DockerfileSource::GitHub { .. }
+            | DockerfileSource::Git { .. }
+            | DockerfileSource::TarballUrl { .. } => {}
+        }
+
+        // Serve local named contexts under their names
+        for (name, source) in &config.named_contexts {
+            if let NamedContext::Local(path) = source {
+                if matches!(name.as_str(), "context" | "dockerfile") {
+                    return Err(Error::InvalidConfig(format!(
+                        "local build context cannot be named {}",
+                        name
+                    )));
+                }
+                let abs_path = std::fs::canonicalize(path).map_err(|e| Error::PathResolution {
+                    path: path.clone(),
                     source: e,
                 })?;
-            session.add_file_sync(abs_path).await;
+                session
+                    .add_file_sync_dir(name.clone(), FileSyncServer::new(abs_path))
+                    .await;
+            }
         }
 
         // Add auth for registry authentication
-        if let Some(ref registry_auth) = config.registry_auth {
+        if config.all_registry_auths().next().is_some() || config.docker_credentials {
             let mut auth = crate::session::AuthServer::new();
-            auth.add_registry(crate::session::RegistryAuthConfig {
-                host: registry_auth.host.clone(),
-                username: registry_auth.username.clone(),
-                password: registry_auth.password.clone(),
-            });
+            if config.docker_credentials {
+                auth.add_docker_config(crate::session::DockerConfig::load()?);
+            }
+            for registry_auth in config.all_registry_auths() {
+                auth.add_registry(crate::session::RegistryAuthConfig {
+                    host: registry_auth.host.clone(),
+                    username: registry_auth.username.clone(),
+                    password: registry_auth.password.clone(),
+                    identity_token: registry_auth.identity_token.clone(),
+                    registry_token: registry_auth.registry_token.clone(),
+                    scope: registry_auth.scope,
+                });
+            }
             session.add_auth(auth).await;
         }
 
         // Add secrets if provided
-        if !config.secrets.is_empty() {
-            let secrets = crate::session::SecretsServer::from_map(config.secrets.clone())
-                .map_err(|e| Error::secrets(format!("Failed to create secrets server: {}", e)))?;
+        let secret_values = config.resolve_secrets()?;
+        if !secret_values.is_empty() {
+            let count = secret_values.len();
+            let mut secrets = crate::session::SecretsServer::new();
+            for (id, value) in secret_values {
+                secrets.add_secret(id, value)?;
+            }
             session.add_secrets(secrets).await;
-            tracing::debug!("Added {} secrets to session", config.secrets.len());
+            tracing::debug!("Added {} secrets to session", count);
+        }
+
+        // Forward SSH agents
+        let ssh_agents = config.resolve_ssh_agents();
+        if !ssh_agents.is_empty() {
+            let count = ssh_agents.len();
+            let ssh = ssh_agents.into_iter().fold(
+                crate::session::SshForwardServer::new(),
+                |ssh, (id, agent)| ssh.with_agent(id, agent),
+            );
+            session.add_ssh_forward(ssh).await;
+            tracing::debug!("Added {} SSH agents to session", count);
+        }
+
+        // Serve the directories of local caches
+        let content = local_cache_dirs(config)
+            .into_iter()
+            .fold(ContentStoreServer::new(), |content, dir| {
+                content.with_local_store(dir)
+            });
+        if !content.is_empty() {
+            session.add_content_store(content).await;
+        }
+
+        // Receive client-side exports
+        if !config.exports.is_empty() {
+            session
+                .add_file_send(file_send_server(&config.exports))
+                .await;
         }
 
         // Start the session by connecting to BuildKit
         session.start(self.control().clone()).await?;
 
-        tracing::info!("Session started: {}", session.get_id());
+        Ok(session)
+    }
 
-        // Prepare frontend attributes
+    /// Dockerfile frontend options for a build
+    pub(crate) async fn frontend_attrs(
+        &self,
+        config: &BuildConfig,
+        session: &Session,
+    ) -> Result<HashMap<String, String>> {
         let mut frontend_attrs = HashMap::new();
 
         // Set dockerfile filename
@@ -84,18 +741,27 @@ impl BuildKitClient {
             DockerfileSource::Local {
                 dockerfile_path, ..
             } => {
-                if let Some(path) = dockerfile_path {
+                // Served from its own directory, see `dockerfile_dir`
+                if let Some(name) = dockerfile_path.as_deref().and_then(Path::file_name) {
                     frontend_attrs
-                        .insert("filename".to_string(), path.to_string_lossy().to_string());
+                        .insert("filename".to_string(), name.to_string_lossy().to_string());
                 }
             }
             DockerfileSource::GitHub {
                 dockerfile_path, ..
+            }
+            | DockerfileSource::Git {
+                dockerfile_path, ..
+            }
+            | DockerfileSource::TarballUrl {
+                dockerfile_path, ..
             } => {
                 if let Some(path) = dockerfile_path {
                     frontend_attrs.insert("filename".to_string(), path.clone());
                 }
             }
+            // Inline content is always served as the default "Dockerfile"
+            DockerfileSource::Inline { .. } => {}
         }
 
         // Add build args
@@ -119,6 +785,10 @@ impl BuildKitClient {
             frontend_attrs.insert("platform".to_string(), platforms_str);
         }
 
+        for (kind, params) in &config.attestations {
+            frontend_attrs.insert(format!("attest:{}", kind), params.clone());
+        }
+
         // Set no-cache
         if config.no_cache {
             frontend_attrs.insert("no-cache".to_string(), "true".to_string());
@@ -130,234 +800,1051 @@ impl BuildKitClient {
         }
 
         // Prepare context source
-        let context = self.prepare_context(&config, &session).await?;
+        let context = self.prepare_context(config, session).await?;
         frontend_attrs.insert("context".to_string(), context);
 
-        // Prepare exports (push to registry)
-        let mut exports = Vec::new();
-        if !config.tags.is_empty() {
-            let mut export_attrs = HashMap::new();
-            export_attrs.insert("name".to_string(), config.tags.join(","));
-            export_attrs.insert("push".to_string(), "true".to_string());
-
-            // Check if registry needs insecure flag based on tag or registry_auth
-            let registry_host = if let Some(auth) = &config.registry_auth {
-                Some(auth.host.as_str())
-            } else {
-                // Extract registry host from the first tag (format: host/image:tag or image:tag)
-                config.tags.first().and_then(|tag| {
-                    let parts: Vec<&str> = tag.split('/').collect();
-                    if parts.len() > 1
-                        && (parts[0].contains(':')
-                            || parts[0].contains('.')
-                            || parts[0] == "localhost")
-                    {
-                        Some(parts[0])
-                    } else {
-                        None
-                    }
-                })
-            };
+        for (name, source) in &config.named_contexts {
+            frontend_attrs.insert(format!("context:{}", name), source.frontend_value(name));
+        }
 
-            // Determine if registry is insecure (HTTP instead of HTTPS)
-            if let Some(host) = registry_host {
-                let is_insecure = host.starts_with("localhost")
-                    || host.starts_with("127.0.0.1")
-                    || host.starts_with("registry:") // Docker Compose service name
-                    || (!host.contains('.') && !host.starts_with("docker.io")); // Simple heuristic for local names
+        config.frontend.apply_attrs(&mut frontend_attrs)?;
 
-                if is_insecure {
-                    export_attrs.insert("registry.insecure".to_string(), "true".to_string());
+        Ok(frontend_attrs)
+    }
+
+    /// Prepare build context based on source type
+    async fn prepare_context(&self, config: &BuildConfig, session: &Session) -> Result<String> {
+        match &config.source {
+            DockerfileSource::Local { context_path, .. }
+            | DockerfileSource::Inline { context_path, .. } => {
+                // Validate the context path
+                let file_sync = FileSync::new(context_path);
+                file_sync.validate()?;
+
+                // Use session-based input
+                // The format is: input:<name> where name references the session
+                Ok(format!("input:{}:context", session.shared_key))
+            }
+            // Tokens reach BuildKit as session secrets, never in the URL
+            DockerfileSource::GitHub {
+                repo_url: url,
+                git_ref,
+                subdir,
+                ..
+            }
+            | DockerfileSource::Git {
+                url,
+                git_ref,
+                subdir,
+                ..
+            } => git_context(url, git_ref.as_deref(), subdir.as_deref()),
+            // The Dockerfile frontend downloads URL contexts and extracts
+            // tarballs
+            DockerfileSource::TarballUrl { url, .. } => {
+                if !url.starts_with("https://") && !url.starts_with("http://") {
+                    return Err(Error::InvalidConfig(format!(
+                        "tarball context {} is not an HTTP or HTTPS URL",
+                        url
+                    )));
                 }
+                Ok(url.clone())
             }
-
-            exports.push(Exporter {
-                r#type: "image".to_string(),
-                attrs: export_attrs,
-            });
         }
+    }
 
-        // Prepare cache imports
-        let cache_imports = config
-            .cache_from
-            .iter()
-            .map(|source| {
-                let mut attrs = HashMap::new();
-                attrs.insert("ref".to_string(), source.clone());
-                CacheOptionsEntry {
-                    r#type: "registry".to_string(),
-                    attrs,
+    /// Monitor build progress, recording vertex state and forwarding updates to the handler
+    ///
+    /// Credentials and secret values are scrubbed from each update before it
+    /// is recorded or forwarded. A stream broken by a failure the options
+    /// retry is reopened for the same build ref, up to `max_retries` times
+    /// per build however many updates arrived in between; BuildKit replays
+    /// the build's progress so far on the new stream.
+    ///
+    /// Updates are queued for the handler and the build log, so the stream
+    /// keeps being read while an asynchronous handler awaits.
+    pub(crate) async fn monitor_progress<H: AsyncProgressHandler + ?Sized>(
+        control: ControlClient<Channel>,
+        options: &ClientOptions,
+        build_ref: &str,
+        progress: ProgressContext<'_, H>,
+    ) -> Result<()> {
+        let ProgressContext {
+            handler,
+            log,
+            tracker,
+            events,
+            scrubber,
+        } = progress;
+        let status_request = StatusRequest {
+            r#ref: build_ref.to_string(),
+        };
+        let (update_tx, mut update_rx) = mpsc::unbounded_channel();
+
+        let read = async {
+            // Delivery ends once the stream is done and the sender dropped
+            let update_tx = update_tx;
+            let mut retries = 0;
+            'resume: loop {
+                let mut stream = options
+                    .retry("Status", || {
+                        let mut control = control.clone();
+                        let request = status_request.clone();
+                        async move { control.status(request).await }
+                    })
+                    .await?
+                    .into_inner();
+
+                while let Some(response) = stream.next().await {
+                    match response {
+                        Ok(mut status) => {
+                            scrub_status(&mut status, scrubber);
+                            let warnings = tracker.observe(&status);
+                            events.observe(&status);
+                            let _ = update_tx.send(Ok((status, warnings)));
+                        }
+                        Err(e) if options.should_retry(&e, retries) => {
+                            let delay = options.delay(retries);
+                            tracing::warn!(
+                                "Status stream broke ({}), resuming in {:?}",
+                                scrubber.scrub(e.message()),
+                                delay
+                            );
+                            tokio::time::sleep(delay).await;
+                            retries += 1;
+                            continue 'resume;
+                        }
+                        Err(e) => {
+                            let message = scrubber.scrub(&e.to_string());
+                            tracing::error!("Status stream error: {}", message);
+                            let _ = update_tx.send(Err(message));
+                            break;
+                        }
+                    }
                 }
-            })
-            .collect();
+                break;
+            }
+            Ok::<(), Error>(())
+        };
 
-        // Prepare cache exports
-        let cache_exports = config
-            .cache_to
-            .iter()
-            .map(|dest| {
-                let mut attrs = HashMap::new();
-                attrs.insert("ref".to_string(), dest.clone());
-                attrs.insert("mode".to_string(), "max".to_string());
-                CacheOptionsEntry {
-                    r#type: "registry".to_string(),
-                    attrs,
+        let deliver = async {
+            let mut handler = handler;
+            let mut log = log;
+            while let Some(update) = update_rx.recv().await {
+                if let (Some(log), Ok((status, _))) = (log.as_deref_mut(), &update) {
+                    log.record(status).await;
                 }
-            })
-            .collect();
+                let Some(handler) = handler.as_deref_mut() else {
+                    continue;
+                };
+                match update {
+                    Ok((status, warnings)) => {
+                        handler.on_status(status).await?;
+                        for warning in &warnings {
+                            handler.on_warning(warning).await?;
+                        }
+                    }
+                    Err(message) => handler.on_error(&message).await?,
+                }
+            }
+            Ok(())
+        };
 
-        // Debug: Log exporter configuration
-        tracing::debug!("Configured {} exporters", exports.len());
-        for (i, exporter) in exports.iter().enumerate() {
-            tracing::debug!(
-                "Exporter {}: type={}, attrs={:?}",
-                i,
-                exporter.r#type,
-                exporter.attrs
-            );
+        tokio::try_join!(read, deliver)?;
+        Ok(())
+    }
+}
+
+/// Where the updates of a build's status stream go
+pub(crate) struct ProgressContext<'a, H: ?Sized> {
+    /// Handler the updates are forwarded to
+    pub(crate) handler: Option<&'a mut H>,
+    /// Log step output is written to
+    pub(crate) log: Option<&'a mut BuildLog>,
+    /// Vertex state of the build
+    pub(crate) tracker: &'a mut StatusTracker,
+    /// Lifecycle events of the build
+    pub(crate) events: &'a mut BuildEvents,
+    /// Scrubber applied to each update first
+    pub(crate) scrubber: &'a Scrubber,
+}
+
+/// A progress handler as an asynchronous one
+fn asynchronous(handler: Box<dyn ProgressHandler>) -> Box<dyn AsyncProgressHandler> {
+    Box::new(handler)
+}
+
+/// Serve a file sync server's context from a snapshot taken now
+///
+/// Paths left out by the context filter or `.dockerignore` are not read.
+/// Files unchanged since the server's change cache recorded them are not
+/// hashed again.
+async fn snapshot_context(file_sync: FileSyncServer) -> Result<FileSyncServer> {
+    let root = file_sync.get_root_path();
+    let filter = file_sync.context_filter().cloned();
+    let ignore = file_sync.ignore_filter(&[])?;
+    let cache_dir = file_sync.change_cache_dir().map(Path::to_path_buf);
+    let snapshot = tokio::task::spawn_blocking(move || {
+        let filters: Vec<&ContextFilter> = filter.iter().chain([&ignore]).collect();
+        match cache_dir {
+            Some(dir) => {
+                let mut cache = ChangeCache::open(dir, &root)?;
+                let snapshot =
+                    ContextSnapshot::capture_filtered(&root, &filters, Some(&mut cache))?;
+                if let Err(e) = cache.save(true) {
+                    tracing::warn!("Failed to save change cache: {}", e);
+                }
+                Ok(snapshot)
+            }
+            None => ContextSnapshot::capture_filtered(&root, &filters, None),
         }
+    })
+    .await
+    .map_err(|e| Error::other(format!("context snapshot task failed: {}", e)))??;
+    tracing::info!("Snapshotted {} context entries", snapshot.len());
+    Ok(file_sync.with_snapshot(Arc::new(snapshot)))
+}
 
-        // Create solve request with session
-        let request = SolveRequest {
-            r#ref: build_ref.clone(),
-            definition: None,
-            exporter_deprecated: String::new(),
-            exporter_attrs_deprecated: HashMap::new(),
-            session: session.get_id(), // Use session ID
-            frontend: "dockerfile.v0".to_string(),
-            frontend_attrs,
-            cache: Some(CacheOptions {
-                export_ref_deprecated: String::new(),
-                import_refs_deprecated: vec![],
-                export_attrs_deprecated: HashMap::new(),
-                exports: cache_exports,
-                imports: cache_imports,
-            }),
-            entitlements: vec![],
-            frontend_inputs: HashMap::new(),
-            internal: false,
-            source_policy: None,
-            exporters: exports,
-            enable_session_exporter: false,
-            // source_policy_session: String::new(),
+/// Remove credentials and secret values from a status update
+///
+/// Vertex names include the build context URL and RUN commands, and logs
+/// include command output, any of which may echo a secret.
+fn scrub_status(status: &mut StatusResponse, scrubber: &Scrubber) {
+    for vertex in &mut status.vertexes {
+        vertex.name = scrubber.scrub(&vertex.name);
+        vertex.error = scrubber.scrub(&vertex.error);
+    }
+    for log in &mut status.logs {
+        scrubber.scrub_bytes(&mut log.msg);
+    }
+    for warning in &mut status.warnings {
+        scrubber.scrub_bytes(&mut warning.short);
+        for detail in &mut warning.detail {
+            scrubber.scrub_bytes(detail);
+        }
+    }
+}
+
+/// Requested platforms without an image among the built ones
+///
+/// `linux/arm64` and `linux/arm64/v8` are the same platform.
+fn missing_platforms(requested: &[Platform], built: &[Platform]) -> Vec<String> {
+    let normalize = |p: &Platform| {
+        let variant = match (p.arch.as_str(), p.variant.as_deref()) {
+            ("arm64", Some("v8")) => None,
+            (_, variant) => variant,
         };
+        (p.os.clone(), p.arch.clone(), variant.map(str::to_string))
+    };
+    let built: Vec<_> = built.iter().map(normalize).collect();
+    requested
+        .iter()
+        .filter(|p| !built.contains(&normalize(p)))
+        .map(|p| p.to_string())
+        .collect()
+}
 
-        // Start the build
-        tracing::info!("Sending solve request to buildkit");
+/// Directory BuildKit's `dockerfile` mount is served from: the one holding
+/// the Dockerfile, which may be outside the context
+fn dockerfile_dir(dockerfile: &Path) -> Result<PathBuf> {
+    let dir = dockerfile.parent().unwrap_or(Path::new("/"));
+    std::fs::canonicalize(dir).map_err(|e| Error::PathResolution {
+        path: dir.to_path_buf(),
+        source: e,
+    })
+}
 
-        // Create request with session metadata headers
-        let mut grpc_request = tonic::Request::new(request);
-        let metadata = grpc_request.metadata_mut();
-
-        // Add session metadata headers
-        for (key, values) in session.metadata() {
-            if let Ok(k) = key.parse::<tonic::metadata::MetadataKey<tonic::metadata::Ascii>>() {
-                // Add each value for the key (supports multi-value headers)
-                for value in values {
-                    if let Ok(v) =
-                        value.parse::<tonic::metadata::MetadataValue<tonic::metadata::Ascii>>()
-                    {
-                        metadata.append(k.clone(), v);
-                    }
-                }
+/// Context of a Git repository, `<url>#<ref>:<subdir>` in BuildKit's syntax
+///
+/// HTTP URLs get a `.git` suffix, so that BuildKit fetches them as
+/// repositories instead of downloading them. References and subdirectories
+/// that would not survive the encoding are rejected.
+fn git_context(url: &str, git_ref: Option<&str>, subdir: Option<&str>) -> Result<String> {
+    let invalid = |what: &str, value: &str, reason: &str| {
+        Error::InvalidConfig(format!("invalid git {} {}: {}", what, value, reason))
+    };
+    if url.contains('#') {
+        return Err(invalid(
+            "URL",
+            url,
+            "set the reference and subdirectory separately",
+        ));
+    }
+    if let Some(git_ref) = git_ref {
+        if git_ref.contains([':', '#']) || git_ref.chars().any(char::is_whitespace) {
+            return Err(invalid(
+                "reference",
+                git_ref,
+                "contains ':', '#' or whitespace",
+            ));
+        }
+    }
+    let subdir = match subdir {
+        Some(subdir) => {
+            if subdir.contains('#') {
+                return Err(invalid("subdirectory", subdir, "contains '#'"));
+            }
+            let path = Path::new(subdir);
+            if path.is_absolute()
+                || path
+                    .components()
+                    .any(|c| matches!(c, std::path::Component::ParentDir))
+            {
+                return Err(invalid(
+                    "subdirectory",
+                    subdir,
+                    "must stay within the repository",
+                ));
             }
+            Some(subdir.trim_start_matches("./").trim_end_matches('/'))
+                .filter(|subdir| !subdir.is_empty() && *subdir != ".")
         }
+        None => None,
+    };
 
-        let response = self.control().solve(grpc_request).await?;
+    let mut context = url.to_string();
+    let http = url.starts_with("https://") || url.starts_with("http://");
+    if http && !context.ends_with(".git") {
+        context.push_str(".git");
+    }
+    if git_ref.is_some() || subdir.is_some() {
+        context.push('#');
+        context.push_str(git_ref.unwrap_or_default());
+        if let Some(subdir) = subdir {
+            context.push(':');
+            context.push_str(subdir);
+        }
+    }
+    Ok(context)
+}
 
-        let solve_response = response.into_inner();
+/// Log of a build, if its config asks for one
+pub(crate) async fn build_log(config: &BuildConfig) -> Result<Option<BuildLog>> {
+    match &config.log_output {
+        Some(sink) => Ok(Some(BuildLog::open(sink).await?)),
+        None => Ok(None),
+    }
+}
 
-        // Monitor build progress if handler is provided
-        if let Some(ref mut handler) = progress_handler {
-            self.monitor_progress(&build_ref, handler).await?;
+/// Source policy of a build, read from its file
+///
+/// The checksum and headers of a tarball context are added as a rule
+/// converting its download. Its `Authorization` header is read by BuildKit
+/// from a session secret rather than sent in the rule.
+pub(crate) fn source_policy(config: &BuildConfig) -> Result<Option<Policy>> {
+    let mut policy = config
+        .source_policy
+        .as_ref()
+        .map(crate::source_policy::load)
+        .transpose()?;
+    if let DockerfileSource::TarballUrl {
+        url,
+        checksum,
+        headers,
+        ..
+    } = &config.source
+    {
+        let attrs: HashMap<String, String> = checksum
+            .iter()
+            .map(|checksum| ("http.checksum".to_string(), checksum.clone()))
+            .chain(
+                headers
+                    .iter()
+                    .filter(|(name, _)| !name.eq_ignore_ascii_case("authorization"))
+                    .map(|(name, value)| (format!("http.header.{}", name), value.clone())),
+            )
+            .chain(config.tarball_authorization().map(|_| {
+                (
+                    "http.authheadersecret".to_string(),
+                    TARBALL_AUTH_HEADER.to_string(),
+                )
+            }))
+            .collect();
+        if !attrs.is_empty() {
+            policy
+                .get_or_insert_with(Policy::default)
+                .rules
+                .push(crate::source_policy::http_rule(url, attrs));
         }
+    }
+    Ok(policy)
+}
 
-        // Extract digest and metadata
-        let digest = solve_response
-            .exporter_response
-            .get("containerimage.digest")
-            .cloned();
+/// Entitlement names of a build
+pub(crate) fn entitlements(config: &BuildConfig) -> Vec<String> {
+    config
+        .entitlements
+        .iter()
+        .map(|entitlement| entitlement.as_str().to_string())
+        .collect()
+}
 
-        tracing::info!("Build completed successfully");
-        if let Some(ref d) = digest {
-            tracing::info!("Image digest: {}", d);
+/// Cache imports and exports of a build
+pub(crate) fn cache_options(config: &BuildConfig) -> CacheOptions {
+    let imports = config
+        .cache_from
+        .iter()
+        .filter_map(|source| {
+            Some(cache_entry(
+                source.cache_type(),
+                cache_import_attrs(source)?,
+            ))
+        })
+        .collect();
+    let exports = config
+        .cache_to
+        .iter()
+        .map(|dest| cache_entry(dest.cache_type(), dest.attrs()))
+        .collect();
+
+    CacheOptions {
+        export_ref_deprecated: String::new(),
+        import_refs_deprecated: vec![],
+        export_attrs_deprecated: HashMap::new(),
+        exports,
+        imports,
+    }
+}
+
+/// Attributes of a cache import, or `None` for a local cache that has
+/// not been exported to yet
+///
+/// A local cache is imported from the manifest tagged in its `index.json`,
+/// unless a `digest` is given.
+pub(crate) fn cache_import_attrs(source: &CacheImport) -> Option<BTreeMap<String, String>> {
+    let mut attrs = source.attrs();
+    if source.cache_type() != "local" || attrs.contains_key("digest") {
+        return Some(attrs);
+    }
+    let Some(src) = attrs.get("src") else {
+        return Some(attrs);
+    };
+    match local_cache_digest(Path::new(src)) {
+        Some(digest) => {
+            attrs.insert("digest".to_string(), digest);
+            Some(attrs)
+        }
+        None => {
+            tracing::debug!("Skipping local cache {} without a cache manifest", src);
+            None
         }
+    }
+}
 
-        Ok(BuildResult {
-            digest,
-            metadata: solve_response.exporter_response,
+/// Directories of a build's local cache imports and exports
+fn local_cache_dirs(config: &BuildConfig) -> Vec<String> {
+    let imports = config
+        .cache_from
+        .iter()
+        .filter(|source| source.cache_type() == "local")
+        .filter_map(|source| source.attrs().remove("src"));
+    let exports = config
+        .cache_to
+        .iter()
+        .filter(|dest| dest.cache_type() == "local")
+        .filter_map(|dest| dest.attrs().remove("dest"));
+    imports.chain(exports).collect()
+}
+
+/// Tag the cache manifest a build exported in its local caches, so later
+/// builds import it
+pub(crate) fn tag_local_cache_exports(
+    config: &BuildConfig,
+    exporter_response: &HashMap<String, String>,
+) -> Result<()> {
+    let dests: Vec<String> = config
+        .cache_to
+        .iter()
+        .filter(|dest| dest.cache_type() == "local")
+        .filter_map(|dest| dest.attrs().remove("dest"))
+        .collect();
+    if dests.is_empty() {
+        return Ok(());
+    }
+    let Some(manifest) = exporter_response.get("cache.manifest") else {
+        tracing::warn!("BuildKit did not report the exported cache manifest");
+        return Ok(());
+    };
+
+    // Older BuildKit sends the descriptor as JSON, newer as base64 of it
+    let descriptor = match serde_json::from_str::<serde_json::Value>(manifest) {
+        Ok(descriptor) => descriptor,
+        Err(_) => base64::engine::general_purpose::STANDARD
+            .decode(manifest)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| Error::protocol("invalid cache manifest descriptor"))?,
+    };
+    for dest in dests {
+        tag_local_cache(Path::new(&dest), &descriptor)?;
+    }
+    Ok(())
+}
+
+/// Cache options entry for a backend and its attributes
+pub(crate) fn cache_entry(cache_type: &str, attrs: BTreeMap<String, String>) -> CacheOptionsEntry {
+    CacheOptionsEntry {
+        r#type: cache_type.to_string(),
+        attrs: attrs.into_iter().collect(),
+    }
+}
+
+/// Exporters of a build: its client-side outputs, then registry pushes
+///
+/// Client-side outputs come first, so that the exporter index BuildKit
+/// sends when streaming an output back is the index in
+/// [`BuildConfig::exports`]. Several exporters need BuildKit v0.13 or later;
+/// their metadata comes back merged into one exporter response.
+pub(crate) fn exporters(config: &BuildConfig) -> Result<Vec<Exporter>> {
+    check_exports(&config.exports)?;
+    let mut exporters: Vec<Exporter> = config
+        .exports
+        .iter()
+        .map(|export| match export {
+            Export::Local(_) => Exporter {
+                r#type: "local".to_string(),
+                attrs: HashMap::new(),
+            },
+            Export::Oci(_) => tarball_exporter("oci", &config.tags),
+            Export::Docker(_) | Export::DockerLoad(_) => tarball_exporter("docker", &config.tags),
         })
+        .collect();
+    if config.push {
+        exporters.extend(image_exporters(&config.tags, &config.insecure_registries));
     }
+    Ok(exporters)
+}
 
-    /// Prepare build context based on source type
-    async fn prepare_context(&self, config: &BuildConfig, session: &Session) -> Result<String> {
-        match &config.source {
-            DockerfileSource::Local { context_path, .. } => {
-                // Validate the context path
-                let file_sync = FileSync::new(context_path);
-                file_sync.validate()?;
+/// Reject client-side outputs writing to the same path, which would
+/// overwrite each other as BuildKit streams them concurrently
+fn check_exports(exports: &[Export]) -> Result<()> {
+    let mut paths: HashMap<&Path, &Export> = HashMap::new();
+    for export in exports {
+        let path = match export {
+            Export::Local(path) | Export::Oci(path) | Export::Docker(path) => path.as_path(),
+            Export::DockerLoad(_) => continue,
+        };
+        if let Some(other) = paths.insert(path, export) {
+            return Err(Error::InvalidConfig(format!(
+                "outputs {:?} and {:?} both write to {}",
+                other,
+                export,
+                path.display()
+            )));
+        }
+    }
+    Ok(())
+}
 
-                // Use session-based input
-                // The format is: input:<name> where name references the session
-                Ok(format!("input:{}:context", session.shared_key))
+/// Exporter sending an image tarball of the given type, named after `tags`
+fn tarball_exporter(exporter_type: &str, tags: &[String]) -> Exporter {
+    let mut attrs = HashMap::new();
+    if !tags.is_empty() {
+        attrs.insert("name".to_string(), tags.join(","));
+    }
+    Exporter {
+        r#type: exporter_type.to_string(),
+        attrs,
+    }
+}
+
+/// Session service receiving the client-side outputs of a build
+fn file_send_server(exports: &[Export]) -> FileSendServer {
+    exports
+        .iter()
+        .enumerate()
+        .fold(FileSendServer::new(), |server, (id, export)| match export {
+            Export::Local(dest) => server.with_directory(id as u32, dest),
+            Export::Oci(path) | Export::Docker(path) => server.with_file(id as u32, path),
+            Export::DockerLoad(daemon) => server.with_docker_load(id as u32, daemon.clone()),
+        })
+}
+
+/// Build image exporters that push the given tags
+///
+/// `registry.insecure` applies to a whole exporter, so tags on plain-HTTP
+/// registries get their own exporter when mixed with secure registries.
+pub(crate) fn image_exporters(
+    tags: &[String],
+    insecure_registries: &HashMap<String, bool>,
+) -> Vec<Exporter> {
+    let (insecure, secure): (Vec<&String>, Vec<&String>) = tags.iter().partition(|tag| {
+        registry_host(tag).is_some_and(|host| {
+            insecure_registries
+                .get(&host)
+                .copied()
+                .unwrap_or_else(|| guess_insecure_registry(&host))
+        })
+    });
+
+    [(secure, false), (insecure, true)]
+        .into_iter()
+        .filter(|(tags, _)| !tags.is_empty())
+        .map(|(tags, insecure)| {
+            let mut attrs = HashMap::new();
+            let names: Vec<&str> = tags.iter().map(|t| t.as_str()).collect();
+            attrs.insert("name".to_string(), names.join(","));
+            attrs.insert("push".to_string(), "true".to_string());
+            if insecure {
+                attrs.insert("registry.insecure".to_string(), "true".to_string());
             }
-            DockerfileSource::GitHub {
-                repo_url,
-                git_ref,
-                token,
-                ..
-            } => {
-                let mut url = repo_url.clone();
+            Exporter {
+                r#type: "image".to_string(),
+                attrs,
+            }
+        })
+        .collect()
+}
 
-                // Ensure URL ends with .git for Git protocol
-                if !url.ends_with(".git") {
-                    url.push_str(".git");
-                }
+/// Registry host of an image tag, if it names one
+pub(crate) fn registry_host(tag: &str) -> Option<String> {
+    Reference::parse(tag).ok().map(|r| r.domain)
+}
 
-                // Add authentication token if provided
-                if let Some(token) = token {
-                    // Format: https://token@github.com/user/repo.git
-                    url = url.replace("https://", &format!("https://{}@", token));
-                }
+/// Guess whether a registry is served over plain HTTP from its host name
+///
+/// Only used for hosts without an explicit setting in
+/// [`BuildConfig::insecure_registries`].
+pub(crate) fn guess_insecure_registry(host: &str) -> bool {
+    host.starts_with("localhost")
+        || host.starts_with("127.0.0.1")
+        || host.starts_with("registry:") // Docker Compose service name
+        || (!host.contains('.') && !host.starts_with("docker.io")) // Simple heuristic for local names
+}
 
-                // Add git reference
-                if let Some(git_ref) = git_ref {
-                    url = format!("{}#{}", url, git_ref);
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                Ok(url)
-            }
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    fn result(metadata: &[(&str, &str)]) -> BuildResult {
+        BuildResult {
+            build_ref: "build-1".to_string(),
+            digest: None,
+            metadata: metadata
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            report: Default::default(),
+            warnings: Vec::new(),
+            platforms: Vec::new(),
+            context_stats: Default::default(),
         }
     }
 
-    /// Monitor build progress and send updates to the handler
-    async fn monitor_progress(
-        &mut self,
-        build_ref: &str,
-        handler: &mut Box<dyn ProgressHandler>,
-    ) -> Result<()> {
-        let status_request = StatusRequest {
-            r#ref: build_ref.to_string(),
+    #[test]
+    fn buildx_metadata_decodes_json_values() {
+        use base64::Engine;
+
+        let descriptor = base64::engine::general_purpose::STANDARD
+            .encode(r#"{"mediaType":"application/vnd.oci.image.index.v1+json","size":856}"#);
+        let result = result(&[
+            ("containerimage.digest", "sha256:abc"),
+            ("containerimage.descriptor", &descriptor),
+            ("image.name", "localhost:5000/app:latest"),
+        ]);
+
+        let json = result.metadata_json(MetadataFormat::Buildx);
+        assert_eq!(json["containerimage.digest"], "sha256:abc");
+        assert_eq!(json["containerimage.descriptor"]["size"], 856);
+        assert_eq!(json["image.name"], "localhost:5000/app:latest");
+        assert_eq!(json["buildx.build.ref"], "build-1");
+
+        let raw = result.metadata_json(MetadataFormat::Raw);
+        assert_eq!(raw["containerimage.descriptor"], descriptor.as_str());
+        assert!(raw.get("buildx.build.ref").is_none());
+    }
+
+    #[test]
+    fn metadata_is_written_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metadata.json");
+        result(&[("containerimage.digest", "sha256:abc")])
+            .write_metadata(&path, MetadataFormat::Buildx)
+            .unwrap();
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["containerimage.digest"], "sha256:abc");
+    }
+
+    #[test]
+    fn pushed_reference_is_pinned_to_digest() {
+        let mut pushed = result(&[(
+            "image.name",
+            "localhost:5000/app:latest,localhost:5000/app:v1",
+        )]);
+        assert_eq!(
+            pushed.pushed_reference().as_deref(),
+            Some("localhost:5000/app:latest")
+        );
+
+        pushed.digest = Some("sha256:abc".to_string());
+        assert_eq!(
+            pushed.pushed_reference().as_deref(),
+            Some("localhost:5000/app@sha256:abc")
+        );
+
+        assert!(result(&[]).pushed_reference().is_none());
+    }
+
+    #[test]
+    fn status_updates_are_scrubbed() {
+        use crate::proto::moby::buildkit::v1::{Vertex, VertexLog};
+
+        let scrubber = Scrubber::new(["ghp_token", "s3cr3t-value"]);
+        let mut status = StatusResponse {
+            vertexes: vec![Vertex {
+                name: "[internal] load git source https://ghp_token@github.com/o/r.git".to_string(),
+                error: "failed to fetch https://ghp_token@github.com/o/r.git".to_string(),
+                ..Default::default()
+            }],
+            statuses: vec![],
+            logs: vec![VertexLog {
+                msg: b"echo s3cr3t-value\n".to_vec(),
+                ..Default::default()
+            }],
+            warnings: vec![],
         };
 
-        let mut stream = self.control().status(status_request).await?.into_inner();
+        scrub_status(&mut status, &scrubber);
+        assert_eq!(
+            status.vertexes[0].name,
+            "[internal] load git source https://***@github.com/o/r.git"
+        );
+        assert_eq!(
+            status.vertexes[0].error,
+            "failed to fetch https://***@github.com/o/r.git"
+        );
+        assert_eq!(status.logs[0].msg, b"echo ***\n");
+    }
 
-        handler.on_start()?;
+    #[test]
+    fn errors_are_scrubbed() {
+        let scrubber = Scrubber::new(["ghp_token"]);
+        let error = Error::from(tonic::Status::unknown(
+            "failed to load https://ghp_token@github.com/o/r.git",
+        ))
+        .scrub(&scrubber);
+        assert!(!error.to_string().contains("ghp_token"));
+        assert_eq!(error.grpc_status().unwrap().code(), tonic::Code::Unknown);
+    }
 
-        while let Some(response) = stream.next().await {
-            match response {
-                Ok(status) => {
-                    handler.on_status(status)?;
-                }
-                Err(e) => {
-                    tracing::error!("Status stream error: {}", e);
-                    handler.on_error(&e.to_string())?;
-                    break;
-                }
-            }
+    #[test]
+    fn client_side_exporters_come_first() {
+        let config = BuildConfig::local(".")
+            .tag("localhost:5000/app:latest")
+            .export_oci("app.tar")
+            .export_local("dist");
+        let exporters = exporters(&config).unwrap();
+
+        let types: Vec<&str> = exporters.iter().map(|e| e.r#type.as_str()).collect();
+        assert_eq!(types, ["oci", "local", "image"]);
+        assert_eq!(exporters[0].attrs["name"], "localhost:5000/app:latest");
+
+        let file_send = file_send_server(&config.exports);
+        assert_eq!(file_send.file(0), Some(Path::new("app.tar")));
+        assert_eq!(file_send.directory(1), Some(Path::new("dist")));
+    }
+
+    #[test]
+    fn gateway_frontends_keep_build_options() {
+        use crate::builder::Frontend;
+
+        let mut attrs = HashMap::from([("target".to_string(), "release".to_string())]);
+        let frontend = Frontend::Gateway {
+            image: "docker/dockerfile:1.7".to_string(),
+            attrs: BTreeMap::from([("build-arg:GO_VERSION".to_string(), "1.22".to_string())]),
+        };
+        frontend.apply_attrs(&mut attrs).unwrap();
+        assert_eq!(frontend.name(), "gateway.v0");
+        assert_eq!(attrs["source"], "docker/dockerfile:1.7");
+        assert_eq!(attrs["build-arg:GO_VERSION"], "1.22");
+
+        let frontend = Frontend::Gateway {
+            image: "docker/dockerfile:1.7".to_string(),
+            attrs: BTreeMap::from([("target".to_string(), "debug".to_string())]),
+        };
+        let mut attrs = HashMap::from([("target".to_string(), "release".to_string())]);
+        assert!(matches!(
+            frontend.apply_attrs(&mut attrs),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn tarball_checksums_and_headers_convert_the_download() {
+        let url = "https://example.com/app.tar.gz";
+        assert!(source_policy(&BuildConfig::tarball_url(url))
+            .unwrap()
+            .is_none());
+
+        let config = BuildConfig::tarball_url(url)
+            .tarball_checksum("sha256:abc")
+            .tarball_header("Accept", "application/gzip")
+            .tarball_header("Authorization", "Bearer t0ken");
+        let policy = source_policy(&config).unwrap().unwrap();
+        assert_eq!(policy.rules.len(), 1);
+        let updates = policy.rules[0].updates.as_ref().unwrap();
+        assert_eq!(updates.identifier, url);
+        assert_eq!(updates.attrs["http.checksum"], "sha256:abc");
+        assert_eq!(updates.attrs["http.header.Accept"], "application/gzip");
+        // Credentials are session secrets, never part of the request
+        assert!(!updates.attrs.contains_key("http.header.Authorization"));
+        assert_eq!(updates.attrs["http.authheadersecret"], TARBALL_AUTH_HEADER);
+        assert_eq!(
+            config.resolve_secrets().unwrap()[TARBALL_AUTH_HEADER],
+            b"Bearer t0ken"
+        );
+    }
+
+    #[test]
+    fn dockerfiles_outside_the_context_are_served_from_their_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("app")).unwrap();
+        std::fs::create_dir_all(dir.path().join("docker")).unwrap();
+
+        let dockerfile = dir.path().join("app").join("../docker/Dockerfile");
+        assert_eq!(
+            dockerfile_dir(&dockerfile).unwrap(),
+            std::fs::canonicalize(dir.path().join("docker")).unwrap()
+        );
+    }
+
+    #[test]
+    fn git_contexts_encode_ref_and_subdir() {
+        let context = |url, git_ref, subdir| git_context(url, git_ref, subdir).unwrap();
+        assert_eq!(
+            context("https://github.com/o/r", Some("main"), None),
+            "https://github.com/o/r.git#main"
+        );
+        assert_eq!(
+            context("git@gitlab.com:o/r.git", None, Some("services/api")),
+            "git@gitlab.com:o/r.git#:services/api"
+        );
+        assert_eq!(
+            context("ssh://git@example.com/o/r", Some("v1"), Some("./app/")),
+            "ssh://git@example.com/o/r#v1:app"
+        );
+        assert_eq!(
+            context("https://github.com/o/r.git", None, Some(".")),
+            "https://github.com/o/r.git"
+        );
+    }
+
+    #[test]
+    fn git_subdirs_stay_within_the_repository() {
+        for subdir in ["../other", "/etc", "app/../../x", "a#b"] {
+            assert!(matches!(
+                git_context("https://github.com/o/r.git", None, Some(subdir)),
+                Err(Error::InvalidConfig(_))
+            ));
         }
+        assert!(git_context("https://github.com/o/r.git", Some("a:b"), None).is_err());
+    }
 
-        handler.on_complete()?;
-        Ok(())
+    #[test]
+    fn git_tokens_are_session_secrets() {
+        use crate::builder::GitAuth;
+
+        let config = BuildConfig::github("https://github.com/o/r").github_token("ghp_token");
+        assert_eq!(
+            config.resolve_secrets().unwrap()["GIT_AUTH_TOKEN"],
+            b"ghp_token"
+        );
+
+        let config = BuildConfig::git("https://bitbucket.org/o/r.git")
+            .git_auth(GitAuth::Header("Basic dXNlcjpwYXNz".to_string()));
+        assert_eq!(
+            config.resolve_secrets().unwrap()["GIT_AUTH_HEADER"],
+            b"Basic dXNlcjpwYXNz"
+        );
+    }
+
+    #[test]
+    fn outputs_to_the_same_path_conflict() {
+        let config = BuildConfig::local(".")
+            .export_local("out")
+            .export_oci("out");
+        assert!(matches!(exporters(&config), Err(Error::InvalidConfig(_))));
+
+        let config = BuildConfig::local(".")
+            .export_local("dist")
+            .export_oci("app.tar")
+            .export_docker("app-docker.tar");
+        assert_eq!(exporters(&config).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn docker_load_without_push() {
+        let daemon = crate::docker::DockerDaemon::default();
+        let config = BuildConfig::local(".")
+            .tag("app:dev")
+            .push(false)
+            .export_docker("app.tar")
+            .load_into_docker(daemon.clone());
+        let exporters = exporters(&config).unwrap();
+
+        let types: Vec<&str> = exporters.iter().map(|e| e.r#type.as_str()).collect();
+        assert_eq!(types, ["docker", "docker"]);
+        assert_eq!(exporters[1].attrs["name"], "app:dev");
+
+        let file_send = file_send_server(&config.exports);
+        assert_eq!(file_send.file(0), Some(Path::new("app.tar")));
+        assert_eq!(file_send.docker_daemon(1), Some(&daemon));
+    }
+
+    #[test]
+    fn single_exporter_for_one_registry() {
+        let exporters = image_exporters(
+            &tags(&["ghcr.io/org/app:v1", "ghcr.io/org/app:latest"]),
+            &HashMap::new(),
+        );
+        assert_eq!(exporters.len(), 1);
+        assert_eq!(
+            exporters[0].attrs["name"],
+            "ghcr.io/org/app:v1,ghcr.io/org/app:latest"
+        );
+        assert!(!exporters[0].attrs.contains_key("registry.insecure"));
+    }
+
+    #[test]
+    fn insecure_registries_get_their_own_exporter() {
+        let exporters = image_exporters(
+            &tags(&[
+                "localhost:5000/app:v1",
+                "docker.io/user/app:v1",
+                "registry:5000/app:v1",
+            ]),
+            &HashMap::new(),
+        );
+        assert_eq!(exporters.len(), 2);
+        assert_eq!(exporters[0].attrs["name"], "docker.io/user/app:v1");
+        assert!(!exporters[0].attrs.contains_key("registry.insecure"));
+        assert_eq!(
+            exporters[1].attrs["name"],
+            "localhost:5000/app:v1,registry:5000/app:v1"
+        );
+        assert_eq!(exporters[1].attrs["registry.insecure"], "true");
+    }
+
+    #[test]
+    fn explicit_setting_overrides_guess() {
+        let overrides = HashMap::from([
+            ("localhost:5000".to_string(), false),
+            ("registry.ci.svc:5000".to_string(), true),
+        ]);
+        let exporters = image_exporters(
+            &tags(&["localhost:5000/app:v1", "registry.ci.svc:5000/app:v1"]),
+            &overrides,
+        );
+        assert_eq!(exporters.len(), 2);
+        assert_eq!(exporters[0].attrs["name"], "localhost:5000/app:v1");
+        assert!(!exporters[0].attrs.contains_key("registry.insecure"));
+        assert_eq!(exporters[1].attrs["name"], "registry.ci.svc:5000/app:v1");
+        assert_eq!(exporters[1].attrs["registry.insecure"], "true");
+    }
+
+    #[test]
+    fn no_exporters_without_tags() {
+        assert!(image_exporters(&[], &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn local_cache_is_imported_from_its_tagged_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = format!("type=local,src={}", dir.path().display());
+        let config = BuildConfig::local(".")
+            .cache_from(spec.as_str())
+            .cache_to(format!("type=local,dest={}", dir.path().display()));
+        assert_eq!(local_cache_dirs(&config).len(), 2);
+
+        // Nothing to import before the first export
+        assert!(cache_options(&config).imports.is_empty());
+
+        let descriptor = serde_json::json!({ "digest": "sha256:abc", "size": 10 });
+        let encoded = base64::engine::general_purpose::STANDARD.encode(descriptor.to_string());
+        let response = HashMap::from([("cache.manifest".to_string(), encoded)]);
+        tag_local_cache_exports(&config, &response).unwrap();
+
+        let imports = cache_options(&config).imports;
+        assert_eq!(imports[0].r#type, "local");
+        assert_eq!(imports[0].attrs["digest"], "sha256:abc");
+        assert_eq!(
+            cache_options(&config).exports[0].attrs["dest"],
+            dir.path().display().to_string()
+        );
+    }
+
+    #[test]
+    fn missing_platforms_treat_arm64_v8_as_arm64() {
+        let platforms = |specs: &[&str]| -> Vec<Platform> {
+            specs.iter().map(|p| Platform::parse(p).unwrap()).collect()
+        };
+        let requested = platforms(&["linux/amd64", "linux/arm64", "linux/arm/v7"]);
+        assert!(missing_platforms(
+            &requested,
+            &platforms(&["linux/arm/v7", "linux/arm64/v8", "linux/amd64"])
+        )
+        .is_empty());
+        assert_eq!(
+            missing_platforms(&requested, &platforms(&["linux/amd64", "linux/arm/v6"])),
+            vec!["linux/arm64".to_string(), "linux/arm/v7".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn updates_before_the_status_call_are_seen() {
+        use crate::proto::moby::buildkit::v1::Vertex;
+        use crate::targets::ForwardProgress;
+        use crate::testing::MockControl;
+
+        let vertex = Vertex {
+            digest: "sha256:from".to_string(),
+            name: "[1/2] FROM alpine".to_string(),
+            ..Default::default()
+        };
+        let client = MockControl {
+            status: vec![StatusResponse {
+                vertexes: vec![vertex],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+        .serve()
+        .await;
+
+        // The solve finishes before the status call is made
+        let request = tonic::Request::new(SolveRequest {
+            r#ref: "build-early".to_string(),
+            ..Default::default()
+        });
+        client.solve(request).await.unwrap();
+
+        let (status_tx, mut status_rx) = mpsc::unbounded_channel();
+        let mut handler: Box<dyn ProgressHandler> = Box::new(ForwardProgress(status_tx));
+        let mut tracker = StatusTracker::new();
+        let mut events = BuildEvents::new(Vec::new(), "build-early", &BuildConfig::local("."));
+        let mut control = client.clone();
+        BuildKitClient::monitor_progress(
+            control.control().clone(),
+            client.options(),
+            "build-early",
+            ProgressContext {
+                handler: Some(&mut handler),
+                log: None,
+                tracker: &mut tracker,
+                events: &mut events,
+                scrubber: &Scrubber::default(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let status = status_rx.try_recv().unwrap();
+        assert_eq!(status.vertexes[0].name, "[1/2] FROM alpine");
+        assert_eq!(tracker.report().vertexes.len(), 1);
     }
 }
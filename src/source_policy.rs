@@ -0,0 +1,243 @@
+//! BuildKit source policies
+//!
+//! A source policy lets BuildKit allow, deny or rewrite the sources a
+//! build uses, e.g. pinning `docker-image://alpine:3.20` to a digest or
+//! denying git sources outside an organization. Policies are JSON files in
+//! the format `buildctl` reads from `EXPERIMENTAL_BUILDKIT_SOURCE_POLICY`;
+//! field names may be snake_case or camelCase, and enum values names or
+//! numbers.
+//!
+//! # Example
+//!
+//! ```
+//! use buildkit_client::source_policy;
+//!
+//! let policy = source_policy::parse(r#"{
+//!     "rules": [{
+//!         "action": "CONVERT",
+//!         "selector": { "identifier": "docker-image://docker.io/library/alpine:3.20" },
+//!         "updates": {
+//!             "identifier": "docker-image://docker.io/library/alpine:3.20@sha256:beefdbd8a1da6d2915566fde36db9db0b524eb737fc57cd1367effd16dc0d06d"
+//!         }
+//!     }]
+//! }"#)?;
+//! assert_eq!(policy.rules.len(), 1);
+//! # Ok::<(), buildkit_client::Error>(())
+//! ```
+
+use crate::error::{Error, Result};
+use crate::proto::moby::buildkit::v1::sourcepolicy::{
+    AttrConstraint, AttrMatch, MatchType, Policy, PolicyAction, Rule, Selector, Update,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Read a source policy from a JSON file
+pub fn load(path: impl AsRef<Path>) -> Result<Policy> {
+    let path = path.as_ref();
+    let json = std::fs::read_to_string(path).map_err(|e| Error::file_operation("read", path, e))?;
+    parse(&json).map_err(|e| match e {
+        Error::InvalidConfig(msg) => Error::InvalidConfig(format!("{}: {}", path.display(), msg)),
+        e => e,
+    })
+}
+
+/// Parse a source policy from JSON
+pub fn parse(json: &str) -> Result<Policy> {
+    let file: PolicyFile = serde_json::from_str(json)
+        .map_err(|e| Error::InvalidConfig(format!("invalid source policy: {}", e)))?;
+    Ok(Policy {
+        version: file.version,
+        rules: file
+            .rules
+            .into_iter()
+            .map(RuleFile::into_rule)
+            .collect::<Result<_>>()?,
+    })
+}
+
+/// Rule setting attributes of the download of an HTTP source, such as
+/// `http.checksum` or `http.header.<name>`
+pub(crate) fn http_rule(url: &str, attrs: HashMap<String, String>) -> Rule {
+    Rule {
+        action: PolicyAction::Convert.into(),
+        selector: Some(Selector {
+            identifier: url.to_string(),
+            match_type: MatchType::Exact.into(),
+            constraints: Vec::new(),
+        }),
+        updates: Some(Update {
+            identifier: url.to_string(),
+            attrs,
+        }),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    version: i64,
+    #[serde(default)]
+    rules: Vec<RuleFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    #[serde(default)]
+    action: Option<EnumValue>,
+    selector: Option<SelectorFile>,
+    #[serde(default)]
+    updates: Option<UpdateFile>,
+}
+
+impl RuleFile {
+    fn into_rule(self) -> Result<Rule> {
+        let action = enum_value(self.action, "action", PolicyAction::from_str_name)?;
+        let selector = self
+            .selector
+            .ok_or_else(|| Error::InvalidConfig("source policy rule without selector".into()))?;
+        let match_type = enum_value(selector.match_type, "match type", MatchType::from_str_name)?;
+        let constraints = selector
+            .constraints
+            .into_iter()
+            .map(|constraint| {
+                Ok(AttrConstraint {
+                    key: constraint.key,
+                    value: constraint.value,
+                    condition: enum_value(
+                        constraint.condition,
+                        "condition",
+                        AttrMatch::from_str_name,
+                    )?,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Rule {
+            action,
+            selector: Some(Selector {
+                identifier: selector.identifier,
+                match_type,
+                constraints,
+            }),
+            updates: self.updates.map(|update| Update {
+                identifier: update.identifier,
+                attrs: update.attrs,
+            }),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SelectorFile {
+    identifier: String,
+    #[serde(default, alias = "matchType")]
+    match_type: Option<EnumValue>,
+    #[serde(default)]
+    constraints: Vec<ConstraintFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConstraintFile {
+    key: String,
+    #[serde(default)]
+    value: String,
+    #[serde(default)]
+    condition: Option<EnumValue>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UpdateFile {
+    #[serde(default)]
+    identifier: String,
+    #[serde(default)]
+    attrs: HashMap<String, String>,
+}
+
+/// An enum given by name, e.g. `"CONVERT"`, or by number
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EnumValue {
+    Name(String),
+    Number(i32),
+}
+
+/// Number of an enum value, the first variant when it is not given
+fn enum_value<E: Into<i32>>(
+    value: Option<EnumValue>,
+    what: &str,
+    from_name: fn(&str) -> Option<E>,
+) -> Result<i32> {
+    match value {
+        None => Ok(0),
+        Some(EnumValue::Number(number)) => Ok(number),
+        Some(EnumValue::Name(name)) => {
+            from_name(&name.to_uppercase())
+                .map(Into::into)
+                .ok_or_else(|| {
+                    Error::InvalidConfig(format!("unknown source policy {} {}", what, name))
+                })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_are_parsed_in_either_case() {
+        let policy = parse(
+            r#"{
+                "version": 1,
+                "rules": [
+                    {
+                        "action": "DENY",
+                        "selector": {
+                            "identifier": "git://*",
+                            "matchType": "WILDCARD",
+                            "constraints": [{ "key": "git.fullurl", "value": "x", "condition": 1 }]
+                        }
+                    },
+                    {
+                        "action": 2,
+                        "selector": { "identifier": "docker-image://alpine:*", "match_type": "wildcard" },
+                        "updates": { "identifier": "docker-image://alpine:3.20", "attrs": { "a": "b" } }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(policy.version, 1);
+        let deny = &policy.rules[0];
+        assert_eq!(deny.action, PolicyAction::Deny as i32);
+        let selector = deny.selector.as_ref().unwrap();
+        assert_eq!(
+            selector.constraints[0].condition,
+            AttrMatch::Notequal as i32
+        );
+
+        let convert = &policy.rules[1];
+        assert_eq!(convert.action, PolicyAction::Convert as i32);
+        let updates = convert.updates.as_ref().unwrap();
+        assert_eq!(updates.identifier, "docker-image://alpine:3.20");
+        assert_eq!(updates.attrs["a"], "b");
+    }
+
+    #[test]
+    fn invalid_policies_are_rejected() {
+        assert!(
+            parse(r#"{ "rules": [{ "action": "PIN", "selector": { "identifier": "x" } }] }"#)
+                .is_err()
+        );
+        assert!(parse(r#"{ "rules": [{ "action": "DENY" }] }"#).is_err());
+        assert!(parse(r#"{ "rule": [] }"#).is_err());
+    }
+}
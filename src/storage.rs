@@ -0,0 +1,232 @@
+//! Build cache storage of the daemon
+//!
+//! BuildKit keeps the layers and snapshots of past builds until its
+//! garbage collector or a prune removes them.
+//! [`BuildKitClient::disk_usage`] shows what that cache holds and
+//! [`BuildKitClient::prune`] frees it on demand, e.g. on CI runners short
+//! of disk.
+
+use crate::client::BuildKitClient;
+use crate::error::{Error, Result};
+use crate::proto::moby::buildkit::v1::{DiskUsageRequest, PruneRequest, UsageRecord};
+use std::time::Duration;
+use tokio_stream::{Stream, StreamExt};
+
+/// Which cache records a prune removes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneOptions {
+    /// Keep records used more recently than this
+    pub keep_duration: Option<Duration>,
+    /// Keep this many bytes of cache, removing the least recently used
+    /// records beyond it
+    pub keep_bytes: Option<u64>,
+    /// Containerd filters over the records, e.g. `type==regular`; a record
+    /// matching any of them is pruned
+    pub filters: Vec<String>,
+    /// Also remove records that are still referenced, such as those of
+    /// local contexts, instead of only unused ones
+    pub all: bool,
+}
+
+impl PruneOptions {
+    /// Create options pruning all unused records
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep records used within `duration`
+    pub fn keep_duration(mut self, duration: Duration) -> Self {
+        self.keep_duration = Some(duration);
+        self
+    }
+
+    /// Keep `bytes` of cache
+    pub fn keep_bytes(mut self, bytes: u64) -> Self {
+        self.keep_bytes = Some(bytes);
+        self
+    }
+
+    /// Only prune records matching a filter
+    pub fn filter(mut self, filter: impl Into<String>) -> Self {
+        self.filters.push(filter.into());
+        self
+    }
+
+    /// Prune records that are still referenced too
+    pub fn all(mut self, all: bool) -> Self {
+        self.all = all;
+        self
+    }
+
+    fn to_request(&self) -> PruneRequest {
+        PruneRequest {
+            filter: self.filters.clone(),
+            all: self.all,
+            keep_duration: self
+                .keep_duration
+                .map_or(0, |d| i64::try_from(d.as_nanos()).unwrap_or(i64::MAX)),
+            reserved_space: self
+                .keep_bytes
+                .map_or(0, |b| i64::try_from(b).unwrap_or(i64::MAX)),
+            ..Default::default()
+        }
+    }
+}
+
+impl BuildKitClient {
+    /// Remove cache records from the daemon
+    ///
+    /// The stream yields each record as BuildKit frees it and ends when
+    /// the prune is done.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::storage::PruneOptions;
+    /// use buildkit_client::BuildKitClient;
+    /// use std::time::Duration;
+    /// use tokio_stream::StreamExt;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     let options = PruneOptions::new().keep_duration(Duration::from_secs(48 * 3600));
+    ///     let mut freed = std::pin::pin!(client.prune(options).await?);
+    ///     while let Some(record) = freed.next().await {
+    ///         let record = record?;
+    ///         println!("{} {}", record.id, record.size);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn prune(
+        &mut self,
+        options: PruneOptions,
+    ) -> Result<impl Stream<Item = Result<UsageRecord>>> {
+        let response = self.control().prune(options.to_request()).await?;
+        Ok(response
+            .into_inner()
+            .map(|record| record.map_err(Error::from)))
+    }
+
+    /// List the daemon's cache records matching any of `filters`
+    ///
+    /// Filters use containerd syntax over the record fields, e.g.
+    /// `type==exec.cachemount` or `inuse==true`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::BuildKitClient;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     let records = client.disk_usage::<&str>(&[]).await?;
+    ///     let total: i64 = records.iter().map(|r| r.size).sum();
+    ///     println!("{} records, {} bytes", records.len(), total);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn disk_usage<S: AsRef<str>>(&mut self, filters: &[S]) -> Result<Vec<UsageRecord>> {
+        let request = DiskUsageRequest {
+            filter: filters.iter().map(|f| f.as_ref().to_string()).collect(),
+            ..Default::default()
+        };
+        let response = self.control().disk_usage(request).await?;
+        Ok(response.into_inner().record)
+    }
+}
+
+/// JSON document of cache records, largest first, with their total size
+pub fn usage_json(records: &[UsageRecord]) -> serde_json::Value {
+    let mut records: Vec<&UsageRecord> = records.iter().collect();
+    records.sort_by_key(|r| std::cmp::Reverse(r.size));
+    let time = |t: &Option<prost_types::Timestamp>| t.as_ref().map(|t| t.to_string());
+    serde_json::json!({
+        "total_bytes": records.iter().map(|r| r.size).sum::<i64>(),
+        "records": records.iter().map(|r| {
+            serde_json::json!({
+                "id": r.id,
+                "size": r.size,
+                "type": r.record_type,
+                "description": r.description,
+                "mutable": r.mutable,
+                "in_use": r.in_use,
+                "shared": r.shared,
+                "usage_count": r.usage_count,
+                "created_at": time(&r.created_at),
+                "last_used_at": time(&r.last_used_at),
+                "parents": r.parents,
+            })
+        }).collect::<Vec<_>>(),
+    })
+}
+
+/// Parse a byte size such as `512`, `10MB` or `2GiB`
+///
+/// Units are case-insensitive; `kB`, `MB`, ... are decimal and `KiB`,
+/// `MiB`, ... binary.
+pub fn parse_bytes(size: &str) -> Result<u64> {
+    let invalid = || Error::InvalidConfig(format!("invalid size: {}", size));
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000u64.pow(2),
+        "g" | "gb" => 1000u64.pow(3),
+        "t" | "tb" => 1000u64.pow(4),
+        "ki" | "kib" => 1 << 10,
+        "mi" | "mib" => 1 << 20,
+        "gi" | "gib" => 1 << 30,
+        "ti" | "tib" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_parsed_with_units() {
+        assert_eq!(parse_bytes("512").unwrap(), 512);
+        assert_eq!(parse_bytes("10MB").unwrap(), 10_000_000);
+        assert_eq!(parse_bytes("1.5gb").unwrap(), 1_500_000_000);
+        assert_eq!(parse_bytes("2GiB").unwrap(), 2 << 30);
+        assert!(parse_bytes("ten").is_err());
+        assert!(parse_bytes("5XB").is_err());
+    }
+
+    #[test]
+    fn usage_is_listed_largest_first() {
+        let record = |id: &str, size| UsageRecord {
+            id: id.to_string(),
+            size,
+            ..Default::default()
+        };
+        let json = usage_json(&[record("small", 10), record("large", 200)]);
+        assert_eq!(json["total_bytes"], 210);
+        assert_eq!(json["records"][0]["id"], "large");
+        assert_eq!(json["records"][1]["last_used_at"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn prune_options_map_to_the_request() {
+        let request = PruneOptions::new()
+            .keep_duration(Duration::from_secs(2))
+            .keep_bytes(1_000)
+            .filter("type==regular")
+            .all(true)
+            .to_request();
+        assert_eq!(request.keep_duration, 2_000_000_000);
+        assert_eq!(request.reserved_space, 1_000);
+        assert_eq!(request.filter, vec!["type==regular".to_string()]);
+        assert!(request.all);
+        assert_eq!(PruneOptions::new().to_request().keep_duration, 0);
+    }
+}
@@ -0,0 +1,370 @@
+//! Builds reporting their progress as a stream of typed events
+//!
+//! [`BuildKitClient::build_with_events`] runs a build in the background and
+//! turns BuildKit's status updates into [`BuildEvent`]s, which suits GUIs
+//! and services better than implementing [`ProgressHandler`]. These are
+//! progress events of one build; [`crate::events`] has the lifecycle
+//! events sent to sinks of every build of a client.
+
+use crate::builder::BuildConfig;
+use crate::client::BuildKitClient;
+use crate::error::{Error, Result};
+use crate::progress::ProgressHandler;
+use crate::proto::moby::buildkit::v1::StatusResponse;
+use crate::solve::BuildResult;
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::Stream;
+use tokio_util::sync::CancellationToken;
+
+/// Progress event of a build run by [`BuildKitClient::build_with_events`]
+///
+/// Vertexes are the steps of the build graph, identified by their digest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildEvent {
+    /// A step started running, or was found in the cache
+    VertexStarted {
+        /// Digest of the step
+        digest: String,
+        /// Name of the step, e.g. `[2/3] RUN make`
+        name: String,
+    },
+    /// A step finished
+    VertexCompleted {
+        /// Digest of the step
+        digest: String,
+        /// Name of the step
+        name: String,
+        /// Whether the step's result came from the cache
+        cached: bool,
+        /// How long the step ran, if BuildKit reported both its times
+        duration: Option<Duration>,
+        /// Error of a failed step
+        error: Option<String>,
+    },
+    /// Output of a step
+    Log {
+        /// Digest of the step
+        digest: String,
+        /// 1 for stdout, 2 for stderr
+        stream: i64,
+        /// The output, which may end within a line
+        message: String,
+    },
+    /// Progress of a transfer or another sub-task of a step
+    Progress {
+        /// Digest of the step
+        digest: String,
+        /// Name of the sub-task, e.g. a layer being pulled
+        id: String,
+        /// Units done so far, bytes for transfers
+        current: i64,
+        /// Units in total, 0 if unknown
+        total: i64,
+        /// Whether the sub-task is done
+        completed: bool,
+    },
+    /// A warning about the build definition, such as a Dockerfile lint
+    Warning {
+        /// Digest of the step the warning is about
+        digest: String,
+        /// Short description of the warning
+        message: String,
+        /// Link to more information, if any
+        url: Option<String>,
+    },
+    /// The build finished; no events follow
+    Done {
+        /// Error of a failed build
+        error: Option<String>,
+    },
+}
+
+/// Build running in the background, see [`BuildKitClient::build_with_events`]
+pub struct BuildHandle {
+    task: JoinHandle<Result<BuildResult>>,
+    cancel_token: CancellationToken,
+}
+
+impl BuildHandle {
+    /// Wait for the build to finish
+    pub async fn wait(self) -> Result<BuildResult> {
+        self.task
+            .await
+            .map_err(|e| Error::other(format!("build task failed: {}", e)))?
+    }
+
+    /// Cancel the build, which then fails with [`Error::BuildCancelled`]
+    pub fn cancel(&self) {
+        self.cancel_token.cancel();
+    }
+
+    /// Whether the build has finished
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl BuildKitClient {
+    /// Start a build, returning a handle to it and a stream of its progress
+    ///
+    /// The build runs on a clone of the client in a Tokio task and
+    /// continues if the stream is dropped. The stream ends with
+    /// [`BuildEvent::Done`]. Cancelling the client's token cancels the
+    /// build too.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::stream::BuildEvent;
+    /// use buildkit_client::{BuildConfig, BuildKitClient};
+    /// use tokio_stream::StreamExt;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     let (build, events) = client.build_with_events(BuildConfig::local("./my-app"));
+    ///     let mut events = std::pin::pin!(events);
+    ///     while let Some(event) = events.next().await {
+    ///         if let BuildEvent::VertexCompleted { name, duration, .. } = event {
+    ///             println!("{} {:?}", name, duration);
+    ///         }
+    ///     }
+    ///     let result = build.wait().await?;
+    ///     println!("Image digest: {:?}", result.digest);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn build_with_events(
+        &self,
+        config: BuildConfig,
+    ) -> (BuildHandle, impl Stream<Item = BuildEvent>) {
+        let cancel_token = match self.cancel_token() {
+            Some(token) => token.child_token(),
+            None => CancellationToken::new(),
+        };
+        let client = self.clone().with_cancel_token(cancel_token.clone());
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+
+        let task = tokio::spawn(async move {
+            let forward: Box<dyn ProgressHandler> = Box::new(ForwardEvents {
+                tx: event_tx.clone(),
+                translator: EventTranslator::default(),
+            });
+            let result = client.build(config, Some(forward)).await;
+            // The receiver may have been dropped; the build still completes
+            let _ = event_tx.send(BuildEvent::Done {
+                error: result.as_ref().err().map(|e| e.to_string()),
+            });
+            result
+        });
+
+        (
+            BuildHandle { task, cancel_token },
+            UnboundedReceiverStream::new(event_rx),
+        )
+    }
+}
+
+/// Progress handler sending the events of status updates to a channel
+struct ForwardEvents {
+    tx: mpsc::UnboundedSender<BuildEvent>,
+    translator: EventTranslator,
+}
+
+impl ProgressHandler for ForwardEvents {
+    fn on_start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_status(&mut self, status: StatusResponse) -> Result<()> {
+        for event in self.translator.events(status) {
+            let _ = self.tx.send(event);
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_error(&mut self, _error: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Turns status updates into events, reporting each step's start and
+/// completion once however often BuildKit repeats the step
+#[derive(Default)]
+struct EventTranslator {
+    started: HashSet<String>,
+    completed: HashSet<String>,
+}
+
+impl EventTranslator {
+    fn events(&mut self, status: StatusResponse) -> Vec<BuildEvent> {
+        let time =
+            |t: &Option<prost_types::Timestamp>| (*t).and_then(|t| SystemTime::try_from(t).ok());
+        let mut events = Vec::new();
+
+        for vertex in status.vertexes {
+            let started = time(&vertex.started);
+            let completed = time(&vertex.completed);
+            let finished = completed.is_some() || vertex.cached || !vertex.error.is_empty();
+            if (started.is_some() || finished) && self.started.insert(vertex.digest.clone()) {
+                events.push(BuildEvent::VertexStarted {
+                    digest: vertex.digest.clone(),
+                    name: vertex.name.clone(),
+                });
+            }
+            if finished && self.completed.insert(vertex.digest.clone()) {
+                events.push(BuildEvent::VertexCompleted {
+                    duration: started
+                        .zip(completed)
+                        .and_then(|(started, completed)| completed.duration_since(started).ok()),
+                    error: (!vertex.error.is_empty()).then_some(vertex.error),
+                    cached: vertex.cached,
+                    digest: vertex.digest,
+                    name: vertex.name,
+                });
+            }
+        }
+
+        for vertex_status in status.statuses {
+            events.push(BuildEvent::Progress {
+                digest: vertex_status.vertex,
+                completed: vertex_status.completed.is_some(),
+                id: vertex_status.id,
+                current: vertex_status.current,
+                total: vertex_status.total,
+            });
+        }
+
+        for log in status.logs {
+            events.push(BuildEvent::Log {
+                digest: log.vertex,
+                stream: log.stream,
+                message: String::from_utf8_lossy(&log.msg).into_owned(),
+            });
+        }
+
+        for warning in status.warnings {
+            events.push(BuildEvent::Warning {
+                digest: warning.vertex,
+                message: String::from_utf8_lossy(&warning.short).into_owned(),
+                url: (!warning.url.is_empty()).then_some(warning.url),
+            });
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::moby::buildkit::v1::{Vertex, VertexLog, VertexWarning};
+
+    fn timestamp(seconds: i64) -> Option<prost_types::Timestamp> {
+        Some(prost_types::Timestamp { seconds, nanos: 0 })
+    }
+
+    #[test]
+    fn steps_start_and_complete_once() {
+        let mut translator = EventTranslator::default();
+        let run = Vertex {
+            digest: "sha256:run".to_string(),
+            name: "[2/3] RUN make".to_string(),
+            started: timestamp(100),
+            ..Default::default()
+        };
+
+        let events = translator.events(StatusResponse {
+            vertexes: vec![run.clone()],
+            logs: vec![VertexLog {
+                vertex: "sha256:run".to_string(),
+                stream: 1,
+                msg: b"cc -c main.c\n".to_vec(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        assert_eq!(
+            events,
+            vec![
+                BuildEvent::VertexStarted {
+                    digest: "sha256:run".to_string(),
+                    name: "[2/3] RUN make".to_string(),
+                },
+                BuildEvent::Log {
+                    digest: "sha256:run".to_string(),
+                    stream: 1,
+                    message: "cc -c main.c\n".to_string(),
+                },
+            ]
+        );
+
+        let completed = Vertex {
+            completed: timestamp(103),
+            ..run
+        };
+        let events = translator.events(StatusResponse {
+            vertexes: vec![completed.clone()],
+            warnings: vec![VertexWarning {
+                vertex: "sha256:run".to_string(),
+                short: b"JSONArgsRecommended".to_vec(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        assert_eq!(
+            events,
+            vec![
+                BuildEvent::VertexCompleted {
+                    digest: "sha256:run".to_string(),
+                    name: "[2/3] RUN make".to_string(),
+                    cached: false,
+                    duration: Some(Duration::from_secs(3)),
+                    error: None,
+                },
+                BuildEvent::Warning {
+                    digest: "sha256:run".to_string(),
+                    message: "JSONArgsRecommended".to_string(),
+                    url: None,
+                },
+            ]
+        );
+
+        // Repeated vertexes report nothing new
+        let events = translator.events(StatusResponse {
+            vertexes: vec![completed],
+            ..Default::default()
+        });
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn cached_steps_start_and_complete_at_once() {
+        let mut translator = EventTranslator::default();
+        let events = translator.events(StatusResponse {
+            vertexes: vec![Vertex {
+                digest: "sha256:load".to_string(),
+                name: "[internal] load build definition".to_string(),
+                cached: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            &events[1],
+            BuildEvent::VertexCompleted {
+                cached: true,
+                duration: None,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,370 @@
+//! Building several targets of one Dockerfile in a single call
+//!
+//! All targets share one session, so the context is synced once; each
+//! target gets its own solve, run concurrently, and stages shared between
+//! targets are built once through BuildKit's cache.
+
+use crate::builder::BuildConfig;
+use crate::client::BuildKitClient;
+use crate::error::{Error, Result};
+use crate::events::BuildEvents;
+use crate::progress::{ProgressHandler, StatusTracker};
+use crate::proto::moby::buildkit::v1::{CacheOptions, SolveRequest, StatusResponse};
+use crate::raw::{with_session_metadata, SolveRequestBuilder};
+use crate::redact::Scrubber;
+use crate::solve::{
+    build_log, cache_options, entitlements, image_exporters, source_policy, BuildResult,
+    ProgressContext,
+};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+/// A target stage to build, with the tags to push it to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildTarget {
+    /// Stage name in the Dockerfile
+    pub name: String,
+    /// Image tags to push; the target is built without pushing if empty
+    pub tags: Vec<String>,
+}
+
+impl BuildTarget {
+    /// Build a stage without pushing it
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            tags: Vec::new(),
+        }
+    }
+
+    /// Push the stage to an image tag
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+}
+
+impl From<&str> for BuildTarget {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+impl From<String> for BuildTarget {
+    fn from(name: String) -> Self {
+        Self::new(name)
+    }
+}
+
+/// Outcome of one target of [`BuildKitClient::build_targets`]
+#[derive(Debug)]
+pub struct TargetResult {
+    /// Stage name
+    pub target: String,
+    /// Build result, or the error the target failed with
+    pub result: Result<BuildResult>,
+}
+
+impl BuildKitClient {
+    /// Build several targets of a Dockerfile, syncing the context once
+    ///
+    /// Each target is solved concurrently under its own build reference
+    /// and pushed to its own tags; `config.target` and `config.tags` are
+    /// ignored. Cache is imported from `config.cache_from` but not
+    /// exported. Status updates of all targets go to `progress_handler`.
+    /// `config.timeout` applies to each target's solve.
+    ///
+    /// Returns one result per target, in the order given. A failing target
+    /// does not stop the others.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::targets::BuildTarget;
+    /// use buildkit_client::{BuildConfig, BuildKitClient};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     let targets = [
+    ///         BuildTarget::new("test"),
+    ///         BuildTarget::new("runtime").tag("localhost:5000/my-app:latest"),
+    ///     ];
+    ///
+    ///     for built in client
+    ///         .build_targets(BuildConfig::local("./my-app"), targets, None)
+    ///         .await?
+    ///     {
+    ///         println!("{}: {}", built.target, built.result.is_ok());
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn build_targets<I, T>(
+        &self,
+        config: BuildConfig,
+        targets: I,
+        mut progress_handler: Option<Box<dyn ProgressHandler>>,
+    ) -> Result<Vec<TargetResult>>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<BuildTarget>,
+    {
+        let targets: Vec<BuildTarget> = targets.into_iter().map(Into::into).collect();
+        if targets.is_empty() {
+            return Err(Error::InvalidConfig("no targets to build".to_string()));
+        }
+        if !config.exports.is_empty() {
+            return Err(Error::InvalidConfig(
+                "client-side exports are not supported when building several targets".to_string(),
+            ));
+        }
+
+        // The targets share one session and count as one build, run on
+        // their own handle to the shared connection
+        let _permit = self.build_permit().await?;
+        let session = self.clone().start_session(&config).await?;
+        tracing::info!(
+            "Session started: {} ({} targets)",
+            session.get_id(),
+            targets.len()
+        );
+
+        let frontend_attrs = self.frontend_attrs(&config, &session).await?;
+        let policy = source_policy(&config)?;
+        let scrubber = config.scrubber();
+        let (status_tx, mut status_rx) = mpsc::unbounded_channel();
+        let mut log = build_log(&config).await?;
+
+        if let Some(ref mut handler) = progress_handler {
+            handler.on_start()?;
+        }
+
+        let mut builds = JoinSet::new();
+        for (index, target) in targets.into_iter().enumerate() {
+            let build_ref = format!("build-{}", Uuid::new_v4());
+            tracing::info!("Building target {} with ref: {}", target.name, build_ref);
+
+            let mut target_config = config.clone().target(target.name.clone());
+            target_config.tags = target.tags.clone();
+            let mut events =
+                BuildEvents::new(self.event_sinks().to_vec(), &build_ref, &target_config);
+            events.queued();
+            events.started(&session.get_id());
+
+            let mut request = SolveRequestBuilder::new(&build_ref)
+                .session(&session)
+                .frontend(config.frontend.name())
+                .build();
+            request.frontend_attrs = frontend_attrs.clone();
+            request
+                .frontend_attrs
+                .insert("target".to_string(), target.name.clone());
+            request.entitlements = entitlements(&config);
+            request.source_policy = policy.clone();
+            if config.push {
+                request.exporters = image_exporters(&target.tags, &config.insecure_registries);
+            }
+            request.cache = Some(CacheOptions {
+                exports: vec![],
+                ..cache_options(&config)
+            });
+            let request = with_session_metadata(request, &session);
+
+            let mut client = self.clone();
+            let session_id = session.get_id();
+            let timeout = config.timeout;
+            let scrubber = scrubber.clone();
+            let status_tx = status_tx.clone();
+            builds.spawn(async move {
+                let result = client
+                    .solve_target(
+                        request,
+                        &session_id,
+                        timeout,
+                        &mut events,
+                        &scrubber,
+                        status_tx,
+                    )
+                    .await;
+                match &result {
+                    Ok(result) => events.completed(result.digest.clone()),
+                    Err(e) => events.failed(e),
+                }
+                (
+                    index,
+                    TargetResult {
+                        target: target.name,
+                        result,
+                    },
+                )
+            });
+        }
+        // Forwarding ends once every target has dropped its sender
+        drop(status_tx);
+
+        let forward = async {
+            while let Some(status) = status_rx.recv().await {
+                if let Some(ref mut log) = log {
+                    log.record(&status).await;
+                }
+                if let Some(ref mut handler) = progress_handler {
+                    handler.on_status(status)?;
+                }
+            }
+            Ok::<_, Error>(())
+        };
+        let collect = async {
+            let mut results = Vec::new();
+            while let Some(joined) = builds.join_next().await {
+                results.push(
+                    joined.map_err(|e| Error::other(format!("target build task failed: {}", e)))?,
+                );
+            }
+            Ok::<_, Error>(results)
+        };
+        let (forwarded, collected) = tokio::join!(forward, collect);
+        forwarded?;
+        let mut results = collected?;
+        results.sort_by_key(|(index, _)| *index);
+
+        if let Some(ref mut handler) = progress_handler {
+            match results.iter().find_map(|(_, r)| r.result.as_ref().err()) {
+                Some(e) => handler.on_error(&e.to_string())?,
+                None => handler.on_complete()?,
+            }
+        }
+
+        Ok(results.into_iter().map(|(_, result)| result).collect())
+    }
+
+    /// Solve one target while forwarding its status updates
+    ///
+    /// The target is abandoned as a single build would be once `timeout`
+    /// expires.
+    async fn solve_target(
+        &mut self,
+        request: tonic::Request<SolveRequest>,
+        session_id: &str,
+        timeout: Option<Duration>,
+        events: &mut BuildEvents,
+        scrubber: &Scrubber,
+        status_tx: mpsc::UnboundedSender<StatusResponse>,
+    ) -> Result<BuildResult> {
+        let build_ref = request.get_ref().r#ref.clone();
+        let mut tracker = StatusTracker::new();
+        let mut forward: Box<dyn ProgressHandler> = Box::new(ForwardProgress(status_tx));
+
+        let status_control = self.control().clone();
+        let outcome = self
+            .unless_cancelled(Self::within(timeout, async {
+                // As for single builds, BuildKit replays the target's
+                // early updates to a status call arriving after the solve
+                tokio::join!(
+                    Self::monitor_progress(
+                        status_control,
+                        self.options(),
+                        &build_ref,
+                        ProgressContext {
+                            handler: Some(&mut forward),
+                            log: None,
+                            tracker: &mut tracker,
+                            events,
+                            scrubber,
+                        },
+                    ),
+                    self.solve(request),
+                )
+            }))
+            .await;
+        let (monitor_result, solve_result) = match outcome {
+            Some(Some(results)) => results,
+            Some(None) => return Err(self.build_timed_out(timeout).await),
+            None => return Err(self.build_cancelled(&tracker).await),
+        };
+
+        // A step that failed on the status stream fails the target even
+        // when the solve itself returned successfully
+        let response = match tracker.failure(session_id, solve_result.as_ref().err()) {
+            Some(failure) => Err(failure),
+            None => solve_result.map_err(Error::from),
+        }
+        .map_err(|e| e.scrub(scrubber))?;
+        monitor_result.map_err(|e| e.scrub(scrubber))?;
+
+        let mut result =
+            BuildResult::from_exporter_response(build_ref, response.into_inner().exporter_response);
+        result.report = tracker.report();
+        result.warnings = tracker.warnings();
+        Ok(result)
+    }
+}
+
+/// Progress handler sending status updates to a handler shared by
+/// concurrent builds
+pub(crate) struct ForwardProgress(pub(crate) mpsc::UnboundedSender<StatusResponse>);
+
+impl ProgressHandler for ForwardProgress {
+    fn on_start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_status(&mut self, status: StatusResponse) -> Result<()> {
+        // The receiver only goes away once all targets are done
+        let _ = self.0.send(status);
+        Ok(())
+    }
+
+    fn on_complete(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_error(&mut self, _error: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockControl;
+
+    #[test]
+    fn targets_convert_from_names() {
+        let target: BuildTarget = "runtime".into();
+        assert_eq!(target, BuildTarget::new("runtime"));
+        assert!(target.tags.is_empty());
+
+        let tagged = BuildTarget::new("runtime").tag("localhost:5000/app:latest");
+        assert_eq!(tagged.tags, vec!["localhost:5000/app:latest"]);
+    }
+
+    #[tokio::test]
+    async fn targets_time_out() {
+        let mut client = MockControl {
+            hang: true,
+            ..Default::default()
+        }
+        .serve()
+        .await;
+        let request = tonic::Request::new(SolveRequestBuilder::new("build-target").build());
+        let mut events = BuildEvents::new(Vec::new(), "build-target", &BuildConfig::local("."));
+        let (status_tx, _status_rx) = mpsc::unbounded_channel();
+
+        let result = client
+            .solve_target(
+                request,
+                "session",
+                Some(Duration::from_millis(50)),
+                &mut events,
+                &Scrubber::default(),
+                status_tx,
+            )
+            .await;
+        match result {
+            Err(Error::Build(message)) => assert!(message.contains("timed out")),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+}
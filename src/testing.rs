@@ -0,0 +1,120 @@
+//! An in-process stand-in for buildkitd's Control service, for tests of
+//! the solve and status handling
+
+use crate::client::BuildKitClient;
+use crate::proto::moby::buildkit::v1::control_server::{Control, ControlServer};
+use crate::proto::moby::buildkit::v1::*;
+use std::pin::Pin;
+use tokio::net::TcpListener;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status, Streaming};
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Control service answering solves and replaying canned status updates
+///
+/// As buildkitd does, a status call gets every update of the build so far,
+/// however late it arrives.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MockControl {
+    /// Updates sent on every status stream
+    pub(crate) status: Vec<StatusResponse>,
+    /// Never answer solves, and keep status streams open
+    pub(crate) hang: bool,
+}
+
+impl MockControl {
+    /// Serve the mock and connect a client to it
+    pub(crate) async fn serve(self) -> BuildKitClient {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = async_stream::stream! {
+            loop {
+                yield listener.accept().await.map(|(stream, _)| stream);
+            }
+        };
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(ControlServer::new(self))
+                .serve_with_incoming(incoming),
+        );
+        BuildKitClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap()
+    }
+}
+
+#[tonic::async_trait]
+impl Control for MockControl {
+    type PruneStream = ResponseStream<UsageRecord>;
+    type StatusStream = ResponseStream<StatusResponse>;
+    type SessionStream = ResponseStream<BytesMessage>;
+    type ListenBuildHistoryStream = ResponseStream<BuildHistoryEvent>;
+
+    async fn disk_usage(
+        &self,
+        _: Request<DiskUsageRequest>,
+    ) -> Result<Response<DiskUsageResponse>, Status> {
+        Err(Status::unimplemented("disk usage"))
+    }
+
+    async fn prune(&self, _: Request<PruneRequest>) -> Result<Response<Self::PruneStream>, Status> {
+        Err(Status::unimplemented("prune"))
+    }
+
+    async fn solve(&self, _: Request<SolveRequest>) -> Result<Response<SolveResponse>, Status> {
+        if self.hang {
+            std::future::pending::<()>().await;
+        }
+        Ok(Response::new(SolveResponse::default()))
+    }
+
+    async fn status(
+        &self,
+        _: Request<StatusRequest>,
+    ) -> Result<Response<Self::StatusStream>, Status> {
+        let updates = self.status.clone();
+        let hang = self.hang;
+        let stream = async_stream::stream! {
+            for update in updates {
+                yield Ok(update);
+            }
+            if hang {
+                std::future::pending::<()>().await;
+            }
+        };
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn session(
+        &self,
+        _: Request<Streaming<BytesMessage>>,
+    ) -> Result<Response<Self::SessionStream>, Status> {
+        Err(Status::unimplemented("session"))
+    }
+
+    async fn list_workers(
+        &self,
+        _: Request<ListWorkersRequest>,
+    ) -> Result<Response<ListWorkersResponse>, Status> {
+        Ok(Response::new(ListWorkersResponse::default()))
+    }
+
+    async fn info(&self, _: Request<InfoRequest>) -> Result<Response<InfoResponse>, Status> {
+        Ok(Response::new(InfoResponse::default()))
+    }
+
+    async fn listen_build_history(
+        &self,
+        _: Request<BuildHistoryRequest>,
+    ) -> Result<Response<Self::ListenBuildHistoryStream>, Status> {
+        Err(Status::unimplemented("build history"))
+    }
+
+    async fn update_build_history(
+        &self,
+        _: Request<UpdateBuildHistoryRequest>,
+    ) -> Result<Response<UpdateBuildHistoryResponse>, Status> {
+        Err(Status::unimplemented("build history"))
+    }
+}
@@ -0,0 +1,184 @@
+//! BuildKit workers and worker selection constraints
+//!
+//! Builders in a mixed fleet differ in their workers' platforms and
+//! labels. A build pinned with [`BuildConfig::worker_constraint`] is
+//! checked against the daemon's workers before its session starts, so it
+//! fails fast on the wrong builder instead of running under emulation.
+//! [`BuildKitClient::list_workers`] shows the workers a daemon offers.
+//!
+//! [`BuildConfig::worker_constraint`]: crate::BuildConfig::worker_constraint
+
+use crate::builder::Platform;
+use crate::client::BuildKitClient;
+use crate::error::{Error, Result};
+use crate::proto::moby::buildkit::v1::types::WorkerRecord;
+use crate::proto::moby::buildkit::v1::ListWorkersRequest;
+use crate::proto::pb;
+
+impl BuildKitClient {
+    /// List the daemon's workers matching all `filters`
+    ///
+    /// Filters use containerd syntax over the worker ID, labels and
+    /// platforms, e.g. `platforms==linux/arm64`. The first worker of the
+    /// unfiltered list is the one BuildKit runs builds on.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use buildkit_client::BuildKitClient;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let mut client = BuildKitClient::connect("http://localhost:1234").await?;
+    ///     for worker in client.list_workers(&["platforms==linux/arm64"]).await? {
+    ///         println!("{} {:?}", worker.id, worker.labels);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_workers<S: AsRef<str>>(
+        &mut self,
+        filters: &[S],
+    ) -> Result<Vec<WorkerRecord>> {
+        let request = ListWorkersRequest {
+            filter: combined_filter(filters).into_iter().collect(),
+        };
+        let response = self.control().list_workers(request).await?;
+        Ok(response.into_inner().record)
+    }
+
+    /// Fail unless the worker BuildKit builds on matches all constraints
+    pub(crate) async fn check_worker_constraints(&mut self, constraints: &[String]) -> Result<()> {
+        let Some(filter) = combined_filter(constraints) else {
+            return Ok(());
+        };
+        let unavailable = |reason: String| Error::WorkerUnavailable {
+            constraints: filter.clone(),
+            reason,
+        };
+
+        let workers = self.list_workers::<&str>(&[]).await?;
+        let default = workers
+            .first()
+            .ok_or_else(|| unavailable("the daemon has no workers".to_string()))?;
+        let matching = self.list_workers(constraints).await?;
+
+        if matching.iter().any(|w| w.id == default.id) {
+            tracing::debug!("Worker {} matches constraints {}", default.id, filter);
+            return Ok(());
+        }
+        Err(unavailable(match matching.first() {
+            Some(other) => format!(
+                "worker {} matches, but builds run on the default worker {}",
+                other.id, default.id
+            ),
+            None => format!(
+                "the daemon's workers are {}",
+                workers
+                    .iter()
+                    .map(|w| w.id.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }))
+    }
+}
+
+impl From<&pb::Platform> for Platform {
+    fn from(p: &pb::Platform) -> Self {
+        Platform {
+            os: p.os.clone(),
+            arch: p.architecture.clone(),
+            variant: (!p.variant.is_empty()).then(|| p.variant.clone()),
+        }
+    }
+}
+
+/// JSON document of workers with their platforms, labels, GC policy and
+/// BuildKit version
+pub fn workers_json(workers: &[WorkerRecord]) -> serde_json::Value {
+    let worker_json = |w: &WorkerRecord| {
+        let platforms: Vec<String> = w
+            .platforms
+            .iter()
+            .map(|p| Platform::from(p).to_string())
+            .collect();
+        let gc_policy: Vec<serde_json::Value> = w
+            .gc_policy
+            .iter()
+            .map(|p| {
+                serde_json::json!({
+                    "all": p.all,
+                    "keep_duration_secs": p.keep_duration / 1_000_000_000,
+                    "filters": p.filters,
+                    "reserved_space": p.reserved_space,
+                    "max_used_space": p.max_used_space,
+                    "min_free_space": p.min_free_space,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "id": w.id,
+            "platforms": platforms,
+            "labels": w.labels,
+            "gc_policy": gc_policy,
+            "buildkit_version": w.buildkit_version.as_ref().map(|v| &v.version),
+        })
+    };
+    workers.iter().map(worker_json).collect()
+}
+
+/// Join filters into one containerd filter matching all of them
+///
+/// Separate filters in a request match any of them; comma-separated
+/// conditions within one filter must all hold.
+fn combined_filter<S: AsRef<str>>(filters: &[S]) -> Option<String> {
+    let filters: Vec<&str> = filters
+        .iter()
+        .map(|f| f.as_ref().trim())
+        .filter(|f| !f.is_empty())
+        .collect();
+    (!filters.is_empty()).then(|| filters.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn worker_platforms_are_listed() {
+        let worker = WorkerRecord {
+            id: "w1".to_string(),
+            platforms: vec![
+                pb::Platform {
+                    os: "linux".to_string(),
+                    architecture: "amd64".to_string(),
+                    ..Default::default()
+                },
+                pb::Platform {
+                    os: "linux".to_string(),
+                    architecture: "arm".to_string(),
+                    variant: "v7".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let json = workers_json(&[worker]);
+        assert_eq!(json[0]["id"], "w1");
+        assert_eq!(
+            json[0]["platforms"],
+            serde_json::json!(["linux/amd64", "linux/arm/v7"])
+        );
+        assert_eq!(json[0]["buildkit_version"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn filters_are_combined_with_and() {
+        assert_eq!(combined_filter::<&str>(&[]), None);
+        assert_eq!(combined_filter(&[" "]), None);
+        assert_eq!(
+            combined_filter(&["platforms==linux/arm64", "labels.pool==native"]),
+            Some("platforms==linux/arm64,labels.pool==native".to_string())
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Unit tests for bake file resolution
+
+use buildkit_client::bake::BakeFile;
+use buildkit_client::{DockerfileSource, Platform};
+use std::path::PathBuf;
+
+const BAKE: &str = r#"{
+    "variable": {
+        "REGISTRY": { "default": "localhost:5000" },
+        "BAKE_TEST_TAG": { "default": "latest" }
+    },
+    "group": {
+        "default": { "targets": ["app"] },
+        "all": { "targets": ["default", "tools"] }
+    },
+    "target": {
+        "base": {
+            "context": "./src",
+            "dockerfile": "docker/Dockerfile",
+            "platforms": ["linux/amd64", "linux/arm64"],
+            "args": { "RUST_VERSION": "1.80", "PROFILE": "debug" }
+        },
+        "app": {
+            "inherits": ["base"],
+            "target": "app",
+            "tags": ["${REGISTRY}/app:${BAKE_TEST_TAG}"],
+            "args": { "PROFILE": "release" }
+        },
+        "tools": {
+            "inherits": ["base"],
+            "target": "tools-${TOOL}",
+            "matrix": { "TOOL": ["lint", "fmt"] },
+            "tags": ["${REGISTRY}/${TOOL}:${BAKE_TEST_TAG}"],
+            "no-cache": true
+        }
+    }
+}"#;
+
+#[test]
+fn test_resolve_default_group() {
+    let bake = BakeFile::from_json(BAKE)
+        .unwrap()
+        .set("BAKE_TEST_TAG", "v1");
+    let configs = bake.resolve(&[]).unwrap();
+    assert_eq!(configs.len(), 1);
+
+    let config = &configs[0];
+    assert_eq!(config.tags, vec!["localhost:5000/app:v1"]);
+    assert_eq!(config.target.as_deref(), Some("app"));
+    let platforms: Vec<String> = config.platforms.iter().map(Platform::to_string).collect();
+    assert_eq!(platforms, vec!["linux/amd64", "linux/arm64"]);
+    assert_eq!(config.build_args["RUST_VERSION"], "1.80");
+    assert_eq!(config.build_args["PROFILE"], "release");
+    match &config.source {
+        DockerfileSource::Local {
+            context_path,
+            dockerfile_path,
+        } => {
+            assert_eq!(context_path, &PathBuf::from("./src"));
+            assert_eq!(
+                dockerfile_path.as_deref(),
+                Some(PathBuf::from("docker/Dockerfile").as_path())
+            );
+        }
+        _ => panic!("Expected local source"),
+    }
+}
+
+#[test]
+fn test_matrix_targets_expand_per_value() {
+    let bake = BakeFile::from_json(BAKE)
+        .unwrap()
+        .set("BAKE_TEST_TAG", "v1");
+    let targets = bake.resolve_targets(&["all"]).unwrap();
+
+    let names: Vec<&str> = targets.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["app", "tools-lint", "tools-fmt"]);
+    assert_eq!(targets[1].target.target.as_deref(), Some("tools-lint"));
+    assert_eq!(targets[2].target.tags, vec!["localhost:5000/fmt:v1"]);
+    assert_eq!(targets[2].target.no_cache, Some(true));
+}
+
+#[test]
+fn test_selection_is_deduplicated() {
+    let bake = BakeFile::from_json(BAKE).unwrap();
+    let targets = bake.resolve_targets(&["app", "default"]).unwrap();
+    assert_eq!(targets.len(), 1);
+}
+
+#[test]
+fn test_unknown_names_and_variables_are_errors() {
+    let bake = BakeFile::from_json(BAKE).unwrap();
+    assert!(bake.resolve(&["missing"]).is_err());
+
+    let bake =
+        BakeFile::from_json(r#"{"target": {"app": {"tags": ["app:${UNDEFINED}"]}}}"#).unwrap();
+    assert!(bake.resolve(&["app"]).is_err());
+}
+
+#[test]
+fn test_inheritance_cycles_are_errors() {
+    let bake =
+        BakeFile::from_json(r#"{"target": {"a": {"inherits": ["b"]}, "b": {"inherits": ["a"]}}}"#)
+            .unwrap();
+    let err = bake.resolve(&["a"]).unwrap_err();
+    assert!(err.to_string().contains("a -> b -> a"));
+}
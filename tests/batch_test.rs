@@ -0,0 +1,70 @@
+//! Unit tests for batch manifests
+
+use buildkit_client::batch::BatchManifest;
+use buildkit_client::{DockerfileSource, Platform};
+use std::path::PathBuf;
+
+const MANIFEST: &str = r#"{
+    "concurrency": 2,
+    "builds": [
+        {
+            "name": "api",
+            "context": "services/api",
+            "dockerfile": "docker/Dockerfile",
+            "target": "runtime",
+            "tags": ["localhost:5000/api:latest"],
+            "platforms": ["linux/amd64", "linux/arm64"],
+            "args": { "RUST_VERSION": "1.80" },
+            "no-cache": true
+        },
+        { "context": "services/worker" }
+    ]
+}"#;
+
+#[test]
+fn test_build_configs_resolve_contexts() {
+    let manifest: BatchManifest = serde_json::from_str(MANIFEST).unwrap();
+    assert_eq!(manifest.concurrency, Some(2));
+
+    let builds = manifest.build_configs("/release").unwrap();
+    let names: Vec<&str> = builds.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["api", "services/worker"]);
+
+    let config = &builds[0].1;
+    assert_eq!(config.tags, vec!["localhost:5000/api:latest"]);
+    assert_eq!(config.target.as_deref(), Some("runtime"));
+    assert_eq!(config.build_args["RUST_VERSION"], "1.80");
+    assert!(config.no_cache);
+    let platforms: Vec<String> = config.platforms.iter().map(Platform::to_string).collect();
+    assert_eq!(platforms, vec!["linux/amd64", "linux/arm64"]);
+    match &config.source {
+        DockerfileSource::Local {
+            context_path,
+            dockerfile_path,
+        } => {
+            assert_eq!(context_path, &PathBuf::from("/release/services/api"));
+            assert_eq!(
+                dockerfile_path.as_deref(),
+                Some(PathBuf::from("docker/Dockerfile").as_path())
+            );
+        }
+        _ => panic!("Expected local source"),
+    }
+}
+
+#[test]
+fn test_duplicate_names_are_errors() {
+    let manifest: BatchManifest = serde_json::from_str(
+        r#"{"builds": [{"name": "app", "context": "a"}, {"name": "app", "context": "b"}]}"#,
+    )
+    .unwrap();
+    let err = manifest.build_configs(".").unwrap_err();
+    assert!(err.to_string().contains("duplicate build name"));
+}
+
+#[test]
+fn test_unknown_fields_are_rejected() {
+    let result: Result<BatchManifest, _> =
+        serde_json::from_str(r#"{"builds": [{"context": ".", "tag": "app:latest"}]}"#);
+    assert!(result.is_err());
+}
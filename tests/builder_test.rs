@@ -1,7 +1,11 @@
 //! Unit tests for BuildConfig and related types
 
-use buildkit_client::{BuildConfig, DockerfileSource, Platform, RegistryAuth};
+use buildkit_client::{
+    BuildConfig, CacheExport, CacheImport, CacheMode, DockerfileSource, Entitlement, Export,
+    GhaCache, NamedContext, Platform, RegistryAuth, S3Cache, SecretSource,
+};
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[test]
 fn test_platform_parse() {
@@ -109,6 +113,7 @@ fn test_registry_auth() {
         host: "docker.io".to_string(),
         username: "testuser".to_string(),
         password: "testpass".to_string(),
+        ..Default::default()
     };
 
     let config = BuildConfig::local("./app").registry_auth(auth);
@@ -119,6 +124,103 @@ fn test_registry_auth() {
     assert_eq!(registry_auth.username, "testuser");
 }
 
+#[test]
+fn test_registry_auth_per_host() {
+    let auth = |host: &str| RegistryAuth {
+        host: host.to_string(),
+        username: "user".to_string(),
+        password: "pass".to_string(),
+        ..Default::default()
+    };
+
+    let config = BuildConfig::local("./app")
+        .tag("ghcr.io/org/app:v1")
+        .tag("registry.example.com/app:v1")
+        .registry_auth(auth("ghcr.io"))
+        .add_registry_auth(auth("registry.example.com"));
+
+    let hosts: Vec<&str> = config
+        .all_registry_auths()
+        .map(|a| a.host.as_str())
+        .collect();
+    assert_eq!(hosts, vec!["ghcr.io", "registry.example.com"]);
+}
+
+#[test]
+fn test_debug_output_redacts_credentials() {
+    let config = BuildConfig::github("https://github.com/org/private")
+        .github_token("ghp_supersecret")
+        .secret("npm_token", "npm-secret-value")
+        .registry_auth(RegistryAuth {
+            host: "ghcr.io".to_string(),
+            username: "user".to_string(),
+            password: "registry-password".to_string(),
+            identity_token: Some("identity-token".to_string()),
+            ..Default::default()
+        });
+
+    let debug = format!("{:?}", config);
+    for secret in [
+        "ghp_supersecret",
+        "npm-secret-value",
+        "registry-password",
+        "identity-token",
+    ] {
+        assert!(!debug.contains(secret), "{} leaked in {}", secret, debug);
+    }
+    assert!(debug.contains("npm_token"));
+    assert!(debug.contains("ghcr.io"));
+    assert!(debug.contains("***"));
+}
+
+#[test]
+fn test_config_hash() {
+    let base = || {
+        BuildConfig::local("./app")
+            .tag("app:latest")
+            .build_arg("A", "1")
+            .build_arg("B", "2")
+    };
+
+    assert_eq!(base().config_hash(), base().config_hash());
+    assert!(base().config_hash().starts_with("sha256:"));
+
+    // Credentials and secret values do not affect the hash
+    let with_creds = base()
+        .secret("token", "value-1")
+        .registry_auth(RegistryAuth {
+            host: "ghcr.io".to_string(),
+            password: "one".to_string(),
+            ..Default::default()
+        });
+    let rotated = base()
+        .secret("token", "value-2")
+        .registry_auth(RegistryAuth {
+            host: "ghcr.io".to_string(),
+            password: "two".to_string(),
+            ..Default::default()
+        });
+    assert_eq!(with_creds.config_hash(), rotated.config_hash());
+
+    assert_ne!(base().config_hash(), base().target("dev").config_hash());
+}
+
+#[test]
+fn test_insecure_registry() {
+    let config = BuildConfig::local("./app")
+        .insecure_registry("registry.ci.svc:5000", true)
+        .insecure_registry("localhost:5443", false);
+
+    assert_eq!(
+        config.insecure_registries.get("registry.ci.svc:5000"),
+        Some(&true)
+    );
+    assert_eq!(
+        config.insecure_registries.get("localhost:5443"),
+        Some(&false)
+    );
+}
+
 #[test]
 fn test_cache_config() {
     let config = BuildConfig::local("./app")
@@ -126,9 +228,153 @@ fn test_cache_config() {
         .cache_to("type=inline");
 
     assert_eq!(config.cache_from.len(), 1);
-    assert_eq!(config.cache_from[0], "type=registry,ref=myapp:cache");
+    assert_eq!(
+        config.cache_from[0],
+        CacheImport::Registry {
+            reference: "myapp:cache".to_string()
+        }
+    );
     assert_eq!(config.cache_to.len(), 1);
-    assert_eq!(config.cache_to[0], "type=inline");
+    assert_eq!(config.cache_to[0], CacheExport::Inline);
+}
+
+#[test]
+fn test_cache_spec_parse() {
+    assert_eq!(
+        CacheImport::parse("myapp:cache"),
+        CacheImport::Registry {
+            reference: "myapp:cache".to_string()
+        }
+    );
+    assert_eq!(
+        CacheExport::parse("myapp:cache"),
+        CacheExport::registry("myapp:cache", CacheMode::Max)
+    );
+    assert_eq!(
+        CacheExport::parse("type=registry,ref=myapp:cache,mode=min"),
+        CacheExport::registry("myapp:cache", CacheMode::Min)
+    );
+
+    // Attributes the enums don't model are kept as they are
+    let export = CacheExport::parse("type=registry,ref=myapp:cache,compression=zstd");
+    assert_eq!(export.cache_type(), "registry");
+    assert_eq!(export.attrs()["compression"], "zstd");
+    assert_eq!(
+        export.to_string(),
+        "type=registry,compression=zstd,ref=myapp:cache"
+    );
+
+    let import = CacheImport::parse("type=gha,scope=main");
+    assert_eq!(import.cache_type(), "gha");
+    assert_eq!(import.attrs()["scope"], "main");
+}
+
+#[test]
+fn test_cache_gha() {
+    let config = BuildConfig::local("./app").cache_gha(
+        "https://artifactcache.actions.githubusercontent.com/abc/",
+        "runtime-token",
+        "app",
+    );
+
+    let cache = GhaCache::new(
+        "https://artifactcache.actions.githubusercontent.com/abc/",
+        "runtime-token",
+        "app",
+    );
+    assert_eq!(config.cache_from, vec![CacheImport::Gha(cache.clone())]);
+    assert_eq!(
+        config.cache_to,
+        vec![CacheExport::Gha {
+            cache,
+            mode: CacheMode::Max
+        }]
+    );
+    assert_eq!(config.cache_to[0].attrs()["token"], "runtime-token");
+    assert_eq!(config.cache_to[0].attrs()["mode"], "max");
+
+    // The token stays out of logs and the config hash
+    assert!(!format!("{:?}", config).contains("runtime-token"));
+    assert!(!config.cache_to[0].to_string().contains("runtime-token"));
+
+    let export =
+        CacheExport::parse("type=gha,url=https://cache.example.com/,token=t,scope=web,mode=min");
+    assert_eq!(
+        export,
+        CacheExport::Gha {
+            cache: GhaCache::new("https://cache.example.com/", "t", "web"),
+            mode: CacheMode::Min
+        }
+    );
+}
+
+#[test]
+fn test_cache_s3() {
+    let cache = S3Cache::new("build-cache", "eu-west-1")
+        .prefix("app/")
+        .endpoint_url("http://minio:9000")
+        .credentials("AKIAEXAMPLE", "secret-access-key");
+    let config = BuildConfig::local("./app").cache_s3(cache.clone());
+
+    assert_eq!(config.cache_from, vec![CacheImport::S3(cache.clone())]);
+    let attrs = config.cache_to[0].attrs();
+    assert_eq!(config.cache_to[0].cache_type(), "s3");
+    assert_eq!(attrs["bucket"], "build-cache");
+    assert_eq!(attrs["region"], "eu-west-1");
+    assert_eq!(attrs["prefix"], "app/");
+    assert_eq!(attrs["use_path_style"], "true");
+    assert_eq!(attrs["access_key_id"], "AKIAEXAMPLE");
+    assert_eq!(attrs["secret_access_key"], "secret-access-key");
+    assert_eq!(attrs["mode"], "max");
+    assert!(!attrs.contains_key("session_token"));
+
+    assert!(!format!("{:?}", config).contains("secret-access-key"));
+    assert!(!config.cache_to[0].to_string().contains("secret-access-key"));
+
+    let spec = "type=s3,bucket=build-cache,region=eu-west-1,access_key_id=AKIAEXAMPLE,secret_access_key=secret-access-key,mode=min";
+    match CacheExport::parse(spec) {
+        CacheExport::S3 { cache, mode } => {
+            assert_eq!(cache.bucket, "build-cache");
+            assert_eq!(
+                cache.secret_access_key.as_deref(),
+                Some("secret-access-key")
+            );
+            assert_eq!(mode, CacheMode::Min);
+        }
+        other => panic!("expected an S3 cache, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_entitlements() {
+    let config = BuildConfig::local("./app")
+        .allow(Entitlement::NetworkHost)
+        .allow(Entitlement::parse("security.insecure").unwrap())
+        .allow(Entitlement::NetworkHost);
+
+    assert_eq!(
+        config.entitlements,
+        vec![Entitlement::NetworkHost, Entitlement::SecurityInsecure]
+    );
+    assert_eq!(
+        Entitlement::SecurityInsecure.to_string(),
+        "security.insecure"
+    );
+    assert!(Entitlement::parse("device").is_err());
+}
+
+#[test]
+fn test_source_policy_file() {
+    let config = BuildConfig::local("./app").source_policy_file("policy.json");
+    assert_eq!(config.source_policy, Some(PathBuf::from("policy.json")));
+    assert!(BuildConfig::local("./app").source_policy.is_none());
+}
+
+#[test]
+fn test_timeout() {
+    let config = BuildConfig::local("./app").timeout(Duration::from_secs(600));
+    assert_eq!(config.timeout, Some(Duration::from_secs(600)));
+    assert!(BuildConfig::local("./app").timeout.is_none());
 }
 
 #[test]
@@ -148,6 +394,119 @@ fn test_secrets_config() {
     );
 }
 
+#[test]
+fn test_secret_sources() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("token");
+    std::fs::write(&path, "file-secret").unwrap();
+
+    let config = BuildConfig::local("./app")
+        .secret_file("npm_token", &path)
+        .secret_env("api_key", "BUILDKIT_CLIENT_TEST_UNSET_SECRET");
+
+    assert_eq!(
+        config.secret_sources.get("npm_token"),
+        Some(&SecretSource::File(path.clone()))
+    );
+    assert_eq!(
+        SecretSource::File(path).read().unwrap(),
+        b"file-secret".to_vec()
+    );
+    assert!(matches!(
+        config.secret_sources["api_key"].read(),
+        Err(buildkit_client::Error::SecretNotFound(_))
+    ));
+}
+
+#[test]
+fn test_secret_spec_parse() {
+    let parse = |spec: &str| SecretSource::parse(spec).unwrap();
+    assert_eq!(
+        parse("id=npmrc,src=./.npmrc"),
+        (
+            "npmrc".to_string(),
+            SecretSource::File(PathBuf::from("./.npmrc"))
+        )
+    );
+    assert_eq!(
+        parse("id=aws,type=file,source=/root/.aws/credentials"),
+        (
+            "aws".to_string(),
+            SecretSource::File(PathBuf::from("/root/.aws/credentials"))
+        )
+    );
+    assert_eq!(
+        parse("id=token,env=GITHUB_TOKEN"),
+        (
+            "token".to_string(),
+            SecretSource::Env("GITHUB_TOKEN".to_string())
+        )
+    );
+    assert_eq!(
+        parse("type=env,id=token,src=GITHUB_TOKEN"),
+        (
+            "token".to_string(),
+            SecretSource::Env("GITHUB_TOKEN".to_string())
+        )
+    );
+    assert_eq!(
+        parse("id=TOKEN"),
+        ("TOKEN".to_string(), SecretSource::Env("TOKEN".to_string()))
+    );
+
+    for spec in [
+        "src=./file",
+        "id=x,type=file",
+        "id=x,type=ssh",
+        "id=x,mode=0400",
+        "npmrc",
+    ] {
+        assert!(SecretSource::parse(spec).is_err(), "{} was accepted", spec);
+    }
+}
+
+#[test]
+fn test_named_context_parse() {
+    let parse = |spec: &str| NamedContext::parse(spec).unwrap();
+    assert_eq!(
+        parse("alpine=docker-image://alpine:3.20"),
+        (
+            "alpine".to_string(),
+            NamedContext::Image("alpine:3.20".to_string())
+        )
+    );
+    assert_eq!(
+        parse("src=https://github.com/user/repo.git#main"),
+        (
+            "src".to_string(),
+            NamedContext::Url("https://github.com/user/repo.git#main".to_string())
+        )
+    );
+    assert_eq!(
+        parse("src=git@github.com:user/repo.git"),
+        (
+            "src".to_string(),
+            NamedContext::Url("git@github.com:user/repo.git".to_string())
+        )
+    );
+    assert_eq!(
+        parse("assets=../assets"),
+        (
+            "assets".to_string(),
+            NamedContext::Local(PathBuf::from("../assets"))
+        )
+    );
+
+    for spec in ["assets", "=../assets", "assets="] {
+        assert!(NamedContext::parse(spec).is_err(), "{} was accepted", spec);
+    }
+
+    let base = || BuildConfig::local("./app");
+    let config = base().named_context("assets", NamedContext::Local(PathBuf::from("../assets")));
+    assert_eq!(config.named_contexts.len(), 1);
+    assert_ne!(config.config_hash(), base().config_hash());
+}
+
 #[test]
 fn test_multi_platform_build() {
     let config = BuildConfig::local("./app")
@@ -191,3 +550,203 @@ fn test_dockerfile_path_github() {
         _ => panic!("Expected GitHub source"),
     }
 }
+
+#[test]
+fn test_build_config_inline() {
+    let config = BuildConfig::inline("FROM alpine\n", "./app").dockerfile("ignored.Dockerfile");
+
+    match config.source {
+        DockerfileSource::Inline {
+            content,
+            context_path,
+        } => {
+            assert_eq!(content, b"FROM alpine\n".to_vec());
+            assert_eq!(context_path, PathBuf::from("./app"));
+        }
+        _ => panic!("Expected Inline source"),
+    }
+}
+
+#[test]
+fn test_export_local() {
+    let config = BuildConfig::local("./app")
+        .tag("localhost:5000/app:latest")
+        .export_local("./dist");
+
+    assert_eq!(config.exports, vec![Export::Local(PathBuf::from("./dist"))]);
+    assert_eq!(config.tags.len(), 1);
+    assert!(BuildConfig::default().exports.is_empty());
+}
+
+#[test]
+fn test_multiple_exports() {
+    let config = BuildConfig::local("./app")
+        .export(Export::Oci(PathBuf::from("app.tar")))
+        .export_local("./dist");
+
+    assert_eq!(
+        config.exports,
+        vec![
+            Export::Oci(PathBuf::from("app.tar")),
+            Export::Local(PathBuf::from("./dist")),
+        ]
+    );
+}
+
+#[test]
+fn test_push_flag() {
+    assert!(BuildConfig::default().push);
+    let config = BuildConfig::local("./app").tag("app:dev").push(false);
+    assert!(!config.push);
+    assert_eq!(config.tags, vec!["app:dev"]);
+}
+
+#[test]
+fn test_log_output() {
+    use buildkit_client::LogSink;
+
+    let config = BuildConfig::local("./app").log_output(LogSink::file("build.log"));
+    assert!(matches!(
+        config.log_output,
+        Some(LogSink::File(ref path)) if path == std::path::Path::new("build.log")
+    ));
+    assert!(BuildConfig::local("./app").log_output.is_none());
+}
+
+#[test]
+fn test_attestations() {
+    let config = BuildConfig::local("./app")
+        .platform(Platform::linux_arm64())
+        .attest("provenance", "mode=max")
+        .attest("sbom", "");
+    assert_eq!(config.attestations["provenance"], "mode=max");
+    assert_eq!(config.attestations["sbom"], "");
+    assert!(BuildConfig::default().attestations.is_empty());
+}
+
+#[test]
+fn test_frontend() {
+    use buildkit_client::Frontend;
+
+    assert_eq!(BuildConfig::default().frontend, Frontend::Dockerfile);
+    let config = BuildConfig::local("./app").frontend(Frontend::gateway("docker/dockerfile:1.7"));
+    assert_eq!(
+        config.frontend,
+        Frontend::Gateway {
+            image: "docker/dockerfile:1.7".to_string(),
+            attrs: Default::default(),
+        }
+    );
+}
+
+#[test]
+fn test_tarball_url() {
+    let config = BuildConfig::tarball_url("https://example.com/app-1.2.0.tar.gz")
+        .tarball_checksum("sha256:abc")
+        .tarball_header("Authorization", "Bearer t0ken")
+        .dockerfile("app-1.2.0/Dockerfile");
+
+    match &config.source {
+        DockerfileSource::TarballUrl {
+            url,
+            checksum,
+            dockerfile_path,
+            headers,
+        } => {
+            assert_eq!(url, "https://example.com/app-1.2.0.tar.gz");
+            assert_eq!(checksum.as_deref(), Some("sha256:abc"));
+            assert_eq!(dockerfile_path.as_deref(), Some("app-1.2.0/Dockerfile"));
+            assert_eq!(headers["Authorization"], "Bearer t0ken");
+        }
+        _ => panic!("Expected TarballUrl source"),
+    }
+    assert!(!format!("{:?}", config).contains("t0ken"));
+}
+
+#[test]
+fn test_build_config_git() {
+    use buildkit_client::GitAuth;
+
+    let config = BuildConfig::git("https://gitlab.com/org/repo.git")
+        .git_ref("v1.2.0")
+        .git_subdir("services/api")
+        .dockerfile("Dockerfile.prod")
+        .git_auth(GitAuth::Token("glpat-secret".to_string()));
+
+    match &config.source {
+        DockerfileSource::Git {
+            url,
+            git_ref,
+            subdir,
+            dockerfile_path,
+            auth,
+        } => {
+            assert_eq!(url, "https://gitlab.com/org/repo.git");
+            assert_eq!(git_ref.as_deref(), Some("v1.2.0"));
+            assert_eq!(subdir.as_deref(), Some("services/api"));
+            assert_eq!(dockerfile_path.as_deref(), Some("Dockerfile.prod"));
+            assert!(matches!(auth, Some(GitAuth::Token(_))));
+        }
+        _ => panic!("Expected Git source"),
+    }
+    assert!(!format!("{:?}", config).contains("glpat-secret"));
+}
+
+#[test]
+fn test_git_url_fragment() {
+    let config = BuildConfig::github("https://github.com/org/monorepo.git#main:services/api");
+    match &config.source {
+        DockerfileSource::GitHub {
+            repo_url,
+            git_ref,
+            subdir,
+            ..
+        } => {
+            assert_eq!(repo_url, "https://github.com/org/monorepo.git");
+            assert_eq!(git_ref.as_deref(), Some("main"));
+            assert_eq!(subdir.as_deref(), Some("services/api"));
+        }
+        _ => panic!("Expected GitHub source"),
+    }
+
+    let config = BuildConfig::git("git@gitlab.com:org/repo.git#:app").git_subdir("web");
+    match &config.source {
+        DockerfileSource::Git {
+            url,
+            git_ref,
+            subdir,
+            ..
+        } => {
+            assert_eq!(url, "git@gitlab.com:org/repo.git");
+            assert!(git_ref.is_none());
+            assert_eq!(subdir.as_deref(), Some("web"));
+        }
+        _ => panic!("Expected Git source"),
+    }
+}
+
+#[test]
+fn test_validate_local_paths() {
+    use buildkit_client::Error;
+
+    let dir = tempfile::tempdir().unwrap();
+    let message = |config: BuildConfig| match config.validate() {
+        Err(Error::InvalidConfig(message)) => message,
+        other => panic!("Expected InvalidConfig, got {:?}", other),
+    };
+
+    let missing = dir.path().join("missing");
+    assert!(message(BuildConfig::local(&missing)).contains(&missing.display().to_string()));
+    assert!(message(BuildConfig::local(dir.path())).ends_with("Dockerfile not found"));
+
+    std::fs::create_dir(dir.path().join("docker")).unwrap();
+    assert!(message(BuildConfig::local(dir.path()).dockerfile("docker")).contains("is a directory"));
+
+    std::fs::write(dir.path().join("Dockerfile"), "FROM alpine\n").unwrap();
+    assert!(BuildConfig::local(dir.path()).validate().is_ok());
+    assert!(message(
+        BuildConfig::local(dir.path())
+            .named_context("assets", NamedContext::Local(missing.clone()))
+    )
+    .contains("build context assets"));
+}
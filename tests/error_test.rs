@@ -0,0 +1,143 @@
+//! Unit tests for error classification
+
+use buildkit_client::error::{SourceLocation, StepFailure};
+use buildkit_client::Error;
+use std::path::PathBuf;
+use tonic::{Code, Status};
+
+#[test]
+fn test_grpc_status_is_mapped_by_code() {
+    assert!(matches!(
+        Error::from(Status::unavailable("connection refused")),
+        Error::Unavailable(_)
+    ));
+    assert!(matches!(
+        Error::from(Status::deadline_exceeded("timeout")),
+        Error::DeadlineExceeded(_)
+    ));
+    assert!(matches!(
+        Error::from(Status::permission_denied("denied")),
+        Error::PermissionDenied(_)
+    ));
+    assert!(matches!(
+        Error::from(Status::resource_exhausted("no space left on device")),
+        Error::ResourceExhausted(_)
+    ));
+    assert!(matches!(
+        Error::from(Status::internal("boom")),
+        Error::Grpc(_)
+    ));
+}
+
+#[test]
+fn test_grpc_status_is_preserved() {
+    let error = Error::from(Status::not_found("no such ref"));
+    let status = error.grpc_status().unwrap();
+    assert_eq!(status.code(), Code::NotFound);
+    assert_eq!(status.message(), "no such ref");
+    assert_eq!(error.to_string(), "Not found: no such ref");
+
+    assert!(Error::other("plain").grpc_status().is_none());
+}
+
+#[test]
+fn test_retryable_classification() {
+    assert!(Error::from(Status::unavailable("")).is_retryable());
+    assert!(Error::from(Status::deadline_exceeded("")).is_retryable());
+    assert!(Error::from(Status::resource_exhausted("")).is_retryable());
+    assert!(Error::from(Status::aborted("")).is_retryable());
+
+    assert!(!Error::from(Status::invalid_argument("")).is_retryable());
+    assert!(!Error::from(Status::internal("")).is_retryable());
+    assert!(!Error::InvalidConfig("bad".to_string()).is_retryable());
+}
+
+#[test]
+fn test_user_error_classification() {
+    assert!(Error::from(Status::invalid_argument("")).is_user_error());
+    assert!(Error::from(Status::unauthenticated("")).is_user_error());
+    assert!(Error::from(Status::failed_precondition("")).is_user_error());
+    assert!(Error::PathNotFound(PathBuf::from("/missing")).is_user_error());
+
+    assert!(!Error::from(Status::unavailable("")).is_user_error());
+    assert!(!Error::from(Status::internal("")).is_user_error());
+}
+
+#[test]
+fn test_error_report_for_grpc_error() {
+    let report = Error::from(Status::unavailable("connection refused")).to_report();
+    assert_eq!(report.kind, "unavailable");
+    assert_eq!(report.message, "BuildKit unavailable: connection refused");
+    assert!(report.retryable);
+    assert!(!report.hints.is_empty());
+    assert!(report.step.is_none());
+}
+
+#[test]
+fn test_error_report_for_failed_step() {
+    let error = Error::BuildStepFailed(Box::new(StepFailure {
+        step: "[2/3] RUN make".to_string(),
+        digest: "sha256:abc".to_string(),
+        exit_code: Some(2),
+        message: "exit code: 2".to_string(),
+        logs: vec!["error: missing ;".to_string()],
+        session_id: Some("session-1".to_string()),
+        source_location: Some(SourceLocation {
+            filename: "Dockerfile".to_string(),
+            start_line: 3,
+            end_line: 3,
+            lines: vec!["RUN make".to_string()],
+        }),
+        stack: vec![],
+    }));
+    let report = error.to_report();
+    assert_eq!(report.kind, "build_step_failed");
+    assert_eq!(
+        report.message,
+        "Build step '[2/3] RUN make' failed: exit code: 2"
+    );
+    assert_eq!(report.step.as_deref(), Some("[2/3] RUN make"));
+    assert_eq!(report.digest.as_deref(), Some("sha256:abc"));
+    assert_eq!(report.exit_code, Some(2));
+    assert_eq!(report.session_id.as_deref(), Some("session-1"));
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+    assert_eq!(json["kind"], "build_step_failed");
+    assert_eq!(json["exit_code"], 2);
+    assert_eq!(json["logs"][0], "error: missing ;");
+    assert_eq!(json["source_location"]["start_line"], 3);
+    assert!(error
+        .to_string()
+        .contains("--- Dockerfile:3 ---\n   3 | RUN make"));
+    assert_eq!(json["retryable"], false);
+}
+
+#[test]
+fn test_error_report_includes_source_chain() {
+    let error = Error::PathResolution {
+        path: PathBuf::from("/missing"),
+        source: std::io::Error::new(std::io::ErrorKind::NotFound, "no such file"),
+    };
+    let report = error.to_report();
+    assert_eq!(report.kind, "path_resolution");
+    assert_eq!(report.chain.len(), 2);
+    assert_eq!(report.chain[1], "no such file");
+
+    let report = report.with_session_id("abc");
+    assert_eq!(report.session_id.as_deref(), Some("abc"));
+}
+
+#[test]
+fn test_oversized_secret_is_a_typed_error() {
+    use buildkit_client::session::SecretsServer;
+
+    let mut secrets = SecretsServer::new();
+    secrets.add_secret("small", vec![0; 16]).unwrap();
+    match secrets.add_secret("large", vec![0; 600 * 1024]) {
+        Err(error @ Error::SecretTooLarge { .. }) => {
+            assert_eq!(error.kind(), "secret_too_large");
+            assert!(error.is_user_error());
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
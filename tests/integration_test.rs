@@ -8,6 +8,8 @@
 
 mod common;
 
+use buildkit_client::container::{run_in_result, RunOptions};
+use buildkit_client::registry::RegistryClient;
 use buildkit_client::{BuildConfig, BuildKitClient};
 use common::*;
 
@@ -40,7 +42,7 @@ async fn test_simple_local_build() {
     create_test_dockerfile(&test_dir, None);
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir);
 
@@ -71,7 +73,7 @@ RUN echo "Custom Dockerfile"
     .unwrap();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir).dockerfile("Custom.Dockerfile");
 
@@ -94,7 +96,7 @@ async fn test_build_with_args() {
     create_dockerfile_with_args(&test_dir);
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir)
         .build_arg("VERSION", "1.2.3")
@@ -115,7 +117,7 @@ async fn test_multistage_build_with_target() {
     create_multistage_dockerfile(&test_dir);
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     // Build only the builder stage
     let config = BuildConfig::local(&test_dir).target("builder");
@@ -131,6 +133,121 @@ async fn test_multistage_build_with_target() {
     );
 }
 
+#[tokio::test]
+async fn test_gateway_build_reads_result_files() {
+    skip_without_buildkit!();
+
+    let test_dir = create_temp_dir("gateway-read");
+    create_test_dockerfile(
+        &test_dir,
+        Some("FROM alpine:latest\nRUN echo 1.2.3 > /VERSION\n"),
+    );
+
+    let addr = get_buildkit_addr();
+    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+
+    let config = BuildConfig::local(&test_dir);
+    let result = client
+        .gateway_build(config, None, |result| async move {
+            let stat = result.stat_file("/VERSION").await?;
+            let data = result.read_file("/VERSION").await?;
+            Ok((stat, data))
+        })
+        .await;
+
+    cleanup_temp_dir(&test_dir);
+
+    let (stat, data) = result.expect("Gateway build failed");
+    assert_eq!(data, b"1.2.3\n");
+    assert_eq!(stat.size, 6);
+}
+
+#[tokio::test]
+async fn test_frontend_build_solves_through_gateway() {
+    skip_without_buildkit!();
+
+    let test_dir = create_temp_dir("frontend-build");
+    create_test_dockerfile(
+        &test_dir,
+        Some("FROM alpine:latest\nRUN echo 1.2.3 > /VERSION\n"),
+    );
+
+    let addr = get_buildkit_addr();
+    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+
+    let config = BuildConfig::local(&test_dir);
+    let result = client
+        .frontend_build(config, None, |gateway| async move {
+            let attrs = gateway.frontend_attrs().clone();
+            let result = gateway
+                .solve_frontend(buildkit_client::raw::DOCKERFILE_FRONTEND, attrs)
+                .await?;
+            assert_eq!(result.read_file("/VERSION").await?, b"1.2.3\n");
+            Ok(result)
+        })
+        .await;
+
+    cleanup_temp_dir(&test_dir);
+
+    result.expect("Frontend build failed");
+}
+
+#[tokio::test]
+async fn test_solve_llb_definition() {
+    use buildkit_client::llb;
+
+    skip_without_buildkit!();
+
+    let test_dir = create_temp_dir("solve-llb");
+    create_test_context(&test_dir);
+
+    let addr = get_buildkit_addr();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
+
+    let base = llb::image("alpine:latest");
+    let with_app = llb::copy(&llb::local("context"), "/app", &base, "/app");
+    let state = llb::exec(&with_app, ["sh", "-c", "cat /app/main.txt > /main.txt"]).root();
+
+    let config = BuildConfig::local(&test_dir);
+    let result = client.solve_llb(config, state.marshal(), None).await;
+
+    cleanup_temp_dir(&test_dir);
+
+    result.expect("LLB build failed");
+}
+
+#[tokio::test]
+async fn test_run_in_result() {
+    skip_without_buildkit!();
+
+    let test_dir = create_temp_dir("run-in-result");
+    create_test_dockerfile(
+        &test_dir,
+        Some("FROM alpine:latest\nENV GREETING=hello\nWORKDIR /srv\n"),
+    );
+
+    let addr = get_buildkit_addr();
+    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+
+    let config = BuildConfig::local(&test_dir);
+    let result = client
+        .gateway_build(config, None, |result| async move {
+            let cmd = vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo $GREETING $(pwd); exit 3".to_string(),
+            ];
+            run_in_result(&result, &cmd, RunOptions::default()).await
+        })
+        .await;
+
+    cleanup_temp_dir(&test_dir);
+
+    let output = result.expect("Run in result failed");
+    assert_eq!(output.exit_code, 3);
+    assert_eq!(output.stdout, b"hello /srv\n");
+}
+
 #[tokio::test]
 async fn test_build_with_context_files() {
     skip_without_buildkit!();
@@ -148,7 +265,7 @@ RUN cat /main.txt /config.txt /data.txt
     std::fs::write(test_dir.join("Dockerfile"), dockerfile_content).unwrap();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir);
 
@@ -171,7 +288,7 @@ async fn test_build_with_no_cache() {
     create_test_dockerfile(&test_dir, None);
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir).no_cache(true);
 
@@ -201,7 +318,7 @@ RUN apk add --no-cache curl
     );
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir).pull(true);
 
@@ -222,7 +339,7 @@ async fn test_build_with_progress_handler() {
     create_test_dockerfile(&test_dir, None);
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let progress = Box::new(ConsoleProgressHandler::new(true));
 
@@ -255,7 +372,7 @@ RUN test ! -d /app/subdir || (echo "subdir should be ignored" && exit 1)
     std::fs::write(test_dir.join("Dockerfile"), dockerfile_content).unwrap();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir);
 
@@ -280,7 +397,7 @@ async fn test_invalid_dockerfile_syntax() {
     std::fs::write(test_dir.join("Dockerfile"), "INVALID DOCKERFILE SYNTAX").unwrap();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir);
 
@@ -308,7 +425,7 @@ async fn test_build_nonexistent_base_image() {
     .unwrap();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir);
 
@@ -331,7 +448,7 @@ async fn test_multiple_tags() {
     create_test_dockerfile(&test_dir, None);
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     // Note: This test now only verifies that multiple tags can be configured
     // For actual push testing, see test_push_multiple_tags which uses local registry
@@ -366,7 +483,7 @@ async fn test_large_context() {
     }
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir).tag(random_test_tag());
 
@@ -394,7 +511,7 @@ async fn test_push_to_local_registry() {
     create_test_dockerfile(&test_dir, None);
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     // Generate unique tag with registry prefix
     let image_name = format!("push-test-{}", rand::random::<u32>());
@@ -437,7 +554,7 @@ async fn test_push_multiple_tags() {
     create_test_dockerfile(&test_dir, None);
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let image_name = format!("multi-tag-{}", rand::random::<u32>());
     let registry_host = get_registry_push_host();
@@ -468,6 +585,88 @@ async fn test_push_multiple_tags() {
     assert!(body.contains("latest"), "Tag 'latest' not found");
 }
 
+#[tokio::test]
+async fn test_inspect_pushed_image() {
+    skip_without_buildkit!();
+    skip_without_registry!();
+
+    let test_dir = create_temp_dir("registry-inspect");
+    create_test_dockerfile(
+        &test_dir,
+        Some("FROM alpine:latest\nLABEL org.example.test=inspect\nCMD [\"echo\", \"hi\"]\n"),
+    );
+
+    let addr = get_buildkit_addr();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
+
+    let image_name = format!("inspect-test-{}", rand::random::<u32>());
+    let tag = format!("{}/{image_name}:latest", get_registry_push_host());
+
+    let result = client
+        .build(BuildConfig::local(&test_dir).tag(&tag), None)
+        .await;
+    cleanup_temp_dir(&test_dir);
+    assert!(result.is_ok(), "Build failed: {:?}", result.err());
+
+    // Inspect through the registry address reachable from the test runner
+    let base_url = get_registry_http_base_url();
+    let host = base_url
+        .trim_start_matches("http://")
+        .trim_end_matches('/')
+        .to_string();
+    let registry = RegistryClient::new().plain_http(host.clone());
+    let image = registry
+        .inspect(&format!("{host}/{image_name}:latest"))
+        .await
+        .expect("inspect pushed image");
+
+    assert!(!image.images.is_empty());
+    let config = &image.images[0].config;
+    assert_eq!(config.config.labels["org.example.test"], "inspect");
+    assert_eq!(config.config.cmd, vec!["echo", "hi"]);
+}
+
+#[tokio::test]
+async fn test_copy_pushed_image() {
+    skip_without_buildkit!();
+    skip_without_registry!();
+
+    let test_dir = create_temp_dir("registry-copy");
+    create_test_dockerfile(&test_dir, None);
+
+    let addr = get_buildkit_addr();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
+
+    let image_name = format!("copy-test-{}", rand::random::<u32>());
+    let tag = format!("{}/{image_name}:build", get_registry_push_host());
+
+    let result = client
+        .build(BuildConfig::local(&test_dir).tag(&tag), None)
+        .await;
+    cleanup_temp_dir(&test_dir);
+    assert!(result.is_ok(), "Build failed: {:?}", result.err());
+
+    let base_url = get_registry_http_base_url();
+    let host = base_url
+        .trim_start_matches("http://")
+        .trim_end_matches('/')
+        .to_string();
+    let registry = RegistryClient::new().plain_http(host.clone());
+
+    let source = format!("{host}/{image_name}:build");
+    let retag = format!("{host}/{image_name}:release");
+    let other_repo = format!("{host}/{image_name}-copy:latest");
+    let digest = registry
+        .copy_image(&source, &[&retag, &other_repo])
+        .await
+        .expect("copy image");
+
+    for reference in [&retag, &other_repo] {
+        let image = registry.inspect(reference).await.expect("inspect copy");
+        assert_eq!(image.digest, digest);
+    }
+}
+
 // ============================================================================
 // Secrets Tests
 // ============================================================================
@@ -491,7 +690,7 @@ RUN echo "Secret was successfully mounted and verified"
     std::fs::write(test_dir.join("Dockerfile"), dockerfile).unwrap();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir).secret("test_secret", "my-secret-value");
 
@@ -533,7 +732,7 @@ RUN echo "All secrets verified successfully"
     std::fs::write(test_dir.join("Dockerfile"), dockerfile).unwrap();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir)
         .secret("api_key", "key-12345")
@@ -568,7 +767,7 @@ RUN echo "Secret environment variable verified"
     std::fs::write(test_dir.join("Dockerfile"), dockerfile).unwrap();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir).secret("my_token", "token-abc-123");
 
@@ -603,7 +802,7 @@ RUN echo "Secret isolation verified"
     std::fs::write(test_dir.join("Dockerfile"), dockerfile).unwrap();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir).secret("temp_secret", "temporary");
 
@@ -627,7 +826,7 @@ async fn test_github_public_repo_build() {
     skip_without_buildkit!();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::github("https://github.com/buildkit-rs/hello-world-public");
 
@@ -645,7 +844,7 @@ async fn test_github_public_repo_with_ref() {
     skip_without_buildkit!();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config =
         BuildConfig::github("https://github.com/buildkit-rs/hello-world-public").git_ref("main");
@@ -671,7 +870,7 @@ async fn test_github_private_repo_build() {
         std::env::var("PAT_TOKEN").expect("PAT_TOKEN environment variable is not set");
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::github("https://github.com/buildkit-rs/hello-world-private")
         .github_token(github_token);
@@ -697,7 +896,7 @@ async fn test_github_private_repo_with_ref() {
         std::env::var("PAT_TOKEN").expect("PAT_TOKEN environment variable is not set");
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::github("https://github.com/buildkit-rs/hello-world-private")
         .git_ref("main")
@@ -717,7 +916,7 @@ async fn test_github_with_custom_dockerfile() {
     skip_without_buildkit!();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::github("https://github.com/buildkit-rs/hello-world-public")
         .dockerfile("Dockerfile");
@@ -736,7 +935,7 @@ async fn test_github_with_build_args() {
     skip_without_buildkit!();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::github("https://github.com/buildkit-rs/hello-world-public")
         .build_arg("VERSION", "1.0.0")
@@ -756,7 +955,7 @@ async fn test_github_private_without_token() {
     skip_without_buildkit!();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     // Try to build private repo without token - should fail
     let config = BuildConfig::github("https://github.com/buildkit-rs/hello-world-private");
@@ -777,7 +976,7 @@ async fn test_github_with_progress_handler() {
     use buildkit_client::progress::ConsoleProgressHandler;
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let progress = Box::new(ConsoleProgressHandler::new(true));
 
@@ -798,7 +997,7 @@ async fn test_github_with_commit_ref() {
     skip_without_buildkit!();
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     // Use a specific commit hash (this would need to be a real commit in the repo)
     let config =
@@ -29,7 +29,7 @@ RUN cat /file.txt
     eprintln!("data/file.txt");
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir);
 
@@ -10,3 +10,40 @@ async fn test_proto_types_exist() {
     // If this compiles, it means the proto files were correctly processed
     let _: Option<BuildKitClient> = None;
 }
+
+#[tokio::test]
+async fn test_raw_solve_request_builder() {
+    use buildkit_client::raw::{self, SolveRequestBuilder};
+    use buildkit_client::session::Session;
+    use std::collections::HashMap;
+
+    let session = Session::new();
+    let request = SolveRequestBuilder::new("build-1")
+        .session(&session)
+        .frontend_attr("target", "release")
+        .exporter(
+            "image",
+            HashMap::from([("name".to_string(), "app:latest".to_string())]),
+        )
+        .cache_import("registry", HashMap::new())
+        .entitlement("network.host")
+        .build();
+
+    assert_eq!(request.r#ref, "build-1");
+    assert_eq!(request.session, session.get_id());
+    assert_eq!(request.frontend, raw::DOCKERFILE_FRONTEND);
+    assert_eq!(request.frontend_attrs["target"], "release");
+    assert_eq!(request.exporters[0].attrs["name"], "app:latest");
+    assert_eq!(request.cache.unwrap().imports.len(), 1);
+    assert_eq!(request.entitlements, vec!["network.host"]);
+
+    let grpc_request = raw::with_session_metadata(raw::status_request("build-1"), &session);
+    assert_eq!(grpc_request.get_ref().r#ref, "build-1");
+    assert_eq!(
+        grpc_request
+            .metadata()
+            .get("x-docker-expose-session-uuid")
+            .unwrap(),
+        session.get_id().as_str()
+    );
+}
@@ -0,0 +1,79 @@
+//! Unit tests for image reference parsing and normalization
+
+use buildkit_client::{Error, Reference};
+
+#[test]
+fn test_reference_normalizes_shorthand() {
+    let reference = Reference::parse("myimg").unwrap();
+    assert_eq!(reference.domain, "docker.io");
+    assert_eq!(reference.path, "library/myimg");
+    assert_eq!(reference.tag.as_deref(), Some("latest"));
+    assert_eq!(reference.to_string(), "docker.io/library/myimg:latest");
+
+    let reference = Reference::parse("user/app:1.0").unwrap();
+    assert_eq!(reference.to_string(), "docker.io/user/app:1.0");
+
+    let reference = Reference::parse("index.docker.io/user/app").unwrap();
+    assert_eq!(reference.to_string(), "docker.io/user/app:latest");
+}
+
+#[test]
+fn test_reference_with_registry_host() {
+    let reference = Reference::parse("localhost:5000/team/app:v1").unwrap();
+    assert_eq!(reference.domain, "localhost:5000");
+    assert_eq!(reference.path, "team/app");
+    assert_eq!(reference.tag.as_deref(), Some("v1"));
+
+    let reference = Reference::parse("ghcr.io/org/app").unwrap();
+    assert_eq!(reference.name(), "ghcr.io/org/app");
+    assert_eq!(reference.tag.as_deref(), Some("latest"));
+
+    let reference = Reference::parse("localhost/app").unwrap();
+    assert_eq!(reference.domain, "localhost");
+}
+
+#[test]
+fn test_reference_with_digest() {
+    let digest = format!("sha256:{}", "a".repeat(64));
+    let reference = Reference::parse(&format!("alpine@{}", digest)).unwrap();
+    assert_eq!(reference.tag, None);
+    assert_eq!(reference.digest.as_deref(), Some(digest.as_str()));
+    assert_eq!(
+        reference.to_string(),
+        format!("docker.io/library/alpine@{}", digest)
+    );
+
+    let reference = Reference::parse(&format!("alpine:3.19@{}", digest)).unwrap();
+    assert_eq!(reference.tag.as_deref(), Some("3.19"));
+}
+
+#[test]
+fn test_reference_rejects_invalid() {
+    for invalid in [
+        "",
+        "MyImage",
+        "docker.io/User/app",
+        "app:",
+        "app:-bad",
+        "app@sha256:abc",
+        "app@sha256:ZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZ",
+        "foo//bar",
+        "foo_-bar",
+        "-foo",
+    ] {
+        let result = Reference::parse(invalid);
+        assert!(
+            matches!(result, Err(Error::InvalidReference { .. })),
+            "expected '{}' to be rejected, got {:?}",
+            invalid,
+            result
+        );
+    }
+}
+
+#[test]
+fn test_reference_from_str() {
+    let reference: Reference = "registry:5000/app:dev".parse().unwrap();
+    assert_eq!(reference.domain, "registry:5000");
+    assert_eq!(reference.path, "app");
+}
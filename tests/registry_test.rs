@@ -0,0 +1,112 @@
+//! Unit tests for registry manifest and config types
+
+use buildkit_client::registry::{ImageConfig, Manifest, MEDIA_TYPE_OCI_INDEX};
+
+const INDEX: &str = r#"{
+    "schemaVersion": 2,
+    "mediaType": "application/vnd.oci.image.index.v1+json",
+    "manifests": [
+        {
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "digest": "sha256:1111111111111111111111111111111111111111111111111111111111111111",
+            "size": 480,
+            "platform": {"architecture": "amd64", "os": "linux"}
+        },
+        {
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "digest": "sha256:2222222222222222222222222222222222222222222222222222222222222222",
+            "size": 480,
+            "platform": {"architecture": "arm64", "os": "linux", "variant": "v8"}
+        },
+        {
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "digest": "sha256:3333333333333333333333333333333333333333333333333333333333333333",
+            "size": 566,
+            "annotations": {
+                "vnd.docker.reference.digest": "sha256:1111111111111111111111111111111111111111111111111111111111111111",
+                "vnd.docker.reference.type": "attestation-manifest"
+            },
+            "platform": {"architecture": "unknown", "os": "unknown"}
+        }
+    ]
+}"#;
+
+const MANIFEST: &str = r#"{
+    "schemaVersion": 2,
+    "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+    "config": {
+        "mediaType": "application/vnd.docker.container.image.v1+json",
+        "digest": "sha256:4444444444444444444444444444444444444444444444444444444444444444",
+        "size": 1470
+    },
+    "layers": [
+        {
+            "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+            "digest": "sha256:5555555555555555555555555555555555555555555555555555555555555555",
+            "size": 3623807
+        }
+    ]
+}"#;
+
+#[test]
+fn test_parse_index() {
+    let manifest = Manifest::parse(INDEX.as_bytes(), Some(MEDIA_TYPE_OCI_INDEX)).unwrap();
+    let Manifest::Index(index) = manifest else {
+        panic!("expected an index");
+    };
+
+    assert_eq!(index.manifests.len(), 3);
+    let platforms: Vec<String> = index
+        .manifests
+        .iter()
+        .filter(|d| !d.is_attestation())
+        .filter_map(|d| d.platform.as_ref())
+        .map(|p| buildkit_client::Platform::from(p).to_string())
+        .collect();
+    assert_eq!(platforms, vec!["linux/amd64", "linux/arm64/v8"]);
+}
+
+#[test]
+fn test_parse_manifest_without_content_type() {
+    let manifest = Manifest::parse(MANIFEST.as_bytes(), None).unwrap();
+    let Manifest::Image(image) = manifest else {
+        panic!("expected an image manifest");
+    };
+
+    assert_eq!(image.schema_version, 2);
+    assert_eq!(image.layers.len(), 1);
+    assert_eq!(image.config.size, 1470);
+}
+
+#[test]
+fn test_parse_invalid_manifest() {
+    assert!(Manifest::parse(b"not json", None).is_err());
+}
+
+#[test]
+fn test_parse_image_config() {
+    let config: ImageConfig = serde_json::from_str(
+        r#"{
+            "architecture": "arm64",
+            "os": "linux",
+            "variant": "v8",
+            "config": {
+                "Env": ["PATH=/usr/bin"],
+                "Cmd": ["/bin/sh"],
+                "Labels": {"org.opencontainers.image.version": "1.0.0"},
+                "Entrypoint": null
+            },
+            "rootfs": {"type": "layers", "diff_ids": ["sha256:abc"]}
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(config.platform().to_string(), "linux/arm64/v8");
+    assert_eq!(config.config.cmd, vec!["/bin/sh"]);
+    assert!(config.config.entrypoint.is_empty());
+    assert_eq!(
+        config.config.labels["org.opencontainers.image.version"],
+        "1.0.0"
+    );
+    assert_eq!(config.rootfs.unwrap().diff_ids, vec!["sha256:abc"]);
+}
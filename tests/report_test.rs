@@ -0,0 +1,114 @@
+//! Unit tests for build graph rendering
+
+use buildkit_client::report::{BuildReport, VertexReport};
+use std::time::{Duration, SystemTime};
+
+fn report() -> BuildReport {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let at = |secs: u64| Some(start + Duration::from_secs(secs));
+    BuildReport {
+        vertexes: vec![
+            VertexReport {
+                digest: "sha256:base".to_string(),
+                name: "[1/3] FROM docker.io/library/alpine".to_string(),
+                cached: true,
+                ..Default::default()
+            },
+            VertexReport {
+                digest: "sha256:deps".to_string(),
+                name: "[2/3] RUN apk add \"build-base\"".to_string(),
+                inputs: vec!["sha256:base".to_string()],
+                started: at(0),
+                completed: at(95),
+                ..Default::default()
+            },
+            VertexReport {
+                digest: "sha256:make".to_string(),
+                name: "[3/3] RUN make".to_string(),
+                inputs: vec!["sha256:deps".to_string(), "sha256:unknown".to_string()],
+                started: at(95),
+                completed: at(97),
+                error: Some("exit code: 2".to_string()),
+                ..Default::default()
+            },
+        ],
+    }
+}
+
+#[test]
+fn test_dot_graph_annotates_steps() {
+    let dot = report().to_dot();
+    assert!(dot.starts_with("digraph build {"));
+    assert!(dot.contains(
+        r##""sha256:deps" [label="[2/3] RUN apk add \"build-base\"\n1m35s", fillcolor="#fde2a7"];"##
+    ));
+    assert!(dot.contains(r#"label="[1/3] FROM docker.io/library/alpine\ncached""#));
+    assert!(dot.contains(r##"label="[3/3] RUN make\nfailed", fillcolor="#f4b6b6""##));
+    assert!(dot.contains(r#""sha256:base" -> "sha256:deps";"#));
+    assert!(dot.contains(r#""sha256:deps" -> "sha256:make";"#));
+    assert!(!dot.contains("sha256:unknown"));
+}
+
+#[test]
+fn test_mermaid_graph_uses_indexed_ids() {
+    let mermaid = report().to_mermaid();
+    assert!(mermaid.starts_with("flowchart TB\n"));
+    assert!(mermaid.contains(r#"v1["[2/3] RUN apk add #quot;build-base#quot;<br/>1m35s"]"#));
+    assert!(mermaid.contains("v0 --> v1"));
+    assert!(mermaid.contains("v1 --> v2"));
+    assert!(mermaid.contains("style v0 fill:#e0e0e0"));
+}
+
+#[test]
+fn test_durations() {
+    let report = report();
+    assert_eq!(report.duration(), Some(Duration::from_secs(97)));
+    assert_eq!(report.vertexes[0].duration(), None);
+    assert_eq!(report.vertexes[1].duration(), Some(Duration::from_secs(95)));
+}
+
+#[test]
+fn test_timing_summary_lists_slowest_first() {
+    let mut report = report();
+    report.vertexes.push(VertexReport {
+        digest: "sha256:test".to_string(),
+        name: "[4/4] RUN make test".to_string(),
+        started: report.vertexes[2].completed,
+        ..Default::default()
+    });
+    report.vertexes.push(VertexReport {
+        digest: "sha256:pending".to_string(),
+        name: "exporting to image".to_string(),
+        ..Default::default()
+    });
+
+    let summary = report.timing_summary();
+    assert_eq!(
+        summary.lines().collect::<Vec<_>>(),
+        [
+            "   1m35s  [2/3] RUN apk add \"build-base\"",
+            "  failed  [3/3] RUN make",
+            "  cached  [1/3] FROM docker.io/library/alpine",
+            " running  [4/4] RUN make test",
+            "   1m37s  total",
+        ]
+    );
+}
+
+#[test]
+fn test_step_timings_slowest_first() {
+    let timings = report().step_timings();
+    let steps: Vec<(&str, Option<Duration>)> = timings
+        .iter()
+        .map(|t| (t.digest.as_str(), t.duration))
+        .collect();
+    assert_eq!(
+        steps,
+        [
+            ("sha256:deps", Some(Duration::from_secs(95))),
+            ("sha256:make", Some(Duration::from_secs(2))),
+            ("sha256:base", None),
+        ]
+    );
+    assert!(timings[2].cached);
+}
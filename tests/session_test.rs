@@ -81,23 +81,297 @@ fn test_auth_server_with_registries() {
         host: "docker.io".to_string(),
         username: "user1".to_string(),
         password: "pass1".to_string(),
+        ..Default::default()
     });
 
     auth.add_registry(RegistryAuthConfig {
         host: "gcr.io".to_string(),
         username: "user2".to_string(),
         password: "pass2".to_string(),
+        ..Default::default()
     });
 
     auth.add_registry(RegistryAuthConfig {
         host: "localhost:5000".to_string(),
         username: "admin".to_string(),
         password: "secret".to_string(),
+        ..Default::default()
     });
 
     // Successfully created auth server with multiple registries
 }
 
+#[tokio::test]
+async fn test_auth_server_identity_token() {
+    use buildkit_client::proto::moby::filesync::v1::{auth_server::Auth, CredentialsRequest};
+
+    let mut auth = AuthServer::new();
+    auth.add_registry(RegistryAuthConfig {
+        host: "registry.example.com".to_string(),
+        username: "user".to_string(),
+        identity_token: Some("refresh-token".to_string()),
+        ..Default::default()
+    });
+
+    let response = auth
+        .credentials(tonic::Request::new(CredentialsRequest {
+            host: "registry.example.com".to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    // Identity tokens are passed with an empty username
+    assert_eq!(response.username, "");
+    assert_eq!(response.secret, "refresh-token");
+}
+
+#[tokio::test]
+async fn test_auth_server_registry_token() {
+    use buildkit_client::proto::moby::filesync::v1::{auth_server::Auth, FetchTokenRequest};
+
+    let mut auth = AuthServer::new();
+    auth.add_registry(RegistryAuthConfig {
+        host: "registry.example.com".to_string(),
+        registry_token: Some("bearer-token".to_string()),
+        ..Default::default()
+    });
+
+    let fetch = |host: &str| FetchTokenRequest {
+        client_id: String::new(),
+        host: host.to_string(),
+        realm: "https://auth.example.com/token".to_string(),
+        service: "registry.example.com".to_string(),
+        scopes: vec!["repository:app:pull,push".to_string()],
+    };
+
+    let response = auth
+        .fetch_token(tonic::Request::new(fetch("registry.example.com")))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.token, "bearer-token");
+    assert!(response.issued_at > 0);
+
+    // Without a token endpoint there is nothing to exchange
+    let response = auth
+        .fetch_token(tonic::Request::new(FetchTokenRequest {
+            realm: String::new(),
+            ..fetch("other.example.com")
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(response.token.is_empty());
+}
+
+/// Serve `count` token requests, answering `token-1`, `token-2`, ... and
+/// returning the raw requests received
+async fn serve_tokens(count: usize) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let realm = format!("http://{}/token", listener.local_addr().unwrap());
+    let handle = tokio::spawn(async move {
+        let mut requests = Vec::new();
+        for i in 1..=count {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 16 * 1024];
+            let mut request = String::new();
+            while !request.contains("\r\n\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+            let body = format!("{{\"token\":\"token-{}\",\"expires_in\":300}}", i);
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            requests.push(request);
+        }
+        requests
+    });
+    (realm, handle)
+}
+
+#[tokio::test]
+async fn test_auth_server_exchanges_and_caches_tokens() {
+    use buildkit_client::proto::moby::filesync::v1::{auth_server::Auth, FetchTokenRequest};
+
+    let (realm, server) = serve_tokens(2).await;
+    let mut auth = AuthServer::new();
+    auth.add_registry(RegistryAuthConfig {
+        host: "registry.example.com".to_string(),
+        username: "user".to_string(),
+        password: "pass".to_string(),
+        ..Default::default()
+    });
+
+    let fetch = |host: &str| FetchTokenRequest {
+        client_id: String::new(),
+        host: host.to_string(),
+        realm: realm.clone(),
+        service: "registry.example.com".to_string(),
+        scopes: vec!["repository:app:pull".to_string()],
+    };
+
+    let first = auth
+        .fetch_token(tonic::Request::new(fetch("registry.example.com")))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(first.token, "token-1");
+    assert_eq!(first.expires_in, 300);
+    assert!(first.issued_at > 0);
+
+    // Reused until it is about to expire
+    let cached = auth
+        .fetch_token(tonic::Request::new(fetch("registry.example.com")))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(cached, first);
+
+    // Hosts without credentials get an anonymous token
+    let anonymous = auth
+        .fetch_token(tonic::Request::new(fetch("mirror.example.com")))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(anonymous.token, "token-2");
+
+    let requests: Vec<String> = server
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| r.to_ascii_lowercase())
+        .collect();
+    assert!(requests[0]
+        .starts_with("get /token?scope=repository%3aapp%3apull&service=registry.example.com"));
+    // "user:pass"
+    assert!(requests[0].contains("authorization: basic dxnlcjpwyxnz"));
+    assert!(!requests[1].contains("authorization:"));
+}
+
+#[tokio::test]
+async fn test_auth_server_token_authority() {
+    use buildkit_client::proto::moby::filesync::v1::{
+        auth_server::Auth, GetTokenAuthorityRequest, VerifyTokenAuthorityRequest,
+    };
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    let mut auth = AuthServer::new();
+    auth.add_registry(RegistryAuthConfig {
+        host: "registry.example.com".to_string(),
+        username: "user".to_string(),
+        password: "pass".to_string(),
+        ..Default::default()
+    });
+
+    let public_key = |host: &str, salt: &[u8]| {
+        let auth = auth.clone();
+        let request = GetTokenAuthorityRequest {
+            host: host.to_string(),
+            salt: salt.to_vec(),
+        };
+        async move {
+            auth.get_token_authority(tonic::Request::new(request))
+                .await
+                .map(|r| r.into_inner().public_key)
+        }
+    };
+
+    let key = public_key("registry.example.com", b"salt").await.unwrap();
+    assert_eq!(key.len(), 32);
+    // Stable for the same salt, different for another one
+    assert_eq!(
+        public_key("registry.example.com", b"salt").await.unwrap(),
+        key
+    );
+    assert_ne!(
+        public_key("registry.example.com", b"other").await.unwrap(),
+        key
+    );
+
+    let signed = auth
+        .verify_token_authority(tonic::Request::new(VerifyTokenAuthorityRequest {
+            host: "registry.example.com".to_string(),
+            payload: b"payload".to_vec(),
+            salt: b"salt".to_vec(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .signed;
+    UnparsedPublicKey::new(&ED25519, &key)
+        .verify(b"payload", &signed)
+        .unwrap();
+
+    // Without credentials BuildKit falls back to Credentials
+    let status = public_key("other.example.com", b"salt").await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+}
+
+#[tokio::test]
+async fn test_auth_server_scoped_credentials() {
+    use buildkit_client::proto::moby::filesync::v1::{
+        auth_server::Auth, CredentialsRequest, FetchTokenRequest,
+    };
+    use buildkit_client::CredentialScope;
+
+    let mut auth = AuthServer::new();
+    auth.add_registry(RegistryAuthConfig {
+        host: "registry.example.com".to_string(),
+        username: "reader".to_string(),
+        registry_token: Some("pull-token".to_string()),
+        scope: CredentialScope::Pull,
+        ..Default::default()
+    });
+    auth.add_registry(RegistryAuthConfig {
+        host: "registry.example.com".to_string(),
+        username: "robot".to_string(),
+        registry_token: Some("push-token".to_string()),
+        scope: CredentialScope::Push,
+        ..Default::default()
+    });
+
+    let fetch = |scope: &str| FetchTokenRequest {
+        client_id: String::new(),
+        host: "registry.example.com".to_string(),
+        realm: "https://auth.example.com/token".to_string(),
+        service: "registry.example.com".to_string(),
+        scopes: vec![scope.to_string()],
+    };
+
+    let token = |scope: &'static str| {
+        let auth = auth.clone();
+        async move {
+            auth.fetch_token(tonic::Request::new(fetch(scope)))
+                .await
+                .unwrap()
+                .into_inner()
+                .token
+        }
+    };
+    assert_eq!(token("repository:app:pull").await, "pull-token");
+    assert_eq!(token("repository:app:pull,push").await, "push-token");
+
+    // Without scope information the push identity is preferred
+    let response = auth
+        .credentials(tonic::Request::new(CredentialsRequest {
+            host: "registry.example.com".to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.username, "robot");
+}
+
 #[test]
 fn test_session_with_file_sync() {
     let temp_dir = std::env::temp_dir();
@@ -21,7 +21,7 @@ RUN echo "test"
     eprintln!("\n=== Single file test (only Dockerfile) ===");
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir);
 
@@ -53,7 +53,7 @@ RUN cat /test.txt
     eprintln!("\n=== Root level files test ===");
 
     let addr = get_buildkit_addr();
-    let mut client = BuildKitClient::connect(&addr).await.unwrap();
+    let client = BuildKitClient::connect(&addr).await.unwrap();
 
     let config = BuildConfig::local(&test_dir);
 